@@ -6,11 +6,12 @@ use std::io::{stdin, stdout};
 use std::path::PathBuf;
 
 use ansi_term::Color::{Green, Red, Yellow};
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use clap::{
     arg, command, crate_authors, value_parser, ArgAction, ArgMatches, Command,
 };
 
+use yara_x::escape::terminal_safe;
 use yara_x::Scanner;
 use yara_x::{Compiler, Rule};
 use yara_x_fmt::Formatter;
@@ -198,7 +199,9 @@ fn cmd_scan(args: &ArgMatches) -> anyhow::Result<()> {
     let path_as_namespace = args.get_flag("path-as-namespace");
     let negate = args.get_flag("negate");
 
-    let mut compiler = Compiler::new().colorize_errors(true);
+    let mut compiler = Compiler::new();
+
+    compiler.colorize_errors(true);
 
     for path in rules_path {
         let src = fs::read(path)
@@ -208,13 +211,13 @@ fn cmd_scan(args: &ArgMatches) -> anyhow::Result<()> {
             .origin(path.as_os_str().to_str().unwrap());
 
         if path_as_namespace {
-            compiler = compiler.new_namespace(path.to_string_lossy().as_ref());
+            compiler.new_namespace(path.to_string_lossy().as_ref());
         }
 
-        compiler = compiler.add_source(src)?;
+        compiler.add_source(src).map_err(with_report)?;
     }
 
-    let rules = compiler.build()?;
+    let rules = compiler.build().map_err(with_report)?;
     let rules_ref = &rules;
 
     let mut walker = walk::ParallelWalk::new(path);
@@ -235,20 +238,20 @@ fn cmd_scan(args: &ArgMatches) -> anyhow::Result<()> {
                 scan_results.iter().collect()
             };
 
+            // File names can contain control characters.
+            let file_path =
+                terminal_safe(&file_path.to_string_lossy()).into_owned();
+
             for matching_rule in matching_rules {
                 if print_namespace {
                     println!(
                         "{}:{} {}",
                         matching_rule.namespace(),
                         matching_rule.name(),
-                        file_path.display()
+                        file_path
                     );
                 } else {
-                    println!(
-                        "{} {}",
-                        matching_rule.name(),
-                        file_path.display()
-                    );
+                    println!("{} {}", matching_rule.name(), file_path);
                 }
             }
             Ok::<(), anyhow::Error>(())
@@ -265,7 +268,10 @@ fn cmd_ast(args: &ArgMatches) -> anyhow::Result<()> {
     let src = SourceCode::from(src.as_slice())
         .origin(rules_path.as_os_str().to_str().unwrap());
 
-    let ast = Parser::new().colorize_errors(true).build_ast(src)?;
+    let ast = Parser::new()
+        .colorize_errors(true)
+        .build_ast(src)
+        .map_err(|err| anyhow!("{}", err.as_str()))?;
 
     let mut output = String::new();
     ascii_tree::write_tree(&mut output, &ast.ascii_tree())?;
@@ -286,10 +292,10 @@ fn cmd_wasm(args: &ArgMatches) -> anyhow::Result<()> {
 
     rules_path.set_extension("wasm");
 
-    Compiler::new()
-        .colorize_errors(true)
-        .add_source(src)?
-        .emit_wasm_file(rules_path.as_path())?;
+    let mut compiler = Compiler::new();
+
+    compiler.colorize_errors(true).add_source(src).map_err(with_report)?;
+    compiler.emit_wasm_file(rules_path.as_path())?;
 
     Ok(())
 }
@@ -354,7 +360,7 @@ fn cmd_check(args: &ArgMatches) -> anyhow::Result<()> {
                         Red.paint("ERROR"),
                         file_path.display()
                     );
-                    println!("{}", err);
+                    println!("{}", err.as_str());
                 }
             };
 
@@ -379,3 +385,12 @@ fn cmd_format(args: &ArgMatches) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Errors returned by the compiler only display a one-line summary, this
+/// replaces the error with its detailed report when it has one.
+fn with_report(err: yara_x::Error) -> anyhow::Error {
+    match err.report() {
+        Some(report) => anyhow!("{}", report),
+        None => err.into(),
+    }
+}
@@ -247,9 +247,9 @@ impl Formatter {
 
         let tokens = processor::Processor::new(tokens)
             //
-            // Insert newline in front of import statements, making sure that
-            // each import starts at a new line. The newline is not inserted if
-            // the statement is at the start of the file.
+            // Insert newline in front of import and include statements,
+            // making sure that each of them starts at a new line. The newline
+            // is not inserted if the statement is at the start of the file.
             //
             // Example:
             //
@@ -262,7 +262,8 @@ impl Formatter {
                     let next_token = ctx.token(1);
                     let prev_token = ctx.token(-1);
 
-                    next_token.eq(&Begin(GrammarRule::import_stmt))
+                    (next_token.eq(&Begin(GrammarRule::import_stmt))
+                        || next_token.eq(&Begin(GrammarRule::include_stmt)))
                         && prev_token.neq(&Begin(GrammarRule::source_file))
                         && prev_token.is_not(*NEWLINE)
                 },
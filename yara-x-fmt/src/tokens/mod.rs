@@ -350,6 +350,7 @@ impl<'a> Token<'a> {
             | GrammarRule::k_BASE64WIDE
             | GrammarRule::k_CONDITION
             | GrammarRule::k_CONTAINS
+            | GrammarRule::k_COUNT_OF
             | GrammarRule::k_DEFINED
             | GrammarRule::k_ENDSWITH
             | GrammarRule::k_ENTRYPOINT
@@ -362,6 +363,7 @@ impl<'a> Token<'a> {
             | GrammarRule::k_IENDSWITH
             | GrammarRule::k_IEQUALS
             | GrammarRule::k_IMPORT
+            | GrammarRule::k_INCLUDE
             | GrammarRule::k_IN
             | GrammarRule::k_ISTARTSWITH
             | GrammarRule::k_MATCHES
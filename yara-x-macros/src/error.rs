@@ -16,7 +16,7 @@ pub(crate) fn impl_error_macro(
 ) -> syn::Result<TokenStream> {
     let name = &input.ident;
 
-    let (variants, funcs) = match &input.data {
+    let variants = match &input.data {
         syn::Data::Struct(_) | syn::Data::Union(_) => {
             return Err(syn::Error::new(
                 name.span(),
@@ -27,66 +27,113 @@ pub(crate) fn impl_error_macro(
         syn::Data::Enum(data_enum) => impl_enum_error_macro(data_enum)?,
     };
 
+    let funcs = variants.iter().map(|v| &v.build_func);
+    let codes = variants.iter().map(|v| &v.code);
+    let code_strs = variants.iter().map(|v| &v.code_str);
+    let report_types = variants.iter().map(|v| &v.report_type);
+    let titles = variants.iter().map(|v| &v.title);
+    let labels = variants.iter().map(|v| &v.labels);
+    let label_origins = variants.iter().map(|v| &v.label_origins);
+    let notes = variants.iter().map(|v| &v.note);
+
     let (impl_generics, ty_generics, where_clause) =
         input.generics.split_for_impl();
 
     syn::Result::Ok(quote! {
-        use yansi::Color;
-
         #[automatically_derived]
         impl #impl_generics #name #ty_generics #where_clause {
             #(#funcs)*
-        }
 
-        #[automatically_derived]
-        impl #impl_generics Display for #name #ty_generics #where_clause {
-            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            /// Identifiers returned by `code`, one per variant.
+            pub const CODES: &'static [&'static str] = &[ #(#code_strs),* ];
+
+            /// Returns an identifier for this kind of error or warning.
+            ///
+            /// The identifier is the name of the variant in snake case, like
+            /// `duplicate_tag` for `DuplicateTag`. Unlike titles, which may
+            /// contain details like identifiers, it's the same for all the
+            /// instances of the same variant.
+            pub fn code(&self) -> &'static str {
                 match self {
-                    #(Self::#variants { detailed_report, .. })|* => {
-                         write!(f, "{}", detailed_report)
-                    }
+                    #(#codes),*
                 }
             }
         }
 
         #[automatically_derived]
-        impl #impl_generics Debug for #name #ty_generics #where_clause {
-            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        impl #impl_generics Report for #name #ty_generics #where_clause {
+            fn report_type(&self) -> ReportType {
                 match self {
-                    #(Self::#variants { detailed_report, .. })|* => {
-                         write!(f, "{}", detailed_report)
-                    }
+                    #(#report_types),*
                 }
             }
-        }
 
-        #[automatically_derived]
-        impl #impl_generics std::error::Error for #name #ty_generics #where_clause {
-            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-                None
+            #[allow(unused_variables)]
+            fn title(&self) -> String {
+                match self {
+                    #(#titles),*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn labels(&self) -> Vec<(Span, String, yansi::Style)> {
+                match self {
+                    #(#labels),*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn label_origins(&self) -> Vec<Option<String>> {
+                match self {
+                    #(#label_origins),*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn note(&self) -> Option<String> {
+                match self {
+                    #(#notes),*
+                }
             }
         }
     })
 }
 
+/// Code generated for each variant in the enum.
+struct VariantCode {
+    /// Function that creates an instance of the variant.
+    build_func: TokenStream,
+    /// Identifier of the variant, as returned by `code`.
+    code_str: String,
+    /// Match arm that returns the identifier of the variant.
+    code: TokenStream,
+    /// Match arm that returns the report type.
+    report_type: TokenStream,
+    /// Match arm that returns the report title.
+    title: TokenStream,
+    /// Match arm that returns the labels.
+    labels: TokenStream,
+    /// Match arm that returns the origins of the labels.
+    label_origins: TokenStream,
+    /// Match arm that returns the note.
+    note: TokenStream,
+}
+
 fn impl_enum_error_macro(
     data_enum: &DataEnum,
-) -> syn::Result<(Vec<&Ident>, Vec<TokenStream>)> {
-    // Generate a proto function for each variant in the enum labelled
-    // with #[error(...)] or #[warning(...)].
-    let mut funcs = Vec::new();
+) -> syn::Result<Vec<VariantCode>> {
     let mut variants = Vec::new();
     // For each variant in the enum...
     for variant in &data_enum.variants {
         // ...look for #[error(...)] or #[warning(...)] attributes.
         for attr in &variant.attrs {
             if let Some((attr_type, attr_args)) = parse_attr(attr)? {
-                variants.push(&variant.ident);
-                funcs.push(gen_build_func(attr_type, attr_args, variant)?);
+                variants
+                    .push(gen_variant_code(attr_type, attr_args, variant)?);
             }
         }
     }
-    Ok((variants, funcs))
+    Ok(variants)
 }
 
 // Checks if an attribute is #[error(...)] and returns its arguments if that's
@@ -122,12 +169,13 @@ fn parse_attr(
 }
 
 // Given an error or warning variant, generates the function that builds
-// an instance of this error or warning.
-fn gen_build_func(
+// an instance of this error or warning, and the match arms used in the
+// implementation of the `Report` trait.
+fn gen_variant_code(
     report_type: &str,
     attr_args: AttrArgs,
     variant: &Variant,
-) -> syn::Result<TokenStream> {
+) -> syn::Result<VariantCode> {
     match &variant.fields {
         syn::Fields::Named(fields) => {
             // Each error variant has one or more labels (e.g. #[label(...)]), 
@@ -137,43 +185,33 @@ fn gen_build_func(
             // The variant can also have a note (e.g. #[note(...)]).
             let note = get_note(variant)?;
 
-            // The main label is the first label in the tuple.
-            let main_label = &labels.first().ok_or_else(|| {
-                syn::Error::new_spanned(
+            if labels.is_empty() {
+                return Err(syn::Error::new_spanned(
                     variant,
                     "#[error(...)] must be accompanied by at least one instance of #[label(...)}",
-                )})?;
-
-            // The span identifier is the first item in the tuples returned
-            // by get_labels.
-            let main_label_span = &main_label.0;
+                ));
+            }
 
             // The arguments to the function have the same names and types as 
-            // the fields in the struct variant. Except for the field named
-            // `detailed_report`, which is not included in the arguments.
+            // the fields in the struct variant.
             let mut args = TokenStream::new();
-            args.append_all(
-                fields
-                    .named
-                    .pairs()
-                    .filter(
-                        |pair| *pair.value().ident.as_ref().unwrap() != "detailed_report"
-                    ),
-            );
+            args.append_all(fields.named.pairs());
 
-            let field_identifiers =
+            let field_identifiers: Vec<&Ident> =
                 fields
                     .named
                     .iter()
-                    .map(|field| field.ident.as_ref().unwrap());
+                    .map(|field| field.ident.as_ref().unwrap())
+                    .collect();
 
             let variant_ident = &variant.ident;
-            let fn_ident = Ident::new(
-                &variant_ident.to_string().to_case(Case::Snake), Span::call_site());
+            let code_str = variant_ident.to_string().to_case(Case::Snake);
+            let fn_ident = Ident::new(&code_str, Span::call_site());
 
             // Labels is a vector of tuples (Ident, TokenStream), convert it
             // to a vector of TokenStream, Idents are dropped.
-            let labels = labels.iter().map(|(_, labels)| labels);
+            let labels: Vec<_> =
+                labels.iter().map(|(_, labels)| labels).collect();
 
             let report_type = match report_type {
                 "error" => quote!(ReportType::Error),
@@ -181,24 +219,47 @@ fn gen_build_func(
                 _  => unreachable!(),
             };
 
-            Ok(quote!(
-                #[doc(hidden)]
-                pub fn #fn_ident(report_builder: &ReportBuilder, src: &SourceCode, #args) -> Self {
-                    let detailed_report = report_builder.create_report(
-                        #report_type,
-                        src,
-                        #main_label_span,
-                        format!(#attr_args),
-                        vec![
-                            #( #labels ),*
-                        ],
-                        #note.clone(),
-                    );
-                    Self::#variant_ident{
-                        #( #field_identifiers ),*
+            let pattern = quote!(
+                Self::#variant_ident { #( #field_identifiers ),* }
+            );
+
+            Ok(VariantCode {
+                build_func: quote!(
+                    #[doc(hidden)]
+                    pub fn #fn_ident(#args) -> Self {
+                        Self::#variant_ident{
+                            #( #field_identifiers ),*
+                        }
                     }
-                }
-            ))
+                ),
+                code: quote!(
+                    Self::#variant_ident { .. } => #code_str
+                ),
+                code_str,
+                report_type: quote!(
+                    Self::#variant_ident { .. } => #report_type
+                ),
+                title: quote!(
+                    #pattern => format!(#attr_args)
+                ),
+                labels: quote!(
+                    #pattern => vec![ #( #labels ),* ]
+                        .into_iter()
+                        .flatten()
+                        .map(|(label, _)| label)
+                        .collect()
+                ),
+                label_origins: quote!(
+                    #pattern => vec![ #( #labels ),* ]
+                        .into_iter()
+                        .flatten()
+                        .map(|(_, origin)| origin)
+                        .collect()
+                ),
+                note: quote!(
+                    #pattern => #note
+                ),
+            })
         }
         syn::Fields::Unnamed(_) | syn::Fields::Unit => {
             Err(syn::Error::new_spanned(
@@ -260,7 +321,7 @@ fn get_note(variant: &Variant) -> syn::Result<TokenStream> {
             )
         })?;
 
-        return Ok(note_field.to_token_stream());
+        return Ok(quote!(#note_field.clone()));
     }
 
     Ok(quote!(None))
@@ -306,48 +367,41 @@ fn get_labels(
         // The default label style depends on the type of report. It's red
         // for errors and yellow for warnings.
         let mut style = match report_type {
-            "error" => quote!(Color::Red.style().bold()),
-            "warning" => quote!(Color::Yellow.style().bold()),
+            "error" => quote!(yansi::Color::Red.style().bold()),
+            "warning" => quote!(yansi::Color::Yellow.style().bold()),
             _ => unreachable!(),
         };
 
-        // Take the last argument, which should be either the style or the
-        // name of the field containing the span for the label.
-        let last_arg = args.next_back().unwrap();
-
-        // If the last argument is a named value (e.g. foo = "bar") it can't be
-        // the name of the span field, so it should be the style.
-        let label_span_field =
-            if let NestedMeta::Meta(Meta::NameValue(value)) = last_arg.value()
-            {
-                // The argument is a named value, but return an error if the
-                // name is not "style".
-                if !value.path.is_ident("style") {
+        // The field containing the origin of the source code the label
+        // points to, if the label points to some other source code.
+        let mut origin_field: Option<Ident> = None;
+
+        // Take the last argument, which should be either a named value like
+        // the style, or the name of the field containing the span for the
+        // label.
+        let mut last_arg = args.next_back().unwrap();
+
+        // Named values (e.g. foo = "bar") can't be the name of the span
+        // field, they are optional arguments that come after it.
+        while let NestedMeta::Meta(Meta::NameValue(value)) = last_arg.value() {
+            // Make sure that the value is a literal string.
+            let value_str = match &value.lit {
+                Lit::Str(l) => l.value(),
+                _ => {
                     return Err(syn::Error::new_spanned(
                         value,
-                        format!(
-                            "unkown argument {}",
-                            value.path.get_ident().unwrap()
-                        ),
+                        "named arguments must be string literals",
                     ));
                 }
-                // Make sure that the style is a literal string.
-                let style_name = match &value.lit {
-                    Lit::Str(l) => l.value(),
-                    _ => {
-                        return Err(syn::Error::new_spanned(
-                            value,
-                            "argument style must be a string literal",
-                        ));
-                    }
-                };
+            };
 
+            if value.path.is_ident("style") {
                 // Override the label style with the one specified as an
                 // argument. (e.g. #[label(..., style="<style>")]).
-                style = match style_name.as_str() {
-                    "error" => quote!(Color::Red.style().bold()),
-                    "warning" => quote!(Color::Yellow.style().bold()),
-                    "note" => quote!(Color::Cyan.style().bold()),
+                style = match value_str.as_str() {
+                    "error" => quote!(yansi::Color::Red.style().bold()),
+                    "warning" => quote!(yansi::Color::Yellow.style().bold()),
+                    "note" => quote!(yansi::Color::Cyan.style().bold()),
                     s => {
                         return Err(syn::Error::new_spanned(
                             &last_arg,
@@ -355,15 +409,48 @@ fn get_labels(
                         ))
                     }
                 };
-
-                // Now return the argument next argument from the right, which
-                // should be the name of the span field.
-                args.next_back().unwrap()
+            } else if value.path.is_ident("origin") {
+                // The label points to the source code with the origin
+                // indicated by a field of type `Option<String>`. (e.g.
+                // #[label(..., origin="<field>")]).
+                let field = Ident::new(&value_str, Span::call_site());
+                if !variant
+                    .fields
+                    .iter()
+                    .any(|f| f.ident.as_ref() == Some(&field))
+                {
+                    return Err(syn::Error::new_spanned(
+                        value,
+                        format!(
+                            "field `{}` not found in `{}`",
+                            value_str, variant.ident
+                        ),
+                    ));
+                }
+                origin_field = Some(field);
             } else {
-                // The last argument is not a named value, so it should be the
-                // name of the span field.
-                last_arg
-            };
+                return Err(syn::Error::new_spanned(
+                    value,
+                    format!(
+                        "unkown argument {}",
+                        value.path.get_ident().unwrap()
+                    ),
+                ));
+            }
+
+            // Continue with the next argument from the right, which is
+            // either another named value or the name of the span field.
+            last_arg =
+                match args.next_back() {
+                    Some(arg) => arg,
+                    None => return Err(syn::Error::new_spanned(
+                        &attr_args.nested,
+                        "#[label(...)] must receive the label's text and span",
+                    )),
+                };
+        }
+
+        let label_span_field = last_arg;
 
         // Make sure that it's an identifier.
         let label_span_field = match label_span_field.value() {
@@ -403,12 +490,25 @@ fn get_labels(
             arg.to_tokens(&mut label_fmt_args);
         }
 
-        labels.push((
-            label_span_field.clone(),
-            quote!(
-                (#label_span_field, format!(#label_fmt_args), #style)
+        // Each label is an `Option` with the label and the origin of the
+        // source code it points to. Labels with an origin field are omitted
+        // when the field is `None`.
+        let label = match origin_field {
+            Some(origin_field) => quote!(
+                #origin_field.as_ref().map(|origin| (
+                    (*#label_span_field, format!(#label_fmt_args), #style),
+                    Some(origin.clone()),
+                ))
             ),
-        ));
+            None => quote!(
+                Some((
+                    (*#label_span_field, format!(#label_fmt_args), #style),
+                    None::<String>,
+                ))
+            ),
+        };
+
+        labels.push((label_span_field.clone(), label));
     }
 
     Ok(labels)
@@ -64,10 +64,11 @@ pub fn span_macro_derive(input: TokenStream) -> TokenStream {
 /// The `Error` derive macro generates boilerplate code for YARA error types.
 ///
 /// This macro can be applied only to enums with struct-like variants. It
-/// won't work if the enum contains unit-like or tuple-like variants. Each
-/// variant in the enum must have a `detailed_report` field of [`String`]
-/// type. This field will contain fully detailed error message, like
-/// this one...
+/// won't work if the enum contains unit-like or tuple-like variants. The
+/// fields in each variant contain the information that describes the error,
+/// like the spans and identifiers involved, and nothing else. The enum
+/// is pure data, the text-mode report that is shown to the user, like this
+/// one...
 ///
 /// ```text
 /// error: duplicate tag `tag1`
@@ -79,8 +80,9 @@ pub fn span_macro_derive(input: TokenStream) -> TokenStream {
 /// ───╯
 /// ```
 ///
-/// The rest of the fields vary from variant to variant. But they usually
-/// contain information that is used for rendering the detailed report.
+/// is rendered separately, by passing the enum to `ReportBuilder::render`
+/// together with the source code. This macro implements the `Report` trait
+/// for the enum, which tells the report builder what the report looks like.
 ///
 /// Each variant in the enum must be tagged with `#[error(...)]` or
 /// `#[warning(...)]` where the arguments inside the parenthesis are directly
@@ -92,7 +94,6 @@ pub fn span_macro_derive(input: TokenStream) -> TokenStream {
 ///    #[error("duplicate tag `{tag}`")]
 ///    #[label("duplicate tag", tag_span)]
 ///    DuplicateTag {
-///      detailed_report: String,
 ///      tag: String,
 ///      tag_span: Span,
 ///    },
@@ -109,7 +110,8 @@ pub fn span_macro_derive(input: TokenStream) -> TokenStream {
 /// passed to `#[label(...)]` are also passed to [`format!`] for creating a
 /// label, except for the last one, which should be the name of a field of
 /// type `Span` in the structure. The label will associated to the code
-/// span indicated by that field.
+/// span indicated by that field. The report is centered around the span
+/// of the first label.
 ///
 /// In the example above we use `#[label("duplicate tag", tag_span)]` for
 /// creating a label with the text "duplicate tag" associated to the span
@@ -125,26 +127,35 @@ pub fn span_macro_derive(input: TokenStream) -> TokenStream {
 /// "error" for labels accompanied by `#[error(...)]` and "warning" for
 /// those accompanied by `#[warning(...)]`.
 ///
+/// Labels usually point to the source code the report refers to. A label
+/// can point to some other source code with `origin="field"`, where `field`
+/// is a field of type `Option<String>` containing the origin of that source
+/// code. The label is omitted if the field is `None`. For example:
+///
+/// `#[label("declared here", decl_span, origin="decl_origin", style="note")]`
+///
+/// A note can be added at the end of the report with `#[note(field)]`,
+/// where `field` is a field of type `Option<String>`.
+///
 /// Also, for each variant a new function for creating instances of that
 /// variant is automatically generated. The functions have a name similar to
 /// the variant, but using snake-case instead of camel-case. For example, for
 /// variant `DuplicateTag` the function would be named `duplicate_tag`.
 ///
 /// Each function receives as arguments the fields declared in the
-/// corresponding structure, with the same names and types. Except for the
-/// `detailed_report` field, which won't appear in the function arguments.
-/// Also, the first two arguments for the function are always
-/// `&ReportBuilder`, and `&SourceCode`.
-///
-/// So, the function for the `DuplicateTag` example above will be...
+/// corresponding structure, with the same names and types. So, the function
+/// for the `DuplicateTag` example above will be...
 ///
 /// ```text
-/// duplicate_tag(
-///     report_builder: &ReportBuilder,
-///     src: &SourceCode,
-///     tag: String,
-///     tag_span: Span) -> Error
+/// duplicate_tag(tag: String, tag_span: Span) -> Error
 /// ```
+///
+/// The variant name in snake-case is also the identifier returned by the
+/// generated `code` method, and the associated constant `CODES` lists the
+/// identifiers of all the variants.
+///
+/// The code that uses this macro must have `Report`, `ReportType` and `Span`
+/// in scope.
 #[proc_macro_derive(Error, attributes(error, warning, label, note))]
 pub fn error_macro_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -34,7 +34,9 @@ impl<'ast> FuncSignatureParser<'ast> {
             "i32" | "i64" => Ok(Cow::Borrowed("i")),
             "f32" | "f64" => Ok(Cow::Borrowed("f")),
             "bool" => Ok(Cow::Borrowed("b")),
-            "PatternId" | "RuleId" => Ok(Cow::Borrowed("i")),
+            "PatternId" | "RegexpId" | "RuleId" | "StringSetId" => {
+                Ok(Cow::Borrowed("i"))
+            }
             "RuntimeString" => Ok(Cow::Borrowed("s")),
             type_ident => Err(syn::Error::new_spanned(
                 type_path,
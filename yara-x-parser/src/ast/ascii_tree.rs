@@ -247,6 +247,13 @@ pub(crate) fn expr_ascii_tree(expr: &Expr) -> Tree {
                 Leaf(vec![s.name.to_string()])
             }
         }
+        Expr::CountOf(c) => Node(
+            "count_of(<items>)".to_string(),
+            vec![Node(
+                "<items: pattern_set>".to_string(),
+                vec![pattern_set_ascii_tree(&c.pattern_set)],
+            )],
+        ),
         Expr::PatternOffset(s) | Expr::PatternLength(s) => {
             if let Some(index) = &s.index {
                 Node(
@@ -436,6 +443,19 @@ pub(crate) fn expr_ascii_tree(expr: &Expr) -> Tree {
 
             Node(node_title, children)
         }
+        Expr::InSet(in_set) => Node(
+            format!("<expr> in <set>{}", value),
+            vec![
+                Node(
+                    "<expr>".to_string(),
+                    vec![expr_ascii_tree(&in_set.item)],
+                ),
+                Node(
+                    "<set>".to_string(),
+                    vec![Leaf(vec![in_set.set.name.to_string()])],
+                ),
+            ],
+        ),
     }
 }
 
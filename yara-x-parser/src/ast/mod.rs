@@ -24,7 +24,7 @@ mod span;
 
 use std::borrow::Cow;
 use std::collections::btree_map::Values;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::{fmt, mem};
@@ -43,6 +43,9 @@ pub use crate::types::Type;
 /// Abstract Syntax Tree (AST) for YARA rules.
 pub struct AST<'src> {
     pub namespaces: Vec<Namespace<'src>>,
+    /// Pragmas found at the top of the source code, in the same order they
+    /// appear.
+    pub pragmas: Vec<Pragma<'src>>,
     /// Warnings generated while building this AST.
     pub warnings: Vec<Warning>,
 }
@@ -81,6 +84,7 @@ impl<'src> AST<'src> {
 pub struct Namespace<'src> {
     pub rules: Vec<Rule<'src>>,
     pub imports: Vec<Import>,
+    pub includes: Vec<Include>,
 }
 
 bitmask! {
@@ -101,12 +105,35 @@ pub struct Import {
     pub module_name: String,
 }
 
+/// An include statement.
+#[derive(Debug, HasSpan)]
+pub struct Include {
+    pub span: Span,
+    pub file_name: String,
+}
+
+/// A pragma, like `disable_warning(duplicate_tag)` in a comment of the form
+/// `// yarax: disable_warning(duplicate_tag)`.
+///
+/// Pragmas are options for the compiler that apply only to the source code
+/// that contains them. The parser checks their syntax, but their meaning is
+/// up to the compiler.
+#[derive(Debug, HasSpan)]
+pub struct Pragma<'src> {
+    pub span: Span,
+    pub name: &'src str,
+    pub argument: Option<&'src str>,
+}
+
 /// A YARA rule.
 #[derive(Debug)]
 pub struct Rule<'src> {
+    /// Span of the whole rule declaration, from the modifiers to the
+    /// closing brace.
+    pub span: Span,
     pub flags: RuleFlags,
     pub identifier: Ident<'src>,
-    pub tags: Option<HashSet<&'src str>>,
+    pub tags: Option<Vec<Ident<'src>>>,
     pub meta: Option<Vec<Meta<'src>>>,
     pub patterns: Option<Vec<Pattern<'src>>>,
     pub condition: Expr<'src>,
@@ -120,12 +147,36 @@ pub struct Meta<'src> {
 }
 
 /// Each of the possible values that can have a metadata entry.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MetaValue<'src> {
     Bool(bool),
     Integer(i64),
     Float(f64),
-    String(&'src str),
+    String(Cow<'src, BStr>),
+}
+
+impl From<bool> for MetaValue<'_> {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i64> for MetaValue<'_> {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<f64> for MetaValue<'_> {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl<'src> From<&'src str> for MetaValue<'src> {
+    fn from(value: &'src str) -> Self {
+        Self::String(Cow::Borrowed(BStr::new(value)))
+    }
 }
 
 impl<'src> Display for MetaValue<'src> {
@@ -134,7 +185,7 @@ impl<'src> Display for MetaValue<'src> {
             Self::Bool(v) => write!(f, "{}", v),
             Self::Integer(v) => write!(f, "{}", v),
             Self::Float(v) => write!(f, "{:.1}", v),
-            Self::String(v) => write!(f, "{}", v),
+            Self::String(v) => write!(f, "{:?}", v),
         }
     }
 }
@@ -167,6 +218,14 @@ impl<'src> Pattern<'src> {
             Pattern::Hex(p) => &p.identifier,
         }
     }
+
+    pub fn modifiers(&self) -> &PatternModifiers<'src> {
+        match self {
+            Pattern::Text(p) => &p.modifiers,
+            Pattern::Regexp(p) => &p.modifiers,
+            Pattern::Hex(p) => &p.modifiers,
+        }
+    }
 }
 
 /// A set of modifiers associated to a pattern.
@@ -222,6 +281,11 @@ impl<'src> PatternModifiers<'src> {
     pub fn xor(&self) -> Option<&PatternModifier> {
         self.modifiers.get("xor")
     }
+
+    #[inline]
+    pub fn private(&self) -> Option<&PatternModifier<'src>> {
+        self.modifiers.get("private")
+    }
 }
 
 /// Iterator that returns all the modifiers in a [`PatternModifiers`].
@@ -497,6 +561,10 @@ pub enum Expr<'src> {
     /// Pattern count expression (e.g. `#a`, `#a in (0..10)`)
     PatternCount(Box<IdentWithRange<'src>>),
 
+    /// Count of patterns that matched (e.g. `count_of($a*)`,
+    /// `count_of(them)`)
+    CountOf(Box<CountOf<'src>>),
+
     /// Pattern offset expression (e.g. `@a`, `@a[1]`)
     PatternOffset(Box<IdentWithIndex<'src>>),
 
@@ -610,6 +678,9 @@ pub enum Expr<'src> {
 
     /// A `for <quantifier> <vars> in ...` expression. (e.g. `for all i in (1..100) : ( ... )`)
     ForIn(Box<ForIn<'src>>),
+
+    /// Set membership expression (e.g. `dns.query in bad_domains`).
+    InSet(Box<InSet<'src>>),
 }
 
 /// A pattern match expression (e.g. `$a`, `$b at 0`, `$c in (0..10)`).
@@ -656,6 +727,20 @@ pub struct In<'src> {
     pub range: Range<'src>,
 }
 
+/// An expression that checks if a string belongs to a set of strings
+/// defined outside the source code (e.g. `dns.query in bad_domains`).
+///
+/// Sets are defined with `Compiler::define_string_set`, the parser only
+/// knows their names.
+#[derive(Debug, HasSpan)]
+pub struct InSet<'src> {
+    pub span: Span,
+    #[doc(hidden)]
+    pub type_value: TypeValue,
+    pub item: Expr<'src>,
+    pub set: Ident<'src>,
+}
+
 /// An identifier (e.g. `some_ident`).
 #[derive(Debug, Clone, HasSpan)]
 pub struct Ident<'src> {
@@ -947,6 +1032,16 @@ pub struct Of<'src> {
     pub anchor: Option<MatchAnchor<'src>>,
 }
 
+/// A `count_of` expression (e.g. `count_of($a, $b)`, `count_of(them)`).
+///
+/// The result is the number of patterns in the set that matched at least
+/// once. This expression is an extension to the YARA language.
+#[derive(Debug, HasSpan)]
+pub struct CountOf<'src> {
+    pub span: Span,
+    pub pattern_set: PatternSet<'src>,
+}
+
 /// A `for .. of` expression (e.g `for all of them : (..)`,
 /// `for 1 of ($a,$b) : (..)`)
 #[derive(Debug, HasSpan)]
@@ -1073,6 +1168,7 @@ impl<'src> Expr<'src> {
             Expr::Ident(ident) => &ident.type_value,
             Expr::Regexp(regexp) => &regexp.type_value,
             Expr::FnCall(fn_call) => &fn_call.type_value,
+            Expr::InSet(in_set) => &in_set.type_value,
 
             Expr::Literal(l) => &l.type_value,
             Expr::True { .. } => &TRUE,
@@ -1086,6 +1182,7 @@ impl<'src> Expr<'src> {
             Expr::Filesize { .. }
             | Expr::Entrypoint { .. }
             | Expr::PatternCount(_)
+            | Expr::CountOf(_)
             | Expr::PatternOffset(_)
             | Expr::PatternLength(_) => &UNKNOWN_INT,
 
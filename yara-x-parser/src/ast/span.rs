@@ -7,12 +7,19 @@ pub trait HasSpan {
 
 /// Contains the starting and ending position for a piece of source code.
 #[derive(Debug, Default, Hash, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Span {
     pub(crate) start: usize,
     pub(crate) end: usize,
 }
 
 impl Span {
+    /// Creates a span that goes from `start` to `end`, both are byte
+    /// offsets within the source code.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
     pub fn start(&self) -> usize {
         self.start
     }
@@ -0,0 +1,193 @@
+/*! Conversion of arbitrary bytes and strings into text that is safe to show.
+
+Scanned data, literal strings in rules, metadata values and file names can
+contain anything: zeroes, ANSI escape sequences that change the terminal's
+state, Unicode characters that reorder the text around them, or invalid
+UTF-8. This module is the single place where such content is converted
+into something that can be printed or stored without side effects.
+
+There are two kinds of conversions:
+
+* [`escape`] converts bytes into a lossless representation that contains
+  only printable ASCII characters, which can be converted back with
+  [`unescape`]. This is the representation used for bytes in JSON
+  documents, and [`escape_truncated`] is a variant for human-readable
+  output that limits the size of the result.
+
+* [`terminal_safe`] replaces the control characters in a string that
+  is already valid UTF-8, leaving everything else untouched. Each replaced
+  character is substituted by exactly one character, so offsets expressed
+  in characters are still valid after the conversion. This is used for
+  reports, where source code is shown together with labels pointing to
+  specific positions.
+*/
+
+use std::borrow::Cow;
+
+/// Escapes `data`, producing a string that contains only printable ASCII
+/// characters.
+///
+/// Printable ASCII characters are kept as is, except the backslash, which
+/// is escaped as `\\`. Any other byte is escaped as `\xNN`, where `NN` are
+/// two lowercase hex digits. The original bytes can be recovered with
+/// [`unescape`].
+///
+/// ```
+/// # use yara_x_parser::escape::escape;
+/// assert_eq!(escape(b"foo\x1b[31m\\bar\x00"), r"foo\x1b[31m\\bar\x00");
+/// ```
+pub fn escape(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len());
+    for b in data {
+        match b {
+            b'\\' => result.push_str(r"\\"),
+            0x20..=0x7e => result.push(*b as char),
+            _ => result.push_str(&format!(r"\x{:02x}", b)),
+        }
+    }
+    result
+}
+
+/// Like [`escape`], but escapes at most `max_len` bytes from `data`.
+///
+/// If `data` is longer than `max_len`, the result ends with a notice that
+/// indicates the number of bytes that were left out, like `[+12 bytes]`.
+/// The notice is separated from the escaped data by a space, which makes
+/// the result ambiguous, so it is intended for human-readable output only.
+///
+/// ```
+/// # use yara_x_parser::escape::escape_truncated;
+/// assert_eq!(escape_truncated(b"foo\x00bar", 4), r"foo\x00 [+3 bytes]");
+/// assert_eq!(escape_truncated(b"foo", 4), "foo");
+/// ```
+pub fn escape_truncated(data: &[u8], max_len: usize) -> String {
+    if data.len() <= max_len {
+        return escape(data);
+    }
+    format!("{} [+{} bytes]", escape(&data[..max_len]), data.len() - max_len)
+}
+
+/// Reverts the conversion done by [`escape`].
+///
+/// Returns [`None`] if `s` contains anything that [`escape`] can't produce,
+/// like unknown escape sequences or non-printable characters.
+///
+/// ```
+/// # use yara_x_parser::escape::unescape;
+/// assert_eq!(unescape(r"foo\x00\\").unwrap(), b"foo\x00\\");
+/// assert_eq!(unescape(r"foo\n"), None);
+/// ```
+pub fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\\' => match bytes.next()? {
+                b'\\' => result.push(b'\\'),
+                b'x' => {
+                    let hex = [bytes.next()?, bytes.next()?];
+                    let hex = std::str::from_utf8(&hex).ok()?;
+                    // `from_str_radix` accepts a leading sign, which is
+                    // not a hex digit.
+                    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return None;
+                    }
+                    result.push(u8::from_str_radix(hex, 16).ok()?);
+                }
+                _ => return None,
+            },
+            0x20..=0x7e => result.push(b),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+/// Replaces the characters in `s` that could alter the state of a terminal
+/// or the way in which the surrounding text is displayed.
+///
+/// ASCII control characters are replaced by their corresponding symbols in
+/// the Unicode "Control Pictures" block (e.g. the escape character `\x1b`
+/// becomes `␛`), except new lines, tabs, and carriage returns followed by a
+/// new line. Other control characters, the Unicode line and paragraph
+/// separators, and the characters that change the direction of the text
+/// are replaced with `U+FFFD`. Each character is replaced by exactly one
+/// character.
+///
+/// ```
+/// # use yara_x_parser::escape::terminal_safe;
+/// assert_eq!(terminal_safe("foo\x1b[2J\tbar\r\n"), "foo␛[2J\tbar\r\n");
+/// assert_eq!(terminal_safe("foo\u{202e}bar"), "foo\u{fffd}bar");
+/// ```
+pub fn terminal_safe(s: &str) -> Cow<'_, str> {
+    let mut chars = s.char_indices().peekable();
+    let mut result: Option<String> = None;
+
+    while let Some((i, c)) = chars.next() {
+        let replacement = match c {
+            '\n' | '\t' => None,
+            '\r' if matches!(chars.peek(), Some((_, '\n'))) => None,
+            '\x00'..='\x1f' => char::from_u32(0x2400 + c as u32),
+            '\x7f' => Some('\u{2421}'),
+            '\u{80}'..='\u{9f}'
+            | '\u{061c}'
+            | '\u{200e}'
+            | '\u{200f}'
+            | '\u{2028}'..='\u{202e}'
+            | '\u{2066}'..='\u{2069}' => Some(char::REPLACEMENT_CHARACTER),
+            _ => None,
+        };
+        match (replacement, result.as_mut()) {
+            (Some(r), Some(result)) => result.push(r),
+            (Some(r), None) => {
+                let mut s = s[..i].to_string();
+                s.push(r);
+                result = Some(s);
+            }
+            (None, Some(result)) => result.push(c),
+            (None, None) => {}
+        }
+    }
+
+    match result {
+        Some(result) => Cow::Owned(result),
+        None => Cow::Borrowed(s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, terminal_safe, unescape};
+
+    #[test]
+    fn escape_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let escaped = escape(&data);
+
+        assert!(escaped.bytes().all(|b| (0x20..=0x7e).contains(&b)));
+        assert_eq!(unescape(&escaped).unwrap(), data);
+    }
+
+    #[test]
+    fn unescape_invalid() {
+        assert_eq!(unescape(r"\x1"), None);
+        assert_eq!(unescape(r"\x+1"), None);
+        assert_eq!(unescape(r"\xzz"), None);
+        assert_eq!(unescape(r"\"), None);
+        assert_eq!(unescape("\x1b"), None);
+        assert_eq!(unescape("ñ"), None);
+    }
+
+    #[test]
+    fn terminal_safe_keeps_char_count() {
+        let s = "\x00\x07\x1b]0;title\x07\r\x7f\u{85}\u{202e}ñ\r\n";
+        let safe = terminal_safe(s);
+
+        assert_eq!(safe.chars().count(), s.chars().count());
+        assert_eq!(safe, "␀␇␛]0;title␇␍␡\u{fffd}\u{fffd}ñ\r\n");
+        assert!(matches!(
+            terminal_safe("foo\tbar"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+}
@@ -28,11 +28,16 @@ extern crate core;
 
 pub mod ast;
 pub mod cst;
+pub mod escape;
+pub mod line_index;
 pub use parser::*;
 
 #[doc(inline)]
 pub use warnings::*;
 
+#[doc(inline)]
+pub use report::Label;
+
 mod parser;
 
 #[doc(hidden)]
@@ -0,0 +1,251 @@
+/*! Conversion between byte offsets and line/column positions.
+
+Spans in the AST are byte offsets within the source code, while humans and
+tools like editors talk about lines and columns. [`LineIndex`] is computed
+once per source code, and after that any offset can be converted into a
+position, and vice versa, without scanning the source code from the start.
+
+Columns can be expressed in UTF-8 code units (bytes), UTF-16 code units,
+or Unicode scalar values (chars), see [`ColumnEncoding`]. The source code
+doesn't need to be valid UTF-8, each invalid sequence counts as a single
+U+FFFD replacement character, as in [`String::from_utf8_lossy`].
+
+Lines are separated by `\n`, a `\r` before it is considered part of the
+line.
+*/
+
+use bstr::ByteSlice;
+
+/// Units in which columns are expressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnEncoding {
+    /// Columns are UTF-8 code units, which means bytes.
+    Utf8,
+    /// Columns are UTF-16 code units. This is the default encoding in the
+    /// Language Server Protocol.
+    Utf16,
+    /// Columns are Unicode scalar values, which means chars.
+    Utf32,
+}
+
+/// A zero-based line and column within some source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Index of the lines in some source code.
+///
+/// The index doesn't keep a copy of the source code, functions that need
+/// to look at the characters receive it as an argument, which must be the
+/// same source code the index was created from.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    /// Byte offset where each line starts. The first item is always 0.
+    line_starts: Vec<usize>,
+    /// Number of chars that precede each line.
+    chars_before: Vec<usize>,
+    /// Length of the source code in bytes.
+    len: usize,
+}
+
+impl LineIndex {
+    /// Creates the index for the given source code.
+    pub fn new<T: AsRef<[u8]> + ?Sized>(text: &T) -> Self {
+        let text = text.as_ref();
+        let mut line_starts = vec![0];
+        let mut chars_before = vec![0];
+        let mut chars = 0;
+
+        for (_, end, c) in text.char_indices() {
+            chars += 1;
+            if c == '\n' {
+                line_starts.push(end);
+                chars_before.push(chars);
+            }
+        }
+
+        Self { line_starts, chars_before, len: text.len() }
+    }
+
+    /// Returns the number of lines. A source code that ends with `\n` has
+    /// an empty line after it, so the result is never zero.
+    pub fn num_lines(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the byte offset where the given line starts, or `None` if
+    /// the line doesn't exist.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line).cloned()
+    }
+
+    /// Returns the line that contains the given byte offset. Offsets past
+    /// the end of the source code belong to the last line.
+    pub fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        }
+    }
+
+    /// Converts a byte offset into a position.
+    ///
+    /// An offset in the middle of a character is treated as the offset
+    /// where that character starts. Offsets past the end of the source code
+    /// are treated as the end of the source code.
+    pub fn position<T: AsRef<[u8]> + ?Sized>(
+        &self,
+        text: &T,
+        offset: usize,
+        encoding: ColumnEncoding,
+    ) -> Position {
+        let offset = offset.min(self.len);
+        let line = self.line_of(offset);
+        let line_start = self.line_starts[line];
+
+        let column = text.as_ref()[line_start..]
+            .char_indices()
+            .take_while(|(_, end, _)| line_start + end <= offset)
+            .map(|(start, end, c)| units(c, end - start, encoding))
+            .sum();
+
+        Position { line, column }
+    }
+
+    /// Converts a position into a byte offset.
+    ///
+    /// Returns `None` if the line doesn't exist. A column past the end of
+    /// the line is treated as the end of the line, and a column in the
+    /// middle of a character is treated as the start of that character.
+    pub fn offset<T: AsRef<[u8]> + ?Sized>(
+        &self,
+        text: &T,
+        position: Position,
+        encoding: ColumnEncoding,
+    ) -> Option<usize> {
+        let line_start = self.line_start(position.line)?;
+        let line_end = self
+            .line_start(position.line + 1)
+            .map_or(self.len, |next_line_start| next_line_start - 1);
+
+        let mut column = 0;
+
+        for (start, end, c) in
+            text.as_ref()[line_start..line_end].char_indices()
+        {
+            column += units(c, end - start, encoding);
+            if column > position.column {
+                return Some(line_start + start);
+            }
+        }
+
+        Some(line_end)
+    }
+
+    /// Returns the number of chars that precede the given byte offset.
+    ///
+    /// An offset in the middle of a character is treated as the offset
+    /// where that character starts.
+    pub fn char_offset<T: AsRef<[u8]> + ?Sized>(
+        &self,
+        text: &T,
+        offset: usize,
+    ) -> usize {
+        let offset = offset.min(self.len);
+        let line = self.line_of(offset);
+        self.chars_before[line]
+            + self.position(text, offset, ColumnEncoding::Utf32).column
+    }
+}
+
+/// Returns the number of code units in the given encoding that are needed
+/// for representing `c`, which occupies `len` bytes in the source code. `len`
+/// may differ from `c.len_utf8()` when `c` is a replacement character that
+/// stands for an invalid UTF-8 sequence.
+fn units(c: char, len: usize, encoding: ColumnEncoding) -> usize {
+    match encoding {
+        ColumnEncoding::Utf8 => len,
+        ColumnEncoding::Utf16 => c.len_utf16(),
+        ColumnEncoding::Utf32 => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{ColumnEncoding::*, LineIndex, Position};
+
+    fn pos(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn ascii() {
+        let text = "rule a {\n  condition:\n    true\n}\n";
+        let index = LineIndex::new(text);
+
+        assert_eq!(index.num_lines(), 5);
+        assert_eq!(index.line_start(1), Some(9));
+        assert_eq!(index.line_start(5), None);
+
+        let offset = text.find("true").unwrap();
+
+        for encoding in [Utf8, Utf16, Utf32] {
+            assert_eq!(index.position(text, offset, encoding), pos(2, 4));
+            assert_eq!(index.offset(text, pos(2, 4), encoding), Some(offset));
+        }
+
+        assert_eq!(index.position(text, 0, Utf8), pos(0, 0));
+        assert_eq!(index.position(text, text.len(), Utf8), pos(4, 0));
+        assert_eq!(index.position(text, text.len() + 10, Utf8), pos(4, 0));
+        assert_eq!(index.char_offset(text, offset), offset);
+
+        // Columns past the end of a line are the end of the line.
+        assert_eq!(index.offset(text, pos(0, 100), Utf8), Some(8));
+        assert_eq!(index.offset(text, pos(5, 0), Utf8), None);
+    }
+
+    #[test]
+    fn multi_byte() {
+        // 'ñ' is 2 bytes in UTF-8 and 1 unit in UTF-16, '𝄞' is 4 bytes in
+        // UTF-8 and 2 units in UTF-16.
+        let text = "// ñ𝄞\n\"ñ𝄞\" == x";
+        let index = LineIndex::new(text);
+
+        let offset = text.find('x').unwrap();
+
+        assert_eq!(index.position(text, offset, Utf8), pos(1, 12));
+        assert_eq!(index.position(text, offset, Utf16), pos(1, 9));
+        assert_eq!(index.position(text, offset, Utf32), pos(1, 8));
+
+        assert_eq!(index.offset(text, pos(1, 12), Utf8), Some(offset));
+        assert_eq!(index.offset(text, pos(1, 9), Utf16), Some(offset));
+        assert_eq!(index.offset(text, pos(1, 8), Utf32), Some(offset));
+
+        assert_eq!(index.char_offset(text, offset), 14);
+
+        // An offset in the middle of '𝄞' is the start of '𝄞'.
+        let clef = text.rfind('𝄞').unwrap();
+        assert_eq!(index.position(text, clef + 2, Utf16), pos(1, 2));
+        // A column in the middle of '𝄞' is the start of '𝄞'.
+        assert_eq!(index.offset(text, pos(1, 3), Utf16), Some(clef));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let text = b"a\xff\xfeb\nc";
+        let index = LineIndex::new(text);
+
+        // Each invalid byte counts as a replacement character.
+        assert_eq!(index.position(text, 3, Utf8), pos(0, 3));
+        assert_eq!(index.position(text, 3, Utf16), pos(0, 3));
+        assert_eq!(
+            Some(index.char_offset(text, 5)),
+            String::from_utf8_lossy(text).chars().position(|c| c == 'c')
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use std::borrow::Cow;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::iter::Iterator;
 use std::str;
 
@@ -11,9 +11,10 @@ use pest::pratt_parser::{Assoc, Op, PrattParser};
 
 use crate::ast::*;
 use crate::cst::*;
+use crate::parser::regexp::matches_empty;
 use crate::parser::{Context, Error, ErrorInfo, GrammarRule};
 use crate::types::TypeValue;
-use crate::warnings::Warning;
+use crate::warnings::WarningInfo;
 
 macro_rules! expect {
     ($next:expr, $parser_rule:expr) => {{
@@ -212,7 +213,6 @@ lazy_static! {
 /// Certain modifiers can't be used in conjunction, and this function
 /// returns an error in those cases.
 fn check_pattern_modifiers(
-    ctx: &mut Context<'_, '_>,
     rule_type: GrammarRule,
     modifiers: &PatternModifiers,
 ) -> Result<(), Error> {
@@ -235,8 +235,6 @@ fn check_pattern_modifiers(
             };
 
             return Err(Error::new(ErrorInfo::invalid_modifier(
-                ctx.report_builder,
-                &ctx.src,
                 error_detail.to_string(),
                 modifier.span(),
             )));
@@ -258,8 +256,6 @@ fn check_pattern_modifiers(
     for (name1, modifier1, name2, modifier2) in invalid_combinations {
         if let (Some(modifier1), Some(modifier2)) = (modifier1, modifier2) {
             return Err(Error::new(ErrorInfo::invalid_modifier_combination(
-                ctx.report_builder,
-                &ctx.src,
                 name1.to_string(),
                 name2.to_string(),
                 modifier1.span(),
@@ -273,10 +269,11 @@ fn check_pattern_modifiers(
 }
 
 pub(crate) fn namespace_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     cst: CST<'src>,
 ) -> Result<Namespace<'src>, Error> {
     let mut imports: Vec<Import> = Vec::new();
+    let mut includes: Vec<Include> = Vec::new();
     let mut rules: Vec<Rule> = Vec::new();
     let mut rules_index: HashMap<&str, usize> = HashMap::new();
 
@@ -289,7 +286,7 @@ pub(crate) fn namespace_from_cst<'src>(
                 expect!(children.next().unwrap(), GrammarRule::k_IMPORT);
 
                 let module_name =
-                    utf8_string_lit_from_cst(ctx, children.next().unwrap())?;
+                    utf8_string_lit_from_cst(children.next().unwrap())?;
 
                 let already_imported = imports
                     .iter()
@@ -298,13 +295,14 @@ pub(crate) fn namespace_from_cst<'src>(
                 // If the module had been previously imported, raise
                 // warning about the duplicate import.
                 if let Some(already_imported) = already_imported {
-                    ctx.warnings.push(Warning::duplicate_import(
-                        ctx.report_builder,
-                        &ctx.src,
-                        module_name.to_string(),
-                        span.into(),
-                        already_imported.span(),
-                    ));
+                    ctx.warnings.push(
+                        WarningInfo::duplicate_import(
+                            module_name.to_string(),
+                            span.into(),
+                            already_imported.span(),
+                        )
+                        .into(),
+                    );
                 }
 
                 imports.push(Import {
@@ -312,6 +310,20 @@ pub(crate) fn namespace_from_cst<'src>(
                     module_name: module_name.to_string(),
                 });
             }
+            // ... include statements...
+            GrammarRule::include_stmt => {
+                let span = node.as_span();
+                let mut children = node.into_inner();
+                expect!(children.next().unwrap(), GrammarRule::k_INCLUDE);
+
+                let file_name =
+                    utf8_string_lit_from_cst(children.next().unwrap())?;
+
+                includes.push(Include {
+                    span: span.into(),
+                    file_name: file_name.to_string(),
+                });
+            }
             // .. or rule declarations.
             GrammarRule::rule_decl => {
                 let new_rule = rule_from_cst(ctx, node)?;
@@ -321,8 +333,6 @@ pub(crate) fn namespace_from_cst<'src>(
                     let existing_rule = &rules[*index];
 
                     return Err(Error::new(ErrorInfo::duplicate_rule(
-                        ctx.report_builder,
-                        &ctx.src,
                         new_rule.identifier.name.to_string(),
                         new_rule.identifier.span,
                         existing_rule.identifier.span,
@@ -338,17 +348,18 @@ pub(crate) fn namespace_from_cst<'src>(
             rule => unreachable!("unexpected grammar rule: `{:?}`", rule),
         }
     }
-    Ok(Namespace { rules, imports })
+    Ok(Namespace { rules, imports, includes })
 }
 
 /// Given a CST node corresponding to the grammar rule` rule_decl`, returns a
 /// [`Rule`] structure describing the rule.
 fn rule_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     rule_decl: CSTNode<'src>,
 ) -> Result<Rule<'src>, Error> {
     expect!(rule_decl, GrammarRule::rule_decl);
 
+    let span = Span::from(rule_decl.as_span());
     let mut children = rule_decl.into_inner();
     let mut node = children.next().unwrap();
     let mut flags = RuleFlags::none();
@@ -392,28 +403,15 @@ fn rule_from_cst<'src>(
     // ├─ ident "bar"
     // └─ ident "baz"
     //
+    // Duplicate tags are accepted here, the compiler warns about them.
     let tags = if let GrammarRule::rule_tags = node.as_rule() {
-        let mut tags = HashSet::new();
-
         // Iterate over all `ident`s that are children of `rule_tags`,
         // ignoring other grammar rules like `COLON`.
-        let idents = node
+        let tags = node
             .into_inner()
-            .filter(|item| item.as_rule() == GrammarRule::ident);
-
-        for ident in idents {
-            if !tags.insert(ident.as_str()) {
-                return Err(Error::new(ErrorInfo::duplicate_tag(
-                    ctx.report_builder,
-                    &ctx.src,
-                    ident.as_str().to_string(),
-                    Span {
-                        start: ident.as_span().start(),
-                        end: ident.as_span().end(),
-                    },
-                )));
-            }
-        }
+            .filter(|item| item.as_rule() == GrammarRule::ident)
+            .map(Ident::from)
+            .collect();
 
         node = children.next().unwrap();
 
@@ -428,7 +426,7 @@ fn rule_from_cst<'src>(
 
     // Process the `meta` section, if any.
     let meta = if let GrammarRule::meta_defs = node.as_rule() {
-        let meta = meta_from_cst(ctx, node)?;
+        let meta = meta_from_cst(node)?;
         node = children.next().unwrap();
         Some(meta)
     } else {
@@ -465,8 +463,6 @@ fn rule_from_cst<'src>(
     if let Some(ident) = unused_pattern {
         let ident = ctx.declared_patterns.get(ident).unwrap();
         return Err(Error::new(ErrorInfo::unused_pattern(
-            ctx.report_builder,
-            &ctx.src,
             ident.name.to_string(),
             ident.span,
         )));
@@ -482,13 +478,13 @@ fn rule_from_cst<'src>(
     // Nothing more after the closing brace.
     assert!(children.next().is_none());
 
-    Ok(Rule { flags, identifier, tags, meta, patterns, condition })
+    Ok(Rule { span, flags, identifier, tags, meta, patterns, condition })
 }
 
 /// Given a CST node corresponding to the grammar rule` pattern_defs`, returns
 /// a vector of [`Pattern`] structs describing the defined strings.
 fn patterns_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     pattern_defs: CSTNode<'src>,
 ) -> Result<Vec<Pattern<'src>>, Error> {
     expect!(pattern_defs, GrammarRule::pattern_defs);
@@ -514,8 +510,6 @@ fn patterns_from_cst<'src>(
                 ctx.declared_patterns.get(&new_pattern_ident.name[1..])
             {
                 return Err(Error::new(ErrorInfo::duplicate_pattern(
-                    ctx.report_builder,
-                    &ctx.src,
                     new_pattern_ident.name.to_string(),
                     new_pattern_ident.span,
                     existing_pattern_ident.span,
@@ -544,7 +538,7 @@ fn patterns_from_cst<'src>(
 /// Given a CST node corresponding to the grammar rule `pattern_def`, returns
 /// a [`Pattern`] struct describing the defined pattern.
 fn pattern_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     pattern_def: CSTNode<'src>,
 ) -> Result<Pattern<'src>, Error> {
     expect!(pattern_def, GrammarRule::pattern_def);
@@ -583,11 +577,7 @@ fn pattern_from_cst<'src>(
             expect!(hex_pattern.next().unwrap(), GrammarRule::RBRACE);
 
             let modifiers = if let Some(modifiers) = children.next() {
-                pattern_mods_from_cst(
-                    ctx,
-                    GrammarRule::hex_pattern,
-                    modifiers,
-                )?
+                pattern_mods_from_cst(GrammarRule::hex_pattern, modifiers)?
             } else {
                 PatternModifiers::default()
             };
@@ -601,9 +591,9 @@ fn pattern_from_cst<'src>(
         }
         GrammarRule::string_lit => {
             let span = node.as_span().into();
-            let value = string_lit_from_cst(ctx, node, true)?;
+            let value = string_lit_from_cst(node, true)?;
             let modifiers = if let Some(modifiers) = children.next() {
-                pattern_mods_from_cst(ctx, GrammarRule::string_lit, modifiers)?
+                pattern_mods_from_cst(GrammarRule::string_lit, modifiers)?
             } else {
                 PatternModifiers::default()
             };
@@ -618,8 +608,6 @@ fn pattern_from_cst<'src>(
 
             if value.len() < min_len {
                 return Err(Error::new(ErrorInfo::invalid_pattern(
-                    ctx.report_builder,
-                    &ctx.src,
                     ctx.current_pattern_ident(),
                     "this pattern is too short".to_string(),
                     span,
@@ -639,11 +627,27 @@ fn pattern_from_cst<'src>(
             }))
         }
         GrammarRule::regexp => {
+            let span = node.as_span().into();
+            let regexp = regexp_from_cst(node)?;
             let modifiers = if let Some(modifiers) = children.next() {
-                pattern_mods_from_cst(ctx, GrammarRule::regexp, modifiers)?
+                pattern_mods_from_cst(GrammarRule::regexp, modifiers)?
             } else {
                 PatternModifiers::default()
             };
+
+            // A regexp that matches the empty string would match at every
+            // offset of the scanned data, without consuming any byte.
+            let body = &regexp.regexp[1..regexp.regexp.rfind('/').unwrap()];
+
+            if matches_empty(body) {
+                return Err(Error::new(ErrorInfo::invalid_pattern(
+                    ctx.current_pattern_ident(),
+                    "this regexp can match an empty string".to_string(),
+                    span,
+                    Some("patterns must match at least one byte".to_string()),
+                )));
+            }
+
             // Take the identifier and set ctx.current_pattern
             // to None.
             let identifier = ctx.current_pattern.take().unwrap();
@@ -651,8 +655,8 @@ fn pattern_from_cst<'src>(
             Pattern::Regexp(Box::new(RegexpPattern {
                 identifier,
                 modifiers,
-                span: node.as_span().into(),
-                regexp: regexp_from_cst(ctx, node)?,
+                span,
+                regexp,
             }))
         }
         rule => unreachable!("{:?}", rule),
@@ -664,7 +668,6 @@ fn pattern_from_cst<'src>(
 /// Given a CST node corresponding to the grammar rule `regexp`, returns the
 /// corresponding [`Regexp`] struct describing the regexp.
 fn regexp_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
     regexp: CSTNode<'src>,
 ) -> Result<Regexp<'src>, Error> {
     let re = regexp.as_str();
@@ -688,8 +691,6 @@ fn regexp_from_cst<'src>(
                 let span = regexp.as_span();
 
                 return Err(Error::new(ErrorInfo::invalid_regexp_modifier(
-                    ctx.report_builder,
-                    &ctx.src,
                     format!("{}", c),
                     Span {
                         start: span.start() + after_closing_slash + i,
@@ -715,7 +716,6 @@ fn regexp_from_cst<'src>(
 /// Given a CST node corresponding to the grammar rule `pattern_mods`, returns
 /// a [`PatternModifiers`] struct describing the modifiers.
 fn pattern_mods_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
     rule_type: GrammarRule,
     pattern_mods: CSTNode<'src>,
 ) -> Result<PatternModifiers<'src>, Error> {
@@ -755,7 +755,7 @@ fn pattern_mods_from_cst<'src>(
                         let lower_bound_span = node.as_span().into();
 
                         // Parse the integer after the opening parenthesis `(`.
-                        lower_bound = integer_lit_from_cst::<u8>(ctx, node)?;
+                        lower_bound = integer_lit_from_cst::<u8>(node)?;
 
                         // See what comes next, it could be a hyphen `-` or the
                         // closing parenthesis `)`
@@ -768,7 +768,6 @@ fn pattern_mods_from_cst<'src>(
                             // hyphen.
                             GrammarRule::HYPHEN => {
                                 let integer = integer_lit_from_cst::<u8>(
-                                    ctx,
                                     children.next().unwrap(),
                                 )?;
                                 expect!(
@@ -781,10 +780,7 @@ fn pattern_mods_from_cst<'src>(
                         };
 
                         if lower_bound > upper_bound {
-                            return Err(Error::new(ErrorInfo::invalid_range(
-                               ctx.report_builder,
-                               &ctx.src,
-                               format!(
+                            return Err(Error::new(ErrorInfo::invalid_range(format!(
                                    "lower bound ({}) is greater than upper bound ({})",
                                    lower_bound, upper_bound),
                                 lower_bound_span,
@@ -806,14 +802,12 @@ fn pattern_mods_from_cst<'src>(
                         children.next().unwrap();
                         let node = children.next().unwrap();
                         let span = node.as_span().into();
-                        let lit = utf8_string_lit_from_cst(ctx, node)?;
+                        let lit = utf8_string_lit_from_cst(node)?;
 
                         // Make sure the base64 alphabet is a valid one.
                         if let Err(e) = base64::alphabet::Alphabet::new(lit) {
                             return Err(Error::new(
                                 ErrorInfo::invalid_base_64_alphabet(
-                                    ctx.report_builder,
-                                    &ctx.src,
                                     e.to_string().to_lowercase(),
                                     span,
                                 ),
@@ -842,18 +836,14 @@ fn pattern_mods_from_cst<'src>(
 
         let span = modifier.span();
         if modifiers.insert(node.as_str(), modifier).is_some() {
-            return Err(Error::new(ErrorInfo::duplicate_modifier(
-                ctx.report_builder,
-                &ctx.src,
-                span,
-            )));
+            return Err(Error::new(ErrorInfo::duplicate_modifier(span)));
         }
     }
 
     let modifiers = PatternModifiers::new(modifiers);
 
     // Check for invalid combinations of modifiers.
-    check_pattern_modifiers(ctx, rule_type, &modifiers)?;
+    check_pattern_modifiers(rule_type, &modifiers)?;
 
     Ok(modifiers)
 }
@@ -861,7 +851,6 @@ fn pattern_mods_from_cst<'src>(
 /// Given a CST node corresponding to the grammar rule` meta_defs`, returns
 /// a vector of [`Meta`] structs describing the defined metadata.
 fn meta_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
     meta_defs: CSTNode<'src>,
 ) -> Result<Vec<Meta<'src>>, Error> {
     expect!(meta_defs, GrammarRule::meta_defs);
@@ -888,12 +877,14 @@ fn meta_from_cst<'src>(
             GrammarRule::k_TRUE => MetaValue::Bool(true),
             GrammarRule::k_FALSE => MetaValue::Bool(false),
             GrammarRule::integer_lit => {
-                MetaValue::Integer(integer_lit_from_cst(ctx, value_node)?)
+                MetaValue::Integer(integer_lit_from_cst(value_node)?)
             }
             GrammarRule::float_lit => {
-                MetaValue::Float(float_lit_from_cst(ctx, value_node)?)
+                MetaValue::Float(float_lit_from_cst(value_node)?)
+            }
+            GrammarRule::string_lit => {
+                MetaValue::String(string_lit_from_cst(value_node, true)?)
             }
-            GrammarRule::string_lit => MetaValue::String(value_node.as_str()),
             rule => unreachable!("{:?}", rule),
         };
 
@@ -975,7 +966,7 @@ lazy_static! {
 /// From a CST node corresponding to the grammar rule `boolean_expr`, returns
 /// an [`Expr`] describing the boolean expression.
 fn boolean_expr_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     boolean_expr: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(boolean_expr, GrammarRule::boolean_expr);
@@ -1003,7 +994,7 @@ fn boolean_expr_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `boolean_term`, returns
 /// an [`Expr`] describing the boolean term.
 fn boolean_term_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     boolean_term: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(boolean_term, GrammarRule::boolean_term);
@@ -1058,22 +1049,7 @@ fn boolean_term_from_cst<'src>(
             let ident_name = ident.as_str();
             let anchor = anchor_from_cst(ctx, children)?;
 
-            // The use of `$` in the condition doesn't mean that all anonymous
-            // pattern identifiers are used. Anonymous pattern identifiers are
-            // considered used when the `them` keyword is used, or when the
-            // pattern `$*` appears in a pattern identifiers tuple.
-            if ident_name != "$" {
-                ctx.unused_patterns.remove(&ident_name[1..]);
-            }
-            // `$` used outside a `for .. of` statement, that's invalid.
-            else if !ctx.inside_for_of {
-                return Err(Error::new(ErrorInfo::syntax_error(
-                    ctx.report_builder,
-                    &ctx.src,
-                    "this `$` is outside of the condition of a `for .. of` statement".to_string(),
-                    ident.as_span().into(),
-                )));
-            }
+            use_pattern(ctx, ident_name, ident.as_span().into())?;
 
             Expr::PatternMatch(Box::new(PatternMatch {
                 // TODO: this is not the best way of computing the span for
@@ -1092,6 +1068,26 @@ fn boolean_term_from_cst<'src>(
             }))
         }
         GrammarRule::expr => {
+            let children: Vec<CSTNode> = children.collect();
+
+            // Set membership expressions like `<expr> in <ident>` are not
+            // handled by the Pratt parser, they are the only case in which
+            // the `expr` is followed by the `in` keyword.
+            if let [_, k_in, _] = children.as_slice() {
+                if k_in.as_rule() == GrammarRule::k_IN {
+                    let mut children = children.into_iter();
+                    let item = expr_from_cst(ctx, children.next().unwrap())?;
+                    let set = Ident::from(children.nth(1).unwrap());
+
+                    return Ok(Expr::InSet(Box::new(InSet {
+                        span: boolean_term_span.into(),
+                        type_value: TypeValue::Bool(None),
+                        item,
+                        set,
+                    })));
+                }
+            }
+
             // See comments in `boolean_expr_from_cst` for some explanation
             // of the logic below.
             PRATT_PARSER
@@ -1108,7 +1104,7 @@ fn boolean_term_from_cst<'src>(
                         create_binary_expr(lhs?, op.as_rule(), rhs?)
                     },
                 )
-                .parse(children.map(|node| node.into_pair()))?
+                .parse(children.into_iter().map(|node| node.into_pair()))?
         }
         GrammarRule::of_expr => {
             of_expr_from_cst(ctx, children.next().unwrap())?
@@ -1125,7 +1121,7 @@ fn boolean_term_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `expr`, returns an
 /// [`Expr`] describing the expression.
 fn expr_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     expr: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(expr, GrammarRule::expr);
@@ -1155,7 +1151,7 @@ fn expr_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `term` , returns
 /// an [`Expr`] describing the term.
 fn term_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     term: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(term, GrammarRule::term);
@@ -1179,7 +1175,7 @@ fn term_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `primary_expr` , returns
 /// an [`Expr`] describing the expression.
 fn primary_expr_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     primary_expr: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     // The CST node passed to this function must correspond to a primary
@@ -1241,22 +1237,21 @@ fn primary_expr_from_cst<'src>(
             node.as_span().as_str(),
             node.as_span().into(),
             TypeValue::String(Some(
-                string_lit_from_cst(ctx, node, true)?.into_owned(),
+                string_lit_from_cst(node, true)?.into_owned(),
             )),
         ))),
         GrammarRule::float_lit => Expr::Literal(Box::new(Literal::new(
             node.as_span().as_str(),
             node.as_span().into(),
-            TypeValue::Float(Some(float_lit_from_cst(ctx, node)?)),
+            TypeValue::Float(Some(float_lit_from_cst(node)?)),
         ))),
         GrammarRule::integer_lit => Expr::Literal(Box::new(Literal::new(
             node.as_span().as_str(),
             node.as_span().into(),
-            TypeValue::Integer(Some(integer_lit_from_cst(ctx, node)?)),
+            TypeValue::Integer(Some(integer_lit_from_cst(node)?)),
         ))),
-        GrammarRule::regexp => {
-            Expr::Regexp(Box::new(regexp_from_cst(ctx, node)?))
-        }
+        GrammarRule::regexp => Expr::Regexp(Box::new(regexp_from_cst(node)?)),
+        GrammarRule::count_of_expr => count_of_expr_from_cst(ctx, node)?,
         GrammarRule::pattern_count => {
             // Is there some range after the pattern count?
             // Example: #a in (0..10)
@@ -1269,9 +1264,7 @@ fn primary_expr_from_cst<'src>(
 
             let ident_name = node.as_span().as_str();
 
-            // Remove from ctx.unused_patterns, indicating that the
-            // identifier has been used.
-            ctx.unused_patterns.remove(&ident_name[1..]);
+            use_pattern(ctx, ident_name, node.as_span().into())?;
 
             Expr::PatternCount(Box::new(IdentWithRange {
                 span: term_span.into(),
@@ -1301,9 +1294,7 @@ fn primary_expr_from_cst<'src>(
 
             let ident_name = node.as_span().as_str();
 
-            // Remove from ctx.unused_patterns, indicating that the
-            // identifier has been used.
-            ctx.unused_patterns.remove(&ident_name[1..]);
+            use_pattern(ctx, ident_name, node.as_span().into())?;
 
             expr_type(Box::new(IdentWithIndex {
                 span: term_span.into(),
@@ -1320,7 +1311,7 @@ fn primary_expr_from_cst<'src>(
 }
 
 fn indexing_expr_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     indexing_expr: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(indexing_expr, GrammarRule::indexing_expr);
@@ -1345,7 +1336,7 @@ fn indexing_expr_from_cst<'src>(
 }
 
 fn func_call_expr_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     func_call_expr: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(func_call_expr, GrammarRule::func_call_expr);
@@ -1396,7 +1387,7 @@ fn func_call_expr_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `range`, returns a
 /// [`Range`] with the lower and upper bounds of the range.
 fn range_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     range: CSTNode<'src>,
 ) -> Result<Range<'src>, Error> {
     expect!(range, GrammarRule::range);
@@ -1420,10 +1411,68 @@ fn range_from_cst<'src>(
     Ok(Range { span: range_span.into(), lower_bound, upper_bound })
 }
 
+/// Marks the pattern referenced by `ident` as used in the condition. The
+/// identifier includes its prefix (i.e: `$a`, `#a`, `@a`, `!a`). When the
+/// prefix appears alone, the identifier refers to the pattern in the current
+/// iteration of a `for .. of` loop, and it is ignored.
+///
+/// Returns an error if the current rule doesn't declare the pattern.
+fn use_pattern<'src>(
+    ctx: &mut Context<'src>,
+    ident: &'src str,
+    span: Span,
+) -> Result<(), Error> {
+    let name = &ident[1..];
+
+    // Anonymous identifiers like `$`, `#`, `@` and `!` refer to the pattern
+    // being iterated by a `for .. of` statement, and they are valid only
+    // in the statement's condition. The use of `$` in the condition doesn't
+    // mean that all anonymous pattern identifiers are used. Anonymous pattern
+    // identifiers are considered used when the `them` keyword is used, or when
+    // the pattern `$*` appears in a pattern identifiers tuple.
+    if name.is_empty() {
+        if !ctx.inside_for_of {
+            return Err(Error::new(ErrorInfo::syntax_error(
+                format!(
+                    "this `{}` is outside of the condition of a `for .. of` statement",
+                    ident
+                ),
+                span,
+            )));
+        }
+        return Ok(());
+    }
+
+    if !ctx.declared_patterns.contains_key(name) {
+        return Err(Error::new(ErrorInfo::unknown_pattern(
+            ident.to_string(),
+            span,
+        )));
+    }
+
+    ctx.unused_patterns.remove(name);
+
+    Ok(())
+}
+
+/// Marks all the patterns in the current rule as used, as a result of using
+/// the `them` keyword in the condition.
+///
+/// Returns an error if the current rule doesn't declare any pattern.
+fn use_them(ctx: &mut Context, span: Span) -> Result<(), Error> {
+    if ctx.declared_patterns.is_empty() {
+        return Err(Error::new(ErrorInfo::no_patterns(span)));
+    }
+
+    ctx.unused_patterns.clear();
+
+    Ok(())
+}
+
 /// From a CST node corresponding to the grammar rule `of_expr`, returns
 /// an [`Expr`] describing the `of` statement.
 fn of_expr_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     of_expr: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(of_expr, GrammarRule::of_expr);
@@ -1439,8 +1488,7 @@ fn of_expr_from_cst<'src>(
 
     let items = match node.as_rule() {
         GrammarRule::k_THEM => {
-            // `them` was used in the condition, all the patterns are used.
-            ctx.unused_patterns.clear();
+            use_them(ctx, node.as_span().into())?;
             OfItems::PatternSet(PatternSet::Them)
         }
         GrammarRule::pattern_ident_tuple => OfItems::PatternSet(
@@ -1460,10 +1508,45 @@ fn of_expr_from_cst<'src>(
     Ok(Expr::Of(Box::new(Of { span: span.into(), quantifier, items, anchor })))
 }
 
+/// From a CST node corresponding to the grammar rule `count_of_expr`,
+/// returns an [`Expr`] describing the `count_of` expression.
+fn count_of_expr_from_cst<'src>(
+    ctx: &mut Context<'src>,
+    count_of_expr: CSTNode<'src>,
+) -> Result<Expr<'src>, Error> {
+    expect!(count_of_expr, GrammarRule::count_of_expr);
+
+    let span = count_of_expr.as_span();
+    let mut children = count_of_expr.into_inner();
+
+    expect!(children.next().unwrap(), GrammarRule::k_COUNT_OF);
+
+    let node = children.next().unwrap();
+
+    let pattern_set = match node.as_rule() {
+        GrammarRule::LPAREN => {
+            let them = children.next().unwrap();
+            expect!(them, GrammarRule::k_THEM);
+            expect!(children.next().unwrap(), GrammarRule::RPAREN);
+            use_them(ctx, them.as_span().into())?;
+            PatternSet::Them
+        }
+        GrammarRule::pattern_ident_tuple => {
+            PatternSet::Set(pattern_ident_tuple(ctx, node)?)
+        }
+        rule => unreachable!("{:?}", rule),
+    };
+
+    // Make sure that there are no more nodes.
+    assert!(children.next().is_none());
+
+    Ok(Expr::CountOf(Box::new(CountOf { span: span.into(), pattern_set })))
+}
+
 /// From a CST node corresponding to the grammar rule `for_expr`, returns
 /// an [`Expr`] describing the `for` statement.
 fn for_expr_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     for_expr: CSTNode<'src>,
 ) -> Result<Expr<'src>, Error> {
     expect!(for_expr, GrammarRule::for_expr);
@@ -1481,6 +1564,10 @@ fn for_expr_from_cst<'src>(
     let mut iterator = None;
     let mut variables = Vec::new();
 
+    // `for .. of` statements can be nested, `inside_for_of` must be restored
+    // after processing the condition of the inner statement.
+    let inside_for_of = ctx.inside_for_of;
+
     if let GrammarRule::k_OF = children.peek().unwrap().as_rule() {
         // Consume the `of` keyword.
         children.next().unwrap();
@@ -1488,7 +1575,10 @@ fn for_expr_from_cst<'src>(
         // identifiers.
         let node = children.next().unwrap();
         pattern_set = Some(match node.as_rule() {
-            GrammarRule::k_THEM => PatternSet::Them,
+            GrammarRule::k_THEM => {
+                use_them(ctx, node.as_span().into())?;
+                PatternSet::Them
+            }
             GrammarRule::pattern_ident_tuple => {
                 PatternSet::Set(pattern_ident_tuple(ctx, node)?)
             }
@@ -1526,7 +1616,7 @@ fn for_expr_from_cst<'src>(
 
     let condition = boolean_expr_from_cst(ctx, children.next().unwrap())?;
 
-    ctx.inside_for_of = false;
+    ctx.inside_for_of = inside_for_of;
 
     expect!(children.next().unwrap(), GrammarRule::RPAREN);
 
@@ -1553,7 +1643,7 @@ fn for_expr_from_cst<'src>(
 }
 
 fn anchor_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     mut iter: impl Iterator<Item = CSTNode<'src>>,
 ) -> Result<Option<MatchAnchor<'src>>, Error> {
     let anchor = if let Some(node) = iter.next() {
@@ -1583,7 +1673,7 @@ fn anchor_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `quantifier`, returns
 /// a [`Quantifier`].
 fn quantifier_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     quantifier: CSTNode<'src>,
 ) -> Result<Quantifier<'src>, Error> {
     expect!(quantifier, GrammarRule::quantifier);
@@ -1620,7 +1710,7 @@ fn quantifier_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `pattern_ident_tuple`, returns
 /// a vector of [`PatternSetItem`].
 fn pattern_ident_tuple<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     pattern_ident_tuple: CSTNode<'src>,
 ) -> Result<Vec<PatternSetItem<'src>>, Error> {
     expect!(pattern_ident_tuple, GrammarRule::pattern_ident_tuple);
@@ -1642,13 +1732,27 @@ fn pattern_ident_tuple<'src>(
                 // Notice however that the `$` is ignored.
                 let pattern = &node.as_str()[1..];
 
-                if let Some(prefix) = pattern.strip_suffix('*') {
+                let declared = if let Some(prefix) = pattern.strip_suffix('*')
+                {
                     // If the pattern has a wildcard, remove all identifiers
                     // that starts with the prefix before the wildcard.
                     ctx.unused_patterns
                         .retain(|ident| !ident.starts_with(prefix));
+
+                    ctx.declared_patterns
+                        .keys()
+                        .any(|ident| ident.starts_with(prefix))
                 } else {
                     ctx.unused_patterns.remove(pattern);
+                    ctx.declared_patterns.contains_key(pattern)
+                };
+
+                // Each item in the tuple must refer to some pattern.
+                if !declared {
+                    return Err(Error::new(ErrorInfo::unknown_pattern(
+                        node.as_str().to_string(),
+                        node.as_span().into(),
+                    )));
                 }
 
                 result.push(PatternSetItem {
@@ -1672,7 +1776,7 @@ fn pattern_ident_tuple<'src>(
 /// From a CST node corresponding to the grammar rule `boolean_expr_tuple`, returns
 /// a vector of [`Expr`].
 fn boolean_expr_tuple_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     boolean_expr_tuple: CSTNode<'src>,
 ) -> Result<Vec<Expr<'src>>, Error> {
     expect!(boolean_expr_tuple, GrammarRule::boolean_expr_tuple);
@@ -1707,7 +1811,7 @@ fn boolean_expr_tuple_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `expr_tuple`, returns
 /// a vector of [`Expr`].
 fn expr_tuple_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     expr_tuple: CSTNode<'src>,
 ) -> Result<Vec<Expr<'src>>, Error> {
     expect!(expr_tuple, GrammarRule::expr_tuple);
@@ -1742,7 +1846,7 @@ fn expr_tuple_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `iterable`, returns
 /// a [`Iterable`].
 fn iterator_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     iterator: CSTNode<'src>,
 ) -> Result<Iterable<'src>, Error> {
     expect!(iterator, GrammarRule::iterable);
@@ -1763,7 +1867,6 @@ fn iterator_from_cst<'src>(
 /// the the corresponding integer. This is a generic function that can be used
 /// for obtaining any type of integer, like u8, i64, etc.
 fn integer_lit_from_cst<'src, T>(
-    ctx: &mut Context<'src, '_>,
     integer_lit: CSTNode<'src>,
 ) -> Result<T, Error>
 where
@@ -1800,8 +1903,6 @@ where
 
     let build_error = || {
         Error::new(ErrorInfo::invalid_integer(
-            ctx.report_builder,
-            &ctx.src,
             format!(
                 "this number is out of the valid range: [{}, {}]",
                 T::min_value(),
@@ -1829,22 +1930,14 @@ where
 
 /// From a CST node corresponding to the grammar rule `float_lit`, returns
 /// the `f64` representing the literal.
-fn float_lit_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
-    float_lit: CSTNode<'src>,
-) -> Result<f64, Error> {
+fn float_lit_from_cst<'src>(float_lit: CSTNode<'src>) -> Result<f64, Error> {
     expect!(float_lit, GrammarRule::float_lit);
 
     let literal = float_lit.as_str();
     let span = float_lit.as_span().into();
 
     literal.parse::<f64>().map_err(|err| {
-        Error::new(ErrorInfo::invalid_float(
-            ctx.report_builder,
-            &ctx.src,
-            err.to_string(),
-            span,
-        ))
+        Error::new(ErrorInfo::invalid_float(err.to_string(), span))
     })
 }
 
@@ -1865,7 +1958,6 @@ fn float_lit_from_cst<'src>(
 /// When called with `allow_escaped_char: false`, the returned string can be
 /// safely converted to [`String`] or &[`str`].
 fn string_lit_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
     string_lit: CSTNode<'src>,
     allow_escape_char: bool,
 ) -> Result<Cow<'src, BStr>, Error> {
@@ -1888,8 +1980,6 @@ fn string_lit_from_cst<'src>(
         return Ok(Cow::from(BStr::new(literal)));
     } else if !allow_escape_char {
         return Err(Error::new(ErrorInfo::unexpected_escape_sequence(
-            ctx.report_builder,
-            &ctx.src,
             string_lit.as_span().into(),
         )));
     }
@@ -1917,8 +2007,6 @@ fn string_lit_from_cst<'src>(
                 if next_byte.is_none() {
                     return Err(Error::new(
                         ErrorInfo::invalid_escape_sequence(
-                            ctx.report_builder,
-                            &ctx.src,
                             r"missing escape sequence after `\`".to_string(),
                             Span {
                                 start: literal_start + backslash_pos,
@@ -1947,8 +2035,6 @@ fn string_lit_from_cst<'src>(
                             } else {
                                 return Err(Error::new(
                                     ErrorInfo::invalid_escape_sequence(
-                                        ctx.report_builder,
-                                        &ctx.src,
                                         format!(
                                             r"invalid hex value `{}` after `\x`",
                                             &literal[start..=end]
@@ -1964,8 +2050,6 @@ fn string_lit_from_cst<'src>(
                         _ => {
                             return Err(Error::new(
                                 ErrorInfo::invalid_escape_sequence(
-                                    ctx.report_builder,
-                                    &ctx.src,
                                     r"expecting two hex digits after `\x`"
                                         .to_string(),
                                     Span {
@@ -1979,8 +2063,6 @@ fn string_lit_from_cst<'src>(
                     _ => {
                         return Err(Error::new(
                             ErrorInfo::invalid_escape_sequence(
-                                ctx.report_builder,
-                                &ctx.src,
                                 format!(
                                     "invalid escape sequence `{}`",
                                     &literal[backslash_pos..backslash_pos + 2]
@@ -2005,14 +2087,13 @@ fn string_lit_from_cst<'src>(
 /// This function is similar [`string_lit_from_cst`] but guarantees that the
 /// string is a valid UTF-8 string.
 fn utf8_string_lit_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
     string_lit: CSTNode<'src>,
 ) -> Result<&'src str, Error> {
     // Call string_lit_from_cst with allow_escape_char set to false. This
     // guarantees that the returned string is borrowed from the source code
     // and is valid UTF-8, therefore is safe to convert it to &str without
     // additional checks.
-    match string_lit_from_cst(ctx, string_lit, false)? {
+    match string_lit_from_cst(string_lit, false)? {
         Cow::Borrowed(a) => unsafe { Ok(a.to_str_unchecked()) },
         _ => unreachable!(),
     }
@@ -2021,7 +2102,7 @@ fn utf8_string_lit_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `hex_pattern`, returns
 /// the [`HexPattern`] representing it.
 fn hex_pattern_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     hex_tokens: CSTNode<'src>,
 ) -> Result<HexTokens, Error> {
     expect!(hex_tokens, GrammarRule::hex_tokens);
@@ -2066,8 +2147,6 @@ fn hex_pattern_from_cst<'src>(
                     // allows this case, even if invalid, precisely for detecting
                     // it here and providing a meaningful error message.
                     return Err(Error::new(ErrorInfo::invalid_pattern(
-                        ctx.report_builder,
-                        &ctx.src,
                         ctx.current_pattern_ident(),
                         "uneven number of nibbles".to_string(),
                         node.as_span().into(),
@@ -2078,8 +2157,6 @@ fn hex_pattern_from_cst<'src>(
                 // ~?? is not allowed.
                 if negated && mask == 0x00 {
                     return Err(Error::new(ErrorInfo::invalid_pattern(
-                        ctx.report_builder,
-                        &ctx.src,
                         ctx.current_pattern_ident(),
                         "negation of `??` is not allowed".to_string(),
                         node.as_span().into(),
@@ -2097,7 +2174,7 @@ fn hex_pattern_from_cst<'src>(
             )),
             GrammarRule::hex_jump => {
                 let mut jump_span: Span = node.as_span().into();
-                let mut jump = hex_jump_from_cst(ctx, node)?;
+                let mut jump = hex_jump_from_cst(node)?;
                 let mut consecutive_jumps = false;
 
                 // If there are two consecutive jumps they will be coalesced
@@ -2108,7 +2185,6 @@ fn hex_pattern_from_cst<'src>(
                     }
                     let span = node.as_span();
                     jump.coalesce(hex_jump_from_cst(
-                        ctx,
                         children.next().unwrap(),
                     )?);
                     jump_span = jump_span.combine(&span.into());
@@ -2116,21 +2192,19 @@ fn hex_pattern_from_cst<'src>(
                 }
 
                 if consecutive_jumps {
-                    ctx.warnings.push(Warning::consecutive_jumps(
-                        ctx.report_builder,
-                        &ctx.src,
-                        ctx.current_pattern_ident(),
-                        format!("{}", jump),
-                        jump_span,
-                    ));
+                    ctx.warnings.push(
+                        WarningInfo::consecutive_jumps(
+                            ctx.current_pattern_ident(),
+                            format!("{}", jump),
+                            jump_span,
+                        )
+                        .into(),
+                    );
                 }
 
                 if let (Some(start), Some(end)) = (jump.start, jump.end) {
                     if start > end {
-                        return Err(Error::new(ErrorInfo::invalid_pattern(
-                            ctx.report_builder,
-                            &ctx.src,
-                            ctx.current_pattern_ident(),
+                        return Err(Error::new(ErrorInfo::invalid_pattern(ctx.current_pattern_ident(),
                             format!(
                                 "lower bound ({}) is greater than upper bound ({})",
                                 start, end),
@@ -2157,10 +2231,7 @@ fn hex_pattern_from_cst<'src>(
 
 /// From a CST node corresponding to the grammar rule `hex_jump`, returns
 /// the [`HexPattern`] representing it.
-fn hex_jump_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
-    hex_jump: CSTNode<'src>,
-) -> Result<HexJump, Error> {
+fn hex_jump_from_cst<'src>(hex_jump: CSTNode<'src>) -> Result<HexJump, Error> {
     expect!(hex_jump, GrammarRule::hex_jump);
 
     let mut children = hex_jump.into_inner();
@@ -2173,7 +2244,7 @@ fn hex_jump_from_cst<'src>(
     let mut end = None;
 
     if let GrammarRule::integer_lit = node.as_rule() {
-        start = Some(integer_lit_from_cst(ctx, node)?);
+        start = Some(integer_lit_from_cst(node)?);
     };
 
     node = children.next().unwrap();
@@ -2181,7 +2252,7 @@ fn hex_jump_from_cst<'src>(
     if let GrammarRule::HYPHEN = node.as_rule() {
         node = children.next().unwrap();
         if let GrammarRule::integer_lit = node.as_rule() {
-            end = Some(integer_lit_from_cst(ctx, node)?);
+            end = Some(integer_lit_from_cst(node)?);
             node = children.next().unwrap();
         }
     } else {
@@ -2196,7 +2267,7 @@ fn hex_jump_from_cst<'src>(
 /// From a CST node corresponding to the grammar rule `hex_alternative`, returns
 /// the [`HexAlternative`] representing it.
 fn hex_alternative_from_cst<'src>(
-    ctx: &mut Context<'src, '_>,
+    ctx: &mut Context<'src>,
     hex_alternative: CSTNode<'src>,
 ) -> Result<HexAlternative, Error> {
     expect!(hex_alternative, GrammarRule::hex_alternative);
@@ -2,11 +2,10 @@ use std::collections::{HashMap, HashSet};
 
 use crate::ast::Ident;
 use crate::parser::SourceCode;
-use crate::report::ReportBuilder;
 use crate::warnings::Warning;
 
 /// A structure that holds information about the parsing process.
-pub(crate) struct Context<'src, 'rb> {
+pub(crate) struct Context<'src> {
     /// The source code being parsed.
     pub(crate) src: SourceCode<'src>,
 
@@ -38,25 +37,18 @@ pub(crate) struct Context<'src, 'rb> {
     /// While parsing a pattern declaration this holds its identifier.
     pub(crate) current_pattern: Option<Ident<'src>>,
 
-    /// Used for building error messages and warnings.
-    pub(crate) report_builder: &'rb ReportBuilder,
-
     /// Warnings generated during the parsing process.
     pub(crate) warnings: Vec<Warning>,
 }
 
-impl<'src, 'rb> Context<'src, 'rb> {
-    pub(crate) fn new(
-        src: SourceCode<'src>,
-        report_builder: &'rb ReportBuilder,
-    ) -> Self {
+impl<'src> Context<'src> {
+    pub(crate) fn new(src: SourceCode<'src>) -> Self {
         Self {
             src,
             inside_for_of: false,
             declared_patterns: HashMap::new(),
             unused_patterns: HashSet::new(),
             current_pattern: None,
-            report_builder,
             warnings: Vec::new(),
         }
     }
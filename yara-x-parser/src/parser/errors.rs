@@ -1,56 +1,118 @@
 use std::fmt::{Debug, Display, Formatter};
+
+use pest::error::ErrorVariant::{CustomError, ParsingError};
+use pest::error::InputLocation;
 use yara_x_macros::Error as Err;
 
 use crate::ast::Span;
-use crate::report::ReportBuilder;
-use crate::report::ReportType;
+use crate::escape::terminal_safe;
+use crate::report::{Label, Report, ReportBuilder, ReportType};
 use crate::SourceCode;
 
 use super::GrammarRule;
 
 /// An error occurred while parsing YARA rules.
 ///
-/// Each variant also contains additional pieces of information that are
-/// relevant for that specific error. This information is usually contained
-/// inside the detailed report itself, but having access to the individual
-/// pieces is useful for applications that can't rely on text-based reports.
-pub struct Error(Box<ErrorInfo>);
+/// The error contains a detailed text-mode report, and an [`ErrorInfo`]
+/// with the individual pieces of information that are relevant for that
+/// specific error. This information is usually contained inside the detailed
+/// report itself, but having access to the individual pieces is useful for
+/// applications that can't rely on text-based reports.
+///
+/// [`Display`] produces a single-line summary (e.g: "syntax error"), the
+/// detailed report is returned by [`Error::as_str`].
+pub struct Error {
+    info: Box<ErrorInfo>,
+    labels: Vec<Label>,
+    report: String,
+}
 
 impl Error {
+    /// Creates a new error from an [`ErrorInfo`].
+    ///
+    /// The detailed report is not rendered here, but by [`Error::render`]
+    /// when the error is about to be returned by the parser. This way the
+    /// code that detects errors doesn't need to know about the source code
+    /// or the report builder.
     pub(crate) fn new(info: ErrorInfo) -> Self {
-        Self(Box::new(info))
+        Self {
+            info: Box::new(info),
+            labels: Vec::new(),
+            report: String::new(),
+        }
+    }
+
+    /// Renders the detailed report for the error.
+    pub(crate) fn render(
+        mut self,
+        report_builder: &ReportBuilder,
+        src: &SourceCode,
+    ) -> Self {
+        self.report = report_builder.render(src, self.info.as_ref());
+        self.labels = report_builder.labels(src, self.info.as_ref());
+        self
     }
 
     /// Returns a string with a detailed text-mode report like this one ...
     ///
     /// ```text
-    /// error: duplicate tag `tag1`
-    ///    ╭─[line:1:18]
+    /// error: unknown pattern `$b`
+    ///    ╭─[line:1:50]
     ///    │
-    ///  1 │ rule test : tag1 tag1 { condition: true }
-    ///    ·                  ──┬─
-    ///    ·                    ╰─── duplicate tag
+    ///  1 │ rule test { strings: $a = "foo" condition: $a or $b }
+    ///    ·                                                  ─┬
+    ///    ·                                                   ╰── this pattern is not declared in the `strings` section
     /// ───╯
     /// ```
     pub fn as_str(&self) -> &str {
-        self.0.as_str()
+        self.report.as_str()
     }
 
     /// Returns additional information about the error.
     pub fn info(&self) -> &ErrorInfo {
-        self.0.as_ref()
+        self.info.as_ref()
+    }
+
+    /// Returns the labels in the report. The first one is the main label.
+    pub fn labels(&self) -> &[Label] {
+        self.labels.as_slice()
+    }
+}
+
+impl From<pest::error::Error<GrammarRule>> for Error {
+    fn from(pest_error: pest::error::Error<GrammarRule>) -> Self {
+        // Start and ending offset within the original code that is going
+        // to be highlighted in the error message. The span can cover
+        // multiple lines.
+        let error_span = match pest_error.location {
+            InputLocation::Pos(p) => Span { start: p, end: p },
+            InputLocation::Span(span) => Span { start: span.0, end: span.1 },
+        };
+
+        let error_msg = match &pest_error.variant {
+            CustomError { message } => message.to_owned(),
+            ParsingError { positives, negatives } => {
+                ErrorInfo::syntax_error_message(
+                    positives,
+                    negatives,
+                    ErrorInfo::printable_string,
+                )
+            }
+        };
+
+        Error::new(ErrorInfo::syntax_error(error_msg, error_span))
     }
 }
 
 impl Debug for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.0)
+        write!(f, "{}", self.report)
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", terminal_safe(&self.info.title()))
     }
 }
 
@@ -58,24 +120,16 @@ impl std::error::Error for Error {}
 
 /// Additional information about an error occurred during parsing.
 #[rustfmt::skip]
-#[derive(Err)]
+#[derive(Err, Debug)]
+#[non_exhaustive]
 pub enum ErrorInfo {
     #[error("syntax error")]
     #[label("{error_msg}", error_span)]
     SyntaxError {
-        detailed_report: String,
         error_msg: String,
         error_span: Span
     },
 
-    #[error("duplicate tag `{tag}`")]
-    #[label("duplicate tag", tag_span)]
-    DuplicateTag {
-        detailed_report: String,
-        tag: String,
-        tag_span: Span,
-    },
-
     #[error("duplicate rule `{rule_ident}`")]
     #[label(
         "duplicate declaration of `{rule_ident}`",
@@ -87,7 +141,6 @@ pub enum ErrorInfo {
         style="note"
     )]
     DuplicateRule {
-        detailed_report: String,
         rule_ident: String,
         new_rule_name_span: Span,
         existing_rule_name_span: Span,
@@ -104,7 +157,6 @@ pub enum ErrorInfo {
         style="note"
     )]
     DuplicatePattern {
-        detailed_report: String,
         pattern_ident: String,
         new_pattern_span: Span,
         existing_pattern_span: Span,
@@ -113,7 +165,6 @@ pub enum ErrorInfo {
     #[error("invalid pattern modifier")]
     #[label("{error_msg}", error_span)]
     InvalidModifier {
-        detailed_report: String,
         error_msg: String,
         error_span: Span,
     },
@@ -121,7 +172,6 @@ pub enum ErrorInfo {
     #[error("duplicate pattern modifier")]
     #[label("duplicate modifier", modifier_span)]
     DuplicateModifier {
-        detailed_report: String,
         modifier_span: Span,
     },
 
@@ -132,7 +182,6 @@ pub enum ErrorInfo {
     #[label("`{modifier2}` modifier used here", modifier2_span)]
     #[note(note)]
     InvalidModifierCombination {
-        detailed_report: String,
         modifier1: String,
         modifier2: String,
         modifier1_span: Span,
@@ -143,23 +192,33 @@ pub enum ErrorInfo {
     #[error("invalid base64 alphabet")]
     #[label("{error_msg}", error_span)]
     InvalidBase64Alphabet {
-        detailed_report: String,
         error_msg: String,
         error_span: Span},
     
     #[error("unused pattern `{pattern_ident}`")]
     #[label("this pattern was not used in the condition", pattern_ident_span)]
     UnusedPattern {
-        detailed_report: String,
         pattern_ident: String,
         pattern_ident_span: Span,
     },
 
+    #[error("unknown pattern `{pattern_ident}`")]
+    #[label("this pattern is not declared in the `strings` section", pattern_ident_span)]
+    UnknownPattern {
+        pattern_ident: String,
+        pattern_ident_span: Span,
+    },
+
+    #[error("no patterns to refer to")]
+    #[label("`them` used in a rule without patterns", them_span)]
+    NoPatterns {
+        them_span: Span,
+    },
+
     #[error("invalid pattern `{pattern_ident}`")]
     #[label("{error_msg}", error_span)]
     #[note(note)]
     InvalidPattern {
-        detailed_report: String,
         pattern_ident: String,
         error_msg: String,
         error_span: Span,
@@ -169,7 +228,6 @@ pub enum ErrorInfo {
     #[error("invalid range")]
     #[label("{error_msg}", error_span)]
     InvalidRange {
-        detailed_report: String,
         error_msg: String,
         error_span: Span,
     },
@@ -177,7 +235,6 @@ pub enum ErrorInfo {
     #[error("invalid integer")]
     #[label("{error_msg}", error_span)]
     InvalidInteger {
-        detailed_report: String,
         error_msg: String,
         error_span: Span,
     },
@@ -185,7 +242,6 @@ pub enum ErrorInfo {
     #[error("invalid float")]
     #[label("{error_msg}", error_span)]
     InvalidFloat {
-        detailed_report: String,
         error_msg: String,
         error_span: Span,
     },
@@ -193,7 +249,6 @@ pub enum ErrorInfo {
     #[error("invalid escape sequence")]
     #[label("{error_msg}", error_span)]
     InvalidEscapeSequence {
-        detailed_report: String,
         error_msg: String,
         error_span: Span,
     },
@@ -201,83 +256,29 @@ pub enum ErrorInfo {
     #[error("unexpected escape sequence")]
     #[label("escape sequences are not allowed in this string", error_span)]
     UnexpectedEscapeSequence {
-        detailed_report: String,
         error_span: Span,
     },
 
     #[error("invalid regexp modifier `{modifier}`")]
     #[label("invalid modifier", error_span)]
     InvalidRegexpModifier {
-        detailed_report: String,
         modifier: String,
         error_span: Span,
     },
+
+    #[error("invalid pragma")]
+    #[label("{error_msg}", error_span)]
+    InvalidPragma {
+        error_msg: String,
+        error_span: Span,
+    },
     
     #[error("invalid UTF-8")]
     #[label("invalid UTF-8 character", error_span)]
     InvalidUTF8 {
-        detailed_report: String,
         error_span: Span},
 }
 
-impl ErrorInfo {
-    pub fn as_str(&self) -> &str {
-        match self {
-            Self::SyntaxError { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::DuplicateTag { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::DuplicateRule { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::DuplicatePattern { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidModifier { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::DuplicateModifier { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidModifierCombination { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidBase64Alphabet { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::UnusedPattern { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidPattern { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidRange { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidInteger { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidFloat { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidEscapeSequence { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::UnexpectedEscapeSequence { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidRegexpModifier { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-            Self::InvalidUTF8 { detailed_report, .. } => {
-                detailed_report.as_str()
-            }
-        }
-    }
-}
-
 impl ErrorInfo {
     pub(crate) fn syntax_error_message<F>(
         expected: &[GrammarRule],
@@ -387,12 +388,14 @@ impl ErrorInfo {
             GrammarRule::k_BASE64 => "`base64`",
             GrammarRule::k_BASE64WIDE => "`base64wide`",
             GrammarRule::k_CONDITION => "`condition`",
+            GrammarRule::k_COUNT_OF => "`count_of`",
             GrammarRule::k_FALSE => "`false`",
             GrammarRule::k_FILESIZE => "`filesize`",
             GrammarRule::k_FOR => "`for`",
             GrammarRule::k_FULLWORD => "`fullword`",
             GrammarRule::k_GLOBAL => "`global`",
             GrammarRule::k_IMPORT => "`import`",
+            GrammarRule::k_INCLUDE => "`include`",
             GrammarRule::k_IN => "`in`",
             GrammarRule::k_META => "`meta`",
             GrammarRule::k_NOCASE => "`nocase`",
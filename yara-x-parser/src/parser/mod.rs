@@ -1,5 +1,5 @@
-use crate::ast::{Span, AST};
-use crate::cst::CST;
+use crate::ast::{MetaValue, Span, AST};
+use crate::cst::{CSTNode, CST};
 use bstr::{BStr, ByteSlice};
 use pest::Parser as PestParser;
 
@@ -14,6 +14,8 @@ pub(crate) use crate::report::*;
 mod ast_builder;
 mod context;
 mod errors;
+mod pragmas;
+mod regexp;
 
 #[cfg(test)]
 mod tests;
@@ -52,6 +54,8 @@ pub struct SourceCode<'src> {
     /// An optional string that tells which is the origin of the code. Usually
     /// a file path.
     pub(crate) origin: Option<String>,
+    /// Metadata entries inherited by all the rules in the source code.
+    pub(crate) default_meta: Vec<(String, MetaValue<'src>)>,
 }
 
 impl<'src> SourceCode<'src> {
@@ -61,11 +65,50 @@ impl<'src> SourceCode<'src> {
     /// but it can be an arbitrary string. The origin appears in error and
     /// warning messages.
     pub fn origin(self, origin: &str) -> Self {
-        Self {
-            raw: self.raw,
-            valid: self.valid,
-            origin: Some(origin.to_owned()),
+        Self { origin: Some(origin.to_owned()), ..self }
+    }
+
+    /// Returns the origin of the source code, if any.
+    pub fn get_origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Sets metadata entries that are inherited by every rule in the source
+    /// code.
+    ///
+    /// Each compiled rule gets the entries whose identifiers don't appear
+    /// in its own `meta` section, which means that rules can override the
+    /// defaults. Source code defaults take precedence over the ones set for
+    /// the namespace with `Compiler::namespace_default_meta`. If the same
+    /// identifier appears more than once, the last value is the one used.
+    ///
+    /// ```
+    /// use yara_x_parser::SourceCode;
+    /// let src = SourceCode::from("rule test { condition: true }")
+    ///     .with_default_meta([("author", "John".into()), ("version", 2.into())]);
+    /// ```
+    pub fn with_default_meta<I, K>(mut self, meta: I) -> Self
+    where
+        I: IntoIterator<Item = (K, MetaValue<'src>)>,
+        K: Into<String>,
+    {
+        for (identifier, value) in meta {
+            let identifier = identifier.into();
+            self.default_meta.retain(|(i, _)| *i != identifier);
+            self.default_meta.push((identifier, value));
         }
+        self
+    }
+
+    /// Returns the source code itself, which may contain invalid UTF-8.
+    pub fn as_bytes(&self) -> &'src [u8] {
+        self.raw.as_bytes()
+    }
+
+    /// Returns the metadata entries set with
+    /// [`SourceCode::with_default_meta`].
+    pub fn get_default_meta(&self) -> &[(String, MetaValue<'src>)] {
+        self.default_meta.as_slice()
     }
 
     /// Make sure that the source code is valid UTF-8. If that's the case
@@ -83,7 +126,12 @@ impl<'src> From<&'src str> for SourceCode<'src> {
     fn from(src: &'src str) -> Self {
         // Because the input is a &str we know that the code is valid UTF-8,
         // so the `valid` field can be set to the provided reference.
-        Self { raw: BStr::new(src), valid: Some(src), origin: None }
+        Self {
+            raw: BStr::new(src),
+            valid: Some(src),
+            origin: None,
+            default_meta: Vec::new(),
+        }
     }
 }
 
@@ -93,7 +141,12 @@ impl<'src> From<&'src [u8]> for SourceCode<'src> {
         // Because the input is a &[u8], the code can contain invalid UTF-8,
         // so the `valid` field is set to `None`. The `validate_utf8` function
         // must be used for validating the source code.
-        Self { raw: BStr::new(src), valid: None, origin: None }
+        Self {
+            raw: BStr::new(src),
+            valid: None,
+            origin: None,
+            default_meta: Vec::new(),
+        }
     }
 }
 
@@ -170,12 +223,29 @@ impl<'a> Parser<'a> {
         let root = cst.into_iter().next().unwrap();
         assert_eq!(root.as_rule(), GrammarRule::source_file);
 
-        let mut ctx = Context::new(src, self.get_report_builder());
+        let report_builder = self.get_report_builder();
+        let mut ctx = Context::new(src);
+
+        // Pragmas are inside comments, so they are extracted from a copy of
+        // the CST where comments are not ignored.
+        let pair = root.into_pair();
+
+        // Errors and warnings are rendered here, once the source code has
+        // been processed.
+        let pragmas = pragmas::pragmas_from_cst(&mut ctx, pair.clone())
+            .map_err(|err| err.render(report_builder, &ctx.src))?;
+
+        let namespace =
+            namespace_from_cst(&mut ctx, CSTNode::from(pair).into_inner())
+                .map_err(|err| err.render(report_builder, &ctx.src))?;
 
-        let namespace = namespace_from_cst(&mut ctx, root.into_inner())?;
         let namespaces = vec![namespace];
 
-        Ok(AST { namespaces, warnings: ctx.warnings })
+        for warning in ctx.warnings.iter_mut() {
+            warning.render(report_builder, &ctx.src);
+        }
+
+        Ok(AST { namespaces, pragmas, warnings: ctx.warnings })
     }
 
     /// Build the Concrete Syntax Tree (CST) for a YARA source.
@@ -249,16 +319,16 @@ impl<'a> Parser<'a> {
             } else {
                 span_start
             };
-            return Err(Error::new(ErrorInfo::invalid_utf_8(
-                report_builder,
-                &src,
-                Span { start: span_start, end: span_end },
-            )));
+            return Err(Error::new(ErrorInfo::invalid_utf_8(Span {
+                start: span_start,
+                end: span_end,
+            }))
+            .render(report_builder, &src));
         }
 
         let pairs = grammar::ParserImpl::parse(rule, src.valid.unwrap())
             .map_err(|pest_error| {
-                report_builder.convert_pest_error(&src, pest_error)
+                Error::from(pest_error).render(report_builder, &src)
             })?;
 
         Ok(CST { comments: false, whitespaces: false, pairs: Box::new(pairs) })
@@ -0,0 +1,151 @@
+/*! Extraction of pragmas from comments.
+
+Pragmas are single-line comments that start with `yarax:`, followed by a
+comma-separated list of pragmas, each one with an optional argument between
+parenthesis, like in:
+
+```text
+// yarax: extensions, disable_warning(duplicate_tag)
+```
+
+Pragmas must appear at the top of the source code, before any import
+statement or rule. A comment that looks like a pragma anywhere else is
+ignored with a warning, as it would be easy to believe that it applies only
+to the rule that follows.
+*/
+
+use pest::iterators::Pair;
+
+use crate::ast::{Pragma, Span};
+use crate::parser::{Context, Error, ErrorInfo, GrammarRule};
+use crate::warnings::WarningInfo;
+
+/// Prefix that distinguishes pragmas from ordinary comments.
+const PRAGMA_PREFIX: &str = "yarax:";
+
+/// Returns the pragmas in the source code, given the root node of its CST,
+/// which must correspond to [`GrammarRule::source_file`].
+pub(crate) fn pragmas_from_cst<'src>(
+    ctx: &mut Context<'src>,
+    source_file: Pair<'src, GrammarRule>,
+) -> Result<Vec<Pragma<'src>>, Error> {
+    let mut pragmas = Vec::new();
+    let mut at_top = true;
+
+    // Comments can appear at any depth in the CST, not only between rules.
+    for pair in source_file.into_inner().flatten() {
+        match pair.as_rule() {
+            GrammarRule::COMMENT => {}
+            GrammarRule::WHITESPACE => continue,
+            _ => {
+                at_top = false;
+                continue;
+            }
+        }
+
+        let comment = pair.as_str();
+        let start = pair.as_span().start();
+
+        let body = match comment.strip_prefix("//") {
+            Some(body) => body,
+            None => continue,
+        };
+
+        let list = match body.trim_start().strip_prefix(PRAGMA_PREFIX) {
+            Some(list) => list,
+            None => continue,
+        };
+
+        if !at_top {
+            ctx.warnings.push(
+                WarningInfo::misplaced_pragma(
+                    Span { start, end: start + comment.len() },
+                    Some(
+                        "pragmas must appear before any import statement or rule"
+                            .to_string(),
+                    ),
+                )
+                .into(),
+            );
+            continue;
+        }
+
+        // Offset of `list` within the source code.
+        let mut offset = start + comment.len() - list.len();
+
+        if list.trim().is_empty() {
+            return Err(Error::new(ErrorInfo::invalid_pragma(
+                "expecting a pragma".to_string(),
+                Span { start, end: start + comment.len() },
+            )));
+        }
+
+        for item in list.split(',') {
+            let trimmed = item.trim_start();
+            let item_start = offset + item.len() - trimmed.len();
+            offset += item.len() + 1;
+            pragmas.push(pragma_from_str(trimmed.trim_end(), item_start)?);
+        }
+    }
+
+    Ok(pragmas)
+}
+
+/// Parses a single pragma, like `disable_warning(duplicate_tag)`, that
+/// starts at offset `start` in the source code.
+fn pragma_from_str(pragma: &str, start: usize) -> Result<Pragma<'_>, Error> {
+    let span = Span { start, end: start + pragma.len() };
+
+    if pragma.is_empty() {
+        return Err(Error::new(ErrorInfo::invalid_pragma(
+            "expecting a pragma".to_string(),
+            span,
+        )));
+    }
+
+    let name_len = identifier_len(pragma);
+
+    if name_len == 0 {
+        return Err(Error::new(ErrorInfo::invalid_pragma(
+            "expecting a pragma name".to_string(),
+            span,
+        )));
+    }
+
+    let (name, rest) = pragma.split_at(name_len);
+
+    if rest.is_empty() {
+        return Ok(Pragma { span, name, argument: None });
+    }
+
+    let rest_span = Span { start: start + name_len, end: span.end };
+
+    let argument =
+        match rest.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+            Some(argument) => argument.trim(),
+            None => {
+                return Err(Error::new(ErrorInfo::invalid_pragma(
+                    "expecting an argument between parenthesis".to_string(),
+                    rest_span,
+                )));
+            }
+        };
+
+    if argument.is_empty() || identifier_len(argument) != argument.len() {
+        return Err(Error::new(ErrorInfo::invalid_pragma(
+            "the argument must be an identifier".to_string(),
+            rest_span,
+        )));
+    }
+
+    Ok(Pragma { span, name, argument: Some(argument) })
+}
+
+/// Returns the length of the identifier at the start of `s`, or zero if `s`
+/// doesn't start with an identifier.
+fn identifier_len(s: &str) -> usize {
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        return 0;
+    }
+    s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(s.len())
+}
@@ -0,0 +1,227 @@
+/*! Analysis of regular expressions used as patterns.
+
+A pattern that matches the empty string would produce a zero-length match
+at every offset of the scanned data, so such regexps are rejected. This
+module determines whether a regexp can match the empty string, working
+directly with the regexp's source. It only needs to understand the
+structure of the regexp (alternatives, groups, quantifiers and the things
+that consume no input, like anchors), not the exact set of characters
+matched by each piece.
+*/
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Returns true if the regexp `re` can match the empty string.
+///
+/// `re` is the regexp without the enclosing slashes and modifiers, as in
+/// `a*` for `/a*/i`. The regexp is assumed to be syntactically valid, the
+/// result for invalid regexps is unspecified.
+pub(crate) fn matches_empty(re: &str) -> bool {
+    Nullable { chars: re.chars().peekable() }.alternation()
+}
+
+/// Recursive-descent analyzer that computes whether each piece of a regexp
+/// can match the empty string.
+struct Nullable<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Nullable<'_> {
+    /// alternation := concatenation ( '|' concatenation )*
+    fn alternation(&mut self) -> bool {
+        let mut nullable = self.concatenation();
+        while self.chars.next_if_eq(&'|').is_some() {
+            // Alternatives are analyzed even if the result is known, so
+            // that all of them are consumed.
+            nullable |= self.concatenation();
+        }
+        nullable
+    }
+
+    /// concatenation := ( atom quantifier* )*
+    fn concatenation(&mut self) -> bool {
+        let mut nullable = true;
+        while !matches!(self.chars.peek(), None | Some('|') | Some(')')) {
+            let atom = self.atom();
+            nullable &= self.quantifiers(atom);
+        }
+        nullable
+    }
+
+    fn atom(&mut self) -> bool {
+        match self.chars.next() {
+            Some('(') => {
+                // Non-capturing groups, like `(?:abc)`.
+                if self.chars.next_if_eq(&'?').is_some() {
+                    self.chars.next_if_eq(&':');
+                }
+                let nullable = self.alternation();
+                self.chars.next_if_eq(&')');
+                nullable
+            }
+            Some('[') => {
+                self.class();
+                false
+            }
+            Some('\\') => match self.chars.next() {
+                // Word boundaries don't consume any input.
+                Some('b' | 'B') => true,
+                Some('x') => {
+                    self.chars.next_if(char::is_ascii_hexdigit);
+                    self.chars.next_if(char::is_ascii_hexdigit);
+                    false
+                }
+                _ => false,
+            },
+            // Anchors don't consume any input.
+            Some('^' | '$') => true,
+            _ => false,
+        }
+    }
+
+    /// Consumes a character class, like `[a-z]`, `[^\]]` or `[]a]`. The
+    /// opening bracket was already consumed.
+    fn class(&mut self) {
+        self.chars.next_if_eq(&'^');
+        // A closing bracket right at the start is a literal.
+        self.chars.next_if_eq(&']');
+        while let Some(c) = self.chars.next() {
+            match c {
+                '\\' => {
+                    self.chars.next();
+                }
+                ']' => break,
+                _ => {}
+            }
+        }
+    }
+
+    /// Consumes the quantifiers that follow an atom, if any, and returns
+    /// whether the quantified atom can match the empty string.
+    fn quantifiers(&mut self, mut nullable: bool) -> bool {
+        loop {
+            match self.chars.peek() {
+                Some('*' | '?') => {
+                    self.chars.next();
+                    nullable = true;
+                }
+                Some('+') => {
+                    self.chars.next();
+                }
+                Some('{') => match self.repetition() {
+                    Some(min) => nullable |= min == 0,
+                    // Not a valid repetition, the brace is a literal.
+                    None => return nullable,
+                },
+                _ => return nullable,
+            }
+            // Non-greedy quantifiers, like `a*?`.
+            self.chars.next_if_eq(&'?');
+        }
+    }
+
+    /// Consumes a repetition like `{2}`, `{2,}`, `{,3}` or `{2,3}`, and
+    /// returns the minimum number of repetitions. If the brace doesn't
+    /// start a repetition nothing is consumed and the result is [`None`].
+    fn repetition(&mut self) -> Option<u64> {
+        let mut chars = self.chars.clone();
+
+        chars.next_if_eq(&'{')?;
+
+        let mut min = String::new();
+        while let Some(c) = chars.next_if(char::is_ascii_digit) {
+            min.push(c);
+        }
+
+        let mut max = String::new();
+        if chars.next_if_eq(&',').is_some() {
+            while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                max.push(c);
+            }
+        }
+
+        chars.next_if_eq(&'}')?;
+
+        if min.is_empty() && max.is_empty() {
+            return None;
+        }
+
+        self.chars = chars;
+
+        // An omitted minimum, as in `{,3}`, is zero. A minimum that
+        // overflows is not zero either.
+        Some(if min.is_empty() { 0 } else { min.parse().unwrap_or(u64::MAX) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches_empty;
+
+    #[test]
+    fn nullable() {
+        for re in [
+            "",
+            "a*",
+            "a?",
+            "(a|)",
+            "(|a)",
+            "a*b*",
+            "(ab)*",
+            "(a+|b?)",
+            "^",
+            "^$",
+            r"\b",
+            r"\B(a*)\b",
+            "a{0}",
+            "a{,3}",
+            "a{0,}",
+            "[ab]*?",
+            "(a{2}|(b*))",
+            "a|b|",
+            "(?:a|b)?",
+        ] {
+            assert!(
+                matches_empty(re),
+                "`{}` should match the empty string",
+                re
+            );
+        }
+    }
+
+    #[test]
+    fn not_nullable() {
+        for re in [
+            "a",
+            "a+",
+            "ab*",
+            "(a|b)",
+            "(a*)b",
+            "^a$",
+            r"\x00*a",
+            r"\xff",
+            r"\d",
+            r"\*",
+            r"\(",
+            "a{1}",
+            "a{2,}",
+            "a{1,3}",
+            "a{,}",
+            "a{x}",
+            "[]]*a",
+            r"[\]]",
+            "[^a]",
+            "(a|b|c)+",
+            r"\b\w+\b",
+            "a{99999999999999999999999}",
+            "(?:ab)",
+        ] {
+            assert!(
+                !matches_empty(re),
+                "`{}` should not match the empty string",
+                re
+            );
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use pretty_assertions::assert_eq;
 
 use crate::parser::Parser;
+use crate::report::ReportBuilder;
 
 #[test]
 fn utf8_errors() {
@@ -14,7 +15,7 @@ fn utf8_errors() {
         Parser::new()
             .build_ast(src.as_slice())
             .expect_err("expected error")
-            .to_string(),
+            .as_str(),
         "error: invalid UTF-8
    ╭─[line:1:5]
    │
@@ -26,22 +27,172 @@ fn utf8_errors() {
     );
 }
 
+#[test]
+fn long_lines() {
+    let filler = "A".repeat(512 * 1024);
+
+    // A 1MB source code in a single line, with an error in the middle.
+    let src = format!(
+        r#"rule test {{ strings: $a = "{}" xor(300) $b = "{}" condition: $a }}"#,
+        filler, filler
+    );
+
+    let err = Parser::new().build_ast(src.as_str()).unwrap_err();
+    let report = err.as_str();
+
+    assert!(report.len() < crate::report::DEFAULT_MAX_REPORT_SIZE);
+
+    // The header contains the real position of the error.
+    let error_offset = src.find("300").unwrap();
+    assert!(report.contains(&format!("[line:1:{}]", error_offset + 1)));
+
+    // The caret must point to the number that is out of range.
+    let lines: Vec<&str> = report.lines().collect();
+    let code_line = lines.iter().find(|l| l.starts_with(" 1 │ ")).unwrap();
+    let caret_line = lines.iter().find(|l| l.contains('┬')).unwrap();
+
+    let code: Vec<char> = code_line.chars().collect();
+    let caret_col = caret_line.chars().position(|c| c == '┬').unwrap();
+
+    assert!(code_line.starts_with(" 1 │ ..."));
+    assert!(code_line.ends_with("..."));
+    assert_eq!(code[caret_col - 1..=caret_col + 1], ['3', '0', '0']);
+
+    // The structured label keeps the untruncated offsets.
+    let label = &err.labels()[0];
+    assert_eq!(label.span().start, error_offset);
+    assert_eq!(label.start().column, error_offset);
+}
+
+#[test]
+fn long_lines_after_first_line() {
+    let filler = "A".repeat(1000);
+
+    let src = format!(
+        "rule test {{\n strings:\n  $a = \"{}\" xor(300)\n condition: $a }}",
+        filler
+    );
+
+    let err = Parser::new().build_ast(src.as_str()).unwrap_err();
+    let report = err.as_str();
+
+    // Line numbers and columns are the ones in the original source code.
+    assert!(report.contains("[line:3:1015]"));
+
+    let lines: Vec<&str> = report.lines().collect();
+    let code_line = lines.iter().find(|l| l.starts_with(" 3 │ ")).unwrap();
+    let caret_line = lines.iter().find(|l| l.contains('┬')).unwrap();
+
+    let code: Vec<char> = code_line.chars().collect();
+    let caret_col = caret_line.chars().position(|c| c == '┬').unwrap();
+
+    assert!(code_line.starts_with(" 3 │ ...AAA"));
+    assert_eq!(code[caret_col - 1..=caret_col + 1], ['3', '0', '0']);
+}
+
+#[test]
+fn many_reports_in_large_source() {
+    use std::time::{Duration, Instant};
+
+    use yansi::Style;
+
+    use crate::ast::Span;
+    use crate::parser::SourceCode;
+    use crate::report::{Report, ReportType};
+
+    struct Synthetic(Span);
+
+    impl Report for Synthetic {
+        fn report_type(&self) -> ReportType {
+            ReportType::Warning
+        }
+        fn title(&self) -> String {
+            "synthetic warning".to_string()
+        }
+        fn labels(&self) -> Vec<(Span, String, Style)> {
+            vec![(self.0, "here".to_string(), Style::default())]
+        }
+        fn note(&self) -> Option<String> {
+            None
+        }
+    }
+
+    // A 10MB generated source, with some non-ASCII characters and a few
+    // long lines.
+    let mut src = String::with_capacity(10 * 1024 * 1024);
+    let mut i = 0;
+    while src.len() < 10 * 1024 * 1024 {
+        if i % 1000 == 0 {
+            src.push_str(&format!("// {}\n", "ñ".repeat(50_000)));
+        }
+        src.push_str(&format!(
+            "rule r{} {{ strings: $a = \"ñandú\" condition: $a }}\n",
+            i
+        ));
+        i += 1;
+    }
+
+    let report_builder = ReportBuilder::new();
+    let src = SourceCode::from(src.as_str());
+
+    report_builder.register_source(&src);
+
+    let offsets: Vec<usize> = src
+        .valid
+        .unwrap()
+        .match_indices("$a }")
+        .map(|(offset, _)| offset)
+        .step_by(10)
+        .take(5000)
+        .collect();
+
+    assert_eq!(offsets.len(), 5000);
+
+    let start = Instant::now();
+
+    for offset in offsets {
+        let report = report_builder
+            .render(&src, &Synthetic(Span { start: offset, end: offset + 2 }));
+        assert!(report.starts_with("warning: synthetic warning\n"));
+    }
+
+    // With a linear scan of the source code per report this takes minutes.
+    assert!(start.elapsed() < Duration::from_secs(60));
+}
+
+#[test]
+fn display() {
+    let err =
+        Parser::new().build_ast("rule test { condition: $a }").unwrap_err();
+
+    // Display is a one-line summary, the detailed report is in `as_str`.
+    assert_eq!(err.to_string(), "unknown pattern `$a`");
+    assert!(err.as_str().starts_with("error: unknown pattern `$a`\n"));
+}
+
+#[test]
+fn report_size() {
+    let mut src = "rule test { condition: true }\n".repeat(10);
+    src.push_str("rule test { condition: }");
+
+    let mut report_builder = ReportBuilder::new();
+    report_builder.max_report_size(32);
+
+    let report = Parser::new()
+        .set_report_builder(&report_builder)
+        .build_ast(src.as_str())
+        .unwrap_err();
+
+    let (head, notice) = report.as_str().split_at(32);
+
+    assert!(head.starts_with("error: syntax error\n"));
+    assert!(notice.starts_with("\n[report truncated, "));
+    assert!(notice.ends_with(" bytes omitted]\n"));
+}
+
 #[test]
 fn syntax_errors() {
     let tests = vec![
-        ////////////////////////////////////////////////////////////
-        (
-            line!(),
-            r#"rule test : tag1 tag1 { condition: true }"#,
-            r#"error: duplicate tag `tag1`
-   ╭─[line:1:18]
-   │
- 1 │ rule test : tag1 tag1 { condition: true }
-   ·                  ──┬─  
-   ·                    ╰─── duplicate tag
-───╯
-"#,
-        ),
         ////////////////////////////////////////////////////////////
         (
             line!(),
@@ -419,7 +570,7 @@ rule test {
    │
  3 │   condition: 1  1 == 1
    ·                 │ 
-   ·                 ╰─ expected `of`, closing brace `}`, dot `.`, opening bracket `[`, opening parenthesis `(`, operator, or percent `%`
+   ·                 ╰─ expected `in`, `of`, closing brace `}`, dot `.`, opening bracket `[`, opening parenthesis `(`, operator, or percent `%`
 ───╯
 "#,
         ),
@@ -808,6 +959,30 @@ rule test {
    · 
    · Note: these two modifiers can't be used together
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings: 
+    $a = "foo" base64 xor(0x10-0x1f)
+  condition:
+    $a
+}
+"#,
+            r#"error: invalid modifier combination: `base64` `xor`
+   ╭─[line:4:16]
+   │
+ 4 │     $a = "foo" base64 xor(0x10-0x1f)
+   ·                ───┬── ─┬─  
+   ·                   ╰──────── `base64` modifier used here
+   ·                        │   
+   ·                        ╰─── `xor` modifier used here
+   · 
+   · Note: these two modifiers can't be used together
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -907,6 +1082,26 @@ rule test {
    ·                       ──┬─  
    ·                         ╰─── invalid length - must be 64 bytes
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings: 
+    $a = "foo" base64wide("!BCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+!")
+  condition:
+    $a
+}
+        "#,
+            r#"error: invalid base64 alphabet
+   ╭─[line:4:27]
+   │
+ 4 │     $a = "foo" base64wide("!BCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+!")
+   ·                           ─────────────────────────────────┬────────────────────────────────  
+   ·                                                            ╰────────────────────────────────── duplicated byte: 0x21
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -949,6 +1144,94 @@ rule test {
    ·          ─┬  
    ·           ╰── this pattern is too short
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = /a*/
+  condition:
+    $a
+}
+        "#,
+            r#"error: invalid pattern `$a`
+   ╭─[line:4:10]
+   │
+ 4 │     $a = /a*/
+   ·          ──┬─  
+   ·            ╰─── this regexp can match an empty string
+   · 
+   · Note: patterns must match at least one byte
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = /(foo|)/i wide
+  condition:
+    $a
+}
+        "#,
+            r#"error: invalid pattern `$a`
+   ╭─[line:4:10]
+   │
+ 4 │     $a = /(foo|)/i wide
+   ·          ────┬────  
+   ·              ╰────── this regexp can match an empty string
+   · 
+   · Note: patterns must match at least one byte
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = /\b(a|b)?\b/
+  condition:
+    $a
+}
+        "#,
+            r#"error: invalid pattern `$a`
+   ╭─[line:4:10]
+   │
+ 4 │     $a = /\b(a|b)?\b/
+   ·          ──────┬─────  
+   ·                ╰─────── this regexp can match an empty string
+   · 
+   · Note: patterns must match at least one byte
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = /x{0,3}?/s
+  condition:
+    $a
+}
+        "#,
+            r#"error: invalid pattern `$a`
+   ╭─[line:4:10]
+   │
+ 4 │     $a = /x{0,3}?/s
+   ·          ─────┬────  
+   ·               ╰────── this regexp can match an empty string
+   · 
+   · Note: patterns must match at least one byte
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -967,6 +1250,184 @@ rule test {
    ·                        ┬  
    ·                        ╰── invalid modifier
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a and $b
+}
+"#,
+            r#"error: unknown pattern `$b`
+   ╭─[line:6:12]
+   │
+ 6 │     $a and $b
+   ·            ─┬  
+   ·             ╰── this pattern is not declared in the `strings` section
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a and #b > 1
+}
+"#,
+            r#"error: unknown pattern `#b`
+   ╭─[line:6:12]
+   │
+ 6 │     $a and #b > 1
+   ·            ─┬  
+   ·             ╰── this pattern is not declared in the `strings` section
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    any of ($a, $b*)
+}
+"#,
+            r#"error: unknown pattern `$b*`
+   ╭─[line:6:17]
+   │
+ 6 │     any of ($a, $b*)
+   ·                 ─┬─  
+   ·                  ╰─── this pattern is not declared in the `strings` section
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition:
+    any of them
+}
+"#,
+            r#"error: no patterns to refer to
+   ╭─[line:4:12]
+   │
+ 4 │     any of them
+   ·            ──┬─  
+   ·              ╰─── `them` used in a rule without patterns
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a and # > 0
+}
+"#,
+            r#"error: syntax error
+   ╭─[line:6:12]
+   │
+ 6 │     $a and # > 0
+   ·            ┬  
+   ·            ╰── this `#` is outside of the condition of a `for .. of` statement
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    for any of them : (for all of them : ($)) and $
+}
+"#,
+            r#"error: syntax error
+   ╭─[line:6:51]
+   │
+ 6 │     for any of them : (for all of them : ($)) and $
+   ·                                                   ┬  
+   ·                                                   ╰── this `$` is outside of the condition of a `for .. of` statement
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"// yarax:
+rule test { condition: true }
+"#,
+            r#"error: invalid pragma
+   ╭─[line:1:1]
+   │
+ 1 │ // yarax:
+   · ────┬────  
+   ·     ╰────── expecting a pragma
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"// yarax: extensions, disable_warning(duplicate_tag
+rule test { condition: true }
+"#,
+            r#"error: invalid pragma
+   ╭─[line:1:38]
+   │
+ 1 │ // yarax: extensions, disable_warning(duplicate_tag
+   ·                                      ───────┬──────  
+   ·                                             ╰──────── expecting an argument between parenthesis
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"// yarax: extensions,, disable_warning(duplicate_tag)
+rule test { condition: true }
+"#,
+            r#"error: invalid pragma
+   ╭─[line:1:22]
+   │
+ 1 │ // yarax: extensions,, disable_warning(duplicate_tag)
+   ·                      │ 
+   ·                      ╰─ expecting a pragma
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"// yarax: disable_warning(duplicate tag)
+rule test { condition: true }
+"#,
+            r#"error: invalid pragma
+   ╭─[line:1:26]
+   │
+ 1 │ // yarax: disable_warning(duplicate tag)
+   ·                          ───────┬───────  
+   ·                                 ╰───────── the argument must be an identifier
+───╯
 "#,
         ),
     ];
@@ -978,7 +1439,7 @@ rule test {
                     "rule at line {} parsed without errors, but error was expected.\n\n",
                     t.0,
                 ))
-                .to_string(),
+                .as_str(),
             t.2,
             "test at line {}", t.0
         )
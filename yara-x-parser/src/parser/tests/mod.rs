@@ -44,6 +44,15 @@ fn identifiers() {
         .is_ok());
     assert!(Parser::new().build_cst("rule rules { condition: true }").is_ok());
     assert!(Parser::new().build_cst("rule _true { condition: true }").is_ok());
+
+    // `count_of` is not a keyword, it's special only when followed by a
+    // parenthesis.
+    assert!(Parser::new()
+        .build_cst("rule count_of { condition: count_of }")
+        .is_ok());
+    assert!(Parser::new()
+        .build_cst("rule test { condition: count_ofs and count_of_x }")
+        .is_ok());
 }
 
 mod ast;
@@ -1,18 +1,17 @@
-use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
+use std::iter;
 use std::ops::Range;
 
-use ariadne::{Color, Label, ReportKind, Source};
-use pest::error::ErrorVariant::{CustomError, ParsingError};
-use pest::error::InputLocation;
+use ariadne::{Color, ReportKind, Source};
+use bstr::ByteSlice;
 use yansi::Style;
 
 use crate::ast::Span;
-use crate::parser::GrammarRule;
+use crate::escape::terminal_safe;
+use crate::line_index::{ColumnEncoding, LineIndex, Position};
 use crate::parser::SourceCode;
-use crate::parser::{Error, ErrorInfo};
 
 /// Types of reports created by [`ReportBuilder`].
 pub enum ReportType {
@@ -20,9 +19,110 @@ pub enum ReportType {
     Warning,
 }
 
+/// Describes an error or warning report.
+///
+/// Errors and warnings are plain data, like the names and spans involved.
+/// This trait exposes the pieces of the report built from that data, which
+/// are turned into text by [`ReportBuilder::render`]. Types describing
+/// errors and warnings don't implement this trait manually, but with
+/// `#[derive(Error)]` from `yara_x_macros`, which documents how titles,
+/// labels and notes are declared.
+pub trait Report {
+    /// Returns the type of report.
+    fn report_type(&self) -> ReportType;
+    /// Returns the report's title (e.g: "duplicate tag `foo`").
+    fn title(&self) -> String;
+    /// Returns the labels in the report. The first one is the main label.
+    fn labels(&self) -> Vec<(Span, String, Style)>;
+    /// Returns the origin of the source code each label points to, in the
+    /// same order as [`Report::labels`]. `None` stands for the source code
+    /// the report refers to, which is where all labels point by default.
+    fn label_origins(&self) -> Vec<Option<String>> {
+        Vec::new()
+    }
+    /// Returns a note that is shown at the end of the report, if any.
+    fn note(&self) -> Option<String>;
+}
+
+/// A label in an error or warning report.
+///
+/// Labels point to some portion of the source code and describe what is
+/// wrong with it. This is the structured counterpart of the labels shown
+/// in the text-mode report, intended for tools like editors that show the
+/// errors next to the code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Label {
+    text: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip_serializing_if = "Option::is_none")
+    )]
+    origin: Option<String>,
+    span: Span,
+    start: Position,
+    end: Position,
+}
+
+impl Label {
+    /// Creates a new label. Labels are usually created by
+    /// [`ReportBuilder::labels`], this is intended for code that stores
+    /// labels in some other format and needs to restore them.
+    #[doc(hidden)]
+    pub fn new(
+        text: String,
+        origin: Option<String>,
+        span: Span,
+        start: Position,
+        end: Position,
+    ) -> Self {
+        Self { text, origin, span, start, end }
+    }
+
+    /// Returns the label's text.
+    pub fn text(&self) -> &str {
+        self.text.as_str()
+    }
+
+    /// Returns the origin of the source code the label points to, if it's
+    /// not the source code where the error or warning was found. This is
+    /// the case of labels that point to a rule declared in some other
+    /// source code, for instance.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Returns the byte offsets where the labeled code starts and ends.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the line and column where the labeled code starts.
+    ///
+    /// Lines and columns start at zero and columns are counted in chars,
+    /// so they are one less than the ones shown in text-mode reports.
+    pub fn start(&self) -> Position {
+        self.start
+    }
+
+    /// Returns the line and column where the labeled code ends. The end
+    /// is not included in the labeled code.
+    pub fn end(&self) -> Position {
+        self.end
+    }
+}
+
+/// Default value for [`ReportBuilder::max_line_width`].
+pub const DEFAULT_MAX_LINE_WIDTH: usize = 256;
+
+/// Default value for [`ReportBuilder::max_report_size`].
+pub const DEFAULT_MAX_REPORT_SIZE: usize = 64 * 1024;
+
 /// Build error and warning reports.
 pub struct ReportBuilder {
     with_colors: bool,
+    max_line_width: usize,
+    max_report_size: usize,
     // RefCell allows getting a mutable reference to the cache, even if we have
     // an immutable reference to the report builder.
     cache: RefCell<Cache>,
@@ -32,14 +132,50 @@ struct Cache {
     data: CacheMap,
 }
 
-struct CacheMap(HashMap<String, ariadne::Source>);
+struct CacheMap(HashMap<String, CachedSource>);
 
-/// &CacheMap implements the [`ariadne::Cache`] trait.
-impl ariadne::Cache<String> for &CacheMap {
-    fn fetch(&mut self, id: &String) -> Result<&Source, Box<dyn Debug + '_>> {
-        self.0
+impl Cache {
+    /// Returns the source code registered with the given origin.
+    ///
+    /// # Panics
+    ///
+    /// If no source code was registered with that origin.
+    fn get(&self, id: &str) -> &CachedSource {
+        self.data
+            .0
             .get(id)
-            .ok_or(Box::new(format!("Failed to fetch source `{}`", id)) as _)
+            .unwrap_or_else(|| panic!("source `{}` is not registered", id))
+    }
+}
+
+/// A source code registered with [`ReportBuilder::register_source`].
+struct CachedSource {
+    /// The source code, as it was registered.
+    raw: Vec<u8>,
+    /// Index used for converting the byte offsets in spans into the char
+    /// offsets expected by ariadne, and for locating the lines that contain
+    /// them.
+    line_index: LineIndex,
+    /// The source code as seen by ariadne.
+    source: ariadne::Source,
+}
+
+/// The sources passed to ariadne when rendering a report. These are the
+/// registered ones, except for the source code the report refers to, which
+/// is replaced by its [`Window`] when the labeled lines are too long.
+struct RenderSources<'a> {
+    cached: &'a CacheMap,
+    window: Option<(&'a str, &'a Source)>,
+}
+
+impl ariadne::Cache<String> for RenderSources<'_> {
+    fn fetch(&mut self, id: &String) -> Result<&Source, Box<dyn Debug + '_>> {
+        match self.window {
+            Some((window_id, source)) if window_id == id => Ok(source),
+            _ => self.cached.0.get(id).map(|cached| &cached.source).ok_or(
+                Box::new(format!("Failed to fetch source `{}`", id)) as _,
+            ),
+        }
     }
 
     fn display<'a>(&self, id: &'a String) -> Option<Box<dyn Display + 'a>> {
@@ -58,6 +194,8 @@ impl ReportBuilder {
     pub fn new() -> Self {
         Self {
             with_colors: false,
+            max_line_width: DEFAULT_MAX_LINE_WIDTH,
+            max_report_size: DEFAULT_MAX_REPORT_SIZE,
             cache: RefCell::new(Cache { data: CacheMap(HashMap::new()) }),
         }
     }
@@ -69,45 +207,81 @@ impl ReportBuilder {
         self
     }
 
+    /// Maximum number of columns of source code shown in a report.
+    ///
+    /// Source code lines that are longer than this are not shown completely,
+    /// only the portion around the labeled spans is shown, and the removed
+    /// parts are replaced with ellipses. This is useful when the source code
+    /// contains very long lines, like huge literal strings generated by some
+    /// tool. The default value is [`DEFAULT_MAX_LINE_WIDTH`].
+    pub fn max_line_width(&mut self, width: usize) -> &mut Self {
+        self.max_line_width = width;
+        self
+    }
+
+    /// Maximum size of a report in bytes.
+    ///
+    /// Reports larger than this are truncated, and a notice indicating
+    /// the number of bytes removed is added at the end. The default value
+    /// is [`DEFAULT_MAX_REPORT_SIZE`].
+    pub fn max_report_size(&mut self, size: usize) -> &mut Self {
+        self.max_report_size = size;
+        self
+    }
+
     /// Registers a source code with the report builder.
     ///
-    /// Before calling [`ReportBuilder::create_report`] with some [`SourceCode`]
+    /// Before calling [`ReportBuilder::render`] with some [`SourceCode`]
     /// the source code must be registered by calling this function. If
-    /// [`SourceCode`] was already registered this is a no-op.
+    /// [`SourceCode`] was already registered this is a no-op. Sources are
+    /// identified by their origin, registering a different source code with
+    /// the same origin replaces the previous one.
     ///
     /// This function allows code that is not valid UTF-8, in such cases it
     /// replaces the invalid characters with the UTF-8 replacement character.
+    /// Control characters are replaced as described in [`terminal_safe`],
+    /// which doesn't alter the position of the remaining characters.
     pub(crate) fn register_source(&self, src: &SourceCode) -> &Self {
         let key = src.origin.as_deref().unwrap_or("line");
+        let raw: &[u8] = src.raw.as_ref();
         {
             let map = &mut self.cache.borrow_mut().data.0;
-            // ariadne::Source::from(...) is an expensive operation, so it's
-            // done only if the SourceCode is not already in the cache.
-            if map.get(key).is_none() {
-                let s = if let Some(s) = src.valid {
-                    Cow::Borrowed(s)
-                } else {
-                    String::from_utf8_lossy(src.raw.as_ref())
-                };
-                map.insert(key.to_string(), ariadne::Source::from(s));
+            // Indexing the source code is expensive, so it's done only if
+            // the SourceCode is not already in the cache.
+            if !matches!(map.get(key), Some(cached) if cached.raw == raw) {
+                map.insert(
+                    key.to_string(),
+                    CachedSource {
+                        raw: raw.to_vec(),
+                        line_index: LineIndex::new(raw),
+                        source: ariadne::Source::from(terminal_safe(
+                            &String::from_utf8_lossy(raw),
+                        )),
+                    },
+                );
             }
         }
         self
     }
 
-    /// Creates a new error or warning report.
-    pub fn create_report(
-        &self,
-        report_type: ReportType,
-        src: &SourceCode,
-        span: Span,
-        title: String,
-        labels: Vec<(Span, String, Style)>,
-        note: Option<String>,
-    ) -> String {
+    /// Renders an error or warning as text.
+    ///
+    /// `src` is the source code the spans in the report refer to, and it
+    /// must be registered with [`ReportBuilder::register_source`].
+    pub fn render(&self, src: &SourceCode, report: &dyn Report) -> String {
+        let labels = report.labels();
+        let origins: Vec<Option<String>> = report
+            .label_origins()
+            .into_iter()
+            .chain(iter::repeat(None))
+            .take(labels.len())
+            .collect();
+        // The report's position is the one of the main label.
+        let span =
+            labels.first().map(|(span, _, _)| *span).unwrap_or_default();
         let id = src.origin.clone().unwrap_or_else(|| "line".to_string());
 
-        let kind = match report_type {
+        let kind = match report.report_type() {
             ReportType::Error => {
                 ReportKind::Custom("error", self.color(Color::Red))
             }
@@ -116,84 +290,162 @@ impl ReportBuilder {
             }
         };
 
+        // Titles, labels and notes can contain pieces of the source code,
+        // like literal strings, that must be made safe too.
+        let title = terminal_safe(&report.title()).into_owned();
         let title = if self.with_colors {
             Color::Default.style().bold().paint(title)
         } else {
             Color::Unset.paint(title)
         };
 
-        let mut report_builder =
-            ariadne::Report::build(kind, id.clone(), span.start)
-                .with_config(
-                    ariadne::Config::default().with_color(self.with_colors),
-                )
-                .with_message(title);
+        let cache = self.cache.borrow();
+        let cached = cache.get(&id);
+
+        // Spans of the labels that point to the source code the report
+        // refers to, the ones pointing to other source codes don't count
+        // for deciding which columns are shown.
+        let label_spans: Vec<Span> = labels
+            .iter()
+            .zip(&origins)
+            .filter(|(_, origin)| origin.is_none())
+            .map(|((span, _, _), _)| *span)
+            .collect();
+
+        let window = Window::new(cached, &label_spans, self.max_line_width);
+
+        // Spans are byte offsets, but ariadne works with char offsets.
+        let char_offset = |label_id: &str, offset| match &window {
+            Some(window) if label_id == id => {
+                window.char_offset(cached, offset)
+            }
+            _ => {
+                let src = cache.get(label_id);
+                src.line_index.char_offset(&src.raw, offset)
+            }
+        };
+
+        let mut report_builder = ariadne::Report::build(
+            kind,
+            id.clone(),
+            char_offset(&id, span.start),
+        )
+        .with_config(ariadne::Config::default().with_color(self.with_colors))
+        .with_message(title);
 
-        for (span, label, style) in labels {
+        for ((span, label, style), origin) in labels.into_iter().zip(origins) {
+            let label = terminal_safe(&label).into_owned();
             let label = if self.with_colors {
                 style.paint(label)
             } else {
                 Color::Unset.paint(label)
             };
+            let label_id = origin.unwrap_or_else(|| id.clone());
+            let range = Range {
+                start: char_offset(&label_id, span.start),
+                end: char_offset(&label_id, span.end),
+            };
             report_builder = report_builder.with_label(
-                Label::new((
-                    id.clone(),
-                    Range { start: span.start, end: span.end },
-                ))
-                .with_message(label),
+                ariadne::Label::new((label_id, range)).with_message(label),
             );
         }
 
-        if let Some(note) = note {
-            report_builder = report_builder.with_note(note);
+        if let Some(note) = report.note() {
+            report_builder =
+                report_builder.with_note(terminal_safe(&note).into_owned());
         }
 
         let report = report_builder.finish();
         let mut buffer = Vec::<u8>::new();
 
-        report.write(&self.cache.borrow_mut().data, &mut buffer).unwrap();
+        report
+            .write(
+                RenderSources {
+                    cached: &cache.data,
+                    window: window.as_ref().map(|w| (id.as_str(), &w.source)),
+                },
+                &mut buffer,
+            )
+            .unwrap();
 
-        String::from_utf8(buffer).unwrap()
+        let mut report = String::from_utf8(buffer).unwrap();
+
+        // The column in the header is the one ariadne sees in the window,
+        // but it must be the column in the original line.
+        if let Some(window) = &window {
+            let start = cached.line_index.position(
+                &cached.raw,
+                span.start,
+                ColumnEncoding::Utf32,
+            );
+            let header = |column: usize| {
+                format!("[{}:{}:{}]", id, start.line + 1, column + 1)
+            };
+            report = report.replacen(
+                &header(window.position(cached, span.start).column),
+                &header(start.column),
+                1,
+            );
+        }
+
+        self.truncate_report(report)
     }
 
-    pub(crate) fn convert_pest_error(
-        &self,
-        src: &SourceCode,
-        pest_error: pest::error::Error<GrammarRule>,
-    ) -> Error {
-        // Start and ending offset within the original code that is going
-        // to be highlighted in the error message. The span can cover
-        // multiple lines.
-        let error_span = match pest_error.location {
-            InputLocation::Pos(p) => Span { start: p, end: p },
-            InputLocation::Span(span) => Span { start: span.0, end: span.1 },
-        };
+    /// Returns the labels of an error or warning, with the positions of the
+    /// labeled code.
+    ///
+    /// `src` is the source code the spans in the report refer to, and it
+    /// must be registered with [`ReportBuilder::register_source`].
+    pub fn labels(&self, src: &SourceCode, report: &dyn Report) -> Vec<Label> {
+        let id = src.origin.as_deref().unwrap_or("line");
+        let cache = self.cache.borrow();
+        let mut origins = report.label_origins().into_iter();
 
-        let error_msg = match &pest_error.variant {
-            CustomError { message } => message.to_owned(),
-            ParsingError { positives, negatives } => {
-                ErrorInfo::syntax_error_message(
-                    positives,
-                    negatives,
-                    ErrorInfo::printable_string,
-                )
-            }
-        };
+        report
+            .labels()
+            .into_iter()
+            .map(|(span, text, _)| {
+                let origin = origins.next().flatten();
+                let cached = cache.get(origin.as_deref().unwrap_or(id));
+                let position = |offset| {
+                    cached.line_index.position(
+                        &cached.raw,
+                        offset,
+                        ColumnEncoding::Utf32,
+                    )
+                };
+                Label {
+                    text,
+                    origin,
+                    span,
+                    start: position(span.start),
+                    end: position(span.end),
+                }
+            })
+            .collect()
+    }
 
-        let detailed_report = self.create_report(
-            ReportType::Error,
-            src,
-            error_span,
-            "syntax error".to_string(),
-            vec![(error_span, error_msg.clone(), Color::Red.style().bold())],
-            None,
+    /// Truncates the report if it is longer than `max_report_size`.
+    fn truncate_report(&self, mut report: String) -> String {
+        if report.len() <= self.max_report_size {
+            return report;
+        }
+
+        let mut end = self.max_report_size;
+
+        while !report.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let omitted = report.len() - end;
+
+        report.truncate(end);
+        report.push_str(
+            format!("\n[report truncated, {} bytes omitted]\n", omitted)
+                .as_str(),
         );
 
-        Error::new(ErrorInfo::SyntaxError {
-            detailed_report,
-            error_msg,
-            error_span,
-        })
+        report
     }
 
     fn color(&self, c: Color) -> Color {
@@ -204,3 +456,159 @@ impl ReportBuilder {
         }
     }
 }
+
+/// Replaces the parts of a line removed by [`Window`].
+const ELLIPSIS: &str = "...";
+
+/// The lines of a source code shown in a report, where the lines that are
+/// longer than [`ReportBuilder::max_line_width`] keep only the columns
+/// around the labeled spans, and the removed parts are replaced with
+/// ellipses.
+///
+/// ariadne receives this instead of the whole source code, so the time and
+/// memory needed for rendering a report don't depend on the length of the
+/// lines. The lines that precede the window are empty, so that ariadne
+/// still shows the original line numbers.
+struct Window {
+    /// Source code passed to ariadne.
+    source: Source,
+    /// First line in the window.
+    first_line: usize,
+    /// Columns shown in the lines that were cut.
+    columns: Range<usize>,
+    /// Number of chars in each line of the window, after cutting it, and
+    /// whether it was cut.
+    lines: Vec<(usize, bool)>,
+}
+
+impl Window {
+    /// Creates the window for the lines that contain the given spans.
+    /// Returns `None` if none of those lines is longer than `max_width`,
+    /// in which case the whole source code can be passed to ariadne.
+    fn new(
+        src: &CachedSource,
+        spans: &[Span],
+        max_width: usize,
+    ) -> Option<Self> {
+        let line_of =
+            |offset: usize| src.line_index.line_of(offset.min(src.raw.len()));
+
+        let first_line = spans.iter().map(|span| line_of(span.start)).min()?;
+        let last_line = spans.iter().map(|span| line_of(span.end)).max()?;
+
+        let line = |n: usize| {
+            let start = src.line_index.line_start(n).unwrap();
+            let end = src
+                .line_index
+                .line_start(n + 1)
+                .map_or(src.raw.len(), |next_line_start| next_line_start - 1);
+            &src.raw[start..end]
+        };
+
+        let is_long = |line: &[u8]| {
+            line.len() > max_width && line.chars().count() > max_width
+        };
+
+        if !(first_line..=last_line).any(|n| is_long(line(n))) {
+            return None;
+        }
+
+        let column = |offset| {
+            src.line_index
+                .position(&src.raw, offset, ColumnEncoding::Utf32)
+                .column
+        };
+
+        // Columns of the leftmost and rightmost labeled positions.
+        let first_col =
+            spans.iter().map(|span| column(span.start)).min().unwrap_or(0);
+
+        let last_col =
+            spans.iter().map(|span| column(span.end)).max().unwrap_or(0);
+
+        // The window of visible columns is centered around the labels. If
+        // the labels don't fit in the window, it starts at the leftmost one.
+        let margin =
+            max_width.saturating_sub(last_col.saturating_sub(first_col)) / 2;
+
+        let start = first_col.saturating_sub(margin);
+        let columns = start..start + max_width;
+
+        let mut text = "\n".repeat(first_line);
+        let mut lines = Vec::with_capacity(last_line - first_line + 1);
+
+        for n in first_line..=last_line {
+            let line = line(n);
+            let (shown, cut) = if is_long(line) {
+                (cut_line(line, &columns), true)
+            } else {
+                (String::from_utf8_lossy(line).into_owned(), false)
+            };
+            lines.push((shown.chars().count(), cut));
+            text.push_str(&terminal_safe(&shown));
+            text.push('\n');
+        }
+
+        Some(Self { source: Source::from(text), first_line, columns, lines })
+    }
+
+    /// Returns the position within the window of the char at the given byte
+    /// offset in the original source code.
+    fn position(&self, src: &CachedSource, offset: usize) -> Position {
+        let pos =
+            src.line_index.position(&src.raw, offset, ColumnEncoding::Utf32);
+
+        let column = match self
+            .lines
+            .get(pos.line.wrapping_sub(self.first_line))
+        {
+            Some((len, true)) => {
+                let prefix =
+                    if self.columns.start > 0 { ELLIPSIS.len() } else { 0 };
+                let column =
+                    pos.column.clamp(self.columns.start, self.columns.end);
+                (prefix + column - self.columns.start).min(*len)
+            }
+            Some((len, false)) => pos.column.min(*len),
+            // Lines before the window are empty.
+            None => 0,
+        };
+
+        Position { line: pos.line, column }
+    }
+
+    /// Returns the offset within [`Window::source`], in chars, of the char
+    /// at the given byte offset in the original source code.
+    fn char_offset(&self, src: &CachedSource, offset: usize) -> usize {
+        let pos = self.position(src, offset);
+        let lines_in_window =
+            pos.line.saturating_sub(self.first_line).min(self.lines.len());
+
+        pos.line.min(self.first_line)
+            + self.lines[..lines_in_window]
+                .iter()
+                .map(|(len, _)| len + 1)
+                .sum::<usize>()
+            + pos.column
+    }
+}
+
+/// Returns the chars of `line` that are within `columns`, with ellipses
+/// replacing the removed ones.
+fn cut_line(line: &[u8], columns: &Range<usize>) -> String {
+    let mut result = String::with_capacity(columns.len() + 2 * ELLIPSIS.len());
+
+    if columns.start > 0 {
+        result.push_str(ELLIPSIS);
+    }
+
+    let mut chars = line.chars().skip(columns.start);
+
+    result.extend(chars.by_ref().take(columns.len()));
+
+    if chars.next().is_some() {
+        result.push_str(ELLIPSIS);
+    }
+
+    result
+}
@@ -1,13 +1,14 @@
 use crate::types::{Struct, TypeValue};
 use bstr::BString;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub enum Array {
-    Integers(Vec<i64>),
+    /// Integers that are `None` are undefined.
+    Integers(Vec<Option<i64>>),
     Floats(Vec<f64>),
     Bools(Vec<bool>),
     Strings(Vec<BString>),
-    Structs(Vec<Rc<Struct>>),
+    Structs(Vec<Arc<Struct>>),
 }
 
 impl Array {
@@ -35,7 +36,7 @@ impl Array {
         }
     }
 
-    pub fn as_integer_array(&self) -> &Vec<i64> {
+    pub fn as_integer_array(&self) -> &Vec<Option<i64>> {
         if let Self::Integers(v) = self {
             v
         } else {
@@ -67,7 +68,7 @@ impl Array {
         }
     }
 
-    pub fn as_struct_array(&self) -> &Vec<Rc<Struct>> {
+    pub fn as_struct_array(&self) -> &Vec<Arc<Struct>> {
         if let Self::Structs(v) = self {
             v
         } else {
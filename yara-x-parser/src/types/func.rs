@@ -33,6 +33,11 @@ use crate::types::TypeValue;
 ///  receives no argument and returns a string that may undefined will have
 ///  a mangled name: `foo@@su`.
 ///
+///  Arguments can be followed by a `u` character too, meaning that the
+///  function accepts an undefined value for that argument. Functions are
+///  not called when some argument is undefined, and their result is
+///  undefined, except if the argument is marked with `u`.
+///
 /// Both `<arguments>` and `<return type>` can be empty if the function
 /// doesn't receive arguments or doesn't return a value. Let's see some e
 /// examples:
@@ -44,6 +49,7 @@ use crate::types::TypeValue;
 /// foo() -> Option<()>            ->  foo@@u
 /// foo() -> Option<f32>           ->  foo@@fu
 /// foo() -> Option<(f64,f64)>     ->  foo@@ffu
+/// foo(i: Option<i64>)            ->  foo@iu@
 /// ```
 pub struct MangledFnName(String);
 
@@ -71,6 +77,9 @@ impl MangledFnName {
                 'b' => args.push(TypeValue::Bool(None)),
                 's' => args.push(TypeValue::String(None)),
                 'r' => args.push(TypeValue::Regexp(None)),
+                // `u` only tells that the previous argument may be
+                // undefined, see `args_may_be_undef`.
+                'u' if !args.is_empty() => {}
                 _ => panic!("unexpected argument type: `{}`", t),
             }
         }
@@ -95,6 +104,20 @@ impl MangledFnName {
     pub fn result_may_be_undef(&self) -> bool {
         self.0.ends_with('u')
     }
+
+    /// Returns a vector with one item per argument, which is true if the
+    /// function accepts an undefined value for that argument.
+    pub fn args_may_be_undef(&self) -> Vec<bool> {
+        let arg_types = self.0.split('@').nth(1).unwrap_or_default();
+        let mut result = vec![];
+        for t in arg_types.chars() {
+            match t {
+                'u' => *result.last_mut().unwrap() = true,
+                _ => result.push(false),
+            }
+        }
+        result
+    }
 }
 
 impl<S> From<S> for MangledFnName
@@ -116,6 +139,7 @@ pub struct FuncSignature {
     pub args: Vec<TypeValue>,
     pub result: TypeValue,
     pub result_may_be_undef: bool,
+    pub args_may_be_undef: Vec<bool>,
 }
 
 impl Ord for FuncSignature {
@@ -142,8 +166,15 @@ impl From<String> for FuncSignature {
     fn from(value: String) -> Self {
         let mangled_name = MangledFnName::from(value);
         let result_may_be_undef = mangled_name.result_may_be_undef();
+        let args_may_be_undef = mangled_name.args_may_be_undef();
         let (args, result) = mangled_name.unmangle();
-        Self { mangled_name, args, result, result_may_be_undef }
+        Self {
+            mangled_name,
+            args,
+            result,
+            result_may_be_undef,
+            args_may_be_undef,
+        }
     }
 }
 
@@ -210,6 +241,25 @@ mod test {
 
         assert!(!MangledFnName::from("foo@i@i").result_may_be_undef());
         assert!(MangledFnName::from("foo@i@iu").result_may_be_undef());
+
+        assert_eq!(
+            MangledFnName::from("foo@siuf@b").unmangle(),
+            (
+                vec![
+                    TypeValue::String(None),
+                    TypeValue::Integer(None),
+                    TypeValue::Float(None)
+                ],
+                TypeValue::Bool(None)
+            )
+        );
+
+        assert_eq!(
+            MangledFnName::from("foo@siuf@b").args_may_be_undef(),
+            vec![false, true, false]
+        );
+        assert!(!MangledFnName::from("foo@iu@").result_may_be_undef());
+        assert!(MangledFnName::from("foo@@").args_may_be_undef().is_empty());
     }
 
     #[test]
@@ -229,4 +279,10 @@ mod test {
     fn invalid_mangled_name_3() {
         MangledFnName::from("foo@x@i").unmangle();
     }
+
+    #[test]
+    #[should_panic]
+    fn invalid_mangled_name_4() {
+        MangledFnName::from("foo@ui@i").unmangle();
+    }
 }
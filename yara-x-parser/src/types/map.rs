@@ -2,6 +2,10 @@ use crate::types::TypeValue;
 use bstr::BString;
 use indexmap::IndexMap;
 
+/// A map stored in a module's structure.
+///
+/// Items are sorted by key (numerically for integer keys and byte-wise for
+/// string keys), and `for .. in` loops iterate the map in that order.
 pub enum Map {
     /// A map that has integer keys.
     IntegerKeys {
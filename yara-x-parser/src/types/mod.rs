@@ -2,7 +2,7 @@ use std::fmt::{Debug, Display, Formatter};
 use std::ops::BitAnd;
 use std::ops::BitOr;
 use std::ops::BitXor;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use bstr::ByteSlice;
 use bstr::{BStr, BString};
@@ -91,10 +91,10 @@ pub enum TypeValue {
     Bool(Option<bool>),
     String(Option<BString>),
     Regexp(Option<String>),
-    Struct(Rc<Struct>),
-    Array(Rc<Array>),
-    Map(Rc<Map>),
-    Func(Rc<Func>),
+    Struct(Arc<Struct>),
+    Array(Arc<Array>),
+    Map(Arc<Map>),
+    Func(Arc<Func>),
 }
 
 macro_rules! gen_boolean_op {
@@ -284,6 +284,14 @@ macro_rules! gen_comparison_op {
                         _ => Self::Bool(None)
                     }
                 }
+                (Self::Bool(lhs), Self::Bool(rhs)) => {
+                    match (lhs, rhs) {
+                        (Some(lhs), Some(rhs)) => {
+                            Self::Bool(Some(lhs $op rhs))
+                        },
+                        _ => Self::Bool(None)
+                    }
+                }
                 _ => Self::Unknown,
             }
         }
@@ -322,6 +330,7 @@ impl TypeValue {
     pub fn defined(&self) -> Self {
         match self {
             Self::Unknown => Self::Unknown,
+            Self::Bool(Some(_)) => Self::Bool(Some(true)),
             Self::Integer(Some(_)) => Self::Bool(Some(true)),
             Self::Float(Some(_)) => Self::Bool(Some(true)),
             Self::String(Some(_)) => Self::Bool(Some(true)),
@@ -351,7 +360,7 @@ impl TypeValue {
 
     pub fn minus(&self) -> Self {
         match self {
-            Self::Integer(Some(value)) => Self::Integer(Some(-*value)),
+            Self::Integer(Some(value)) => Self::Integer(value.checked_neg()),
             Self::Float(Some(value)) => Self::Float(Some(-*value)),
             Self::Integer(None) => Self::Integer(None),
             Self::Float(None) => Self::Float(None),
@@ -433,7 +442,7 @@ impl TypeValue {
         }
     }
 
-    pub fn as_array(&self) -> Rc<Array> {
+    pub fn as_array(&self) -> Arc<Array> {
         if let TypeValue::Array(array) = self {
             array.clone()
         } else {
@@ -441,7 +450,7 @@ impl TypeValue {
         }
     }
 
-    pub fn as_struct(&self) -> Rc<Struct> {
+    pub fn as_struct(&self) -> Arc<Struct> {
         if let TypeValue::Struct(structure) = self {
             structure.clone()
         } else {
@@ -449,7 +458,7 @@ impl TypeValue {
         }
     }
 
-    pub fn as_map(&self) -> Rc<Map> {
+    pub fn as_map(&self) -> Arc<Map> {
         if let TypeValue::Map(map) = self {
             map.clone()
         } else {
@@ -628,6 +637,15 @@ mod tests {
         assert_eq!(Integer(Some(2)).rem(&Integer(Some(0))), Integer(None));
     }
 
+    #[test]
+    fn minus() {
+        assert_eq!(Unknown.minus(), Unknown);
+        assert_eq!(Integer(None).minus(), Integer(None));
+        assert_eq!(Integer(Some(1)).minus(), Integer(Some(-1)));
+        assert_eq!(Integer(Some(i64::MIN)).minus(), Integer(None));
+        assert_eq!(Float(Some(1.5)).minus(), Float(Some(-1.5)));
+    }
+
     #[test]
     fn and() {
         assert_eq!(Unknown.and(&Bool(Some(true))), Unknown);
@@ -672,4 +690,20 @@ mod tests {
         assert_eq!(Integer(Some(1)).shr(&Integer(Some(1))), Integer(Some(0)));
         assert_eq!(Integer(Some(2)).shr(&Integer(Some(1))), Integer(Some(1)));
     }
+
+    #[test]
+    fn comparison() {
+        assert_eq!(Unknown.eq(&Integer(Some(1))), Unknown);
+        assert_eq!(Integer(Some(1)).eq(&Unknown), Unknown);
+        assert_eq!(Integer(None).eq(&Integer(Some(1))), Bool(None));
+        assert_eq!(Integer(Some(1)).eq(&Float(None)), Bool(None));
+        assert_eq!(Integer(Some(1)).eq(&Float(Some(1.0))), Bool(Some(true)));
+        assert_eq!(Float(Some(1.0)).ne(&Integer(Some(1))), Bool(Some(false)));
+        assert_eq!(Integer(Some(1)).lt(&Float(Some(1.5))), Bool(Some(true)));
+        assert_eq!(Float(Some(1.5)).ge(&Integer(Some(2))), Bool(Some(false)));
+        assert_eq!(Bool(Some(true)).eq(&Bool(Some(true))), Bool(Some(true)));
+        assert_eq!(Bool(Some(true)).ne(&Bool(None)), Bool(None));
+        assert_eq!(Bool(Some(true)).eq(&Integer(Some(1))), Unknown);
+        assert_eq!(Integer(Some(1)).eq(&String(None)), Unknown);
+    }
 }
@@ -1,5 +1,5 @@
 use std::ops::Deref;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use bstr::BString;
 use indexmap::IndexMap;
@@ -9,7 +9,7 @@ use protobuf::reflect::{
     ReflectRepeatedRef, ReflectValueRef, RuntimeFieldType, RuntimeType,
 };
 use protobuf::MessageDyn;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 
 use yara_x_proto::exts::enum_options as yara_enum_options;
 use yara_x_proto::exts::field_options as yara_field_options;
@@ -18,7 +18,7 @@ use yara_x_proto::exts::module_options as yara_module_options;
 use crate::types::{Array, Map, TypeValue};
 
 /// A field in a [`Struct`].
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct StructField {
     /// Field name.
     pub name: String,
@@ -45,7 +45,7 @@ pub struct StructField {
 /// The structures that represent a YARA module are created from the protobuf
 /// associated to that module. Functions [`Struct::from_proto_msg`] and
 /// [`Struct::from_proto_descriptor_and_msg`] are used for that purpose.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Struct {
     /// Fields in this structure. The index of each field is the index that it
     /// has in this vector. Fields are sorted by field number, which means that
@@ -72,6 +72,9 @@ impl Struct {
 
     /// Adds a new field to the structure.
     ///
+    /// If the structure already has a field with the same name, the value
+    /// of the existing field is replaced, and the field keeps its index.
+    ///
     /// The field name may be a dot-separated sequence of field names, like
     /// "foo.bar.baz". In such cases the structure must contain a field named
     /// "foo", which must be another struct with a field named "bar", which
@@ -84,7 +87,7 @@ impl Struct {
     /// name is "foo.bar.baz" but the field "foo" doesn't exist or is not
     /// a structure.
     ///
-    /// Also panics if there is some [`Rc`] or [`Weak`] pointer pointing to
+    /// Also panics if there is some [`Arc`] or [`Weak`] pointer pointing to
     /// any of the intermediate structures (e.g: the structures in the "foo"
     /// and "bar" fields).
     pub fn add_field(&mut self, name: &str, value: TypeValue) -> &mut Self {
@@ -95,9 +98,9 @@ impl Struct {
                 });
 
             if let TypeValue::Struct(ref mut s) = field.type_value {
-                let s = Rc::<Struct>::get_mut(s).unwrap_or_else(|| {
+                let s = Arc::<Struct>::get_mut(s).unwrap_or_else(|| {
                     panic!(
-                        "`add_field` was called while an `Rc` or `Weak` pointer points to field `{}`",
+                        "`add_field` was called while an `Arc` or `Weak` pointer points to field `{}`",
                         (&name[0..dot])
                     )
                 });
@@ -106,6 +109,8 @@ impl Struct {
             } else {
                 panic!("field `{}` is not a struct", &name[0..dot])
             }
+        } else if let Some(field) = self.field_by_name_mut(name) {
+            field.type_value = value;
         } else {
             let index = self.fields.len();
             self.fields.push(StructField {
@@ -120,6 +125,31 @@ impl Struct {
         self
     }
 
+    /// Returns an iterator over the fields in the structure, sorted by
+    /// index.
+    pub fn fields(&self) -> impl Iterator<Item = &StructField> {
+        self.fields.iter()
+    }
+
+    /// Returns the number of fields in the structure.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns true if the structure doesn't have any field.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Removes the fields with index `len` or greater, keeping the first
+    /// `len` fields. Does nothing if the structure has `len` fields or less.
+    pub fn truncate(&mut self, len: usize) {
+        self.fields.truncate(len);
+        self.field_index.retain(|_, index| *index < len);
+    }
+
     #[inline]
     pub fn field_by_index(&self, index: usize) -> Option<&StructField> {
         self.fields.get(index)
@@ -201,7 +231,8 @@ impl Struct {
     ///
     /// If `MyMessage` is the root message for the module, both `SomeEnum`
     /// and `SomeOtherEnum` will be included as fields of the [`Struct`]
-    /// created for `MyMessage`.
+    /// created for `MyMessage`. The items of inline enums are included as
+    /// integer fields instead, see [`Self::enum_is_inline`].
     ///
     /// # Panics
     ///
@@ -212,7 +243,18 @@ impl Struct {
         msg: Option<&dyn MessageDyn>,
         generate_fields_for_enums: bool,
     ) -> Self {
-        let syntax = msg_descriptor.file_descriptor().syntax();
+        // Without a message the structure describes the types of the
+        // fields, but the fields have no values, not even the default values
+        // of proto3 fields. Otherwise, the compiler would take the default
+        // values as constants while compiling expressions like
+        // `module.field == 1`. For that reason, fields are handled as in
+        // proto2, where missing values are undefined.
+        let syntax = if msg.is_some() {
+            msg_descriptor.file_descriptor().syntax()
+        } else {
+            Syntax::Proto2
+        };
+
         let mut fields = Vec::new();
 
         for fd in msg_descriptor.fields() {
@@ -307,6 +349,22 @@ impl Struct {
                 };
 
             for enum_ in enums {
+                // Items in inline enums are added as integer fields of this
+                // structure, instead of fields of a nested structure.
+                if Self::enum_is_inline(&enum_) {
+                    for item in enum_.values() {
+                        fields.push(StructField {
+                            index: fields.len(),
+                            type_value: TypeValue::Integer(Some(
+                                item.value() as i64
+                            )),
+                            number: 0,
+                            name: item.name().to_owned(),
+                        })
+                    }
+                    continue;
+                }
+
                 let mut enum_struct = Struct::new();
 
                 for item in enum_.values() {
@@ -318,7 +376,7 @@ impl Struct {
 
                 fields.push(StructField {
                     index: fields.len(),
-                    type_value: TypeValue::Struct(Rc::new(enum_struct)),
+                    type_value: TypeValue::Struct(Arc::new(enum_struct)),
                     number: 0,
                     name: Self::enum_name(&enum_),
                 })
@@ -344,6 +402,110 @@ impl Struct {
         Self { fields, field_index }
     }
 
+    /// Checks that a [`Struct`] can be created from the given
+    /// [`MessageDescriptor`].
+    ///
+    /// The descriptors of built-in modules are always valid, but the
+    /// descriptors of user-defined modules can use features that YARA
+    /// doesn't support, like messages that contain themselves, maps with
+    /// keys that are not strings or integers, or fields and enums with the
+    /// same name. In such cases [`Struct::from_proto_descriptor_and_msg`]
+    /// panics, while this function returns a description of the problem.
+    pub fn check_proto_descriptor(
+        msg_descriptor: &MessageDescriptor,
+    ) -> Result<(), String> {
+        Self::check_proto_descriptor_impl(msg_descriptor, &mut Vec::new())
+    }
+
+    fn check_proto_descriptor_impl(
+        msg_descriptor: &MessageDescriptor,
+        parents: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let msg_name = msg_descriptor.full_name().to_owned();
+
+        if parents.contains(&msg_name) {
+            return Err(format!("message `{}` contains itself", msg_name));
+        }
+
+        let mut names = Vec::new();
+        let mut nested = Vec::new();
+
+        for fd in msg_descriptor.fields() {
+            if Self::ignore_field(&fd) {
+                continue;
+            }
+
+            names.push(Self::field_name(&fd));
+
+            let value_ty = match fd.runtime_field_type() {
+                RuntimeFieldType::Singular(ty)
+                | RuntimeFieldType::Repeated(ty) => ty,
+                RuntimeFieldType::Map(key_ty, value_ty) => {
+                    if !matches!(
+                        key_ty,
+                        RuntimeType::String
+                            | RuntimeType::I32
+                            | RuntimeType::I64
+                            | RuntimeType::U32
+                            | RuntimeType::U64
+                    ) {
+                        return Err(format!(
+                            "field `{}` in `{}` is a map with keys of type `{}`",
+                            fd.name(),
+                            msg_name,
+                            key_ty
+                        ));
+                    }
+                    value_ty
+                }
+            };
+
+            if let RuntimeType::Message(nested_descriptor) = value_ty {
+                nested.push(nested_descriptor);
+            }
+        }
+
+        let enums: Box<dyn Iterator<Item = EnumDescriptor>> =
+            if Self::is_root_msg(msg_descriptor) {
+                Box::new(
+                    msg_descriptor
+                        .nested_enums()
+                        .chain(msg_descriptor.file_descriptor().enums()),
+                )
+            } else {
+                Box::new(msg_descriptor.nested_enums())
+            };
+
+        for enum_ in enums {
+            if Self::enum_is_inline(&enum_) {
+                names.extend(enum_.values().map(|v| v.name().to_owned()));
+            } else {
+                names.push(Self::enum_name(&enum_));
+            }
+        }
+
+        let mut seen = FxHashSet::default();
+
+        for name in names {
+            if !seen.insert(name.clone()) {
+                return Err(format!(
+                    "duplicate field name `{}` in `{}`",
+                    name, msg_name
+                ));
+            }
+        }
+
+        parents.push(msg_name);
+
+        for nested_descriptor in nested {
+            Self::check_proto_descriptor_impl(&nested_descriptor, parents)?;
+        }
+
+        parents.pop();
+
+        Ok(())
+    }
+
     /// Returns true if the given message is the YARA module's root message.
     fn is_root_msg(msg_descriptor: &MessageDescriptor) -> bool {
         let file_descriptor = msg_descriptor.file_descriptor();
@@ -384,6 +546,33 @@ impl Struct {
         }
     }
 
+    /// Given a [`EnumDescriptor`] returns `true` if the enum is inline.
+    ///
+    /// The items of an inline enum are not grouped in a structure named
+    /// after the enum, instead they are added directly to the structure
+    /// where the enum is declared. Inline enums are declared as follows:
+    ///
+    /// ```text
+    /// enum Enumeration {
+    ///   option (yara.enum_options).inline = true;
+    ///   ITEM_0 = 0;
+    ///   ITEM_1 = 1;
+    /// }
+    /// ```
+    ///
+    /// Here `ITEM_0` and `ITEM_1` are accessed as `module.ITEM_0` and
+    /// `module.ITEM_1`, instead of `module.Enumeration.ITEM_0` and
+    /// `module.Enumeration.ITEM_1`.
+    fn enum_is_inline(enum_descriptor: &EnumDescriptor) -> bool {
+        if let Some(enum_options) =
+            yara_enum_options.get(&enum_descriptor.proto().options)
+        {
+            enum_options.inline.unwrap_or(false)
+        } else {
+            false
+        }
+    }
+
     /// Given a [`FieldDescriptor`] returns the name that this field will
     /// have in the corresponding [`Struct`].
     ///
@@ -459,17 +648,19 @@ impl Struct {
             | RuntimeType::I64
             | RuntimeType::U32
             | RuntimeType::U64
-            | RuntimeType::Enum(_) => TypeValue::Integer(
-                value.map(Self::value_as_i64).or_else(|| {
-                    // None values are translated to their default values,
-                    // in proto3. In proto2 they are left as None.
+            | RuntimeType::Enum(_) => TypeValue::Integer(value.map_or_else(
+                || {
+                    // None values are translated to their default
+                    // values, in proto3. In proto2 they are left as
+                    // None.
                     if syntax == Syntax::Proto3 {
                         Some(0)
                     } else {
                         None
                     }
-                }),
-            ),
+                },
+                Self::value_as_i64,
+            )),
             RuntimeType::F32 | RuntimeType::F64 => {
                 TypeValue::Float(value.map(Self::value_as_f64).or_else(|| {
                     if syntax == Syntax::Proto3 {
@@ -511,7 +702,7 @@ impl Struct {
                         enum_as_fields,
                     )
                 };
-                TypeValue::Struct(Rc::new(structure))
+                TypeValue::Struct(Arc::new(structure))
             }
         }
     }
@@ -522,45 +713,22 @@ impl Struct {
         enum_as_fields: bool,
     ) -> TypeValue {
         let array = match ty {
-            RuntimeType::I32 => {
-                if let Some(repeated) = repeated {
-                    Array::Integers(
-                        repeated
-                            .into_iter()
-                            .map(|value| value.to_i32().unwrap() as i64)
-                            .collect(),
-                    )
-                } else {
-                    Array::Integers(vec![])
-                }
-            }
-            RuntimeType::I64 => {
-                if let Some(repeated) = repeated {
-                    Array::Integers(
-                        repeated
-                            .into_iter()
-                            .map(|value| value.to_i64().unwrap())
-                            .collect(),
-                    )
-                } else {
-                    Array::Integers(vec![])
-                }
-            }
-            RuntimeType::U32 => {
+            RuntimeType::I32
+            | RuntimeType::I64
+            | RuntimeType::U32
+            | RuntimeType::U64
+            | RuntimeType::Enum(_) => {
+                // Integers are converted like in singular fields, values
+                // that don't fit in an `i64` are undefined, but they keep
+                // their positions in the array.
                 if let Some(repeated) = repeated {
                     Array::Integers(
-                        repeated
-                            .into_iter()
-                            .map(|value| value.to_u32().unwrap() as i64)
-                            .collect(),
+                        repeated.into_iter().map(Self::value_as_i64).collect(),
                     )
                 } else {
                     Array::Integers(vec![])
                 }
             }
-            RuntimeType::U64 => {
-                todo!()
-            }
             RuntimeType::F32 => {
                 if let Some(repeated) = repeated {
                     Array::Floats(
@@ -625,34 +793,24 @@ impl Struct {
                     Array::Strings(vec![])
                 }
             }
-            RuntimeType::Enum(_) => {
-                if let Some(repeated) = repeated {
-                    Array::Integers(
-                        repeated
-                            .into_iter()
-                            .map(|value| value.to_enum_value().unwrap() as i64)
-                            .collect(),
-                    )
-                } else {
-                    Array::Integers(vec![])
-                }
-            }
             RuntimeType::Message(msg_descriptor) => {
                 if let Some(repeated) = repeated {
                     Array::Structs(
                         repeated
                             .into_iter()
                             .map(|value| {
-                                Rc::new(Self::from_proto_descriptor_and_value(
-                                    msg_descriptor,
-                                    value,
-                                    enum_as_fields,
-                                ))
+                                Arc::new(
+                                    Self::from_proto_descriptor_and_value(
+                                        msg_descriptor,
+                                        value,
+                                        enum_as_fields,
+                                    ),
+                                )
                             })
                             .collect(),
                     )
                 } else {
-                    Array::Structs(vec![Rc::new(
+                    Array::Structs(vec![Arc::new(
                         Struct::from_proto_descriptor_and_msg(
                             msg_descriptor,
                             None,
@@ -663,7 +821,7 @@ impl Struct {
             }
         };
 
-        TypeValue::Array(Rc::new(array))
+        TypeValue::Array(Arc::new(array))
     }
 
     fn new_map(
@@ -694,7 +852,7 @@ impl Struct {
             }
         };
 
-        TypeValue::Map(Rc::new(map))
+        TypeValue::Map(Arc::new(map))
     }
 
     fn new_map_with_integer_key(
@@ -706,8 +864,13 @@ impl Struct {
         if let Some(map) = map {
             let mut result = IndexMap::default();
             for (key, value) in map.into_iter() {
+                // Keys that don't fit in an `i64` can't be used in YARA.
+                let key = match Self::value_as_i64(key) {
+                    Some(key) => key,
+                    None => continue,
+                };
                 result.insert(
-                    Self::value_as_i64(key),
+                    key,
                     Self::new_value(
                         value_ty,
                         Some(value),
@@ -716,6 +879,9 @@ impl Struct {
                     ),
                 );
             }
+            // Protobuf maps don't guarantee any iteration order, items are
+            // sorted by key so that loops over the map are deterministic.
+            result.sort_keys();
             Map::IntegerKeys { deputy: None, map: result }
         } else {
             Map::IntegerKeys {
@@ -749,6 +915,9 @@ impl Struct {
                     ),
                 );
             }
+            // Protobuf maps don't guarantee any iteration order, items are
+            // sorted by key so that loops over the map are deterministic.
+            result.sort_keys();
             Map::StringKeys { deputy: None, map: result }
         } else {
             Map::StringKeys {
@@ -779,13 +948,15 @@ impl Struct {
         }
     }
 
-    fn value_as_i64(value: ReflectValueRef) -> i64 {
+    /// Converts an integer value into an `i64`. Returns `None` for `uint64`
+    /// values that don't fit in an `i64`, which are undefined in YARA.
+    fn value_as_i64(value: ReflectValueRef) -> Option<i64> {
         match value {
-            ReflectValueRef::U32(v) => v as i64,
-            ReflectValueRef::U64(v) => v as i64,
-            ReflectValueRef::I32(v) => v as i64,
-            ReflectValueRef::I64(v) => v,
-            ReflectValueRef::Enum(_, v) => v as i64,
+            ReflectValueRef::U32(v) => Some(v as i64),
+            ReflectValueRef::U64(v) => i64::try_from(v).ok(),
+            ReflectValueRef::I32(v) => Some(v as i64),
+            ReflectValueRef::I64(v) => Some(v),
+            ReflectValueRef::Enum(_, v) => Some(v as i64),
             _ => panic!(),
         }
     }
@@ -818,14 +989,14 @@ impl Struct {
 mod tests {
     use super::Struct;
     use crate::types::TypeValue;
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     #[test]
     fn test_struct() {
         let mut root = Struct::default();
         let foo = Struct::default();
 
-        root.add_field("foo", TypeValue::Struct(Rc::new(foo)));
+        root.add_field("foo", TypeValue::Struct(Arc::new(foo)));
 
         let field1 = root.field_by_name("foo").unwrap();
         let field2 = root.field_by_index(field1.index).unwrap();
@@ -834,5 +1005,15 @@ mod tests {
         assert_eq!(field1.name, field2.name);
 
         root.add_field("foo.bar", TypeValue::Integer(Some(1)));
+
+        // Adding a field that already exists replaces its value.
+        root.add_field("baz", TypeValue::Integer(Some(1)));
+        root.add_field("baz", TypeValue::Integer(Some(2)));
+
+        let baz = root.field_by_name("baz").unwrap();
+
+        assert_eq!(baz.index, 1);
+        assert!(matches!(baz.type_value, TypeValue::Integer(Some(2))));
+        assert!(root.field_by_index(2).is_none());
     }
 }
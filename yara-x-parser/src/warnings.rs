@@ -2,19 +2,115 @@ use std::fmt::{Debug, Display, Formatter};
 use yara_x_macros::Error;
 
 use crate::ast::Span;
-use crate::report::ReportBuilder;
-use crate::report::ReportType;
+use crate::escape::terminal_safe;
+use crate::report::{Label, Report, ReportBuilder, ReportType};
 use crate::types::Type;
 use crate::SourceCode;
 
 /// A warning raised while parsing or compiling YARA rules.
+///
+/// Like [`crate::Error`], the warning contains a detailed text-mode report,
+/// and a [`WarningInfo`] with the individual pieces of information that are
+/// relevant for that specific warning. The pieces of the report, like the
+/// title and labels, are also available separately.
+///
+/// With the `serde` feature, warnings implement `serde::Serialize`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Warning {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    info: Box<WarningInfo>,
+    code: &'static str,
+    title: String,
+    origin: Option<String>,
+    labels: Vec<Label>,
+    report: String,
+}
+
+impl Warning {
+    /// Renders the detailed report for the warning.
+    ///
+    /// Warnings are created without a report, which is rendered once the
+    /// parser or compiler finishes processing the source code the warning
+    /// refers to.
+    #[doc(hidden)]
+    pub fn render(
+        &mut self,
+        report_builder: &ReportBuilder,
+        src: &SourceCode,
+    ) {
+        self.report = report_builder.render(src, self.info.as_ref());
+        self.labels = report_builder.labels(src, self.info.as_ref());
+        self.origin = src.origin.clone();
+    }
+
+    /// Returns a string with a detailed text-mode report.
+    pub fn as_str(&self) -> &str {
+        self.report.as_str()
+    }
+
+    /// Returns additional information about the warning.
+    pub fn info(&self) -> &WarningInfo {
+        self.info.as_ref()
+    }
+
+    /// Returns the identifier for this kind of warning, like
+    /// `non_boolean_as_boolean`. See [`WarningInfo::CODES`] for the full
+    /// list.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Returns the warning's title, which is the first line of the report
+    /// without the `warning:` prefix.
+    pub fn title(&self) -> &str {
+        self.title.as_str()
+    }
+
+    /// Returns the labels in the report. The first one is the main label.
+    pub fn labels(&self) -> &[Label] {
+        self.labels.as_slice()
+    }
+
+    /// Returns the origin of the source code that raised the warning, as
+    /// set by [`SourceCode::origin`].
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+}
+
+impl From<WarningInfo> for Warning {
+    fn from(info: WarningInfo) -> Self {
+        Self {
+            code: info.code(),
+            title: terminal_safe(&info.title()).into_owned(),
+            origin: None,
+            labels: Vec::new(),
+            info: Box::new(info),
+            report: String::new(),
+        }
+    }
+}
+
+impl Debug for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.report)
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.report)
+    }
+}
+
+/// Additional information about a warning.
 #[rustfmt::skip]
-#[derive(Error)]
-pub enum Warning {
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum WarningInfo {
     #[warning("consecutive jumps in hex pattern `{pattern_ident}`")]
     #[label("these consecutive jumps will be treated as {coalesced_jump}", jumps_span)]
     ConsecutiveJumps {
-        detailed_report: String,
         pattern_ident: String,
         coalesced_jump: String,
         jumps_span: Span,
@@ -24,7 +120,6 @@ pub enum Warning {
     #[label("this implies that multiple patterns must match", quantifier_span)]
     #[label("but they must match at the same offset", at_span)]
     PotentiallyWrongExpression {
-        detailed_report: String,
         quantifier_span: Span,
         at_span: Span,
     },
@@ -33,17 +128,50 @@ pub enum Warning {
     #[label("this expression is always {value}", span)]
     #[note(note)]
     InvariantBooleanExpression {
-        detailed_report: String,
         value: bool,
         span: Span,
         note: Option<String>,
     },
 
+    #[warning("rule condition is always {value}")]
+    #[label("this condition is known at compile time", span)]
+    InvariantCondition {
+        value: bool,
+        span: Span,
+    },
+
+    #[warning("pattern `{pattern_ident}` is shadowed by `{shadowing_ident}`")]
+    #[label("every match of `{pattern_ident}` contains a match of `{shadowing_ident}`", pattern_span)]
+    #[label("`{shadowing_ident}` declared here", shadowing_span, style="note")]
+    #[label("`{pattern_ident}` can't change the result of this expression", of_span)]
+    ShadowedPattern {
+        pattern_ident: String,
+        shadowing_ident: String,
+        pattern_span: Span,
+        shadowing_span: Span,
+        of_span: Span,
+    },
+
+    #[warning("`{feature}` is deprecated")]
+    #[label("`{feature}` is deprecated and will be removed", span)]
+    #[note(note)]
+    DeprecatedFeature {
+        feature: String,
+        span: Span,
+        note: Option<String>,
+    },
+
+    #[warning("percentage out of range")]
+    #[label("this is {percentage}%, but percentages go from 0% to 100%", span)]
+    PercentageOutOfRange {
+        percentage: i64,
+        span: Span,
+    },
+
     #[warning("non-boolean expression used as boolean")]
     #[label("this expression is `{expression_type}` but is being used as `bool`", span)]
     #[note(note)]
     NonBooleanAsBoolean {
-        detailed_report: String,
         expression_type: Type,
         span: Span,
         note: Option<String>,
@@ -60,9 +188,95 @@ pub enum Warning {
       style="note"
     )]
     DuplicateImport {
-        detailed_report: String,
         module_name: String,
         new_import_span: Span,
         existing_import_span: Span,
     },
+
+    #[warning("duplicate tag `{tag}`")]
+    #[label("duplicate tag", new_tag_span)]
+    #[label(
+      "`{tag}` used here for the first time",
+      existing_tag_span,
+      style="note"
+    )]
+    DuplicateTag {
+        tag: String,
+        new_tag_span: Span,
+        existing_tag_span: Span,
+    },
+
+    #[warning("private rule `{rule}` is never used")]
+    #[label("this rule is not referenced by any other rule", span)]
+    #[note(note)]
+    UnusedPrivateRule {
+        rule: String,
+        span: Span,
+        note: Option<String>,
+    },
+
+    #[warning("{kind} `{identifier}` violates the naming policy")]
+    #[label("{reason}", span)]
+    NamingPolicyViolation {
+        kind: &'static str,
+        identifier: String,
+        reason: String,
+        span: Span,
+    },
+
+    #[warning("namespace `{namespace}` violates the naming policy")]
+    #[label("{reason}", span)]
+    #[note(note)]
+    NamespaceNamingPolicyViolation {
+        namespace: String,
+        reason: String,
+        span: Span,
+        note: Option<String>,
+    },
+
+    #[warning("misplaced pragma")]
+    #[label("this pragma is ignored", span)]
+    #[note(note)]
+    MisplacedPragma {
+        span: Span,
+        note: Option<String>,
+    },
+
+    #[warning("unknown pragma `{pragma}`")]
+    #[label("this pragma is ignored", span)]
+    UnknownPragma {
+        pragma: String,
+        span: Span,
+    },
+
+    #[warning("pragma `{pragma}` is overridden")]
+    #[label("this pragma is ignored", span)]
+    #[note(note)]
+    OverriddenPragma {
+        pragma: String,
+        span: Span,
+        note: Option<String>,
+    },
+
+    #[warning("module `{module}` is never used")]
+    #[label("this module is not used by any rule", span)]
+    UnusedImport {
+        module: String,
+        span: Span,
+    },
+
+    #[warning("unknown module `{module}`")]
+    #[label("module `{module}` not found, this import is ignored", span)]
+    UnknownModule {
+        module: String,
+        span: Span,
+    },
+
+    #[warning("rule `{rule}` is ignored")]
+    #[label("this rule depends on the unknown module `{module}`", span)]
+    IgnoredRule {
+        rule: String,
+        module: String,
+        span: Span,
+    },
 }
@@ -23,11 +23,9 @@ macro_rules! gen_bench {
             });
 
             group.bench_function("yara-x", |b| {
-                let rules = yara_x::Compiler::new()
-                    .add_source($rule)
-                    .unwrap()
-                    .build()
-                    .unwrap();
+                let mut compiler = yara_x::Compiler::new();
+                compiler.add_source($rule).unwrap();
+                let rules = compiler.build().unwrap();
 
                 let mut scanner = yara_x::Scanner::new(&rules);
 
@@ -71,9 +69,63 @@ gen_bench!(
     "fabadafabafabadafabafabafafabadafabafabadafabafabafafabadafabafabadafabafabafa".as_bytes()
 );
 
+fn bench_diagnostics(c: &mut Criterion) {
+    let mut src = String::new();
+    for i in 0..2000 {
+        src.push_str(&format!(
+            "rule test_{i} : foo foo {{ condition: {i} }}\n"
+        ));
+    }
+
+    let mut compiler = yara_x::Compiler::new();
+    compiler.add_source(src.as_str()).unwrap();
+
+    let diagnostics: Vec<_> =
+        compiler.warnings().iter().map(yara_x::Diagnostic::from).collect();
+
+    let mut bundle = yara_x::DiagnosticsBundle::new();
+    for diagnostic in &diagnostics {
+        bundle.push(diagnostic);
+    }
+
+    let bytes = bundle.encode();
+
+    let mut group = c.benchmark_group("Diagnostics");
+
+    group.bench_function("binary decode-filter", |b| {
+        b.iter(|| {
+            let reader = yara_x::DiagnosticsReader::new(&bytes).unwrap();
+            reader.filter(|_, code| code == "duplicate_tag").count()
+        })
+    });
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_vec(&diagnostics).unwrap();
+
+        println!(
+            "diagnostics size: binary {} bytes, JSON {} bytes ({:.1}x)",
+            bytes.len(),
+            json.len(),
+            json.len() as f64 / bytes.len() as f64
+        );
+
+        group.bench_function("JSON decode-filter", |b| {
+            b.iter(|| {
+                let values: Vec<serde_json::Value> =
+                    serde_json::from_slice(&json).unwrap();
+                values
+                    .iter()
+                    .filter(|value| value["code"] == "duplicate_tag")
+                    .count()
+            })
+        });
+    }
+}
+
 criterion_group!(
     name = benches; 
     config = Criterion::default(); 
-    targets = bench_loop_1, bench_loop_2, bench_loop_3, bench_simple_pattern);
+    targets = bench_loop_1, bench_loop_2, bench_loop_3, bench_simple_pattern, bench_diagnostics);
 
 criterion_main!(benches);
@@ -0,0 +1,219 @@
+/*! Deterministic generators for synthetic rule sets and data corpora.
+
+Everything produced here depends only on the parameters and the seed, not
+on the platform or on the versions of other crates, which makes the
+workloads reproducible between runs and between machines.
+*/
+
+/// Pseudo-random number generator implementing SplitMix64.
+///
+/// It is not suitable for anything but generating workloads, its only
+/// virtue is that the sequence it produces is fully determined by the seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in the range `[0, n)`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns a number in the range `[lo, hi]`.
+    pub fn between(&mut self, lo: usize, hi: usize) -> usize {
+        lo + self.below((hi - lo + 1) as u64) as usize
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        self.next_u64() as u8
+    }
+
+    /// Returns a random lowercase ASCII letter.
+    pub fn letter(&mut self) -> u8 {
+        b'a' + self.below(26) as u8
+    }
+
+    /// Returns a string of lowercase letters with a length in the range
+    /// `[min_len, max_len]`.
+    pub fn word(&mut self, min_len: usize, max_len: usize) -> String {
+        let len = self.between(min_len, max_len);
+        (0..len).map(|_| self.letter() as char).collect()
+    }
+}
+
+/// Relative weights of each kind of rule in a generated rule set.
+///
+/// For example, with `text: 3` and `hex: 1`, three out of four rules have
+/// a text pattern and one has a hex pattern.
+#[derive(Clone, Copy, Debug)]
+pub struct PatternMix {
+    /// Name used in the benchmark IDs.
+    pub name: &'static str,
+    /// Rules with a text pattern, like `"foobar"`.
+    pub text: u32,
+    /// Rules with a hex pattern containing wildcards and jumps.
+    pub hex: u32,
+    /// Rules with a regular expression.
+    pub regexp: u32,
+    /// Rules without patterns, with a condition that reads the data.
+    pub condition_only: u32,
+}
+
+impl PatternMix {
+    /// Only text patterns, the best case for the Aho-Corasick scan.
+    pub const TEXT: Self =
+        Self { name: "text", text: 1, hex: 0, regexp: 0, condition_only: 0 };
+
+    /// A mix resembling real-world rule sets.
+    pub const MIXED: Self =
+        Self { name: "mixed", text: 6, hex: 2, regexp: 1, condition_only: 1 };
+
+    fn total(&self) -> u32 {
+        self.text + self.hex + self.regexp + self.condition_only
+    }
+
+    /// Returns the kind of rule that corresponds to `n`, which must be in
+    /// the range `[0, total)`.
+    fn pick(&self, n: u32) -> RuleKind {
+        if n < self.text {
+            RuleKind::Text
+        } else if n < self.text + self.hex {
+            RuleKind::Hex
+        } else if n < self.text + self.hex + self.regexp {
+            RuleKind::Regexp
+        } else {
+            RuleKind::ConditionOnly
+        }
+    }
+}
+
+enum RuleKind {
+    Text,
+    Hex,
+    Regexp,
+    ConditionOnly,
+}
+
+/// A generated rule set.
+#[derive(Clone, Copy, Debug)]
+pub struct RuleSet {
+    pub num_rules: usize,
+    pub mix: PatternMix,
+    pub seed: u64,
+}
+
+impl RuleSet {
+    /// Short description used in the benchmark IDs.
+    pub fn name(&self) -> String {
+        format!("{}_rules_{}", self.num_rules, self.mix.name)
+    }
+
+    /// Returns the source code of the rule set.
+    pub fn source(&self) -> String {
+        let mut rng = Rng::new(self.seed);
+        let mut src = String::new();
+        let total = self.mix.total() as u64;
+
+        for i in 0..self.num_rules {
+            let body = match self.mix.pick(rng.below(total) as u32) {
+                RuleKind::Text => format!(
+                    "strings: $a = \"{}\" condition: $a",
+                    rng.word(6, 12)
+                ),
+                RuleKind::Hex => format!(
+                    "strings: $a = {} condition: $a",
+                    hex_pattern(&mut rng)
+                ),
+                RuleKind::Regexp => format!(
+                    "strings: $a = /{}[0-9]{{2,4}}{}/ condition: $a",
+                    rng.word(4, 6),
+                    rng.word(2, 4)
+                ),
+                RuleKind::ConditionOnly => format!(
+                    "condition: filesize > {} and uint8({}) == {:#04x}",
+                    rng.below(1024),
+                    rng.below(64),
+                    rng.byte()
+                ),
+            };
+
+            src.push_str(&format!("rule rule_{i} {{ {body} }}\n"));
+        }
+
+        src
+    }
+}
+
+/// Returns a hex pattern like `{ 4d 5a ?? 90 [2-8] 50 45 }`.
+fn hex_pattern(rng: &mut Rng) -> String {
+    let mut pattern = String::from("{");
+    for _ in 0..rng.between(3, 6) {
+        pattern.push_str(&format!(" {:02x}", rng.byte()));
+    }
+    pattern.push_str(" ??");
+    pattern.push_str(&format!(" [{}-{}]", rng.below(4), rng.between(4, 16)));
+    for _ in 0..rng.between(2, 4) {
+        pattern.push_str(&format!(" {:02x}", rng.byte()));
+    }
+    pattern.push_str(" }");
+    pattern
+}
+
+/// How the bytes in a generated corpus are distributed.
+#[derive(Clone, Copy, Debug)]
+pub enum ByteDistribution {
+    /// All byte values are equally likely, like in compressed data.
+    Uniform,
+    /// Lowercase words separated by spaces and newlines, which produces
+    /// many partial matches for text patterns.
+    Text,
+    /// All bytes are zero, like in padding or sparse files.
+    Zeros,
+}
+
+/// A generated block of data to be scanned.
+#[derive(Clone, Copy, Debug)]
+pub struct Corpus {
+    pub size: usize,
+    pub distribution: ByteDistribution,
+    pub seed: u64,
+}
+
+impl Corpus {
+    /// Short description used in the benchmark IDs.
+    pub fn name(&self) -> String {
+        format!("{:?}_{}KiB", self.distribution, self.size / 1024)
+            .to_lowercase()
+    }
+
+    pub fn generate(&self) -> Vec<u8> {
+        let mut rng = Rng::new(self.seed);
+        let mut data = Vec::with_capacity(self.size);
+
+        match self.distribution {
+            ByteDistribution::Uniform => {
+                data.extend((0..self.size).map(|_| rng.byte()));
+            }
+            ByteDistribution::Text => {
+                while data.len() < self.size {
+                    data.extend(rng.word(2, 10).as_bytes());
+                    data.push(if rng.below(12) == 0 { b'\n' } else { b' ' });
+                }
+                data.truncate(self.size);
+            }
+            ByteDistribution::Zeros => data.resize(self.size, 0),
+        }
+
+        data
+    }
+}
@@ -0,0 +1,170 @@
+/*! Benchmarks with synthetic workloads.
+
+These benchmarks measure the compilation time, the time required for
+creating a scanner, which instantiates the compiled WASM module, and the
+scan throughput, for generated rule sets and data corpora (see the
+`generators` module). They also measure the compilation time of rules that
+use large string sets. Compiled rules can't be serialized yet, when they
+can, serialization and deserialization should be measured here as well.
+
+`cargo bench --bench workloads` runs the benchmarks with the full-size
+workloads, up to 100k rules. `cargo test` runs every benchmark once with
+tiny workloads, so that the benchmarks and generators can't rot.
+
+# Adding a scenario
+
+Add the new rule set to [`rule_sets`], or the new corpus to [`corpora`],
+and every benchmark will use it. Both functions receive a `smoke` argument,
+scenarios must be small when it's true. If the new scenario requires a
+different kind of rule or data, extend [`generators::PatternMix`] or
+[`generators::ByteDistribution`], keeping everything deterministic: all
+randomness must come from [`generators::Rng`] and a fixed seed. Data is
+always generated, never checked into the repository.
+*/
+
+use criterion::{
+    criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode,
+    Throughput,
+};
+
+use generators::{ByteDistribution, Corpus, PatternMix, RuleSet};
+
+mod generators;
+
+/// Returns true if the benchmarks are being run by `cargo test` instead
+/// of `cargo bench`. See `Criterion::configure_from_args`.
+fn smoke_mode() -> bool {
+    let mut bench = false;
+    let mut test = false;
+    for arg in std::env::args() {
+        bench |= arg == "--bench";
+        test |= arg == "--test";
+    }
+    !bench || test
+}
+
+fn rule_sets(smoke: bool) -> Vec<RuleSet> {
+    let sizes: &[usize] = if smoke { &[10] } else { &[100, 10_000, 100_000] };
+    let mut rule_sets = Vec::new();
+    for &num_rules in sizes {
+        for mix in [PatternMix::TEXT, PatternMix::MIXED] {
+            rule_sets.push(RuleSet { num_rules, mix, seed: 1 });
+        }
+    }
+    rule_sets
+}
+
+fn corpora(smoke: bool) -> Vec<Corpus> {
+    let size = if smoke { 1024 } else { 1024 * 1024 };
+    [
+        ByteDistribution::Uniform,
+        ByteDistribution::Text,
+        ByteDistribution::Zeros,
+    ]
+    .into_iter()
+    .map(|distribution| Corpus { size, distribution, seed: 2 })
+    .collect()
+}
+
+fn compile(rule_set: &RuleSet, src: &str) -> yara_x::Rules {
+    let mut compiler = yara_x::Compiler::new();
+    compiler
+        .add_source(src)
+        .unwrap_or_else(|err| panic!("{}: {}", rule_set.name(), err));
+    compiler.build().unwrap()
+}
+
+fn bench_compile(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Compile");
+
+    group.sample_size(10).sampling_mode(SamplingMode::Flat);
+
+    for rule_set in rule_sets(smoke_mode()) {
+        let src = rule_set.source();
+        group.throughput(Throughput::Elements(rule_set.num_rules as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(rule_set.name()),
+            &src,
+            |b, src| b.iter(|| compile(&rule_set, src)),
+        );
+    }
+}
+
+fn bench_new_scanner(c: &mut Criterion) {
+    let mut group = c.benchmark_group("New scanner");
+
+    for rule_set in rule_sets(smoke_mode()) {
+        let rules = compile(&rule_set, &rule_set.source());
+        group.bench_with_input(
+            BenchmarkId::from_parameter(rule_set.name()),
+            &rules,
+            |b, rules| b.iter(|| yara_x::Scanner::new(rules)),
+        );
+    }
+}
+
+fn bench_scan(c: &mut Criterion) {
+    let smoke = smoke_mode();
+    let corpora: Vec<_> = corpora(smoke)
+        .into_iter()
+        .map(|corpus| (corpus.name(), corpus.generate()))
+        .collect();
+
+    let mut group = c.benchmark_group("Scan");
+
+    group.sample_size(20);
+
+    for rule_set in rule_sets(smoke) {
+        let rules = compile(&rule_set, &rule_set.source());
+        let mut scanner = yara_x::Scanner::new(&rules);
+
+        for (corpus_name, data) in corpora.iter() {
+            group.throughput(Throughput::Bytes(data.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new(rule_set.name(), corpus_name),
+                data,
+                |b, data| b.iter(|| scanner.scan(data).num_matching_rules()),
+            );
+        }
+    }
+}
+
+fn bench_string_sets(c: &mut Criterion) {
+    let sizes: &[usize] = if smoke_mode() { &[10] } else { &[100_000] };
+
+    let mut group = c.benchmark_group("Compile with string set");
+
+    group.sample_size(10).sampling_mode(SamplingMode::Flat);
+
+    for &size in sizes {
+        let names: Vec<String> =
+            (0..size).map(|i| format!("domain{}.com", i)).collect();
+
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &names,
+            |b, names| {
+                b.iter(|| {
+                    let mut compiler = yara_x::Compiler::new();
+                    compiler
+                        .enable_extensions(true)
+                        .define_string_set("names", names.iter())
+                        .unwrap()
+                        .add_source(
+                            r#"rule test { condition: "domain1.com" in names }"#,
+                        )
+                        .unwrap();
+                    compiler.build().unwrap()
+                })
+            },
+        );
+    }
+}
+
+criterion_group!(
+    name = workloads;
+    config = Criterion::default();
+    targets = bench_compile, bench_new_scanner, bench_scan, bench_string_sets);
+
+criterion_main!(workloads);
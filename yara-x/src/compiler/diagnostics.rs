@@ -0,0 +1,541 @@
+/*! Compact binary encoding for compile errors and warnings.
+
+Build systems that archive the diagnostics produced while compiling large
+rule sets can store them with [`DiagnosticsBundle`], which is much smaller
+than the JSON produced by serializing errors and warnings with `serde`.
+Bundles are read with [`DiagnosticsReader`], which decodes individual
+diagnostics on demand, and can filter them by severity and code without
+decoding the rest.
+
+A [`Diagnostic`] holds the same structured information exposed by
+[`CompileError`] and [`Warning`] (severity, code, title, origin and labels),
+but not the text-mode report, which can be rendered again from the source
+code. The conversion to JSON is lossless: a diagnostic read from a bundle
+serializes exactly like the original one.
+
+# Format
+
+All integers are unsigned LEB128 varints.
+
+```text
+bundle  := "YRXD" version:u8 num_strings string* num_records record*
+string  := len bytes                    (UTF-8)
+record  := len severity:u8 code title origin num_labels label*
+label   := text origin start len line column end_line_delta end_column
+```
+
+`code`, `title` and `text` are indexes in the table of strings, `origin`
+is an index plus one, or zero if there's no origin. Labels don't have an
+`origin` in version 1 of the format, which can still be read. Bundles are treated as
+untrusted input, malformed data produces [`DiagnosticsError`] instead of
+panicking.
+*/
+
+use std::str;
+
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+use yara_x_parser::ast::Span;
+use yara_x_parser::line_index::Position;
+use yara_x_parser::Label;
+
+use crate::compiler::errors::CompileError;
+use crate::compiler::Warning;
+
+/// Magic bytes at the start of every bundle.
+const MAGIC: &[u8; 4] = b"YRXD";
+
+/// Version of the format produced by [`DiagnosticsBundle::encode`].
+const VERSION: u8 = 2;
+
+/// Oldest version of the format that can be read.
+const MIN_VERSION: u8 = 1;
+
+/// Errors returned while reading a diagnostics bundle.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DiagnosticsError {
+    /// The data doesn't start with the bundle's magic bytes.
+    #[error("not a diagnostics bundle")]
+    InvalidFormat,
+
+    /// The bundle was produced by a newer version of the format.
+    #[error("unsupported diagnostics bundle version {0}")]
+    UnsupportedVersion(u8),
+
+    /// The bundle is truncated or corrupted.
+    #[error("malformed diagnostics bundle")]
+    Malformed,
+}
+
+/// Severity of a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A compile error or warning, without its text-mode report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Diagnostic {
+    severity: Severity,
+    code: String,
+    title: String,
+    origin: Option<String>,
+    labels: Vec<Label>,
+}
+
+impl Diagnostic {
+    /// Creates a new diagnostic. Diagnostics are usually obtained from
+    /// [`CompileError`] and [`Warning`] with [`From`], this allows adding
+    /// custom diagnostics to a bundle.
+    pub fn new(
+        severity: Severity,
+        code: &str,
+        title: &str,
+        origin: Option<&str>,
+        labels: Vec<Label>,
+    ) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            title: title.to_string(),
+            origin: origin.map(String::from),
+            labels,
+        }
+    }
+
+    /// Returns the severity of the diagnostic.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Returns the error or warning code, like `unknown_identifier`.
+    pub fn code(&self) -> &str {
+        self.code.as_str()
+    }
+
+    /// Returns the title of the error or warning.
+    pub fn title(&self) -> &str {
+        self.title.as_str()
+    }
+
+    /// Returns the origin of the source code, if any.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Returns the labels. The first one is the main label.
+    pub fn labels(&self) -> &[Label] {
+        self.labels.as_slice()
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(err: &CompileError) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: err.code().to_string(),
+            title: err.title().to_string(),
+            origin: err.origin().map(String::from),
+            labels: err.labels().to_vec(),
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Self {
+            severity: Severity::Warning,
+            code: warning.code().to_string(),
+            title: warning.title().to_string(),
+            origin: warning.origin().map(String::from),
+            labels: warning.labels().to_vec(),
+        }
+    }
+}
+
+/// Builds a compact binary representation of a set of diagnostics.
+///
+/// ```
+/// # use yara_x::{Compiler, Diagnostic, DiagnosticsBundle, DiagnosticsReader, Severity};
+/// let mut compiler = Compiler::new();
+/// compiler.add_source("rule test { condition: 1 }")?;
+///
+/// let mut bundle = DiagnosticsBundle::new();
+/// for warning in compiler.warnings() {
+///     bundle.push(&Diagnostic::from(warning));
+/// }
+///
+/// let bytes = bundle.encode();
+/// let reader = DiagnosticsReader::new(&bytes)?;
+///
+/// assert_eq!(reader.len(), 1);
+/// assert_eq!(reader.get(0)?.code(), "non_boolean_as_boolean");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Default)]
+pub struct DiagnosticsBundle {
+    /// Strings referenced by the records, each one appears only once.
+    strings: Vec<String>,
+    /// Index of each string in `strings`.
+    string_ids: FxHashMap<String, u64>,
+    /// Encoded records, without their length prefix.
+    records: Vec<Vec<u8>>,
+}
+
+impl DiagnosticsBundle {
+    /// Creates an empty bundle.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a diagnostic to the bundle.
+    pub fn push(&mut self, diagnostic: &Diagnostic) -> &mut Self {
+        let mut record = Vec::new();
+
+        record.push(match diagnostic.severity {
+            Severity::Error => 0,
+            Severity::Warning => 1,
+        });
+
+        let code = self.intern(&diagnostic.code);
+        let title = self.intern(&diagnostic.title);
+        let origin = diagnostic
+            .origin
+            .as_ref()
+            .map_or(0, |origin| self.intern(origin) + 1);
+
+        write_varint(&mut record, code);
+        write_varint(&mut record, title);
+        write_varint(&mut record, origin);
+        write_varint(&mut record, diagnostic.labels.len() as u64);
+
+        for label in &diagnostic.labels {
+            let (start, end) = (label.start(), label.end());
+            let span = label.span();
+            let origin =
+                label.origin().map_or(0, |origin| self.intern(origin) + 1);
+            write_varint(&mut record, self.intern(label.text()));
+            write_varint(&mut record, origin);
+            write_varint(&mut record, span.start() as u64);
+            write_varint(
+                &mut record,
+                span.end().saturating_sub(span.start()) as u64,
+            );
+            write_varint(&mut record, start.line as u64);
+            write_varint(&mut record, start.column as u64);
+            write_varint(
+                &mut record,
+                end.line.saturating_sub(start.line) as u64,
+            );
+            write_varint(&mut record, end.column as u64);
+        }
+
+        self.records.push(record);
+        self
+    }
+
+    /// Returns the number of diagnostics in the bundle.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if the bundle doesn't contain any diagnostic.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns the binary representation of the bundle.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+
+        write_varint(&mut buf, self.strings.len() as u64);
+        for s in &self.strings {
+            write_varint(&mut buf, s.len() as u64);
+            buf.extend_from_slice(s.as_bytes());
+        }
+
+        write_varint(&mut buf, self.records.len() as u64);
+        for record in &self.records {
+            write_varint(&mut buf, record.len() as u64);
+            buf.extend_from_slice(record);
+        }
+
+        buf
+    }
+
+    fn intern(&mut self, s: &str) -> u64 {
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as u64;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+}
+
+/// Reads diagnostics from the binary representation produced by
+/// [`DiagnosticsBundle::encode`].
+///
+/// Creating the reader validates the header and the table of strings, and
+/// locates the records, but doesn't decode them. Each diagnostic is decoded
+/// when requested, so a corrupted record is reported by the functions that
+/// return it.
+pub struct DiagnosticsReader<'a> {
+    version: u8,
+    strings: Vec<&'a str>,
+    records: Vec<&'a [u8]>,
+}
+
+impl<'a> DiagnosticsReader<'a> {
+    /// Creates a reader for the given bundle.
+    pub fn new(data: &'a [u8]) -> Result<Self, DiagnosticsError> {
+        let data = data
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or(DiagnosticsError::InvalidFormat)?;
+
+        let mut input = Input(data);
+
+        let version = input.byte()?;
+
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(DiagnosticsError::UnsupportedVersion(version));
+        }
+
+        // Each string and record takes at least one byte, a count larger
+        // than the remaining data is malformed, this prevents allocating
+        // huge vectors for bogus counts.
+        let num_strings = input.count()?;
+        let mut strings = Vec::with_capacity(num_strings);
+
+        for _ in 0..num_strings {
+            let len = input.count()?;
+            let s = str::from_utf8(input.bytes(len)?)
+                .map_err(|_| DiagnosticsError::Malformed)?;
+            strings.push(s);
+        }
+
+        let num_records = input.count()?;
+        let mut records = Vec::with_capacity(num_records);
+
+        for _ in 0..num_records {
+            let len = input.count()?;
+            records.push(input.bytes(len)?);
+        }
+
+        if !input.0.is_empty() {
+            return Err(DiagnosticsError::Malformed);
+        }
+
+        Ok(Self { version, strings, records })
+    }
+
+    /// Returns the number of diagnostics in the bundle.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if the bundle doesn't contain any diagnostic.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Decodes the diagnostic at the given index.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not lower than [`DiagnosticsReader::len`].
+    pub fn get(&self, index: usize) -> Result<Diagnostic, DiagnosticsError> {
+        let mut input = Input(self.records[index]);
+
+        let (severity, code) = self.decode_head(&mut input)?;
+        let title = self.string(input.varint()?)?;
+        let origin = self.origin(&mut input)?;
+
+        let num_labels = input.count()?;
+        let mut labels = Vec::with_capacity(num_labels);
+
+        for _ in 0..num_labels {
+            let text = self.string(input.varint()?)?;
+            let origin = if self.version >= 2 {
+                self.origin(&mut input)?
+            } else {
+                None
+            };
+            let start = input.usize()?;
+            let end = start
+                .checked_add(input.usize()?)
+                .ok_or(DiagnosticsError::Malformed)?;
+            let start_pos =
+                Position { line: input.usize()?, column: input.usize()? };
+            let end_pos = Position {
+                line: start_pos
+                    .line
+                    .checked_add(input.usize()?)
+                    .ok_or(DiagnosticsError::Malformed)?,
+                column: input.usize()?,
+            };
+            labels.push(Label::new(
+                text.to_string(),
+                origin,
+                Span::new(start, end),
+                start_pos,
+                end_pos,
+            ));
+        }
+
+        if !input.0.is_empty() {
+            return Err(DiagnosticsError::Malformed);
+        }
+
+        Ok(Diagnostic {
+            severity,
+            code: code.to_string(),
+            title: title.to_string(),
+            origin,
+            labels,
+        })
+    }
+
+    /// Returns an iterator that decodes all the diagnostics in the bundle.
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = Result<Diagnostic, DiagnosticsError>> + '_ {
+        (0..self.len()).map(|index| self.get(index))
+    }
+
+    /// Returns an iterator that decodes only the diagnostics for which
+    /// `predicate` returns true. The predicate receives the severity and
+    /// code of each diagnostic, which are read without decoding the rest
+    /// of it.
+    ///
+    /// ```
+    /// # use yara_x::{DiagnosticsReader, Severity};
+    /// # fn f(reader: &DiagnosticsReader) {
+    /// let unknown_identifiers = reader.filter(|severity, code| {
+    ///     severity == Severity::Error && code == "unknown_identifier"
+    /// });
+    /// # }
+    /// ```
+    pub fn filter<P>(
+        &self,
+        predicate: P,
+    ) -> impl Iterator<Item = Result<Diagnostic, DiagnosticsError>> + '_
+    where
+        P: Fn(Severity, &str) -> bool + 'a,
+    {
+        (0..self.len()).filter_map(move |index| {
+            match self.decode_head(&mut Input(self.records[index])) {
+                Ok((severity, code)) if predicate(severity, code) => {
+                    Some(self.get(index))
+                }
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            }
+        })
+    }
+
+    /// Decodes the severity and code at the start of a record.
+    fn decode_head(
+        &self,
+        input: &mut Input<'a>,
+    ) -> Result<(Severity, &'a str), DiagnosticsError> {
+        let severity = match input.byte()? {
+            0 => Severity::Error,
+            1 => Severity::Warning,
+            _ => return Err(DiagnosticsError::Malformed),
+        };
+        Ok((severity, self.string(input.varint()?)?))
+    }
+
+    /// Decodes an optional origin, which is zero or a string index plus
+    /// one.
+    fn origin(
+        &self,
+        input: &mut Input<'a>,
+    ) -> Result<Option<String>, DiagnosticsError> {
+        match input.varint()? {
+            0 => Ok(None),
+            n => Ok(Some(self.string(n - 1)?.to_string())),
+        }
+    }
+
+    fn string(&self, index: u64) -> Result<&'a str, DiagnosticsError> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.strings.get(index))
+            .copied()
+            .ok_or(DiagnosticsError::Malformed)
+    }
+}
+
+/// Appends `value` to `buf` as an unsigned LEB128 varint.
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Untrusted input being decoded.
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn byte(&mut self) -> Result<u8, DiagnosticsError> {
+        let (first, rest) =
+            self.0.split_first().ok_or(DiagnosticsError::Malformed)?;
+        self.0 = rest;
+        Ok(*first)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DiagnosticsError> {
+        if len > self.0.len() {
+            return Err(DiagnosticsError::Malformed);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, DiagnosticsError> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            // The last byte of a 64-bits value can't have more than one
+            // significant bit.
+            if shift == 63 && bits > 1 {
+                return Err(DiagnosticsError::Malformed);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DiagnosticsError::Malformed)
+    }
+
+    fn usize(&mut self) -> Result<usize, DiagnosticsError> {
+        usize::try_from(self.varint()?)
+            .map_err(|_| DiagnosticsError::Malformed)
+    }
+
+    /// Reads the number of items that follow. Every item takes at least
+    /// one byte, so the count can't exceed the number of remaining bytes.
+    fn count(&mut self) -> Result<usize, DiagnosticsError> {
+        let count = self.usize()?;
+        if count > self.0.len() {
+            return Err(DiagnosticsError::Malformed);
+        }
+        Ok(count)
+    }
+}
@@ -8,6 +8,7 @@ expressions or language constructs.
 
 use std::mem::size_of;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use bstr::ByteSlice;
 use walrus::ir::ExtendedLoad::ZeroExtend;
@@ -21,6 +22,7 @@ use yara_x_parser::ast::{
 use yara_x_parser::ast::{ForOf, Of};
 use yara_x_parser::types::{Array, Map, Type, TypeValue};
 
+use crate::compiler::semcheck::promoted_type;
 use crate::compiler::{Context, PatternId, RuleId, Var};
 use crate::symbols::{Symbol, SymbolKind, SymbolLookup, SymbolTable};
 use crate::wasm;
@@ -88,31 +90,45 @@ macro_rules! emit_const_or_code {
 
 /// This macro emits the code for the left and right operands of some
 /// operation, converting integer operands to float if the other operand
-/// is a float.
+/// is a float. Returns the types of both operands after the conversion.
+/// See [`promoted_type`] for details about the promotion rules.
 macro_rules! emit_operands {
     ($ctx:ident, $instr:ident, $lhs:expr, $rhs:expr) => {{
-        let mut lhs_type = $lhs.ty();
-        let mut rhs_type = $rhs.ty();
+        let lhs_type = $lhs.ty();
+        let rhs_type = $rhs.ty();
 
-        emit_expr($ctx, $instr, &$lhs);
+        // Both operands are converted to the type they are promoted to, if
+        // they don't have that type already. Semantic checks guarantee that
+        // there's a promoted type for any pair of operands that reaches this
+        // point.
+        let promoted_type = promoted_type(lhs_type, rhs_type)
+            .expect("operands must be of compatible types");
 
-        // If the left operand is integer, but the right one is float,
-        // convert the left operand to float.
-        if lhs_type == Type::Integer && rhs_type == Type::Float {
-            $instr.unop(UnaryOp::F64ConvertSI64);
-            lhs_type = Type::Float;
-        }
+        emit_expr($ctx, $instr, &$lhs);
+        emit_promotion!($instr, lhs_type, promoted_type);
 
         emit_expr($ctx, $instr, &$rhs);
+        emit_promotion!($instr, rhs_type, promoted_type);
 
-        // If the right operand is integer, but the left one is float,
-        // convert the right operand to float.
-        if lhs_type == Type::Float && rhs_type == Type::Integer {
-            $instr.unop(UnaryOp::F64ConvertSI64);
-            rhs_type = Type::Float;
-        }
+        (promoted_type, promoted_type)
+    }};
+}
 
-        (lhs_type, rhs_type)
+/// Emits the code that converts the value at the top of the stack from
+/// type `$from` to type `$to`. The only supported conversion is from
+/// integer to float, which is the only one ever needed when promoting the
+/// operands of an expression.
+macro_rules! emit_promotion {
+    ($instr:ident, $from:expr, $to:expr) => {{
+        match ($from, $to) {
+            (from, to) if from == to => {}
+            (Type::Integer, Type::Float) => {
+                $instr.unop(UnaryOp::F64ConvertSI64);
+            }
+            (from, to) => {
+                unreachable!("can't promote `{}` to `{}`", from, to)
+            }
+        }
     }};
 }
 
@@ -135,7 +151,7 @@ macro_rules! emit_arithmetic_op {
 }
 
 macro_rules! emit_comparison_op {
-    ($ctx:ident, $instr:ident, $expr:expr, $operands:expr, $int_op:tt, $float_op:tt, $str_op:expr) => {{
+    ($ctx:ident, $instr:ident, $expr:expr, $operands:expr, $int_op:tt, $float_op:tt, $str_op:expr $(, $bool_op:tt)?) => {{
         emit_const_or_code!($ctx, $instr, $expr.type_value(), {
             match emit_operands!($ctx, $instr, $operands.lhs, $operands.rhs) {
                 (Type::Integer, Type::Integer) => {
@@ -147,6 +163,13 @@ macro_rules! emit_comparison_op {
                 (Type::String, Type::String) => {
                     $instr.call($ctx.function_id($str_op));
                 }
+                // Booleans are represented as i32, but only some comparison
+                // operators accept them.
+                $(
+                (Type::Bool, Type::Bool) => {
+                    $instr.binop(BinaryOp::$bool_op);
+                }
+                )?
                 _ => unreachable!(),
             };
         });
@@ -158,7 +181,9 @@ macro_rules! emit_shift_op {
         emit_const_or_code!($ctx, $instr, $expr.type_value(), {
             match emit_operands!($ctx, $instr, $operands.lhs, $operands.rhs) {
                 (Type::Integer, Type::Integer) => {
-                    // When the left operand is >= 64, shift operations don't
+                    // Shifting by a negative number of bits is undefined.
+                    throw_undef_if_negative($ctx, $instr);
+                    // When the right operand is >= 64, shift operations don't
                     // behave in the same way in WebAssembly and YARA. In YARA,
                     // 1 << 64 == 0, but in WebAssembly 1 << 64 == 1.
                     // In general, X << Y behaves as X << (Y mod 64) in
@@ -215,6 +240,13 @@ pub(super) fn emit_rule_code(
     rule_id: RuleId,
     rule: &Rule,
 ) {
+    // Tell the memory checks which rule is being evaluated.
+    #[cfg(any(test, feature = "memory-checks"))]
+    {
+        instr.i32_const(rule_id.0);
+        instr.call(ctx.function_id(wasm::export__enter_rule.mangled_name));
+    }
+
     // Emit WASM code for the rule's condition.
     instr.block(None, |block| {
         catch_undef(ctx, block, |ctx, instr| {
@@ -232,12 +264,27 @@ pub(super) fn emit_rule_code(
         // Emit call instruction for calling `rule_match`.
         block.call(ctx.function_id(wasm::export__rule_match.mangled_name));
     });
+
+    // When profiling is enabled, or the scan was started with a callback,
+    // notify the scanner that the rule was evaluated. Otherwise this is the
+    // only overhead, no calls are made.
+    instr.global_get(ctx.wasm_symbols.profiling_enabled);
+    instr.if_else(
+        None,
+        |then_| {
+            then_.i32_const(rule_id.0);
+            then_.call(
+                ctx.function_id(wasm::export__rule_evaluated.mangled_name),
+            );
+        },
+        |_| {},
+    );
 }
 
 /// Emits code that checks if the pattern search phase has not been executed
 /// yet, and do it in that case.
 fn emit_lazy_pattern_search(ctx: &mut Context, instr: &mut InstrSeqBuilder) {
-    instr.local_get(ctx.wasm_symbols.pattern_search_done);
+    instr.global_get(ctx.wasm_symbols.pattern_search_done);
     instr.if_else(
         None,
         |_then| {
@@ -253,11 +300,51 @@ fn emit_lazy_pattern_search(ctx: &mut Context, instr: &mut InstrSeqBuilder) {
             );
             // Set pattern_search_done to true.
             _else.i32_const(1);
-            _else.local_set(ctx.wasm_symbols.pattern_search_done);
+            _else.global_set(ctx.wasm_symbols.pattern_search_done);
         },
     );
 }
 
+/// Emits the code that pushes the ID of a pattern in the stack.
+///
+/// For normal pattern identifiers (e.g: `$a`, `#a`, `@foo`) the ID is known
+/// at compile time. Anonymous identifiers (e.g: `$`, `#`) are used inside
+/// `for .. of` statements and represent a different pattern on each
+/// iteration, in those cases the ID is obtained from the loop variable
+/// [`Context::for_of_pattern`].
+fn emit_pattern_id(
+    ctx: &mut Context,
+    instr: &mut InstrSeqBuilder,
+    ident: &str,
+) {
+    if ident.len() == 1 {
+        // The parser rejects anonymous identifiers outside `for .. of`
+        // statements.
+        let var = ctx.for_of_pattern.unwrap();
+        load_var(ctx, instr, var);
+        // load_var returns a I64, convert it to I32.
+        instr.unop(UnaryOp::I32WrapI64);
+    } else {
+        instr.i32_const(ctx.get_pattern_from_current_rule(ident).0);
+    }
+}
+
+/// Emits the code that puts the index of a match in the stack, in
+/// expressions like `@a[i]` and `!a[i]`. Without the index, as in `@a`,
+/// the first match is used.
+fn emit_match_index(
+    ctx: &mut Context,
+    instr: &mut InstrSeqBuilder,
+    index: Option<&Expr>,
+) {
+    match index {
+        Some(index) => emit_expr(ctx, instr, index),
+        None => {
+            instr.i64_const(1);
+        }
+    }
+}
+
 /// Emits the code that determines if some pattern is matching.
 ///
 /// This function assumes that the pattern ID is at the top of the stack.
@@ -299,7 +386,11 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
             instr.global_get(ctx.wasm_symbols.filesize);
         }
         Expr::Entrypoint { .. } => {
-            todo!()
+            emit_call_and_handle_undef(
+                ctx,
+                instr,
+                ctx.function_id(wasm::export__entrypoint.mangled_name),
+            );
         }
         Expr::Regexp(_) => {
             todo!()
@@ -356,6 +447,13 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
                         ctx.lookup_start = Some(var);
                     }
                     SymbolKind::Func(func) => {
+                        // Functions are not looked up at runtime, so the
+                        // indexes of the structures containing the function
+                        // must be discarded. Otherwise, they would be used
+                        // in the next lookup.
+                        ctx.lookup_stack.clear();
+                        ctx.lookup_start = None;
+
                         let signature =
                             &func.signatures()[ctx.current_signature.unwrap()];
 
@@ -412,43 +510,58 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
             // If the patterns has not been searched yet, do it now.
             emit_lazy_pattern_search(ctx, instr);
 
-            // Push the pattern ID in the stack. Identifier "$" is an special
-            // case, as this is used inside `for` loops and it represents a
-            // different pattern on each iteration. In those cases the pattern
-            // ID is obtained from a loop variable.
-            if pattern.identifier.name == "$" {
-                match ctx.symbol_table.lookup("$").unwrap().kind {
-                    SymbolKind::WasmVar(var) => {
-                        load_var(ctx, instr, var);
-                        // load_var returns a I64, convert it to I32.
-                        instr.unop(UnaryOp::I32WrapI64);
-                    }
-                    _ => unreachable!(),
-                }
-            }
-            // For normal pattern identifiers (e.g: $a, $b, $foo) we find the
-            // corresponding pattern in the current rule, and push its ID.
-            else {
-                instr.i32_const(
-                    ctx.get_pattern_from_current_rule(&pattern.identifier).0,
-                );
-            };
-
+            emit_pattern_id(ctx, instr, pattern.identifier.name);
             emit_pattern_match(ctx, instr, pattern.anchor.as_ref());
         }
-        Expr::PatternCount(_) => {
+        Expr::PatternCount(pattern) => {
             // If the patterns has not been searched yet, do it now.
             emit_lazy_pattern_search(ctx, instr);
-            // TODO
+
+            emit_pattern_id(ctx, instr, pattern.name);
+
+            match &pattern.range {
+                Some(range) => {
+                    emit_expr(ctx, instr, &range.lower_bound);
+                    emit_expr(ctx, instr, &range.upper_bound);
+                    instr.call(ctx.function_id(
+                        wasm::export__pat_matches_in.mangled_name,
+                    ));
+                }
+                None => {
+                    instr.call(
+                        ctx.function_id(
+                            wasm::export__pat_matches.mangled_name,
+                        ),
+                    );
+                }
+            }
         }
-        Expr::PatternOffset(_) => {
+        Expr::CountOf(count_of) => {
+            emit_count_of(ctx, instr, &count_of.pattern_set);
+        }
+        Expr::PatternOffset(pattern) => {
             // If the patterns has not been searched yet, do it now.
             emit_lazy_pattern_search(ctx, instr);
-            // TODO
+
+            emit_pattern_id(ctx, instr, pattern.name);
+            emit_match_index(ctx, instr, pattern.index.as_ref());
+            emit_call_and_handle_undef(
+                ctx,
+                instr,
+                ctx.function_id(wasm::export__pat_offset.mangled_name),
+            );
         }
-        Expr::PatternLength(_) => {
+        Expr::PatternLength(pattern) => {
+            // If the patterns has not been searched yet, do it now.
             emit_lazy_pattern_search(ctx, instr);
-            // TODO
+
+            emit_pattern_id(ctx, instr, pattern.name);
+            emit_match_index(ctx, instr, pattern.index.as_ref());
+            emit_call_and_handle_undef(
+                ctx,
+                instr,
+                ctx.function_id(wasm::export__pat_length.mangled_name),
+            );
         }
         Expr::Lookup(operands) => {
             emit_const_or_code!(ctx, instr, expr.type_value(), {
@@ -482,17 +595,40 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
             emit_const_or_code!(ctx, instr, expr.type_value(), {
                 emit_expr(ctx, instr, &operands.lhs);
 
-                ctx.current_struct =
-                    Some(operands.lhs.type_value().as_struct());
+                // The only field in arrays is `length`. The array is looked
+                // up and its length is returned.
+                if operands.lhs.ty() == Type::Array {
+                    emit_lookup_common(ctx, instr);
+                    instr.call(ctx.function_id(
+                        wasm::export__lookup_array_len.mangled_name,
+                    ));
+                } else {
+                    ctx.current_struct =
+                        Some(operands.lhs.type_value().as_struct());
 
-                emit_expr(ctx, instr, &operands.rhs);
+                    emit_expr(ctx, instr, &operands.rhs);
 
-                ctx.current_struct = None;
+                    ctx.current_struct = None;
+                }
             })
         }
         Expr::FnCall(fn_call) => {
-            for expr in fn_call.args.iter() {
-                emit_expr(ctx, instr, expr);
+            let func = match fn_call.callable.type_value() {
+                TypeValue::Func(func) => func.clone(),
+                _ => unreachable!(),
+            };
+
+            let signature =
+                &func.signatures()[fn_call.fn_signature_index.unwrap()];
+
+            for (expr, may_be_undef) in
+                fn_call.args.iter().zip(signature.args_may_be_undef.iter())
+            {
+                if *may_be_undef {
+                    emit_arg_and_undef_flag(ctx, instr, expr);
+                } else {
+                    emit_expr(ctx, instr, expr);
+                }
             }
 
             let previous = ctx
@@ -710,7 +846,8 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
                 operands,
                 I64Eq,
                 F64Eq,
-                wasm::export__str_eq.mangled_name
+                wasm::export__str_eq.mangled_name,
+                I32Eq
             );
         }
         Expr::Ne(operands) => {
@@ -721,7 +858,8 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
                 operands,
                 I64Ne,
                 F64Ne,
-                wasm::export__str_ne.mangled_name
+                wasm::export__str_ne.mangled_name,
+                I32Ne
             );
         }
         Expr::Lt(operands) => {
@@ -826,8 +964,20 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
                 );
             });
         }
-        Expr::Matches(_) => {
-            // TODO
+        Expr::Matches(operands) => {
+            emit_const_or_code!(ctx, instr, expr.type_value(), {
+                let regexp_id = match &operands.rhs {
+                    Expr::Regexp(regexp) => ctx
+                        .regexp_id(regexp)
+                        .expect("regexp must be valid after semantic checks"),
+                    _ => unreachable!(),
+                };
+                emit_expr(ctx, instr, &operands.lhs);
+                instr.i32_const(regexp_id.0);
+                instr.call(
+                    ctx.function_id(wasm::export__str_matches.mangled_name),
+                );
+            });
         }
         Expr::Of(of) => match &of.items {
             OfItems::PatternSet(pattern_set) => {
@@ -851,6 +1001,18 @@ fn emit_expr(ctx: &mut Context, instr: &mut InstrSeqBuilder, expr: &Expr) {
                 emit_for_in_expr(ctx, instr, for_in, iterable);
             }
         },
+        Expr::InSet(in_set) => {
+            emit_const_or_code!(ctx, instr, expr.type_value(), {
+                let set_id = ctx
+                    .get_string_set(in_set.set.name)
+                    .expect("string set must exist after semantic checks");
+                instr.i32_const(set_id.0);
+                emit_expr(ctx, instr, &in_set.item);
+                instr.call(
+                    ctx.function_id(wasm::export__str_in_set.mangled_name),
+                );
+            });
+        }
     }
 }
 
@@ -882,8 +1044,6 @@ fn emit_check_for_rule_match(
             offset: MATCHING_RULES_BITMAP_BASE as u32,
         },
     );
-    // This is the first operator for the I32ShrU operation.
-    instr.i32_const(rule_id.0 % 8);
     // Compute byte & (1 << (rule_id % 8)), which clears all
     // bits except the one we are interested in.
     instr.i32_const(1 << (rule_id.0 % 8));
@@ -891,6 +1051,7 @@ fn emit_check_for_rule_match(
     // Now shift the byte to the right, leaving the
     // interesting bit as the LSB. So the result is either
     // 1 or 0.
+    instr.i32_const(rule_id.0 % 8);
     instr.binop(BinaryOp::I32ShrU);
 }
 
@@ -966,7 +1127,7 @@ fn emit_check_for_pattern_match(
 fn emit_array_indexing(
     ctx: &mut Context,
     instr: &mut InstrSeqBuilder,
-    array: &Rc<Array>,
+    array: &Arc<Array>,
     dst_var: Option<Var>,
 ) {
     // Emit the code that fills the `lookup_stack` in WASM memory.
@@ -1021,7 +1182,7 @@ fn emit_array_indexing(
 fn emit_map_lookup_by_index(
     ctx: &mut Context,
     instr: &mut InstrSeqBuilder,
-    map: &Rc<Map>,
+    map: &Arc<Map>,
     dst_var: Option<Var>,
 ) {
     // Emit the code that fills the `lookup_stack` in WASM memory.
@@ -1106,7 +1267,7 @@ fn emit_map_lookup_by_index(
 fn emit_map_lookup(
     ctx: &mut Context,
     instr: &mut InstrSeqBuilder,
-    map: &Rc<Map>,
+    map: &Arc<Map>,
 ) {
     match map.as_ref() {
         Map::IntegerKeys { deputy, .. } => {
@@ -1163,56 +1324,49 @@ fn emit_of_pattern_set(
     of: &Of,
     pattern_set: &PatternSet,
 ) {
-    let pattern_ids: Vec<PatternId> =
-        patterns_matching(ctx, pattern_set).collect();
-
-    let num_patterns = pattern_ids.len();
-    let mut pattern_ids = pattern_ids.into_iter();
-    let next_pattern_id = ctx.new_var(Type::Integer);
-
     // Make sure the pattern search phase is executed, as the `of` statement
     // depends on patterns.
     emit_lazy_pattern_search(ctx, instr);
 
-    emit_for(
+    // `<quantifier> of <pattern set> <anchor>` is equivalent to
+    // `for <quantifier> of <pattern set> : ($ <anchor>)`.
+    emit_for_each_pattern(
         ctx,
         instr,
         &of.quantifier,
-        |ctx, instr, n, _| {
-            // Set n = number of patterns.
-            set_var(ctx, instr, n, |_, instr| {
-                instr.i64_const(num_patterns as i64);
-            });
-        },
-        // Before each iteration.
-        |ctx, instr, i| {
-            // Get the i-th pattern ID, and store it in `next_pattern_id`.
-            set_var(ctx, instr, next_pattern_id, |ctx, instr| {
-                load_var(ctx, instr, i);
-                emit_switch(ctx, I64, instr, |_, instr| {
-                    if let Some(pattern_id) = pattern_ids.next() {
-                        instr.i64_const(pattern_id.into());
-                        return true;
-                    }
-                    false
-                });
-            });
-        },
-        // Condition
+        pattern_set,
         |ctx, instr| {
-            // Push the pattern ID into the stack.
-            load_var(ctx, instr, next_pattern_id);
-            // load_var returns a I64, convert it to I32.
-            instr.unop(UnaryOp::I32WrapI64);
-
+            emit_pattern_id(ctx, instr, "$");
             emit_pattern_match(ctx, instr, of.anchor.as_ref());
         },
-        // After each iteration.
-        |_, _, _| {},
     );
+}
 
-    // Free loop variables.
-    ctx.free_vars(next_pattern_id);
+/// Emits the code for a `count_of` expression, which leaves in the stack
+/// the number of patterns in `pattern_set` that matched.
+///
+/// The result is the sum of the bits corresponding to those patterns in the
+/// bitmap of matching patterns.
+fn emit_count_of(
+    ctx: &mut Context,
+    instr: &mut InstrSeqBuilder,
+    pattern_set: &PatternSet,
+) {
+    let pattern_ids: Vec<PatternId> =
+        patterns_matching(ctx, pattern_set).collect();
+
+    // Make sure the pattern search phase is executed, as the result
+    // depends on patterns.
+    emit_lazy_pattern_search(ctx, instr);
+
+    instr.i64_const(0);
+
+    for pattern_id in pattern_ids {
+        instr.i32_const(pattern_id.0);
+        emit_check_for_pattern_match(ctx, instr);
+        instr.unop(UnaryOp::I64ExtendUI32);
+        instr.binop(BinaryOp::I64Add);
+    }
 }
 
 fn emit_of_expr_tuple(
@@ -1270,25 +1424,46 @@ fn emit_for_of_pattern_set(
     instr: &mut InstrSeqBuilder,
     for_of: &ForOf,
 ) {
+    emit_for_each_pattern(
+        ctx,
+        instr,
+        &for_of.quantifier,
+        &for_of.pattern_set,
+        |ctx, instr| {
+            emit_expr(ctx, instr, &for_of.condition);
+        },
+    );
+}
+
+/// Emits a loop that evaluates `condition` for each pattern in
+/// `pattern_set`, and leaves the result of the quantifier in the stack.
+///
+/// The ID of the pattern being iterated is stored in a new variable, which
+/// is the current `for .. of` pattern (see [`Context::for_of_pattern`])
+/// while the condition is emitted. The previous one is restored afterwards,
+/// which makes nested loops possible.
+fn emit_for_each_pattern<C>(
+    ctx: &mut Context,
+    instr: &mut InstrSeqBuilder,
+    quantifier: &Quantifier,
+    pattern_set: &PatternSet,
+    condition: C,
+) where
+    C: FnOnce(&mut Context, &mut InstrSeqBuilder),
+{
     let pattern_ids: Vec<PatternId> =
-        patterns_matching(ctx, &for_of.pattern_set).collect();
+        patterns_matching(ctx, pattern_set).collect();
 
     let num_patterns = pattern_ids.len();
     let mut pattern_ids = pattern_ids.into_iter();
     let next_pattern_id = ctx.new_var(Type::Integer);
 
-    let mut symbol = Symbol::new(TypeValue::Integer(None));
-    symbol.kind = SymbolKind::WasmVar(next_pattern_id);
-
-    let mut loop_vars = SymbolTable::new();
-    loop_vars.insert("$", symbol);
-
-    ctx.symbol_table.push(Rc::new(loop_vars));
+    let outer_pattern = ctx.for_of_pattern.replace(next_pattern_id);
 
     emit_for(
         ctx,
         instr,
-        &for_of.quantifier,
+        quantifier,
         |ctx, instr, n, _| {
             // Set n = number of patterns.
             set_var(ctx, instr, n, |_, instr| {
@@ -1309,16 +1484,12 @@ fn emit_for_of_pattern_set(
                 });
             });
         },
-        // Condition
-        |ctx, instr| {
-            emit_expr(ctx, instr, &for_of.condition);
-        },
+        condition,
         // After each iteration.
         |_, _, _| {},
     );
 
-    // Remove the symbol table that contains the loop variable.
-    ctx.symbol_table.pop();
+    ctx.for_of_pattern = outer_pattern;
 
     // Free loop variables.
     ctx.free_vars(next_pattern_id);
@@ -1372,14 +1543,16 @@ fn emit_for_in_range(
                 instr.binop(BinaryOp::I64Add);
             });
 
-            // If n <= 0, exit from the loop.
+            // If n <= 0 the lower bound is greater than the upper bound,
+            // and the loop doesn't iterate at all. Exit from the loop with
+            // the result of a quantifier applied to an empty set.
             load_var(ctx, instr, n);
             instr.i64_const(0);
             instr.binop(BinaryOp::I64LeS);
             instr.if_else(
                 None,
                 |then_| {
-                    then_.i32_const(0);
+                    emit_empty_quantifier(ctx, then_, &for_in.quantifier);
                     then_.br(loop_end);
                 },
                 |_| {},
@@ -1407,6 +1580,34 @@ fn emit_for_in_range(
     ctx.free_vars(next_item);
 }
 
+/// Emits the code that produces the result of a `for` loop that iterates
+/// zero times.
+///
+/// With no items, `all` and `none` are true, while `any` is false. A
+/// quantifier like `n` or `n%` requires `n` items out of zero, it is true
+/// only when `n` is zero, which is always the case for percentages. The
+/// emitted code leaves 0 or 1 at the top of the stack.
+fn emit_empty_quantifier(
+    ctx: &mut Context,
+    instr: &mut InstrSeqBuilder,
+    quantifier: &Quantifier,
+) {
+    match quantifier {
+        Quantifier::All { .. }
+        | Quantifier::None { .. }
+        | Quantifier::Percentage(_) => {
+            instr.i32_const(1);
+        }
+        Quantifier::Any { .. } => {
+            instr.i32_const(0);
+        }
+        Quantifier::Expr(expr) => {
+            emit_expr(ctx, instr, expr);
+            instr.unop(UnaryOp::I64Eqz);
+        }
+    }
+}
+
 fn emit_for_in_expr(
     ctx: &mut Context,
     instr: &mut InstrSeqBuilder,
@@ -2333,6 +2534,38 @@ fn emit_call_and_handle_undef(
     );
 }
 
+/// Emits the code for a function argument that may be undefined.
+///
+/// Instead of raising an exception when the argument is undefined, this
+/// leaves the argument's value at the top of the stack followed by an `i32`
+/// that is 1 if the argument is undefined, and zero if otherwise. When the
+/// argument is undefined its value is meaningless.
+fn emit_arg_and_undef_flag(
+    ctx: &mut Context,
+    instr: &mut InstrSeqBuilder,
+    expr: &Expr,
+) {
+    let value_tmp = match expr.ty() {
+        Type::Integer | Type::String => ctx.wasm_symbols.i64_tmp,
+        Type::Float => ctx.wasm_symbols.f64_tmp,
+        ty => unreachable!("arguments of type {} can't be undefined", ty),
+    };
+
+    // The value is saved in a temp variable, the result of the block is
+    // zero if an exception was raised while evaluating the argument, and
+    // 1 if otherwise.
+    catch_undef(ctx, instr, |ctx, block| {
+        emit_expr(ctx, block, expr);
+        block.local_set(value_tmp);
+        block.i32_const(1);
+    });
+
+    instr.unop(UnaryOp::I32Eqz);
+    instr.local_set(ctx.wasm_symbols.i32_tmp);
+    instr.local_get(value_tmp);
+    instr.local_get(ctx.wasm_symbols.i32_tmp);
+}
+
 fn emit_lookup_common(ctx: &mut Context, instr: &mut InstrSeqBuilder) {
     let num_lookup_indexes = ctx.lookup_stack.len();
     let main_memory = ctx.wasm_symbols.main_memory;
@@ -2544,9 +2777,26 @@ fn throw_undef_if_zero(ctx: &Context, instr: &mut InstrSeqBuilder) {
     );
 }
 
+/// Similar to [`throw_undef_if_zero`], but throws the exception if the top
+/// of the stack is negative.
+fn throw_undef_if_negative(ctx: &Context, instr: &mut InstrSeqBuilder) {
+    instr.local_tee(ctx.wasm_symbols.i64_tmp);
+    instr.i64_const(0);
+    instr.binop(BinaryOp::I64LtS);
+    instr.if_else(
+        I64,
+        |then| {
+            throw_undef(ctx, then);
+        },
+        |else_| {
+            else_.local_get(ctx.wasm_symbols.i64_tmp);
+        },
+    );
+}
+
 /// Returns the patterns (a.k.a: strings) in the current rule that match a
 /// pattern set.
-fn patterns_matching<'a>(
+pub(in crate::compiler) fn patterns_matching<'a>(
     ctx: &'a mut Context,
     pattern_set: &'a PatternSet,
 ) -> Box<dyn Iterator<Item = PatternId> + 'a> {
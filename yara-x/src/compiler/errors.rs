@@ -1,37 +1,399 @@
 use std::fmt::{Debug, Display, Formatter};
+use std::path::PathBuf;
 
 use thiserror::Error;
-use yara_x_macros::Error as CompileError;
+use yara_x_macros::Error as Err;
 
 use yara_x_parser::ast::Span;
-use yara_x_parser::report::ReportBuilder;
-use yara_x_parser::report::ReportType;
+use yara_x_parser::escape::terminal_safe;
+use yara_x_parser::report::{Label, Report, ReportBuilder, ReportType};
+use yara_x_parser::warnings::{Warning, WarningInfo};
 use yara_x_parser::SourceCode;
 
 /// Errors returned by the compiler.
+///
+/// [`Display`] produces a single-line summary of the error. Errors that
+/// refer to some location in the source code also have a detailed report,
+/// returned by [`Error::report`].
+///
+/// New variants may be added in the future, so matching on this type
+/// requires a wildcard arm:
+///
+/// ```
+/// # use yara_x::{Compiler, Error};
+/// match Compiler::new().add_source("rule test {").unwrap_err() {
+///     Error::ParseError(err) => println!("{}", err.as_str()),
+///     Error::CompileError(err) => println!("{}", err.as_str()),
+///     Error::CompileErrors(errs) => println!("{}", errs.as_str()),
+///     err => println!("{}", err),
+/// }
+/// ```
+///
+/// ```compile_fail,E0004
+/// # use yara_x::{Compiler, Error};
+/// match Compiler::new().add_source("rule test {").unwrap_err() {
+///     Error::ParseError(_) => {}
+///     Error::CompileError(_) => {}
+///     Error::CompileErrors(_) => {}
+///     Error::IoError(_) => {}
+///     Error::DuplicateStringSet(_) => {}
+///     Error::UnknownWarning(_) => {}
+/// }
+/// ```
 #[derive(Error, Debug)]
-#[allow(clippy::large_enum_variant)]
+#[non_exhaustive]
 pub enum Error {
+    /// The source code is not syntactically valid.
     #[error(transparent)]
     ParseError(#[from] yara_x_parser::Error),
 
+    /// The source code is syntactically valid, but semantically wrong.
     #[error(transparent)]
     CompileError(#[from] CompileError),
 
+    /// Like [`Error::CompileError`], but more than one rule has errors.
     #[error(transparent)]
-    EmitError(#[from] anyhow::Error),
+    CompileErrors(CompileErrors),
+
+    /// Error while reading or writing a file.
+    #[error("I/O error")]
+    IoError(#[from] std::io::Error),
+
+    /// A file passed to [`crate::Compiler::add_source_file`] or found by
+    /// [`crate::Compiler::add_source_dir`] could not be read.
+    #[error("can not open `{}`", path.display())]
+    OpenError { path: PathBuf, source: std::io::Error },
+
+    /// A string set with the same name was already defined with
+    /// [`crate::Compiler::define_string_set`].
+    #[error("string set `{0}` is already defined")]
+    DuplicateStringSet(String),
+
+    /// The name passed to [`crate::Compiler::define_global`] is not a valid
+    /// identifier.
+    #[error("invalid global variable name `{0}`")]
+    InvalidGlobal(String),
+
+    /// The name passed to [`crate::Compiler::define_global`] is already
+    /// used by another global variable, a module or a built-in function.
+    #[error("global variable `{0}` is already defined")]
+    DuplicateGlobal(String),
+
+    /// The name passed to [`crate::Compiler::register_module`] is the name
+    /// of a built-in module, or of a module that was already registered.
+    #[error("module `{0}` is already defined")]
+    DuplicateModule(String),
+
+    /// The descriptor passed to [`crate::Compiler::register_module`] uses
+    /// some protobuf feature that YARA doesn't support.
+    #[error("module `{name}` is not supported: {reason}")]
+    UnsupportedModule { name: String, reason: String },
+
+    /// The identifier passed to [`crate::Compiler::disable_warning`] doesn't
+    /// correspond to any warning.
+    #[error("unknown warning `{0}`")]
+    UnknownWarning(String),
+
+    /// The text passed to [`crate::Pattern::compile`] is valid, but it's
+    /// not a single pattern.
+    #[error("`{0}` is not a single pattern")]
+    InvalidPattern(String),
+
+    /// The pattern passed to [`crate::Pattern::compile`] is a hex pattern
+    /// or a regular expression, which are not supported yet.
+    #[error("`{0}` is not a text pattern")]
+    UnsupportedPattern(String),
+
+    /// The build was cancelled with the [`crate::CancellationToken`] passed
+    /// to [`crate::Compiler::cancellation_token`].
+    #[error("build cancelled")]
+    Cancelled,
+}
+
+impl Error {
+    /// Returns the detailed text-mode report for errors that refer to some
+    /// location in the source code, or [`None`] for the rest of them.
+    pub fn report(&self) -> Option<&str> {
+        match self {
+            Error::ParseError(err) => Some(err.as_str()),
+            Error::CompileError(err) => Some(err.as_str()),
+            Error::CompileErrors(errs) => Some(errs.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Renders the detailed report for compile errors. Other kinds of
+    /// errors are returned unchanged, parser errors are already rendered
+    /// by the parser itself.
+    pub(crate) fn render(
+        self,
+        report_builder: &ReportBuilder,
+        src: &SourceCode,
+    ) -> Self {
+        match self {
+            Error::CompileError(err) => {
+                Error::CompileError(err.render(report_builder, src))
+            }
+            err => err,
+        }
+    }
+}
+
+impl From<CompileErrorInfo> for Error {
+    fn from(info: CompileErrorInfo) -> Self {
+        Error::CompileError(CompileError::new(info))
+    }
 }
 
 /// An error occurred during the compilation process.
-#[derive(CompileError)]
-pub enum CompileError {
+///
+/// Like [`yara_x_parser::Error`], it contains a detailed text-mode report
+/// and a [`CompileErrorInfo`] with the individual pieces of information
+/// that describe the error. [`Display`] only produces the first line of
+/// the report, without the `error:` prefix. The pieces of the report, like
+/// the title and labels, are also available separately, for tools that show
+/// the errors in their own way.
+///
+/// With the `serde` feature, errors implement `serde::Serialize`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CompileError {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    info: Box<CompileErrorInfo>,
+    code: &'static str,
+    title: String,
+    origin: Option<String>,
+    labels: Vec<Label>,
+    report: String,
+}
+
+impl CompileError {
+    /// Creates a new error from a [`CompileErrorInfo`]. The report is
+    /// rendered later by [`CompileError::render`], once the error reaches
+    /// [`crate::Compiler::add_source`].
+    pub(crate) fn new(info: CompileErrorInfo) -> Self {
+        Self {
+            code: info.code(),
+            title: terminal_safe(&info.title()).into_owned(),
+            origin: None,
+            labels: Vec::new(),
+            info: Box::new(info),
+            report: String::new(),
+        }
+    }
+
+    /// Creates an error from a warning, for compilers that treat warnings
+    /// as errors. The report is the warning's report, including all its
+    /// labels, but reported as an error.
+    pub(crate) fn from_warning(
+        warning: &Warning,
+        report_builder: &ReportBuilder,
+        src: &SourceCode,
+    ) -> Self {
+        let info = warning.info();
+        let mut err = Self::new(CompileErrorInfo::warning_as_error(
+            warning.code(),
+            info.title(),
+            info.labels()
+                .first()
+                .map(|(span, _, _)| *span)
+                .unwrap_or_default(),
+        ));
+        err.report = report_builder.render(src, &WarningAsError(info));
+        err.labels = report_builder.labels(src, info);
+        err.origin = src.get_origin().map(String::from);
+        err
+    }
+
+    /// Creates an error from a parser error, for combining it with other
+    /// compile errors. The report and labels are the ones of the parser
+    /// error.
+    pub(crate) fn from_parse_error(
+        err: &yara_x_parser::Error,
+        origin: Option<&str>,
+    ) -> Self {
+        let info = err.info();
+        let mut compile_err = Self::new(CompileErrorInfo::parse_error(
+            info.code(),
+            info.title(),
+            info.labels()
+                .first()
+                .map(|(span, _, _)| *span)
+                .unwrap_or_default(),
+        ));
+        compile_err.report = err.as_str().to_string();
+        compile_err.labels = err.labels().to_vec();
+        compile_err.origin = origin.map(String::from);
+        compile_err
+    }
+
+    /// Renders the detailed report for the error.
+    pub(crate) fn render(
+        mut self,
+        report_builder: &ReportBuilder,
+        src: &SourceCode,
+    ) -> Self {
+        self.report = report_builder.render(src, self.info.as_ref());
+        self.labels = report_builder.labels(src, self.info.as_ref());
+        self.origin = src.get_origin().map(String::from);
+        self
+    }
+
+    /// Returns the detailed text-mode report for the error.
+    pub fn as_str(&self) -> &str {
+        self.report.as_str()
+    }
+
+    /// Returns additional information about the error.
+    pub fn info(&self) -> &CompileErrorInfo {
+        self.info.as_ref()
+    }
+
+    /// Returns the identifier for this kind of error, like
+    /// `unknown_identifier`. See [`CompileErrorInfo::CODES`] for the full
+    /// list.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Returns the error's title, which is the first line of the report
+    /// without the `error:` prefix.
+    pub fn title(&self) -> &str {
+        self.title.as_str()
+    }
+
+    /// Returns the labels in the report. The first one is the main label.
+    ///
+    /// When warnings are treated as errors (see
+    /// [`crate::Compiler::errors_on_warnings`]), these are the labels of
+    /// the warning.
+    pub fn labels(&self) -> &[Label] {
+        self.labels.as_slice()
+    }
+
+    /// Returns the origin of the source code where the error was found, as
+    /// set by [`SourceCode::origin`].
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl Debug for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.report)
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.title)
+    }
+}
+
+/// Errors occurred in multiple rules while compiling the same source code.
+///
+/// The compiler doesn't stop at the first rule with errors, it checks the
+/// remaining rules and reports the errors found in each of them. Errors
+/// within a single rule are not recovered, so there's at most one error per
+/// rule. [`Display`] produces the summary of the first error followed by the
+/// number of additional errors.
+pub struct CompileErrors {
+    errors: Vec<CompileError>,
+    report: String,
+}
+
+impl CompileErrors {
+    /// Creates a new [`CompileErrors`] from already rendered errors.
+    pub(crate) fn new(errors: Vec<CompileError>) -> Self {
+        debug_assert!(errors.len() > 1);
+        let report = errors
+            .iter()
+            .map(|err| err.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self { errors, report }
+    }
+
+    /// Returns the individual errors, in the order they were found.
+    pub fn as_slice(&self) -> &[CompileError] {
+        self.errors.as_slice()
+    }
+
+    /// Consumes the errors and returns them in a vector, in the order they
+    /// were found.
+    pub fn into_vec(self) -> Vec<CompileError> {
+        self.errors
+    }
+
+    /// Returns the detailed text-mode reports of all the errors, separated
+    /// by empty lines.
+    pub fn as_str(&self) -> &str {
+        self.report.as_str()
+    }
+}
+
+impl std::error::Error for CompileErrors {}
+
+impl Debug for CompileErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.report)
+    }
+}
+
+impl Display for CompileErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (and {} more errors)",
+            self.errors[0],
+            self.errors.len() - 1
+        )
+    }
+}
+
+/// Reports a warning as an error, see [`CompileError::from_warning`].
+struct WarningAsError<'a>(&'a WarningInfo);
+
+impl Report for WarningAsError<'_> {
+    fn report_type(&self) -> ReportType {
+        ReportType::Error
+    }
+
+    fn title(&self) -> String {
+        self.0.title()
+    }
+
+    fn labels(&self) -> Vec<(Span, String, yansi::Style)> {
+        self.0.labels()
+    }
+
+    fn label_origins(&self) -> Vec<Option<String>> {
+        self.0.label_origins()
+    }
+
+    fn note(&self) -> Option<String> {
+        self.0.note()
+    }
+}
+
+/// Information about a compile error.
+///
+/// Variants only hold the data describing the error, the message templates
+/// are declared with the attributes understood by the [`yara_x_macros::Error`]
+/// derive macro. Adding a new error consists in adding a variant with its
+/// `#[error(...)]` and `#[label(...)]` attributes, and returning it from the
+/// compiler with `Err(CompileErrorInfo::new_variant(...).into())`. The
+/// report is rendered when the error leaves [`crate::Compiler::add_source`].
+#[derive(Err, Debug)]
+#[non_exhaustive]
+pub enum CompileErrorInfo {
     #[error("wrong type")]
     #[label(
         "expression should be {expected_types}, but is `{actual_type}`",
         expression_span
     )]
     WrongType {
-        detailed_report: String,
         expected_types: String,
         actual_type: String,
         expression_span: Span,
@@ -41,7 +403,6 @@ pub enum CompileError {
     #[label("this expression is `{type1}`", type1_span)]
     #[label("this expression is `{type2}`", type2_span)]
     MismatchingTypes {
-        detailed_report: String,
         type1: String,
         type2: String,
         type1_span: Span,
@@ -51,17 +412,12 @@ pub enum CompileError {
     #[error("wrong arguments")]
     #[label("wrong arguments in this call", args_span)]
     #[note(note)]
-    WrongArguments {
-        detailed_report: String,
-        args_span: Span,
-        note: Option<String>,
-    },
+    WrongArguments { args_span: Span, note: Option<String> },
 
     #[error("assignment mismatch")]
     #[label("this expects {expected_values} value(s)", error_span)]
     #[label("this produces {actual_values} value(s)", iterable_span)]
     AssignmentMismatch {
-        detailed_report: String,
         expected_values: u8,
         actual_values: u8,
         iterable_span: Span,
@@ -70,26 +426,164 @@ pub enum CompileError {
 
     #[error("unexpected negative number")]
     #[label("this number can not be negative", span)]
-    UnexpectedNegativeNumber { detailed_report: String, span: Span },
+    UnexpectedNegativeNumber { span: Span },
 
     #[error("number out of range")]
     #[label("this number is out of the allowed range [{min}-{max}]", span)]
-    NumberOutOfRange {
-        detailed_report: String,
-        min: i64,
-        max: i64,
-        span: Span,
-    },
+    NumberOutOfRange { min: i64, max: i64, span: Span },
+
+    #[error("arithmetic overflow")]
+    #[label(
+        "the result of this expression doesn't fit in a 64-bit integer",
+        span
+    )]
+    ArithmeticOverflow { span: Span },
 
     #[error("unknown identifier `{identifier}`")]
     #[label("this identifier has not been declared", span)]
-    UnknownIdentifier {
-        detailed_report: String,
+    #[note(note)]
+    UnknownIdentifier { identifier: String, span: Span, note: Option<String> },
+
+    #[error("unknown string set `{identifier}`")]
+    #[label("this string set has not been defined", span)]
+    UnknownStringSet { identifier: String, span: Span },
+
+    #[error("loop variable `{identifier}` shadows a {kind}")]
+    #[label("this {kind} is hidden by the loop variable", span)]
+    ShadowedIdentifier { identifier: String, kind: &'static str, span: Span },
+
+    #[error("unknown module `{identifier}`")]
+    #[label("module `{identifier}` not found", span)]
+    UnknownModule { identifier: String, span: Span },
+
+    #[error("`{extension}` is a YARA-X extension")]
+    #[label(
+        "this is not supported by YARA, enable extensions to use it",
+        span
+    )]
+    ExtensionNotEnabled { extension: String, span: Span },
+
+    #[error("{kind} `{identifier}` violates the naming policy")]
+    #[label("{reason}", span)]
+    NamingPolicyViolation {
+        kind: &'static str,
         identifier: String,
+        reason: String,
         span: Span,
     },
 
-    #[error("unknown module `{identifier}`")]
-    #[label("module `{identifier}` not found", span)]
-    UnknownModule { detailed_report: String, identifier: String, span: Span },
+    #[error("invalid pragma `{pragma}`")]
+    #[label("{error}", span)]
+    InvalidPragma { pragma: String, error: String, span: Span },
+
+    #[error("invalid regular expression")]
+    #[label("{error}", span)]
+    InvalidRegexp { error: String, span: Span },
+
+    #[error("too many nested loops")]
+    #[label(
+        "this loop exceeds the maximum nesting depth of {max_nesting}",
+        span
+    )]
+    TooManyNestedLoops { max_nesting: usize, span: Span },
+
+    #[error("too many rules")]
+    #[label("this rule exceeds the limit of {max_rules} rules", span)]
+    TooManyRules { max_rules: usize, span: Span },
+
+    #[error("too many patterns in a rule")]
+    #[label(
+        "this pattern exceeds the limit of {max_patterns} patterns per rule",
+        span
+    )]
+    TooManyPatternsInRule { max_patterns: usize, span: Span },
+
+    #[error("too many patterns")]
+    #[label(
+        "this pattern exceeds the limit of {max_patterns} patterns in total",
+        span
+    )]
+    TooManyPatterns { max_patterns: usize, span: Span },
+
+    #[error("too many identifiers")]
+    #[label("the compiler can't store more identifiers or literals", span)]
+    TooManyIdentifiers { span: Span },
+
+    #[error("duplicate rule `{rule}`")]
+    #[label("duplicate declaration of `{rule}`", new_rule_span)]
+    #[label(
+        "`{rule}` declared here for the first time",
+        existing_rule_span,
+        origin = "existing_rule_origin",
+        style = "note"
+    )]
+    #[note(note)]
+    DuplicateRule {
+        rule: String,
+        new_rule_span: Span,
+        existing_rule_span: Span,
+        existing_rule_origin: Option<String>,
+        note: Option<String>,
+    },
+
+    #[error("rule `{rule}` references itself")]
+    #[label("a rule can't be used in its own condition", span)]
+    SelfReferencingRule { rule: String, span: Span },
+
+    #[error("rule `{rule}` is used before being defined")]
+    #[label("`{rule}` is used here", span)]
+    #[label("`{rule}` is defined here", definition_span, style = "note")]
+    #[note(note)]
+    RuleUsedBeforeDefinition {
+        rule: String,
+        span: Span,
+        definition_span: Span,
+        note: Option<String>,
+    },
+
+    #[error("rule `{rule}` references disabled rule `{disabled_rule}`")]
+    #[label("`{disabled_rule}` is disabled", span)]
+    DisabledRuleReference { rule: String, disabled_rule: String, span: Span },
+
+    #[error("namespace `{namespace}` violates the naming policy")]
+    #[label("{reason}", span)]
+    #[note(note)]
+    NamespaceNamingPolicyViolation {
+        namespace: String,
+        reason: String,
+        span: Span,
+        note: Option<String>,
+    },
+
+    #[error("{title}")]
+    #[label("warning `{code}` is treated as an error", span)]
+    WarningAsError { code: &'static str, title: String, span: Span },
+
+    #[error("{title}")]
+    #[label("parser error `{code}`", span)]
+    ParseError { code: &'static str, title: String, span: Span },
+
+    #[error("{title}")]
+    #[label("{message}", span)]
+    BannedModule { module: String, title: String, message: String, span: Span },
+
+    #[error("can't include `{file_name}`")]
+    #[label("{error}", span)]
+    IncludeError { file_name: String, error: String, span: Span },
+
+    #[error("circular include of `{file_name}`")]
+    #[label("`{file_name}` is already being included", span)]
+    #[note(note)]
+    CircularInclude { file_name: String, span: Span, note: Option<String> },
+
+    #[error("too many nested includes")]
+    #[label(
+        "this include exceeds the maximum nesting depth of {max_depth}",
+        span
+    )]
+    TooManyNestedIncludes { max_depth: usize, span: Span },
+
+    #[error("includes are not allowed")]
+    #[label("includes are disabled in this compiler", span)]
+    IncludesNotAllowed { span: Span },
 }
@@ -0,0 +1,59 @@
+/*! Access to the files read by the compiler.
+
+[`Compiler::add_source_file`], [`Compiler::add_source_dir`] and the
+default resolution of `include` statements don't use the file system
+directly, they go through a [`FileReader`]. By default this is
+[`OsFileReader`], which reads from the file system, but other
+implementations can be set with [`Compiler::set_file_reader`], for
+instance for compiling rules stored in an archive.
+
+[`Compiler::add_source_file`]: crate::Compiler::add_source_file
+[`Compiler::add_source_dir`]: crate::Compiler::add_source_dir
+[`Compiler::set_file_reader`]: crate::Compiler::set_file_reader
+*/
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An entry in a directory, as returned by [`FileReader::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Path of the entry, which is the path of the directory joined with
+    /// the entry's name.
+    pub path: PathBuf,
+    /// True if the entry is a directory. Symbolic links to directories
+    /// are not directories.
+    pub is_dir: bool,
+}
+
+/// Reads the files and directories used by the compiler.
+pub trait FileReader {
+    /// Returns the content of the file at `path`.
+    fn read(&mut self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Returns the entries in the directory at `path`, in any order.
+    fn read_dir(&mut self, path: &Path) -> io::Result<Vec<DirEntry>>;
+}
+
+/// A [`FileReader`] that reads from the file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFileReader;
+
+impl FileReader for OsFileReader {
+    fn read(&mut self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_dir(&mut self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok(DirEntry {
+                    path: entry.path(),
+                    is_dir: entry.file_type()?.is_dir(),
+                })
+            })
+            .collect()
+    }
+}
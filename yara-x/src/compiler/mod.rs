@@ -4,13 +4,22 @@ YARA rules must be compiled before they can be used for scanning data. This
 module implements the YARA compiler.
 */
 use aho_corasick::AhoCorasick;
+use bitvec::vec::BitVec;
 use bstr::ByteSlice;
-use rustc_hash::FxHashMap;
+use lazy_static::lazy_static;
+use protobuf::reflect::MessageDescriptor;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::path::Path;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
-use std::{fmt, mem};
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::{fmt, io, mem, panic, thread};
 use walrus::ir::InstrSeqId;
 use walrus::{FunctionId, Module, ValType};
 
@@ -24,28 +33,41 @@ use yara_x_parser::ast::*;
 use yara_x_parser::report::ReportBuilder;
 use yara_x_parser::types::{Func, FuncSignature};
 use yara_x_parser::types::{Struct, TypeValue};
-use yara_x_parser::warnings::Warning;
-use yara_x_parser::{ErrorInfo as ParserError, Parser, SourceCode};
+pub use yara_x_parser::warnings::{Warning, WarningInfo};
+pub use yara_x_parser::SourceCode;
+use yara_x_parser::{ErrorInfo as ParserError, Parser};
 
 use crate::compiler::emit::emit_rule_code;
 use crate::compiler::semcheck::{semcheck, warn_if_not_bool};
+use crate::compiler::shadowing::{shadowed_patterns, ShadowedPattern};
+use crate::scanner::Rule;
 use crate::string_pool::{BStringPool, StringPool};
 use crate::symbols::{
     StackedSymbolTable, Symbol, SymbolKind, SymbolLookup, SymbolTable,
 };
-
+use crate::variables::Variable;
 use crate::wasm;
 use crate::wasm::builder::ModuleBuilder;
 use crate::wasm::{WasmSymbols, WASM_EXPORTS};
 
 #[doc(inline)]
+pub use crate::compiler::diagnostics::*;
 pub use crate::compiler::errors::*;
+pub use crate::compiler::files::*;
+pub use crate::compiler::options::*;
+pub use crate::compiler::progress::*;
 use crate::modules::BUILTIN_MODULES;
 
 mod atoms;
+mod diagnostics;
 mod emit;
 mod errors;
+mod files;
+mod options;
+mod progress;
+mod regexp;
 mod semcheck;
+mod shadowing;
 
 #[cfg(test)]
 mod tests;
@@ -69,9 +91,19 @@ pub fn compile<'src, S>(src: S) -> Result<Rules, Error>
 where
     S: Into<SourceCode<'src>>,
 {
-    Compiler::new().add_source(src)?.build()
+    let mut compiler = Compiler::new();
+    compiler.add_source(src)?;
+    compiler.build()
 }
 
+/// Name of the namespace that contains the rules added to the compiler
+/// before any call to [`Compiler::new_namespace`].
+///
+/// This is an ordinary namespace that happens to exist from the start, rules
+/// can be added to it later by calling [`Compiler::new_namespace`] with this
+/// same name.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
 /// Structure that contains information about a rule namespace.
 ///
 /// Includes the IdentId corresponding to the namespace's identifier
@@ -80,8 +112,109 @@ where
 struct Namespace {
     ident_id: IdentId,
     symbols: Rc<RefCell<SymbolTable>>,
+    /// True if the namespace's identifier was already checked against the
+    /// naming policy. Namespaces are created via API and don't appear in
+    /// the source code, so they are checked when a rule is added to them,
+    /// and the verdict is reported at that rule's location.
+    naming_checked: bool,
+    /// Identifiers that the rules in the namespace can't use because they
+    /// are unknown modules, or rules ignored for using those modules. See
+    /// [`Compiler::ignore_unknown_modules`]. Values are the names of the
+    /// unknown modules.
+    ignored_identifiers: FxHashMap<String, String>,
+}
+
+/// State of the compiler before processing a rule, used for removing the
+/// rule if it must be ignored. See [`Compiler::take_snapshot`].
+struct Snapshot {
+    next_pattern_id: i32,
+    num_rules: usize,
+    num_sub_patterns: usize,
+    num_atoms: usize,
+    num_regexps: usize,
+}
+
+/// State of the compiler before adding a source code, used for discarding
+/// the changes made by the source code if it has errors. See
+/// [`Compiler::take_source_snapshot`].
+struct SourceSnapshot {
+    rules: Snapshot,
+    num_imported_modules: usize,
+    num_modules: usize,
+    num_rule_fns: usize,
+    symbol_table_len: usize,
+    symbols: SymbolTable,
+    ignored_identifiers: FxHashMap<String, String>,
+    naming_checked: bool,
+}
+
+/// State of the compiler that is specific to the source code being
+/// compiled, put aside while compiling an included source. See
+/// [`Compiler::take_source_state`].
+struct SourceState {
+    current_origin: Option<IdentId>,
+    source_extensions: bool,
+    source_disabled_warnings: FxHashSet<&'static str>,
+    current_default_meta: Vec<(IdentId, MetaValue)>,
+    unused_private_rules: Vec<(RuleId, Span)>,
+    unused_imports: Vec<(String, Span)>,
+    upcoming_rules: FxHashMap<String, Span>,
+    first_source_warning: usize,
+}
+
+/// Kinds of identifiers that are checked by a naming policy.
+///
+/// See [`Compiler::naming_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdentifierKind {
+    Namespace,
+    Rule,
+    Tag,
+    Pattern,
+}
+
+impl IdentifierKind {
+    /// Returns the name of this kind of identifier (e.g: `"rule"`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Namespace => "namespace",
+            Self::Rule => "rule",
+            Self::Tag => "tag",
+            Self::Pattern => "pattern",
+        }
+    }
+}
+
+impl fmt::Display for IdentifierKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Result of checking an identifier against a naming policy.
+///
+/// See [`Compiler::naming_policy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NamingPolicyVerdict {
+    /// The identifier complies with the policy.
+    Accept,
+    /// The identifier doesn't comply with the policy, and a warning with
+    /// the given reason is raised.
+    Warn(String),
+    /// The identifier doesn't comply with the policy, and compilation fails
+    /// with an error that includes the given reason.
+    Reject(String),
 }
 
+type NamingPolicy<'a> = Box<
+    dyn Fn(IdentifierKind, &str, Option<Span>) -> NamingPolicyVerdict + 'a,
+>;
+
+type BuildProgressCallback<'a> = Box<dyn FnMut(&BuildProgress) + 'a>;
+
+type IncludeResolver<'a> =
+    Box<dyn FnMut(&str, Option<&Path>) -> io::Result<Vec<u8>> + 'a>;
+
 /// Takes YARA source code and produces compiled [`Rules`].
 pub struct Compiler<'a> {
     /// Used for generating error and warning reports.
@@ -90,10 +223,20 @@ pub struct Compiler<'a> {
     /// The main symbol table used by the compiler.
     symbol_table: StackedSymbolTable<'a>,
 
+    /// Symbol table at the bottom of `symbol_table`, which contains the
+    /// built-in functions and the global variables defined with
+    /// [`Compiler::define_global`]. These symbols are visible from all
+    /// namespaces.
+    global_symbols: Rc<RefCell<SymbolTable>>,
+
     /// Information about the current namespace (i.e: the namespace that will
     /// contain any new rules added via a call to `add_sources`.
     current_namespace: Namespace,
 
+    /// Namespaces that were current at some point, but not anymore. They
+    /// are kept here in case they become current again.
+    namespaces: Vec<Namespace>,
+
     /// Pool that contains all the identifiers used in the rules. Each
     /// identifier appears only once, even if they are used by multiple
     /// rules. For example, the pool contains a single copy of the common
@@ -119,6 +262,10 @@ pub struct Compiler<'a> {
     /// Next (not unused yet) [`PatternId`].
     next_pattern_id: i32,
 
+    /// Patterns whose matches must be all found, indexed by [`PatternId`].
+    /// See [`Rules::all_matches_required`].
+    all_matches_required: BitVec,
+
     /// A vector with all the sub-patterns from all the rules. A
     /// [`SubPatternId`] is an index in this vector.
     sub_patterns: Vec<(PatternId, SubPattern)>,
@@ -133,12 +280,134 @@ pub struct Compiler<'a> {
     imported_modules: Vec<IdentId>,
 
     /// Structure where each field corresponds to a module imported by the
-    /// rules. The value of each field is the structure that describes the
-    /// module.
+    /// rules, or to a global variable defined with
+    /// [`Compiler::define_global`]. For modules, the value of each field is
+    /// the structure that describes the module. For global variables, it's
+    /// the variable's default value.
     modules_struct: Struct,
 
+    /// Modules registered with [`Compiler::register_module`]. Keys are the
+    /// module names, and values describe the module's structure.
+    user_modules: FxHashMap<String, MessageDescriptor>,
+
+    /// Sets of strings defined with [`Compiler::define_string_set`]. A
+    /// [`StringSetId`] is an index in this vector.
+    string_sets: Vec<StringSet>,
+
+    /// Regexps used with the `matches` operator. A [`RegexpId`] is an index
+    /// in this vector.
+    regexps: Vec<regex::bytes::Regex>,
+
     /// Warnings generated while compiling the rules.
     warnings: Vec<Warning>,
+
+    /// Naming policy that identifiers must comply with, if any.
+    naming_policy: Option<NamingPolicy<'a>>,
+
+    /// Callback that receives the progress of [`Compiler::build`], if any.
+    build_progress: Option<BuildProgressCallback<'a>>,
+
+    /// Token for cancelling [`Compiler::build`], if any.
+    cancellation_token: Option<CancellationToken>,
+
+    /// Whether YARA-X extensions to the YARA language are allowed, or
+    /// [`None`] if [`Compiler::enable_extensions`] wasn't called, in which
+    /// case extensions are disabled unless enabled by a pragma.
+    extensions: Option<bool>,
+
+    /// If true, extensions are enabled by the `extensions` pragma in the
+    /// source being compiled.
+    source_extensions: bool,
+
+    /// If true, rules disabled with `enabled = false` are compiled as
+    /// any other rule.
+    force_enable_rules: bool,
+
+    /// If true, patterns shadowed by other patterns in the same rule are
+    /// reported. See [`Compiler::detect_shadowed_patterns`].
+    detect_shadowed_patterns: bool,
+
+    /// If true, imports of unknown modules raise warnings instead of
+    /// errors. See [`Compiler::ignore_unknown_modules`].
+    ignore_unknown_modules: bool,
+
+    /// Maximum number of rules, see [`Compiler::set_max_rules`].
+    max_rules: usize,
+
+    /// Maximum number of patterns per rule, see
+    /// [`Compiler::set_max_patterns_per_rule`].
+    max_patterns_per_rule: usize,
+
+    /// Maximum number of patterns in all rules, see
+    /// [`Compiler::set_max_total_patterns`].
+    max_total_patterns: usize,
+
+    /// Modules banned with [`Compiler::ban_module`]. Values are the title
+    /// and message of the error raised when the module is imported.
+    banned_modules: FxHashMap<String, (String, String)>,
+
+    /// If false, include statements produce errors. See
+    /// [`Compiler::enable_includes`].
+    includes_enabled: bool,
+
+    /// Callback that returns the content of included files, if any. When
+    /// [`None`], included files are read with `file_reader`.
+    include_resolver: Option<IncludeResolver<'a>>,
+
+    /// Reads source files, directories and included files. See
+    /// [`Compiler::set_file_reader`].
+    file_reader: Box<dyn FileReader + 'a>,
+
+    /// Origins of the sources that are including other sources at the
+    /// moment, from the outermost to the innermost. Used for detecting
+    /// circular includes.
+    include_stack: Vec<String>,
+
+    /// Number of included sources being compiled at the moment.
+    include_depth: usize,
+
+    /// Private rules in the source being compiled that haven't been
+    /// referenced by other rules yet, together with the span of their
+    /// identifiers.
+    unused_private_rules: Vec<(RuleId, Span)>,
+
+    /// Rules declared in the namespace being processed that come after the
+    /// rule being compiled, together with the span of their identifiers.
+    /// Used for reporting rules that are used before being defined.
+    upcoming_rules: FxHashMap<String, Span>,
+
+    /// Modules imported by the source being compiled that haven't been
+    /// used by any rule yet, together with the span of their import
+    /// statements.
+    unused_imports: Vec<(String, Span)>,
+
+    /// ID in the identifiers pool of the origin of the source being
+    /// compiled, if it has one.
+    current_origin: Option<IdentId>,
+
+    /// If true, warnings are turned into errors.
+    errors_on_warnings: bool,
+
+    /// Identifiers of the warnings disabled with
+    /// [`Compiler::disable_warning`].
+    disabled_warnings: FxHashSet<&'static str>,
+
+    /// Identifiers of the warnings disabled with the `disable_warning`
+    /// pragma in the source being compiled.
+    source_disabled_warnings: FxHashSet<&'static str>,
+
+    /// Metadata entries inherited by the rules in each namespace, set with
+    /// [`Compiler::namespace_default_meta`]. Keys are the IDs of the
+    /// namespaces in the identifiers pool.
+    namespace_default_meta: FxHashMap<IdentId, Vec<(IdentId, MetaValue)>>,
+
+    /// Metadata entries inherited by the rules in the source being
+    /// compiled, see [`SourceCode::with_default_meta`].
+    current_default_meta: Vec<(IdentId, MetaValue)>,
+
+    /// Index in `warnings` of the first warning raised by the source being
+    /// compiled.
+    first_source_warning: usize,
 }
 
 impl<'a> Compiler<'a> {
@@ -151,7 +420,7 @@ impl<'a> Compiler<'a> {
         let global_symbols = symbol_table.push_new();
 
         for export in WASM_EXPORTS.iter().filter(|e| e.public) {
-            let func = Rc::new(Func::with_signature(FuncSignature::from(
+            let func = Arc::new(Func::with_signature(FuncSignature::from(
                 export.mangled_name.to_string(),
             )));
 
@@ -165,24 +434,59 @@ impl<'a> Compiler<'a> {
         // namespace, unless the user defines some namespace explicitly by calling
         // `Compiler::new_namespace`.
         let default_namespace = Namespace {
-            ident_id: ident_pool.get_or_intern("default"),
+            ident_id: ident_pool.get_or_intern(DEFAULT_NAMESPACE),
             symbols: symbol_table.push_new(),
+            naming_checked: true,
+            ignored_identifiers: FxHashMap::default(),
         };
 
         Self {
             ident_pool,
             symbol_table,
+            global_symbols,
             next_pattern_id: 0,
+            all_matches_required: BitVec::new(),
             current_namespace: default_namespace,
+            namespaces: Vec::new(),
             warnings: Vec::new(),
             rules: Vec::new(),
             sub_patterns: Vec::new(),
             atoms: Vec::new(),
             imported_modules: Vec::new(),
             modules_struct: Struct::new(),
+            user_modules: FxHashMap::default(),
+            string_sets: Vec::new(),
+            regexps: Vec::new(),
             report_builder: ReportBuilder::new(),
             lit_pool: BStringPool::new(),
             wasm_mod: ModuleBuilder::new(),
+            naming_policy: None,
+            build_progress: None,
+            cancellation_token: None,
+            extensions: None,
+            source_extensions: false,
+            force_enable_rules: false,
+            detect_shadowed_patterns: false,
+            ignore_unknown_modules: false,
+            max_rules: MAX_RULES,
+            max_patterns_per_rule: MAX_PATTERNS,
+            max_total_patterns: MAX_PATTERNS,
+            banned_modules: FxHashMap::default(),
+            includes_enabled: true,
+            include_resolver: None,
+            file_reader: Box::new(OsFileReader),
+            include_stack: Vec::new(),
+            include_depth: 0,
+            unused_private_rules: Vec::new(),
+            upcoming_rules: FxHashMap::default(),
+            unused_imports: Vec::new(),
+            current_origin: None,
+            errors_on_warnings: false,
+            disabled_warnings: FxHashSet::default(),
+            source_disabled_warnings: FxHashSet::default(),
+            namespace_default_meta: FxHashMap::default(),
+            current_default_meta: Vec::new(),
+            first_source_warning: 0,
         }
     }
 
@@ -190,15 +494,458 @@ impl<'a> Compiler<'a> {
     ///
     /// Colorized error messages contain ANSI escape sequences that make them
     /// look nicer on compatible consoles. The default setting is `false`.
-    pub fn colorize_errors(mut self, b: bool) -> Self {
+    pub fn colorize_errors(&mut self, b: bool) -> &mut Self {
         self.report_builder.with_colors(b);
         self
     }
 
+    /// Enables or disables YARA-X extensions to the YARA language.
+    ///
+    /// Extensions are language features that are not supported by YARA,
+    /// like `count_of(...)`. Rules that use them are rejected while
+    /// extensions are disabled, which guarantees that the rules accepted by
+    /// the compiler are portable to YARA. The default setting is `false`.
+    ///
+    /// When this function is not called, a source code can enable
+    /// extensions for itself with the `// yarax: extensions` pragma. After
+    /// calling `enable_extensions(false)` the pragma is ignored with a
+    /// warning, as options set with the API take precedence over pragmas.
+    pub fn enable_extensions(&mut self, yes: bool) -> &mut Self {
+        self.extensions = Some(yes);
+        self
+    }
+
+    /// Compiles disabled rules as if they were enabled.
+    ///
+    /// A rule is disabled by including `enabled = false` in its `meta`
+    /// section. Disabled rules are fully checked by the compiler, but they
+    /// don't contribute patterns to the scanner and their conditions are
+    /// never evaluated, so they never match. Enabled rules can't reference
+    /// disabled ones. The default setting is `false`.
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, Scanner};
+    /// let src = "rule foo { meta: enabled = false condition: true }";
+    ///
+    /// let mut compiler = Compiler::new();
+    /// compiler.add_source(src).unwrap();
+    /// let rules = compiler.build().unwrap();
+    /// let mut scanner = Scanner::new(&rules);
+    /// assert_eq!(scanner.scan(b"").num_matching_rules(), 0);
+    ///
+    /// let mut compiler = Compiler::new();
+    /// compiler.force_enable_rules(true).add_source(src).unwrap();
+    /// let rules = compiler.build().unwrap();
+    /// let mut scanner = Scanner::new(&rules);
+    /// assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+    /// ```
+    pub fn force_enable_rules(&mut self, yes: bool) -> &mut Self {
+        self.force_enable_rules = yes;
+        self
+    }
+
+    /// Reports patterns that are shadowed by other patterns in the same
+    /// rule.
+    ///
+    /// A pattern is shadowed by another one when every match of the first
+    /// pattern contains a match of the second, like in `$a = "malware"` and
+    /// `$b = "malware detected"`. In expressions like `any of ($a, $b)`,
+    /// `none of ($a, $b)` or `1 of ($a, $b)`, the shadowed pattern can't
+    /// change the result, and a [`WarningInfo::ShadowedPattern`] warning is
+    /// raised. Other expressions like `all of ($a, $b)` or `#b > 2` are
+    /// not affected.
+    ///
+    /// The analysis takes modifiers into account, and only reports patterns
+    /// that are shadowed for sure. Patterns with `xor`, `base64` or
+    /// `base64wide` modifiers, regular expressions, and hex patterns with
+    /// variable jumps or negated bytes are not analyzed. The default
+    /// setting is `false`.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let src = r#"
+    /// rule test {
+    ///   strings:
+    ///     $a = "malware"
+    ///     $b = "malware detected"
+    ///   condition:
+    ///     any of them
+    /// }"#;
+    ///
+    /// let mut compiler = Compiler::new();
+    /// compiler.detect_shadowed_patterns(true).add_source(src).unwrap();
+    ///
+    /// assert_eq!(compiler.warnings()[0].code(), "shadowed_pattern");
+    /// ```
+    pub fn detect_shadowed_patterns(&mut self, yes: bool) -> &mut Self {
+        self.detect_shadowed_patterns = yes;
+        self
+    }
+
+    /// Ignores the imports of unknown modules, instead of failing.
+    ///
+    /// When enabled, importing a module that doesn't exist raises a
+    /// [`WarningInfo::UnknownModule`] warning instead of an error, and the
+    /// rules in the same namespace that use the module are ignored, raising
+    /// a [`WarningInfo::IgnoredRule`] warning for each of them. Rules that
+    /// depend on ignored rules are ignored too. The remaining rules are
+    /// compiled as usual. This allows using sets of rules where only a few
+    /// of them depend on modules not supported by YARA-X. The default
+    /// setting is `false`.
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, Scanner};
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .ignore_unknown_modules(true)
+    ///     .add_source(r#"
+    ///         import "foo"
+    ///         rule uses_foo { condition: foo.bar == 1 }
+    ///         rule test { condition: true }"#)
+    ///     .unwrap();
+    /// let rules = compiler.build().unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+    /// ```
+    pub fn ignore_unknown_modules(&mut self, yes: bool) -> &mut Self {
+        self.ignore_unknown_modules = yes;
+        self
+    }
+
+    /// Forbids importing the module with the given name.
+    ///
+    /// Importing a banned module produces a [`CompileErrorInfo::BannedModule`]
+    /// error, where `error_title` is the error's title, and `error_message`
+    /// is the label shown at the import statement. This allows restricting
+    /// the modules used by rules that come from untrusted sources, for
+    /// instance because they are too expensive. Banned modules can be
+    /// built-in modules or modules registered with
+    /// [`Compiler::register_module`]. Banning a module that doesn't exist
+    /// has no effect.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let err = Compiler::new()
+    ///     .ban_module("hash", "forbidden module", "`hash` is too expensive")
+    ///     .add_source(r#"import "hash""#)
+    ///     .unwrap_err();
+    ///
+    /// assert_eq!(err.to_string(), "forbidden module");
+    /// ```
+    pub fn ban_module(
+        &mut self,
+        name: &str,
+        error_title: &str,
+        error_message: &str,
+    ) -> &mut Self {
+        self.banned_modules.insert(
+            name.to_string(),
+            (error_title.to_string(), error_message.to_string()),
+        );
+        self
+    }
+
+    /// Sets the maximum number of rules.
+    ///
+    /// Adding a rule when the compiler already has `n` rules produces a
+    /// [`CompileErrorInfo::TooManyRules`] error. This, together with
+    /// [`Compiler::set_max_patterns_per_rule`] and
+    /// [`Compiler::set_max_total_patterns`], bounds the resources used when
+    /// compiling rules from untrusted sources. Rules that are ignored, or
+    /// that were discarded because of errors, don't count. By default, and
+    /// for values larger than that, the limit is the maximum number of rules
+    /// that the compiler supports, which is `i32::MAX`.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let err = Compiler::new()
+    ///     .set_max_rules(1)
+    ///     .add_source("rule foo { condition: true } rule bar { condition: true }")
+    ///     .unwrap_err();
+    ///
+    /// assert_eq!(err.to_string(), "too many rules");
+    /// ```
+    pub fn set_max_rules(&mut self, n: usize) -> &mut Self {
+        self.max_rules = n.min(MAX_RULES);
+        self
+    }
+
+    /// Sets the maximum number of patterns in a single rule.
+    ///
+    /// Rules with more than `n` patterns produce a
+    /// [`CompileErrorInfo::TooManyPatternsInRule`] error. By default there's
+    /// no limit other than the maximum number of patterns in all rules. See
+    /// [`Compiler::set_max_rules`].
+    pub fn set_max_patterns_per_rule(&mut self, n: usize) -> &mut Self {
+        self.max_patterns_per_rule = n.min(MAX_PATTERNS);
+        self
+    }
+
+    /// Sets the maximum number of patterns in all rules.
+    ///
+    /// Adding a rule that makes the total number of patterns exceed `n`
+    /// produces a [`CompileErrorInfo::TooManyPatterns`] error. By default,
+    /// and for values larger than that, the limit is the maximum number of
+    /// patterns that the compiler supports, which is `i32::MAX`. See
+    /// [`Compiler::set_max_rules`].
+    pub fn set_max_total_patterns(&mut self, n: usize) -> &mut Self {
+        self.max_total_patterns = n.min(MAX_PATTERNS);
+        self
+    }
+
+    /// Enables or disables include statements.
+    ///
+    /// When disabled, source codes containing `include` statements are
+    /// rejected with a [`CompileErrorInfo::IncludesNotAllowed`] error. This
+    /// is useful in sandboxed environments, where rules must not read
+    /// arbitrary files. The default setting is `true`.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let err = Compiler::new()
+    ///     .enable_includes(false)
+    ///     .add_source(r#"include "other.yar""#)
+    ///     .unwrap_err();
+    ///
+    /// assert_eq!(err.to_string(), "includes are not allowed");
+    /// ```
+    pub fn enable_includes(&mut self, yes: bool) -> &mut Self {
+        self.includes_enabled = yes;
+        self
+    }
+
+    /// Sets a callback that returns the content of included files.
+    ///
+    /// The callback receives the file name in the `include` statement and
+    /// the origin of the source code that contains it, if any. By default,
+    /// included files are read with the [`FileReader`] set with
+    /// [`Compiler::set_file_reader`], and relative paths are
+    /// relative to the directory of the including source's origin, or to
+    /// the current directory when the source has no origin.
+    ///
+    /// Included files are compiled into the same namespace as the source
+    /// that includes them, at the position of the `include` statement, as
+    /// in YARA. Rules in the included file can use the rules declared
+    /// before the `include` statement, and the rules after it can use the
+    /// ones in the included file. Pragmas and default metadata don't cross
+    /// the boundaries of a file, though, they only apply to the file that
+    /// contains them.
+    ///
+    /// The origin of an included source is the path of the file, so errors
+    /// and warnings in it refer to that path. Including a file that is
+    /// already being included produces a
+    /// [`CompileErrorInfo::CircularInclude`] error, nesting includes more
+    /// than [`MAX_INCLUDE_DEPTH`] levels deep produces a
+    /// [`CompileErrorInfo::TooManyNestedIncludes`] error, and errors
+    /// returned by the callback produce a [`CompileErrorInfo::IncludeError`].
+    ///
+    /// ```
+    /// # use std::io;
+    /// # use yara_x::Compiler;
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .set_include_resolver(|file_name, _| match file_name {
+    ///         "foo.yar" => Ok(b"rule foo { condition: true }".to_vec()),
+    ///         _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+    ///     })
+    ///     .add_source(r#"
+    ///         include "foo.yar"
+    ///         rule bar { condition: foo }"#)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(compiler.build().unwrap().iter().count(), 2);
+    /// ```
+    pub fn set_include_resolver<F>(&mut self, resolver: F) -> &mut Self
+    where
+        F: FnMut(&str, Option<&Path>) -> io::Result<Vec<u8>> + 'a,
+    {
+        self.include_resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Sets the [`FileReader`] used for reading source files.
+    ///
+    /// The reader is used by [`Compiler::add_source_file`] and
+    /// [`Compiler::add_source_dir`], and for reading included files unless
+    /// a callback was set with [`Compiler::set_include_resolver`]. By
+    /// default this is [`OsFileReader`], which reads from the file system.
+    pub fn set_file_reader<R>(&mut self, reader: R) -> &mut Self
+    where
+        R: FileReader + 'a,
+    {
+        self.file_reader = Box::new(reader);
+        self
+    }
+
+    /// Turns warnings into errors.
+    ///
+    /// When enabled, [`Compiler::add_source`] fails with one error for each
+    /// warning raised by the source code. The report of each error is the
+    /// report of the corresponding warning, and [`CompileError::info`]
+    /// returns [`CompileErrorInfo::WarningAsError`], which contains the
+    /// identifier of the warning. Warnings disabled with
+    /// [`Compiler::disable_warning`] are not turned into errors. The
+    /// default setting is `false`.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let src = "rule test { condition: 1 }";
+    ///
+    /// assert!(Compiler::new().add_source(src).is_ok());
+    /// assert!(Compiler::new().errors_on_warnings(true).add_source(src).is_err());
+    /// ```
+    pub fn errors_on_warnings(&mut self, yes: bool) -> &mut Self {
+        self.errors_on_warnings = yes;
+        self
+    }
+
+    /// Disables the warnings with the given identifier.
+    ///
+    /// Disabled warnings raised by source code added after calling this
+    /// function are not returned by [`Compiler::warnings`], and they don't
+    /// produce errors when [`Compiler::errors_on_warnings`] is enabled. The identifier is the one returned by [`Warning::code`],
+    /// like `non_boolean_as_boolean`, and [`WarningInfo::CODES`] contains
+    /// all valid identifiers. Unknown identifiers produce
+    /// [`Error::UnknownWarning`].
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .disable_warning("non_boolean_as_boolean")?
+    ///     .add_source("rule test { condition: 1 }")?;
+    ///
+    /// assert!(compiler.warnings().is_empty());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn disable_warning(&mut self, code: &str) -> Result<&mut Self, Error> {
+        let code = WarningInfo::CODES
+            .iter()
+            .find(|c| **c == code)
+            .ok_or_else(|| Error::UnknownWarning(code.to_string()))?;
+
+        self.disabled_warnings.insert(code);
+        Ok(self)
+    }
+
+    /// Sets a naming policy for namespaces, rules, tags and patterns.
+    ///
+    /// The policy is a function that receives the kind of identifier being
+    /// declared, the identifier itself and its span in the source code, and
+    /// decides whether the identifier is acceptable or not. The function is
+    /// called once for every declared identifier. Pattern identifiers
+    /// include the `$` prefix.
+    ///
+    /// Namespaces are not declared in the source code, so they don't have
+    /// a span. The policy is applied to a namespace created with
+    /// [`Compiler::new_namespace`] when the first rule is added to it after
+    /// the policy is set, regardless of whether the namespace was created
+    /// before or after that, and any warning or error is reported at that
+    /// rule. Namespaces without rules are not part of the compiled rules,
+    /// and they are not checked. The default namespace is not checked
+    /// either.
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, IdentifierKind, NamingPolicyVerdict};
+    /// let mut compiler = Compiler::new();
+    /// let result = compiler
+    ///     .naming_policy(|kind, ident, _| {
+    ///         if kind == IdentifierKind::Rule && !ident.starts_with("acme_") {
+    ///             NamingPolicyVerdict::Reject(
+    ///                 "rule names must start with `acme_`".to_string(),
+    ///             )
+    ///         } else {
+    ///             NamingPolicyVerdict::Accept
+    ///         }
+    ///     })
+    ///     .add_source("rule foo { condition: true }");
+    ///
+    /// assert!(result.is_err());
+    /// ```
+    pub fn naming_policy<F>(&mut self, policy: F) -> &mut Self
+    where
+        F: Fn(IdentifierKind, &str, Option<Span>) -> NamingPolicyVerdict + 'a,
+    {
+        self.naming_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Sets a callback that receives the progress of [`Compiler::build`].
+    ///
+    /// The callback is called when each [`BuildPhase`] starts and ends, and
+    /// periodically while the native code is being compiled, which is the
+    /// longest phase for large sets of rules.
+    ///
+    /// ```
+    /// # use yara_x::{BuildPhase, Compiler};
+    /// let mut phases = Vec::new();
+    ///
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .build_progress(|progress| {
+    ///         if progress.fraction == Some(1.0) {
+    ///             phases.push(progress.phase)
+    ///         }
+    ///     })
+    ///     .add_source("rule test { condition: true }")
+    ///     .unwrap();
+    /// compiler.build().unwrap();
+    ///
+    /// assert_eq!(
+    ///     phases,
+    ///     [BuildPhase::WasmFinalization, BuildPhase::NativeCompilation]
+    /// );
+    /// ```
+    pub fn build_progress<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&BuildProgress) + 'a,
+    {
+        self.build_progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets a token that allows cancelling [`Compiler::build`] from another
+    /// thread.
+    ///
+    /// The token is checked between phases, and periodically while the
+    /// native code is being compiled. A cancelled build returns
+    /// [`Error::Cancelled`]. The compilation of native code can't be
+    /// interrupted, it continues in the background and its result is
+    /// discarded. The next build that has a cancellation token or a
+    /// progress callback waits until it finishes before compiling its own
+    /// native code, and it can be cancelled while waiting.
+    ///
+    /// ```
+    /// # use yara_x::{CancellationToken, Compiler, Error};
+    /// let token = CancellationToken::new();
+    ///
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .cancellation_token(token.clone())
+    ///     .add_source("rule test { condition: true }")
+    ///     .unwrap();
+    ///
+    /// token.cancel();
+    ///
+    /// assert!(matches!(compiler.build(), Err(Error::Cancelled)));
+    /// ```
+    pub fn cancellation_token(
+        &mut self,
+        token: CancellationToken,
+    ) -> &mut Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
     /// Creates a new namespace with a given name.
     ///
     /// Further calls to [`Compiler::add_source`] will put the rules under the
-    /// newly created namespace.
+    /// newly created namespace. If the namespace already exists, the rules
+    /// are added to the existing one, and they can use the rules and modules
+    /// that were previously added to it. This includes the default namespace,
+    /// named [`DEFAULT_NAMESPACE`], there's no way of creating a different
+    /// namespace with that name.
     ///
     /// In the example below both rules `foo` and `bar` are put into the same
     /// namespace (the default namespace), therefore `bar` can use `foo` as
@@ -229,87 +976,589 @@ impl<'a> Compiler<'a> {
     ///
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn new_namespace(mut self, namespace: &str) -> Self {
+    pub fn new_namespace(&mut self, namespace: &str) -> &mut Self {
+        let ident_id = self.ident_pool.get_or_intern(namespace);
+
+        if ident_id == self.current_namespace.ident_id {
+            return self;
+        }
+
         // Remove the symbol table corresponding to the previous namespace.
         self.symbol_table.pop().expect("expecting a namespace");
-        // Create a new namespace.
-        self.current_namespace = Namespace {
-            ident_id: self.ident_pool.get_or_intern(namespace),
-            symbols: self.symbol_table.push_new(),
+
+        let existing =
+            self.namespaces.iter().position(|ns| ns.ident_id == ident_id);
+
+        let namespace = if let Some(index) = existing {
+            // The namespace existed, its symbol table becomes visible again.
+            let namespace = self.namespaces.swap_remove(index);
+            self.symbol_table.push(namespace.symbols.clone());
+            namespace
+        } else {
+            // Create a new namespace.
+            Namespace {
+                ident_id,
+                symbols: self.symbol_table.push_new(),
+                naming_checked: false,
+                ignored_identifiers: FxHashMap::default(),
+            }
         };
+
+        let previous = mem::replace(&mut self.current_namespace, namespace);
+        self.namespaces.push(previous);
         self
     }
 
-    /// Adds a YARA source code to be compiled.
+    /// Sets metadata entries that are inherited by every rule in the given
+    /// namespace.
+    ///
+    /// Each compiled rule gets the entries whose identifiers don't appear
+    /// in its own `meta` section, or in the defaults set for its source
+    /// code with [`SourceCode::with_default_meta`]. In other words, the
+    /// rule's own metadata takes precedence over the source code defaults,
+    /// which take precedence over the namespace defaults. Inherited entries
+    /// are included in [`crate::Rule::metadata`] after the rule's own
+    /// entries, and [`crate::Rule::inherited_metadata`] tells which ones
+    /// they are.
+    ///
+    /// The defaults apply to the rules added after calling this function.
+    /// Calling it again for the same namespace adds new entries, or replaces
+    /// the values of existing ones.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .namespace_default_meta("default", [("license", "MIT".into())])
+    ///     .add_source("rule test { condition: true }")?;
+    /// let rules = compiler.build()?;
     ///
-    /// This function can be called multiple times.
-    pub fn add_source<'src, S>(mut self, src: S) -> Result<Self, Error>
+    /// let rule = rules.iter().next().unwrap();
+    /// assert_eq!(rule.inherited_metadata().count(), 1);
+    ///
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn namespace_default_meta<'m, I, K>(
+        &mut self,
+        namespace: &str,
+        meta: I,
+    ) -> &mut Self
     where
-        S: Into<SourceCode<'src>>,
+        I: IntoIterator<Item = (K, ast::MetaValue<'m>)>,
+        K: AsRef<str>,
     {
-        // Convert `src` into an instance of `SourceCode` if it is something
-        // else, like a &str.
-        let src = src.into();
+        let namespace_id = self.ident_pool.get_or_intern(namespace);
+        let mut default_meta = self
+            .namespace_default_meta
+            .remove(&namespace_id)
+            .unwrap_or_default();
+
+        for (identifier, value) in meta {
+            let ident_id = self.ident_pool.get_or_intern(identifier.as_ref());
+            let value = intern_meta_value(&mut self.lit_pool, &value);
+            default_meta.retain(|(i, _)| *i != ident_id);
+            default_meta.push((ident_id, value));
+        }
 
-        // Parse the source code and build the Abstract Syntax Tree.
-        let mut ast = Parser::new()
-            .set_report_builder(&self.report_builder)
-            .build_ast(src.clone())?;
+        self.namespace_default_meta.insert(namespace_id, default_meta);
+        self
+    }
 
-        // Transfer the warnings generated by the parser to the compiler
-        self.warnings.append(&mut ast.warnings);
+    /// Defines a named set of strings that rule conditions can use with the
+    /// `in` operator, as in `pe.dll_name in bad_dlls`.
+    ///
+    /// This is intended for lists that are maintained outside the rules,
+    /// like domain names or mutex names. Each string is stored once in the
+    /// literals pool, and checking whether a string belongs to the set
+    /// takes constant time, regardless of the size of the set. The set must
+    /// be defined before adding the source code that uses it, and it's
+    /// visible from all namespaces. As YARA doesn't support this operator,
+    /// extensions must be enabled with [`Compiler::enable_extensions`].
+    ///
+    /// Defining two sets with the same name is an error.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .enable_extensions(true)
+    ///     .define_string_set("bad_domains", ["evil.com", "bad.net"])?
+    ///     .add_source(r#"rule test { condition: "bad.net" in bad_domains }"#)?;
+    /// let rules = compiler.build()?;
+    ///
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn define_string_set<I, S>(
+        &mut self,
+        name: &str,
+        items: I,
+    ) -> Result<&mut Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        if self
+            .string_sets
+            .iter()
+            .any(|set| self.ident_pool.get(set.ident_id) == Some(name))
+        {
+            return Err(Error::DuplicateStringSet(name.to_string()));
+        }
 
-        for ns in ast.namespaces.iter_mut() {
-            // Process import statements. Checks that all imported modules
-            // actually exist, and raise warnings in case of duplicated
-            // imports within the same source file. For each module add a
-            // symbol to the current namespace.
-            self.process_imports(&src, &ns.imports)?;
+        let literals = items
+            .into_iter()
+            .map(|item| self.lit_pool.get_or_intern(item))
+            .collect();
 
-            // Iterate over the list of declared rules and verify that their
-            // conditions are semantically valid. For each rule add a symbol
-            // to the current namespace.
-            for rule in ns.rules.iter_mut() {
-                self.process_rule(&src, rule)?;
-            }
-        }
+        self.string_sets.push(StringSet {
+            ident_id: self.ident_pool.get_or_intern(name),
+            literals,
+        });
 
         Ok(self)
     }
 
-    /// Builds the source code previously added to the compiler.
+    /// Defines a global variable that rule conditions can use like any
+    /// other identifier.
     ///
-    /// This function consumes the compiler and returns an instance of
-    /// [`Rules`].
-    pub fn build(self) -> Result<Rules, Error> {
-        // Finish building the WASM module.
-        let mut wasm_mod = self.wasm_mod.build();
-
-        // Compile the WASM module for the current platform. This panics
+    /// The type of the variable is the type of `value`, which is also the
+    /// variable's default value. Each scanner can change the value with
+    /// [`crate::Scanner::set_global`], but not its type. The variable must
+    /// be defined before adding the source code that uses it, and it's
+    /// visible from all namespaces.
+    ///
+    /// Defining a variable with the name of another variable, a module or
+    /// a built-in function is an error.
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, Scanner};
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .define_global("max_size", 100)?
+    ///     .add_source("rule test { condition: filesize < max_size }")?;
+    /// let rules = compiler.build()?;
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// assert_eq!(scanner.scan(&[0; 10]).num_matching_rules(), 1);
+    ///
+    /// scanner.set_global("max_size", 5)?;
+    /// assert_eq!(scanner.scan(&[0; 10]).num_matching_rules(), 0);
+    ///
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn define_global<T: Into<Variable>>(
+        &mut self,
+        ident: &str,
+        value: T,
+    ) -> Result<&mut Self, Error> {
+        let mut chars = ident.chars();
+
+        let is_valid =
+            chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !is_valid {
+            return Err(Error::InvalidGlobal(ident.to_string()));
+        }
+
+        if self.global_symbols.borrow().lookup(ident).is_some()
+            || self.modules_struct.field_by_name(ident).is_some()
+        {
+            return Err(Error::DuplicateGlobal(ident.to_string()));
+        }
+
+        let type_value: TypeValue = value.into().into();
+
+        // The default value is stored in the structure that becomes the
+        // root structure of each scanner, while the symbol doesn't have a
+        // value. Otherwise, the value would be treated as a constant and
+        // folded into the conditions at compile time.
+        let mut symbol = Symbol::new(type_value.clone_without_value());
+
+        self.modules_struct.add_field(ident, type_value);
+
+        symbol.kind = SymbolKind::FieldIndex(
+            self.modules_struct.field_by_name(ident).unwrap().index as i32,
+        );
+
+        self.global_symbols.borrow_mut().insert(ident, symbol);
+
+        Ok(self)
+    }
+
+    /// Registers a module that rules can import like any built-in module.
+    ///
+    /// The module's structure is described by `descriptor`, which can come
+    /// from code generated for a .proto file, or from a descriptor built at
+    /// runtime with [`protobuf::reflect::FileDescriptor::new_dynamic`]. The
+    /// data for each scan is provided by the handler passed to
+    /// [`crate::Scanner::set_module_handler`]. Registered modules don't
+    /// export functions.
+    ///
+    /// Registering a module with the name of a built-in module, or with the
+    /// name of an already registered module, is an error. Descriptors that
+    /// use protobuf features not supported by YARA, like messages that
+    /// contain themselves, are rejected with [`Error::UnsupportedModule`].
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, Scanner};
+    /// use yara_x::protobuf::well_known_types::timestamp::Timestamp;
+    /// use yara_x::protobuf::MessageFull;
+    ///
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .register_module("clock", Timestamp::descriptor())?
+    ///     .add_source(r#"
+    ///         import "clock"
+    ///         rule test { condition: clock.seconds == 1000 }"#)?;
+    /// let rules = compiler.build()?;
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    ///
+    /// scanner.set_module_handler("clock", |_data| {
+    ///     let mut timestamp = Timestamp::new();
+    ///     timestamp.seconds = 1000;
+    ///     Box::new(timestamp)
+    /// });
+    ///
+    /// assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn register_module(
+        &mut self,
+        name: &str,
+        descriptor: MessageDescriptor,
+    ) -> Result<&mut Self, Error> {
+        if BUILTIN_MODULES.contains_key(name)
+            || self.user_modules.contains_key(name)
+        {
+            return Err(Error::DuplicateModule(name.to_string()));
+        }
+
+        if let Err(reason) = Struct::check_proto_descriptor(&descriptor) {
+            return Err(Error::UnsupportedModule {
+                name: name.to_string(),
+                reason,
+            });
+        }
+
+        self.user_modules.insert(name.to_string(), descriptor);
+
+        Ok(self)
+    }
+
+    /// Adds a YARA source code to be compiled.
+    ///
+    /// This function can be called multiple times. If the source code has
+    /// errors, none of its rules are added, but the compiler keeps the rules
+    /// and warnings from previous source codes, as well as the warnings
+    /// raised by the failed source code, so it can still be used. Files
+    /// included with `include` statements are part of the source code, see
+    /// [`Compiler::set_include_resolver`].
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let mut compiler = Compiler::new();
+    ///
+    /// compiler.add_source("rule foo { condition: 1 }")?;
+    /// assert!(compiler.add_source("rule bar { condition: baz }").is_err());
+    ///
+    /// // The warning raised by `foo` is still there.
+    /// assert_eq!(compiler.warnings().len(), 1);
+    ///
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn add_source<'src, S>(&mut self, src: S) -> Result<&mut Self, Error>
+    where
+        S: Into<SourceCode<'src>>,
+    {
+        let snapshot = self.take_source_snapshot();
+
+        if let Err(err) = self.compile_source(src.into()) {
+            self.restore_source_snapshot(snapshot);
+            return Err(err);
+        }
+
+        Ok(self)
+    }
+
+    /// Adds the YARA source code in a file.
+    ///
+    /// The origin of the source code is the file's path, so errors and
+    /// warnings refer to it. The content is passed to the parser as raw
+    /// bytes, without checking that it's valid UTF-8 first, so invalid
+    /// UTF-8 is reported as a parser error that points to the offending
+    /// bytes. Files that can't be read produce an [`Error::OpenError`].
+    ///
+    /// The file is read with the reader set with
+    /// [`Compiler::set_file_reader`].
+    pub fn add_source_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<&mut Self, Error> {
+        let path = path.as_ref();
+        let src = self.file_reader.read(path).map_err(|err| {
+            Error::OpenError { path: path.to_path_buf(), source: err }
+        })?;
+        self.add_source(
+            SourceCode::from(src.as_slice()).origin(&path.to_string_lossy()),
+        )
+    }
+
+    /// Adds the YARA source code in every `*.yar` and `*.yara` file in a
+    /// directory.
+    ///
+    /// Files are added with [`Compiler::add_source_file`] in the order of
+    /// their paths, so the rules get the same IDs every time the same
+    /// directory is compiled. If `recursive` is true, files in
+    /// subdirectories are added too, symbolic links to directories are not
+    /// followed.
+    ///
+    /// Files with errors don't stop the compilation of the remaining ones.
+    /// Their errors are returned together once all the files were added,
+    /// while the rules in the remaining files are kept. Errors while
+    /// reading the directory or its files are returned immediately.
+    pub fn add_source_dir<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recursive: bool,
+    ) -> Result<&mut Self, Error> {
+        let mut files = Vec::new();
+        source_files(
+            self.file_reader.as_mut(),
+            path.as_ref(),
+            recursive,
+            &mut files,
+        )?;
+        files.sort();
+
+        let mut errors = Vec::new();
+
+        for file in files {
+            match self.add_source_file(&file) {
+                Ok(_) => {}
+                Err(Error::ParseError(err)) => {
+                    errors.push(CompileError::from_parse_error(
+                        &err,
+                        Some(&file.to_string_lossy()),
+                    ))
+                }
+                Err(Error::CompileError(err)) => errors.push(err),
+                Err(Error::CompileErrors(errs)) => {
+                    errors.extend(errs.into_vec())
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        match errors.len() {
+            0 => Ok(self),
+            1 => Err(Error::CompileError(errors.pop().unwrap())),
+            _ => Err(Error::CompileErrors(CompileErrors::new(errors))),
+        }
+    }
+
+    /// Returns the warnings raised by the source code added so far.
+    pub fn warnings(&self) -> &[Warning] {
+        self.warnings.as_slice()
+    }
+
+    /// Builds the source code previously added to the compiler.
+    ///
+    /// This function consumes the compiler and returns an instance of
+    /// [`Rules`]. The progress can be followed with
+    /// [`Compiler::build_progress`], and the build can be cancelled with
+    /// [`Compiler::cancellation_token`].
+    pub fn build(mut self) -> Result<Rules, Error> {
+        let cancellation_token = self.cancellation_token.take();
+        let mut build_progress = self.build_progress.take();
+        let in_background =
+            cancellation_token.is_some() || build_progress.is_some();
+
+        let is_cancelled =
+            || cancellation_token.as_ref().is_some_and(|t| t.is_cancelled());
+
+        let mut report_progress = |phase, fraction| {
+            if let Some(callback) = build_progress.as_mut() {
+                callback(&BuildProgress { phase, fraction })
+            }
+        };
+
+        if is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        report_progress(BuildPhase::WasmFinalization, Some(0.0));
+
+        // Finish building the WASM module.
+        let mut wasm_mod = self.wasm_mod.build();
+        let wasm_binary = wasm_mod.emit_wasm();
+
+        report_progress(BuildPhase::WasmFinalization, Some(1.0));
+
+        if is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        report_progress(BuildPhase::NativeCompilation, Some(0.0));
+
+        // Compile the WASM module for the current platform. This panics
         // if the WASM code is invalid, which should not happen as the code is
         // emitted by YARA itself. If this ever happens is probably because
         // wrong WASM code is being emitted.
-        let compiled_wasm_mod = wasmtime::Module::from_binary(
-            &crate::wasm::ENGINE,
-            wasm_mod.emit_wasm().as_slice(),
-        )
-        .expect("WASM module is not valid");
+        let compile = move || {
+            wasmtime::Module::from_binary(
+                &crate::wasm::ENGINE,
+                wasm_binary.as_slice(),
+            )
+            .expect("WASM module is not valid")
+        };
+
+        let compiled_wasm_mod = if !in_background {
+            compile()
+        } else {
+            // wasmtime doesn't report its progress, nor can it be
+            // interrupted, so the compilation runs in a separate thread while
+            // this one reports the progress and checks for cancellation. If
+            // the build is cancelled the thread is kept running until the
+            // compilation finishes, and the next build waits for it, so
+            // cancelled builds don't pile up threads compiling native code.
+            loop {
+                let mut cancelled = CANCELLED_COMPILATIONS.lock().unwrap();
+                let (finished, running): (Vec<_>, Vec<_>) =
+                    cancelled.drain(..).partition(|t| t.is_finished());
+                let waiting = !running.is_empty();
+                *cancelled = running;
+                drop(cancelled);
+
+                // The result of cancelled builds is not used, not even if
+                // they panicked.
+                for thread in finished {
+                    let _ = thread.join();
+                }
+
+                if !waiting {
+                    break;
+                }
+                if is_cancelled() {
+                    return Err(Error::Cancelled);
+                }
+                report_progress(BuildPhase::NativeCompilation, None);
+                thread::sleep(BUILD_POLLING_INTERVAL);
+            }
+
+            let (sender, receiver) = mpsc::channel();
+
+            let thread = thread::spawn(move || {
+                let _ = sender.send(compile());
+            });
+
+            loop {
+                match receiver.recv_timeout(BUILD_POLLING_INTERVAL) {
+                    Ok(compiled_wasm_mod) => {
+                        let _ = thread.join();
+                        break compiled_wasm_mod;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        if is_cancelled() {
+                            CANCELLED_COMPILATIONS
+                                .lock()
+                                .unwrap()
+                                .push(thread);
+                            return Err(Error::Cancelled);
+                        }
+                        report_progress(BuildPhase::NativeCompilation, None);
+                    }
+                    // The sender is dropped without sending anything only
+                    // if the compilation panicked.
+                    Err(RecvTimeoutError::Disconnected) => {
+                        match thread.join() {
+                            Err(panic) => panic::resume_unwind(panic),
+                            Ok(_) => unreachable!(),
+                        }
+                    }
+                }
+            }
+        };
+
+        // The build may have been cancelled while the compilation was
+        // finishing.
+        if is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        report_progress(BuildPhase::NativeCompilation, Some(1.0));
 
         // Build the Aho-Corasick automaton used while searching for the atoms
         // in the scanned data.
         let ac = AhoCorasick::new(self.atoms.iter().map(|x| &x.atom));
 
+        // Disabled rules never match, if they were taken into account as
+        // global rules they would suppress every rule in their namespaces.
+        let global_rules = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.global && !rule.disabled)
+            .map(|(i, _)| RuleId(i as i32))
+            .collect();
+
+        let rules_by_name = self
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                ((rule.namespace_id, rule.ident_id), RuleId(i as i32))
+            })
+            .collect();
+
+        let mut disabled_warnings: Vec<_> = self
+            .disabled_warnings
+            .iter()
+            .map(|code| code.to_string())
+            .collect();
+
+        disabled_warnings.sort();
+
+        let build_options = BuildOptions {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            compile_time_optimization: cfg!(
+                feature = "compile-time-optimization"
+            ),
+            extensions: self.extensions.unwrap_or(false),
+            force_enable_rules: self.force_enable_rules,
+            errors_on_warnings: self.errors_on_warnings,
+            detect_shadowed_patterns: self.detect_shadowed_patterns,
+            naming_policy: self.naming_policy.is_some(),
+            disabled_warnings,
+            string_sets: self
+                .string_sets
+                .iter()
+                .map(|set| {
+                    self.ident_pool.get(set.ident_id).unwrap().to_string()
+                })
+                .collect(),
+        };
+
         Ok(Rules {
             ac,
             compiled_wasm_mod,
             wasm_mod,
             num_patterns: self.next_pattern_id as usize,
+            all_matches_required: self.all_matches_required,
             ident_pool: self.ident_pool,
             lit_pool: self.lit_pool,
             imported_modules: self.imported_modules,
+            globals_struct: self.modules_struct,
+            user_modules: self.user_modules,
+            string_sets: self.string_sets,
+            regexps: self.regexps,
             rules: self.rules,
+            rules_by_name,
+            global_rules,
             sub_patterns: self.sub_patterns,
             atoms: self.atoms,
+            build_options,
         })
     }
 
@@ -323,11 +1572,455 @@ impl<'a> Compiler<'a> {
         P: AsRef<Path>,
     {
         let mut wasm_mod = self.wasm_mod.build();
-        Ok(wasm_mod.emit_wasm_file(path)?)
+        Ok(std::fs::write(path, wasm_mod.emit_wasm())?)
+    }
+
+    /// Returns the WASM module generated by the compiler in WebAssembly
+    /// text format (WAT).
+    ///
+    /// Functions are referenced by name, and the condition of each rule is
+    /// evaluated by a function named after the rule's namespace and
+    /// identifier (e.g: `func $default.foo`). Like
+    /// [`Compiler::emit_wasm_file`], this is intended for debugging the
+    /// emitted code.
+    pub fn emit_wat(self) -> String {
+        self.wasm_mod.build_wat()
+    }
+
+    /// Writes a `.wat` file with the WASM module generated by the compiler
+    /// in text format. See [`Compiler::emit_wat`].
+    pub fn emit_wat_file<P>(self, path: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(std::fs::write(path, self.emit_wat())?)
     }
 }
 
 impl<'a> Compiler<'a> {
+    /// Compiles a source code, see [`Compiler::add_source`].
+    fn compile_source(&mut self, src: SourceCode) -> Result<(), Error> {
+        // Parse the source code and build the Abstract Syntax Tree.
+        let mut ast = Parser::new()
+            .set_report_builder(&self.report_builder)
+            .build_ast(src.clone())?;
+
+        self.current_origin = src
+            .get_origin()
+            .map(|origin| self.ident_pool.get_or_intern(origin));
+
+        // Options set by pragmas apply only to the source that contains
+        // them, the ones set by the previous source are discarded.
+        self.source_extensions = false;
+        self.source_disabled_warnings.clear();
+
+        self.current_default_meta = src
+            .get_default_meta()
+            .iter()
+            .map(|(identifier, value)| {
+                (
+                    self.ident_pool.get_or_intern(identifier),
+                    intern_meta_value(&mut self.lit_pool, value),
+                )
+            })
+            .collect();
+
+        self.first_source_warning = self.warnings.len();
+
+        let num_parser_warnings = ast.warnings.len();
+
+        // Transfer the warnings generated by the parser to the compiler
+        self.warnings.append(&mut ast.warnings);
+
+        // Errors returned by `process_namespaces` as `Err` don't need to
+        // be rendered, they are either rendered already, like the ones in
+        // included sources, or are not compile errors.
+        let result = self
+            .process_pragmas(&ast.pragmas)
+            .map_err(|err| err.render(&self.report_builder, &src))
+            .and_then(|_| {
+                self.process_namespaces(ast.namespaces.as_mut_slice(), &src)
+            });
+
+        // Warnings raised by included sources are placed before the ones
+        // raised by this source, which start at `first_source_warning`.
+        let first_parser_warning = self.first_source_warning;
+        let first_warning = first_parser_warning + num_parser_warnings;
+
+        // Private rules can be referenced only by rules that come after
+        // them in the same source, any private rule that remains unused
+        // at this point won't have any effect.
+        for (rule_id, span) in mem::take(&mut self.unused_private_rules) {
+            if matches!(&result, Ok(errors) if errors.is_empty()) {
+                self.warnings.push(
+                    WarningInfo::unused_private_rule(
+                        self.ident_pool
+                            .get(self.rules[rule_id.0 as usize].ident_id)
+                            .unwrap()
+                            .to_string(),
+                        span,
+                        Some(
+                            "private rules are not included in scan results"
+                                .to_string(),
+                        ),
+                    )
+                    .into(),
+                );
+            }
+        }
+
+        // The same applies to imported modules, they are visible to the
+        // rules in other sources added to the same namespace, but those
+        // sources should import the modules too.
+        for (module, span) in mem::take(&mut self.unused_imports) {
+            if matches!(&result, Ok(errors) if errors.is_empty()) {
+                self.warnings
+                    .push(WarningInfo::unused_import(module, span).into());
+            }
+        }
+
+        // Errors and warnings produced while compiling the AST don't have
+        // a report yet, it is rendered here, where the source code is known.
+        for warning in self.warnings[first_warning..].iter_mut() {
+            warning.render(&self.report_builder, &src);
+        }
+
+        let mut errors: Vec<CompileError> = result?
+            .into_iter()
+            .map(|err| err.render(&self.report_builder, &src))
+            .collect();
+
+        // Drop the new warnings that are disabled.
+        let new_warnings = self.warnings.split_off(first_parser_warning);
+
+        self.warnings.extend(new_warnings.into_iter().filter(|warning| {
+            !self.disabled_warnings.contains(warning.code())
+                && !self.source_disabled_warnings.contains(warning.code())
+        }));
+
+        if self.errors_on_warnings && errors.is_empty() {
+            errors = self.warnings[first_parser_warning..]
+                .iter()
+                .map(|warning| {
+                    CompileError::from_warning(
+                        warning,
+                        &self.report_builder,
+                        &src,
+                    )
+                })
+                .collect();
+        }
+
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(Error::CompileError(errors.pop().unwrap())),
+            _ => Err(Error::CompileErrors(CompileErrors::new(errors))),
+        }
+    }
+
+    /// Compiles the source included by an `include` statement in `src`.
+    fn process_include(
+        &mut self,
+        include: &Include,
+        src: &SourceCode,
+    ) -> Result<(), Error> {
+        if let Some(origin) = src.get_origin() {
+            self.include_stack.push(origin.to_string());
+        }
+
+        let result = self.compile_include(include, src);
+
+        if src.get_origin().is_some() {
+            self.include_stack.pop();
+        }
+
+        result
+    }
+
+    /// Reads and compiles the source included by `include`, see
+    /// [`Compiler::process_include`].
+    fn compile_include(
+        &mut self,
+        include: &Include,
+        src: &SourceCode,
+    ) -> Result<(), Error> {
+        // Errors in the included source are rendered by `compile_source`,
+        // the ones produced here are rendered with the including source.
+        let error = |info| Error::from(info).render(&self.report_builder, src);
+
+        if !self.includes_enabled {
+            return Err(error(CompileErrorInfo::includes_not_allowed(
+                include.span(),
+            )));
+        }
+
+        let includer = src.get_origin().map(Path::new);
+        let path = include_path(&include.file_name, includer);
+        let origin = path.to_string_lossy().into_owned();
+
+        // Cycles are detected by comparing paths, but a resolver can map
+        // different paths to the same content, like `a/b.yar` including
+        // `a/b.yar`, which resolves to `a/a/b.yar`, and so on.
+        if self.include_depth >= MAX_INCLUDE_DEPTH {
+            return Err(error(CompileErrorInfo::too_many_nested_includes(
+                MAX_INCLUDE_DEPTH,
+                include.span(),
+            )));
+        }
+
+        if let Some(pos) = self.include_stack.iter().position(|o| o == &origin)
+        {
+            let mut chain = self.include_stack[pos..].to_vec();
+            chain.push(origin);
+            return Err(error(CompileErrorInfo::circular_include(
+                include.file_name.clone(),
+                include.span(),
+                Some(format!("include chain: {}", chain.join(" -> "))),
+            )));
+        }
+
+        let content = match self.include_resolver.as_mut() {
+            Some(resolver) => resolver(&include.file_name, includer),
+            None => self.file_reader.read(&path),
+        };
+
+        let content = content.map_err(|err| {
+            error(CompileErrorInfo::include_error(
+                include.file_name.clone(),
+                err.to_string(),
+                include.span(),
+            ))
+        })?;
+
+        // The included source is compiled in the middle of this one, so
+        // the state of this source is put aside meanwhile, including its
+        // warnings, which must come after the ones in the included source.
+        let pending_warnings =
+            self.warnings.split_off(self.first_source_warning);
+        let state = self.take_source_state();
+
+        self.include_depth += 1;
+
+        let result = self.compile_source(
+            SourceCode::from(content.as_slice()).origin(&origin),
+        );
+
+        self.include_depth -= 1;
+
+        self.restore_source_state(state);
+        self.first_source_warning = self.warnings.len();
+        self.warnings.extend(pending_warnings);
+
+        result
+    }
+
+    /// Takes the state that is specific to the source being compiled,
+    /// leaving the compiler ready for compiling another source.
+    fn take_source_state(&mut self) -> SourceState {
+        SourceState {
+            current_origin: self.current_origin.take(),
+            source_extensions: mem::take(&mut self.source_extensions),
+            source_disabled_warnings: mem::take(
+                &mut self.source_disabled_warnings,
+            ),
+            current_default_meta: mem::take(&mut self.current_default_meta),
+            unused_private_rules: mem::take(&mut self.unused_private_rules),
+            unused_imports: mem::take(&mut self.unused_imports),
+            upcoming_rules: mem::take(&mut self.upcoming_rules),
+            first_source_warning: self.first_source_warning,
+        }
+    }
+
+    /// Restores the state taken by [`Compiler::take_source_state`].
+    fn restore_source_state(&mut self, state: SourceState) {
+        self.current_origin = state.current_origin;
+        self.source_extensions = state.source_extensions;
+        self.source_disabled_warnings = state.source_disabled_warnings;
+        self.current_default_meta = state.current_default_meta;
+        self.unused_private_rules = state.unused_private_rules;
+        self.unused_imports = state.unused_imports;
+        self.upcoming_rules = state.upcoming_rules;
+        self.first_source_warning = state.first_source_warning;
+    }
+
+    /// Returns the state of the compiler that [`Compiler::compile_source`]
+    /// modifies while adding a source code.
+    fn take_source_snapshot(&mut self) -> SourceSnapshot {
+        SourceSnapshot {
+            rules: self.take_snapshot(),
+            num_imported_modules: self.imported_modules.len(),
+            num_modules: self.modules_struct.len(),
+            num_rule_fns: self.wasm_mod.num_rule_fns(),
+            symbol_table_len: self.symbol_table.len(),
+            symbols: self.current_namespace.symbols.borrow().clone(),
+            ignored_identifiers: self
+                .current_namespace
+                .ignored_identifiers
+                .clone(),
+            naming_checked: self.current_namespace.naming_checked,
+        }
+    }
+
+    /// Discards the rules, imports and code added by a source code with
+    /// errors. Warnings are kept.
+    fn restore_source_snapshot(&mut self, snapshot: SourceSnapshot) {
+        self.next_pattern_id = snapshot.rules.next_pattern_id;
+        self.all_matches_required
+            .truncate(snapshot.rules.next_pattern_id as usize);
+        self.rules.truncate(snapshot.rules.num_rules);
+        self.sub_patterns.truncate(snapshot.rules.num_sub_patterns);
+        self.atoms.truncate(snapshot.rules.num_atoms);
+        self.regexps.truncate(snapshot.rules.num_regexps);
+        self.imported_modules.truncate(snapshot.num_imported_modules);
+        self.modules_struct.truncate(snapshot.num_modules);
+        self.wasm_mod.truncate_rule_fns(snapshot.num_rule_fns);
+        self.symbol_table.truncate(snapshot.symbol_table_len);
+        *self.current_namespace.symbols.borrow_mut() = snapshot.symbols;
+        self.current_namespace.ignored_identifiers =
+            snapshot.ignored_identifiers;
+        self.current_namespace.naming_checked = snapshot.naming_checked;
+        self.unused_private_rules.clear();
+        self.unused_imports.clear();
+    }
+
+    /// Processes the namespaces in the AST, returning the compile errors
+    /// found in them.
+    ///
+    /// A compile error in a rule doesn't prevent the remaining rules from
+    /// being processed, but errors in import statements do, as they would
+    /// cause spurious errors in the rules that use the imported modules.
+    /// Included sources are compiled at the position of their `include`
+    /// statements, and an error in any of them is returned as `Err`, like
+    /// any other error that is not a [`CompileError`].
+    fn process_namespaces(
+        &mut self,
+        namespaces: &mut [ast::Namespace],
+        src: &SourceCode,
+    ) -> Result<Vec<CompileError>, Error> {
+        let mut errors = Vec::new();
+
+        for ns in namespaces.iter_mut() {
+            // Process import statements. Checks that all imported modules
+            // actually exist, and keeps track of the ones that are not used
+            // by any rule. Duplicated imports within the same source file
+            // are reported by the parser. For each module add a symbol to
+            // the current namespace.
+            match self.process_imports(&ns.imports) {
+                Ok(()) => {}
+                Err(Error::CompileError(err)) => {
+                    errors.push(err);
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+
+            // Iterate over the list of declared rules and verify that their
+            // conditions are semantically valid. For each rule add a symbol
+            // to the current namespace. A rule with errors is still declared,
+            // so other rules can reference it without producing additional
+            // errors.
+            self.upcoming_rules = ns
+                .rules
+                .iter()
+                .map(|rule| {
+                    (rule.identifier.name.to_string(), rule.identifier.span)
+                })
+                .collect();
+
+            let mut includes = ns.includes.iter().peekable();
+
+            for rule in ns.rules.iter_mut() {
+                while let Some(include) =
+                    includes.next_if(|i| i.span.start() < rule.span.start())
+                {
+                    self.process_include(include, src)?;
+                }
+                self.upcoming_rules.remove(rule.identifier.name);
+                // A rule with errors may leave the symbol tables created
+                // while checking its condition (e.g: the ones containing loop
+                // variables) in the stack, they are removed here.
+                let symbol_table_len = self.symbol_table.len();
+                let snapshot = self.take_snapshot();
+                match self.process_rule(rule, src.as_bytes()) {
+                    Ok(()) => {}
+                    Err(Error::CompileError(err)) => {
+                        self.symbol_table.truncate(symbol_table_len);
+                        // Rules that use an unknown module that was ignored
+                        // are removed, as if they were never added.
+                        if let Some(module) = self.ignored_module(&err) {
+                            self.restore_snapshot(snapshot, rule);
+                            self.warnings.push(
+                                WarningInfo::ignored_rule(
+                                    rule.identifier.name.to_string(),
+                                    module.clone(),
+                                    rule.identifier.span,
+                                )
+                                .into(),
+                            );
+                            self.current_namespace.ignored_identifiers.insert(
+                                rule.identifier.name.to_string(),
+                                module,
+                            );
+                        } else {
+                            errors.push(err);
+                        }
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+
+            for include in includes {
+                self.process_include(include, src)?;
+            }
+        }
+
+        Ok(errors)
+    }
+
+    /// Returns the state of the compiler that [`Compiler::process_rule`]
+    /// modifies before checking the rule's condition.
+    fn take_snapshot(&self) -> Snapshot {
+        Snapshot {
+            next_pattern_id: self.next_pattern_id,
+            num_rules: self.rules.len(),
+            num_sub_patterns: self.sub_patterns.len(),
+            num_atoms: self.atoms.len(),
+            num_regexps: self.regexps.len(),
+        }
+    }
+
+    /// Removes the rule that was being processed when the snapshot was
+    /// taken. The rule must have failed the checks of its condition, so
+    /// its code was not emitted.
+    fn restore_snapshot(&mut self, snapshot: Snapshot, rule: &ast::Rule) {
+        self.next_pattern_id = snapshot.next_pattern_id;
+        self.all_matches_required.truncate(snapshot.next_pattern_id as usize);
+        self.rules.truncate(snapshot.num_rules);
+        self.sub_patterns.truncate(snapshot.num_sub_patterns);
+        self.atoms.truncate(snapshot.num_atoms);
+        self.regexps.truncate(snapshot.num_regexps);
+        self.unused_private_rules
+            .retain(|(rule_id, _)| (rule_id.0 as usize) < snapshot.num_rules);
+        self.current_namespace
+            .symbols
+            .borrow_mut()
+            .remove(rule.identifier.name);
+    }
+
+    /// If `err` is caused by an identifier that was ignored by
+    /// [`Compiler::ignore_unknown_modules`], returns the name of the unknown
+    /// module that caused it.
+    fn ignored_module(&self, err: &CompileError) -> Option<String> {
+        match err.info() {
+            CompileErrorInfo::UnknownIdentifier { identifier, .. } => self
+                .current_namespace
+                .ignored_identifiers
+                .get(identifier)
+                .cloned(),
+            _ => None,
+        }
+    }
+
     #[inline]
     fn push_sub_pattern(&mut self, sub_pattern: SubPattern) -> SubPatternId {
         let id = self.sub_patterns.len();
@@ -337,9 +2030,61 @@ impl<'a> Compiler<'a> {
 
     fn process_rule(
         &mut self,
-        src: &SourceCode,
         rule: &mut ast::Rule,
+        src: &[u8],
     ) -> Result<(), Error> {
+        self.check_duplicate_rule(rule)?;
+        self.check_naming_policy(rule)?;
+        self.check_limits(rule)?;
+
+        // Metadata entries are kept in declaration order, including
+        // duplicated identifiers. They are followed by the inherited ones,
+        // source code defaults first, as they take precedence over the
+        // namespace defaults.
+        let mut metadata: Vec<(IdentId, MetaValue)> = rule
+            .meta
+            .iter()
+            .flatten()
+            .map(|meta| {
+                (
+                    self.ident_pool.get_or_intern(meta.identifier.name),
+                    intern_meta_value(&mut self.lit_pool, &meta.value),
+                )
+            })
+            .collect();
+
+        let num_own_metadata = metadata.len();
+
+        let namespace_default_meta = self
+            .namespace_default_meta
+            .get(&self.current_namespace.ident_id)
+            .into_iter()
+            .flatten();
+
+        for (ident_id, value) in
+            self.current_default_meta.iter().chain(namespace_default_meta)
+        {
+            if !metadata.iter().any(|(i, _)| i == ident_id) {
+                metadata.push((*ident_id, *value));
+            }
+        }
+
+        // An inherited `enabled = false` disables the rule too.
+        let disabled = !self.force_enable_rules
+            && metadata.iter().any(|(ident_id, value)| {
+                self.ident_pool.get(*ident_id) == Some("enabled")
+                    && matches!(value, MetaValue::Bool(false))
+            });
+
+        // The patterns in a disabled rule are processed like any other
+        // pattern, but their atoms are discarded afterwards, so they are
+        // never searched for.
+        let num_atoms = self.atoms.len();
+
+        // Private patterns are not reported in the scan results, their
+        // IDs are kept apart.
+        let mut private_patterns = Vec::new();
+
         // Create array with pairs (IdentId, PatternId) that describe
         // the patterns in a compiled rule.
         let pairs = if let Some(patterns) = &rule.patterns {
@@ -362,6 +2107,10 @@ impl<'a> Compiler<'a> {
                     }
                 };
 
+                if pattern.modifiers().private().is_some() {
+                    private_patterns.push(PatternId(self.next_pattern_id));
+                }
+
                 pairs.push((ident_id, PatternId(self.next_pattern_id)));
                 self.next_pattern_id += 1;
             }
@@ -370,28 +2119,98 @@ impl<'a> Compiler<'a> {
             Vec::new()
         };
 
+        if disabled {
+            self.atoms.truncate(num_atoms);
+        }
+
+        // The flags are set while checking the condition, if some
+        // expression needs all the matches of the pattern.
+        self.all_matches_required.resize(self.next_pattern_id as usize, false);
+
+        // Each tag is stored once, repeating a tag in the same rule raises
+        // a warning.
+        let mut tags = Vec::new();
+
+        if let Some(rule_tags) = &rule.tags {
+            for (i, tag) in rule_tags.iter().enumerate() {
+                if let Some(existing_tag) =
+                    rule_tags[..i].iter().find(|t| t.name == tag.name)
+                {
+                    self.warnings.push(
+                        WarningInfo::duplicate_tag(
+                            tag.name.to_string(),
+                            tag.span,
+                            existing_tag.span,
+                        )
+                        .into(),
+                    );
+                } else {
+                    tags.push(self.ident_pool.get_or_intern(tag.name));
+                }
+            }
+        }
+
         let rule_id = RuleId(self.rules.len() as i32);
 
         self.rules.push(RuleInfo {
             ident_id: self.ident_pool.get_or_intern(rule.identifier.name),
+            ident_span: rule.identifier.span,
             namespace_id: self.current_namespace.ident_id,
+            origin: self.current_origin,
+            tags,
+            num_inherited_metadata: metadata.len() - num_own_metadata,
+            metadata,
             patterns: pairs,
+            private_patterns,
+            private: rule.flags.contains(RuleFlag::Private),
+            global: rule.flags.contains(RuleFlag::Global),
+            disabled,
+            dependencies: Vec::new(),
+            fingerprint: fingerprint(&src[rule.span.start()..rule.span.end()]),
         });
 
+        if rule.flags.contains(RuleFlag::Private) {
+            self.unused_private_rules.push((rule_id, rule.identifier.span));
+        }
+
+        let shadowed_patterns = match &rule.patterns {
+            Some(patterns) if self.detect_shadowed_patterns => {
+                shadowed_patterns(patterns)
+            }
+            _ => Vec::new(),
+        };
+
+        // Disabled rules don't have any code, so they never match. The
+        // condition of enabled rules is evaluated by their own function.
+        let mut rule_fn = (!disabled).then(|| self.wasm_mod.new_rule_fn());
+
         let mut ctx = Context {
-            src,
             current_struct: None,
             current_signature: None,
             symbol_table: &mut self.symbol_table,
             ident_pool: &mut self.ident_pool,
             lit_pool: &mut self.lit_pool,
-            report_builder: &self.report_builder,
+            string_sets: self.string_sets.as_slice(),
+            regexps: &mut self.regexps,
             current_rule: self.rules.last().unwrap(),
+            current_rule_id: rule_id,
+            rules: self.rules.as_slice(),
+            unused_private_rules: &mut self.unused_private_rules,
+            upcoming_rules: &self.upcoming_rules,
+            all_matches_required: &mut self.all_matches_required,
+            rule_dependencies: Vec::new(),
+            unused_imports: &mut self.unused_imports,
             wasm_symbols: self.wasm_mod.wasm_symbols(),
             wasm_funcs: &self.wasm_mod.wasm_funcs,
             warnings: &mut self.warnings,
+            extensions: self.extensions == Some(true)
+                || self.source_extensions,
+            shadowed_patterns,
             exception_handler_stack: Vec::new(),
             vars_stack_top: 0,
+            loop_nesting: 0,
+            for_of_pattern: None,
+            for_of_pattern_ids: Vec::new(),
             lookup_start: None,
             lookup_stack: VecDeque::new(),
         };
@@ -422,83 +2241,212 @@ impl<'a> Compiler<'a> {
         // raise a warning about it.
         warn_if_not_bool(&mut ctx, &rule.condition);
 
+        // If the condition was folded into a constant the rule matches
+        // always or never, which is probably a mistake. Conditions that
+        // are just `true` or `false` are not reported, as they are
+        // written that way on purpose.
+        if let TypeValue::Bool(Some(value)) = rule.condition.type_value() {
+            if !matches!(
+                rule.condition,
+                Expr::True { .. } | Expr::False { .. }
+            ) {
+                ctx.warnings.push(
+                    WarningInfo::invariant_condition(
+                        *value,
+                        rule.condition.span(),
+                    )
+                    .into(),
+                );
+            }
+        }
+
         // Emit the code for the rule's condition.
-        emit_rule_code(
-            &mut ctx,
-            &mut self.wasm_mod.main_fn.func_body(),
-            rule_id,
-            rule,
-        );
+        if let Some(rule_fn) = rule_fn.as_mut() {
+            emit_rule_code(&mut ctx, &mut rule_fn.func_body(), rule_id, rule);
+        }
 
         // After emitting the whole condition, the stack should be empty.
         assert_eq!(ctx.vars_stack_top, 0);
 
+        let dependencies = mem::take(&mut ctx.rule_dependencies);
+
+        // The context borrows from the compiler, it must be dropped before
+        // modifying the compiler.
+        drop(ctx);
+
+        self.rules.last_mut().unwrap().dependencies = dependencies;
+
+        if let Some(rule_fn) = rule_fn {
+            // The function is named after the rule, see `emit_wat`.
+            let name = format!(
+                "{}.{}",
+                self.ident_pool.get(self.current_namespace.ident_id).unwrap(),
+                rule.identifier.name
+            );
+            self.wasm_mod.add_rule_fn(rule_id, name, rule_fn);
+        }
+
         Ok(())
     }
 
-    fn process_text_pattern(&mut self, p: &TextPattern) {
-        if p.modifiers.base64().is_some() || p.modifiers.base64wide().is_some()
+    /// Checks that no other rule with the same name has been declared in
+    /// the current namespace. Duplicates within a single source code are
+    /// detected by the parser, this catches the ones declared by different
+    /// calls to [`Compiler::add_source`].
+    fn check_duplicate_rule(&self, rule: &ast::Rule) -> Result<(), Error> {
+        let symbol = self
+            .current_namespace
+            .symbols
+            .as_ref()
+            .borrow()
+            .lookup(rule.identifier.name);
+
+        let Some(Symbol { kind: SymbolKind::Rule(existing_rule_id), .. }) =
+            symbol
+        else {
+            return Ok(());
+        };
+
+        let existing_rule = &self.rules[existing_rule_id.0 as usize];
+
+        // The existing rule can be labeled only if its source code had an
+        // origin, sources without origin are not kept by the report builder
+        // after a new one is added.
+        let existing_rule_origin = existing_rule
+            .origin
+            .map(|origin| self.ident_pool.get(origin).unwrap().to_string());
+
+        let note = existing_rule_origin.is_none().then(|| {
+            "the first declaration is in a previously added source code"
+                .to_string()
+        });
+
+        Err(Error::from(CompileErrorInfo::duplicate_rule(
+            rule.identifier.name.to_string(),
+            rule.identifier.span,
+            existing_rule.ident_span,
+            existing_rule_origin,
+            note,
+        )))
+    }
+
+    /// Checks that adding the rule doesn't exceed the limits in the number
+    /// of rules and patterns, nor the capacity of the identifiers and
+    /// literals pools.
+    fn check_limits(&self, rule: &ast::Rule) -> Result<(), Error> {
+        if self.rules.len() >= self.max_rules {
+            return Err(Error::from(CompileErrorInfo::too_many_rules(
+                self.max_rules,
+                rule.identifier.span,
+            )));
+        }
+
+        if self.ident_pool.len() >= MAX_POOL_ENTRIES
+            || self.lit_pool.len() >= MAX_POOL_ENTRIES
         {
-            self.process_text_pattern_base64(p);
-            return;
+            return Err(Error::from(CompileErrorInfo::too_many_identifiers(
+                rule.identifier.span,
+            )));
         }
 
-        let id = self.lit_pool.get_or_intern(p.value.as_ref());
-        let mut atoms = Vec::new();
-        let mut implicit_ascii = true;
+        let patterns = rule.patterns.as_deref().unwrap_or_default();
 
-        if let Some(PatternModifier::Xor { start, end, .. }) =
-            p.modifiers.xor()
-        {
-            // When `xor` is used `ascii` is not implicit anymore.
-            implicit_ascii = false;
+        if let Some(pattern) = patterns.get(self.max_patterns_per_rule) {
+            return Err(Error::from(
+                CompileErrorInfo::too_many_patterns_in_rule(
+                    self.max_patterns_per_rule,
+                    pattern.identifier().span,
+                ),
+            ));
+        }
 
-            debug_assert!(p.modifiers.nocase().is_none());
-            debug_assert!(p.modifiers.base64().is_none());
-            debug_assert!(p.modifiers.base64wide().is_none());
+        let available = self
+            .max_total_patterns
+            .saturating_sub(self.next_pattern_id as usize);
 
-            let sub_pattern_id = self.push_sub_pattern(SubPattern::Xor(id));
-            let atom = best_atom_from_slice(
-                p.value.as_ref().as_bytes(),
-                DESIRED_ATOM_SIZE,
-            );
+        if let Some(pattern) = patterns.get(available) {
+            return Err(Error::from(CompileErrorInfo::too_many_patterns(
+                self.max_total_patterns,
+                pattern.identifier().span,
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn process_text_pattern(&mut self, p: &TextPattern) {
+        if p.modifiers.base64().is_some() || p.modifiers.base64wide().is_some()
+        {
+            self.process_text_pattern_base64(p);
+            return;
+        }
 
-            atoms.reserve((end - start) as usize + 1);
+        let mut literals = Vec::new();
+        let wide_pattern;
 
-            for atom in XorGenerator::new(&atom, *start..=*end) {
-                atoms.push(AtomInfo { sub_pattern_id, atom });
-            }
+        // The `ascii` modifier is usually implicit, except when `wide` is
+        // used. When both `ascii` and `wide` are used, the pattern has two
+        // variants, one for each encoding. All the variants share the same
+        // PatternId, so the pattern matches if any of them is found.
+        let mut implicit_ascii = true;
+
+        if p.modifiers.wide().is_some() {
+            implicit_ascii = false;
+            wide_pattern = make_wide(p.value.as_ref());
+            literals.push((wide_pattern.as_slice(), true));
         }
 
-        if p.modifiers.nocase().is_some() {
-            debug_assert!(p.modifiers.base64().is_none());
-            debug_assert!(p.modifiers.base64wide().is_none());
+        if implicit_ascii || p.modifiers.ascii().is_some() {
+            literals.push((p.value.as_ref(), false));
+        }
 
-            let sub_pattern_id =
-                self.push_sub_pattern(SubPattern::FixedCaseInsensitive(id));
+        for (literal, wide) in literals {
+            let id = self.lit_pool.get_or_intern(literal);
+            let flags = SubPatternFlags {
+                wide,
+                fullword: p.modifiers.fullword().is_some(),
+            };
 
+            // In wide patterns every other byte is zero, atoms must be twice
+            // as long for having the same quality than in ascii patterns.
             let atom = best_atom_from_slice(
-                p.value.as_ref().as_bytes(),
-                DESIRED_ATOM_SIZE,
+                literal,
+                if wide { DESIRED_ATOM_SIZE * 2 } else { DESIRED_ATOM_SIZE },
             );
 
-            for atom in CaseGenerator::new(&atom) {
-                atoms.push(AtomInfo { sub_pattern_id, atom });
-            }
-        }
+            if let Some(PatternModifier::Xor { start, end, .. }) =
+                p.modifiers.xor()
+            {
+                debug_assert!(p.modifiers.nocase().is_none());
+                debug_assert!(p.modifiers.base64().is_none());
+                debug_assert!(p.modifiers.base64wide().is_none());
+
+                let sub_pattern_id = self.push_sub_pattern(SubPattern::Xor(
+                    id,
+                    *start..=*end,
+                    flags,
+                ));
 
-        if implicit_ascii || p.modifiers.ascii().is_some() {
-            let sub_pattern_id = self.push_sub_pattern(SubPattern::Fixed(id));
+                self.atoms.reserve((end - start) as usize + 1);
 
-            let best_atom = best_atom_from_slice(
-                p.value.as_ref().as_bytes(),
-                DESIRED_ATOM_SIZE,
-            );
+                for atom in XorGenerator::new(&atom, *start..=*end) {
+                    self.atoms.push(AtomInfo { sub_pattern_id, atom });
+                }
+            } else if p.modifiers.nocase().is_some() {
+                let sub_pattern_id = self.push_sub_pattern(
+                    SubPattern::FixedCaseInsensitive(id, flags),
+                );
 
-            atoms.push(AtomInfo { sub_pattern_id, atom: best_atom })
-        };
+                for atom in CaseGenerator::new(&atom) {
+                    self.atoms.push(AtomInfo { sub_pattern_id, atom });
+                }
+            } else {
+                let sub_pattern_id =
+                    self.push_sub_pattern(SubPattern::Fixed(id, flags));
 
-        self.atoms.extend(atoms);
+                self.atoms.push(AtomInfo { sub_pattern_id, atom })
+            }
+        }
     }
 
     fn process_text_pattern_base64(&mut self, p: &TextPattern) {
@@ -585,34 +2533,228 @@ impl<'a> Compiler<'a> {
         }
     }
 
-    fn process_imports(
-        &mut self,
-        src: &SourceCode,
-        imports: &[Import],
-    ) -> Result<(), Error> {
+    /// Checks the identifiers declared by a rule against the naming policy,
+    /// if any. This includes the identifier of the current namespace when
+    /// `rule` is the first rule added to it since the policy was set.
+    fn check_naming_policy(&mut self, rule: &ast::Rule) -> Result<(), Error> {
+        let policy = match &self.naming_policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+
+        if !self.current_namespace.naming_checked {
+            self.current_namespace.naming_checked = true;
+
+            let namespace =
+                self.ident_pool.get(self.current_namespace.ident_id).unwrap();
+
+            let verdict = policy(IdentifierKind::Namespace, namespace, None);
+
+            let note = Some(format!(
+                "namespace `{}` was checked when adding rule `{}` to it",
+                namespace, rule.identifier.name
+            ));
+
+            match verdict {
+                NamingPolicyVerdict::Accept => {}
+                NamingPolicyVerdict::Warn(reason) => {
+                    self.warnings.push(
+                        WarningInfo::namespace_naming_policy_violation(
+                            namespace.to_string(),
+                            reason,
+                            rule.identifier.span,
+                            note,
+                        )
+                        .into(),
+                    );
+                }
+                NamingPolicyVerdict::Reject(reason) => {
+                    return Err(
+                        CompileErrorInfo::namespace_naming_policy_violation(
+                            namespace.to_string(),
+                            reason,
+                            rule.identifier.span,
+                            note,
+                        )
+                        .into(),
+                    );
+                }
+            }
+        }
+
+        let rule_ident = (IdentifierKind::Rule, &rule.identifier);
+
+        let tags =
+            rule.tags.iter().flatten().map(|tag| (IdentifierKind::Tag, tag));
+
+        let patterns =
+            rule.patterns.iter().flatten().map(|pattern| {
+                (IdentifierKind::Pattern, pattern.identifier())
+            });
+
+        for (kind, ident) in
+            std::iter::once(rule_ident).chain(tags).chain(patterns)
+        {
+            match policy(kind, ident.name, Some(ident.span)) {
+                NamingPolicyVerdict::Accept => {}
+                NamingPolicyVerdict::Warn(reason) => {
+                    self.warnings.push(
+                        WarningInfo::naming_policy_violation(
+                            kind.as_str(),
+                            ident.name.to_string(),
+                            reason,
+                            ident.span,
+                        )
+                        .into(),
+                    );
+                }
+                NamingPolicyVerdict::Reject(reason) => {
+                    return Err(CompileErrorInfo::naming_policy_violation(
+                        kind.as_str(),
+                        ident.name.to_string(),
+                        reason,
+                        ident.span,
+                    )
+                    .into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the options set by the pragmas in the source being compiled.
+    ///
+    /// Options set explicitly with the compiler's API take precedence over
+    /// pragmas, a pragma that contradicts them is ignored with a warning.
+    fn process_pragmas(&mut self, pragmas: &[Pragma]) -> Result<(), Error> {
+        for pragma in pragmas {
+            match (pragma.name, pragma.argument) {
+                ("extensions", None) => {
+                    if self.extensions == Some(false) {
+                        self.warnings.push(
+                            WarningInfo::overridden_pragma(
+                                pragma.name.to_string(),
+                                pragma.span(),
+                                Some(
+                                    "extensions were disabled with `Compiler::enable_extensions`"
+                                        .to_string(),
+                                ),
+                            )
+                            .into(),
+                        );
+                    } else {
+                        self.source_extensions = true;
+                    }
+                }
+                ("disable_warning", Some(code)) => {
+                    let code = WarningInfo::CODES
+                        .iter()
+                        .find(|c| **c == code)
+                        .ok_or_else(|| {
+                            CompileErrorInfo::invalid_pragma(
+                                pragma.name.to_string(),
+                                format!("unknown warning `{code}`"),
+                                pragma.span(),
+                            )
+                        })?;
+
+                    self.source_disabled_warnings.insert(code);
+                }
+                ("extensions", Some(_)) => {
+                    return Err(CompileErrorInfo::invalid_pragma(
+                        pragma.name.to_string(),
+                        "this pragma doesn't accept arguments".to_string(),
+                        pragma.span(),
+                    )
+                    .into());
+                }
+                ("disable_warning", None) => {
+                    return Err(CompileErrorInfo::invalid_pragma(
+                        pragma.name.to_string(),
+                        "expecting the identifier of a warning, like `disable_warning(duplicate_tag)`".to_string(),
+                        pragma.span(),
+                    )
+                    .into());
+                }
+                (name, _) => {
+                    self.warnings.push(
+                        WarningInfo::unknown_pragma(
+                            name.to_string(),
+                            pragma.span(),
+                        )
+                        .into(),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_imports(&mut self, imports: &[Import]) -> Result<(), Error> {
         // Iterate over the list of imported modules.
-        for import in imports.iter() {
-            // Does the imported module actually exist? ...
-            if let Some(module) =
-                BUILTIN_MODULES.get(import.module_name.as_str())
+        for (i, import) in imports.iter().enumerate() {
+            let module_name = import.module_name.as_str();
+
+            // Duplicate imports are reported by the parser, only the first
+            // one has any effect.
+            if imports[..i]
+                .iter()
+                .any(|other| other.module_name == module_name)
+            {
+                continue;
+            }
+
+            // Banned modules are rejected before doing anything else, so
+            // they never make it to the list of imported modules.
+            if let Some((title, message)) =
+                self.banned_modules.get(module_name)
             {
+                return Err(CompileErrorInfo::banned_module(
+                    module_name.to_string(),
+                    title.clone(),
+                    message.clone(),
+                    import.span(),
+                )
+                .into());
+            }
+
+            // Modules registered with `register_module` are looked up first,
+            // and then the built-in ones. Registered modules don't have an
+            // associated Rust module.
+            let module =
+                if let Some(descriptor) = self.user_modules.get(module_name) {
+                    Some((descriptor.clone(), None))
+                } else {
+                    BUILTIN_MODULES.get(module_name).map(|module| {
+                        (
+                            module.root_struct_descriptor.clone(),
+                            module.rust_module_name,
+                        )
+                    })
+                };
+
+            // Does the imported module actually exist? ...
+            if let Some((root_struct_descriptor, rust_module_name)) = module {
                 // ... if yes, add the module to the list of imported modules
                 // and the symbol table.
-                let module_name = import.module_name.as_str();
+                self.unused_imports
+                    .push((module_name.to_string(), import.span()));
 
                 self.imported_modules
                     .push(self.ident_pool.get_or_intern(module_name));
 
                 // Create the structure that describes the module.
                 let mut module_struct = Struct::from_proto_descriptor_and_msg(
-                    &module.root_struct_descriptor,
+                    &root_struct_descriptor,
                     None,
                     true,
                 );
 
                 // Does the YARA module has an associated Rust module? If
                 // yes, search for functions exported by the module.
-                if let Some(mod_name) = module.rust_module_name {
+                if let Some(mod_name) = rust_module_name {
                     // This map will contain all the functions exported by the
                     // YARA module. Keys are the function names, and values
                     // are `Func` objects.
@@ -648,12 +2790,14 @@ impl<'a> Compiler<'a> {
 
                     // Insert the functions in the module's struct.
                     for (name, export) in functions.drain() {
-                        module_struct
-                            .add_field(name, TypeValue::Func(Rc::new(export)));
+                        module_struct.add_field(
+                            name,
+                            TypeValue::Func(Arc::new(export)),
+                        );
                     }
                 }
 
-                let module_struct = TypeValue::Struct(Rc::new(module_struct));
+                let module_struct = TypeValue::Struct(Arc::new(module_struct));
 
                 // Insert the module in the struct that contains all imported
                 // modules. This struct contains all modules imported, from
@@ -679,16 +2823,26 @@ impl<'a> Compiler<'a> {
                     .as_ref()
                     .borrow_mut()
                     .insert(module_name, symbol);
+            } else if self.ignore_unknown_modules {
+                // ... if no, but unknown modules are ignored, that's a
+                // warning, and the rules that use the module are ignored.
+                self.warnings.push(
+                    WarningInfo::unknown_module(
+                        module_name.to_string(),
+                        import.span(),
+                    )
+                    .into(),
+                );
+                self.current_namespace
+                    .ignored_identifiers
+                    .insert(module_name.to_string(), module_name.to_string());
             } else {
                 // ... if no, that's an error.
-                return Err(Error::CompileError(
-                    CompileError::unknown_module(
-                        &self.report_builder,
-                        src,
-                        import.module_name.to_string(),
-                        import.span(),
-                    ),
-                ));
+                return Err(CompileErrorInfo::unknown_module(
+                    import.module_name.to_string(),
+                    import.span(),
+                )
+                .into());
             }
         }
 
@@ -709,7 +2863,7 @@ impl Default for Compiler<'_> {
 }
 
 /// ID associated to each identifier in the identifiers pool.
-#[derive(PartialEq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub(crate) struct IdentId(u32);
 
 impl From<u32> for IdentId {
@@ -725,7 +2879,7 @@ impl From<IdentId> for u32 {
 }
 
 /// ID associated to each literal string in the literals pool.
-#[derive(PartialEq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub(crate) struct LiteralId(u32);
 
 impl From<i32> for LiteralId {
@@ -759,8 +2913,18 @@ impl From<LiteralId> for u64 {
 }
 
 /// ID associated to each rule.
-#[derive(Copy, Clone, Debug)]
-pub(crate) struct RuleId(i32);
+///
+/// IDs are assigned sequentially, starting at 0, in the order in which
+/// rules are added to the compiler, and they don't change once the rules
+/// are compiled. However, they are meaningful only within the [`Rules`]
+/// they belong to. Compiling the same source code again produces the same
+/// IDs, but adding, removing or reordering rules changes them.
+///
+/// The ID can be converted into a `usize` and back, which is useful for
+/// storing it elsewhere. See [`Rules::rule_by_id`] and
+/// [`Rules::rule_by_name`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RuleId(i32);
 
 impl From<i32> for RuleId {
     #[inline]
@@ -793,8 +2957,12 @@ impl From<RuleId> for usize {
 /// if one rule defines `$a = "mz"` and another one `$mz = "mz"`, the pattern
 /// `"mz"` is shared by the two rules. Each rule has a Vec<(IdentId, PatternId)>
 /// that associates identifiers to their corresponding patterns.
-#[derive(Copy, Clone, Debug)]
-pub(crate) struct PatternId(i32);
+///
+/// Like [`RuleId`], IDs are assigned sequentially in the order in which
+/// patterns are found, so compiling the same source code again produces
+/// the same IDs. See [`crate::Pattern::id`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PatternId(i32);
 
 impl From<i32> for PatternId {
     #[inline]
@@ -824,6 +2992,57 @@ impl From<PatternId> for usize {
     }
 }
 
+/// ID associated to each set of strings defined with
+/// [`Compiler::define_string_set`].
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct StringSetId(i32);
+
+impl From<i32> for StringSetId {
+    #[inline]
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<usize> for StringSetId {
+    #[inline]
+    fn from(value: usize) -> Self {
+        Self(value as i32)
+    }
+}
+
+impl From<StringSetId> for usize {
+    #[inline]
+    fn from(value: StringSetId) -> Self {
+        value.0 as usize
+    }
+}
+
+/// ID associated to each regexp used with the `matches` operator.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct RegexpId(i32);
+
+impl From<i32> for RegexpId {
+    #[inline]
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<usize> for RegexpId {
+    #[inline]
+    fn from(value: usize) -> Self {
+        Self(value as i32)
+    }
+}
+
+impl From<RegexpId> for usize {
+    #[inline]
+    fn from(value: RegexpId) -> Self {
+        value.0 as usize
+    }
+}
+
 /// ID associated to each sub-pattern.
 ///
 /// For each pattern there's one or more sub-patterns, depending on the pattern
@@ -835,9 +3054,6 @@ pub(crate) struct SubPatternId(u32);
 /// Structure that contains information and data structures required during the
 /// current compilation process.
 struct Context<'a, 'sym> {
-    /// Builder for creating error and warning reports.
-    report_builder: &'a ReportBuilder,
-
     /// Symbol table that contains the currently defined identifiers, modules,
     /// functions, etc.
     symbol_table: &'a mut StackedSymbolTable<'sym>,
@@ -845,7 +3061,7 @@ struct Context<'a, 'sym> {
     /// Symbol table for the currently active structure. When this contains
     /// some value, symbols are looked up in this table and the main symbol
     /// table (i.e: `symbol_table`) is ignored.
-    current_struct: Option<Rc<dyn SymbolLookup + 'a>>,
+    current_struct: Option<Arc<dyn SymbolLookup + 'a>>,
 
     /// Used during code emitting for tracking the function signature
     /// associated to a function call.
@@ -858,21 +3074,55 @@ struct Context<'a, 'sym> {
     /// values are the function's ID in the WASM module.
     wasm_funcs: &'a FxHashMap<String, FunctionId>,
 
-    /// Source code that is being compiled.
-    src: &'a SourceCode<'a>,
-
     /// Rule that is being compiled.
     current_rule: &'a RuleInfo,
 
+    /// ID of the rule that is being compiled.
+    current_rule_id: RuleId,
+
+    /// Rules compiled so far, including the current one.
+    rules: &'a [RuleInfo],
+
+    /// Private rules that haven't been referenced by other rules yet.
+    unused_private_rules: &'a mut Vec<(RuleId, Span)>,
+
+    /// Rules declared after the current one in the same namespace, see
+    /// [`Compiler::upcoming_rules`].
+    upcoming_rules: &'a FxHashMap<String, Span>,
+
+    /// Rules referenced by the condition of the current rule.
+    rule_dependencies: Vec<RuleId>,
+
+    /// Patterns whose matches must be all found, see
+    /// [`Rules::all_matches_required`].
+    all_matches_required: &'a mut BitVec,
+
+    /// Modules imported by the current source that haven't been used by
+    /// any rule yet.
+    unused_imports: &'a mut Vec<(String, Span)>,
+
     /// Warnings generated during the compilation.
     warnings: &'a mut Vec<Warning>,
 
+    /// True if YARA-X extensions are enabled.
+    extensions: bool,
+
+    /// Patterns in the current rule that are shadowed by other patterns.
+    /// Empty unless [`Compiler::detect_shadowed_patterns`] is enabled.
+    shadowed_patterns: Vec<ShadowedPattern>,
+
     /// Pool with identifiers used in the rules.
     ident_pool: &'a mut StringPool<IdentId>,
 
     /// Pool with literal strings used in the rules.
     lit_pool: &'a mut BStringPool<LiteralId>,
 
+    /// Sets of strings defined with [`Compiler::define_string_set`].
+    string_sets: &'a [StringSet],
+
+    /// Regexps used with the `matches` operator, see [`Context::regexp_id`].
+    regexps: &'a mut Vec<regex::bytes::Regex>,
+
     /// Stack of installed exception handlers for catching undefined values.
     exception_handler_stack: Vec<(ValType, InstrSeqId)>,
 
@@ -880,10 +3130,65 @@ struct Context<'a, 'sym> {
     /// with each call to [`Context::new_var`].
     vars_stack_top: i32,
 
+    /// Number of loops that contain the expression being checked by
+    /// `semcheck`. See [`MAX_LOOP_NESTING`].
+    loop_nesting: usize,
+
+    /// Variable that holds the ID of the pattern being iterated by the
+    /// innermost `for .. of` statement. Anonymous pattern identifiers (i.e:
+    /// `$`, `#`, `@` and `!`) refer to this pattern.
+    for_of_pattern: Option<Var>,
+
+    /// Patterns iterated by the innermost `for .. of` statement, used by
+    /// `semcheck` for knowing which patterns the anonymous pattern
+    /// identifiers refer to.
+    for_of_pattern_ids: Vec<PatternId>,
+
     lookup_start: Option<Var>,
     lookup_stack: VecDeque<i32>,
 }
 
+/// Interval at which [`Compiler::build`] reports progress and checks for
+/// cancellation while the native code is being compiled.
+const BUILD_POLLING_INTERVAL: Duration = Duration::from_millis(50);
+
+lazy_static! {
+    /// Threads that are compiling the native code of cancelled builds. See
+    /// [`Compiler::build`].
+    static ref CANCELLED_COMPILATIONS: Mutex<Vec<JoinHandle<()>>> =
+        Mutex::new(Vec::new());
+}
+
+/// Number of variables that fit in the vars stack.
+const VARS_STACK_CAPACITY: i32 = (wasm::VARS_STACK_END
+    - wasm::VARS_STACK_START)
+    / mem::size_of::<i64>() as i32;
+
+/// Maximum number of variables that a single loop allocates with
+/// [`Context::new_var`]. `emit_for` allocates up to 4, and loops over maps
+/// allocate 3 more (the key, the value and the map itself).
+const MAX_VARS_PER_LOOP: i32 = 7;
+
+/// Maximum number of rules, the number of rules must fit in a [`RuleId`].
+const MAX_RULES: usize = i32::MAX as usize;
+
+/// Maximum number of patterns, the number of patterns must fit in a
+/// [`PatternId`].
+const MAX_PATTERNS: usize = i32::MAX as usize;
+
+/// Maximum number of entries in the identifiers and literals pools before
+/// compiling a rule. IDs in the pools are 32-bits integers, this leaves
+/// room for the entries added by the rule itself.
+const MAX_POOL_ENTRIES: usize = 1 << 31;
+
+/// Maximum nesting depth for included files.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Maximum nesting depth for loops (i.e: `for .. in`, `for .. of` and `of`
+/// expressions), deeper loops would exhaust the vars stack.
+pub(crate) const MAX_LOOP_NESTING: usize =
+    (VARS_STACK_CAPACITY / MAX_VARS_PER_LOOP) as usize;
+
 impl<'a, 'sym> Context<'a, 'sym> {
     /// Given an [`IdentId`] returns the identifier as `&str`.
     ///
@@ -921,16 +3226,17 @@ impl<'a, 'sym> Context<'a, 'sym> {
     ///
     /// # Panics
     ///
-    /// Panics if the stack grows past [`wasm::VARS_STACK_END`]
+    /// Panics if the stack grows past [`wasm::VARS_STACK_END`]. This can't
+    /// happen with conditions that passed `semcheck`, which rejects loops
+    /// nested more than [`MAX_LOOP_NESTING`] levels deep.
     #[inline]
     fn new_var(&mut self, ty: Type) -> Var {
         let top = self.vars_stack_top;
         self.vars_stack_top += 1;
-        if self.vars_stack_top * mem::size_of::<i64>() as i32
-            > wasm::VARS_STACK_END - wasm::VARS_STACK_START
-        {
-            panic!("too many nested loops");
-        }
+        assert!(
+            self.vars_stack_top <= VARS_STACK_CAPACITY,
+            "vars stack overflow, semcheck should have rejected this rule"
+        );
         Var { ty, index: top }
     }
 
@@ -953,25 +3259,61 @@ impl<'a, 'sym> Context<'a, 'sym> {
         self.vars_stack_top = top.index;
     }
 
-    /// Given a pattern identifier (e.g. `$a`) search for it in the current
-    /// rule and return its [`PatternID`].
+    /// Given a pattern identifier (e.g. `$a`, `#a`, `@a`, `!a`) search for
+    /// it in the current rule and return its [`PatternID`]. The identifier's
+    /// first character is ignored, `#a` refers to the same pattern as `$a`.
     ///
     /// # Panics
     ///
-    /// Panics if the current rule does not have the requested pattern.
-    fn get_pattern_from_current_rule(&self, ident: &Ident) -> PatternId {
+    /// Panics if the current rule does not have the requested pattern. This
+    /// should not happen, the parser rejects conditions that refer to
+    /// patterns not declared by the rule.
+    fn get_pattern_from_current_rule(&self, ident: &str) -> PatternId {
         for (ident_id, pattern_id) in &self.current_rule.patterns {
-            if self.resolve_ident(*ident_id) == ident.name {
+            if self.resolve_ident(*ident_id)[1..] == ident[1..] {
                 return *pattern_id;
             }
         }
         panic!(
             "rule `{}` does not have pattern `{}` ",
             self.resolve_ident(self.current_rule.ident_id),
-            ident.name
+            ident
         );
     }
 
+    /// Given the name of a set of strings returns its [`StringSetId`], or
+    /// [`None`] if no set with that name has been defined.
+    fn get_string_set(&self, name: &str) -> Option<StringSetId> {
+        self.string_sets
+            .iter()
+            .position(|set| self.resolve_ident(set.ident_id) == name)
+            .map(StringSetId::from)
+    }
+
+    /// Returns the [`RegexpId`] for a regexp used with the `matches`
+    /// operator, compiling the regexp if this is the first time it's used.
+    /// Equivalent regexps share the same ID.
+    fn regexp_id(
+        &mut self,
+        regexp: &ast::Regexp,
+    ) -> Result<RegexpId, CompileErrorInfo> {
+        let translated = regexp::translate(regexp);
+
+        if let Some(pos) =
+            self.regexps.iter().position(|re| re.as_str() == translated)
+        {
+            return Ok(RegexpId::from(pos));
+        }
+
+        let compiled = regexp::compile(&translated).map_err(|error| {
+            CompileErrorInfo::invalid_regexp(error, regexp.span)
+        })?;
+
+        self.regexps.push(compiled);
+
+        Ok(RegexpId::from(self.regexps.len() - 1))
+    }
+
     /// Given a function mangled name returns its id.
     ///
     /// # Panics
@@ -1017,14 +3359,42 @@ pub struct Rules {
     /// the [`IdentId`] corresponding to the module's identifier.
     imported_modules: Vec<IdentId>,
 
+    /// Structure with the imported modules and the global variables defined
+    /// with [`Compiler::define_global`], with the default value of each
+    /// variable. Each scanner starts with a copy of this structure as its
+    /// root structure.
+    globals_struct: Struct,
+
+    /// Modules registered with [`Compiler::register_module`].
+    user_modules: FxHashMap<String, MessageDescriptor>,
+
+    /// Sets of strings defined with [`Compiler::define_string_set`]. A
+    /// [`StringSetId`] is an index in this vector.
+    string_sets: Vec<StringSet>,
+
+    /// Regexps used with the `matches` operator. A [`RegexpId`] is an index
+    /// in this vector.
+    regexps: Vec<regex::bytes::Regex>,
+
     /// Vector containing all the compiled rules. A [`RuleId`] is an index
     /// in this vector.
     rules: Vec<RuleInfo>,
 
+    /// Map from the namespace and identifier of each rule to its
+    /// [`RuleId`], used by [`Rules::rule_by_name`].
+    rules_by_name: FxHashMap<(IdentId, IdentId), RuleId>,
+
+    /// IDs of the global rules that are not disabled.
+    global_rules: Vec<RuleId>,
+
     /// Total number of patterns in all rules. This is equal to the last
     /// [`PatternId`] +  1.
     num_patterns: usize,
 
+    /// Patterns whose matches must be all found, indexed by [`PatternId`].
+    /// See [`Rules::all_matches_required`].
+    all_matches_required: BitVec,
+
     /// Vector with all the sub-patterns used in the rules. A [`SubPatternId`]
     /// is an index in this vector.
     sub_patterns: Vec<(PatternId, SubPattern)>,
@@ -1038,9 +3408,86 @@ pub struct Rules {
     /// This allows to search for all the atoms in the scanned data at the same
     /// time in an efficient manner.
     ac: AhoCorasick,
+
+    /// Options in effect when the rules were built.
+    build_options: BuildOptions,
 }
 
 impl Rules {
+    /// Returns the options in effect when the rules were built.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .enable_extensions(true)
+    ///     .add_source("rule foo { condition: true }")
+    ///     .unwrap();
+    /// let rules = compiler.build().unwrap();
+    ///
+    /// assert!(rules.build_options().extensions);
+    /// assert!(!rules.build_options().force_enable_rules);
+    /// ```
+    pub fn build_options(&self) -> &BuildOptions {
+        &self.build_options
+    }
+
+    /// Returns the rule with the given [`RuleId`], or `None` if the ID
+    /// doesn't belong to any rule in this set.
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, RuleId};
+    /// let mut compiler = Compiler::new();
+    /// compiler.add_source("rule foo { condition: true }").unwrap();
+    /// let rules = compiler.build().unwrap();
+    ///
+    /// assert_eq!(rules.rule_by_id(RuleId::from(0)).unwrap().name(), "foo");
+    /// assert!(rules.rule_by_id(RuleId::from(1)).is_none());
+    /// ```
+    pub fn rule_by_id(&self, id: RuleId) -> Option<Rule<'_, '_>> {
+        // Negative IDs can be created from an `i32`, `get` rejects them
+        // after they are converted to a very large `usize`.
+        self.rules.get(usize::from(id))?;
+        Some(Rule::new(self, None, id))
+    }
+
+    /// Returns the rule with the given name in the given namespace, or
+    /// `None` if there's no such rule.
+    ///
+    /// Rules added without calling [`Compiler::new_namespace`] are in the
+    /// `default` namespace.
+    ///
+    /// ```
+    /// # use yara_x::Compiler;
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .add_source("rule foo { condition: true }")
+    ///     .unwrap()
+    ///     .new_namespace("bar")
+    ///     .add_source("rule foo { condition: true }")
+    ///     .unwrap();
+    /// let rules = compiler.build().unwrap();
+    ///
+    /// assert_eq!(rules.rule_by_name("bar", "foo").unwrap().namespace(), "bar");
+    /// assert!(rules.rule_by_name("bar", "baz").is_none());
+    /// ```
+    pub fn rule_by_name(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Option<Rule<'_, '_>> {
+        let namespace_id = self.ident_pool.get_id(namespace)?;
+        let ident_id = self.ident_pool.get_id(name)?;
+        let id = *self.rules_by_name.get(&(namespace_id, ident_id))?;
+        Some(Rule::new(self, None, id))
+    }
+
+    /// Returns an iterator that yields all the rules, including private
+    /// and disabled rules, sorted by [`RuleId`].
+    pub fn iter(&self) -> RulesIter<'_> {
+        RulesIter { rules: self, iterator: 0..self.rules.len() }
+    }
+
     /// Returns a [`RuleInfo`] given its [`RuleId`].
     ///
     /// # Panics
@@ -1056,6 +3503,37 @@ impl Rules {
         self.rules.as_slice()
     }
 
+    /// Returns the IDs of the global rules, excluding disabled ones.
+    #[inline]
+    pub(crate) fn global_rules(&self) -> &[RuleId] {
+        self.global_rules.as_slice()
+    }
+
+    /// Returns a set of strings by [`StringSetId`].
+    #[inline]
+    pub(crate) fn get_string_set(&self, set_id: StringSetId) -> &StringSet {
+        &self.string_sets[usize::from(set_id)]
+    }
+
+    /// Returns a regexp by [`RegexpId`].
+    #[inline]
+    pub(crate) fn get_regexp(
+        &self,
+        regexp_id: RegexpId,
+    ) -> &regex::bytes::Regex {
+        &self.regexps[usize::from(regexp_id)]
+    }
+
+    /// Returns true if some condition needs all the matches of the
+    /// pattern, like `#a`, `@a[i]`, `!a[i]` or `$a at 100`, instead of
+    /// just knowing whether it matched. Patterns that don't need them are
+    /// searched only until their first match when
+    /// [`crate::Scanner::set_fast_mode`] is enabled.
+    #[inline]
+    pub(crate) fn all_matches_required(&self, pattern_id: PatternId) -> bool {
+        self.all_matches_required[usize::from(pattern_id)]
+    }
+
     /// Returns a sub-pattern by [`SubPatternId`].
     #[inline]
     pub(crate) fn get_sub_pattern(
@@ -1065,6 +3543,35 @@ impl Rules {
         &self.sub_patterns[sub_pattern_id.0 as usize]
     }
 
+    /// Returns true if the pattern has some sub-pattern. Hex and regexp
+    /// patterns don't produce sub-patterns yet, so they never match.
+    pub(crate) fn has_sub_patterns(&self, pattern_id: PatternId) -> bool {
+        self.sub_patterns.iter().any(|(id, _)| *id == pattern_id)
+    }
+
+    /// Returns the maximum length of the matches of any sub-pattern.
+    pub(crate) fn max_match_len(&self) -> usize {
+        let lit_len = |id: &LiteralId| self.lit_pool.get(*id).unwrap().len();
+        // Base64 matches may include up to 3 characters before the encoded
+        // pattern, see `ScanContext::verify_base64_match`.
+        let base64_len = |id: &LiteralId| {
+            base64::encoded_len(lit_len(id), false).unwrap() + 3
+        };
+        self.sub_patterns
+            .iter()
+            .map(|(_, sub_pattern)| match sub_pattern {
+                SubPattern::Fixed(id, _)
+                | SubPattern::FixedCaseInsensitive(id, _)
+                | SubPattern::Xor(id, _, _) => lit_len(id),
+                SubPattern::Base64(id, _)
+                | SubPattern::CustomBase64(id, _, _) => base64_len(id),
+                SubPattern::Base64Wide(id, _)
+                | SubPattern::CustomBase64Wide(id, _, _) => 2 * base64_len(id),
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
     #[inline]
     pub(crate) fn atoms(&self) -> &[AtomInfo] {
         self.atoms.as_slice()
@@ -1091,6 +3598,24 @@ impl Rules {
         }
     }
 
+    /// Returns the structure with the imported modules and the default
+    /// values of the global variables. See [`Compiler::define_global`].
+    #[inline]
+    pub(crate) fn globals_struct(&self) -> &Struct {
+        &self.globals_struct
+    }
+
+    /// Returns the descriptor of a module registered with
+    /// [`Compiler::register_module`], or [`None`] if no module with the
+    /// given name was registered.
+    #[inline]
+    pub(crate) fn user_module(
+        &self,
+        name: &str,
+    ) -> Option<&MessageDescriptor> {
+        self.user_modules.get(name)
+    }
+
     #[inline]
     pub(crate) fn lit_pool(&self) -> &BStringPool<LiteralId> {
         &self.lit_pool
@@ -1121,34 +3646,168 @@ impl<'a> Iterator for Imports<'a> {
     }
 }
 
+/// A set of strings defined with [`Compiler::define_string_set`].
+pub(crate) struct StringSet {
+    /// The ID of the set's name in the identifiers pool.
+    pub(crate) ident_id: IdentId,
+    /// The strings in the set, identified by their IDs in the literals pool.
+    pub(crate) literals: FxHashSet<LiteralId>,
+}
+
+/// Iterator that yields the rules in a [`Rules`], see [`Rules::iter`].
+pub struct RulesIter<'r> {
+    rules: &'r Rules,
+    iterator: Range<usize>,
+}
+
+impl<'r> Iterator for RulesIter<'r> {
+    type Item = Rule<'r, 'r>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = RuleId::from(self.iterator.next()?);
+        Some(Rule::new(self.rules, None, id))
+    }
+}
+
 /// Information about each of the individual rules included in [`Rules`].
 pub(crate) struct RuleInfo {
     /// The ID of the rule identifier in the identifiers pool.
     pub(crate) ident_id: IdentId,
     /// The ID of the rule namespace in the identifiers pool.
     pub(crate) namespace_id: IdentId,
+    /// Span of the rule identifier in the source code that declared it.
+    pub(crate) ident_span: Span,
+    /// The ID in the identifiers pool of the origin of the source code
+    /// that contained the rule, if it had one.
+    pub(crate) origin: Option<IdentId>,
+    /// IDs of the rule tags in the identifiers pool, in the same order they
+    /// were declared.
+    pub(crate) tags: Vec<IdentId>,
+    /// Metadata entries declared in the rule's `meta` section, in the same
+    /// order they were declared, followed by the inherited ones.
+    pub(crate) metadata: Vec<(IdentId, MetaValue)>,
+    /// Number of entries at the end of `metadata` that were inherited from
+    /// the defaults of the source code or namespace.
+    pub(crate) num_inherited_metadata: usize,
     /// Vector with all the patterns defined by this rule.
-    patterns: Vec<(IdentId, PatternId)>,
+    pub(crate) patterns: Vec<(IdentId, PatternId)>,
+    /// IDs of the patterns in `patterns` that have the `private` modifier.
+    pub(crate) private_patterns: Vec<PatternId>,
+    /// True if the rule is private. Private rules can be referenced by
+    /// other rules, but they are not included in scan results.
+    pub(crate) private: bool,
+    /// True if the rule is global. When a global rule doesn't match, no
+    /// other rule in the same namespace matches either.
+    pub(crate) global: bool,
+    /// True if the rule is disabled. Disabled rules don't have any code and
+    /// their patterns don't produce atoms.
+    pub(crate) disabled: bool,
+    /// IDs of the rules referenced in the rule's condition. These rules
+    /// must be evaluated before this one, even if they were not selected
+    /// for the scan, see [`crate::Scanner::scan_with_filter`].
+    pub(crate) dependencies: Vec<RuleId>,
+    /// Hash of the rule's source code, see [`fingerprint`].
+    pub(crate) fingerprint: u64,
+}
+
+/// Computes the fingerprint of a rule from its source code.
+///
+/// The fingerprint is the 64-bit FNV-1a hash of the source, which is stable
+/// across platforms and versions. Any change in the source, including
+/// whitespace and comments, produces a different fingerprint.
+fn fingerprint(src: &[u8]) -> u64 {
+    src.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
 }
 
-/// A structure that describes a rule.
-pub struct Rule<'r> {
-    pub(crate) rules: &'r Rules,
-    pub(crate) rule_info: &'r RuleInfo,
+/// Appends to `files` the paths of the YARA source files in `dir`, including
+/// the ones in subdirectories if `recursive` is true.
+fn source_files(
+    reader: &mut dyn FileReader,
+    dir: &Path,
+    recursive: bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let entries = reader.read_dir(dir).map_err(|err| Error::OpenError {
+        path: dir.to_path_buf(),
+        source: err,
+    })?;
+
+    for DirEntry { path, is_dir } in entries {
+        if is_dir {
+            if recursive {
+                source_files(reader, &path, recursive, files)?;
+            }
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yar" | "yara")
+        ) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
 }
 
-impl<'r> Rule<'r> {
-    /// Returns the rule's name.
-    pub fn name(&self) -> &str {
-        self.rules.ident_pool().get(self.rule_info.ident_id).unwrap()
+/// Returns the path of a file included by a source with the given origin.
+///
+/// Relative paths are relative to the directory of the including source.
+/// The path is normalized by removing `.` components, and `..` components
+/// that follow a normal one, so that the same file gets the same path when
+/// included from different places.
+fn include_path(file_name: &str, includer: Option<&Path>) -> PathBuf {
+    let path = match includer.and_then(Path::parent) {
+        Some(dir) => dir.join(file_name),
+        None => PathBuf::from(file_name),
+    };
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
     }
+    normalized
+}
 
-    /// Returns the rule's namespace.
-    pub fn namespace(&self) -> &str {
-        self.rules.ident_pool().get(self.rule_info.namespace_id).unwrap()
+/// Converts a metadata value from the AST into a [`MetaValue`], storing
+/// strings in the literals pool.
+fn intern_meta_value(
+    lit_pool: &mut BStringPool<LiteralId>,
+    value: &ast::MetaValue,
+) -> MetaValue {
+    match value {
+        ast::MetaValue::Bool(b) => MetaValue::Bool(*b),
+        ast::MetaValue::Integer(i) => MetaValue::Integer(*i),
+        ast::MetaValue::Float(f) => MetaValue::Float(*f),
+        ast::MetaValue::String(s) => {
+            MetaValue::String(lit_pool.get_or_intern(s.as_bstr()))
+        }
     }
 }
 
+/// Value of a metadata entry in a compiled rule.
+///
+/// This is how values are stored in [`RuleInfo`], see
+/// [`crate::scanner::MetaValue`] for the type exposed in the public API.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum MetaValue {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    /// ID of the string in the literals pool.
+    String(LiteralId),
+}
+
 pub(crate) struct AtomInfo {
     pub sub_pattern_id: SubPatternId,
     pub atom: Atom,
@@ -1164,12 +3823,23 @@ pub(crate) struct AtomInfo {
 /// found in the scanned data by the Aho-Corasick algorithm, the scanner
 /// verifies that the sub-pattern actually matches.
 pub(crate) enum SubPattern {
-    Fixed(LiteralId),
-    FixedCaseInsensitive(LiteralId),
-    Xor(LiteralId),
+    Fixed(LiteralId, SubPatternFlags),
+    FixedCaseInsensitive(LiteralId, SubPatternFlags),
+    Xor(LiteralId, RangeInclusive<u8>, SubPatternFlags),
 
     Base64(LiteralId, u8),
     Base64Wide(LiteralId, u8),
     CustomBase64(LiteralId, LiteralId, u8),
     CustomBase64Wide(LiteralId, LiteralId, u8),
 }
+
+/// Flags that affect the verification of a [`SubPattern`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SubPatternFlags {
+    /// The sub-pattern's literal is the wide variant of the pattern, every
+    /// other byte in the literal is zero.
+    pub wide: bool,
+    /// The pattern has the `fullword` modifier. Matches must not be preceded
+    /// or followed by alphanumeric characters.
+    pub fullword: bool,
+}
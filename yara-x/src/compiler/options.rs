@@ -0,0 +1,47 @@
+/*! Snapshot of the options used for building a set of rules.
+
+Any change in these types changes the JSON schema, see [`crate::schemas`]
+before modifying them.
+*/
+
+/// The options in effect when a set of [`crate::Rules`] was built.
+///
+/// The snapshot is taken by [`crate::Compiler::build`], and it's returned
+/// by [`crate::Rules::build_options`]. Options that can be changed between
+/// calls to [`crate::Compiler::add_source`] are recorded with the value
+/// they had at the end.
+///
+/// The snapshot describes how the rules were built, but never includes the
+/// data provided to the compiler. For instance, string sets are recorded
+/// by name, without the strings they contain.
+///
+/// When the `serde` feature is enabled the snapshot can be serialized and
+/// deserialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct BuildOptions {
+    /// Version of YARA-X that built the rules.
+    pub version: String,
+    /// True if compile-time optimizations were applied, see the
+    /// `compile-time-optimization` feature.
+    pub compile_time_optimization: bool,
+    /// See [`crate::Compiler::enable_extensions`].
+    pub extensions: bool,
+    /// See [`crate::Compiler::force_enable_rules`].
+    pub force_enable_rules: bool,
+    /// See [`crate::Compiler::errors_on_warnings`].
+    pub errors_on_warnings: bool,
+    /// See [`crate::Compiler::detect_shadowed_patterns`].
+    pub detect_shadowed_patterns: bool,
+    /// True if a naming policy was set with
+    /// [`crate::Compiler::naming_policy`].
+    pub naming_policy: bool,
+    /// Identifiers of the warnings disabled with
+    /// [`crate::Compiler::disable_warning`], sorted alphabetically.
+    pub disabled_warnings: Vec<String>,
+    /// Names of the string sets defined with
+    /// [`crate::Compiler::define_string_set`], in the order they were
+    /// defined.
+    pub string_sets: Vec<String>,
+}
@@ -0,0 +1,58 @@
+/*! Progress reporting and cancellation for [`crate::Compiler::build`].
+
+Building a large set of rules is dominated by two phases: finishing the
+WASM module that contains the rule conditions, and compiling that module
+to native code. Neither phase exposes its internal progress, so progress
+is reported when each phase starts and ends, and while the native code is
+being compiled the build waits in short intervals, checking whether it was
+cancelled.
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Phases of [`crate::Compiler::build`], in the order they are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildPhase {
+    /// Generation of the WASM module that contains the rule conditions.
+    WasmFinalization,
+    /// Compilation of the WASM module to native code.
+    NativeCompilation,
+}
+
+/// Progress of [`crate::Compiler::build`], passed to the callback set with
+/// [`crate::Compiler::build_progress`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildProgress {
+    /// Phase that is being executed.
+    pub phase: BuildPhase,
+    /// Fraction of the phase that has been completed, between `0.0` and
+    /// `1.0`. It is [`None`] while the phase is in progress and the
+    /// fraction can't be determined.
+    pub fraction: Option<f32>,
+}
+
+/// Allows cancelling [`crate::Compiler::build`] from another thread.
+///
+/// Clones of a token share the same state, cancelling one of them cancels
+/// all of them. See [`crate::Compiler::cancellation_token`].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a new token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the builds that use this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if [`CancellationToken::cancel`] was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,104 @@
+/*! Compilation of the regexps used with the `matches` operator.
+
+These regexps are matched against strings at scan time by the `regex`
+crate, so they are compiled once while the rules are being built. The
+syntax accepted by YARA is almost a subset of the one accepted by the
+`regex` crate, the few differences are translated before compiling.
+*/
+
+use regex::bytes::{Regex, RegexBuilder};
+use yara_x_parser::ast;
+
+/// Translates `regexp` into the syntax of the `regex` crate.
+///
+/// The modifiers are translated into inline flags, and Unicode support is
+/// disabled, so that the regexp matches bytes and `.` matches any byte,
+/// even if the string is not valid UTF-8. Two regexps are equivalent if
+/// they produce the same result.
+pub(super) fn translate(regexp: &ast::Regexp) -> String {
+    let source = regexp.regexp;
+    // The regexp includes the enclosing slashes and the modifiers, as in
+    // `/foo/i`, but only the part between the slashes is needed.
+    let body = &source[1..source.rfind('/').unwrap()];
+
+    let mut result = String::from("(?");
+
+    if regexp.case_insensitive {
+        result.push('i');
+    }
+    if regexp.dotall {
+        result.push('s');
+    }
+
+    result.push_str("-u)");
+
+    let mut chars = body.chars().peekable();
+    let mut in_class = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                // Slashes must be escaped in YARA, as they delimit the
+                // regexp, but escaping them is an error for `regex`.
+                Some('/') => result.push('/'),
+                Some(next) => {
+                    result.push('\\');
+                    result.push(next);
+                }
+                None => result.push('\\'),
+            },
+            '[' if !in_class => {
+                in_class = true;
+                result.push('[');
+                // A closing bracket right at the start is a literal.
+                if let Some(negation) = chars.next_if_eq(&'^') {
+                    result.push(negation);
+                }
+                if let Some(bracket) = chars.next_if_eq(&']') {
+                    result.push('\\');
+                    result.push(bracket);
+                }
+            }
+            ']' if in_class => {
+                in_class = false;
+                result.push(']');
+            }
+            // YARA accepts repetitions without a lower bound, like `{,3}`,
+            // which `regex` requires to be written as `{0,3}`.
+            '{' if !in_class && chars.peek() == Some(&',') => {
+                result.push_str("{0");
+            }
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Compiles a regexp previously translated with [`translate`].
+///
+/// In case of error, returns a short description of the error.
+pub(super) fn compile(translated: &str) -> Result<Regex, String> {
+    // The regexp is parsed first because the errors produced by the parser
+    // are more concise than the ones returned by `RegexBuilder`, which
+    // include a copy of the regexp.
+    let mut parser =
+        regex_syntax::ParserBuilder::new().allow_invalid_utf8(true).build();
+
+    if let Err(err) = parser.parse(translated) {
+        return Err(match err {
+            regex_syntax::Error::Parse(err) => err.kind().to_string(),
+            regex_syntax::Error::Translate(err) => err.kind().to_string(),
+            err => err.to_string(),
+        });
+    }
+
+    RegexBuilder::new(translated).build().map_err(|err| match err {
+        regex::Error::CompiledTooBig(limit) => {
+            format!(
+                "the compiled regexp exceeds the size limit of {limit} bytes"
+            )
+        }
+        err => err.to_string(),
+    })
+}
@@ -1,13 +1,40 @@
 use std::borrow::Borrow;
 use std::iter;
+use std::mem;
 use std::rc::Rc;
 
 use yara_x_parser::ast::*;
 use yara_x_parser::types::{Map, Type, TypeValue};
-use yara_x_parser::warnings::Warning;
+use yara_x_parser::warnings::WarningInfo;
 
-use crate::compiler::{CompileError, Context, Error, ParserError};
-use crate::symbols::{Symbol, SymbolLookup, SymbolTable};
+use crate::compiler::emit::patterns_matching;
+use crate::compiler::{
+    CompileErrorInfo, Context, Error, ParserError, MAX_LOOP_NESTING,
+};
+use crate::symbols::{Symbol, SymbolKind, SymbolLookup, SymbolTable};
+
+/// Returns the type that the operands of an arithmetic or comparison
+/// operation have after type promotion.
+///
+/// This is the single place where the promotion rules are defined, both
+/// the semantic check and the code emitter rely on it. When both operands
+/// have the same type no promotion occurs. When one of them is an integer
+/// and the other one is a float, the integer is promoted to float. Any other
+/// combination of types can't be mixed in the same operation, and the result
+/// is `None`.
+pub(in crate::compiler) fn promoted_type(
+    lhs: Type,
+    rhs: Type,
+) -> Option<Type> {
+    match (lhs, rhs) {
+        (Type::Unknown, _) | (_, Type::Unknown) => None,
+        (lhs, rhs) if lhs == rhs => Some(lhs),
+        (Type::Integer, Type::Float) | (Type::Float, Type::Integer) => {
+            Some(Type::Float)
+        }
+        _ => None,
+    }
+}
 
 macro_rules! semcheck {
     ($ctx:expr, $( $accepted_types:path )|+, $expr:expr) => {
@@ -17,13 +44,10 @@ macro_rules! semcheck {
             let span = (&*$expr).span();
             let ty = semcheck_expr($ctx, $expr)?;
             if !matches!(ty, $( $accepted_types )|+) {
-                return Err(Error::CompileError(CompileError::wrong_type(
-                    $ctx.report_builder,
-                    $ctx.src,
-                    ParserError::join_with_or(&[ $( $accepted_types ),+ ], true),
+                return Err(CompileErrorInfo::wrong_type(ParserError::join_with_or(&[ $( $accepted_types ),+ ], true),
                     ty.to_string(),
                     span,
-                )));
+                ).into());
             }
             Ok::<Type, Error>(ty)
         }
@@ -32,8 +56,13 @@ macro_rules! semcheck {
 
 pub(crate) use semcheck;
 
+/// Checks the operands of a binary operation. Each operand must have one of
+/// the accepted types, and the types of both operands must be compatible
+/// according to `$promote`, a function that returns the type of the
+/// operands after type promotion, or `None` if they can't be mixed. Most
+/// operations use [`promoted_type`].
 macro_rules! semcheck_operands {
-    ($ctx:ident, $expr1:expr, $expr2:expr, $( $accepted_types:path )|+, $compatible_types:expr) => {{
+    ($ctx:ident, $expr1:expr, $expr2:expr, $( $accepted_types:path )|+, $promote:expr) => {{
         let span1 = (&*$expr1).span();
         let span2 = (&*$expr2).span();
 
@@ -44,26 +73,14 @@ macro_rules! semcheck_operands {
         assert!(!matches!(ty1, Type::Unknown));
         assert!(!matches!(ty2, Type::Unknown));
 
-        let types_are_compatible = {
-            // If the types are the same, they are compatible.
-            (ty1 == ty2) ||
-            (
-                // If both types are in the list of compatible types,
-                // they are compatible too.
-                $compatible_types.iter().any(|ty: &Type| ty == &ty1)
-                && $compatible_types.iter().any(|ty: &Type| ty == &ty2)
-            )
-        };
+        let promote: fn(Type, Type) -> Option<Type> = $promote;
 
-        if !types_are_compatible {
-            return Err(Error::CompileError(CompileError::mismatching_types(
-                $ctx.report_builder,
-                $ctx.src,
-                ty1.to_string(),
+        if promote(ty1, ty2).is_none() {
+            return Err(CompileErrorInfo::mismatching_types(ty1.to_string(),
                 ty2.to_string(),
                 span1,
                 span2,
-            )));
+            ).into());
         }
 
         Ok::<_, Error>((ty1, ty2))
@@ -77,13 +94,10 @@ macro_rules! check_non_negative_integer {
         let type_value = (&*$expr).type_value();
         if let TypeValue::Integer(Some(value)) = type_value {
             if *value < 0 {
-                return Err(Error::CompileError(
-                    CompileError::unexpected_negative_number(
-                        $ctx.report_builder,
-                        $ctx.src,
-                        span,
-                    ),
-                ));
+                return Err(CompileErrorInfo::unexpected_negative_number(
+                    span,
+                )
+                .into());
             }
         }
         Ok::<_, Error>(ty)
@@ -97,15 +111,10 @@ macro_rules! check_integer_in_range {
         let type_value = (&*$expr).type_value();
         if let TypeValue::Integer(Some(value)) = type_value {
             if !($min..=$max).contains(value) {
-                return Err(Error::CompileError(
-                    CompileError::number_out_of_range(
-                        $ctx.report_builder,
-                        $ctx.src,
-                        $min,
-                        $max,
-                        span,
-                    ),
-                ));
+                return Err(CompileErrorInfo::number_out_of_range(
+                    $min, $max, span,
+                )
+                .into());
             }
         }
         Ok::<_, Error>(ty)
@@ -113,14 +122,11 @@ macro_rules! check_integer_in_range {
 }
 
 macro_rules! gen_semcheck_boolean_op {
-    ($name:ident, $op:tt) => {
+    ($name:ident, $op:tt, $absorbing:literal) => {
         fn $name(
             ctx: &mut Context,
             expr: &mut Box<BinaryExpr>,
         ) -> Result<Type, Error> {
-            warn_if_not_bool(ctx, &expr.lhs);
-            warn_if_not_bool(ctx, &expr.rhs);
-
             semcheck_operands!(
                 ctx,
                 &mut expr.lhs,
@@ -130,10 +136,59 @@ macro_rules! gen_semcheck_boolean_op {
                 Type::Bool | Type::Integer | Type::Float | Type::String,
                 // All operands types can mixed in a boolean operation, as they
                 // are casted to boolean.
-                &[Type::Bool, Type::Integer, Type::Float, Type::String]
+                |_, _| Some(Type::Bool)
             )?;
 
-            let type_value = expr.lhs.type_value().$op(expr.rhs.type_value());
+            // The operands' types are known only after checking them.
+            warn_if_not_bool(ctx, &expr.lhs);
+            warn_if_not_bool(ctx, &expr.rhs);
+
+            let mut type_value =
+                expr.lhs.type_value().$op(expr.rhs.type_value());
+
+            // If any of the operands is known to be `$absorbing`, the result
+            // is `$absorbing` regardless of the other operand, as in
+            // `false and $a` or `true or $a`.
+            if matches!(type_value, TypeValue::Bool(None))
+                && [&expr.lhs, &expr.rhs].iter().any(|operand| {
+                    matches!(
+                        operand.type_value().cast_to_bool(),
+                        TypeValue::Bool(Some($absorbing))
+                    )
+                })
+            {
+                type_value = TypeValue::Bool(Some($absorbing));
+            }
+
+            // When only one of the operands is constant, it either decides
+            // the result on its own, or doesn't affect it at all. When both
+            // are constant, the expression as a whole is constant, and it's
+            // up to the enclosing expression to report it.
+            match (
+                expr.lhs.type_value().cast_to_bool(),
+                expr.rhs.type_value().cast_to_bool(),
+            ) {
+                (TypeValue::Bool(Some(value)), TypeValue::Bool(None)) => {
+                    warn_invariant_operand(
+                        ctx,
+                        &expr.lhs,
+                        stringify!($op),
+                        value,
+                        $absorbing,
+                    );
+                }
+                (TypeValue::Bool(None), TypeValue::Bool(Some(value))) => {
+                    warn_invariant_operand(
+                        ctx,
+                        &expr.rhs,
+                        stringify!($op),
+                        value,
+                        $absorbing,
+                    );
+                }
+                _ => {}
+            }
+
             let ty = type_value.ty();
 
             expr.set_type_value(type_value);
@@ -143,11 +198,11 @@ macro_rules! gen_semcheck_boolean_op {
     };
 }
 
-gen_semcheck_boolean_op!(semcheck_boolean_and, and);
-gen_semcheck_boolean_op!(semcheck_boolean_or, or);
+gen_semcheck_boolean_op!(semcheck_boolean_and, and, false);
+gen_semcheck_boolean_op!(semcheck_boolean_or, or, true);
 
 macro_rules! gen_semcheck_comparison_op {
-    ($name:ident, $op:tt) => {
+    ($name:ident, $op:tt, $( $accepted_types:path )|+) => {
         fn $name(
             ctx: &mut Context,
             expr: &mut Box<BinaryExpr>,
@@ -156,14 +211,63 @@ macro_rules! gen_semcheck_comparison_op {
                 ctx,
                 &mut expr.lhs,
                 &mut expr.rhs,
-                // Integers, floats and strings can be compared.
-                Type::Integer | Type::Float | Type::String,
-                // Integers can be compared with floats, but string can be
-                // compared only with another string.
-                &[Type::Integer, Type::Float]
+                $( $accepted_types )|+,
+                // Integers can be compared with floats, but any other type
+                // can be compared only with another value of the same type.
+                promoted_type
             )?;
 
-            let type_value = expr.lhs.type_value().$op(expr.rhs.type_value());
+            let mut type_value =
+                expr.lhs.type_value().$op(expr.rhs.type_value());
+
+            // `filesize` and pattern counts are unknown at compile time, but
+            // they are never negative. When compared with a literal integer
+            // the result may be the same for every possible value, like in
+            // `filesize < 0`. The comparison can only change its result at
+            // the literal value, so evaluating it at zero, at the literal
+            // and right after the literal covers all possible values.
+            if matches!(type_value, TypeValue::Bool(None)) {
+                let samples = |n: i64| {
+                    [0, n, n.saturating_add(1)]
+                        .into_iter()
+                        .filter(|x| *x >= 0)
+                        .map(|x| TypeValue::Integer(Some(x)))
+                };
+
+                let results: Option<Vec<_>> =
+                    match (&expr.lhs, expr.rhs.type_value()) {
+                        (
+                            Expr::Filesize { .. } | Expr::PatternCount(_),
+                            TypeValue::Integer(Some(n)),
+                        ) => Some(
+                            samples(*n)
+                                .map(|x| x.$op(expr.rhs.type_value()))
+                                .collect(),
+                        ),
+                        _ => match (expr.lhs.type_value(), &expr.rhs) {
+                            (
+                                TypeValue::Integer(Some(n)),
+                                Expr::Filesize { .. } | Expr::PatternCount(_),
+                            ) => Some(
+                                samples(*n)
+                                    .map(|x| expr.lhs.type_value().$op(&x))
+                                    .collect(),
+                            ),
+                            _ => None,
+                        },
+                    };
+
+                if let Some(results) = results {
+                    if let TypeValue::Bool(Some(value)) = results[0] {
+                        if results.iter().all(|r| {
+                            matches!(r, TypeValue::Bool(Some(v)) if *v == value)
+                        }) {
+                            type_value = TypeValue::Bool(Some(value));
+                        }
+                    }
+                }
+            }
+
             let ty = type_value.ty();
 
             expr.set_type_value(type_value);
@@ -172,12 +276,42 @@ macro_rules! gen_semcheck_comparison_op {
     };
 }
 
-gen_semcheck_comparison_op!(semcheck_comparison_eq, eq);
-gen_semcheck_comparison_op!(semcheck_comparison_ne, ne);
-gen_semcheck_comparison_op!(semcheck_comparison_gt, gt);
-gen_semcheck_comparison_op!(semcheck_comparison_lt, lt);
-gen_semcheck_comparison_op!(semcheck_comparison_ge, ge);
-gen_semcheck_comparison_op!(semcheck_comparison_le, le);
+// Booleans can be tested for equality, but they can't be ordered.
+gen_semcheck_comparison_op!(
+    semcheck_comparison_eq,
+    eq,
+    Type::Integer | Type::Float | Type::String | Type::Bool
+);
+
+gen_semcheck_comparison_op!(
+    semcheck_comparison_ne,
+    ne,
+    Type::Integer | Type::Float | Type::String | Type::Bool
+);
+
+gen_semcheck_comparison_op!(
+    semcheck_comparison_gt,
+    gt,
+    Type::Integer | Type::Float | Type::String
+);
+
+gen_semcheck_comparison_op!(
+    semcheck_comparison_lt,
+    lt,
+    Type::Integer | Type::Float | Type::String
+);
+
+gen_semcheck_comparison_op!(
+    semcheck_comparison_ge,
+    ge,
+    Type::Integer | Type::Float | Type::String
+);
+
+gen_semcheck_comparison_op!(
+    semcheck_comparison_le,
+    le,
+    Type::Integer | Type::Float | Type::String
+);
 
 macro_rules! gen_semcheck_shift_op {
     ($name:ident, $op:tt) => {
@@ -192,20 +326,17 @@ macro_rules! gen_semcheck_shift_op {
                 &mut expr.lhs,
                 &mut expr.rhs,
                 Type::Integer,
-                &[]
+                promoted_type
             )?;
 
             let rhs_type_value = expr.rhs.type_value();
 
             if let TypeValue::Integer(Some(value)) = rhs_type_value {
                 if *value < 0 {
-                    return Err(Error::CompileError(
-                        CompileError::unexpected_negative_number(
-                            ctx.report_builder,
-                            ctx.src,
-                            span,
-                        ),
-                    ));
+                    return Err(CompileErrorInfo::unexpected_negative_number(
+                        span,
+                    )
+                    .into());
                 }
             }
 
@@ -232,7 +363,7 @@ macro_rules! gen_semcheck_bitwise_op {
                 &mut expr.lhs,
                 &mut expr.rhs,
                 Type::Integer,
-                &[]
+                promoted_type
             )?;
 
             let type_value = expr.lhs.type_value().$op(expr.rhs.type_value());
@@ -260,7 +391,7 @@ macro_rules! gen_semcheck_string_op {
                 &mut expr.lhs,
                 &mut expr.rhs,
                 Type::String,
-                &[]
+                promoted_type
             )?;
 
             let type_value = expr
@@ -292,10 +423,29 @@ macro_rules! gen_semcheck_arithmetic_op {
                 &mut expr.lhs,
                 &mut expr.rhs,
                 $( $accepted_types )|+,
-                &[Type::Integer, Type::Float]
+                promoted_type
              )?;
 
              let type_value = expr.lhs.type_value().$op(expr.rhs.type_value());
+
+             // When both operands are known the result is known too, unless
+             // the operation overflows or divides by zero. Division by zero
+             // is left for the scan time, where it makes the result
+             // undefined, but overflows are an error.
+             if let (
+                 TypeValue::Integer(Some(_)),
+                 TypeValue::Integer(Some(rhs)),
+                 TypeValue::Integer(None),
+             ) = (expr.lhs.type_value(), expr.rhs.type_value(), &type_value)
+             {
+                 if *rhs != 0 {
+                     return Err(CompileErrorInfo::arithmetic_overflow(
+                         expr.span(),
+                     )
+                     .into());
+                 }
+             }
+
              let ty = type_value.ty();
 
              expr.set_type_value(type_value);
@@ -361,7 +511,21 @@ pub(super) fn semcheck_expr(
 ) -> Result<Type, Error> {
     match expr {
         Expr::True { .. } | Expr::False { .. } => Ok(Type::Bool),
-        Expr::Filesize { .. } | Expr::Entrypoint { .. } => Ok(Type::Integer),
+        Expr::Filesize { .. } => Ok(Type::Integer),
+        Expr::Entrypoint { span } => {
+            ctx.warnings.push(
+                WarningInfo::deprecated_feature(
+                    "entrypoint".to_string(),
+                    *span,
+                    Some(
+                        "use `pe.entry_point` or `elf.entry_point` instead"
+                            .to_string(),
+                    ),
+                )
+                .into(),
+            );
+            Ok(Type::Integer)
+        }
 
         Expr::Regexp(_) => Ok(Type::Regexp),
         Expr::Literal(lit) => Ok(lit.ty()),
@@ -371,6 +535,18 @@ pub(super) fn semcheck_expr(
             if let Some(ref mut range) = p.range {
                 semcheck_range(ctx, range)?;
             }
+            require_all_matches(ctx, p.name);
+            Ok(Type::Integer)
+        }
+
+        Expr::CountOf(count_of) => {
+            if !ctx.extensions {
+                return Err(CompileErrorInfo::extension_not_enabled(
+                    "count_of".to_string(),
+                    count_of.span,
+                )
+                .into());
+            }
             Ok(Type::Integer)
         }
 
@@ -380,6 +556,7 @@ pub(super) fn semcheck_expr(
             if let Some(ref mut index) = p.index {
                 check_integer_in_range!(ctx, index, 1, i64::MAX)?;
             }
+            require_all_matches(ctx, p.name);
             Ok(Type::Integer)
         }
 
@@ -393,6 +570,11 @@ pub(super) fn semcheck_expr(
                 }
                 None => {}
             }
+            // `$a` alone depends on the first match only, but `$a at 0`
+            // and `$a in (0..10)` may depend on any other match.
+            if p.anchor.is_some() {
+                require_all_matches(ctx, p.identifier.name);
+            }
             Ok(Type::Bool)
         }
 
@@ -408,7 +590,6 @@ pub(super) fn semcheck_expr(
         }
 
         Expr::Not(expr) => {
-            warn_if_not_bool(ctx, &expr.operand);
             // The `not` operator accepts integers, float and strings because
             // those types can be casted to bool.
             semcheck!(
@@ -416,6 +597,7 @@ pub(super) fn semcheck_expr(
                 Type::Bool | Type::Integer | Type::Float | Type::String,
                 &mut expr.operand
             )?;
+            warn_if_not_bool(ctx, &expr.operand);
             let type_value = expr.operand.type_value().not();
             expr.set_type_value(type_value);
             Ok(Type::Bool)
@@ -452,6 +634,15 @@ pub(super) fn semcheck_expr(
                 &mut expr.operand
             )?;
             let type_value = expr.operand.type_value().minus();
+            // Negating the minimum integer overflows.
+            if let (TypeValue::Integer(Some(_)), TypeValue::Integer(None)) =
+                (expr.operand.type_value(), &type_value)
+            {
+                return Err(CompileErrorInfo::arithmetic_overflow(
+                    expr.span.combine(&expr.operand.span()),
+                )
+                .into());
+            }
             expr.set_type_value(type_value);
             Ok(ty)
         }
@@ -469,7 +660,21 @@ pub(super) fn semcheck_expr(
         Expr::EndsWith(expr) => semcheck_string_endswith(ctx, expr, false),
         Expr::IEndsWith(expr) => semcheck_string_endswith(ctx, expr, true),
         Expr::IEquals(expr) => semcheck_string_equals(ctx, expr, true),
-        Expr::Matches(_expr) => todo!(),
+        Expr::Matches(expr) => {
+            semcheck!(ctx, Type::String, &mut expr.lhs)?;
+            semcheck!(ctx, Type::Regexp, &mut expr.rhs)?;
+
+            // The regexp is compiled here, so that errors in the regexp are
+            // reported during the semantic checks.
+            if let Expr::Regexp(regexp) = &expr.rhs {
+                ctx.regexp_id(regexp)?;
+            }
+
+            let type_value =
+                expr.lhs.type_value().matches(expr.rhs.type_value());
+            expr.set_type_value(type_value);
+            Ok(Type::Bool)
+        }
 
         Expr::Lookup(expr) => {
             semcheck_expr(ctx, &mut expr.primary)?;
@@ -505,15 +710,12 @@ pub(super) fn semcheck_expr(
                     // The type of the key/index expression should correspond
                     // with the type of the map's keys.
                     if key_ty != ty {
-                        return Err(Error::CompileError(
-                            CompileError::wrong_type(
-                                ctx.report_builder,
-                                ctx.src,
-                                format!("`{}`", key_ty),
-                                ty.to_string(),
-                                expr.index.span(),
-                            ),
-                        ));
+                        return Err(CompileErrorInfo::wrong_type(
+                            format!("`{}`", key_ty),
+                            ty.to_string(),
+                            expr.index.span(),
+                        )
+                        .into());
                     }
 
                     // The type of the Lookup expression (i.e: map[key])
@@ -522,19 +724,40 @@ pub(super) fn semcheck_expr(
 
                     Ok(expr.ty())
                 }
-                _ => Err(Error::CompileError(CompileError::wrong_type(
-                    ctx.report_builder,
-                    ctx.src,
+                _ => Err(CompileErrorInfo::wrong_type(
                     format!("`{}` or `{}`", Type::Array, Type::Map),
                     expr.primary.ty().to_string(),
                     expr.primary.span(),
-                ))),
+                )
+                .into()),
             }
         }
         Expr::FieldAccess(expr) => {
-            // The left side operand of a field access operation (i.e: foo.bar)
-            // must be a struct.
-            semcheck!(ctx, Type::Struct, &mut expr.lhs)?;
+            let lhs_span = expr.lhs.span();
+            let lhs_ty = semcheck_expr(ctx, &mut expr.lhs)?;
+
+            // Arrays have a single field, `length`, which is the number of
+            // items in the array.
+            if lhs_ty == Type::Array {
+                if let Expr::Ident(ident) = &mut expr.rhs {
+                    if ident.name == "length" {
+                        ident.type_value = TypeValue::Integer(None);
+                        expr.set_type_value(TypeValue::Integer(None));
+                        return Ok(Type::Integer);
+                    }
+                }
+            }
+
+            // Otherwise, the left side operand of a field access operation
+            // (i.e: foo.bar) must be a struct.
+            if lhs_ty != Type::Struct {
+                return Err(CompileErrorInfo::wrong_type(
+                    ParserError::join_with_or(&[Type::Struct], true),
+                    lhs_ty.to_string(),
+                    lhs_span,
+                )
+                .into());
+            }
 
             // Set `current_struct` to the structure returned by the left-hand
             // operand.
@@ -555,18 +778,70 @@ pub(super) fn semcheck_expr(
 
         Expr::FnCall(fn_call) => semcheck_fn_call(ctx, fn_call),
 
-        Expr::Of(of) => semcheck_of(ctx, of),
+        Expr::Of(of) => {
+            semcheck_loop(ctx, of.span, |ctx| semcheck_of(ctx, of))
+        }
 
-        Expr::ForIn(for_in) => semcheck_for_in(ctx, for_in),
+        Expr::ForIn(for_in) => {
+            semcheck_loop(ctx, for_in.span, |ctx| semcheck_for_in(ctx, for_in))
+        }
 
-        Expr::ForOf(for_of) => {
+        Expr::ForOf(for_of) => semcheck_loop(ctx, for_of.span, |ctx| {
             semcheck_quantifier(ctx, &mut for_of.quantifier)?;
-            semcheck!(ctx, Type::Bool, &mut for_of.condition)?;
+            // Anonymous pattern identifiers in the condition refer to the
+            // patterns in the set.
+            let pattern_ids =
+                patterns_matching(ctx, &for_of.pattern_set).collect();
+            let outer_pattern_ids =
+                mem::replace(&mut ctx.for_of_pattern_ids, pattern_ids);
+            let result = semcheck!(ctx, Type::Bool, &mut for_of.condition);
+            ctx.for_of_pattern_ids = outer_pattern_ids;
+            result?;
             Ok(Type::Bool)
-        }
+        }),
+
+        Expr::InSet(in_set) => semcheck_in_set(ctx, in_set),
     }
 }
 
+fn semcheck_in_set(
+    ctx: &mut Context,
+    in_set: &mut Box<InSet>,
+) -> Result<Type, Error> {
+    if !ctx.extensions {
+        return Err(CompileErrorInfo::extension_not_enabled(
+            format!("in {}", in_set.set.name),
+            in_set.span,
+        )
+        .into());
+    }
+
+    let set_id = ctx.get_string_set(in_set.set.name).ok_or_else(|| {
+        CompileErrorInfo::unknown_string_set(
+            in_set.set.name.to_string(),
+            in_set.set.span,
+        )
+    })?;
+
+    semcheck!(ctx, Type::String, &mut in_set.item)?;
+
+    // If the string is known at compile time, the result is also known.
+    // A string that is not in the literals pool can't be in any set, as
+    // all the strings in sets are stored in the pool.
+    let value = match in_set.item.type_value() {
+        TypeValue::String(Some(s)) => {
+            Some(ctx.lit_pool.get_id(s.as_slice()).is_some_and(|id| {
+                ctx.string_sets[usize::from(set_id)].literals.contains(&id)
+            }))
+        }
+        _ => None,
+    };
+
+    in_set.type_value = TypeValue::Bool(value);
+
+    Ok(Type::Bool)
+}
+
 fn semcheck_range(ctx: &mut Context, range: &mut Range) -> Result<(), Error> {
     semcheck!(ctx, Type::Integer, &mut range.lower_bound)?;
     semcheck!(ctx, Type::Integer, &mut range.upper_bound)?;
@@ -583,8 +858,21 @@ fn semcheck_quantifier(
             check_non_negative_integer!(ctx, expr)?;
         }
         Quantifier::Percentage(expr) => {
-            // Percentage should be in the range 0-100%
-            check_integer_in_range!(ctx, expr, 0, 100)?;
+            semcheck!(ctx, Type::Integer, expr)?;
+            // Percentages outside the range 0-100% are accepted, but they
+            // don't make sense. More than 100% is never reached, and less
+            // than 0% is always reached.
+            if let TypeValue::Integer(Some(percentage)) = expr.type_value() {
+                if !(0..=100).contains(percentage) {
+                    ctx.warnings.push(
+                        WarningInfo::percentage_out_of_range(
+                            *percentage,
+                            expr.span(),
+                        )
+                        .into(),
+                    );
+                }
+            }
         }
         _ => {}
     }
@@ -592,6 +880,28 @@ fn semcheck_quantifier(
     Ok(Type::Integer)
 }
 
+/// Checks a loop with the function `f`, making sure that loops are not
+/// nested more than [`MAX_LOOP_NESTING`] levels deep. Each loop needs some
+/// space in the vars stack, which is limited.
+fn semcheck_loop<F>(ctx: &mut Context, span: Span, f: F) -> Result<Type, Error>
+where
+    F: FnOnce(&mut Context) -> Result<Type, Error>,
+{
+    if ctx.loop_nesting == MAX_LOOP_NESTING {
+        return Err(CompileErrorInfo::too_many_nested_loops(
+            MAX_LOOP_NESTING,
+            span,
+        )
+        .into());
+    }
+
+    ctx.loop_nesting += 1;
+    let result = f(ctx);
+    ctx.loop_nesting -= 1;
+
+    result
+}
+
 fn semcheck_of(ctx: &mut Context, of: &mut Of) -> Result<Type, Error> {
     semcheck_quantifier(ctx, &mut of.quantifier)?;
     // `x of (<boolean expr>, <boolean expr>, ...)`: make sure that all
@@ -612,6 +922,14 @@ fn semcheck_of(ctx: &mut Context, of: &mut Of) -> Result<Type, Error> {
         None => {}
     }
 
+    // Like in `$a at 0`, an anchor may depend on any match of the patterns.
+    if let (OfItems::PatternSet(set), Some(_)) = (&of.items, &of.anchor) {
+        let pattern_ids: Vec<_> = patterns_matching(ctx, set).collect();
+        for pattern_id in pattern_ids {
+            ctx.all_matches_required.set(pattern_id.into(), true);
+        }
+    }
+
     // Compute the number of items in the `of` statement.
     let items_count = match of.items {
         // `x of them`: the number of items is the number of declared patterns
@@ -645,16 +963,58 @@ fn semcheck_of(ctx: &mut Context, of: &mut Of) -> Result<Type, Error> {
     if let Quantifier::Expr(expr) = &of.quantifier {
         if let TypeValue::Integer(Some(value)) = expr.type_value() {
             if *value > items_count {
-                ctx.warnings.push(Warning::invariant_boolean_expression(
-                    ctx.report_builder,
-                    ctx.src,
-                    false,
+                ctx.warnings.push(WarningInfo::invariant_boolean_expression(false,
                     of.span(),
                     Some(format!(
                         "the expression requires {} matching patterns out of {}",
                         *value, items_count
                     )),
-                ));
+                ).into());
+            }
+        }
+    }
+
+    // `any of ($a, $b)`, `none of ($a, $b)` and `1 of ($a, $b)` depend only
+    // on whether some pattern in the set matches. If every match of $b
+    // contains a match of $a, $b can't change the result. Anchored sets
+    // are not considered, the match of $a may start at a different offset.
+    let depends_on_any_match = match &of.quantifier {
+        Quantifier::Any { .. } | Quantifier::None { .. } => true,
+        Quantifier::Expr(expr) => {
+            matches!(expr.type_value(), TypeValue::Integer(Some(1)))
+        }
+        _ => false,
+    };
+
+    if depends_on_any_match && of.anchor.is_none() {
+        if let OfItems::PatternSet(set) = &of.items {
+            let in_set = |ident: &str| match set {
+                PatternSet::Them => true,
+                PatternSet::Set(items) => {
+                    items.iter().any(|item| item.matches(ident))
+                }
+            };
+            // Each shadowed pattern is reported only once, even if it
+            // appears in multiple sets.
+            let mut i = 0;
+            while i < ctx.shadowed_patterns.len() {
+                let shadowed = &ctx.shadowed_patterns[i];
+                if in_set(&shadowed.ident) && in_set(&shadowed.shadowing_ident)
+                {
+                    let shadowed = ctx.shadowed_patterns.remove(i);
+                    ctx.warnings.push(
+                        WarningInfo::shadowed_pattern(
+                            shadowed.ident,
+                            shadowed.shadowing_ident,
+                            shadowed.span,
+                            shadowed.shadowing_span,
+                            of.span(),
+                        )
+                        .into(),
+                    );
+                } else {
+                    i += 1;
+                }
             }
         }
     }
@@ -698,18 +1058,34 @@ fn semcheck_of(ctx: &mut Context, of: &mut Of) -> Result<Type, Error> {
         };
 
         if raise_warning {
-            ctx.warnings.push(Warning::potentially_wrong_expression(
-                ctx.report_builder,
-                ctx.src,
-                of.quantifier.span(),
-                of.anchor.as_ref().unwrap().span(),
-            ));
+            ctx.warnings.push(
+                WarningInfo::potentially_wrong_expression(
+                    of.quantifier.span(),
+                    of.anchor.as_ref().unwrap().span(),
+                )
+                .into(),
+            );
         }
     }
 
     Ok(Type::Bool)
 }
 
+/// Marks the pattern referenced by `ident` (e.g: `#a`, `@a`) as a pattern
+/// whose matches must be all found, see [`crate::Rules::all_matches_required`].
+/// Anonymous identifiers (e.g: `#`, `@`) refer to all the patterns iterated
+/// by the innermost `for .. of` statement.
+fn require_all_matches(ctx: &mut Context, ident: &str) {
+    if ident.len() == 1 {
+        for pattern_id in &ctx.for_of_pattern_ids {
+            ctx.all_matches_required.set((*pattern_id).into(), true);
+        }
+    } else {
+        let pattern_id = ctx.get_pattern_from_current_rule(ident);
+        ctx.all_matches_required.set(pattern_id.into(), true);
+    }
+}
+
 fn semcheck_ident(
     ctx: &mut Context,
     ident: &mut Ident,
@@ -723,14 +1099,87 @@ fn semcheck_ident(
     };
 
     let type_value = if let Some(symbol) = symbol {
+        if let SymbolKind::Rule(rule_id) = symbol.kind {
+            // The rule's symbol is declared before checking its condition,
+            // so the condition can find the rule itself.
+            if rule_id == ctx.current_rule_id {
+                return Err(CompileErrorInfo::self_referencing_rule(
+                    ident.name.to_string(),
+                    ident.span(),
+                )
+                .into());
+            }
+            ctx.unused_private_rules.retain(|(id, _)| *id != rule_id);
+            if !ctx.rule_dependencies.contains(&rule_id) {
+                ctx.rule_dependencies.push(rule_id);
+            }
+            // Enabled rules can't depend on disabled ones, as the condition
+            // of a disabled rule is never evaluated.
+            let rule = &ctx.rules[rule_id.0 as usize];
+            if rule.disabled && !ctx.current_rule.disabled {
+                return Err(CompileErrorInfo::disabled_rule_reference(
+                    ctx.ident_pool
+                        .get(ctx.current_rule.ident_id)
+                        .unwrap()
+                        .to_string(),
+                    ident.name.to_string(),
+                    ident.span(),
+                )
+                .into());
+            }
+        }
+        // Outside of a structure, the only symbols with a field index are
+        // modules.
+        if current_struct.is_none()
+            && matches!(symbol.kind, SymbolKind::FieldIndex(_))
+        {
+            ctx.unused_imports.retain(|(module, _)| module != ident.name);
+        }
         symbol.type_value().clone()
     } else {
-        return Err(Error::CompileError(CompileError::unknown_identifier(
-            ctx.report_builder,
-            ctx.src,
+        if current_struct.is_none() {
+            // Rules can't be used before being defined, so the rules that
+            // appear later in the namespace are not in the symbol table
+            // yet.
+            if let Some(definition_span) = ctx.upcoming_rules.get(ident.name) {
+                return Err(CompileErrorInfo::rule_used_before_definition(
+                    ident.name.to_string(),
+                    ident.span(),
+                    *definition_span,
+                    Some(
+                        "move the definition before the rules that use it"
+                            .to_string(),
+                    ),
+                )
+                .into());
+            }
+            // Rules in other namespaces are not visible either.
+            if let Some(namespace) = rule_namespace(ctx, ident.name) {
+                return Err(CompileErrorInfo::unknown_identifier(
+                    ident.name.to_string(),
+                    ident.span(),
+                    Some(format!(
+                        "`{}` is declared in namespace `{}`",
+                        ident.name, namespace
+                    )),
+                )
+                .into());
+            }
+        }
+        // Suggest the most similar identifier among the fields of the
+        // current structure, or among the symbols visible from here.
+        let candidates = if let Some(structure) = &current_struct {
+            structure.identifiers()
+        } else {
+            ctx.symbol_table.identifiers()
+        };
+        return Err(CompileErrorInfo::unknown_identifier(
             ident.name.to_string(),
             ident.span(),
-        )));
+            most_similar(ident.name, &candidates)
+                .map(|similar| format!("did you mean `{}`?", similar)),
+        )
+        .into());
     };
 
     let ty = type_value.ty();
@@ -739,6 +1188,58 @@ fn semcheck_ident(
     Ok(ty)
 }
 
+/// Returns the namespace of a rule named `ident` that is declared in a
+/// namespace other than the one of the current rule, if any.
+fn rule_namespace<'a>(ctx: &'a Context, ident: &str) -> Option<&'a str> {
+    let ident_id = ctx.ident_pool.get_id(ident)?;
+    ctx.rules
+        .iter()
+        .find(|rule| {
+            rule.ident_id == ident_id
+                && rule.namespace_id != ctx.current_rule.namespace_id
+        })
+        .map(|rule| ctx.ident_pool.get(rule.namespace_id).unwrap())
+}
+
+/// Returns the candidate that is most similar to `ident`, if it is similar
+/// enough to be a likely typo.
+///
+/// Similarity is measured with the Levenshtein distance, and candidates are
+/// accepted only if the distance is at most a third of the length of
+/// `ident`. This means that there are no suggestions for identifiers shorter
+/// than three characters, where almost any other short identifier would be
+/// suggested.
+fn most_similar<'c>(ident: &str, candidates: &'c [String]) -> Option<&'c str> {
+    let max_distance = ident.chars().count() / 3;
+    candidates
+        .iter()
+        .map(|candidate| (levenshtein(ident, candidate), candidate))
+        .filter(|(distance, _)| *distance > 0 && *distance <= max_distance)
+        // Ties are resolved alphabetically, so the result doesn't depend
+        // on the order of the candidates.
+        .min_by(|(d1, c1), (d2, c2)| d1.cmp(d2).then(c1.cmp(c2)))
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+/// Returns the number of single character insertions, deletions or
+/// substitutions required for transforming `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // `row[j]` is the distance between the part of `a` processed so far and
+    // the first `j` characters of `b`.
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
 fn semcheck_for_in(
     ctx: &mut Context,
     for_in: &mut ForIn,
@@ -781,21 +1282,36 @@ fn semcheck_for_in(
     if loop_vars.len() != expected_vars.len() {
         let span = loop_vars.first().unwrap().span();
         let span = span.combine(&loop_vars.last().unwrap().span());
-        return Err(Error::CompileError(CompileError::assignment_mismatch(
-            ctx.report_builder,
-            ctx.src,
+        return Err(CompileErrorInfo::assignment_mismatch(
             loop_vars.len() as u8,
             expected_vars.len() as u8,
             for_in.iterable.span(),
             span,
-        )));
+        )
+        .into());
     }
 
     let mut vars = SymbolTable::new();
 
-    // TODO: raise warning when the loop identifier (e.g: "i") hides
-    // an existing identifier with the same name.
     for (var, type_value) in iter::zip(loop_vars, expected_vars) {
+        // A loop variable can hide other loop variables, but not rules or
+        // modules, the condition would refer to the variable in places
+        // where the rule or module was probably intended. In the symbol
+        // table, the only symbols with a field index outside of a structure
+        // are modules.
+        let kind = match ctx.symbol_table.lookup(var.name).map(|s| s.kind) {
+            Some(SymbolKind::Rule(_)) => Some("rule"),
+            Some(SymbolKind::FieldIndex(_)) => Some("module"),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            return Err(CompileErrorInfo::shadowed_identifier(
+                var.name.to_string(),
+                kind,
+                var.span,
+            )
+            .into());
+        }
         vars.insert(var.name, Symbol::new(type_value));
     }
 
@@ -833,16 +1349,13 @@ fn semcheck_iterable(
                 )?;
                 if let Some((prev_ty, prev_span)) = prev {
                     if prev_ty != ty {
-                        return Err(Error::CompileError(
-                            CompileError::mismatching_types(
-                                ctx.report_builder,
-                                ctx.src,
-                                prev_ty.to_string(),
-                                ty.to_string(),
-                                prev_span,
-                                span,
-                            ),
-                        ));
+                        return Err(CompileErrorInfo::mismatching_types(
+                            prev_ty.to_string(),
+                            ty.to_string(),
+                            prev_span,
+                            span,
+                        )
+                        .into());
                     }
                 }
                 prev = Some((ty, span));
@@ -858,71 +1371,103 @@ fn semcheck_fn_call(
 ) -> Result<Type, Error> {
     semcheck!(ctx, Type::Func, &mut fn_call.callable)?;
 
-    let type_value = if let TypeValue::Func(func) =
-        fn_call.callable.type_value()
-    {
-        // Validate the expressions passed as arguments to the function, and
-        // collect their types.
-        let provided_arg_types: Vec<Type> = fn_call
-            .args
-            .iter_mut()
-            .map(|arg| semcheck_expr(ctx, arg))
-            .collect::<Result<_, _>>()?;
-
-        let mut expected_args = Vec::new();
-        let mut matching_signature = None;
-
-        // Determine if any of the signatures for the called function matches
-        // the provided arguments.
-        for (i, signature) in func.signatures().iter().enumerate() {
-            let expected_arg_types: Vec<Type> =
-                signature.args.iter().map(|arg| arg.ty()).collect();
-
-            if provided_arg_types == expected_arg_types {
-                fn_call.fn_signature_index = Some(i);
-                matching_signature = Some(signature);
-                break;
-            }
+    let type_value =
+        if let TypeValue::Func(func) = fn_call.callable.type_value() {
+            // Validate the expressions passed as arguments to the function, and
+            // collect their types.
+            let provided_arg_types: Vec<Type> = fn_call
+                .args
+                .iter_mut()
+                .map(|arg| semcheck_expr(ctx, arg))
+                .collect::<Result<_, _>>()?;
+
+            let mut expected_args = Vec::new();
+            let mut matching_signature = None;
+
+            // Determine if any of the signatures for the called function matches
+            // the provided arguments.
+            for (i, signature) in func.signatures().iter().enumerate() {
+                let expected_arg_types: Vec<Type> =
+                    signature.args.iter().map(|arg| arg.ty()).collect();
+
+                if provided_arg_types == expected_arg_types {
+                    fn_call.fn_signature_index = Some(i);
+                    matching_signature = Some(signature);
+                    break;
+                }
 
-            expected_args.push(expected_arg_types);
-        }
+                expected_args.push(expected_arg_types);
+            }
 
-        if let Some(matching_signature) = matching_signature {
-            matching_signature.result.clone()
+            if let Some(matching_signature) = matching_signature {
+                matching_signature.result.clone()
+            } else {
+                // No matching signature was found, that means that the arguments
+                // provided were incorrect.
+                return Err(CompileErrorInfo::wrong_arguments(
+                    (&fn_call.args).span(),
+                    Some(format!(
+                        "accepted argument combinations:\n\n             {}",
+                        expected_args
+                            .iter()
+                            .map(|v| {
+                                format!(
+                                    "({})",
+                                    v.iter()
+                                        .map(|i| i.to_string())
+                                        .collect::<Vec<String>>()
+                                        .join(", ")
+                                )
+                            })
+                            .collect::<Vec<String>>()
+                            .join("\n             ")
+                    )),
+                )
+                .into());
+            }
         } else {
-            // No matching signature was found, that means that the arguments
-            // provided were incorrect.
-            return Err(Error::CompileError(CompileError::wrong_arguments(
-                ctx.report_builder,
-                ctx.src,
-                (&fn_call.args).span(),
-                Some(format!(
-                    "accepted argument combinations:\n\n             {}",
-                    expected_args
-                        .iter()
-                        .map(|v| {
-                            format!(
-                                "({})",
-                                v.iter()
-                                    .map(|i| i.to_string())
-                                    .collect::<Vec<String>>()
-                                    .join(", ")
-                            )
-                        })
-                        .collect::<Vec<String>>()
-                        .join("\n             ")
-                )),
-            )));
-        }
-    } else {
-        unreachable!()
-    };
+            unreachable!()
+        };
 
     let ty = type_value.ty();
     fn_call.set_type_value(type_value);
     Ok(ty)
 }
 
+/// Raises a warning about an operand of a boolean operation (`and` or `or`)
+/// that is always `value`, while the other operand is not constant.
+///
+/// If `value` is the one that determines the result of the operation by
+/// itself (`false` for `and`, `true` for `or`), the other operand never
+/// affects the result. If not, it's the constant operand the one that
+/// doesn't affect the result.
+fn warn_invariant_operand(
+    ctx: &mut Context,
+    operand: &Expr,
+    op: &str,
+    value: bool,
+    absorbing: bool,
+) {
+    let note = if value == absorbing {
+        format!(
+            "the other operand of `{op}` doesn't affect the result, which is always {value}"
+        )
+    } else {
+        format!(
+            "this operand doesn't affect the result of `{op}`, which depends only on the other operand"
+        )
+    };
+
+    ctx.warnings.push(
+        WarningInfo::invariant_boolean_expression(
+            value,
+            operand.span(),
+            Some(note),
+        )
+        .into(),
+    );
+}
+
 /// If `expr` is not of type boolean, it raises a warning indicating that the
 /// expression is being casted to a boolean.
 pub(super) fn warn_if_not_bool(ctx: &mut Context, expr: &Expr) {
@@ -944,12 +1489,8 @@ pub(super) fn warn_if_not_bool(ctx: &mut Context, expr: &Expr) {
     };
 
     if !matches!(ty, Type::Bool) {
-        ctx.warnings.push(Warning::non_boolean_as_boolean(
-            ctx.report_builder,
-            ctx.src,
-            ty,
-            expr.span(),
-            note,
-        ));
+        ctx.warnings.push(
+            WarningInfo::non_boolean_as_boolean(ty, expr.span(), note).into(),
+        );
     }
 }
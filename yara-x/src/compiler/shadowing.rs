@@ -0,0 +1,203 @@
+/*! Detection of patterns shadowed by other patterns in the same rule.
+
+A pattern `$b` is shadowed by another pattern `$a` when every match of `$b`
+contains a match of `$a`, like in `$a = "malware"` and
+`$b = "malware detected"`. In that case `$b` can't change the result of
+expressions like `any of ($a, $b)`, as any time `$b` matches, `$a` matches
+too.
+
+The analysis works with the sequences of bytes and masks that patterns
+match, and it's conservative: when the relationship between two patterns
+can't be established exactly, they are not reported.
+*/
+
+use yara_x_parser::ast::{HexToken, HexTokens, Pattern, Span};
+
+/// Maximum number of variants a pattern is expanded into. Hex patterns
+/// with alternatives produce one variant for each combination of
+/// alternatives, patterns with more variants than this are not analyzed.
+const MAX_VARIANTS: usize = 64;
+
+/// Maximum length of a pattern variant. Patterns with longer variants, for
+/// instance because of large jumps, are not analyzed.
+const MAX_VARIANT_LEN: usize = 4096;
+
+/// A pattern shadowed by some other pattern in the same rule.
+pub(super) struct ShadowedPattern {
+    /// Identifier of the shadowed pattern.
+    pub ident: String,
+    /// Span of the shadowed pattern's identifier.
+    pub span: Span,
+    /// Identifier of the pattern that shadows the other one.
+    pub shadowing_ident: String,
+    /// Span of the shadowing pattern's identifier.
+    pub shadowing_span: Span,
+}
+
+/// A sequence of bytes, each one with a mask that indicates which bits
+/// must match. A byte `b` in the scanned data matches `(value, mask)` if
+/// `b & mask == value & mask`.
+type Variant = Vec<(u8, u8)>;
+
+/// Returns the patterns in `patterns` that are shadowed by some other
+/// pattern in the same slice.
+///
+/// When two patterns match exactly the same data, both shadow each other,
+/// but only the one declared last is returned.
+pub(super) fn shadowed_patterns(patterns: &[Pattern]) -> Vec<ShadowedPattern> {
+    let variants: Vec<_> = patterns.iter().map(variants).collect();
+    let mut result = Vec::new();
+
+    for (i, pattern) in patterns.iter().enumerate() {
+        let Some(pattern_variants) = &variants[i] else {
+            continue;
+        };
+        for (j, other) in patterns.iter().enumerate() {
+            if i == j || other.modifiers().fullword().is_some() {
+                continue;
+            }
+            let Some(other_variants) = &variants[j] else {
+                continue;
+            };
+            if !shadows(other_variants, pattern_variants) {
+                continue;
+            }
+            // Patterns that shadow each other are equivalent, only the
+            // last one is reported.
+            if j > i && shadows(pattern_variants, other_variants) {
+                continue;
+            }
+            result.push(ShadowedPattern {
+                ident: pattern.identifier().name.to_string(),
+                span: pattern.identifier().span,
+                shadowing_ident: other.identifier().name.to_string(),
+                shadowing_span: other.identifier().span,
+            });
+            break;
+        }
+    }
+
+    result
+}
+
+/// Returns true if every string matching some of the `shadowed` variants
+/// contains a string matching some of the `shadowing` variants.
+fn shadows(shadowing: &[Variant], shadowed: &[Variant]) -> bool {
+    shadowed.iter().all(|shadowed| {
+        shadowing.iter().any(|shadowing| contains(shadowed, shadowing))
+    })
+}
+
+/// Returns true if every string matching `haystack` contains a string
+/// matching `needle`. This happens when `needle` is aligned with some
+/// part of `haystack` where every bit fixed by `needle` is also fixed, and
+/// with the same value, by `haystack`.
+fn contains(haystack: &Variant, needle: &Variant) -> bool {
+    if needle.len() > haystack.len() {
+        return false;
+    }
+    haystack.windows(needle.len()).any(|window| {
+        window.iter().zip(needle).all(
+            |((h_value, h_mask), (n_value, n_mask))| {
+                n_mask & !h_mask == 0 && (h_value ^ n_value) & n_mask == 0
+            },
+        )
+    })
+}
+
+/// Returns all the variants of a pattern, or [`None`] if the pattern can't
+/// be described by a reasonable number of fixed-length variants.
+fn variants(pattern: &Pattern) -> Option<Vec<Variant>> {
+    let modifiers = pattern.modifiers();
+
+    if modifiers.xor().is_some()
+        || modifiers.base64().is_some()
+        || modifiers.base64wide().is_some()
+    {
+        return None;
+    }
+
+    match pattern {
+        Pattern::Text(p) => {
+            let nocase = modifiers.nocase().is_some();
+            let ascii: Variant = p
+                .value
+                .iter()
+                .map(|b| {
+                    if nocase && b.is_ascii_alphabetic() {
+                        (*b, !0x20)
+                    } else {
+                        (*b, 0xff)
+                    }
+                })
+                .collect();
+
+            let mut variants = Vec::with_capacity(2);
+
+            if modifiers.wide().is_some() {
+                variants.push(
+                    ascii.iter().flat_map(|b| [*b, (0x00, 0xff)]).collect(),
+                );
+            }
+            if modifiers.ascii().is_some() || modifiers.wide().is_none() {
+                variants.push(ascii);
+            }
+
+            Some(variants)
+        }
+        Pattern::Hex(p) => hex_variants(&p.tokens),
+        Pattern::Regexp(_) => None,
+    }
+}
+
+/// Returns the variants of a hex pattern.
+fn hex_variants(tokens: &HexTokens) -> Option<Vec<Variant>> {
+    let mut variants = vec![Variant::new()];
+
+    for token in &tokens.tokens {
+        match token {
+            HexToken::Byte(byte) => {
+                for variant in variants.iter_mut() {
+                    variant.push((byte.value & byte.mask, byte.mask));
+                }
+            }
+            // A negated byte can't be described with a mask.
+            HexToken::NotByte(_) => return None,
+            // Only jumps of fixed length, like `[2]`, are supported. They
+            // are equivalent to the same number of `??`.
+            HexToken::Jump(jump) => match (jump.start, jump.end) {
+                (Some(start), Some(end)) if start == end => {
+                    for variant in variants.iter_mut() {
+                        variant.extend((0..start).map(|_| (0x00, 0x00)));
+                    }
+                }
+                _ => return None,
+            },
+            HexToken::Alternative(alt) => {
+                let mut alternatives = Vec::new();
+                for alternative in &alt.alternatives {
+                    alternatives.extend(hex_variants(alternative)?);
+                }
+                if variants.len() * alternatives.len() > MAX_VARIANTS {
+                    return None;
+                }
+                variants = variants
+                    .iter()
+                    .flat_map(|variant| {
+                        alternatives.iter().map(move |alternative| {
+                            let mut v = variant.clone();
+                            v.extend(alternative);
+                            v
+                        })
+                    })
+                    .collect();
+            }
+        }
+
+        if variants.iter().any(|v| v.len() > MAX_VARIANT_LEN) {
+            return None;
+        }
+    }
+
+    Some(variants)
+}
@@ -0,0 +1,84 @@
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{BuildOptions, Compiler, NamingPolicyVerdict};
+
+#[test]
+fn default_build_options() {
+    let mut compiler = Compiler::new();
+    compiler.add_source("rule test { condition: true }").unwrap();
+    let rules = compiler.build().unwrap();
+
+    assert_eq!(
+        rules.build_options(),
+        &BuildOptions {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            compile_time_optimization: cfg!(
+                feature = "compile-time-optimization"
+            ),
+            extensions: false,
+            force_enable_rules: false,
+            errors_on_warnings: false,
+            detect_shadowed_patterns: false,
+            naming_policy: false,
+            disabled_warnings: vec![],
+            string_sets: vec![],
+        }
+    );
+}
+
+#[test]
+fn build_options() {
+    let mut compiler = Compiler::new();
+    compiler
+        .enable_extensions(true)
+        .force_enable_rules(true)
+        .detect_shadowed_patterns(true)
+        .naming_policy(|_, _, _| NamingPolicyVerdict::Accept)
+        .disable_warning("percentage_out_of_range")
+        .unwrap()
+        .disable_warning("duplicate_tag")
+        .unwrap()
+        .define_string_set("secret_domains", ["evil.com"])
+        .unwrap()
+        .define_string_set("hosts", ["localhost"])
+        .unwrap()
+        .add_source("rule test { condition: true }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let options = rules.build_options();
+
+    assert!(options.extensions);
+    assert!(options.force_enable_rules);
+    assert!(!options.errors_on_warnings);
+    assert!(options.detect_shadowed_patterns);
+    assert!(options.naming_policy);
+    assert_eq!(
+        options.disabled_warnings,
+        ["duplicate_tag", "percentage_out_of_range"]
+    );
+    assert_eq!(options.string_sets, ["secret_domains", "hosts"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn build_options_serde() {
+    let mut compiler = Compiler::new();
+    compiler
+        .enable_extensions(true)
+        .define_string_set("domains", ["evil.com"])
+        .unwrap()
+        .add_source(r#"rule test { condition: "evil.com" in domains }"#)
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let json = serde_json::to_string(rules.build_options()).unwrap();
+
+    // String sets are recorded by name, their contents are never included.
+    assert!(json.contains("domains"));
+    assert!(!json.contains("evil.com"));
+
+    let options: BuildOptions = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(&options, rules.build_options());
+}
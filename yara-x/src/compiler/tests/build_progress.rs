@@ -0,0 +1,145 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use crate::compiler::{
+    BuildPhase, BuildProgress, CancellationToken, Compiler, Error,
+    CANCELLED_COMPILATIONS,
+};
+use crate::scanner::Scanner;
+
+/// Returns a set of rules large enough for taking a noticeable time to
+/// compile to native code.
+fn large_source() -> String {
+    (0..500)
+        .map(|i| {
+            format!(
+                "rule test_{i} {{ strings: $a = \"foo{i}\" condition: $a and filesize < {i} }}\n"
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn build_progress() {
+    let mut progress = Vec::new();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .build_progress(|p| progress.push(*p))
+        .add_source("rule test { condition: true }")
+        .unwrap();
+    compiler.build().unwrap();
+
+    // Progress reports without a known fraction may appear while the native
+    // code is being compiled, depending on how long it takes.
+    progress.retain(|p| p.fraction.is_some());
+
+    assert_eq!(
+        progress,
+        [
+            BuildProgress {
+                phase: BuildPhase::WasmFinalization,
+                fraction: Some(0.0)
+            },
+            BuildProgress {
+                phase: BuildPhase::WasmFinalization,
+                fraction: Some(1.0)
+            },
+            BuildProgress {
+                phase: BuildPhase::NativeCompilation,
+                fraction: Some(0.0)
+            },
+            BuildProgress {
+                phase: BuildPhase::NativeCompilation,
+                fraction: Some(1.0)
+            },
+        ]
+    );
+}
+
+#[test]
+fn cancel_build() {
+    let source = large_source();
+    let token = CancellationToken::new();
+    let cancelled_at = Cell::new(None);
+
+    // Cancel the build as soon as the native compilation starts.
+    let mut compiler = Compiler::new();
+    compiler
+        .cancellation_token(token.clone())
+        .build_progress(|p| {
+            if p.phase == BuildPhase::NativeCompilation
+                && p.fraction == Some(0.0)
+            {
+                token.cancel();
+                cancelled_at.set(Some(Instant::now()));
+            }
+        })
+        .add_source(source.as_str())
+        .unwrap();
+    let result = compiler.build();
+
+    assert!(matches!(result, Err(Error::Cancelled)));
+    assert!(
+        cancelled_at.get().unwrap().elapsed() < Duration::from_secs(1),
+        "build didn't return in time after being cancelled"
+    );
+
+    // The native code of cancelled builds is still being compiled, but
+    // each build waits for the previous ones, so there's at most one
+    // of them at a time.
+    for _ in 0..3 {
+        let token = CancellationToken::new();
+        let mut compiler = Compiler::new();
+        compiler
+            .cancellation_token(token.clone())
+            .build_progress(|p| {
+                if p.phase == BuildPhase::NativeCompilation
+                    && p.fraction == Some(0.0)
+                {
+                    token.cancel();
+                }
+            })
+            .add_source(source.as_str())
+            .unwrap();
+
+        assert!(matches!(compiler.build(), Err(Error::Cancelled)));
+        assert!(CANCELLED_COMPILATIONS.lock().unwrap().len() <= 1);
+    }
+
+    // A cancelled build leaves nothing behind, the same rules can be built
+    // again and used for scanning.
+    let mut compiler = Compiler::new();
+    compiler
+        .cancellation_token(CancellationToken::new())
+        .add_source(source.as_str())
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    assert!(CANCELLED_COMPILATIONS.lock().unwrap().is_empty());
+
+    let mut scanner = Scanner::new(&rules);
+
+    // `test_19` and `test_199` match, `test_1` doesn't because of the file
+    // size.
+    assert_eq!(scanner.scan(b"foo199").num_matching_rules(), 2);
+}
+
+#[test]
+fn cancel_build_before_start() {
+    let token = CancellationToken::new();
+    let mut progress = Vec::new();
+
+    token.cancel();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .cancellation_token(token)
+        .build_progress(|p| progress.push(*p))
+        .add_source("rule test { condition: true }")
+        .unwrap();
+    let result = compiler.build();
+
+    assert!(matches!(result, Err(Error::Cancelled)));
+    assert!(progress.is_empty());
+}
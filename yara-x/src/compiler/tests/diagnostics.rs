@@ -0,0 +1,232 @@
+use pretty_assertions::assert_eq;
+use yara_x_parser::SourceCode;
+
+use crate::compiler::{
+    Compiler, Diagnostic, DiagnosticsBundle, DiagnosticsError,
+    DiagnosticsReader, Error, Severity,
+};
+
+/// Compiles a large number of rules that produce warnings and errors, and
+/// returns the resulting diagnostics.
+fn synthetic_diagnostics() -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut compiler = Compiler::new();
+
+    for i in 0..100 {
+        let mut src = String::new();
+        for j in 0..20 {
+            // Each rule produces two warnings, one of them spanning
+            // multiple lines.
+            src.push_str(&format!(
+                "rule rule_{i}_{j} : foo foo {{\n  condition:\n    (1 +\n     {j})\n}}\n"
+            ));
+        }
+        compiler
+            .add_source(
+                SourceCode::from(src.as_str())
+                    .origin(format!("rules/ñandú_{i}.yar").as_str()),
+            )
+            .unwrap();
+    }
+
+    diagnostics.extend(compiler.warnings().iter().map(Diagnostic::from));
+
+    for i in 0..100 {
+        let src = format!(
+            "rule error_{i}_a {{ condition: \"𝄞\" == {i} }}\nrule error_{i}_b {{ condition: foo_{i} }}"
+        );
+        let Err(Error::CompileErrors(errors)) = Compiler::new().add_source(
+            SourceCode::from(src.as_str())
+                .origin(format!("errors/{i}.yar").as_str()),
+        ) else {
+            panic!("expected multiple compile errors");
+        };
+        diagnostics.extend(errors.as_slice().iter().map(Diagnostic::from));
+    }
+
+    diagnostics
+}
+
+fn encode(diagnostics: &[Diagnostic]) -> Vec<u8> {
+    let mut bundle = DiagnosticsBundle::new();
+    for diagnostic in diagnostics {
+        bundle.push(diagnostic);
+    }
+    assert_eq!(bundle.len(), diagnostics.len());
+    bundle.encode()
+}
+
+#[test]
+fn round_trip() {
+    let diagnostics = synthetic_diagnostics();
+    assert_eq!(diagnostics.len(), 4200);
+
+    let bytes = encode(&diagnostics);
+    let reader = DiagnosticsReader::new(&bytes).unwrap();
+
+    assert_eq!(reader.len(), diagnostics.len());
+
+    let decoded: Vec<_> = reader.iter().map(Result::unwrap).collect();
+
+    assert_eq!(decoded, diagnostics);
+
+    // Diagnostics can be decoded in any order.
+    assert_eq!(reader.get(4199).unwrap(), diagnostics[4199]);
+    assert_eq!(reader.get(0).unwrap(), diagnostics[0]);
+
+    // Labels that point to some other source code keep their origin.
+    let Err(Error::CompileError(err)) = Compiler::new()
+        .add_source(SourceCode::from("rule a { condition: true }").origin("a"))
+        .unwrap()
+        .add_source(
+            SourceCode::from("rule a { condition: true }").origin("b"),
+        )
+    else {
+        panic!("expected a compile error");
+    };
+
+    let diagnostic = Diagnostic::from(&err);
+    let bytes = encode(std::slice::from_ref(&diagnostic));
+    let decoded = DiagnosticsReader::new(&bytes).unwrap().get(0).unwrap();
+
+    assert_eq!(decoded.labels()[1].origin(), Some("a"));
+    assert_eq!(decoded, diagnostic);
+
+    let empty = DiagnosticsBundle::new().encode();
+    assert!(DiagnosticsReader::new(&empty).unwrap().is_empty());
+}
+
+#[test]
+fn filter() {
+    let diagnostics = synthetic_diagnostics();
+    let bytes = encode(&diagnostics);
+    let reader = DiagnosticsReader::new(&bytes).unwrap();
+
+    let errors: Vec<_> = reader
+        .filter(|severity, _| severity == Severity::Error)
+        .map(Result::unwrap)
+        .collect();
+
+    assert_eq!(errors.len(), 200);
+    assert!(errors.iter().all(|err| err.severity() == Severity::Error));
+
+    let unknown_identifiers: Vec<_> = reader
+        .filter(|_, code| code == "unknown_identifier")
+        .map(Result::unwrap)
+        .collect();
+
+    assert_eq!(unknown_identifiers.len(), 100);
+    assert_eq!(unknown_identifiers[7].origin(), Some("errors/7.yar"));
+    assert_eq!(unknown_identifiers[7].title(), "unknown identifier `foo_7`");
+
+    assert_eq!(reader.filter(|_, code| code == "duplicate_tag").count(), 2000);
+}
+
+#[test]
+fn malformed() {
+    let diagnostics = synthetic_diagnostics();
+    let bytes = encode(&diagnostics[..50]);
+
+    assert_eq!(
+        DiagnosticsReader::new(b"YRX").err(),
+        Some(DiagnosticsError::InvalidFormat)
+    );
+
+    assert_eq!(
+        DiagnosticsReader::new(b"YRXD\x03").err(),
+        Some(DiagnosticsError::UnsupportedVersion(3))
+    );
+
+    // Counts larger than the data don't cause huge allocations.
+    assert_eq!(
+        DiagnosticsReader::new(b"YRXD\x01\xff\xff\xff\xff\xff\xff\xff\x7f")
+            .err(),
+        Some(DiagnosticsError::Malformed)
+    );
+
+    // Varints that overflow 64 bits.
+    assert_eq!(
+        DiagnosticsReader::new(
+            b"YRXD\x01\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f"
+        )
+        .err(),
+        Some(DiagnosticsError::Malformed)
+    );
+
+    // A string index out of the table.
+    let reader =
+        DiagnosticsReader::new(b"YRXD\x01\x00\x01\x04\x00\x05\x00\x00")
+            .unwrap();
+    assert_eq!(reader.get(0).err(), Some(DiagnosticsError::Malformed));
+    assert_eq!(
+        reader.filter(|_, _| true).next(),
+        Some(Err(DiagnosticsError::Malformed))
+    );
+
+    // Truncated bundles are rejected.
+    for len in 0..bytes.len() {
+        assert!(DiagnosticsReader::new(&bytes[..len]).is_err());
+    }
+
+    // Corrupted bundles are either rejected or produce some diagnostics,
+    // but never panic.
+    let mut state = 0x2545f4914f6cdd1d_u64;
+    for _ in 0..2000 {
+        let mut corrupted = bytes.clone();
+        for _ in 0..4 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let i = 5 + (state as usize) % (corrupted.len() - 5);
+            corrupted[i] = (state >> 32) as u8;
+        }
+        if let Ok(reader) = DiagnosticsReader::new(&corrupted) {
+            for diagnostic in reader.iter() {
+                let _ = diagnostic;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            SourceCode::from("rule test : foo foo { condition: 1 }")
+                .origin("test.yar"),
+        )
+        .unwrap();
+
+    let diagnostics: Vec<_> =
+        compiler.warnings().iter().map(Diagnostic::from).collect();
+
+    let bytes = encode(&diagnostics);
+    let reader = DiagnosticsReader::new(&bytes).unwrap();
+
+    // A decoded diagnostic serializes like the original warning, except
+    // that it has a severity and doesn't have the text-mode report.
+    for (warning, decoded) in compiler.warnings().iter().zip(reader.iter()) {
+        let mut decoded = serde_json::to_value(decoded.unwrap()).unwrap();
+        let mut warning = serde_json::to_value(warning).unwrap();
+        assert_eq!(
+            decoded.as_object_mut().unwrap().remove("severity"),
+            Some(serde_json::json!("warning"))
+        );
+        assert!(warning.as_object_mut().unwrap().remove("report").is_some());
+        assert_eq!(decoded, warning);
+    }
+
+    // The binary encoding is at least 10 times smaller than JSON.
+    let diagnostics = synthetic_diagnostics();
+    let json = serde_json::to_vec(&diagnostics).unwrap();
+    let bytes = encode(&diagnostics);
+
+    assert!(
+        bytes.len() * 10 <= json.len(),
+        "binary: {} bytes, JSON: {} bytes",
+        bytes.len(),
+        json.len()
+    );
+}
@@ -1,6 +1,7 @@
 use pretty_assertions::assert_eq;
 
-use crate::compiler::Compiler;
+use crate::compiler::{Compiler, Error};
+use crate::scanner::Scanner;
 
 #[test]
 fn errors() {
@@ -107,6 +108,40 @@ rule test {
    ·                   ─┬  
    ·                    ╰── this number can not be negative
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition: 0x4d & "M" == 0x4d
+}
+"#,
+            r#"error: wrong type
+   ╭─[line:3:21]
+   │
+ 3 │   condition: 0x4d & "M" == 0x4d
+   ·                     ─┬─  
+   ·                      ╰─── expression should be `integer`, but is `string`
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition: ~1.0 == 0
+}
+"#,
+            r#"error: wrong type
+   ╭─[line:3:15]
+   │
+ 3 │   condition: ~1.0 == 0
+   ·               ─┬─  
+   ·                ╰─── expression should be `integer`, but is `float`
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -151,13 +186,15 @@ rule test {
             line!(),
             r#"
 rule test {
+  strings:
+    $a = "foo"
   condition: $a at "1"
 }
 "#,
             r#"error: wrong type
-   ╭─[line:3:20]
+   ╭─[line:5:20]
    │
- 3 │   condition: $a at "1"
+ 5 │   condition: $a at "1"
    ·                    ─┬─  
    ·                     ╰─── expression should be `integer`, but is `string`
 ───╯
@@ -168,13 +205,15 @@ rule test {
             line!(),
             r#"
 rule test {
+  strings:
+    $a = "foo"
   condition: @a["1"] == 0x100
 }
 "#,
             r#"error: wrong type
-   ╭─[line:3:17]
+   ╭─[line:5:17]
    │
- 3 │   condition: @a["1"] == 0x100
+ 5 │   condition: @a["1"] == 0x100
    ·                 ─┬─  
    ·                  ╰─── expression should be `integer`, but is `string`
 ───╯
@@ -185,13 +224,15 @@ rule test {
             line!(),
             r#"
 rule test {
+  strings:
+    $a = "foo"
   condition: @a[0]
 }
         "#,
             r#"error: number out of range
-   ╭─[line:3:17]
+   ╭─[line:5:17]
    │
- 3 │   condition: @a[0]
+ 5 │   condition: @a[0]
    ·                 ┬  
    ·                 ╰── this number is out of the allowed range [1-9223372036854775807]
 ───╯
@@ -202,12 +243,62 @@ rule test {
             line!(),
             r#"
 rule test {
+  condition: 0x7fffffffffffffff + 1 > 0
+}"#,
+            r#"error: arithmetic overflow
+   ╭─[line:3:14]
+   │
+ 3 │   condition: 0x7fffffffffffffff + 1 > 0
+   ·              ───────────┬──────────  
+   ·                         ╰──────────── the result of this expression doesn't fit in a 64-bit integer
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition: filesize < 2 * (-0x7fffffffffffffff - 1)
+}"#,
+            r#"error: arithmetic overflow
+   ╭─[line:3:25]
+   │
+ 3 │   condition: filesize < 2 * (-0x7fffffffffffffff - 1)
+   ·                         ──────────────┬─────────────  
+   ·                                       ╰─────────────── the result of this expression doesn't fit in a 64-bit integer
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition: -(-0x7fffffffffffffff - 1) > 0
+}"#,
+            r#"error: arithmetic overflow
+   ╭─[line:3:14]
+   │
+ 3 │   condition: -(-0x7fffffffffffffff - 1) > 0
+   ·              ────────────┬────────────  
+   ·                          ╰────────────── the result of this expression doesn't fit in a 64-bit integer
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
   condition: !a[-1]
 }"#,
             r#"error: number out of range
-   ╭─[line:3:17]
+   ╭─[line:5:17]
    │
- 3 │   condition: !a[-1]
+ 5 │   condition: !a[-1]
    ·                 ─┬  
    ·                  ╰── this number is out of the allowed range [1-9223372036854775807]
 ───╯
@@ -218,13 +309,15 @@ rule test {
             line!(),
             r#"
 rule test {
+  strings:
+    $a = "foo"
   condition: #a in (0.."10")
 }
         "#,
             r#"error: wrong type
-   ╭─[line:3:24]
+   ╭─[line:5:24]
    │
- 3 │   condition: #a in (0.."10")
+ 5 │   condition: #a in (0.."10")
    ·                        ──┬─  
    ·                          ╰─── expression should be `integer`, but is `string`
 ───╯
@@ -288,26 +381,6 @@ rule test {
    ·     ─┬─  
    ·      ╰─── expression should be `integer`, but is `string`
 ───╯
-"#,
-        ),
-        ////////////////////////////////////////////////////////////
-        (
-            line!(),
-            r#"
-rule test {
-  strings:
-    $a = "foo"
-  condition:
-    101% of them
-}
-        "#,
-            r#"error: number out of range
-   ╭─[line:6:5]
-   │
- 6 │     101% of them
-   ·     ─┬─  
-   ·      ╰─── this number is out of the allowed range [0-100]
-───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -363,6 +436,26 @@ rule test {
    ·     ────────┬───────  
    ·             ╰───────── this identifier has not been declared
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    count_of(them) > 0
+}
+"#,
+            r#"error: `count_of` is a YARA-X extension
+   ╭─[line:6:5]
+   │
+ 6 │     count_of(them) > 0
+   ·     ───────┬──────  
+   ·            ╰──────── this is not supported by YARA, enable extensions to use it
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -481,6 +574,58 @@ rule test {
    ·       ┬  
    ·       ╰── this identifier has not been declared
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule is_malware { condition: true }
+rule test { condition: is_malwar }
+"#,
+            r#"error: unknown identifier `is_malwar`
+   ╭─[line:3:24]
+   │
+ 3 │ rule test { condition: is_malwar }
+   ·                        ────┬────  
+   ·                            ╰────── this identifier has not been declared
+   · 
+   · Note: did you mean `is_malware`?
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        #[cfg(feature = "test_proto2-module")]
+        (
+            line!(),
+            r#"
+import "test_proto2"
+rule test { condition: test_proto2.int64_onee == 1 }
+"#,
+            r#"error: unknown identifier `int64_onee`
+   ╭─[line:3:36]
+   │
+ 3 │ rule test { condition: test_proto2.int64_onee == 1 }
+   ·                                    ─────┬────  
+   ·                                         ╰────── this identifier has not been declared
+   · 
+   · Note: did you mean `int64_one`?
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test { condition: unknown_function(1) }
+"#,
+            r#"error: unknown identifier `unknown_function`
+   ╭─[line:2:24]
+   │
+ 2 │ rule test { condition: unknown_function(1) }
+   ·                        ────────┬───────  
+   ·                                ╰───────── this identifier has not been declared
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -704,6 +849,26 @@ rule test {
             line!(),
             r#"
 import "test_proto2"
+rule test {
+  condition:
+    test_proto2.map_int64_struct["foo"].nested_int64_one == 1
+}
+"#,
+            r#"error: wrong type
+   ╭─[line:5:34]
+   │
+ 5 │     test_proto2.map_int64_struct["foo"].nested_int64_one == 1
+   ·                                  ──┬──  
+   ·                                    ╰──── expression should be `integer`, but is `string`
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        #[cfg(feature = "test_proto2-module")]
+        (
+            line!(),
+            r#"
+import "test_proto2"
 rule test {
   condition:
     test_proto2(1)
@@ -736,6 +901,48 @@ rule test {
    ·                 ───┬───  
    ·                    ╰───── this identifier has not been declared
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule foo {
+  condition:
+    true
+}
+rule bar {
+  condition:
+    for any foo in (1..10) : ( foo == 1 )
+}
+"#,
+            r#"error: loop variable `foo` shadows a rule
+   ╭─[line:8:13]
+   │
+ 8 │     for any foo in (1..10) : ( foo == 1 )
+   ·             ─┬─  
+   ·              ╰─── this rule is hidden by the loop variable
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        #[cfg(feature = "test_proto2-module")]
+        (
+            line!(),
+            r#"
+import "test_proto2"
+rule test {
+  condition:
+    for any i, test_proto2 in test_proto2.map_string_int64 : ( true )
+}
+"#,
+            r#"error: loop variable `test_proto2` shadows a module
+   ╭─[line:5:16]
+   │
+ 5 │     for any i, test_proto2 in test_proto2.map_string_int64 : ( true )
+   ·                ─────┬─────  
+   ·                     ╰─────── this module is hidden by the loop variable
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -760,6 +967,226 @@ rule test {
 
              (float, float)
              (integer, integer)
+             (integer, integer, integer)
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        #[cfg(feature = "string-module")]
+        (
+            line!(),
+            r#"
+import "string"
+rule test {
+  condition:
+    string.to_int("10", 16, 2) == 16
+}
+"#,
+            r#"error: wrong arguments
+   ╭─[line:5:19]
+   │
+ 5 │     string.to_int("10", 16, 2) == 16
+   ·                   ─────┬─────  
+   ·                        ╰─────── wrong arguments in this call
+   · 
+   · Note: accepted argument combinations:
+
+             (string)
+             (string, integer)
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition:
+    int16("MZ") == 0x5a4d
+}
+"#,
+            r#"error: wrong arguments
+   ╭─[line:4:11]
+   │
+ 4 │     int16("MZ") == 0x5a4d
+   ·           ──┬─  
+   ·             ╰─── wrong arguments in this call
+   · 
+   · Note: accepted argument combinations:
+
+             (integer)
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule foo {
+  meta:
+    enabled = false
+  condition:
+    true
+}
+
+rule bar {
+  condition:
+    foo
+}
+"#,
+            r#"error: rule `bar` references disabled rule `foo`
+    ╭─[line:11:5]
+    │
+ 11 │     foo
+    ·     ─┬─  
+    ·      ╰─── `foo` is disabled
+────╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule foo {
+  strings:
+    $a = "foo"
+  condition:
+    $a and
+    foo
+}
+"#,
+            r#"error: rule `foo` references itself
+   ╭─[line:7:5]
+   │
+ 7 │     foo
+   ·     ─┬─  
+   ·      ╰─── a rule can't be used in its own condition
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule foo {
+  condition:
+    bar
+}
+
+private rule bar {
+  condition:
+    true
+}
+"#,
+            r#"error: rule `bar` is used before being defined
+   ╭─[line:4:5]
+   │
+ 4 │     bar
+   ·     ─┬─  
+   ·      ╰─── `bar` is used here
+   · 
+ 7 │ private rule bar {
+   ·              ─┬─  
+   ·               ╰─── `bar` is defined here
+   · 
+   · Note: move the definition before the rules that use it
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition:
+    "foo" matches /foo(/
+}
+"#,
+            r#"error: invalid regular expression
+   ╭─[line:4:19]
+   │
+ 4 │     "foo" matches /foo(/
+   ·                   ───┬──  
+   ·                      ╰──── unclosed group
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition:
+    1 matches /foo/
+}
+"#,
+            r#"error: wrong type
+   ╭─[line:4:5]
+   │
+ 4 │     1 matches /foo/
+   ·     ┬  
+   ·     ╰── expression should be `string`, but is `integer`
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+import "test_proto2"
+rule test {
+  condition:
+    test_proto2.array_int64.size == 3
+}
+"#,
+            r#"error: wrong type
+   ╭─[line:5:5]
+   │
+ 5 │     test_proto2.array_int64.size == 3
+   ·     ───────────┬───────────  
+   ·                ╰───────────── expression should be `struct`, but is `array`
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"// yarax: disable_warning(foo)
+rule test { condition: true }"#,
+            r#"error: invalid pragma `disable_warning`
+   ╭─[line:1:11]
+   │
+ 1 │ // yarax: disable_warning(foo)
+   ·           ──────────┬─────────  
+   ·                     ╰─────────── unknown warning `foo`
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"// yarax: disable_warning
+rule test { condition: true }"#,
+            r#"error: invalid pragma `disable_warning`
+   ╭─[line:1:11]
+   │
+ 1 │ // yarax: disable_warning
+   ·           ───────┬───────  
+   ·                  ╰───────── expecting the identifier of a warning, like `disable_warning(duplicate_tag)`
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"// yarax: extensions(yes)
+rule test { condition: true }"#,
+            r#"error: invalid pragma `extensions`
+   ╭─[line:1:11]
+   │
+ 1 │ // yarax: extensions(yes)
+   ·           ───────┬───────  
+   ·                  ╰───────── this pragma doesn't accept arguments
 ───╯
 "#,
         ),
@@ -772,9 +1199,585 @@ rule test {
                 "rule at line {} compiled without errors, but error was expected.\n\n",
                 t.0,
             ))
-            .to_string(),
+            .report()
+            .unwrap(),
         t.2,
         "test at line {}", t.0
     )
     }
 }
+
+#[test]
+fn errors_display() {
+    use std::error::Error as StdError;
+
+    // Display is a single line for all variants, errors with a detailed
+    // report produce the report's first line without the `error:` prefix.
+    let err = Compiler::new().add_source("rule test {").unwrap_err();
+    assert!(matches!(err, Error::ParseError(_)));
+    assert_eq!(err.to_string(), "syntax error");
+    assert!(err.report().unwrap().starts_with("error: syntax error\n"));
+
+    let err = Compiler::new()
+        .add_source("rule test { condition: foo }")
+        .unwrap_err();
+    assert!(matches!(err, Error::CompileError(_)));
+    assert_eq!(err.to_string(), "unknown identifier `foo`");
+    assert!(err
+        .report()
+        .unwrap()
+        .starts_with("error: unknown identifier `foo`\n"));
+
+    let err = Compiler::new()
+        .define_string_set("foo", ["a"])
+        .unwrap()
+        .define_string_set("foo", ["b"])
+        .unwrap_err();
+    assert!(matches!(err, Error::DuplicateStringSet(_)));
+    assert_eq!(err.to_string(), "string set `foo` is already defined");
+    assert!(err.report().is_none());
+
+    // The underlying I/O error is available as the error's source.
+    let err = Compiler::new()
+        .emit_wasm_file("non-existent-dir/test.wasm")
+        .unwrap_err();
+    assert!(matches!(err, Error::IoError(_)));
+    assert_eq!(err.to_string(), "I/O error");
+    assert!(err.report().is_none());
+    let source = err.source().unwrap();
+    assert_eq!(
+        source.downcast_ref::<std::io::Error>().unwrap().kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn errors_with_control_characters() {
+    // Control characters in the source code, like the escape sequence that
+    // clears the screen here, are rendered in a way that terminals don't
+    // interpret, both in the source code and in the error's title.
+    let err = Compiler::new()
+        .add_source("import \"\x1b[2J\"\nrule test { condition: true }")
+        .unwrap_err();
+
+    let report = err.report().unwrap();
+
+    assert!(!report.contains('\x1b'));
+    assert_eq!(err.to_string(), "unknown module `␛[2J`");
+    assert_eq!(
+        report,
+        r#"error: unknown module `␛[2J`
+   ╭─[line:1:1]
+   │
+ 1 │ import "␛[2J"
+   · ──────┬──────  
+   ·       ╰──────── module `␛[2J` not found
+───╯
+"#
+    );
+}
+
+#[test]
+fn too_many_nested_loops() {
+    use crate::compiler::MAX_LOOP_NESTING;
+
+    let nested_loops = |n: usize| {
+        let mut condition = String::from("true");
+        for i in 0..n {
+            condition = format!("for any i{} in (0..1) : ({})", i, condition);
+        }
+        format!("rule test {{ condition: {} }}", condition)
+    };
+
+    assert!(Compiler::new()
+        .add_source(nested_loops(MAX_LOOP_NESTING).as_str())
+        .is_ok());
+
+    let src = nested_loops(MAX_LOOP_NESTING + 1);
+    let err = Compiler::new().add_source(src.as_str()).unwrap_err();
+
+    assert!(matches!(err, Error::CompileError(_)));
+    assert_eq!(err.to_string(), "too many nested loops");
+
+    let report = err.report().unwrap();
+
+    assert!(report.contains(&format!(
+        "this loop exceeds the maximum nesting depth of {}",
+        MAX_LOOP_NESTING
+    )));
+
+    // The error points to the innermost loop, the one that exceeds the
+    // limit.
+    assert!(report
+        .contains(&format!("─[line:1:{}]", src.rfind("for ").unwrap() + 1)));
+}
+
+#[test]
+fn multiple_errors() {
+    let err = Compiler::new()
+        .add_source(
+            r#"
+rule a {
+  condition:
+    for any i in (0..1) : (foo)
+}
+
+rule b {
+  condition:
+    a and i == 0
+}
+
+rule c {
+  condition:
+    true
+}
+
+rule d {
+  condition:
+    "bar" == 1
+}
+"#,
+        )
+        .unwrap_err();
+
+    let Error::CompileErrors(errors) = &err else {
+        panic!("expecting multiple errors, got: {:?}", err)
+    };
+
+    // Rule `a` has errors, but it is still declared, so referencing it
+    // from `b` is fine. However, the loop variable `i` must not leak from
+    // `a` into `b`.
+    assert_eq!(
+        errors
+            .as_slice()
+            .iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>(),
+        vec![
+            "unknown identifier `foo`",
+            "unknown identifier `i`",
+            "mismatching types"
+        ]
+    );
+
+    assert_eq!(
+        err.to_string(),
+        "unknown identifier `foo` (and 2 more errors)"
+    );
+
+    assert_eq!(
+        err.report().unwrap(),
+        errors
+            .as_slice()
+            .iter()
+            .map(|err| err.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
+    assert!(err.report().unwrap().contains("[line:19:"));
+
+    // A single error is still returned as `Error::CompileError`.
+    let err = Compiler::new()
+        .add_source("rule a { condition: foo } rule b { condition: true }")
+        .unwrap_err();
+
+    assert!(matches!(err, Error::CompileError(_)));
+}
+
+#[test]
+fn errors_in_different_sources() {
+    // Sources without an origin share the same name in reports, the
+    // snippet must come from the source where the error is.
+    let mut compiler = Compiler::new();
+    compiler.add_source("rule a { condition: true }").unwrap();
+
+    let err = compiler
+        .add_source("\n\nrule b { condition: \"ñandú\" == foo }")
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: unknown identifier `foo`
+   ╭─[line:3:32]
+   │
+ 3 │ rule b { condition: "ñandú" == foo }
+   ·                                ─┬─  
+   ·                                 ╰─── this identifier has not been declared
+───╯
+"#
+    );
+}
+
+#[test]
+fn structured_errors() {
+    use yara_x_parser::line_index::Position;
+    use yara_x_parser::SourceCode;
+
+    let src = "rule test {\n  condition: \"ñandú\" == 1\n}";
+
+    let Err(Error::CompileError(err)) =
+        Compiler::new().add_source(SourceCode::from(src).origin("test.yar"))
+    else {
+        panic!("expected a compile error")
+    };
+
+    assert_eq!(err.code(), "mismatching_types");
+    assert_eq!(err.title(), "mismatching types");
+    assert_eq!(err.origin(), Some("test.yar"));
+
+    let labels: Vec<_> = err
+        .labels()
+        .iter()
+        .map(|label| {
+            (
+                label.text(),
+                &src[label.span().start()..label.span().end()],
+                label.start(),
+                label.end(),
+            )
+        })
+        .collect();
+
+    // Columns are counted in chars, `ñ` and `ú` are two bytes long but
+    // count as one column.
+    assert_eq!(
+        labels,
+        vec![
+            (
+                "this expression is `string`",
+                r#""ñandú""#,
+                Position { line: 1, column: 13 },
+                Position { line: 1, column: 20 },
+            ),
+            (
+                "this expression is `integer`",
+                "1",
+                Position { line: 1, column: 24 },
+                Position { line: 1, column: 25 },
+            ),
+        ]
+    );
+
+    // The structured data doesn't change the report.
+    assert_eq!(
+        err.as_str(),
+        r#"error: mismatching types
+   ╭─[test.yar:2:14]
+   │
+ 2 │   condition: "ñandú" == 1
+   ·              ───┬───    ┬  
+   ·                 ╰────────── this expression is `string`
+   ·                         │  
+   ·                         ╰── this expression is `integer`
+───╯
+"#
+    );
+
+    #[cfg(feature = "serde")]
+    assert_eq!(
+        serde_json::to_string(&err).unwrap(),
+        format!(
+            "{{{},{},{},{},{}}}",
+            r#""code":"mismatching_types","title":"mismatching types""#,
+            r#""origin":"test.yar""#,
+            r#""labels":[{"text":"this expression is `string`","span":{"start":25,"end":34},"start":{"line":1,"column":13},"end":{"line":1,"column":20}}"#,
+            r#"{"text":"this expression is `integer`","span":{"start":38,"end":39},"start":{"line":1,"column":24},"end":{"line":1,"column":25}}]"#,
+            format!(
+                r#""report":{}"#,
+                serde_json::to_string(err.as_str()).unwrap()
+            ),
+        )
+    );
+
+    // Warnings expose the same information.
+    let mut compiler = Compiler::new();
+    compiler.add_source("rule test {\n  condition: 1\n}").unwrap();
+
+    let warning = &compiler.warnings()[0];
+
+    assert_eq!(warning.code(), "non_boolean_as_boolean");
+    assert_eq!(warning.title(), "non-boolean expression used as boolean");
+    assert_eq!(warning.origin(), None);
+    assert_eq!(warning.labels().len(), 1);
+    assert_eq!(warning.labels()[0].start(), Position { line: 1, column: 13 });
+}
+
+#[test]
+fn errors_with_origin() {
+    use yara_x_parser::SourceCode;
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            SourceCode::from("rule a : foo foo {\n  condition: true\n}")
+                .origin("rules/a.yar"),
+        )
+        .unwrap();
+
+    // The warning is attributed to the source that raised it.
+    assert_eq!(compiler.warnings()[0].origin(), Some("rules/a.yar"));
+    assert!(compiler.warnings()[0].as_str().contains("╭─[rules/a.yar:1:14]"));
+
+    let err = compiler
+        .add_source(
+            SourceCode::from("rule b {\n  condition:\n    a and foo\n}")
+                .origin("rules/b.yar"),
+        )
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: unknown identifier `foo`
+   ╭─[rules/b.yar:3:11]
+   │
+ 3 │     a and foo
+   ·           ─┬─  
+   ·            ╰─── this identifier has not been declared
+───╯
+"#
+    );
+}
+
+#[test]
+fn duplicate_rules() {
+    use yara_x_parser::line_index::Position;
+    use yara_x_parser::SourceCode;
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            SourceCode::from("rule a {\n  condition: true\n}")
+                .origin("rules/a.yar"),
+        )
+        .unwrap();
+
+    let Err(Error::CompileError(err)) = compiler.add_source(
+        SourceCode::from(
+            "rule b { condition: true }\nrule a { condition: b }",
+        )
+        .origin("rules/b.yar"),
+    ) else {
+        panic!("expected a compile error")
+    };
+
+    assert_eq!(err.code(), "duplicate_rule");
+
+    // The second label points to the source code that declared the rule
+    // for the first time.
+    let labels: Vec<_> = err
+        .labels()
+        .iter()
+        .map(|label| (label.origin(), label.span().start(), label.start()))
+        .collect();
+
+    assert_eq!(
+        labels,
+        vec![
+            (None, 32, Position { line: 1, column: 5 }),
+            (Some("rules/a.yar"), 5, Position { line: 0, column: 5 }),
+        ]
+    );
+
+    assert_eq!(
+        err.as_str(),
+        r#"error: duplicate rule `a`
+   ╭─[rules/b.yar:2:6]
+   │
+ 2 │ rule a { condition: b }
+   ·      ┬  
+   ·      ╰── duplicate declaration of `a`
+   │
+   ├─[rules/a.yar:1:6]
+   │
+ 1 │ rule a {
+   ·      ┬  
+   ·      ╰── `a` declared here for the first time
+───╯
+"#
+    );
+
+    // Sources without origin can't be labeled, a note is shown instead.
+    let Err(err) = Compiler::new()
+        .add_source("rule a { condition: true }")
+        .unwrap()
+        .add_source("rule a { condition: false }")
+    else {
+        panic!("expected a compile error")
+    };
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: duplicate rule `a`
+   ╭─[line:1:6]
+   │
+ 1 │ rule a { condition: false }
+   ·      ┬  
+   ·      ╰── duplicate declaration of `a`
+   · 
+   · Note: the first declaration is in a previously added source code
+───╯
+"#
+    );
+
+    // Rules with the same name in different namespaces are fine.
+    assert!(Compiler::new()
+        .add_source("rule a { condition: true }")
+        .unwrap()
+        .new_namespace("foo")
+        .add_source("rule a { condition: true }")
+        .is_ok());
+}
+
+#[test]
+#[cfg(feature = "test_proto2-module")]
+fn banned_modules() {
+    use crate::compiler::CompileErrorInfo;
+
+    let mut compiler = Compiler::new();
+    compiler
+        .ban_module(
+            "test_proto2",
+            "module not allowed",
+            "`test_proto2` can't be used here",
+        )
+        .ban_module("foo", "module not allowed", "`foo` can't be used");
+
+    let err = compiler
+        .add_source(
+            r#"
+import "test_proto2"
+rule test { condition: test_proto2.int64_one == 1 }
+"#,
+        )
+        .unwrap_err();
+
+    let Error::CompileError(err) = err else {
+        panic!("expecting a single compile error, got: {err:?}");
+    };
+
+    assert!(matches!(
+        err.info(),
+        CompileErrorInfo::BannedModule { module, .. } if module == "test_proto2"
+    ));
+    assert_eq!(err.code(), "banned_module");
+    assert_eq!(err.to_string(), "module not allowed");
+    assert_eq!(
+        err.as_str(),
+        r#"error: module not allowed
+   ╭─[line:2:1]
+   │
+ 2 │ import "test_proto2"
+   · ──────────┬─────────  
+   ·           ╰─────────── `test_proto2` can't be used here
+───╯
+"#
+    );
+
+    // Banning a module that doesn't exist has no effect, and modules that
+    // are not banned can be imported as usual.
+    let mut compiler = Compiler::new();
+    compiler
+        .ban_module("foo", "module not allowed", "`foo` can't be used")
+        .add_source(
+            r#"
+import "test_proto2"
+rule test { condition: test_proto2.int64_one == 1 }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    assert_eq!(rules.imports().collect::<Vec<_>>(), ["test_proto2"]);
+}
+
+#[test]
+fn add_source_errors() {
+    let mut compiler = Compiler::new();
+
+    compiler.add_source("rule a { condition: true }").unwrap();
+
+    let err = compiler
+        .add_source(
+            r#"
+rule b {
+  strings:
+    $b = "b"
+  condition:
+    $b
+}
+rule c { condition: d }
+"#,
+        )
+        .unwrap_err();
+
+    assert_eq!(err.to_string(), "unknown identifier `d`");
+
+    // None of the rules in the failed source are added, not even `b`,
+    // which doesn't have errors. The compiler can still be used.
+    compiler.add_source("rule b { condition: a }").unwrap();
+
+    let rules = compiler.build().unwrap();
+
+    assert_eq!(rules.iter().map(|r| r.name()).collect::<Vec<_>>(), ["a", "b"]);
+
+    let mut scanner = Scanner::new(&rules);
+    assert_eq!(scanner.scan(b"b").num_matching_rules(), 2);
+}
+
+#[test]
+fn rule_in_other_namespace() {
+    let err = Compiler::new()
+        .add_source("rule a { condition: true }")
+        .unwrap()
+        .new_namespace("foo")
+        .add_source("rule b { condition: a }")
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: unknown identifier `a`
+   ╭─[line:1:21]
+   │
+ 1 │ rule b { condition: a }
+   ·                     ┬  
+   ·                     ╰── this identifier has not been declared
+   · 
+   · Note: `a` is declared in namespace `default`
+───╯
+"#
+    );
+}
+
+#[cfg(all(feature = "test_proto2-module", feature = "test_proto3-module"))]
+#[test]
+fn add_source_errors_with_imports() {
+    let mut compiler = Compiler::new();
+
+    assert!(compiler
+        .add_source(
+            r#"
+import "test_proto3"
+rule a { condition: test_proto3.int64_one == 1 and b }
+"#,
+        )
+        .is_err());
+
+    // Modules imported by the failed source are not imported anymore, and
+    // the ones imported later work as usual.
+    compiler
+        .add_source(
+            r#"
+import "test_proto2"
+rule a { condition: test_proto2.string_foo == "foo" }
+"#,
+        )
+        .unwrap();
+
+    let rules = compiler.build().unwrap();
+
+    assert_eq!(rules.imports().collect::<Vec<_>>(), ["test_proto2"]);
+
+    let mut scanner = Scanner::new(&rules);
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+}
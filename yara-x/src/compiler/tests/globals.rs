@@ -0,0 +1,44 @@
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{Compiler, Error};
+
+#[test]
+fn duplicate_global() {
+    let mut compiler = Compiler::new();
+
+    compiler.define_global("foo", 1).unwrap();
+
+    let err = compiler.define_global("foo", "bar").unwrap_err();
+
+    assert!(matches!(&err, Error::DuplicateGlobal(name) if name == "foo"));
+    assert_eq!(err.to_string(), "global variable `foo` is already defined");
+
+    // Built-in functions can't be redefined either.
+    let err = compiler.define_global("uint8", 1).unwrap_err();
+
+    assert!(matches!(&err, Error::DuplicateGlobal(name) if name == "uint8"));
+}
+
+#[test]
+fn invalid_global() {
+    for name in ["", "1foo", "foo.bar", "foo bar", "$foo"] {
+        let err = Compiler::new().define_global(name, true).unwrap_err();
+
+        assert!(matches!(&err, Error::InvalidGlobal(n) if n == name));
+        assert_eq!(
+            err.to_string(),
+            format!("invalid global variable name `{}`", name)
+        );
+    }
+}
+
+#[test]
+fn wrong_type_in_global() {
+    let err = Compiler::new()
+        .define_global("foo", "bar")
+        .unwrap()
+        .add_source("rule test { condition: foo == 1 }")
+        .unwrap_err();
+
+    assert_eq!(err.to_string(), "mismatching types");
+}
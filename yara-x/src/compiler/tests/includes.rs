@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::io;
+
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{
+    CompileErrorInfo, Compiler, Error, SourceCode, MAX_INCLUDE_DEPTH,
+};
+use crate::tests::fs::FileTree;
+
+/// Returns a compiler that resolves includes from the given files, which
+/// are indexed by file name.
+fn compiler_with_files<'a>(files: &'a [(&str, &str)]) -> Compiler<'a> {
+    let files: HashMap<_, _> = files.iter().copied().collect();
+    let mut compiler = Compiler::new();
+    compiler.set_include_resolver(move |file_name, _| {
+        files
+            .get(file_name)
+            .map(|content| content.as_bytes().to_vec())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    });
+    compiler
+}
+
+fn rule_names(compiler: Compiler) -> Vec<(String, String)> {
+    compiler
+        .build()
+        .unwrap()
+        .iter()
+        .map(|rule| (rule.namespace().to_string(), rule.name().to_string()))
+        .collect()
+}
+
+#[test]
+fn includes() {
+    let mut compiler = compiler_with_files(&[
+        ("foo.yar", r#"include "bar.yar" rule foo { condition: bar }"#),
+        ("bar.yar", "rule bar { condition: true }"),
+    ]);
+
+    compiler
+        .new_namespace("test")
+        .add_source(
+            r#"
+include "foo.yar"
+rule baz { condition: foo }"#,
+        )
+        .unwrap();
+
+    // Included rules are compiled into the namespace of the source that
+    // includes them, at the position of the `include` statement.
+    assert_eq!(
+        rule_names(compiler),
+        [
+            ("test".to_string(), "bar".to_string()),
+            ("test".to_string(), "foo".to_string()),
+            ("test".to_string(), "baz".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn includes_in_order() {
+    let mut compiler = compiler_with_files(&[
+        ("bar.yar", "rule bar { condition: foo }"),
+        ("qux.yar", "rule qux { condition: baz and bar }"),
+    ]);
+
+    // Rules in included files can use the rules declared before the
+    // `include` statement, and the rules after it can use the included
+    // ones.
+    compiler
+        .add_source(
+            r#"
+rule foo { condition: true }
+include "bar.yar"
+rule baz { condition: bar }
+include "qux.yar"
+"#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        rule_names(compiler),
+        [
+            ("default".to_string(), "foo".to_string()),
+            ("default".to_string(), "bar".to_string()),
+            ("default".to_string(), "baz".to_string()),
+            ("default".to_string(), "qux".to_string()),
+        ]
+    );
+
+    // Rules declared after the `include` statement can't be used by the
+    // included file.
+    let mut compiler =
+        compiler_with_files(&[("bar.yar", "rule bar { condition: foo }")]);
+
+    let Err(Error::CompileError(err)) = compiler.add_source(
+        SourceCode::from(
+            r#"
+include "bar.yar"
+rule foo { condition: true }
+"#,
+        )
+        .origin("main.yar"),
+    ) else {
+        panic!("expecting a compile error");
+    };
+
+    assert_eq!(err.origin(), Some("bar.yar"));
+    assert_eq!(err.to_string(), "unknown identifier `foo`");
+}
+
+#[test]
+fn warnings_in_included_files() {
+    let mut compiler =
+        compiler_with_files(&[("bar.yar", "rule bar { condition: 2 }")]);
+
+    compiler
+        .add_source(
+            SourceCode::from(
+                r#"
+rule foo { condition: 1 }
+include "bar.yar"
+rule baz { condition: 3 }
+"#,
+            )
+            .origin("main.yar"),
+        )
+        .unwrap();
+
+    // Warnings in the included file refer to it, and they come before the
+    // ones in the including source.
+    let locations: Vec<_> = compiler
+        .warnings()
+        .iter()
+        .map(|warning| warning.as_str().lines().nth(1).unwrap().trim())
+        .collect();
+
+    assert_eq!(
+        locations,
+        ["╭─[bar.yar:1:23]", "╭─[main.yar:2:23]", "╭─[main.yar:4:23]"]
+    );
+}
+
+#[test]
+fn includes_from_file_system() {
+    let dir = FileTree::new()
+        .file("main.yar", r#"include "rules/foo.yar""#)
+        .file(
+            "rules/foo.yar",
+            r#"include "./bar.yar" rule foo { condition: bar }"#,
+        )
+        .file("rules/bar.yar", "rule bar { condition: true }")
+        .create();
+
+    let main = dir.path().join("main.yar");
+    let src = std::fs::read(&main).unwrap();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            SourceCode::from(src.as_slice()).origin(&main.to_string_lossy()),
+        )
+        .unwrap();
+
+    assert_eq!(
+        rule_names(compiler),
+        [
+            ("default".to_string(), "bar".to_string()),
+            ("default".to_string(), "foo".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn includes_from_file_reader() {
+    let reader = FileTree::new()
+        .file(
+            "rules/foo.yar",
+            r#"include "../bar.yar" rule foo { condition: bar }"#,
+        )
+        .file("bar.yar", "rule bar { condition: true }")
+        .into_reader();
+
+    let mut compiler = Compiler::new();
+    compiler.set_file_reader(reader);
+
+    // Relative paths are relative to the including file, also when it
+    // was read with the file reader.
+    compiler
+        .add_source(SourceCode::from(r#"include "rules/foo.yar""#))
+        .unwrap();
+
+    assert_eq!(
+        rule_names(compiler),
+        [
+            ("default".to_string(), "bar".to_string()),
+            ("default".to_string(), "foo".to_string()),
+        ]
+    );
+
+    // The include resolver takes precedence over the file reader.
+    let mut compiler =
+        compiler_with_files(&[("bar.yar", "rule baz { condition: true }")]);
+    compiler.set_file_reader(
+        FileTree::new()
+            .file("bar.yar", "rule bar { condition: true }")
+            .into_reader(),
+    );
+    compiler.add_source(r#"include "bar.yar""#).unwrap();
+
+    assert_eq!(
+        rule_names(compiler),
+        [("default".to_string(), "baz".to_string())]
+    );
+}
+
+#[test]
+fn errors_in_included_files() {
+    let mut compiler =
+        compiler_with_files(&[("foo.yar", "rule foo {\n  condition: bar\n}")]);
+
+    let Err(Error::CompileError(err)) = compiler.add_source(
+        SourceCode::from(r#"include "foo.yar""#).origin("main.yar"),
+    ) else {
+        panic!("expecting a compile error");
+    };
+
+    assert_eq!(err.origin(), Some("foo.yar"));
+    assert_eq!(err.to_string(), "unknown identifier `bar`");
+    assert_eq!(
+        err.as_str(),
+        r#"error: unknown identifier `bar`
+   ╭─[foo.yar:2:14]
+   │
+ 2 │   condition: bar
+   ·              ─┬─  
+   ·               ╰─── this identifier has not been declared
+───╯
+"#
+    );
+}
+
+#[test]
+fn circular_includes() {
+    let mut compiler = compiler_with_files(&[
+        ("foo.yar", r#"include "bar.yar""#),
+        ("bar.yar", r#"include "foo.yar""#),
+    ]);
+
+    let Err(Error::CompileError(err)) = compiler.add_source(
+        SourceCode::from(r#"include "foo.yar""#).origin("main.yar"),
+    ) else {
+        panic!("expecting a compile error");
+    };
+
+    assert!(matches!(
+        err.info(),
+        CompileErrorInfo::CircularInclude { file_name, .. }
+            if file_name == "foo.yar"
+    ));
+    assert_eq!(err.origin(), Some("bar.yar"));
+    assert_eq!(
+        err.as_str(),
+        r#"error: circular include of `foo.yar`
+   ╭─[bar.yar:1:1]
+   │
+ 1 │ include "foo.yar"
+   · ────────┬────────  
+   ·         ╰────────── `foo.yar` is already being included
+   · 
+   · Note: include chain: foo.yar -> bar.yar -> foo.yar
+───╯
+"#
+    );
+
+    // Nothing is added by a source with circular includes.
+    compiler.add_source("rule foo { condition: true }").unwrap();
+}
+
+#[test]
+fn too_many_nested_includes() {
+    // Each include of `a/b.yar` is relative to the including file, so the
+    // paths never repeat and the cycle is not detected.
+    let mut compiler =
+        compiler_with_files(&[("a/b.yar", r#"include "a/b.yar""#)]);
+
+    let Err(Error::CompileError(err)) = compiler.add_source(
+        SourceCode::from(r#"include "a/b.yar""#).origin("main.yar"),
+    ) else {
+        panic!("expecting a compile error");
+    };
+
+    assert!(matches!(
+        err.info(),
+        CompileErrorInfo::TooManyNestedIncludes { max_depth, .. }
+            if *max_depth == MAX_INCLUDE_DEPTH
+    ));
+
+    let innermost = format!("{}b.yar", "a/".repeat(MAX_INCLUDE_DEPTH));
+    assert_eq!(err.origin(), Some(innermost.as_str()));
+}
+
+#[test]
+fn include_errors() {
+    let err = compiler_with_files(&[])
+        .add_source(r#"include "foo.yar""#)
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: can't include `foo.yar`
+   ╭─[line:1:1]
+   │
+ 1 │ include "foo.yar"
+   · ────────┬────────  
+   ·         ╰────────── entity not found
+───╯
+"#
+    );
+
+    let err =
+        Compiler::new().add_source(r#"include "missing.yar""#).unwrap_err();
+
+    assert!(matches!(
+        err,
+        Error::CompileError(ref err)
+            if matches!(err.info(), CompileErrorInfo::IncludeError { .. })
+    ));
+}
+
+#[test]
+fn includes_disabled() {
+    let err =
+        compiler_with_files(&[("foo.yar", "rule foo { condition: true }")])
+            .enable_includes(false)
+            .add_source(r#"include "foo.yar""#)
+            .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: includes are not allowed
+   ╭─[line:1:1]
+   │
+ 1 │ include "foo.yar"
+   · ────────┬────────  
+   ·         ╰────────── includes are disabled in this compiler
+───╯
+"#
+    );
+}
@@ -0,0 +1,99 @@
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{CompileErrorInfo, Compiler, Error};
+
+#[test]
+fn max_rules() {
+    let mut compiler = Compiler::new();
+    compiler.set_max_rules(2);
+
+    compiler.add_source("rule foo { condition: true }").unwrap();
+
+    // Rules in sources with errors don't count.
+    assert!(compiler
+        .add_source("rule bar { condition: true } rule baz { condition: qux }")
+        .is_err());
+
+    compiler.add_source("rule bar { condition: true }").unwrap();
+
+    let err = compiler.add_source("rule baz { condition: true }").unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: too many rules
+   ╭─[line:1:6]
+   │
+ 1 │ rule baz { condition: true }
+   ·      ─┬─  
+   ·       ╰─── this rule exceeds the limit of 2 rules
+───╯
+"#
+    );
+
+    assert_eq!(compiler.build().unwrap().iter().count(), 2);
+}
+
+#[test]
+fn max_patterns_per_rule() {
+    let err = Compiler::new()
+        .set_max_patterns_per_rule(2)
+        .add_source(
+            r#"
+rule foo {
+  strings:
+    $a = "foo"
+    $b = "bar"
+    $c = "baz"
+  condition:
+    any of them
+}"#,
+        )
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: too many patterns in a rule
+   ╭─[line:6:5]
+   │
+ 6 │     $c = "baz"
+   ·     ─┬  
+   ·      ╰── this pattern exceeds the limit of 2 patterns per rule
+───╯
+"#
+    );
+}
+
+#[test]
+fn max_total_patterns() {
+    let mut compiler = Compiler::new();
+    compiler.set_max_total_patterns(3);
+
+    compiler
+        .add_source(r#"rule foo { strings: $a = "foo" $b = "bar" condition: all of them }"#)
+        .unwrap();
+
+    let Err(Error::CompileError(err)) = compiler.add_source(
+        r#"rule bar { strings: $a = "foo" $b = "bar" condition: all of them }"#,
+    ) else {
+        panic!("expecting a compile error");
+    };
+
+    assert!(matches!(
+        err.info(),
+        CompileErrorInfo::TooManyPatterns { max_patterns: 3, span }
+            if span.start() == 31
+    ));
+
+    // A rule with a single pattern still fits.
+    compiler
+        .add_source(r#"rule bar { strings: $a = "foo" condition: $a }"#)
+        .unwrap();
+
+    // Lowering the limit below the number of existing patterns doesn't
+    // panic, only rules without patterns can be added.
+    compiler.set_max_total_patterns(1);
+    compiler.add_source("rule baz { condition: true }").unwrap();
+    assert!(compiler
+        .add_source(r#"rule qux { strings: $a = "foo" condition: $a }"#)
+        .is_err());
+}
@@ -1,2 +1,15 @@
+mod build_options;
+mod build_progress;
+mod diagnostics;
 mod errors;
+mod globals;
+mod includes;
+mod limits;
+mod naming_policy;
+mod pragmas;
+mod source_files;
+mod string_sets;
+mod unknown_modules;
+mod user_modules;
 mod warnings;
+mod wat;
@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{Compiler, IdentifierKind, NamingPolicyVerdict};
+
+#[test]
+fn policy_calls() {
+    let calls = RefCell::new(Vec::new());
+
+    let mut compiler = Compiler::new();
+    compiler
+        .naming_policy(|kind, ident, span| {
+            calls.borrow_mut().push((kind, ident.to_string(), span.is_some()));
+            NamingPolicyVerdict::Accept
+        })
+        .add_source(
+            r#"
+rule foo : bar baz {
+  strings:
+    $a = "foo"
+    $b = "bar"
+  condition:
+    $a and $b
+}
+
+rule qux {
+  strings:
+    $a = "qux"
+  condition:
+    $a
+}"#,
+        )
+        .unwrap()
+        .new_namespace("ns")
+        .add_source("rule foo { condition: true }")
+        .unwrap()
+        .add_source("rule bar { condition: true }")
+        .unwrap();
+
+    assert!(compiler.warnings.is_empty());
+
+    assert_eq!(
+        *calls.borrow(),
+        vec![
+            (IdentifierKind::Rule, "foo".to_string(), true),
+            (IdentifierKind::Tag, "bar".to_string(), true),
+            (IdentifierKind::Tag, "baz".to_string(), true),
+            (IdentifierKind::Pattern, "$a".to_string(), true),
+            (IdentifierKind::Pattern, "$b".to_string(), true),
+            (IdentifierKind::Rule, "qux".to_string(), true),
+            (IdentifierKind::Pattern, "$a".to_string(), true),
+            (IdentifierKind::Namespace, "ns".to_string(), false),
+            (IdentifierKind::Rule, "foo".to_string(), true),
+            (IdentifierKind::Rule, "bar".to_string(), true),
+        ]
+    );
+}
+
+#[test]
+fn policy_violations() {
+    let policy = |kind, ident: &str, _| match kind {
+        IdentifierKind::Namespace if ident != "acme" => {
+            NamingPolicyVerdict::Reject("expecting `acme`".to_string())
+        }
+        IdentifierKind::Rule if !ident.starts_with("acme_") => {
+            NamingPolicyVerdict::Reject(
+                "rule names must start with `acme_`".to_string(),
+            )
+        }
+        IdentifierKind::Tag | IdentifierKind::Pattern
+            if ident.to_lowercase() != ident =>
+        {
+            NamingPolicyVerdict::Warn("must be lowercase".to_string())
+        }
+        _ => NamingPolicyVerdict::Accept,
+    };
+
+    let mut compiler = Compiler::new();
+    compiler
+        .naming_policy(policy)
+        .add_source(
+            r#"
+rule acme_foo : Bar {
+  strings:
+    $A = "foo"
+  condition:
+    $A
+}"#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        compiler.warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+        vec![
+            r#"warning: tag `Bar` violates the naming policy
+   ╭─[line:2:17]
+   │
+ 2 │ rule acme_foo : Bar {
+   ·                 ─┬─  
+   ·                  ╰─── must be lowercase
+───╯
+"#,
+            r#"warning: pattern `$A` violates the naming policy
+   ╭─[line:4:5]
+   │
+ 4 │     $A = "foo"
+   ·     ─┬  
+   ·      ╰── must be lowercase
+───╯
+"#,
+        ]
+    );
+
+    assert_eq!(
+        Compiler::new()
+            .naming_policy(policy)
+            .add_source("rule foo { condition: true }")
+            .unwrap_err()
+            .report()
+            .unwrap(),
+        r#"error: rule `foo` violates the naming policy
+   ╭─[line:1:6]
+   │
+ 1 │ rule foo { condition: true }
+   ·      ─┬─  
+   ·       ╰─── rule names must start with `acme_`
+───╯
+"#
+    );
+
+    assert_eq!(
+        Compiler::new()
+            .naming_policy(policy)
+            .new_namespace("foo")
+            .add_source("rule acme_foo { condition: true }")
+            .unwrap_err()
+            .report()
+            .unwrap(),
+        r#"error: namespace `foo` violates the naming policy
+   ╭─[line:1:6]
+   │
+ 1 │ rule acme_foo { condition: true }
+   ·      ────┬───  
+   ·          ╰───── expecting `acme`
+   · 
+   · Note: namespace `foo` was checked when adding rule `acme_foo` to it
+───╯
+"#
+    );
+}
+
+#[test]
+fn namespace_created_before_policy() {
+    let policy = |kind, ident: &str, _| match kind {
+        IdentifierKind::Namespace if ident != "acme" => {
+            NamingPolicyVerdict::Reject("expecting `acme`".to_string())
+        }
+        _ => NamingPolicyVerdict::Accept,
+    };
+
+    // The namespace is checked when a rule is added to it, even if it was
+    // created before setting the policy.
+    let mut compiler = Compiler::new();
+    compiler.new_namespace("foo").naming_policy(policy);
+
+    let err = compiler.add_source("rule bar { condition: true }").unwrap_err();
+
+    assert_eq!(err.to_string(), "namespace `foo` violates the naming policy");
+
+    // The failed source doesn't count as checked, the next one fails too.
+    assert!(compiler.add_source("rule baz { condition: true }").is_err());
+
+    // Rules added before setting the policy don't prevent the check.
+    let mut compiler = Compiler::new();
+    compiler
+        .new_namespace("foo")
+        .add_source("rule bar { condition: true }")
+        .unwrap()
+        .naming_policy(policy);
+
+    assert!(compiler.add_source("rule baz { condition: true }").is_err());
+}
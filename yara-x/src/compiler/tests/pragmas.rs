@@ -0,0 +1,144 @@
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{CompileErrorInfo, Compiler, Error};
+
+const COUNT_OF: &str = r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    count_of($a) == 0
+}"#;
+
+fn warning_codes(compiler: &Compiler) -> Vec<&'static str> {
+    compiler.warnings().iter().map(|w| w.code()).collect()
+}
+
+#[test]
+fn extensions_pragma() {
+    let src = format!("// yarax: extensions\n{COUNT_OF}");
+
+    assert!(Compiler::new().add_source(src.as_str()).is_ok());
+
+    // The pragma doesn't leak into the next source.
+    let err = Compiler::new()
+        .add_source(src.as_str())
+        .unwrap()
+        .add_source(COUNT_OF.replace("rule test", "rule test2").as_str())
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        Error::CompileError(ref err)
+            if matches!(err.info(), CompileErrorInfo::ExtensionNotEnabled { .. })
+    ));
+
+    // Extensions disabled explicitly with the API take precedence over the
+    // pragma.
+    let err = Compiler::new()
+        .enable_extensions(false)
+        .add_source(src.as_str())
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        Error::CompileError(ref err)
+            if matches!(err.info(), CompileErrorInfo::ExtensionNotEnabled { .. })
+    ));
+
+    let mut compiler = Compiler::new();
+    compiler
+        .enable_extensions(false)
+        .add_source("// yarax: extensions\nrule test { condition: true }")
+        .unwrap();
+
+    assert_eq!(warning_codes(&compiler), ["overridden_pragma"]);
+    assert_eq!(
+        compiler.warnings()[0].to_string(),
+        r#"warning: pragma `extensions` is overridden
+   ╭─[line:1:11]
+   │
+ 1 │ // yarax: extensions
+   ·           ─────┬────  
+   ·                ╰────── this pragma is ignored
+   · 
+   · Note: extensions were disabled with `Compiler::enable_extensions`
+───╯
+"#
+    );
+}
+
+#[test]
+fn count_of_as_identifier() {
+    // With extensions disabled `count_of` is an ordinary identifier, as it
+    // is in YARA, unless it's followed by a parenthesis.
+    assert!(Compiler::new()
+        .enable_extensions(false)
+        .define_global("count_of", 1)
+        .unwrap()
+        .add_source("rule test { condition: count_of == 1 }")
+        .is_ok());
+
+    assert!(Compiler::new()
+        .enable_extensions(false)
+        .add_source(
+            r#"
+rule count_of { condition: true }
+rule foo { condition: count_of }"#,
+        )
+        .is_ok());
+}
+
+#[test]
+fn disable_warning_pragma() {
+    let src = r#"// A comment that is not a pragma.
+// yarax: disable_warning(duplicate_tag)
+//   yarax: disable_warning(non_boolean_as_boolean)
+rule test : foo foo { condition: 1 }"#;
+
+    let mut compiler = Compiler::new();
+    compiler.add_source(src).unwrap();
+
+    assert!(compiler.warnings().is_empty());
+
+    // The warnings disabled by the pragma are raised again by the next
+    // source.
+    compiler.add_source("rule test2 : foo foo { condition: 1 }").unwrap();
+
+    assert_eq!(
+        warning_codes(&compiler),
+        ["duplicate_tag", "non_boolean_as_boolean"]
+    );
+
+    // Warnings disabled by pragmas don't produce errors.
+    assert!(Compiler::new().errors_on_warnings(true).add_source(src).is_ok());
+}
+
+#[test]
+fn unknown_and_misplaced_pragmas() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"// yarax: relaxed_regex, disable_warning(duplicate_tag)
+rule test : foo foo {
+  // yarax: disable_warning(non_boolean_as_boolean)
+  condition: 1
+}"#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        warning_codes(&compiler),
+        ["misplaced_pragma", "unknown_pragma", "non_boolean_as_boolean"]
+    );
+    assert_eq!(
+        compiler.warnings()[1].title(),
+        "unknown pragma `relaxed_regex`"
+    );
+
+    // Warnings about pragmas can be disabled with a pragma.
+    let mut compiler = Compiler::new();
+    compiler.add_source("// yarax: foo, disable_warning(unknown_pragma)\nrule test { condition: true }").unwrap();
+
+    assert!(compiler.warnings().is_empty());
+}
@@ -0,0 +1,143 @@
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{Compiler, Error};
+use crate::scanner::Scanner;
+use crate::tests::fs::FileTree;
+
+fn rule_names(compiler: Compiler) -> Vec<String> {
+    compiler
+        .build()
+        .unwrap()
+        .iter()
+        .map(|rule| rule.name().to_string())
+        .collect()
+}
+
+#[test]
+fn add_source_file() {
+    let dir = FileTree::new()
+        .file(
+            "test.yar",
+            r#"rule test { strings: $a = "\xFF\xFE" condition: $a }"#,
+        )
+        .file("error.yar", "rule test {\n  condition: foo\n}")
+        .file(
+            "invalid.yar",
+            b"rule test { strings: $a = \"\xFF\" condition: $a }",
+        )
+        .create();
+
+    let mut compiler = Compiler::new();
+    compiler.add_source_file(dir.path().join("test.yar")).unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    assert_eq!(scanner.scan(b"\x00\xFF\xFE\x00").num_matching_rules(), 1);
+
+    // Invalid UTF-8 is reported by the parser.
+    let Err(Error::ParseError(err)) =
+        Compiler::new().add_source_file(dir.path().join("invalid.yar"))
+    else {
+        panic!("expecting a parse error");
+    };
+
+    assert_eq!(err.to_string(), "invalid UTF-8");
+
+    // Errors refer to the file.
+    let path = dir.path().join("error.yar");
+    let Err(Error::CompileError(err)) = Compiler::new().add_source_file(&path)
+    else {
+        panic!("expecting a compile error");
+    };
+
+    assert_eq!(err.origin(), Some(path.to_string_lossy().as_ref()));
+
+    // Files that don't exist can't be opened.
+    let path = dir.path().join("missing.yar");
+    let err = Compiler::new().add_source_file(&path).unwrap_err();
+
+    assert!(matches!(err, Error::OpenError { path: ref p, .. } if p == &path));
+}
+
+#[test]
+fn add_source_dir() {
+    let dir = FileTree::new()
+        .file("b.yar", "rule b { condition: true }")
+        .file("a.yara", "rule a { condition: true }")
+        .file("c.txt", "this is not a YARA source file")
+        .file("sub/d.yar", "rule d { condition: a }")
+        .create();
+
+    let mut compiler = Compiler::new();
+    compiler.add_source_dir(dir.path(), false).unwrap();
+    assert_eq!(rule_names(compiler), ["a", "b"]);
+
+    // Files in subdirectories are added after the ones that sort before
+    // the subdirectory.
+    let mut compiler = Compiler::new();
+    compiler.add_source_dir(dir.path(), true).unwrap();
+    assert_eq!(rule_names(compiler), ["a", "b", "d"]);
+}
+
+#[test]
+fn add_source_dir_errors() {
+    let reader = FileTree::new()
+        .file("rules/a.yar", "rule a { condition: foo }")
+        .file("rules/b.yar", "rule b { condition: true }")
+        .file("rules/c.yar", "rule c {")
+        .file(
+            "rules/d.yar",
+            "rule d1 { condition: bar } rule d2 { condition: baz }",
+        )
+        .into_reader();
+
+    let mut compiler = Compiler::new();
+    compiler.set_file_reader(reader);
+
+    let Err(Error::CompileErrors(errs)) =
+        compiler.add_source_dir("rules", false)
+    else {
+        panic!("expecting multiple compile errors");
+    };
+
+    // Errors from all the files are returned, including syntax errors.
+    assert_eq!(
+        errs.as_slice()
+            .iter()
+            .map(|err| (err.code(), err.origin().unwrap()))
+            .collect::<Vec<_>>(),
+        [
+            ("unknown_identifier", "rules/a.yar"),
+            ("parse_error", "rules/c.yar"),
+            ("unknown_identifier", "rules/d.yar"),
+            ("unknown_identifier", "rules/d.yar"),
+        ]
+    );
+
+    assert_eq!(errs.as_slice()[1].title(), "syntax error");
+    assert!(errs.as_slice()[1].as_str().starts_with("error: syntax error"));
+
+    // The rules in files without errors are kept.
+    assert_eq!(rule_names(compiler), ["b"]);
+}
+
+#[test]
+fn add_source_dir_with_file_reader() {
+    let reader = FileTree::new()
+        .file("b.yar", "rule b { condition: true }")
+        .file("a.yara", "rule a { condition: true }")
+        .file("c.txt", "rule c { condition: true }")
+        .file("sub/d.yar", "rule d { condition: a }")
+        .symlink("e.yar", "c.txt")
+        .symlink("link", "sub")
+        .dir("empty")
+        .into_reader();
+
+    let mut compiler = Compiler::new();
+    compiler.set_file_reader(reader);
+    compiler.add_source_dir("", true).unwrap();
+
+    // Symbolic links to files are followed, but not the ones to
+    // directories, otherwise `d` would be added twice.
+    assert_eq!(rule_names(compiler), ["a", "b", "c", "d"]);
+}
@@ -0,0 +1,80 @@
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{Compiler, Error};
+
+#[test]
+fn duplicate_string_set() {
+    let err = Compiler::new()
+        .define_string_set("foo", ["a", "b"])
+        .unwrap()
+        .define_string_set("foo", ["c"])
+        .unwrap_err();
+
+    assert!(matches!(&err, Error::DuplicateStringSet(name) if name == "foo"));
+    assert_eq!(err.to_string(), "string set `foo` is already defined");
+}
+
+#[test]
+fn unknown_string_set() {
+    let err = Compiler::new()
+        .enable_extensions(true)
+        .define_string_set("foo", ["a", "b"])
+        .unwrap()
+        .add_source(r#"rule test { condition: "a" in bar }"#)
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: unknown string set `bar`
+   ╭─[line:1:31]
+   │
+ 1 │ rule test { condition: "a" in bar }
+   ·                               ─┬─  
+   ·                                ╰─── this string set has not been defined
+───╯
+"#
+    );
+}
+
+#[test]
+fn string_set_without_extensions() {
+    let err = Compiler::new()
+        .define_string_set("foo", ["a", "b"])
+        .unwrap()
+        .add_source(r#"rule test { condition: "a" in foo }"#)
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: `in foo` is a YARA-X extension
+   ╭─[line:1:24]
+   │
+ 1 │ rule test { condition: "a" in foo }
+   ·                        ─────┬────  
+   ·                             ╰────── this is not supported by YARA, enable extensions to use it
+───╯
+"#
+    );
+}
+
+#[test]
+fn wrong_type_in_string_set() {
+    let err = Compiler::new()
+        .enable_extensions(true)
+        .define_string_set("foo", ["a", "b"])
+        .unwrap()
+        .add_source(r#"rule test { condition: 1 in foo }"#)
+        .unwrap_err();
+
+    assert_eq!(
+        err.report().unwrap(),
+        r#"error: wrong type
+   ╭─[line:1:24]
+   │
+ 1 │ rule test { condition: 1 in foo }
+   ·                        ┬  
+   ·                        ╰── expression should be `string`, but is `integer`
+───╯
+"#
+    );
+}
@@ -0,0 +1,126 @@
+use pretty_assertions::assert_eq;
+
+use crate::compiler::{CompileErrorInfo, Compiler, Error};
+use crate::scanner::Scanner;
+
+const SRC: &str = r#"import "foo"
+
+rule uses_foo {
+  strings:
+    $a = "abc"
+  condition:
+    $a and foo.bar == 1
+}
+
+rule depends_on_uses_foo { condition: uses_foo }
+
+rule test {
+  strings:
+    $a = "abc"
+  condition:
+    $a
+}"#;
+
+fn warning_codes(compiler: &Compiler) -> Vec<&'static str> {
+    compiler.warnings().iter().map(|w| w.code()).collect()
+}
+
+#[test]
+fn unknown_modules_are_errors_by_default() {
+    let err = Compiler::new().add_source(SRC).unwrap_err();
+
+    assert!(matches!(
+        err,
+        Error::CompileError(ref err)
+            if matches!(err.info(), CompileErrorInfo::UnknownModule { .. })
+    ));
+}
+
+#[test]
+fn ignore_unknown_modules() {
+    let mut compiler = Compiler::new();
+    compiler.ignore_unknown_modules(true).add_source(SRC).unwrap();
+
+    assert_eq!(
+        warning_codes(&compiler),
+        ["unknown_module", "ignored_rule", "ignored_rule"]
+    );
+
+    assert_eq!(
+        compiler.warnings()[0].to_string(),
+        r#"warning: unknown module `foo`
+   ╭─[line:1:1]
+   │
+ 1 │ import "foo"
+   · ──────┬─────  
+   ·       ╰─────── module `foo` not found, this import is ignored
+───╯
+"#
+    );
+
+    assert_eq!(
+        compiler.warnings()[1].to_string(),
+        r#"warning: rule `uses_foo` is ignored
+   ╭─[line:3:6]
+   │
+ 3 │ rule uses_foo {
+   ·      ────┬───  
+   ·          ╰───── this rule depends on the unknown module `foo`
+───╯
+"#
+    );
+
+    assert_eq!(
+        compiler.warnings()[2].to_string(),
+        r#"warning: rule `depends_on_uses_foo` is ignored
+    ╭─[line:10:6]
+    │
+ 10 │ rule depends_on_uses_foo { condition: uses_foo }
+    ·      ─────────┬─────────  
+    ·               ╰─────────── this rule depends on the unknown module `foo`
+────╯
+"#
+    );
+
+    // The ignored rules are not part of the compiled rules, the remaining
+    // ones work as usual.
+    let rules = compiler.build().unwrap();
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"abc");
+
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+    assert_eq!(matching, ["test"]);
+    assert_eq!(results.iter_non_matches().count(), 0);
+}
+
+#[test]
+fn ignored_identifiers_are_per_namespace() {
+    // The unknown module is ignored only in the namespace that imports it,
+    // other namespaces get the usual error.
+    let err = Compiler::new()
+        .ignore_unknown_modules(true)
+        .add_source(SRC)
+        .unwrap()
+        .new_namespace("other")
+        .add_source("rule other { condition: foo.bar == 1 }")
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        Error::CompileError(ref err)
+            if matches!(err.info(), CompileErrorInfo::UnknownIdentifier { .. })
+    ));
+
+    // Other errors in rules that use the unknown module are not affected.
+    let err = Compiler::new()
+        .ignore_unknown_modules(true)
+        .add_source(r#"import "foo" rule test { condition: bar and foo.bar }"#)
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        Error::CompileError(ref err)
+            if matches!(err.info(), CompileErrorInfo::UnknownIdentifier { identifier, .. }
+                if identifier == "bar")
+    ));
+}
@@ -0,0 +1,137 @@
+use pretty_assertions::assert_eq;
+use protobuf::descriptor::field_descriptor_proto::{Label, Type};
+use protobuf::descriptor::{
+    DescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+};
+use protobuf::reflect::FileDescriptor;
+use protobuf::well_known_types::timestamp::Timestamp;
+use protobuf::MessageFull;
+
+use crate::compiler::{Compiler, Error};
+
+#[test]
+fn duplicate_user_module() {
+    let err = Compiler::new()
+        .register_module("clock", Timestamp::descriptor())
+        .unwrap()
+        .register_module("clock", Timestamp::descriptor())
+        .unwrap_err();
+
+    assert!(matches!(&err, Error::DuplicateModule(name) if name == "clock"));
+    assert_eq!(err.to_string(), "module `clock` is already defined");
+}
+
+#[test]
+#[cfg(feature = "test_proto2-module")]
+fn user_module_with_builtin_name() {
+    let err = Compiler::new()
+        .register_module("test_proto2", Timestamp::descriptor())
+        .unwrap_err();
+
+    assert!(
+        matches!(&err, Error::DuplicateModule(name) if name == "test_proto2")
+    );
+}
+
+#[test]
+fn user_module_fields() {
+    let mut compiler = Compiler::new();
+    compiler.register_module("clock", Timestamp::descriptor()).unwrap();
+
+    // Fields are type-checked like in built-in modules.
+    let err = compiler
+        .add_source(
+            r#"import "clock" rule test { condition: clock.seconds == "1" }"#,
+        )
+        .unwrap_err();
+
+    assert!(matches!(err, Error::CompileError(_)));
+
+    let err = Compiler::new()
+        .register_module("clock", Timestamp::descriptor())
+        .unwrap()
+        .add_source(r#"import "clock" rule test { condition: clock.foo }"#)
+        .unwrap_err();
+
+    assert!(matches!(err, Error::CompileError(_)));
+
+    // Registering a module doesn't make it available with other names.
+    let err = Compiler::new()
+        .register_module("clock", Timestamp::descriptor())
+        .unwrap()
+        .add_source(r#"import "timestamp" rule test { condition: true }"#)
+        .unwrap_err();
+
+    assert!(matches!(err, Error::CompileError(_)));
+}
+
+#[test]
+fn unsupported_user_module() {
+    let field = |name: &str, ty, type_name: &str| {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_string());
+        field.set_number(1);
+        field.set_label(Label::LABEL_OPTIONAL);
+        field.set_type(ty);
+        if !type_name.is_empty() {
+            field.set_type_name(type_name.to_string());
+        }
+        field
+    };
+
+    let descriptor = |message: DescriptorProto| {
+        let name = message.name().to_string();
+        let mut file = FileDescriptorProto::new();
+        file.set_name("unsupported.proto".to_string());
+        file.set_syntax("proto2".to_string());
+        file.message_type = vec![message];
+        FileDescriptor::new_dynamic(file, &[])
+            .unwrap()
+            .message_by_package_relative_name(&name)
+            .unwrap()
+    };
+
+    // message Node { optional Node next = 1; }
+    let mut node = DescriptorProto::new();
+    node.set_name("Node".to_string());
+    node.field = vec![field("next", Type::TYPE_MESSAGE, ".Node")];
+
+    let err =
+        Compiler::new().register_module("node", descriptor(node)).unwrap_err();
+
+    assert!(
+        matches!(&err, Error::UnsupportedModule { name, .. } if name == "node")
+    );
+    assert_eq!(
+        err.to_string(),
+        "module `node` is not supported: message `Node` contains itself"
+    );
+
+    // message Flags { map<bool, string> flags = 1; }
+    let mut entry = DescriptorProto::new();
+    entry.set_name("FlagsEntry".to_string());
+    entry.field = vec![
+        field("key", Type::TYPE_BOOL, ""),
+        field("value", Type::TYPE_STRING, ""),
+    ];
+    entry.field[1].set_number(2);
+    entry.options.mut_or_insert_default().set_map_entry(true);
+
+    let mut flags_field =
+        field("flags", Type::TYPE_MESSAGE, ".Flags.FlagsEntry");
+    flags_field.set_label(Label::LABEL_REPEATED);
+
+    let mut flags = DescriptorProto::new();
+    flags.set_name("Flags".to_string());
+    flags.field = vec![flags_field];
+    flags.nested_type = vec![entry];
+
+    let err = Compiler::new()
+        .register_module("flags", descriptor(flags))
+        .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "module `flags` is not supported: field `flags` in `Flags` is a map with keys of type `bool`"
+    );
+}
@@ -1,6 +1,6 @@
 use pretty_assertions::assert_eq;
 
-use crate::compiler::Compiler;
+use crate::compiler::{CompileErrorInfo, Compiler, Error, WarningInfo};
 
 #[test]
 fn warnings() {
@@ -152,6 +152,228 @@ rule test {
    · 
    · Note: the expression requires 3 matching patterns out of 2
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition:
+    2 + 2 > 3
+}"#,
+            r#"warning: rule condition is always true
+   ╭─[line:4:5]
+   │
+ 4 │     2 + 2 > 3
+   ·     ────┬────  
+   ·         ╰────── this condition is known at compile time
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    false and $a
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:6:5]
+   │
+ 6 │     false and $a
+   ·     ──┬──  
+   ·       ╰──── this expression is always false
+   · 
+   · Note: the other operand of `and` doesn't affect the result, which is always false
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a and false
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:6:12]
+   │
+ 6 │     $a and false
+   ·            ──┬──  
+   ·              ╰──── this expression is always false
+   · 
+   · Note: the other operand of `and` doesn't affect the result, which is always false
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition:
+    filesize > 5 or true
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:4:21]
+   │
+ 4 │     filesize > 5 or true
+   ·                     ──┬─  
+   ·                       ╰─── this expression is always true
+   · 
+   · Note: the other operand of `or` doesn't affect the result, which is always true
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a and 1 == 2
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:6:12]
+   │
+ 6 │     $a and 1 == 2
+   ·            ───┬──  
+   ·               ╰──── this expression is always false
+   · 
+   · Note: the other operand of `and` doesn't affect the result, which is always false
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    filesize < 0 and $a
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:6:5]
+   │
+ 6 │     filesize < 0 and $a
+   ·     ──────┬─────  
+   ·           ╰─────── this expression is always false
+   · 
+   · Note: the other operand of `and` doesn't affect the result, which is always false
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a or #a >= 0
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:6:11]
+   │
+ 6 │     $a or #a >= 0
+   ·           ───┬───  
+   ·              ╰───── this expression is always true
+   · 
+   · Note: the other operand of `or` doesn't affect the result, which is always true
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a and true
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:6:12]
+   │
+ 6 │     $a and true
+   ·            ──┬─  
+   ·              ╰─── this expression is always true
+   · 
+   · Note: this operand doesn't affect the result of `and`, which depends only on the other operand
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition:
+    "foo" != "foo" or 1 > 2
+}"#,
+            r#"warning: rule condition is always false
+   ╭─[line:4:5]
+   │
+ 4 │     "foo" != "foo" or 1 > 2
+   ·     ───────────┬───────────  
+   ·                ╰───────────── this condition is known at compile time
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $foo_1 = "foo"
+    $foo_2 = "bar"
+    $bar = "baz"
+    $qux = "qux"
+  condition:
+    4 of ($foo_*, $bar) and $qux
+}"#,
+            r#"warning: invariant boolean expression
+   ╭─[line:9:5]
+   │
+ 9 │     4 of ($foo_*, $bar) and $qux
+   ·     ──────────┬─────────  
+   ·               ╰─────────── this expression is always false
+   · 
+   · Note: the expression requires 4 matching patterns out of 3
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    101% of them
+}"#,
+            r#"warning: percentage out of range
+   ╭─[line:6:5]
+   │
+ 6 │     101% of them
+   ·     ─┬─  
+   ·      ╰─── this is 101%, but percentages go from 0% to 100%
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -172,6 +394,58 @@ import "test_proto2"
    · ──────────┬─────────  
    ·           ╰─────────── duplicate import
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        #[cfg(feature = "test_proto2-module")]
+        (
+            line!(),
+            r#"
+import "test_proto2"
+rule test { condition: true }
+"#,
+            r#"warning: module `test_proto2` is never used
+   ╭─[line:2:1]
+   │
+ 2 │ import "test_proto2"
+   · ──────────┬─────────  
+   ·           ╰─────────── this module is not used by any rule
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test : foo bar foo { condition: true }
+"#,
+            r#"warning: duplicate tag `foo`
+   ╭─[line:2:21]
+   │
+ 2 │ rule test : foo bar foo { condition: true }
+   ·             ─┬─     ─┬─  
+   ·              ╰─────────── `foo` used here for the first time
+   ·                      │   
+   ·                      ╰─── duplicate tag
+───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+private rule foo { condition: true }
+rule bar { condition: true }
+"#,
+            r#"warning: private rule `foo` is never used
+   ╭─[line:2:14]
+   │
+ 2 │ private rule foo { condition: true }
+   ·              ─┬─  
+   ·               ╰─── this rule is not referenced by any other rule
+   · 
+   · Note: private rules are not included in scan results
+───╯
 "#,
         ),
         ////////////////////////////////////////////////////////////
@@ -293,24 +567,46 @@ rule test {
             line!(),
             r#"
 rule test {
+  strings:
+    $a = "foo"
   condition: !a[1]
 }
 "#,
             r#"warning: non-boolean expression used as boolean
-   ╭─[line:3:14]
+   ╭─[line:5:14]
    │
- 3 │   condition: !a[1]
+ 5 │   condition: !a[1]
    ·              ──┬──  
    ·                ╰──── this expression is `integer` but is being used as `bool`
    · 
    · Note: non-zero integers are considered `true`, while zero is `false`
 ───╯
+"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  condition: entrypoint == 0
+}
+"#,
+            r#"warning: `entrypoint` is deprecated
+   ╭─[line:3:14]
+   │
+ 3 │   condition: entrypoint == 0
+   ·              ─────┬────  
+   ·                   ╰────── `entrypoint` is deprecated and will be removed
+   · 
+   · Note: use `pe.entry_point` or `elf.entry_point` instead
+───╯
 "#,
         ),
     ];
 
     for t in tests {
-        let compiler = Compiler::new().add_source(t.1).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.add_source(t.1).unwrap();
         assert!(
             !compiler.warnings.is_empty(),
             "test at line {} didn't produce warnings",
@@ -396,12 +692,43 @@ rule test {
     $a = "foo"
   condition:
     all of ($a*, $a*) at 0
+}"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+private rule foo { condition: true }
+rule bar { condition: foo }"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+import "test_proto2"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    test_proto2.int64_zero == 0 or $a
+}"#,
+        ),
+        ////////////////////////////////////////////////////////////
+        (
+            line!(),
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    filesize > 0 and #a == 2
 }"#,
         ),
     ];
 
     for t in tests {
-        let compiler = Compiler::new().add_source(t.1).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.add_source(t.1).unwrap();
 
         if !compiler.warnings.is_empty() {
             panic!(
@@ -411,3 +738,403 @@ rule test {
         }
     }
 }
+
+#[test]
+fn shadowed_patterns() {
+    let compile = |src: &str| {
+        let mut compiler = Compiler::new();
+        compiler.detect_shadowed_patterns(true).add_source(src).unwrap();
+        compiler
+    };
+
+    let compiler = compile(
+        r#"
+rule test {
+  strings:
+    $a = "malware"
+    $b = "malware detected"
+  condition:
+    any of ($a, $b)
+}"#,
+    );
+
+    assert_eq!(
+        compiler.warnings()[0].to_string(),
+        r#"warning: pattern `$b` is shadowed by `$a`
+   ╭─[line:5:5]
+   │
+ 4 │     $a = "malware"
+   ·     ─┬  
+   ·      ╰── `$a` declared here
+ 5 │     $b = "malware detected"
+   ·     ─┬  
+   ·      ╰── every match of `$b` contains a match of `$a`
+   · 
+ 7 │     any of ($a, $b)
+   ·     ────────┬───────  
+   ·             ╰───────── `$b` can't change the result of this expression
+───╯
+"#
+    );
+
+    let compiler = compile(
+        r#"
+rule test {
+  strings:
+    $mz = { 4D 5A }
+    $mz_header = { 4D 5A 90 00 }
+  condition:
+    none of them
+}"#,
+    );
+
+    assert_eq!(
+        compiler.warnings()[0].to_string(),
+        r#"warning: pattern `$mz_header` is shadowed by `$mz`
+   ╭─[line:5:5]
+   │
+ 4 │     $mz = { 4D 5A }
+   ·     ─┬─  
+   ·      ╰─── `$mz` declared here
+ 5 │     $mz_header = { 4D 5A 90 00 }
+   ·     ─────┬────  
+   ·          ╰────── every match of `$mz_header` contains a match of `$mz`
+   · 
+ 7 │     none of them
+   ·     ──────┬──────  
+   ·           ╰──────── `$mz_header` can't change the result of this expression
+───╯
+"#
+    );
+
+    // Each test is a set of patterns, and the shadowed patterns that must
+    // be reported in `any of them`.
+    let tests = vec![
+        (line!(), r#"$a = "foo" $b = "foo""#, vec!["$b"]),
+        (line!(), r#"$a = "foo" nocase $b = "xFOOx""#, vec!["$b"]),
+        (line!(), r#"$a = "foo" nocase $b = "xfOOx" nocase"#, vec!["$b"]),
+        (line!(), r#"$a = "foo" $b = "FOO" nocase"#, vec!["$a"]),
+        (line!(), r#"$a = "foo" ascii wide $b = "foobar" wide"#, vec!["$b"]),
+        (
+            line!(),
+            r#"$a = "foo" wide $b = "foobar" wide fullword"#,
+            vec!["$b"],
+        ),
+        (line!(), r#"$a = { 66 6F ?? } $b = "xfoo""#, vec!["$b"]),
+        (line!(), r#"$a = { 66 6F } $b = { 66 6? }"#, vec!["$a"]),
+        (line!(), r#"$a = "fo" $b = { 66 6F [2] 6F }"#, vec!["$b"]),
+        (line!(), r#"$a = { 4D ( 5A | 5B ) } $b = { 00 4D 5A }"#, vec!["$b"]),
+        (line!(), r#"$a = "ab" $b = { ( 61 62 | 62 61 62 ) }"#, vec!["$b"]),
+        (line!(), r#"$a = "foo" $b = "foo" $c = "foobar""#, vec!["$b", "$c"]),
+        // Not shadowed.
+        (line!(), r#"$a = "foo" $b = "bar""#, vec![]),
+        (line!(), r#"$a = "foo" $b = "foobar" nocase"#, vec![]),
+        (line!(), r#"$a = "foo" wide $b = "foobar""#, vec![]),
+        (line!(), r#"$a = "foo" $b = "foobar" wide"#, vec![]),
+        (line!(), r#"$a = "foo" $b = "foobar" ascii wide"#, vec![]),
+        (line!(), r#"$a = "foo" fullword $b = "foobar""#, vec![]),
+        (line!(), r#"$a = "foo" xor $b = "foobar""#, vec![]),
+        (line!(), r#"$a = "foo" $b = "foobar" xor"#, vec![]),
+        (line!(), r#"$a = "foo" $b = "foobar" base64"#, vec![]),
+        (line!(), r#"$a = "fo" $b = { 66 [1-2] 6F }"#, vec![]),
+        (line!(), r#"$a = "fo" $b = { 66 ~6F }"#, vec![]),
+        (line!(), r#"$a = "fo" $b = /foo/"#, vec![]),
+        (line!(), r#"$a = { 66 6F } $b = { 6? 6F 00 }"#, vec![]),
+        (line!(), r#"$a = "ab" $b = { ( 61 63 | 62 61 ) }"#, vec![]),
+    ];
+
+    for (line, patterns, expected) in tests {
+        let src = format!(
+            "rule test {{ strings: {} condition: any of them }}",
+            patterns
+        );
+        let shadowed: Vec<_> = compile(src.as_str())
+            .warnings()
+            .iter()
+            .filter_map(|w| match w.info() {
+                WarningInfo::ShadowedPattern { pattern_ident, .. } => {
+                    Some(pattern_ident.clone())
+                }
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(shadowed, expected, "test at line {}", line);
+    }
+
+    // The warning is raised only for `any`, `none` and `1` quantifiers
+    // without anchors, and only if both patterns are in the set.
+    for (condition, expected) in [
+        ("any of them", true),
+        ("none of ($a, $b)", true),
+        ("1 of ($a, $b)", true),
+        ("$a and 1 of ($a, $b)", true),
+        ("all of them", false),
+        ("2 of them", false),
+        ("50% of them", false),
+        ("any of them at 0", false),
+        ("any of them in (0..10)", false),
+        ("$a or any of ($b)", false),
+        ("#a > 1 and any of ($b)", false),
+        ("for any of them : ($ at 0)", false),
+    ] {
+        let src = format!(
+            r#"rule test {{
+                strings:
+                  $a = "malware"
+                  $b = "malware found"
+                condition:
+                  {condition}
+            }}"#
+        );
+        let codes: Vec<_> = compile(src.as_str())
+            .warnings()
+            .iter()
+            .map(|w| w.code())
+            .collect();
+
+        assert_eq!(
+            codes.contains(&"shadowed_pattern"),
+            expected,
+            "condition `{}`",
+            condition
+        );
+    }
+
+    // Shadowed patterns are not reported by default.
+    assert!(Compiler::new()
+        .add_source(r#"rule test { strings: $a = "a" $b = "ab" condition: any of them }"#)
+        .unwrap()
+        .warnings()
+        .is_empty());
+}
+
+#[test]
+#[cfg(all(feature = "test_proto2-module", feature = "test_proto3-module"))]
+fn unused_imports() {
+    let warning_codes = |compiler: &Compiler| -> Vec<&'static str> {
+        compiler.warnings().iter().map(|w| w.code()).collect()
+    };
+
+    // Only the modules that are not used by any rule are reported.
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "test_proto2"
+import "test_proto3"
+rule test { condition: test_proto2.int64_one == 1 }
+"#,
+        )
+        .unwrap();
+
+    assert_eq!(warning_codes(&compiler), ["unused_import"]);
+    assert!(compiler.warnings()[0]
+        .to_string()
+        .starts_with("warning: module `test_proto3` is never used"));
+
+    // Modules used inside loops, or only for calling their functions,
+    // are used too.
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "test_proto2"
+import "test_proto3"
+rule test {
+  condition:
+    for any x in test_proto2.array_int64 : (x == test_proto3.int64_one)
+}
+"#,
+        )
+        .unwrap()
+        .add_source(
+            r#"
+import "test_proto2"
+rule test2 { condition: test_proto2.add(1, 2) == 3 }
+"#,
+        )
+        .unwrap();
+
+    assert!(compiler.warnings().is_empty());
+
+    // Importing the same module in different namespaces is fine, and each
+    // namespace is checked on its own.
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "test_proto2"
+rule test { condition: test_proto2.int64_one == 1 }
+"#,
+        )
+        .unwrap()
+        .new_namespace("foo")
+        .add_source(
+            r#"
+import "test_proto2"
+rule test { condition: test_proto2.int64_one == 1 }
+"#,
+        )
+        .unwrap()
+        .new_namespace("bar")
+        .add_source(
+            r#"
+import "test_proto2"
+rule test { condition: true }
+"#,
+        )
+        .unwrap();
+
+    assert_eq!(warning_codes(&compiler), ["unused_import"]);
+
+    // Duplicate imports are reported only within the same source.
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "test_proto2"
+import "test_proto2"
+rule test { condition: test_proto2.int64_one == 1 }
+"#,
+        )
+        .unwrap()
+        .new_namespace("foo")
+        .add_source(
+            r#"
+import "test_proto2"
+rule test { condition: test_proto2.int64_one == 1 }
+"#,
+        )
+        .unwrap();
+
+    assert_eq!(warning_codes(&compiler), ["duplicate_import"]);
+}
+
+#[test]
+fn disabled_warnings() {
+    let src = r#"
+import "test_proto2"
+import "test_proto2"
+rule test : foo foo { condition: test_proto2.int64_one }"#;
+
+    let mut compiler = Compiler::new();
+    compiler.add_source(src).unwrap();
+
+    assert_eq!(
+        compiler.warnings().iter().map(|w| w.code()).collect::<Vec<_>>(),
+        vec!["duplicate_import", "duplicate_tag", "non_boolean_as_boolean"]
+    );
+
+    // Both warnings raised by the parser and by the compiler can be
+    // disabled.
+    let mut compiler = Compiler::new();
+    compiler
+        .disable_warning("duplicate_import")
+        .unwrap()
+        .disable_warning("non_boolean_as_boolean")
+        .unwrap()
+        .add_source(src)
+        .unwrap();
+
+    assert_eq!(
+        compiler.warnings().iter().map(|w| w.code()).collect::<Vec<_>>(),
+        vec!["duplicate_tag"]
+    );
+
+    let Err(err) = Compiler::new().disable_warning("foo") else {
+        panic!("unknown warnings must be rejected")
+    };
+
+    assert!(matches!(&err, Error::UnknownWarning(code) if code == "foo"));
+    assert_eq!(err.to_string(), "unknown warning `foo`");
+
+    for code in WarningInfo::CODES {
+        assert!(Compiler::new().disable_warning(code).is_ok());
+    }
+}
+
+#[test]
+fn errors_on_warnings() {
+    let src = r#"
+rule a { condition: true }
+rule b {
+  strings:
+    $a = "foo"
+    $b = "bar"
+  condition:
+    all of them at 0
+}"#;
+
+    let err =
+        Compiler::new().errors_on_warnings(true).add_source(src).unwrap_err();
+
+    let Error::CompileError(err) = err else {
+        panic!("expected a single compile error")
+    };
+
+    assert!(matches!(
+        err.info(),
+        CompileErrorInfo::WarningAsError { code, .. }
+            if *code == "potentially_wrong_expression"
+    ));
+
+    // The report is the same as the warning's report, including all the
+    // labels, but it is an error.
+    assert_eq!(
+        err.as_str(),
+        r#"error: potentially wrong expression
+   ╭─[line:8:5]
+   │
+ 8 │     all of them at 0
+   ·     ─┬─         ──┬─  
+   ·      ╰──────────────── this implies that multiple patterns must match
+   ·                   │   
+   ·                   ╰─── but they must match at the same offset
+───╯
+"#
+    );
+
+    // Each warning produces its own error.
+    let Err(Error::CompileErrors(errs)) = Compiler::new()
+        .errors_on_warnings(true)
+        .add_source("rule a : foo foo { condition: 1 }")
+    else {
+        panic!("expected multiple compile errors")
+    };
+
+    assert_eq!(
+        errs.as_slice().iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+        vec!["duplicate tag `foo`", "non-boolean expression used as boolean"]
+    );
+
+    // Disabled warnings don't produce errors.
+    assert!(Compiler::new()
+        .errors_on_warnings(true)
+        .disable_warning("potentially_wrong_expression")
+        .unwrap()
+        .add_source(src)
+        .is_ok());
+}
+
+#[test]
+fn warnings_after_errors() {
+    let mut compiler = Compiler::new();
+
+    compiler.add_source("rule a { condition: 1 }").unwrap();
+
+    let err = compiler
+        .add_source(
+            "rule b : foo foo { condition: true } rule c { condition: d }",
+        )
+        .unwrap_err();
+
+    assert!(matches!(err, Error::CompileError(_)));
+
+    // The warnings raised by the first source are still there, followed by
+    // the ones raised by the failed source.
+    assert_eq!(
+        compiler.warnings().iter().map(|w| w.code()).collect::<Vec<_>>(),
+        ["non_boolean_as_boolean", "duplicate_tag"]
+    );
+}
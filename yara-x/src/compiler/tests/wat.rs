@@ -0,0 +1,81 @@
+use crate::compiler::Compiler;
+use crate::tests::fs::FileTree;
+use crate::wasm::ENGINE;
+
+#[test]
+fn emit_wat() {
+    let mut compiler = Compiler::new();
+
+    compiler
+        .add_source(
+            r#"
+rule foo { strings: $a = "foo" condition: $a and filesize < 100 }
+rule bar { condition: uint8(0) == 0x4d or foo }"#,
+        )
+        .unwrap();
+
+    let wat = compiler.emit_wat();
+
+    // Each rule has its own function, and functions are referenced by
+    // name.
+    assert!(wat.contains("(func $default.foo"));
+    assert!(wat.contains("(func $default.bar"));
+    assert!(wat.contains("(func $main"));
+    assert!(wat.contains("call $uint8@i@iu"));
+
+    // The output is valid WAT that can be compiled.
+    wasmtime::Module::new(&ENGINE, &wat).unwrap();
+}
+
+#[test]
+fn emit_wat_file() {
+    let dir = FileTree::new().create();
+    let path = dir.path().join("rules.wat");
+
+    let mut compiler = Compiler::new();
+    compiler.add_source("rule foo { condition: true }").unwrap();
+    compiler.emit_wat_file(&path).unwrap();
+
+    let wat = std::fs::read_to_string(path).unwrap();
+
+    assert!(wat.starts_with("(module\n"));
+    assert!(wat.contains("(func $default.foo"));
+}
+
+#[test]
+#[cfg(feature = "test_proto2-module")]
+fn emit_wat_loops_and_modules() {
+    let mut compiler = Compiler::new();
+
+    compiler
+        .add_source(
+            r#"
+import "test_proto2"
+rule loops {
+  strings:
+    $a = "foo" xor(1-3)
+    $b = "bar" base64wide
+  condition:
+    for any i in (0..#a) : (@a[i] > 10 and !a[i] == 3) and
+    for all of ($a, $b) : (# > 0) and
+    2 of them and $b in (0..filesize)
+}
+rule operators {
+  condition:
+    test_proto2.uppercase(test_proto2.string_foo) contains "FOO" and
+    test_proto2.string_foo matches /f.o/i and
+    test_proto2.add(1.5, 2.0) > 3.0 and
+    (filesize >> 2 | 0x0f) & ~1 != 0 and
+    defined test_proto2.int64_undef
+}"#,
+        )
+        .unwrap();
+
+    let wat = compiler.emit_wat();
+
+    assert!(wat.contains("(func $default.loops"));
+    assert!(wat.contains("(func $default.operators"));
+    assert!(wat.contains("call $test_proto2.uppercase@s@s"));
+
+    wasmtime::Module::new(&ENGINE, &wat).unwrap();
+}
@@ -24,13 +24,29 @@ assert_eq!(results.num_matching_rules(), 1);
 */
 pub use compiler::*;
 pub use scanner::*;
+pub use variables::Variable;
+pub use yara_x_parser::escape;
+
+/// The `protobuf` crate used by YARA-X. Modules registered with
+/// [`Compiler::register_module`] must be described with types from this
+/// crate, re-exported here so that their versions always match.
+pub use protobuf;
 
 mod compiler;
 mod modules;
 mod scanner;
 mod string_pool;
 mod symbols;
+mod variables;
 mod wasm;
 
+pub mod regression;
+
+#[cfg(feature = "migration")]
+pub mod migration;
+
+#[cfg(feature = "json-schema")]
+pub mod schemas;
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,622 @@
+/*! Migration of YARA deployments to YARA-X.
+
+Tooling built around YARA usually depends on the metadata of the compiled
+rules, as exported by `yara-python`: the namespace, tags and metadata
+entries of each rule. Before switching to YARA-X, it's necessary to know
+whether YARA-X sees the same metadata, and which rules it doesn't accept.
+
+[`Importer`] compiles the source code of the rules with YARA-X, and
+compares the result with the metadata exported from YARA (see
+[`LegacyMetadata`]). The outcome is a [`MigrationReport`] that lists the
+differences for each rule, the rules that don't compile, and the warnings
+raised by the rest of them. When the `json-schema` feature is enabled, the
+schema of the serialized report is available in [`crate::schemas`].
+
+```
+# use yara_x::migration::{Importer, LegacyMetadata};
+let legacy = LegacyMetadata::from_json(br#"[
+  {"namespace": "default", "identifier": "foo", "tags": ["bar"]}
+]"#).unwrap();
+
+let (rules, report) = Importer::new()
+    .legacy_metadata(legacy)
+    .add_source("rule foo : bar { condition: true }")
+    .import()
+    .unwrap();
+
+assert!(report.is_clean());
+assert_eq!(rules.iter().count(), 1);
+```
+*/
+
+use std::collections::BTreeMap;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+
+use yara_x_parser::ast;
+use yara_x_parser::report::Label;
+use yara_x_parser::{Parser, SourceCode};
+
+use crate::compiler::{Compiler, Error, Rules, DEFAULT_NAMESPACE};
+
+#[cfg(test)]
+mod tests;
+
+/// Metadata of rules compiled by YARA.
+///
+/// This is a JSON array with one object per rule, like the one produced by
+/// iterating the rules compiled by `yara-python`:
+///
+/// ```text
+/// [
+///   {
+///     "namespace": "default",
+///     "identifier": "foo",
+///     "tags": ["bar", "baz"],
+///     "meta": {"author": "qux", "version": 2, "enabled": true}
+///   }
+/// ]
+/// ```
+///
+/// `namespace` defaults to [`DEFAULT_NAMESPACE`], and `tags` and `meta`
+/// can be omitted when empty.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct LegacyMetadata {
+    rules: Vec<LegacyRule>,
+}
+
+impl LegacyMetadata {
+    /// Parses the JSON document with the metadata.
+    pub fn from_json(json: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(json)
+    }
+
+    /// Returns the metadata of each rule indexed by namespace and name. If
+    /// the same rule appears more than once, the first one wins.
+    fn index(&self) -> FxHashMap<(&str, &str), &LegacyRule> {
+        let mut index = FxHashMap::default();
+        for rule in self.rules.iter() {
+            index
+                .entry((rule.namespace.as_str(), rule.identifier.as_str()))
+                .or_insert(rule);
+        }
+        index
+    }
+}
+
+/// Metadata of a single rule compiled by YARA.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct LegacyRule {
+    #[serde(default = "default_namespace")]
+    namespace: String,
+    identifier: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    meta: BTreeMap<String, MetaValue>,
+}
+
+fn default_namespace() -> String {
+    DEFAULT_NAMESPACE.to_string()
+}
+
+/// Value of a metadata entry, as found in [`LegacyMetadata`] or extracted
+/// by YARA-X.
+///
+/// In JSON it is represented by the value itself. Strings that are not
+/// valid UTF-8 have their invalid sequences replaced with U+FFFD.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum MetaValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl From<&ast::MetaValue<'_>> for MetaValue {
+    fn from(value: &ast::MetaValue<'_>) -> Self {
+        match value {
+            ast::MetaValue::Bool(b) => Self::Bool(*b),
+            ast::MetaValue::Integer(i) => Self::Integer(*i),
+            ast::MetaValue::Float(f) => Self::Float(*f),
+            ast::MetaValue::String(s) => {
+                Self::String(String::from_utf8_lossy(s).into_owned())
+            }
+        }
+    }
+}
+
+/// Result of comparing YARA-X with YARA, produced by [`Importer::import`].
+///
+/// Any change in the report types changes the JSON schema, see
+/// [`crate::schemas`] before modifying them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct MigrationReport {
+    /// Source codes passed to the importer, in the same order they were
+    /// added.
+    pub sources: Vec<SourceReport>,
+    /// Rules declared in the source codes, in the same order they were
+    /// declared, followed by the rules that appear only in the legacy
+    /// metadata.
+    pub rules: Vec<RuleReport>,
+}
+
+impl MigrationReport {
+    /// Returns true if every source code compiled without warnings, and
+    /// every rule has the same metadata in YARA and YARA-X.
+    pub fn is_clean(&self) -> bool {
+        self.sources
+            .iter()
+            .all(|source| matches!(source.status, SourceStatus::Compiled))
+            && self.rules.iter().all(|rule| {
+                matches!(rule.status, RuleStatus::Compiled)
+                    && rule.in_legacy_metadata
+                    && rule.differences.is_empty()
+                    && rule.warnings.is_empty()
+            })
+    }
+}
+
+/// Outcome of compiling a source code.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct SourceReport {
+    /// Origin of the source code, as set with [`SourceCode::origin`].
+    pub origin: Option<String>,
+    /// Namespace where the rules in the source code were put.
+    pub namespace: String,
+    pub status: SourceStatus,
+    /// Errors that don't belong to any particular rule, like syntax errors.
+    /// Errors in rules are reported in [`RuleReport::errors`].
+    pub errors: Vec<Issue>,
+}
+
+/// Whether a source code was compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    /// The source code was compiled, and its rules are included in the
+    /// compiled [`Rules`].
+    Compiled,
+    /// The source code has errors, none of its rules are included in the
+    /// compiled [`Rules`].
+    Failed,
+}
+
+/// Outcome of importing a rule.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct RuleReport {
+    pub namespace: String,
+    pub name: String,
+    /// Origin of the source code that declares the rule, if any.
+    pub origin: Option<String>,
+    pub status: RuleStatus,
+    /// True if the rule appears in the legacy metadata.
+    pub in_legacy_metadata: bool,
+    /// Differences between the tags and metadata that YARA-X extracted
+    /// from the rule, and the ones in the legacy metadata.
+    pub differences: Vec<Difference>,
+    /// Errors found in the rule.
+    pub errors: Vec<Issue>,
+    /// Warnings raised by the rule, which usually point to constructs that
+    /// YARA-X handles differently than YARA.
+    pub warnings: Vec<Issue>,
+}
+
+/// Whether a rule was compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RuleStatus {
+    /// The rule is included in the compiled [`Rules`].
+    Compiled,
+    /// The rule has errors.
+    Failed,
+    /// The rule doesn't have errors, but some other rule in the same
+    /// source code does, and the whole source code was left out.
+    Skipped,
+    /// The rule is in the legacy metadata, but it's not declared in any of
+    /// the source codes, or it's declared in a source code that couldn't be
+    /// parsed.
+    NotFound,
+}
+
+/// A difference between the metadata extracted by YARA-X and the legacy
+/// metadata.
+///
+/// When YARA-X finds the same metadata identifier more than once in a
+/// rule, the last value is the one compared, as in `yara-python`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Difference {
+    /// The tag is in the legacy metadata, but not in YARA-X.
+    MissingTag { tag: String },
+    /// The tag is in YARA-X, but not in the legacy metadata.
+    ExtraTag { tag: String },
+    /// The metadata entry is in the legacy metadata, but not in YARA-X.
+    MissingMeta { identifier: String, legacy: MetaValue },
+    /// The metadata entry is in YARA-X, but not in the legacy metadata.
+    ExtraMeta { identifier: String, value: MetaValue },
+    /// The metadata entry has different values, or different types, in
+    /// YARA-X and in the legacy metadata.
+    MetaMismatch { identifier: String, legacy: MetaValue, value: MetaValue },
+}
+
+/// An error or warning raised by YARA-X.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct Issue {
+    /// Identifier of the error or warning, like `unknown_identifier`.
+    pub code: String,
+    pub title: String,
+    /// Zero-based line where the issue is located.
+    pub line: usize,
+    /// Zero-based column where the issue is located, in chars.
+    pub column: usize,
+}
+
+impl Issue {
+    fn new(code: &str, title: &str, labels: &[Label]) -> (Self, usize) {
+        let label = labels.first().expect("reports have a main label");
+        let issue = Self {
+            code: code.to_string(),
+            title: title.to_string(),
+            line: label.start().line,
+            column: label.start().column,
+        };
+        (issue, label.span().start())
+    }
+}
+
+/// Compiles YARA source code and compares the result with the metadata
+/// exported from YARA.
+///
+/// Source codes that have errors are left out of the compiled rules, but
+/// the rest of them are compiled as usual. Notice that rules that depend
+/// on a rule that was left out fail too.
+pub struct Importer<'src> {
+    sources: Vec<(String, SourceCode<'src>)>,
+    namespace: String,
+    legacy_metadata: LegacyMetadata,
+    extensions: bool,
+}
+
+impl<'src> Default for Importer<'src> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'src> Importer<'src> {
+    /// Creates a new importer.
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            legacy_metadata: LegacyMetadata::default(),
+            extensions: false,
+        }
+    }
+
+    /// Sets the metadata exported from YARA. Without it, every rule is
+    /// reported as not being in the legacy metadata.
+    pub fn legacy_metadata(mut self, metadata: LegacyMetadata) -> Self {
+        self.legacy_metadata = metadata;
+        self
+    }
+
+    /// Enables or disables YARA-X extensions to the YARA language, see
+    /// [`Compiler::enable_extensions`].
+    pub fn enable_extensions(mut self, yes: bool) -> Self {
+        self.extensions = yes;
+        self
+    }
+
+    /// Puts the source code added after this call in the given namespace,
+    /// see [`Compiler::new_namespace`].
+    pub fn new_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = namespace.to_string();
+        self
+    }
+
+    /// Adds a YARA source code. Errors in the source code don't make the
+    /// importer fail, they are included in the report.
+    pub fn add_source<S>(mut self, src: S) -> Self
+    where
+        S: Into<SourceCode<'src>>,
+    {
+        self.sources.push((self.namespace.clone(), src.into()));
+        self
+    }
+
+    /// Compiles the source codes and produces the report.
+    ///
+    /// Returns the rules in the source codes that compiled, together with
+    /// the report.
+    pub fn import(self) -> Result<(Rules, MigrationReport), Error> {
+        let legacy_metadata = self.legacy_metadata.index();
+        let mut sources = Vec::with_capacity(self.sources.len());
+        let mut rules = Vec::new();
+
+        // Parse the source codes for extracting the rules declared in
+        // each one.
+        for (namespace, src) in self.sources.iter() {
+            let mut source = SourceReport {
+                origin: src.get_origin().map(String::from),
+                namespace: namespace.clone(),
+                status: SourceStatus::Compiled,
+                errors: Vec::new(),
+            };
+            let mut declared = Vec::new();
+
+            match Parser::new().build_ast(src.clone()) {
+                Ok(ast) => {
+                    for rule in ast.namespaces.iter().flat_map(|ns| &ns.rules)
+                    {
+                        declared
+                            .push((rule.identifier.span.start(), rules.len()));
+                        rules.push(rule_report(
+                            namespace,
+                            src,
+                            rule,
+                            &legacy_metadata,
+                        ));
+                    }
+                    attribute_warnings(&mut rules, &declared, &ast.warnings);
+                }
+                Err(err) => {
+                    source.status = SourceStatus::Failed;
+                    source.errors.push(
+                        Issue::new(
+                            err.info().code(),
+                            &err.to_string(),
+                            err.labels(),
+                        )
+                        .0,
+                    );
+                }
+            }
+
+            sources.push((source, declared));
+        }
+
+        let mut compiler = Compiler::new();
+        let mut warnings = Vec::with_capacity(sources.len());
+
+        compiler.enable_extensions(self.extensions);
+
+        // Compile the source codes that were parsed. A source code that
+        // fails doesn't change the compiler, so the remaining ones are
+        // compiled as if it was never added.
+        for ((namespace, src), (source, declared)) in
+            self.sources.iter().zip(sources.iter_mut())
+        {
+            if matches!(source.status, SourceStatus::Failed) {
+                warnings.push(None);
+                continue;
+            }
+
+            let start = compiler.warnings().len();
+
+            let err = match compiler
+                .new_namespace(namespace)
+                .add_source(src.clone())
+            {
+                Ok(_) => {
+                    warnings.push(Some(start..compiler.warnings().len()));
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            warnings.push(None);
+            source.status = SourceStatus::Failed;
+
+            for (issue, offset) in compile_issues(&err) {
+                match rule_at(declared, offset) {
+                    Some(rule) => rules[rule].errors.push(issue),
+                    None => source.errors.push(issue),
+                }
+            }
+
+            for (_, rule) in declared.iter() {
+                let rule = &mut rules[*rule];
+                rule.status = if rule.errors.is_empty() {
+                    RuleStatus::Skipped
+                } else {
+                    RuleStatus::Failed
+                };
+            }
+        }
+
+        // The warnings raised while parsing were attributed to the rules
+        // already, but for source codes that compiled they are replaced
+        // with the ones raised by the compiler, which include the former.
+        for ((_, declared), warnings) in sources.iter().zip(warnings) {
+            if let Some(warnings) = warnings {
+                for (_, rule) in declared.iter() {
+                    rules[*rule].warnings.clear();
+                }
+                attribute_warnings(
+                    &mut rules,
+                    declared,
+                    &compiler.warnings()[warnings],
+                );
+            }
+        }
+
+        // Rules that appear only in the legacy metadata.
+        let declared: FxHashSet<_> = rules
+            .iter()
+            .map(|rule| (rule.namespace.as_str(), rule.name.as_str()))
+            .collect();
+
+        let mut not_found = Vec::new();
+
+        for legacy in self.legacy_metadata.rules.iter() {
+            let key = (legacy.namespace.as_str(), legacy.identifier.as_str());
+            if !declared.contains(&key) {
+                not_found.push(RuleReport {
+                    namespace: legacy.namespace.clone(),
+                    name: legacy.identifier.clone(),
+                    origin: None,
+                    status: RuleStatus::NotFound,
+                    in_legacy_metadata: true,
+                    differences: Vec::new(),
+                    errors: Vec::new(),
+                    warnings: Vec::new(),
+                });
+            }
+        }
+
+        rules.append(&mut not_found);
+
+        let report = MigrationReport {
+            sources: sources.into_iter().map(|(source, _)| source).collect(),
+            rules,
+        };
+
+        Ok((compiler.build()?, report))
+    }
+}
+
+/// Creates the report for a rule, comparing its tags and metadata with the
+/// legacy metadata.
+fn rule_report(
+    namespace: &str,
+    src: &SourceCode,
+    rule: &ast::Rule,
+    legacy_metadata: &FxHashMap<(&str, &str), &LegacyRule>,
+) -> RuleReport {
+    let name = rule.identifier.name;
+    let legacy = legacy_metadata.get(&(namespace, name)).copied();
+
+    let differences = match legacy {
+        Some(legacy) => compare(rule, legacy),
+        None => Vec::new(),
+    };
+
+    RuleReport {
+        namespace: namespace.to_string(),
+        name: name.to_string(),
+        origin: src.get_origin().map(String::from),
+        status: RuleStatus::Compiled,
+        in_legacy_metadata: legacy.is_some(),
+        differences,
+        errors: Vec::new(),
+        warnings: Vec::new(),
+    }
+}
+
+/// Compares the tags and metadata of a rule with the legacy ones.
+fn compare(rule: &ast::Rule, legacy: &LegacyRule) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    let tags: Vec<&str> =
+        rule.tags.iter().flatten().map(|tag| tag.name).collect();
+
+    for tag in legacy.tags.iter() {
+        if !tags.contains(&tag.as_str()) {
+            differences.push(Difference::MissingTag { tag: tag.clone() });
+        }
+    }
+
+    for tag in tags {
+        if !legacy.tags.iter().any(|legacy| legacy == tag) {
+            differences.push(Difference::ExtraTag { tag: tag.to_string() });
+        }
+    }
+
+    // If an identifier appears more than once, the last value wins.
+    let meta: BTreeMap<&str, MetaValue> = rule
+        .meta
+        .iter()
+        .flatten()
+        .map(|meta| (meta.identifier.name, MetaValue::from(&meta.value)))
+        .collect();
+
+    for (identifier, legacy) in legacy.meta.iter() {
+        match meta.get(identifier.as_str()) {
+            None => differences.push(Difference::MissingMeta {
+                identifier: identifier.clone(),
+                legacy: legacy.clone(),
+            }),
+            Some(value) if value != legacy => {
+                differences.push(Difference::MetaMismatch {
+                    identifier: identifier.clone(),
+                    legacy: legacy.clone(),
+                    value: value.clone(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (identifier, value) in meta {
+        if !legacy.meta.contains_key(identifier) {
+            differences.push(Difference::ExtraMeta {
+                identifier: identifier.to_string(),
+                value,
+            })
+        }
+    }
+
+    differences
+}
+
+/// Returns the errors in an error returned by [`Compiler::add_source`],
+/// with the offset where each one is located.
+fn compile_issues(err: &Error) -> Vec<(Issue, usize)> {
+    match err {
+        Error::ParseError(err) => {
+            vec![Issue::new(err.info().code(), &err.to_string(), err.labels())]
+        }
+        Error::CompileError(err) => {
+            vec![Issue::new(err.code(), err.title(), err.labels())]
+        }
+        Error::CompileErrors(errs) => errs
+            .as_slice()
+            .iter()
+            .map(|err| Issue::new(err.code(), err.title(), err.labels()))
+            .collect(),
+        err => unreachable!("unexpected error: {}", err),
+    }
+}
+
+/// Adds warnings to the rules they belong to. Warnings that don't belong
+/// to any rule are ignored.
+fn attribute_warnings<'a, I>(
+    rules: &mut [RuleReport],
+    declared: &[(usize, usize)],
+    warnings: I,
+) where
+    I: IntoIterator<Item = &'a yara_x_parser::Warning>,
+{
+    for warning in warnings {
+        let (issue, offset) =
+            Issue::new(warning.code(), warning.title(), warning.labels());
+        if let Some(rule) = rule_at(declared, offset) {
+            rules[rule].warnings.push(issue);
+        }
+    }
+}
+
+/// Given the rules declared in a source code, as pairs with the offset of
+/// the rule's identifier and the index of the rule in the report, returns
+/// the index of the rule that contains the given offset.
+///
+/// Each rule goes from its identifier up to the identifier of the next one.
+/// Offsets before the first rule, like the ones in `import` statements,
+/// don't belong to any rule.
+fn rule_at(declared: &[(usize, usize)], offset: usize) -> Option<usize> {
+    let next = declared.partition_point(|(start, _)| *start <= offset);
+    next.checked_sub(1).map(|i| declared[i].1)
+}
@@ -0,0 +1,188 @@
+use pretty_assertions::assert_eq;
+
+use crate::migration::{
+    Difference, Importer, LegacyMetadata, MetaValue, RuleStatus, SourceStatus,
+};
+use crate::SourceCode;
+
+macro_rules! testdata {
+    ($path:literal) => {
+        SourceCode::from(include_str!(concat!("testdata/", $path)))
+            .origin($path)
+    };
+}
+
+fn importer() -> Importer<'static> {
+    let legacy =
+        LegacyMetadata::from_json(include_bytes!("testdata/legacy.json"))
+            .unwrap();
+
+    Importer::new()
+        .legacy_metadata(legacy)
+        .add_source(testdata!("rules/clean.yar"))
+        .add_source(testdata!("rules/divergent.yar"))
+        .add_source(testdata!("rules/broken.yar"))
+        .add_source(testdata!("rules/syntax.yar"))
+        .add_source(testdata!("rules/dependent.yar"))
+        .new_namespace("other")
+        .add_source(testdata!("rules/flagged.yar"))
+}
+
+#[test]
+fn report() {
+    let (_, report) = importer().import().unwrap();
+
+    let mut json = serde_json::to_string_pretty(&report).unwrap();
+    json.push('\n');
+
+    assert_eq!(json, include_str!("testdata/report.json"));
+    assert!(!report.is_clean());
+}
+
+#[test]
+fn statuses() {
+    let (rules, report) = importer().import().unwrap();
+
+    let statuses: Vec<_> = report
+        .rules
+        .iter()
+        .map(|rule| (rule.name.as_str(), rule.status))
+        .collect();
+
+    assert_eq!(
+        statuses,
+        [
+            ("clean", RuleStatus::Compiled),
+            ("renamed_tag", RuleStatus::Compiled),
+            ("changed_meta", RuleStatus::Compiled),
+            ("not_in_legacy", RuleStatus::Compiled),
+            ("before_error", RuleStatus::Skipped),
+            ("unknown_identifier", RuleStatus::Failed),
+            ("after_error", RuleStatus::Skipped),
+            ("depends_on_broken", RuleStatus::Failed),
+            ("flagged", RuleStatus::Compiled),
+            ("lost", RuleStatus::NotFound),
+            ("only_in_legacy", RuleStatus::NotFound),
+        ]
+    );
+
+    let sources: Vec<_> =
+        report.sources.iter().map(|source| source.status).collect();
+
+    assert_eq!(
+        sources,
+        [
+            SourceStatus::Compiled,
+            SourceStatus::Compiled,
+            SourceStatus::Failed,
+            SourceStatus::Failed,
+            SourceStatus::Failed,
+            SourceStatus::Compiled,
+        ]
+    );
+
+    // Only the source codes that compiled are in the compiled rules.
+    let compiled: Vec<_> =
+        rules.iter().map(|rule| (rule.namespace(), rule.name())).collect();
+
+    assert_eq!(
+        compiled,
+        [
+            ("default", "clean"),
+            ("default", "renamed_tag"),
+            ("default", "changed_meta"),
+            ("default", "not_in_legacy"),
+            ("other", "flagged"),
+        ]
+    );
+}
+
+#[test]
+fn differences() {
+    let (_, report) = importer().import().unwrap();
+
+    let rule =
+        |name| report.rules.iter().find(|rule| rule.name == name).unwrap();
+
+    assert!(rule("clean").differences.is_empty());
+    assert!(!rule("not_in_legacy").in_legacy_metadata);
+
+    assert_eq!(
+        rule("renamed_tag").differences,
+        [
+            Difference::MissingTag { tag: "apt_28".to_string() },
+            Difference::ExtraTag { tag: "apt28".to_string() },
+        ]
+    );
+
+    // Values that are the same once parsed, like `0x10` and `16`, are not
+    // reported, and for repeated identifiers the last value is compared.
+    assert_eq!(
+        rule("changed_meta").differences,
+        [
+            Difference::MetaMismatch {
+                identifier: "description".to_string(),
+                legacy: MetaValue::String("first\\tsecond".to_string()),
+                value: MetaValue::String("first\tsecond".to_string()),
+            },
+            Difference::MissingMeta {
+                identifier: "removed".to_string(),
+                legacy: MetaValue::Integer(1),
+            },
+            Difference::MetaMismatch {
+                identifier: "version".to_string(),
+                legacy: MetaValue::String("2".to_string()),
+                value: MetaValue::Integer(2),
+            },
+            Difference::ExtraMeta {
+                identifier: "added".to_string(),
+                value: MetaValue::String("yes".to_string()),
+            },
+        ]
+    );
+
+    // The duplicate tag is reported as a warning, but it is not a
+    // difference.
+    let flagged = rule("flagged");
+
+    assert!(flagged.differences.is_empty());
+    assert_eq!(
+        flagged.warnings.iter().map(|w| w.code.as_str()).collect::<Vec<_>>(),
+        ["duplicate_tag", "non_boolean_as_boolean"]
+    );
+}
+
+#[test]
+fn clean() {
+    let legacy = LegacyMetadata::from_json(
+        br#"[{"identifier": "clean", "tags": ["trojan", "windows"], "meta": {"author": "analyst", "version": 3, "production": true}}]"#,
+    )
+    .unwrap();
+
+    let (rules, report) = Importer::new()
+        .legacy_metadata(legacy)
+        .add_source(testdata!("rules/clean.yar"))
+        .import()
+        .unwrap();
+
+    assert!(report.is_clean());
+    assert_eq!(rules.iter().count(), 1);
+
+    // Without legacy metadata the rule is not clean.
+    let (_, report) = Importer::new()
+        .add_source(testdata!("rules/clean.yar"))
+        .import()
+        .unwrap();
+
+    assert!(!report.is_clean());
+}
+
+#[test]
+fn invalid_legacy_metadata() {
+    assert!(LegacyMetadata::from_json(b"{}").is_err());
+    assert!(LegacyMetadata::from_json(br#"[{"tags": []}]"#).is_err());
+    assert!(LegacyMetadata::from_json(
+        br#"[{"identifier": "a", "meta": {"b": []}}]"#
+    )
+    .is_err());
+}
@@ -0,0 +1,94 @@
+/*! Implementation of the `console` module.
+
+The functions in this module send messages to the callback set with
+[`crate::Scanner::console_log`], if any, and always return true, so that
+they can be used in conditions without changing their results, like in
+`console.log("size: ", filesize) and filesize > 100`. Undefined arguments
+are logged as `undef`.
+
+String arguments can contain anything, including bytes coming from the
+scanned data, so they are passed through [`escape`]. Messages contain only
+printable ASCII characters, and escape sequences can't reach the terminal
+where the messages are shown.
+*/
+
+use yara_x_parser::escape::escape;
+
+use crate::modules::prelude::*;
+use crate::modules::protos::console::*;
+
+/// Text logged in place of undefined arguments.
+const UNDEF: &str = "undef";
+
+#[module_main]
+fn main(_ctx: &ScanContext) -> Console {
+    // Nothing to do, but we have to return our protobuf
+    Console::new()
+}
+
+#[module_export(name = "log")]
+fn log_str(ctx: &mut ScanContext, s: Option<RuntimeString>) -> bool {
+    let message = string_or_undef(ctx, s);
+    log(ctx, message)
+}
+
+#[module_export(name = "log")]
+fn log_int(ctx: &mut ScanContext, i: Option<i64>) -> bool {
+    log(ctx, to_string_or_undef(i))
+}
+
+#[module_export(name = "log")]
+fn log_float(ctx: &mut ScanContext, f: Option<f64>) -> bool {
+    log(ctx, to_string_or_undef(f))
+}
+
+#[module_export(name = "log")]
+fn log_msg_str(
+    ctx: &mut ScanContext,
+    message: Option<RuntimeString>,
+    s: Option<RuntimeString>,
+) -> bool {
+    let message = string_or_undef(ctx, message) + &string_or_undef(ctx, s);
+    log(ctx, message)
+}
+
+#[module_export(name = "log")]
+fn log_msg_int(
+    ctx: &mut ScanContext,
+    message: Option<RuntimeString>,
+    i: Option<i64>,
+) -> bool {
+    let message = string_or_undef(ctx, message) + &to_string_or_undef(i);
+    log(ctx, message)
+}
+
+#[module_export(name = "log")]
+fn log_msg_float(
+    ctx: &mut ScanContext,
+    message: Option<RuntimeString>,
+    f: Option<f64>,
+) -> bool {
+    let message = string_or_undef(ctx, message) + &to_string_or_undef(f);
+    log(ctx, message)
+}
+
+fn log(ctx: &mut ScanContext, message: String) -> bool {
+    if let Some(console_log) = ctx.console_log.as_mut() {
+        console_log(message);
+    }
+    true
+}
+
+fn string_or_undef(ctx: &ScanContext, s: Option<RuntimeString>) -> String {
+    match s {
+        Some(s) => escape(s.as_bstr(ctx)),
+        None => UNDEF.to_string(),
+    }
+}
+
+fn to_string_or_undef<T: ToString>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => UNDEF.to_string(),
+    }
+}
@@ -0,0 +1,54 @@
+/*! Implementation of the `elf` module.
+
+The module parses the headers of ELF files, see [`parser`]. When the data is
+not an ELF file every field is undefined, and the `sections` and `segments`
+arrays are empty.
+*/
+
+use crate::modules::prelude::*;
+use crate::modules::protos::elf::*;
+
+mod parser;
+
+use parser::ElfFile;
+
+#[module_main]
+fn main(ctx: &ScanContext) -> ELF {
+    let mut elf_proto = ELF::new();
+
+    let Some(elf) = ElfFile::parse(ctx.scanned_data()) else {
+        return elf_proto;
+    };
+
+    elf_proto.set_type(elf.elf_type.into());
+    elf_proto.set_machine(elf.machine.into());
+    elf_proto.set_number_of_sections(elf.number_of_sections.into());
+    elf_proto.set_number_of_segments(elf.number_of_segments.into());
+    elf_proto.entry_point =
+        elf.address_to_offset(elf.entry).map(|offset| offset as u64);
+
+    for section in elf.sections.iter() {
+        let mut section_proto = Section::new();
+        section_proto.name = section.name.map(|name| name.to_vec());
+        section_proto.set_type(section.section_type);
+        section_proto.set_flags(section.flags);
+        section_proto.set_address(section.address);
+        section_proto.set_offset(section.offset);
+        section_proto.set_size(section.size);
+        elf_proto.sections.push(section_proto);
+    }
+
+    for segment in elf.segments.iter() {
+        let mut segment_proto = Segment::new();
+        segment_proto.set_type(segment.segment_type);
+        segment_proto.set_flags(segment.flags);
+        segment_proto.set_offset(segment.offset);
+        segment_proto.set_virtual_address(segment.virtual_address);
+        segment_proto.set_physical_address(segment.physical_address);
+        segment_proto.set_file_size(segment.file_size);
+        segment_proto.set_memory_size(segment.memory_size);
+        elf_proto.segments.push(segment_proto);
+    }
+
+    elf_proto
+}
@@ -0,0 +1,331 @@
+/*! Parser for the headers of ELF files.
+
+As with PE files, the parser never fails as a whole once the ELF header has
+been read. Sections and segments are read with bounds checks, and those
+that are outside the file are simply missing, while the rest of the file is
+still parsed.
+*/
+
+/// Maximum length of section names. Longer names are treated as if they
+/// were not in the string table.
+const MAX_NAME_LEN: usize = 1024;
+
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+
+const PT_LOAD: u32 = 1;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u64 = 0x2;
+
+const SIZE_OF_HEADER_32: usize = 52;
+const SIZE_OF_HEADER_64: usize = 64;
+const SIZE_OF_SECTION_HEADER_32: usize = 40;
+const SIZE_OF_SECTION_HEADER_64: usize = 64;
+const SIZE_OF_PROGRAM_HEADER_32: usize = 32;
+const SIZE_OF_PROGRAM_HEADER_64: usize = 56;
+
+/// A section header.
+pub(crate) struct Section<'a> {
+    /// Section name, or [`None`] if it is not in the section names string
+    /// table.
+    pub name: Option<&'a [u8]>,
+    pub section_type: u32,
+    pub flags: u64,
+    pub address: u64,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// A program header, which describes a segment.
+pub(crate) struct Segment {
+    pub segment_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub physical_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+}
+
+/// The result of parsing an ELF file.
+pub(crate) struct ElfFile<'a> {
+    data: &'a [u8],
+    pub elf_type: u16,
+    pub machine: u16,
+    pub entry: u64,
+    pub number_of_sections: u16,
+    pub number_of_segments: u16,
+    pub sections: Vec<Section<'a>>,
+    pub segments: Vec<Segment>,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parses `data` as an ELF file. Returns [`None`] if `data` doesn't
+    /// start with the ELF signature, the class or the byte order are not
+    /// valid, or the ELF header is not complete.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.get(0..4)? != b"\x7fELF" {
+            return None;
+        }
+
+        let is_64_bits = match *data.get(4)? {
+            ELFCLASS32 => false,
+            ELFCLASS64 => true,
+            _ => return None,
+        };
+
+        let big_endian = match *data.get(5)? {
+            ELFDATA2LSB => false,
+            ELFDATA2MSB => true,
+            _ => return None,
+        };
+
+        let reader = Reader { data, is_64_bits, big_endian };
+
+        // The entry point and the offsets of the program and section header
+        // tables are the only fields in the header whose size depends on the
+        // class, so the offset of the fields that follow them, starting with
+        // `e_ehsize`, also depends on the class.
+        let (entry, phoff, shoff, ehsize) = if is_64_bits {
+            if data.len() < SIZE_OF_HEADER_64 {
+                return None;
+            }
+            (reader.u64(24)?, reader.u64(32)?, reader.u64(40)?, 52)
+        } else {
+            if data.len() < SIZE_OF_HEADER_32 {
+                return None;
+            }
+            let entry = reader.u32(24)?.into();
+            (entry, reader.u32(28)?.into(), reader.u32(32)?.into(), 40)
+        };
+
+        let mut elf = Self {
+            data,
+            elf_type: reader.u16(16)?,
+            machine: reader.u16(18)?,
+            entry,
+            number_of_segments: reader.u16(ehsize + 4)?,
+            number_of_sections: reader.u16(ehsize + 8)?,
+            sections: Vec::new(),
+            segments: Vec::new(),
+        };
+
+        let shstrndx = reader.u16(ehsize + 10)?;
+
+        elf.parse_segments(&reader, phoff);
+        elf.parse_sections(&reader, shoff, shstrndx);
+
+        Some(elf)
+    }
+
+    /// Returns the offset within the file that corresponds to the virtual
+    /// `address`.
+    ///
+    /// The address is translated using the loadable segments, or the
+    /// sections that occupy memory when the file doesn't have segments, as
+    /// in relocatable objects.
+    pub fn address_to_offset(&self, address: u64) -> Option<usize> {
+        let offset = if !self.segments.is_empty() {
+            let segment = self.segments.iter().find(|s| {
+                s.segment_type == PT_LOAD
+                    && address >= s.virtual_address
+                    && address - s.virtual_address < s.file_size
+            })?;
+            segment.offset.checked_add(address - segment.virtual_address)?
+        } else {
+            let section = self.sections.iter().find(|s| {
+                s.section_type != SHT_NOBITS
+                    && s.flags & SHF_ALLOC != 0
+                    && address >= s.address
+                    && address - s.address < s.size
+            })?;
+            section.offset.checked_add(address - section.address)?
+        };
+
+        let offset = usize::try_from(offset).ok()?;
+
+        if offset < self.data.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn parse_segments(&mut self, reader: &Reader, start: u64) {
+        let size = if reader.is_64_bits {
+            SIZE_OF_PROGRAM_HEADER_64
+        } else {
+            SIZE_OF_PROGRAM_HEADER_32
+        };
+
+        for offset in table_entries(start, self.number_of_segments, size) {
+            let Some(raw) = reader.sub_reader(offset, size) else {
+                break;
+            };
+
+            // The flags are right after the type in 64-bit files, and
+            // after the remaining fields in 32-bit files.
+            let segment = if raw.is_64_bits {
+                Segment {
+                    segment_type: raw.u32(0).unwrap(),
+                    flags: raw.u32(4).unwrap(),
+                    offset: raw.u64(8).unwrap(),
+                    virtual_address: raw.u64(16).unwrap(),
+                    physical_address: raw.u64(24).unwrap(),
+                    file_size: raw.u64(32).unwrap(),
+                    memory_size: raw.u64(40).unwrap(),
+                }
+            } else {
+                Segment {
+                    segment_type: raw.u32(0).unwrap(),
+                    offset: raw.u32(4).unwrap().into(),
+                    virtual_address: raw.u32(8).unwrap().into(),
+                    physical_address: raw.u32(12).unwrap().into(),
+                    file_size: raw.u32(16).unwrap().into(),
+                    memory_size: raw.u32(20).unwrap().into(),
+                    flags: raw.u32(24).unwrap(),
+                }
+            };
+
+            self.segments.push(segment);
+        }
+    }
+
+    fn parse_sections(&mut self, reader: &Reader, start: u64, shstrndx: u16) {
+        let size = if reader.is_64_bits {
+            SIZE_OF_SECTION_HEADER_64
+        } else {
+            SIZE_OF_SECTION_HEADER_32
+        };
+
+        // Offsets of the section names within the string table, which can
+        // be read only after the string table's own header is parsed.
+        let mut name_offsets = Vec::new();
+
+        for offset in table_entries(start, self.number_of_sections, size) {
+            let Some(raw) = reader.sub_reader(offset, size) else {
+                break;
+            };
+
+            let section = if raw.is_64_bits {
+                Section {
+                    name: None,
+                    section_type: raw.u32(4).unwrap(),
+                    flags: raw.u64(8).unwrap(),
+                    address: raw.u64(16).unwrap(),
+                    offset: raw.u64(24).unwrap(),
+                    size: raw.u64(32).unwrap(),
+                }
+            } else {
+                Section {
+                    name: None,
+                    section_type: raw.u32(4).unwrap(),
+                    flags: raw.u32(8).unwrap().into(),
+                    address: raw.u32(12).unwrap().into(),
+                    offset: raw.u32(16).unwrap().into(),
+                    size: raw.u32(20).unwrap().into(),
+                }
+            };
+
+            name_offsets.push(raw.u32(0).unwrap());
+            self.sections.push(section);
+        }
+
+        let Some(string_table) = self
+            .sections
+            .get(shstrndx as usize)
+            .and_then(|s| self.section_data(s))
+        else {
+            return;
+        };
+
+        for (section, name_offset) in
+            self.sections.iter_mut().zip(name_offsets)
+        {
+            section.name = read_name(string_table, name_offset as usize);
+        }
+    }
+
+    /// Returns the content of `section`, or [`None`] if the section
+    /// extends beyond the end of the file.
+    fn section_data(&self, section: &Section) -> Option<&'a [u8]> {
+        let start = usize::try_from(section.offset).ok()?;
+        let size = usize::try_from(section.size).ok()?;
+        self.data.get(start..start.checked_add(size)?)
+    }
+}
+
+/// Reads integers with the byte order and class of an ELF file.
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64_bits: bool,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    /// Returns a reader for the `size` bytes that start at `offset`, or
+    /// [`None`] if they are not completely within the data.
+    fn sub_reader(&self, offset: usize, size: usize) -> Option<Reader<'a>> {
+        Some(Reader {
+            data: self.data.get(offset..offset.checked_add(size)?)?,
+            is_64_bits: self.is_64_bits,
+            big_endian: self.big_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset.checked_add(2)?)?;
+        let bytes = bytes.try_into().unwrap();
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset.checked_add(4)?)?;
+        let bytes = bytes.try_into().unwrap();
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> Option<u64> {
+        let bytes = self.data.get(offset..offset.checked_add(8)?)?;
+        let bytes = bytes.try_into().unwrap();
+        Some(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+}
+
+/// Returns the offsets of the entries in a table of `count` entries of
+/// `size` bytes each, that starts at `start`. A table at offset 0 is an
+/// empty table, and the offsets that overflow are not returned.
+fn table_entries(
+    start: u64,
+    count: u16,
+    size: usize,
+) -> impl Iterator<Item = usize> {
+    let start = usize::try_from(start).ok().filter(|start| *start != 0);
+    let count = if start.is_some() { count as usize } else { 0 };
+    (0..count).map_while(move |i| start?.checked_add(i * size))
+}
+
+/// Reads a null-terminated name starting at `offset` in a string table.
+/// Returns [`None`] if the null character is not found within the first
+/// [`MAX_NAME_LEN`] bytes, or before the end of the table.
+fn read_name(string_table: &[u8], offset: usize) -> Option<&[u8]> {
+    let data = string_table.get(offset..)?;
+    let data = &data[..data.len().min(MAX_NAME_LEN + 1)];
+    let end = data.iter().position(|b| *b == 0)?;
+    Some(&data[..end])
+}
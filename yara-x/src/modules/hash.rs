@@ -0,0 +1,149 @@
+/*! Implementation of the `hash` module.
+
+Every function in this module has two variants, one that receives a string
+and another one that receives an offset and a size, and operates on that
+block of the scanned data. If the block extends beyond the end of the data,
+or the offset or size are negative, the result is undefined.
+
+`md5`, `sha1` and `sha256` return the digest as a lowercase hex string,
+while `crc32` and `checksum32` return an integer, as in YARA. Rules tend to
+compute the same digest many times, for instance when comparing it with a
+list of known hashes, so digests of the scanned data are cached during the
+scan, see [`Cache`].
+*/
+
+use md5::Md5;
+use rustc_hash::FxHashMap;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::modules::prelude::*;
+use crate::modules::protos::hash::*;
+
+/// Digests of blocks of scanned data, keyed by the algorithm, the offset
+/// and the size of the block. The cache lives in [`ScanContext`] and is
+/// cleared at the beginning of every scan.
+pub(crate) type Cache = FxHashMap<(Algorithm, i64, i64), String>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+#[module_main]
+fn main(_ctx: &ScanContext) -> Hash {
+    // Nothing to do, but we have to return our protobuf
+    Hash::new()
+}
+
+#[module_export(name = "md5")]
+fn md5_data(
+    ctx: &mut ScanContext,
+    offset: i64,
+    size: i64,
+) -> Option<RuntimeString> {
+    cached_digest::<Md5>(ctx, Algorithm::Md5, offset, size)
+}
+
+#[module_export(name = "md5")]
+fn md5_string(ctx: &mut ScanContext, s: RuntimeString) -> RuntimeString {
+    let digest = hex_digest::<Md5>(s.as_bstr(ctx));
+    RuntimeString::from_bytes(ctx, digest)
+}
+
+#[module_export(name = "sha1")]
+fn sha1_data(
+    ctx: &mut ScanContext,
+    offset: i64,
+    size: i64,
+) -> Option<RuntimeString> {
+    cached_digest::<Sha1>(ctx, Algorithm::Sha1, offset, size)
+}
+
+#[module_export(name = "sha1")]
+fn sha1_string(ctx: &mut ScanContext, s: RuntimeString) -> RuntimeString {
+    let digest = hex_digest::<Sha1>(s.as_bstr(ctx));
+    RuntimeString::from_bytes(ctx, digest)
+}
+
+#[module_export(name = "sha256")]
+fn sha256_data(
+    ctx: &mut ScanContext,
+    offset: i64,
+    size: i64,
+) -> Option<RuntimeString> {
+    cached_digest::<Sha256>(ctx, Algorithm::Sha256, offset, size)
+}
+
+#[module_export(name = "sha256")]
+fn sha256_string(ctx: &mut ScanContext, s: RuntimeString) -> RuntimeString {
+    let digest = hex_digest::<Sha256>(s.as_bstr(ctx));
+    RuntimeString::from_bytes(ctx, digest)
+}
+
+#[module_export(name = "crc32")]
+fn crc32_data(ctx: &ScanContext, offset: i64, size: i64) -> Option<i64> {
+    Some(i64::from(crc32fast::hash(data_block(ctx, offset, size)?)))
+}
+
+#[module_export(name = "crc32")]
+fn crc32_string(ctx: &ScanContext, s: RuntimeString) -> i64 {
+    i64::from(crc32fast::hash(s.as_bstr(ctx)))
+}
+
+#[module_export(name = "checksum32")]
+fn checksum32_data(ctx: &ScanContext, offset: i64, size: i64) -> Option<i64> {
+    Some(i64::from(checksum32(data_block(ctx, offset, size)?)))
+}
+
+#[module_export(name = "checksum32")]
+fn checksum32_string(ctx: &ScanContext, s: RuntimeString) -> i64 {
+    i64::from(checksum32(s.as_bstr(ctx)))
+}
+
+/// Returns the digest of a block of scanned data, computing it only if it
+/// is not already in the cache.
+fn cached_digest<D: Digest>(
+    ctx: &mut ScanContext,
+    algorithm: Algorithm,
+    offset: i64,
+    size: i64,
+) -> Option<RuntimeString> {
+    let key = (algorithm, offset, size);
+
+    let digest = match ctx.hash_cache.get(&key) {
+        Some(digest) => digest.clone(),
+        None => {
+            let digest = hex_digest::<D>(data_block(ctx, offset, size)?);
+            ctx.hash_cache.insert(key, digest.clone());
+            digest
+        }
+    };
+
+    Some(RuntimeString::from_bytes(ctx, digest))
+}
+
+/// Returns the block of scanned data that starts at `offset` and has
+/// `size` bytes. Returns [`None`] if `offset` or `size` are negative, or
+/// the block extends beyond the end of the data.
+fn data_block<'a>(
+    ctx: &'a ScanContext,
+    offset: i64,
+    size: i64,
+) -> Option<&'a [u8]> {
+    let offset = usize::try_from(offset).ok()?;
+    let size = usize::try_from(size).ok()?;
+    ctx.scanned_data_in(offset..offset.checked_add(size)?)
+}
+
+/// Returns the digest of `data` as a lowercase hex string.
+fn hex_digest<D: Digest>(data: &[u8]) -> String {
+    D::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Sum of all the bytes in `data`, modulo 2^32.
+fn checksum32(data: &[u8]) -> u32 {
+    data.iter().fold(0_u32, |sum, b| sum.wrapping_add(*b as u32))
+}
@@ -0,0 +1,271 @@
+/*! Implementation of the `math` module.
+
+Most functions in this module have two variants, one that receives a string
+and another one that receives an offset and a size, and operates on that
+block of the scanned data. Blocks that extend beyond the end of the data are
+truncated, but a block that doesn't contain any byte produces an undefined
+result, as well as negative offsets or sizes.
+*/
+
+use std::f64::consts::PI;
+
+use crate::modules::prelude::*;
+use crate::modules::protos::math::*;
+
+#[module_main]
+fn main(_ctx: &ScanContext) -> Math {
+    let mut math_proto = Math::new();
+    math_proto.set_mean_bytes(127.5);
+    math_proto
+}
+
+#[module_export(name = "entropy")]
+fn entropy_data(ctx: &ScanContext, offset: i64, size: i64) -> Option<f64> {
+    Some(entropy(data_block(ctx, offset, size)?))
+}
+
+#[module_export(name = "entropy")]
+fn entropy_string(ctx: &ScanContext, s: RuntimeString) -> Option<f64> {
+    Some(entropy(non_empty(s.as_bstr(ctx))?))
+}
+
+#[module_export(name = "mean")]
+fn mean_data(ctx: &ScanContext, offset: i64, size: i64) -> Option<f64> {
+    Some(mean(data_block(ctx, offset, size)?))
+}
+
+#[module_export(name = "mean")]
+fn mean_string(ctx: &ScanContext, s: RuntimeString) -> Option<f64> {
+    Some(mean(non_empty(s.as_bstr(ctx))?))
+}
+
+#[module_export(name = "deviation")]
+fn deviation_data(
+    ctx: &ScanContext,
+    offset: i64,
+    size: i64,
+    mean: f64,
+) -> Option<f64> {
+    Some(deviation(data_block(ctx, offset, size)?, mean))
+}
+
+#[module_export(name = "deviation")]
+fn deviation_string(
+    ctx: &ScanContext,
+    s: RuntimeString,
+    mean: f64,
+) -> Option<f64> {
+    Some(deviation(non_empty(s.as_bstr(ctx))?, mean))
+}
+
+#[module_export(name = "serial_correlation")]
+fn serial_correlation_data(
+    ctx: &ScanContext,
+    offset: i64,
+    size: i64,
+) -> Option<f64> {
+    Some(serial_correlation(data_block(ctx, offset, size)?))
+}
+
+#[module_export(name = "serial_correlation")]
+fn serial_correlation_string(
+    ctx: &ScanContext,
+    s: RuntimeString,
+) -> Option<f64> {
+    Some(serial_correlation(non_empty(s.as_bstr(ctx))?))
+}
+
+#[module_export(name = "monte_carlo_pi")]
+fn monte_carlo_pi_data(
+    ctx: &ScanContext,
+    offset: i64,
+    size: i64,
+) -> Option<f64> {
+    monte_carlo_pi(data_block(ctx, offset, size)?)
+}
+
+#[module_export(name = "monte_carlo_pi")]
+fn monte_carlo_pi_string(ctx: &ScanContext, s: RuntimeString) -> Option<f64> {
+    monte_carlo_pi(s.as_bstr(ctx))
+}
+
+#[module_export(name = "count")]
+fn count_data(
+    ctx: &ScanContext,
+    byte: i64,
+    offset: i64,
+    size: i64,
+) -> Option<i64> {
+    let byte = u8::try_from(byte).ok()?;
+    Some(wasm_i64(count(data_block(ctx, offset, size)?, byte)))
+}
+
+#[module_export(name = "count")]
+fn count_all(ctx: &ScanContext, byte: i64) -> Option<i64> {
+    let byte = u8::try_from(byte).ok()?;
+    Some(wasm_i64(count(all_data(ctx)?, byte)))
+}
+
+#[module_export(name = "percentage")]
+fn percentage_data(
+    ctx: &ScanContext,
+    byte: i64,
+    offset: i64,
+    size: i64,
+) -> Option<f64> {
+    let byte = u8::try_from(byte).ok()?;
+    let data = data_block(ctx, offset, size)?;
+    Some(count(data, byte) as f64 / data.len() as f64)
+}
+
+#[module_export(name = "percentage")]
+fn percentage_all(ctx: &ScanContext, byte: i64) -> Option<f64> {
+    let byte = u8::try_from(byte).ok()?;
+    let data = non_empty(all_data(ctx)?)?;
+    Some(count(data, byte) as f64 / data.len() as f64)
+}
+
+#[module_export]
+fn in_range(_ctx: &ScanContext, x: f64, lower: f64, upper: f64) -> bool {
+    lower <= x && x <= upper
+}
+
+#[module_export]
+fn min(_ctx: &ScanContext, a: i64, b: i64) -> i64 {
+    a.min(b)
+}
+
+#[module_export]
+fn max(_ctx: &ScanContext, a: i64, b: i64) -> i64 {
+    a.max(b)
+}
+
+#[module_export]
+fn abs(_ctx: &ScanContext, x: i64) -> Option<i64> {
+    x.checked_abs()
+}
+
+/// Returns the block of scanned data that starts at `offset` and has
+/// `size` bytes, truncated at the end of the data. Returns [`None`] if the
+/// block is empty or `offset` or `size` are negative.
+fn data_block<'a>(
+    ctx: &'a ScanContext,
+    offset: i64,
+    size: i64,
+) -> Option<&'a [u8]> {
+    let offset = usize::try_from(offset).ok()?;
+    let size = usize::try_from(size).ok()?;
+    let end = offset.saturating_add(size).min(ctx.scanned_data_size());
+    non_empty(ctx.scanned_data_in(offset..end)?)
+}
+
+/// Returns the whole scanned data, or [`None`] if it is not available, as
+/// it happens in streaming scans, see [`crate::StreamScanner`].
+fn all_data<'a>(ctx: &'a ScanContext) -> Option<&'a [u8]> {
+    ctx.scanned_data_in(0..ctx.scanned_data_size())
+}
+
+fn non_empty(data: &[u8]) -> Option<&[u8]> {
+    if data.is_empty() {
+        None
+    } else {
+        Some(data)
+    }
+}
+
+/// Returns the number of occurrences of each byte value in `data`.
+fn distribution(data: &[u8]) -> [usize; 256] {
+    let mut distribution = [0_usize; 256];
+    for b in data {
+        distribution[*b as usize] += 1;
+    }
+    distribution
+}
+
+fn count(data: &[u8], byte: u8) -> usize {
+    data.iter().filter(|b| **b == byte).count()
+}
+
+/// Shannon entropy of `data`, in bits per byte.
+fn entropy(data: &[u8]) -> f64 {
+    let len = data.len() as f64;
+    distribution(data)
+        .iter()
+        .filter(|n| **n > 0)
+        .map(|n| {
+            let p = *n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn mean(data: &[u8]) -> f64 {
+    data.iter().map(|b| *b as f64).sum::<f64>() / data.len() as f64
+}
+
+/// Mean absolute deviation of the bytes in `data` with respect to `mean`.
+fn deviation(data: &[u8], mean: f64) -> f64 {
+    data.iter().map(|b| (*b as f64 - mean).abs()).sum::<f64>()
+        / data.len() as f64
+}
+
+/// Serial correlation coefficient of `data`, where each byte is compared
+/// with the next one, and the last byte with the first one. Returns
+/// -100000.0 when the coefficient is undefined because all bytes are equal,
+/// as YARA does.
+fn serial_correlation(data: &[u8]) -> f64 {
+    let n = data.len() as f64;
+    let mut t1 = 0.0;
+    let mut t2 = 0.0;
+    let mut t3 = 0.0;
+    let mut last = 0.0;
+
+    for b in data {
+        let b = *b as f64;
+        t1 += last * b;
+        t2 += b;
+        t3 += b * b;
+        last = b;
+    }
+
+    t1 += last * data[0] as f64;
+    t2 *= t2;
+
+    let scc = n * t3 - t2;
+
+    if scc == 0.0 {
+        -100000.0
+    } else {
+        (n * t1 - t2) / scc
+    }
+}
+
+/// Uses the bytes in `data` as coordinates of points in a square, and
+/// approximates π from the proportion of points that fall inside a circle
+/// inscribed in the square. Each point takes six bytes, three for each
+/// coordinate. The result is the relative error of the approximation, which
+/// is low for random data. Returns [`None`] if `data` is shorter than a
+/// single point.
+fn monte_carlo_pi(data: &[u8]) -> Option<f64> {
+    let radius_squared = (256_f64.powi(3) - 1.0).powi(2);
+    let mut points = 0;
+    let mut inside = 0;
+
+    for point in data.chunks_exact(6) {
+        let (x, y) = point.split_at(3);
+        let x = x.iter().fold(0.0, |acc, b| acc * 256.0 + *b as f64);
+        let y = y.iter().fold(0.0, |acc, b| acc * 256.0 + *b as f64);
+        if x * x + y * y <= radius_squared {
+            inside += 1;
+        }
+        points += 1;
+    }
+
+    if points == 0 {
+        return None;
+    }
+
+    let pi = 4.0 * inside as f64 / points as f64;
+
+    Some(((pi - PI) / PI).abs())
+}
@@ -53,9 +53,9 @@ macro_rules! add_module {
     ($modules:expr, $name:literal, $proto:ident, $root_message:ident, $rust_module_name:expr, $main_fn:expr) => {{
         use std::stringify;
         let root_struct_descriptor = protos::$proto::file_descriptor()
-            // message_by_full_name expects a dot (.) at the beginning
-            // of the name.
-            .message_by_full_name(stringify!(.$root_message))
+            // The root message's name is relative to the package declared
+            // in the .proto file, if any.
+            .message_by_package_relative_name(stringify!($root_message))
             .expect(format!(
                 "`root_message` option in protobuf `{}` is wrong, message `{}` is not defined",
                 stringify!($proto),
@@ -1,7 +1,21 @@
 // File generated automatically by build.rs. Do not edit.
-#[cfg(feature = "text-module")]
-pub mod text;
+#[cfg(feature = "console-module")]
+pub mod console;
+#[cfg(feature = "time-module")]
+pub mod time;
 #[cfg(feature = "test_proto2-module")]
 pub mod test_proto2;
 #[cfg(feature = "test_proto3-module")]
-pub mod test_proto3;
\ No newline at end of file
+pub mod test_proto3;
+#[cfg(feature = "hash-module")]
+pub mod hash;
+#[cfg(feature = "math-module")]
+pub mod math;
+#[cfg(feature = "pe-module")]
+pub mod pe;
+#[cfg(feature = "elf-module")]
+pub mod elf;
+#[cfg(feature = "string-module")]
+pub mod string;
+#[cfg(feature = "text-module")]
+pub mod text;
\ No newline at end of file
@@ -0,0 +1,115 @@
+/*! Implementation of the `pe` module.
+
+The module parses the headers of PE files, see [`parser`]. When the data is
+not a PE file, `pe.is_pe` is false and the remaining fields are undefined.
+The import hash is computed the first time `pe.imphash()` is called during
+a scan, and cached for the rest of the scan, see [`Cache`].
+*/
+
+use md5::{Digest, Md5};
+
+use crate::modules::prelude::*;
+use crate::modules::protos::pe::*;
+
+mod parser;
+
+use parser::{ImportedFunc, PEFile};
+
+/// Import hash of the scanned data, which is `None` until `pe.imphash()`
+/// is called, and `Some(None)` if the data doesn't have one. The cache
+/// lives in [`ScanContext`] and is cleared at the beginning of every scan.
+pub(crate) type Cache = Option<Option<String>>;
+
+#[module_main]
+fn main(ctx: &ScanContext) -> PE {
+    let mut pe_proto = PE::new();
+
+    let Some(pe) = PEFile::parse(ctx.scanned_data()) else {
+        pe_proto.set_is_pe(false);
+        return pe_proto;
+    };
+
+    pe_proto.set_is_pe(true);
+    pe_proto.set_machine(pe.machine.into());
+    pe_proto.set_timestamp(pe.timestamp);
+    pe_proto.set_number_of_sections(pe.number_of_sections.into());
+    pe_proto.subsystem = pe.subsystem.map(|subsystem| subsystem.into());
+    pe_proto.entry_point_raw = pe.entry_point_rva;
+    pe_proto.entry_point = pe
+        .entry_point_rva
+        .and_then(|rva| pe.rva_to_offset(rva))
+        .map(|offset| offset as u64);
+
+    for section in pe.sections.iter() {
+        let mut section_proto = Section::new();
+        section_proto.set_name(section.name.to_vec());
+        section_proto.set_virtual_address(section.virtual_address);
+        section_proto.set_virtual_size(section.virtual_size);
+        section_proto.set_raw_data_offset(section.raw_data_offset);
+        section_proto.set_raw_data_size(section.raw_data_size);
+        section_proto.set_characteristics(section.characteristics);
+        pe_proto.sections.push(section_proto);
+    }
+
+    pe_proto
+}
+
+/// Returns the import hash of the file, which is the MD5 of the imported
+/// functions.
+///
+/// Functions are listed as `dll.function`, in the order they appear in the
+/// import table, in lowercase and separated by commas. The `.dll`, `.ocx`
+/// and `.sys` extensions are removed from DLL names, and functions imported
+/// by ordinal are listed as `dll.ordN`. The result is undefined if the file
+/// doesn't import any function.
+#[module_export]
+fn imphash(ctx: &mut ScanContext) -> Option<RuntimeString> {
+    let digest = match &ctx.imphash_cache {
+        Some(digest) => digest.clone(),
+        None => {
+            let digest = compute_imphash(ctx.scanned_data());
+            ctx.imphash_cache = Some(digest.clone());
+            digest
+        }
+    };
+
+    Some(RuntimeString::from_bytes(ctx, digest?))
+}
+
+/// Computes the import hash of `data`, see [`imphash`].
+fn compute_imphash(data: &[u8]) -> Option<String> {
+    let pe = PEFile::parse(data)?;
+    let mut md5 = Md5::new();
+    let mut first = true;
+
+    for import in pe.imports() {
+        let dll_name = import.dll_name.to_ascii_lowercase();
+        let dll_name = [b".dll".as_slice(), b".ocx", b".sys"]
+            .iter()
+            .find_map(|ext| dll_name.strip_suffix(*ext))
+            .unwrap_or(dll_name.as_slice());
+
+        for func in import.functions.iter() {
+            if !first {
+                md5.update(b",");
+            }
+            first = false;
+            md5.update(dll_name);
+            md5.update(b".");
+            match func {
+                ImportedFunc::Name(name) => {
+                    md5.update(name.to_ascii_lowercase());
+                }
+                ImportedFunc::Ordinal(ordinal) => {
+                    md5.update(format!("ord{}", ordinal));
+                }
+            }
+        }
+    }
+
+    if first {
+        return None;
+    }
+
+    Some(md5.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
@@ -0,0 +1,350 @@
+/*! Parser for the headers and import table of PE files.
+
+The parser never fails as a whole. Every structure is read with bounds
+checks, and any piece of information that can't be obtained because the
+file is truncated or malformed is simply [`None`], or missing in the case
+of sections and imports, while the rest of the file is still parsed.
+*/
+
+/// Maximum number of sections parsed, as in YARA.
+const MAX_SECTIONS: usize = 96;
+
+/// Maximum number of import descriptors parsed, as in YARA.
+const MAX_IMPORT_DESCRIPTORS: usize = 16384;
+
+/// Maximum number of entries read from the import lookup tables, for all
+/// DLLs together. Entries that don't resolve to a function count too, as
+/// many descriptors can point to the same table full of invalid entries.
+const MAX_IMPORTS: usize = 16384;
+
+/// Maximum length of the names of DLLs and imported functions.
+const MAX_NAME_LEN: usize = 256;
+
+const IMAGE_NT_OPTIONAL_HDR32_MAGIC: u16 = 0x10b;
+const IMAGE_NT_OPTIONAL_HDR64_MAGIC: u16 = 0x20b;
+
+const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+
+const SIZE_OF_FILE_HEADER: usize = 20;
+const SIZE_OF_SECTION_HEADER: usize = 40;
+const SIZE_OF_IMPORT_DESCRIPTOR: usize = 20;
+
+/// A section header.
+pub(crate) struct Section<'a> {
+    /// Section name, up to the first null character.
+    pub name: &'a [u8],
+    pub virtual_size: u32,
+    pub virtual_address: u32,
+    pub raw_data_size: u32,
+    pub raw_data_offset: u32,
+    pub characteristics: u32,
+}
+
+/// A function imported from a DLL.
+pub(crate) enum ImportedFunc<'a> {
+    Name(&'a [u8]),
+    Ordinal(u16),
+}
+
+/// A DLL and the functions imported from it.
+pub(crate) struct Import<'a> {
+    pub dll_name: &'a [u8],
+    pub functions: Vec<ImportedFunc<'a>>,
+}
+
+/// The result of parsing a PE file.
+pub(crate) struct PEFile<'a> {
+    data: &'a [u8],
+    pub machine: u16,
+    pub number_of_sections: u16,
+    pub timestamp: u32,
+    pub subsystem: Option<u16>,
+    pub entry_point_rva: Option<u32>,
+    pub sections: Vec<Section<'a>>,
+    /// `true` for PE32+ files.
+    is_64_bits: bool,
+    /// RVA and size of the data directories present in the optional header.
+    data_directories: Vec<(u32, u32)>,
+}
+
+impl<'a> PEFile<'a> {
+    /// Parses `data` as a PE file. Returns [`None`] if `data` doesn't have
+    /// valid DOS and PE signatures, or the file header is not complete.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.get(0..2)? != b"MZ" {
+            return None;
+        }
+
+        let pe_offset = read_u32(data, 0x3c)? as usize;
+
+        if data.get(pe_offset..pe_offset.checked_add(4)?)? != b"PE\0\0" {
+            return None;
+        }
+
+        let file_header = pe_offset + 4;
+        let file_header_end = file_header.checked_add(SIZE_OF_FILE_HEADER)?;
+
+        if data.len() < file_header_end {
+            return None;
+        }
+
+        let mut pe = Self {
+            data,
+            machine: read_u16(data, file_header)?,
+            number_of_sections: read_u16(data, file_header + 2)?,
+            timestamp: read_u32(data, file_header + 4)?,
+            subsystem: None,
+            entry_point_rva: None,
+            sections: Vec::new(),
+            is_64_bits: false,
+            data_directories: Vec::new(),
+        };
+
+        let size_of_optional_header =
+            read_u16(data, file_header + 16)? as usize;
+
+        pe.parse_optional_header(file_header_end);
+        pe.parse_sections(file_header_end + size_of_optional_header);
+
+        Some(pe)
+    }
+
+    /// Returns the offset within the file that corresponds to `rva`.
+    pub fn rva_to_offset(&self, rva: u32) -> Option<usize> {
+        // RVAs lower than the address of the first section are part of the
+        // headers, which are mapped to memory as they are in the file.
+        let lowest_section_va =
+            self.sections.iter().map(|s| s.virtual_address).min();
+
+        let offset = match lowest_section_va {
+            Some(lowest) if rva >= lowest => {
+                // If sections overlap, the last one wins, as in Windows.
+                let section = self.sections.iter().rev().find(|s| {
+                    let size = s.virtual_size.max(s.raw_data_size);
+                    rva >= s.virtual_address
+                        && (rva - s.virtual_address) < size
+                })?;
+                section
+                    .raw_data_offset
+                    .checked_add(rva - section.virtual_address)?
+            }
+            _ => rva,
+        };
+
+        let offset = offset as usize;
+
+        if offset < self.data.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the DLLs imported by the file, and the functions imported
+    /// from each of them.
+    pub fn imports(&self) -> Vec<Import<'a>> {
+        let mut imports = Vec::new();
+
+        let Some(&(rva, _)) =
+            self.data_directories.get(IMAGE_DIRECTORY_ENTRY_IMPORT)
+        else {
+            return imports;
+        };
+
+        let Some(mut descriptor) = self.rva_to_offset(rva) else {
+            return imports;
+        };
+
+        let mut remaining_thunks = MAX_IMPORTS;
+
+        // The list of import descriptors ends with a descriptor filled with
+        // zeroes, or where the file ends.
+        for _ in 0..MAX_IMPORT_DESCRIPTORS {
+            let Some(raw) = self.data.get(
+                descriptor
+                    ..descriptor.saturating_add(SIZE_OF_IMPORT_DESCRIPTOR),
+            ) else {
+                break;
+            };
+
+            if raw.iter().all(|b| *b == 0) || remaining_thunks == 0 {
+                break;
+            }
+
+            let original_first_thunk = read_u32(raw, 0).unwrap();
+            let name = read_u32(raw, 12).unwrap();
+            let first_thunk = read_u32(raw, 16).unwrap();
+
+            descriptor += SIZE_OF_IMPORT_DESCRIPTOR;
+
+            let Some(dll_name) = self
+                .rva_to_offset(name)
+                .and_then(|offset| read_name(self.data, offset))
+            else {
+                continue;
+            };
+
+            // The original first thunk points to the import lookup table,
+            // which is not modified when the file is loaded. Some linkers
+            // leave it empty and only the first thunk is present.
+            let thunks = if original_first_thunk != 0 {
+                original_first_thunk
+            } else {
+                first_thunk
+            };
+
+            let functions =
+                self.imported_functions(thunks, &mut remaining_thunks);
+
+            imports.push(Import { dll_name, functions });
+        }
+
+        imports
+    }
+
+    /// Returns the functions in the import lookup table at `thunks_rva`.
+    /// Reads `remaining_thunks` entries at most, and decrements it by the
+    /// number of entries read.
+    fn imported_functions(
+        &self,
+        thunks_rva: u32,
+        remaining_thunks: &mut usize,
+    ) -> Vec<ImportedFunc<'a>> {
+        let mut functions = Vec::new();
+
+        let Some(mut thunk) = self.rva_to_offset(thunks_rva) else {
+            return functions;
+        };
+
+        let (thunk_size, ordinal_flag) =
+            if self.is_64_bits { (8, 1 << 63) } else { (4, 1 << 31) };
+
+        while *remaining_thunks > 0 {
+            let value = if self.is_64_bits {
+                read_u64(self.data, thunk)
+            } else {
+                read_u32(self.data, thunk).map(|value| value as u64)
+            };
+
+            let Some(value) = value.filter(|value| *value != 0) else {
+                break;
+            };
+
+            thunk += thunk_size;
+            *remaining_thunks -= 1;
+
+            if value & ordinal_flag != 0 {
+                functions.push(ImportedFunc::Ordinal(value as u16));
+                continue;
+            }
+
+            // The thunk is the RVA of a hint, which is skipped, followed by
+            // the function's name.
+            let name = u32::try_from(value)
+                .ok()
+                .and_then(|rva| self.rva_to_offset(rva))
+                .and_then(|offset| read_name(self.data, offset + 2));
+
+            if let Some(name) = name {
+                functions.push(ImportedFunc::Name(name));
+            }
+        }
+
+        functions
+    }
+
+    fn parse_optional_header(&mut self, start: usize) {
+        let data = self.data;
+
+        let Some(magic) = read_u16(data, start) else {
+            return;
+        };
+
+        let data_directories_start = match magic {
+            IMAGE_NT_OPTIONAL_HDR32_MAGIC => start + 96,
+            IMAGE_NT_OPTIONAL_HDR64_MAGIC => {
+                self.is_64_bits = true;
+                start + 112
+            }
+            _ => return,
+        };
+
+        self.entry_point_rva = read_u32(data, start + 16);
+        self.subsystem = read_u16(data, start + 68);
+
+        // The number of data directories is the last field before the
+        // data directories themselves. Even if the file claims to have
+        // more, only 16 of them are defined.
+        let num_data_directories = read_u32(data, data_directories_start - 4)
+            .map_or(0, |n| n.min(16) as usize);
+
+        for i in 0..num_data_directories {
+            let offset = data_directories_start + i * 8;
+            match (read_u32(data, offset), read_u32(data, offset + 4)) {
+                (Some(rva), Some(size)) => {
+                    self.data_directories.push((rva, size))
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn parse_sections(&mut self, start: usize) {
+        let num_sections =
+            (self.number_of_sections as usize).min(MAX_SECTIONS);
+
+        for i in 0..num_sections {
+            let offset = start + i * SIZE_OF_SECTION_HEADER;
+
+            let Some(raw) =
+                self.data.get(offset..offset + SIZE_OF_SECTION_HEADER)
+            else {
+                break;
+            };
+
+            let name = &raw[0..8];
+            let name = match name.iter().position(|b| *b == 0) {
+                Some(end) => &name[..end],
+                None => name,
+            };
+
+            self.sections.push(Section {
+                name,
+                virtual_size: read_u32(raw, 8).unwrap(),
+                virtual_address: read_u32(raw, 12).unwrap(),
+                raw_data_size: read_u32(raw, 16).unwrap(),
+                raw_data_offset: read_u32(raw, 20).unwrap(),
+                characteristics: read_u32(raw, 36).unwrap(),
+            });
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a null-terminated name starting at `offset`. Returns [`None`] if
+/// the name is empty, or the null character is not found within the first
+/// [`MAX_NAME_LEN`] bytes.
+fn read_name(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let data = data.get(offset..)?;
+    let data = &data[..data.len().min(MAX_NAME_LEN + 1)];
+    let end = data.iter().position(|b| *b == 0)?;
+    if end == 0 {
+        None
+    } else {
+        Some(&data[..end])
+    }
+}
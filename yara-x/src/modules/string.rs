@@ -0,0 +1,67 @@
+/*! Implementation of the `string` module.
+
+`to_int` mimics `strtoll` as used by YARA, except that octal numbers are not
+recognized when the base is not specified. Instead of returning an error,
+strings that can't be entirely parsed as an integer, or that represent a
+number that doesn't fit in 64 bits, produce an undefined result.
+*/
+
+use crate::modules::prelude::*;
+use crate::modules::protos::string::*;
+
+#[module_main]
+fn main(_ctx: &ScanContext) -> String {
+    // Nothing to do, but we have to return our protobuf
+    String::new()
+}
+
+#[module_export(name = "to_int")]
+fn to_int(ctx: &ScanContext, s: RuntimeString) -> Option<i64> {
+    parse_int(s.as_bstr(ctx), None)
+}
+
+#[module_export(name = "to_int")]
+fn to_int_base(ctx: &ScanContext, s: RuntimeString, base: i64) -> Option<i64> {
+    let base = u32::try_from(base).ok().filter(|b| (2..=36).contains(b))?;
+    parse_int(s.as_bstr(ctx), Some(base))
+}
+
+/// Returns the length of the string in bytes, not in characters.
+#[module_export]
+fn length(ctx: &ScanContext, s: RuntimeString) -> i64 {
+    wasm_i64(s.as_bstr(ctx).len())
+}
+
+/// Parses an integer with an optional sign, in the given base or in base
+/// 10 if no base is specified. Leading whitespaces are ignored, and a `0x`
+/// prefix is accepted when the base is 16 or not specified, in the latter
+/// case the number is parsed in base 16.
+fn parse_int(s: &[u8], base: Option<u32>) -> Option<i64> {
+    let s = std::str::from_utf8(s).ok()?.trim_start();
+
+    let (sign, s) = match s.as_bytes().first() {
+        Some(b'-') => ("-", &s[1..]),
+        Some(b'+') => ("", &s[1..]),
+        _ => ("", s),
+    };
+
+    let (base, digits) = match base {
+        None | Some(16) => {
+            match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                Some(digits) => (16, digits),
+                None => (base.unwrap_or(10), s),
+            }
+        }
+        Some(base) => (base, s),
+    };
+
+    // `from_str_radix` accepts a sign, which at this point would be a
+    // second one.
+    if !digits.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    // The sign and the digits are parsed together, otherwise the most
+    // negative number would overflow.
+    i64::from_str_radix(format!("{}{}", sign, digits).as_str(), base).ok()
+}
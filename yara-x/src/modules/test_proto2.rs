@@ -7,6 +7,16 @@ pub(crate) fn add_i64(_ctx: &mut ScanContext, a: i64, b: i64) -> i64 {
     a + b
 }
 
+#[module_export(name = "add")]
+pub(crate) fn add_i64_i64_i64(
+    _ctx: &mut ScanContext,
+    a: i64,
+    b: i64,
+    c: i64,
+) -> i64 {
+    a + b + c
+}
+
 #[module_export(name = "add")]
 pub(crate) fn add_f64(_ctx: &mut ScanContext, a: f64, b: f64) -> f64 {
     a + b
@@ -105,11 +115,13 @@ fn main(_ctx: &ScanContext) -> TestProto2 {
     test.map_string_int64.insert("one".to_string(), 1);
     test.map_string_string.insert("foo".to_string(), "FOO".to_string());
     test.map_string_bool.insert("foo".to_string(), true);
+    test.map_string_float.insert("foo".to_string(), 1.5);
 
     test.map_int64_struct.insert(100, nested.clone());
     test.map_int64_int64.insert(100, 1000);
     test.map_int64_string.insert(100, "one thousand".to_string());
     test.map_int64_bool.insert(100, true);
+    test.map_int64_float.insert(100, 1.5);
 
     test.set_bool_proto(true);
 
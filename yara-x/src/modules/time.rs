@@ -0,0 +1,21 @@
+/*! Implementation of the `time` module.
+
+The current time is taken from the system clock, unless another clock was
+set with [`crate::Scanner::set_clock`] or
+[`crate::Scanner::set_current_time`].
+*/
+
+use crate::modules::prelude::*;
+use crate::modules::protos::time::*;
+
+#[module_main]
+fn main(_ctx: &ScanContext) -> Time {
+    // Nothing to do, but we have to return our protobuf
+    Time::new()
+}
+
+/// Returns the current time as the number of seconds since the Unix epoch.
+#[module_export]
+fn now(ctx: &ScanContext) -> i64 {
+    ctx.clock.now()
+}
@@ -0,0 +1,410 @@
+/*! Comparison of the verdicts produced by two versions of a set of rules.
+
+Before promoting a new version of a set of rules it is common to scan a
+corpus of known samples with both versions and look for differences. A
+[`Regression`] does exactly that: each sample is scanned with the baseline
+and the candidate rules, and the result is a [`SampleDiff`] that lists the
+rules that started matching, stopped matching or changed their number of
+matches.
+
+Samples are processed one at a time and their diffs are not retained, so
+large corpora can be processed with bounded memory. Only the [`Summary`]
+grows, and it is bounded by the number of rules. For processing a corpus
+in parallel use one [`Regression`] per thread and combine their summaries
+with [`Summary::merge`].
+
+Each sample is scanned twice, once per version of the rules, and modules
+are executed in both scans. Their output is not shared between the scans.
+
+Rules are identified by namespace and name, which is what allows comparing
+rules from two different compilations. The fingerprint of each rule (see
+[`crate::Rule::fingerprint`]) is included in the diffs, so it's possible to
+tell whether the rule itself was modified, or whether its verdict changed
+for other reasons, like a change in another rule it depends on.
+
+```
+# use yara_x::compile;
+# use yara_x::regression::Regression;
+let baseline = compile("rule test { strings: $a = \"foo\" condition: $a }").unwrap();
+let candidate = compile("rule test { strings: $a = \"bar\" condition: $a }").unwrap();
+
+let mut regression = Regression::new(&baseline, &candidate);
+
+let diffs: Vec<_> = regression
+    .compare_all([("sample1", b"foo"), ("sample2", b"baz")])
+    .filter(|diff| !diff.is_empty())
+    .collect();
+
+assert_eq!(diffs.len(), 1);
+assert_eq!(diffs[0].sample, "sample1");
+assert_eq!(regression.summary().lost_matches, 1);
+```
+*/
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::compiler::Rules;
+use crate::scanner::{ScanResults, Scanner};
+
+/// Scans samples with two versions of a set of rules and compares the
+/// results.
+///
+/// See the [module documentation](crate::regression) for details.
+pub struct Regression<'r> {
+    baseline: Side<'r>,
+    candidate: Side<'r>,
+    summary: Summary,
+}
+
+/// One of the versions of the rules being compared.
+struct Side<'r> {
+    scanner: Scanner<'r>,
+    /// Fingerprints of all the non-private rules, by namespace and name.
+    fingerprints: BTreeMap<(&'r str, &'r str), u64>,
+}
+
+impl<'r> Side<'r> {
+    fn new(rules: &'r Rules) -> Self {
+        let fingerprints = rules
+            .iter()
+            .filter(|rule| !rule.is_private())
+            .map(|rule| ((rule.namespace(), rule.name()), rule.fingerprint()))
+            .collect();
+
+        Self { scanner: Scanner::new(rules), fingerprints }
+    }
+
+    /// Scans `data` and returns the number of matches of each matching rule,
+    /// by namespace and name.
+    fn scan(&mut self, data: &[u8]) -> BTreeMap<(&'r str, &'r str), usize> {
+        let results = self.scanner.scan(data);
+        matching_rules(&results)
+    }
+}
+
+fn matching_rules<'r>(
+    results: &ScanResults<'_, 'r>,
+) -> BTreeMap<(&'r str, &'r str), usize> {
+    results
+        .iter()
+        .map(|rule| {
+            let num_matches =
+                rule.patterns().map(|pattern| pattern.matches().len()).sum();
+            ((rule.namespace(), rule.name()), num_matches)
+        })
+        .collect()
+}
+
+impl<'r> Regression<'r> {
+    /// Creates a new [`Regression`] that compares the `candidate` rules
+    /// against the `baseline` rules.
+    pub fn new(baseline: &'r Rules, candidate: &'r Rules) -> Self {
+        Self {
+            baseline: Side::new(baseline),
+            candidate: Side::new(candidate),
+            summary: Summary::default(),
+        }
+    }
+
+    /// Scans a sample with both versions of the rules and returns the
+    /// differences, updating the summary.
+    ///
+    /// `name` identifies the sample in the result, it can be a file path or
+    /// any other string.
+    pub fn compare(&mut self, name: &str, data: &[u8]) -> SampleDiff {
+        let baseline = self.baseline.scan(data);
+        let candidate = self.candidate.scan(data);
+
+        let mut changes = Vec::new();
+
+        // Both maps are sorted by namespace and name, they are merged in
+        // order so that the changes are sorted too.
+        let mut baseline = baseline.into_iter().peekable();
+        let mut candidate = candidate.into_iter().peekable();
+
+        loop {
+            let order = match (baseline.peek(), candidate.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((b, _)), Some((c, _))) => b.cmp(c),
+            };
+
+            let (key, baseline_matches, candidate_matches) = match order {
+                Ordering::Less => {
+                    let (key, matches) = baseline.next().unwrap();
+                    (key, Some(matches), None)
+                }
+                Ordering::Greater => {
+                    let (key, matches) = candidate.next().unwrap();
+                    (key, None, Some(matches))
+                }
+                Ordering::Equal => {
+                    let (key, b) = baseline.next().unwrap();
+                    let (_, c) = candidate.next().unwrap();
+                    (key, Some(b), Some(c))
+                }
+            };
+
+            let kind = match (baseline_matches, candidate_matches) {
+                (None, Some(_)) => ChangeKind::NewMatch,
+                (Some(_), None) => ChangeKind::LostMatch,
+                (Some(b), Some(c)) if b != c => ChangeKind::MatchCountChanged,
+                _ => continue,
+            };
+
+            changes.push(RuleChange {
+                namespace: key.0.to_string(),
+                rule: key.1.to_string(),
+                kind,
+                baseline_fingerprint: self
+                    .baseline
+                    .fingerprints
+                    .get(&key)
+                    .copied(),
+                candidate_fingerprint: self
+                    .candidate
+                    .fingerprints
+                    .get(&key)
+                    .copied(),
+                baseline_matches,
+                candidate_matches,
+            });
+        }
+
+        let diff = SampleDiff { sample: name.to_string(), changes };
+
+        self.summary.add(&diff);
+
+        diff
+    }
+
+    /// Returns an iterator that compares each sample in `samples` as it is
+    /// consumed.
+    ///
+    /// Each item in `samples` is a pair with the sample's name and its
+    /// data. The iterator yields one [`SampleDiff`] per sample, in the same
+    /// order, including the samples without differences.
+    pub fn compare_all<I, N, D>(
+        &mut self,
+        samples: I,
+    ) -> CompareAll<'_, 'r, I::IntoIter>
+    where
+        I: IntoIterator<Item = (N, D)>,
+        N: AsRef<str>,
+        D: AsRef<[u8]>,
+    {
+        CompareAll { regression: self, samples: samples.into_iter() }
+    }
+
+    /// Returns the summary of the samples compared so far.
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Consumes the [`Regression`] and returns the summary of the samples
+    /// compared.
+    pub fn into_summary(self) -> Summary {
+        self.summary
+    }
+}
+
+/// Iterator returned by [`Regression::compare_all`].
+pub struct CompareAll<'a, 'r, I> {
+    regression: &'a mut Regression<'r>,
+    samples: I,
+}
+
+impl<'a, 'r, I, N, D> Iterator for CompareAll<'a, 'r, I>
+where
+    I: Iterator<Item = (N, D)>,
+    N: AsRef<str>,
+    D: AsRef<[u8]>,
+{
+    type Item = SampleDiff;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (name, data) = self.samples.next()?;
+        Some(self.regression.compare(name.as_ref(), data.as_ref()))
+    }
+}
+
+/// Differences found in a single sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SampleDiff {
+    /// Name of the sample, as passed to [`Regression::compare`].
+    pub sample: String,
+    /// Rules with different verdicts, sorted by namespace and name.
+    pub changes: Vec<RuleChange>,
+}
+
+impl SampleDiff {
+    /// Returns true if both versions of the rules produced the same
+    /// verdicts for the sample.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Kinds of differences in the verdict of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChangeKind {
+    /// The rule matches with the candidate rules, but not with the baseline.
+    NewMatch,
+    /// The rule matches with the baseline rules, but not with the candidate.
+    LostMatch,
+    /// The rule matches with both, but the number of matches is different.
+    MatchCountChanged,
+}
+
+/// A rule with different verdicts in the baseline and candidate rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RuleChange {
+    /// Namespace the rule belongs to.
+    pub namespace: String,
+    /// Rule name.
+    pub rule: String,
+    /// Kind of difference.
+    pub kind: ChangeKind,
+    /// Fingerprint of the rule in the baseline, or [`None`] if the rule
+    /// doesn't exist there.
+    pub baseline_fingerprint: Option<u64>,
+    /// Fingerprint of the rule in the candidate, or [`None`] if the rule
+    /// doesn't exist there.
+    pub candidate_fingerprint: Option<u64>,
+    /// Total number of pattern matches with the baseline rules, or [`None`]
+    /// if the rule didn't match.
+    pub baseline_matches: Option<usize>,
+    /// Total number of pattern matches with the candidate rules, or
+    /// [`None`] if the rule didn't match.
+    pub candidate_matches: Option<usize>,
+}
+
+/// Summary of the differences found in all the samples compared by a
+/// [`Regression`].
+///
+/// The summary is intended for deciding whether the candidate rules are
+/// acceptable, for instance by failing a CI job when the number of lost
+/// matches exceeds some threshold.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Summary {
+    /// Number of samples compared.
+    pub samples: usize,
+    /// Number of samples with some difference.
+    pub diverging_samples: usize,
+    /// Number of [`ChangeKind::NewMatch`] differences in all samples.
+    pub new_matches: usize,
+    /// Number of [`ChangeKind::LostMatch`] differences in all samples.
+    pub lost_matches: usize,
+    /// Number of [`ChangeKind::MatchCountChanged`] differences in all
+    /// samples.
+    pub changed_match_counts: usize,
+    /// Differences by rule, sorted by namespace and name. Rules without
+    /// differences are not included.
+    pub rules: Vec<RuleSummary>,
+}
+
+/// Differences found for a single rule in all the samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RuleSummary {
+    /// Namespace the rule belongs to.
+    pub namespace: String,
+    /// Rule name.
+    pub rule: String,
+    /// Number of samples where the rule started matching.
+    pub new_matches: usize,
+    /// Number of samples where the rule stopped matching.
+    pub lost_matches: usize,
+    /// Number of samples where the rule's number of matches changed.
+    pub changed_match_counts: usize,
+}
+
+impl Summary {
+    /// Returns true if no differences were found.
+    pub fn is_clean(&self) -> bool {
+        self.diverging_samples == 0
+    }
+
+    /// Adds the differences in `other` to this summary. This is used for
+    /// combining the summaries of multiple [`Regression`] running in
+    /// parallel over different parts of a corpus.
+    pub fn merge(&mut self, other: &Summary) {
+        self.samples += other.samples;
+        self.diverging_samples += other.diverging_samples;
+        self.new_matches += other.new_matches;
+        self.lost_matches += other.lost_matches;
+        self.changed_match_counts += other.changed_match_counts;
+
+        for rule in &other.rules {
+            let summary = self.rule_summary(&rule.namespace, &rule.rule);
+            summary.new_matches += rule.new_matches;
+            summary.lost_matches += rule.lost_matches;
+            summary.changed_match_counts += rule.changed_match_counts;
+        }
+    }
+
+    fn add(&mut self, diff: &SampleDiff) {
+        self.samples += 1;
+
+        if diff.is_empty() {
+            return;
+        }
+
+        self.diverging_samples += 1;
+
+        for change in &diff.changes {
+            let (total, per_rule) = match change.kind {
+                ChangeKind::NewMatch => (&mut self.new_matches, 0),
+                ChangeKind::LostMatch => (&mut self.lost_matches, 1),
+                ChangeKind::MatchCountChanged => {
+                    (&mut self.changed_match_counts, 2)
+                }
+            };
+
+            *total += 1;
+
+            let summary = self.rule_summary(&change.namespace, &change.rule);
+
+            *[
+                &mut summary.new_matches,
+                &mut summary.lost_matches,
+                &mut summary.changed_match_counts,
+            ][per_rule] += 1;
+        }
+    }
+
+    /// Returns the summary for the given rule, creating it if it doesn't
+    /// exist. Rules are kept sorted by namespace and name.
+    fn rule_summary(
+        &mut self,
+        namespace: &str,
+        rule: &str,
+    ) -> &mut RuleSummary {
+        let index = match self.rules.binary_search_by(|r| {
+            (r.namespace.as_str(), r.rule.as_str()).cmp(&(namespace, rule))
+        }) {
+            Ok(index) => index,
+            Err(index) => {
+                self.rules.insert(
+                    index,
+                    RuleSummary {
+                        namespace: namespace.to_string(),
+                        rule: rule.to_string(),
+                        new_matches: 0,
+                        lost_matches: 0,
+                        changed_match_counts: 0,
+                    },
+                );
+                index
+            }
+        };
+        &mut self.rules[index]
+    }
+}
@@ -0,0 +1,37 @@
+/*! Source of the current time used by the `time` module.
+
+See [`crate::Scanner::set_clock`].
+*/
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the current time for `time.now()`.
+pub trait Clock {
+    /// Returns the current time as the number of seconds since the Unix
+    /// epoch.
+    fn now(&self) -> i64;
+}
+
+/// A [`Clock`] that returns the time of the system clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        // A system clock set before the Unix epoch is treated as if it
+        // was set to the epoch itself.
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64)
+    }
+}
+
+/// A [`Clock`] that always returns the same time, see
+/// [`crate::Scanner::set_current_time`].
+pub(crate) struct FixedClock(pub i64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.0
+    }
+}
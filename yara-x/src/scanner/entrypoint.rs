@@ -0,0 +1,144 @@
+/*! Computes the value of the legacy `entrypoint` keyword.
+
+The entry point is the file offset where the execution of a PE or ELF file
+starts. Only the headers that are strictly necessary for translating the
+entry point address into a file offset are parsed, any inconsistency in
+those headers makes the entry point undefined.
+*/
+
+/// Returns the file offset of the entry point if `data` is a PE or ELF file,
+/// or [`None`] if otherwise.
+pub(crate) fn entrypoint(data: &[u8]) -> Option<u64> {
+    if data.starts_with(b"MZ") {
+        pe_entrypoint(data)
+    } else if data.starts_with(b"\x7fELF") {
+        elf_entrypoint(data)
+    } else {
+        None
+    }
+}
+
+/// Reads `N` bytes at `offset`, returning [`None`] if they are out of
+/// bounds.
+fn read<const N: usize>(data: &[u8], offset: u64) -> Option<[u8; N]> {
+    let start = usize::try_from(offset).ok()?;
+    data.get(start..start.checked_add(N)?)?.try_into().ok()
+}
+
+fn pe_entrypoint(data: &[u8]) -> Option<u64> {
+    let u16_at = |offset| read(data, offset).map(u16::from_le_bytes);
+    let u32_at = |offset| read(data, offset).map(u32::from_le_bytes);
+
+    let pe_offset = u64::from(u32_at(0x3c)?);
+
+    if read(data, pe_offset)? != *b"PE\0\0" {
+        return None;
+    }
+
+    let num_sections = u64::from(u16_at(pe_offset + 6)?);
+    let optional_header_size = u64::from(u16_at(pe_offset + 20)?);
+    let optional_header = pe_offset + 24;
+    let entry_point = u64::from(u32_at(optional_header + 16)?);
+    let sections = optional_header + optional_header_size;
+
+    let mut in_headers = true;
+
+    // Find the section that contains the entry point.
+    for i in 0..num_sections {
+        let section = sections + i * 40;
+        let virtual_size = u64::from(u32_at(section + 8)?);
+        let virtual_address = u64::from(u32_at(section + 12)?);
+        let raw_size = u64::from(u32_at(section + 16)?);
+        let raw_offset = u64::from(u32_at(section + 20)?);
+
+        if entry_point >= virtual_address {
+            in_headers = false;
+        }
+
+        if entry_point >= virtual_address
+            && entry_point - virtual_address < virtual_size.max(raw_size)
+        {
+            return Some(raw_offset + entry_point - virtual_address);
+        }
+    }
+
+    // An entry point that precedes all sections is inside the headers,
+    // which are loaded at the start of the image.
+    in_headers.then_some(entry_point)
+}
+
+fn elf_entrypoint(data: &[u8]) -> Option<u64> {
+    let is_64_bits = match data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+
+    let is_big_endian = match data.get(5)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+
+    let u16_at = |offset| {
+        read(data, offset).map(if is_big_endian {
+            u16::from_be_bytes
+        } else {
+            u16::from_le_bytes
+        })
+    };
+
+    let u32_at = |offset| {
+        read(data, offset).map(if is_big_endian {
+            u32::from_be_bytes
+        } else {
+            u32::from_le_bytes
+        })
+    };
+
+    // Reads an address or offset, which are 32 or 64 bits wide depending
+    // on the ELF class.
+    let addr_at = |offset| {
+        if is_64_bits {
+            read(data, offset).map(if is_big_endian {
+                u64::from_be_bytes
+            } else {
+                u64::from_le_bytes
+            })
+        } else {
+            u32_at(offset).map(u64::from)
+        }
+    };
+
+    let (entry_point, phoff, phentsize, phnum) = if is_64_bits {
+        (addr_at(24)?, addr_at(32)?, u16_at(54)?, u16_at(56)?)
+    } else {
+        (addr_at(24)?, addr_at(28)?, u16_at(42)?, u16_at(44)?)
+    };
+
+    // Offsets of `p_offset`, `p_vaddr` and `p_filesz` within a program
+    // header.
+    let (p_offset, p_vaddr, p_filesz) =
+        if is_64_bits { (8, 16, 32) } else { (4, 8, 16) };
+
+    const PT_LOAD: u32 = 1;
+
+    // Find the loadable segment that contains the entry point.
+    for i in 0..u64::from(phnum) {
+        let header = phoff.checked_add(i * u64::from(phentsize))?;
+
+        if u32_at(header)? != PT_LOAD {
+            continue;
+        }
+
+        let offset = addr_at(header + p_offset)?;
+        let vaddr = addr_at(header + p_vaddr)?;
+        let filesz = addr_at(header + p_filesz)?;
+
+        if entry_point >= vaddr && entry_point - vaddr < filesz {
+            return offset.checked_add(entry_point - vaddr);
+        }
+    }
+
+    None
+}
@@ -0,0 +1,52 @@
+/*! Events sent to the callback of a scan.
+
+See [`crate::Scanner::scan_with_callback`].
+*/
+
+use protobuf::MessageDyn;
+
+use crate::scanner::Rule;
+
+/// An event that happened during a scan started with
+/// [`crate::Scanner::scan_with_callback`].
+///
+/// Events borrow from the scanner, so they are only valid while the
+/// callback is running.
+#[non_exhaustive]
+pub enum ScanEvent<'s, 'r> {
+    /// A module imported by the rules produced its output, before any rule
+    /// is evaluated. `output` is the protobuf message returned by the
+    /// module.
+    ModuleOutput { name: &'r str, output: &'s dyn MessageDyn },
+    /// A rule matched. This is sent only for the rules that are reported
+    /// in the results, so private rules are excluded, and rules in the
+    /// same namespace as some global rule are sent once all the global
+    /// rules in the namespace are known to match.
+    RuleMatched(Rule<'s, 'r>),
+    /// The condition of a rule was evaluated, regardless of whether it
+    /// matched or not. This is sent for every rule, including private and
+    /// global rules.
+    RuleEvaluated(Rule<'s, 'r>),
+    /// A global rule didn't match, which means that no other rule in the
+    /// same namespace matches. This is sent only for the first global rule
+    /// that fails in each namespace.
+    GlobalRuleFailed(Rule<'s, 'r>),
+    /// All the rules were evaluated. This is the last event of a scan that
+    /// was not aborted.
+    ScanFinished,
+}
+
+/// Value returned by the callback passed to
+/// [`crate::Scanner::scan_with_callback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallbackAction {
+    /// Continue scanning.
+    Continue,
+    /// Stop scanning. The rules that were not evaluated yet are considered
+    /// as not matching, and no more events are sent.
+    Abort,
+}
+
+/// Type of the callback passed to [`crate::Scanner::scan_with_callback`].
+pub(crate) type ScanCallback<'r> =
+    dyn for<'s> FnMut(ScanEvent<'s, 'r>) -> CallbackAction + Send + 'r;
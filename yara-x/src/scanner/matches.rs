@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+use std::ops::{Range, RangeInclusive};
+
+/// Represents a match of a pattern in the scanned data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Range within the scanned data where the pattern matched. This is
+    /// not affected by [`crate::Scanner::set_base_offset`], see
+    /// [`Match::address`].
+    pub range: Range<usize>,
+    /// XOR key used for decoding the matching data, only set for patterns
+    /// with the `xor` modifier. Notice that the key can be zero, in which
+    /// case the data matched the pattern as is.
+    pub xor_key: Option<u8>,
+    /// Variant of the base64 encoding found in the matching data, only set
+    /// for patterns with the `base64` or `base64wide` modifiers.
+    pub base64: Option<Base64Variant>,
+    /// Base offset set with [`crate::Scanner::set_base_offset`] when the
+    /// match was found.
+    pub(crate) base_offset: u64,
+}
+
+impl Match {
+    /// Returns the offset where the match starts plus the base offset set
+    /// with [`crate::Scanner::set_base_offset`]. In the results of
+    /// [`crate::Scanner::scan_process`] this is the virtual address where
+    /// the match starts. If the sum overflows, the result is [`u64::MAX`].
+    pub fn address(&self) -> u64 {
+        self.base_offset.saturating_add(self.range.start as u64)
+    }
+}
+
+/// One of the variants in which a pattern can be encoded as base64.
+///
+/// Depending on the offset of the pattern within the original data, its
+/// base64 encoding is one of three possible permutations. Each permutation
+/// can also appear in wide form, if the pattern has the `base64wide`
+/// modifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct Base64Variant {
+    /// Offset of the pattern within the original data, modulo 3. Can be
+    /// 0, 1 or 2.
+    pub offset: u8,
+    /// True if the base64 string is in wide form (UTF-16).
+    pub wide: bool,
+}
+
+/// Summary of the XOR keys and base64 variants found in the matches of a
+/// rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct MatchStats {
+    /// Distinct XOR keys found in the matches, sorted by key.
+    pub xor_keys: Vec<XorKeyStats>,
+    /// Distinct base64 variants found in the matches, sorted by offset,
+    /// with the non-wide variant first.
+    pub base64_variants: Vec<Base64VariantStats>,
+}
+
+/// Number of patterns and matches where some XOR key was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct XorKeyStats {
+    /// The XOR key.
+    pub key: u8,
+    /// Number of patterns with at least one match using this key.
+    pub patterns: usize,
+    /// Total number of matches using this key.
+    pub matches: usize,
+}
+
+/// Number of patterns and matches where some base64 variant was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct Base64VariantStats {
+    /// The base64 variant.
+    pub variant: Base64Variant,
+    /// Number of patterns with at least one match in this variant.
+    pub patterns: usize,
+    /// Total number of matches in this variant.
+    pub matches: usize,
+}
+
+impl MatchStats {
+    /// Computes the statistics for the matches of a set of patterns. Each
+    /// item yielded by `patterns` contains the matches of one pattern.
+    pub(crate) fn new<'a, I>(patterns: I) -> Self
+    where
+        I: Iterator<Item = &'a [Match]>,
+    {
+        // Maps each key or variant to its (patterns, matches) counters.
+        let mut xor_keys = BTreeMap::<u8, (usize, usize)>::new();
+        let mut base64_variants =
+            BTreeMap::<Base64Variant, (usize, usize)>::new();
+
+        for matches in patterns {
+            let mut pattern_xor_keys = BTreeMap::new();
+            let mut pattern_base64_variants = BTreeMap::new();
+
+            for m in matches {
+                if let Some(key) = m.xor_key {
+                    *pattern_xor_keys.entry(key).or_insert(0) += 1;
+                }
+                if let Some(variant) = m.base64 {
+                    *pattern_base64_variants.entry(variant).or_insert(0) += 1;
+                }
+            }
+
+            for (key, n) in pattern_xor_keys {
+                let counters = xor_keys.entry(key).or_default();
+                counters.0 += 1;
+                counters.1 += n;
+            }
+
+            for (variant, n) in pattern_base64_variants {
+                let counters = base64_variants.entry(variant).or_default();
+                counters.0 += 1;
+                counters.1 += n;
+            }
+        }
+
+        Self {
+            xor_keys: xor_keys
+                .into_iter()
+                .map(|(key, (patterns, matches))| XorKeyStats {
+                    key,
+                    patterns,
+                    matches,
+                })
+                .collect(),
+            base64_variants: base64_variants
+                .into_iter()
+                .map(|(variant, (patterns, matches))| Base64VariantStats {
+                    variant,
+                    patterns,
+                    matches,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A list of matches for a single pattern.
+///
+/// Matches are kept sorted by their starting offset, and the list never
+/// contains two matches with the same range. This is important because
+/// the same pattern can have multiple variants (e.g: the `ascii` and `wide`
+/// variants for `$a = "foo" ascii wide`) and different variants can produce
+/// the same match.
+#[derive(Debug, Default)]
+pub(crate) struct MatchList {
+    matches: Vec<Match>,
+    /// True if some match was discarded because the list was full.
+    truncated: bool,
+}
+
+impl MatchList {
+    /// Adds a match to the list. If the list already contains a match with
+    /// the same range the new one is ignored.
+    ///
+    /// The list holds at most `max_matches` matches, the ones with the
+    /// lowest offsets. When it is full, adding a match discards the one
+    /// with the highest offset, which may be the new match itself.
+    pub fn add(&mut self, m: Match, max_matches: usize) {
+        let key = |m: &Match| (m.range.start, m.range.end);
+        if let Err(index) = self.matches.binary_search_by_key(&key(&m), key) {
+            if index >= max_matches {
+                self.truncated = true;
+                return;
+            }
+            self.matches.insert(index, m);
+            if self.matches.len() > max_matches {
+                self.matches.pop();
+                self.truncated = true;
+            }
+        }
+    }
+
+    /// Returns true if some matches were discarded because the list
+    /// reached its maximum number of matches.
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the number of matches the list can hold without allocating
+    /// more memory.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.matches.capacity()
+    }
+
+    /// Removes all the matches, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.matches.clear();
+        self.truncated = false;
+    }
+
+    /// Shrinks the capacity of the list as much as possible, but not below
+    /// `min_capacity`.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.matches.shrink_to(min_capacity);
+    }
+
+    /// Returns the matches as a slice.
+    #[inline]
+    pub fn as_slice(&self) -> &[Match] {
+        self.matches.as_slice()
+    }
+
+    /// Returns the matches that start at some offset within `range`.
+    pub fn starting_in(&self, range: RangeInclusive<usize>) -> &[Match] {
+        let first =
+            self.matches.partition_point(|m| m.range.start < *range.start());
+        let last =
+            self.matches.partition_point(|m| m.range.start <= *range.end());
+        // With a reversed range `last` can be lower than `first`.
+        &self.matches[first..last.max(first)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Base64Variant, Match, MatchList, MatchStats};
+
+    #[test]
+    fn match_list() {
+        let mut ml = MatchList::default();
+
+        ml.add(
+            Match {
+                range: 2..10,
+                xor_key: None,
+                base64: None,
+                base_offset: 0,
+            },
+            3,
+        );
+        ml.add(
+            Match {
+                range: 1..10,
+                xor_key: None,
+                base64: None,
+                base_offset: 0,
+            },
+            3,
+        );
+        ml.add(
+            Match {
+                range: 2..10,
+                xor_key: None,
+                base64: None,
+                base_offset: 0,
+            },
+            3,
+        );
+        ml.add(
+            Match { range: 2..4, xor_key: None, base64: None, base_offset: 0 },
+            3,
+        );
+
+        assert_eq!(
+            ml.as_slice(),
+            &[
+                Match {
+                    range: 1..10,
+                    xor_key: None,
+                    base64: None,
+                    base_offset: 0
+                },
+                Match {
+                    range: 2..4,
+                    xor_key: None,
+                    base64: None,
+                    base_offset: 0
+                },
+                Match {
+                    range: 2..10,
+                    xor_key: None,
+                    base64: None,
+                    base_offset: 0
+                },
+            ]
+        );
+
+        assert_eq!(ml.starting_in(2..=2).len(), 2);
+        assert_eq!(ml.starting_in(0..=1).len(), 1);
+        assert_eq!(ml.starting_in(3..=100).len(), 0);
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 2..=1;
+        assert_eq!(ml.starting_in(reversed).len(), 0);
+
+        // The list is full, but adding a duplicate doesn't discard anything.
+        ml.add(
+            Match { range: 2..4, xor_key: None, base64: None, base_offset: 0 },
+            3,
+        );
+        assert!(!ml.is_truncated());
+
+        // A match with a higher offset is discarded.
+        ml.add(
+            Match { range: 5..6, xor_key: None, base64: None, base_offset: 0 },
+            3,
+        );
+        assert!(ml.is_truncated());
+        assert_eq!(ml.as_slice().len(), 3);
+
+        // A match with a lower offset replaces the one with the highest.
+        ml.add(
+            Match { range: 0..1, xor_key: None, base64: None, base_offset: 0 },
+            3,
+        );
+        assert_eq!(
+            ml.as_slice(),
+            &[
+                Match {
+                    range: 0..1,
+                    xor_key: None,
+                    base64: None,
+                    base_offset: 0
+                },
+                Match {
+                    range: 1..10,
+                    xor_key: None,
+                    base64: None,
+                    base_offset: 0
+                },
+                Match {
+                    range: 2..4,
+                    xor_key: None,
+                    base64: None,
+                    base_offset: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn match_stats() {
+        let xor = |start, key| Match {
+            range: start..start + 3,
+            xor_key: Some(key),
+            base64: None,
+            base_offset: 0,
+        };
+        let base64 = |start, offset, wide| Match {
+            range: start..start + 4,
+            xor_key: None,
+            base64: Some(Base64Variant { offset, wide }),
+            base_offset: 0,
+        };
+
+        let p1 = [xor(0, 0x10), xor(10, 0x10), xor(20, 0x20)];
+        let p2 = [xor(5, 0x10)];
+        let p3 =
+            [base64(0, 2, false), base64(8, 1, true), base64(16, 1, false)];
+        let p4 = [base64(4, 1, false)];
+
+        let stats = MatchStats::new(
+            [&p1[..], &p2[..], &p3[..], &p4[..], &[]].into_iter(),
+        );
+
+        assert_eq!(
+            stats
+                .xor_keys
+                .iter()
+                .map(|s| (s.key, s.patterns, s.matches))
+                .collect::<Vec<_>>(),
+            vec![(0x10, 2, 3), (0x20, 1, 1)]
+        );
+
+        assert_eq!(
+            stats
+                .base64_variants
+                .iter()
+                .map(|s| (
+                    s.variant.offset,
+                    s.variant.wide,
+                    s.patterns,
+                    s.matches
+                ))
+                .collect::<Vec<_>>(),
+            vec![(1, false, 2, 2), (1, true, 1, 1), (2, false, 1, 1)]
+        );
+
+        assert_eq!(MatchStats::new(std::iter::empty()), MatchStats::default());
+    }
+}
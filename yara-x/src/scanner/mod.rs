@@ -4,36 +4,89 @@ The scanner takes the rules produces by the compiler and scans data with them.
 */
 
 use base64::Engine;
-use std::ops::Deref;
-use std::path::Path;
+use std::cmp::Reverse;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read};
+use std::iter::Enumerate;
+use std::mem;
+use std::ops::{Deref, Range, RangeInclusive};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::ptr::{null, NonNull};
-use std::rc::Rc;
 use std::slice::Iter;
+use std::sync::Arc;
+use std::time::Instant;
 
 use bitvec::prelude::*;
-use bstr::ByteSlice;
+use bstr::{BStr, ByteSlice};
 use fmmap::{MmapFile, MmapFileExt};
+use protobuf::reflect::MessageDescriptor;
+use protobuf::MessageDyn;
+use rustc_hash::FxHashMap;
+use thiserror::Error;
 use wasmtime::{
     AsContext, AsContextMut, Global, GlobalType, MemoryType, Mutability,
     Store, TypedFunc, Val, ValType,
 };
 
+use yara_x_parser::escape;
 use yara_x_parser::types::{Struct, TypeValue};
 
-use crate::compiler::{Rule, RuleId, Rules};
+use crate::compiler;
+use crate::compiler::{IdentId, RuleId, RuleInfo, Rules};
+use crate::scanner::events::ScanCallback;
+use crate::scanner::matches::MatchList;
+use crate::scanner::profiling::Profiler;
 use crate::string_pool::BStringPool;
-use crate::wasm::MATCHING_RULES_BITMAP_BASE;
-use crate::{modules, wasm, AtomInfo, LiteralId, PatternId, SubPattern};
+use crate::variables::Variable;
+use crate::wasm::MemoryLayout;
+use crate::{
+    modules, wasm, AtomInfo, LiteralId, PatternId, SubPattern, SubPatternFlags,
+};
+
+#[cfg(feature = "time-module")]
+pub use crate::scanner::clock::{Clock, SystemClock};
+pub use crate::scanner::events::{CallbackAction, ScanEvent};
+pub use crate::scanner::matches::{
+    Base64Variant, Base64VariantStats, Match, MatchStats, XorKeyStats,
+};
+pub use crate::scanner::output::*;
+pub use crate::scanner::pattern::{CompiledPattern, FindIter};
+pub use crate::scanner::profiling::RuleProfile;
+pub use crate::scanner::stream::StreamScanner;
+
+#[cfg(feature = "time-module")]
+mod clock;
+pub(crate) mod entrypoint;
+mod events;
+mod matches;
+mod output;
+mod pattern;
+#[cfg(target_os = "linux")]
+mod process;
+mod profiling;
+mod stream;
 
 #[cfg(test)]
 mod tests;
 
+/// Default value for [`Scanner::set_max_retained_capacity`].
+pub const DEFAULT_MAX_RETAINED_CAPACITY: usize = 1024 * 1024;
+
+/// Default value for [`Scanner::set_max_matches_per_pattern`].
+pub const DEFAULT_MAX_MATCHES_PER_PATTERN: usize = 1_000_000;
+
+/// Number of bytes before and after a match that are needed for deciding
+/// if it is a `fullword` match, see [`ScanContext::is_fullword`].
+const FULLWORD_CONTEXT: usize = 2;
+
 /// Scans data with already compiled YARA rules.
 pub struct Scanner<'r> {
     wasm_store: Pin<Box<Store<ScanContext<'r>>>>,
     wasm_main_fn: TypedFunc<(), ()>,
     filesize: Global,
+    profiling_enabled: Global,
 }
 
 impl<'r> Scanner<'r> {
@@ -55,13 +108,39 @@ impl<'r> Scanner<'r> {
                 compiled_rules: rules,
                 string_pool: BStringPool::new(),
                 current_struct: None,
-                root_struct: Struct::new(),
+                root_struct: rules.globals_struct().clone(),
                 scanned_data: null(),
                 scanned_data_len: 0,
+                stream_offset: None,
                 rules_matching: Vec::new(),
+                private_rules_matching: Vec::new(),
                 main_memory: None,
                 vars_stack: Vec::new(),
                 patterns_found: false,
+                pattern_matches: FxHashMap::default(),
+                free_match_lists: Vec::new(),
+                profiler: None,
+                selected_rules: None,
+                max_retained_capacity: DEFAULT_MAX_RETAINED_CAPACITY,
+                max_matches_per_pattern: DEFAULT_MAX_MATCHES_PER_PATTERN,
+                base_offset: 0,
+                fast_mode: false,
+                callback: None,
+                scan_aborted: false,
+                pending_rule_matches: Vec::new(),
+                module_handlers: FxHashMap::default(),
+                module_outputs: FxHashMap::default(),
+                module_errors: Vec::new(),
+                #[cfg(feature = "console-module")]
+                console_log: None,
+                #[cfg(feature = "hash-module")]
+                hash_cache: Default::default(),
+                #[cfg(feature = "pe-module")]
+                imphash_cache: None,
+                #[cfg(feature = "time-module")]
+                clock: Box::new(SystemClock),
+                #[cfg(any(test, feature = "memory-checks"))]
+                memory_checks: Default::default(),
             },
         ));
 
@@ -80,28 +159,38 @@ impl<'r> Scanner<'r> {
         )
         .unwrap();
 
-        let num_rules = rules.rules().len() as u32;
-        let num_patterns = rules.num_patterns() as u32;
-
-        // Compute the base offset for the bitmap that contains matching
-        // information for patterns. This bitmap has 1 bit per pattern,
-        // the N-th bit is set if pattern with PatternId = N matched. The
-        // bitmap starts right after the bitmap that contains matching
-        // information for rules.
-        let matching_patterns_bitmap_base =
-            wasm::MATCHING_RULES_BITMAP_BASE as u32 + num_rules / 8 + 1;
+        // Global variable that tells the WASM code whether it must notify
+        // the scanner after evaluating each rule, see `set_profiling`.
+        let profiling_enabled = Global::new(
+            wasm_store.as_context_mut(),
+            GlobalType::new(ValType::I32, Mutability::Var),
+            Val::I32(0),
+        )
+        .unwrap();
 
-        // Compute the required memory size in 64KB pages.
-        let mem_size =
-            matching_patterns_bitmap_base + num_patterns / 8 % 65536 + 1;
+        let layout = wasm_store.data().memory_layout();
 
+        // The WASM code finds the bitmap that contains matching information
+        // for patterns by reading this global variable.
         let matching_patterns_bitmap_base = Global::new(
             wasm_store.as_context_mut(),
             GlobalType::new(ValType::I32, Mutability::Const),
-            Val::I32(matching_patterns_bitmap_base as i32),
+            Val::I32(layout.matching_patterns_bitmap().start as i32),
+        )
+        .unwrap();
+
+        // The WASM code finds the bitmap that tells which rules must be
+        // evaluated by reading this global variable.
+        let selected_rules_bitmap_base = Global::new(
+            wasm_store.as_context_mut(),
+            GlobalType::new(ValType::I32, Mutability::Const),
+            Val::I32(layout.selected_rules_bitmap().start as i32),
         )
         .unwrap();
 
+        // Compute the required memory size in 64KB pages.
+        let mem_size = layout.size() as u32 / 65536 + 1;
+
         // Create module's main memory.
         let main_memory = wasmtime::Memory::new(
             wasm_store.as_context_mut(),
@@ -109,18 +198,38 @@ impl<'r> Scanner<'r> {
         )
         .unwrap();
 
+        #[cfg(any(test, feature = "memory-checks"))]
+        wasm::memory_checks::write_canaries(
+            main_memory.data_mut(wasm_store.as_context_mut()),
+            &layout,
+        );
+
+        // All rules are selected unless a scan is started with
+        // `scan_with_filter`.
+        main_memory.data_mut(wasm_store.as_context_mut())
+            [layout.selected_rules_bitmap()]
+        .fill(0xff);
+
         // Instantiate the module. This takes the wasm code provided by the
         // `compiled_wasm_mod` function and links its imported functions with
         // the implementations that YARA provides (see wasm.rs).
         let wasm_instance = wasm::new_linker()
             .define("yara_x", "filesize", filesize)
             .unwrap()
+            .define("yara_x", "profiling_enabled", profiling_enabled)
+            .unwrap()
             .define(
                 "yara_x",
                 "matching_patterns_bitmap_base",
                 matching_patterns_bitmap_base,
             )
             .unwrap()
+            .define(
+                "yara_x",
+                "selected_rules_bitmap_base",
+                selected_rules_bitmap_base,
+            )
+            .unwrap()
             .define("yara_x", "main_memory", main_memory)
             .unwrap()
             .instantiate(
@@ -136,75 +245,777 @@ impl<'r> Scanner<'r> {
 
         wasm_store.data_mut().main_memory = Some(main_memory);
 
-        Self { wasm_store, wasm_main_fn, filesize }
+        Self { wasm_store, wasm_main_fn, filesize, profiling_enabled }
+    }
+
+    /// Sets the maximum number of bytes the scanner keeps allocated between
+    /// scans.
+    ///
+    /// The scanner reuses the memory allocated for storing information
+    /// about a scan, like the matching rules and patterns, in the next scan.
+    /// When a scan requires a lot of memory (e.g: because it found millions
+    /// of matches), keeping it allocated forever would waste memory in
+    /// long-running processes that scan data of very different sizes. If
+    /// the memory retained from the previous scan exceeds this limit, the
+    /// part that exceeds it is released before the next scan starts, and
+    /// the rest is kept. The default value is
+    /// [`DEFAULT_MAX_RETAINED_CAPACITY`]. See [`Scanner::stats`] for
+    /// monitoring the memory retained by the scanner.
+    pub fn set_max_retained_capacity(&mut self, bytes: usize) -> &mut Self {
+        self.wasm_store.data_mut().max_retained_capacity = bytes;
+        self
+    }
+
+    /// Sets the maximum number of matches stored for each pattern.
+    ///
+    /// Patterns like `$a = { 00 00 }` can match millions of times in large
+    /// files, and storing all those matches would exhaust the memory. Once
+    /// a pattern reaches this limit, only the matches with the lowest
+    /// offsets are kept. Conditions see only the stored matches: `#a` is
+    /// at most `n`, and `@a[i]` is undefined for `i` greater than `n`. Use
+    /// [`ScanResults::matches_truncated`] for knowing if some pattern
+    /// reached the limit. The default value is
+    /// [`DEFAULT_MAX_MATCHES_PER_PATTERN`].
+    ///
+    /// ```
+    /// # use yara_x::{compile, Scanner};
+    /// let rules = compile(r#"
+    ///     rule test {
+    ///         strings: $a = "a"
+    ///         condition: #a == 2 and not defined @a[3]
+    ///     }
+    /// "#).unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// scanner.set_max_matches_per_pattern(2);
+    ///
+    /// let results = scanner.scan(b"aaaa");
+    ///
+    /// assert_eq!(results.num_matching_rules(), 1);
+    /// assert!(results.matches_truncated());
+    /// ```
+    pub fn set_max_matches_per_pattern(&mut self, n: usize) -> &mut Self {
+        self.wasm_store.data_mut().max_matches_per_pattern = n;
+        self
+    }
+
+    /// Sets the offset that is added to the offsets of the matches in the
+    /// results of a scan. The default value is 0.
+    ///
+    /// This is useful when the scanned data is a fragment of some larger
+    /// address space, like a memory region dumped from a process, where
+    /// matches must be reported at the address where the data originally
+    /// was. The base offset affects [`Match::address`] and
+    /// [`MatchOutput::offset`] only. Rule conditions and [`Match::range`]
+    /// still refer to offsets within the scanned data, so `$a at 0` is a
+    /// match at the start of the data. If the sum of a match offset and the
+    /// base offset overflows, the result is [`u64::MAX`].
+    ///
+    /// ```
+    /// # use yara_x::{compile, Scanner};
+    /// let rules = compile(r#"
+    ///     rule test { strings: $a = "foo" condition: $a at 2 }
+    /// "#).unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// scanner.set_base_offset(0x1000);
+    ///
+    /// let output = scanner.scan(b"xxfoo").to_owned_output();
+    /// let m = &output.matching_rules[0].patterns[0].matches[0];
+    ///
+    /// assert_eq!(m.offset, 0x1002);
+    /// ```
+    pub fn set_base_offset(&mut self, base_offset: u64) -> &mut Self {
+        self.wasm_store.data_mut().base_offset = base_offset;
+        self
+    }
+
+    /// Enables or disables the fast mode. It is disabled by default.
+    ///
+    /// In fast mode the scanner stops looking for a pattern after its first
+    /// match, unless some condition needs the rest of the matches, as in
+    /// `#a`, `@a[i]`, `!a[i]`, `$a at 100` or `$a in (0..100)`. Conditions
+    /// like `$a` or `any of them` only need to know whether the pattern
+    /// matched, so the rules that match are the same as in normal mode,
+    /// but the scan is faster when patterns match many times. However,
+    /// [`Pattern::matches`] returns only the first match found for those
+    /// patterns.
+    ///
+    /// ```
+    /// # use yara_x::{compile, Scanner};
+    /// let rules = compile(r#"
+    ///     rule test {
+    ///         strings: $a = "a"
+    ///         condition: $a
+    ///     }
+    /// "#).unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// scanner.set_fast_mode(true);
+    ///
+    /// let results = scanner.scan(b"aaaa");
+    /// let rule = results.iter().next().unwrap();
+    ///
+    /// assert_eq!(rule.patterns().next().unwrap().matches().len(), 1);
+    /// ```
+    pub fn set_fast_mode(&mut self, enabled: bool) -> &mut Self {
+        self.wasm_store.data_mut().fast_mode = enabled;
+        self
+    }
+
+    /// Sets a callback that receives the messages logged by the `console`
+    /// module.
+    ///
+    /// Functions like `console.log` don't print anything by themselves,
+    /// they pass their messages to this callback, which decides where the
+    /// messages go. If no callback is set, the messages are discarded.
+    /// Strings in the messages are escaped with [`escape::escape`], so
+    /// they contain only printable ASCII characters.
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, Scanner};
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .add_source(r#"
+    ///         import "console"
+    ///         rule test { condition: console.log("size: ", filesize) }"#)
+    ///     .unwrap();
+    /// let rules = compiler.build().unwrap();
+    ///
+    /// let mut messages = Vec::new();
+    /// let mut scanner = Scanner::new(&rules);
+    ///
+    /// scanner.console_log(|message| messages.push(message));
+    /// scanner.scan(b"foo");
+    ///
+    /// drop(scanner);
+    /// assert_eq!(messages, vec!["size: 3"]);
+    /// ```
+    #[cfg(feature = "console-module")]
+    pub fn console_log<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(String) + Send + 'r,
+    {
+        self.wasm_store.data_mut().console_log = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets the time returned by `time.now()`, as the number of seconds
+    /// since the Unix epoch.
+    ///
+    /// By default `time.now()` uses the system clock. Fixing the time
+    /// makes the results of rules that depend on it reproducible, which
+    /// is useful for testing them.
+    #[cfg(feature = "time-module")]
+    pub fn set_current_time(&mut self, timestamp: i64) -> &mut Self {
+        self.set_clock(clock::FixedClock(timestamp))
+    }
+
+    /// Sets the [`Clock`] that provides the time returned by `time.now()`.
+    ///
+    /// By default this is [`SystemClock`]. [`Scanner::set_current_time`]
+    /// covers the common case of a fixed time, other clocks allow for
+    /// instance advancing the time between scans.
+    #[cfg(feature = "time-module")]
+    pub fn set_clock<C>(&mut self, clock: C) -> &mut Self
+    where
+        C: Clock + Send + 'r,
+    {
+        self.wasm_store.data_mut().clock = Box::new(clock);
+        self
+    }
+
+    /// Sets the value of a global variable defined with
+    /// [`crate::Compiler::define_global`].
+    ///
+    /// The value is used in all subsequent scans, until it's set again.
+    /// Variables that are not set have the default value given to
+    /// [`crate::Compiler::define_global`]. The value must have the same
+    /// type as the default value, otherwise [`ScanError::InvalidGlobalType`]
+    /// is returned. See [`crate::Compiler::define_global`] for an example.
+    pub fn set_global<T: Into<Variable>>(
+        &mut self,
+        ident: &str,
+        value: T,
+    ) -> Result<&mut Self, ScanError> {
+        // Modules are also fields of the root structure, but they are
+        // the only fields of struct type.
+        let field = self
+            .wasm_store
+            .data_mut()
+            .root_struct
+            .field_by_name_mut(ident)
+            .filter(|field| !matches!(field.type_value, TypeValue::Struct(_)))
+            .ok_or_else(|| ScanError::UnknownGlobal(ident.to_string()))?;
+
+        let value: TypeValue = value.into().into();
+
+        if value.ty() != field.type_value.ty() {
+            return Err(ScanError::InvalidGlobalType {
+                variable: ident.to_string(),
+                expected: field.type_value.ty().to_string(),
+                actual: value.ty().to_string(),
+            });
+        }
+
+        field.type_value = value;
+
+        Ok(self)
+    }
+
+    /// Sets the function that provides the data for a module registered
+    /// with [`crate::Compiler::register_module`].
+    ///
+    /// The handler receives the scanned data and returns the protobuf
+    /// message that populates the module, which must be of the type given
+    /// to [`crate::Compiler::register_module`]. The handler is called once
+    /// per scan if the rules import the module, and never otherwise. If no
+    /// handler is set for an imported module, its fields are undefined, or
+    /// have their default values in proto3 messages. If the handler returns
+    /// a message of some other type, the module is handled as if it had no
+    /// handler in that scan, and the error is reported by
+    /// [`ScanResults::module_errors`]. See
+    /// [`crate::Compiler::register_module`] for an example.
+    pub fn set_module_handler<F>(
+        &mut self,
+        name: &str,
+        handler: F,
+    ) -> &mut Self
+    where
+        F: Fn(&[u8]) -> Box<dyn MessageDyn> + Send + 'r,
+    {
+        self.wasm_store
+            .data_mut()
+            .module_handlers
+            .insert(name.to_string(), Box::new(handler));
+        self
+    }
+
+    /// Sets the data of a module for the next scan, instead of the data
+    /// that the module would extract from the scanned data.
+    ///
+    /// This is useful when the information that a module extracts is
+    /// already available, for instance because the file was parsed
+    /// elsewhere, and for testing rules with data that would be hard to
+    /// obtain from a real file. `output` must be a message of the type that
+    /// describes the module's structure, or [`ScanError::InvalidModuleOutput`]
+    /// is returned. The data applies only to the next scan, after that the
+    /// module extracts the data by itself again. Modules without data set
+    /// with this function are not affected.
+    ///
+    /// ```
+    /// # use yara_x::{Compiler, Scanner};
+    /// # use yara_x::protobuf::reflect::ReflectValueBox;
+    /// # use yara_x::protobuf::well_known_types::timestamp::Timestamp;
+    /// # use yara_x::protobuf::MessageFull;
+    /// let mut compiler = Compiler::new();
+    /// compiler
+    ///     .register_module("clock", Timestamp::descriptor())
+    ///     .unwrap()
+    ///     .add_source(r#"
+    ///         import "clock"
+    ///         rule test { condition: clock.seconds == 1000 }"#)
+    ///     .unwrap();
+    /// let rules = compiler.build().unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// let mut timestamp = Timestamp::new();
+    /// timestamp.seconds = 1000;
+    ///
+    /// scanner.set_module_output("clock", Box::new(timestamp)).unwrap();
+    /// assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+    /// assert_eq!(scanner.scan(b"").num_matching_rules(), 0);
+    /// ```
+    pub fn set_module_output(
+        &mut self,
+        name: &str,
+        output: Box<dyn MessageDyn>,
+    ) -> Result<&mut Self, ScanError> {
+        let descriptor = self.module_descriptor(name)?;
+
+        if output.descriptor_dyn() != descriptor {
+            return Err(ScanError::InvalidModuleOutput {
+                module: name.to_string(),
+                expected: descriptor.full_name().to_string(),
+                actual: output.descriptor_dyn().full_name().to_string(),
+            });
+        }
+
+        self.wasm_store
+            .data_mut()
+            .module_outputs
+            .insert(name.to_string(), output);
+
+        Ok(self)
+    }
+
+    /// Like [`Scanner::set_module_output`], but receives the module's data
+    /// as a serialized protobuf message, which is decoded as a message of
+    /// the type that describes the module's structure.
+    pub fn set_module_output_raw(
+        &mut self,
+        name: &str,
+        data: &[u8],
+    ) -> Result<&mut Self, ScanError> {
+        let descriptor = self.module_descriptor(name)?;
+
+        let output = descriptor.parse_from_bytes(data).map_err(|err| {
+            ScanError::ProtoError { module: name.to_string(), source: err }
+        })?;
+
+        self.set_module_output(name, output)
+    }
+
+    /// Enables or disables profiling.
+    ///
+    /// When profiling is enabled, the scanner measures the time spent on
+    /// each rule, both evaluating its condition and verifying the matches
+    /// found for its patterns. The rules where most of the time was spent
+    /// are returned by [`ScanResults::profiling`]. Profiling is disabled by
+    /// default, and it has no overhead while disabled, as the time is not
+    /// measured at all.
+    pub fn set_profiling(&mut self, enabled: bool) -> &mut Self {
+        self.profiling_enabled
+            .set(self.wasm_store.as_context_mut(), Val::I32(enabled.into()))
+            .unwrap();
+
+        let ctx = self.wasm_store.data_mut();
+
+        ctx.profiler = enabled.then(|| {
+            Profiler::new(
+                ctx.compiled_rules.rules().len(),
+                ctx.compiled_rules.num_patterns(),
+            )
+        });
+
+        self
+    }
+
+    /// Returns statistics about the scanner.
+    pub fn stats(&self) -> ScannerStats {
+        ScannerStats {
+            retained_capacity: self.wasm_store.data().retained_capacity(),
+        }
     }
 
     /// Scans a file.
+    ///
+    /// Regular files are memory-mapped, so they are not copied into memory
+    /// regardless of their size. Other kinds of files, like pipes, are read
+    /// into memory instead. Errors opening, mapping or reading the file
+    /// produce a [`ScanError::OpenError`].
     pub fn scan_file<'s, P>(
         &'s mut self,
         path: P,
-    ) -> std::io::Result<ScanResults<'s, 'r>>
+    ) -> Result<ScanResults<'s, 'r>, ScanError>
     where
         P: AsRef<Path>,
     {
-        let file = MmapFile::open(path).unwrap();
-        Ok(self.scan(file.as_slice()))
+        let path = path.as_ref();
+        let open_error = |err| ScanError::OpenError {
+            path: path.to_path_buf(),
+            source: err,
+        };
+
+        let mut file = File::open(path).map_err(open_error)?;
+        let metadata = file.metadata().map_err(open_error)?;
+
+        // Files that report a size of zero are read too, as some special
+        // files (e.g: the ones in `/proc`) have content despite their size,
+        // and reading an actually empty file is cheap anyways.
+        //
+        // Regular files that can't be mapped are not read instead, as they
+        // can be arbitrarily large.
+        let mapped = if metadata.is_file() && metadata.len() > 0 {
+            Some(
+                MmapFile::open(path)
+                    .map_err(|err| open_error(io::Error::other(err)))?,
+            )
+        } else {
+            None
+        };
+
+        let buffer;
+        let data = match &mapped {
+            Some(mapped) => mapped.as_slice(),
+            None => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).map_err(open_error)?;
+                buffer = data;
+                buffer.as_slice()
+            }
+        };
+
+        Ok(self.scan(data))
+    }
+
+    /// Scans the memory of a running process.
+    ///
+    /// Each readable memory region of the process is scanned separately,
+    /// as if it were a file, so `filesize` is the size of the region being
+    /// scanned and patterns spanning more than one region are not found.
+    /// The results include only the regions where some rule matched, and
+    /// the offsets of the matches are virtual addresses within the process,
+    /// regardless of the base offset set with [`Scanner::set_base_offset`].
+    ///
+    /// Regions larger than 64MB are not read at once, they are read in
+    /// blocks and scanned as a stream, which has the limitations described
+    /// in [`StreamScanner`]. For instance, the `pe` and `elf` modules don't
+    /// parse such regions.
+    ///
+    /// Regions that the process unmaps or protects while the scan is in
+    /// progress are not an error, they are listed in
+    /// [`ProcessScanOutput::skipped_regions`]. Lacking the privileges for
+    /// reading the process' memory produces a
+    /// [`ScanError::ProcessAccessDenied`].
+    ///
+    /// Only Linux is supported for now.
+    #[cfg(target_os = "linux")]
+    pub fn scan_process(
+        &mut self,
+        pid: u32,
+    ) -> Result<ProcessScanOutput, ScanError> {
+        // Each region is scanned with its address as the base offset, the
+        // base offset set by the user is restored afterwards.
+        let base_offset = self.wasm_store.data().base_offset;
+        let output = self.scan_process_regions(pid);
+        self.set_base_offset(base_offset);
+        output
+    }
+
+    /// Scans the readable memory regions of a process, see
+    /// [`Scanner::scan_process`].
+    #[cfg(target_os = "linux")]
+    fn scan_process_regions(
+        &mut self,
+        pid: u32,
+    ) -> Result<ProcessScanOutput, ScanError> {
+        let mut output = ProcessScanOutput {
+            regions: Vec::new(),
+            skipped_regions: Vec::new(),
+        };
+
+        let mut buf = Vec::new();
+
+        for region in process::readable_regions(pid)? {
+            self.set_base_offset(region.address as u64);
+
+            let results = if region.size <= process::MAX_BLOCK_SIZE {
+                if !process::read_block(
+                    pid,
+                    region.address,
+                    region.size,
+                    &mut buf,
+                )? {
+                    output.skipped_regions.push(region);
+                    continue;
+                }
+                self.scan(&buf)
+            } else {
+                // Large regions are read in blocks, and scanned as a stream
+                // that receives one block at a time.
+                let mut stream = self.stream();
+                let end = region.address + region.size;
+                let mut readable = true;
+
+                for address in
+                    (region.address..end).step_by(process::MAX_BLOCK_SIZE)
+                {
+                    let size = process::MAX_BLOCK_SIZE.min(end - address);
+                    if !process::read_block(pid, address, size, &mut buf)? {
+                        readable = false;
+                        break;
+                    }
+                    stream.feed(&buf);
+                }
+
+                if !readable {
+                    output.skipped_regions.push(region);
+                    continue;
+                }
+
+                stream.finish()
+            };
+
+            if results.num_matching_rules() == 0 {
+                continue;
+            }
+
+            output.regions.push(RegionOutput {
+                region,
+                output: results.to_owned_output(),
+            });
+        }
+
+        Ok(output)
     }
 
     /// Scans in-memory data.
     pub fn scan<'s>(&'s mut self, data: &[u8]) -> ScanResults<'s, 'r> {
-        // Clear information about matches found in a previous scan, if any.
-        self.clear_matches();
+        self.select_all_rules();
+        self.scan_selected(data)
+    }
 
-        // Set the global variable `filesize` to the size of the scanned data.
-        self.filesize
-            .set(self.wasm_store.as_context_mut(), Val::I64(data.len() as i64))
-            .unwrap();
+    /// Starts scanning data that is received in chunks, see
+    /// [`StreamScanner`].
+    pub fn stream<'s>(&'s mut self) -> StreamScanner<'s, 'r> {
+        self.select_all_rules();
+        StreamScanner::new(self)
+    }
+
+    /// Scans in-memory data with the rules accepted by `filter`.
+    ///
+    /// The filter is called once for each rule, and the conditions of the
+    /// rules for which it returns `false` are not evaluated at all. This
+    /// allows scanning with a subset of the compiled rules without
+    /// compiling them again. The rules that are referenced by a selected
+    /// rule, and the global rules in the same namespace as a selected rule,
+    /// are evaluated too, as the result of the selected rule depends on
+    /// them. However, only the selected rules are reported in the results,
+    /// both as matching and non-matching rules.
+    ///
+    /// ```
+    /// # use yara_x::{compile, Scanner};
+    /// let rules = compile(r#"
+    ///     private rule is_small { condition: filesize < 100 }
+    ///     rule small_foo { condition: is_small and uint8(0) == 0x66 }
+    ///     rule bar { condition: true }
+    /// "#).unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// let results = scanner.scan_with_filter(b"foo", |rule| rule.name() != "bar");
+    ///
+    /// assert_eq!(
+    ///     results.iter().map(|rule| rule.name()).collect::<Vec<_>>(),
+    ///     ["small_foo"]
+    /// );
+    /// ```
+    pub fn scan_with_filter<'s, F>(
+        &'s mut self,
+        data: &[u8],
+        filter: F,
+    ) -> ScanResults<'s, 'r>
+    where
+        F: FnMut(&Rule) -> bool,
+    {
+        self.select_rules(filter);
+        self.scan_selected(data)
+    }
+
+    /// Scans in-memory data, sending the events that happen during the scan
+    /// to `callback`.
+    ///
+    /// The events are sent as soon as they happen, see [`ScanEvent`] for
+    /// the list of events. If the callback returns [`CallbackAction::Abort`]
+    /// the scan stops, the rules that were not evaluated yet don't match,
+    /// and no more events are sent. The results returned by this function
+    /// are the same that [`Scanner::scan`] would return, except for the
+    /// rules that were not evaluated if the scan was aborted.
+    ///
+    /// The callback must be [`Send`], like the handlers passed to
+    /// [`Scanner::set_module_handler`], as the scanner itself is `Send`.
+    ///
+    /// ```
+    /// # use yara_x::{compile, CallbackAction, ScanEvent, Scanner};
+    /// let rules = compile(r#"
+    ///     rule foo { condition: true }
+    ///     rule bar { condition: true }
+    /// "#).unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// let mut first_match = None;
+    ///
+    /// let results = scanner.scan_with_callback(b"", |event| match event {
+    ///     ScanEvent::RuleMatched(rule) => {
+    ///         first_match = Some(rule.name());
+    ///         CallbackAction::Abort
+    ///     }
+    ///     _ => CallbackAction::Continue,
+    /// });
+    ///
+    /// assert_eq!(results.num_matching_rules(), 1);
+    /// assert_eq!(first_match, Some("foo"));
+    /// ```
+    pub fn scan_with_callback<'s, F>(
+        &'s mut self,
+        data: &[u8],
+        mut callback: F,
+    ) -> ScanResults<'s, 'r>
+    where
+        F: FnMut(ScanEvent<'_, 'r>) -> CallbackAction + Send,
+    {
+        self.select_all_rules();
+        self.start_scan();
 
         let ctx = self.wasm_store.data_mut();
 
         ctx.scanned_data = data.as_ptr();
         ctx.scanned_data_len = data.len();
+        ctx.stream_offset = None;
+
+        // The guard removes the callback from the context when dropped,
+        // including when the callback or a module panics.
+        CallbackGuard::new(self, &mut callback).scanner.evaluate(data.len());
+
+        ScanResults::new(self)
+    }
+
+    /// Scans in-memory data with the rules selected by the last call to
+    /// [`Scanner::select_rules`] or [`Scanner::select_all_rules`].
+    fn scan_selected<'s>(&'s mut self, data: &[u8]) -> ScanResults<'s, 'r> {
+        self.start_scan();
+
+        let ctx = self.wasm_store.data_mut();
+
+        ctx.scanned_data = data.as_ptr();
+        ctx.scanned_data_len = data.len();
+        ctx.stream_offset = None;
+
+        self.finish_scan(data.len())
+    }
+
+    /// Prepares the scanner for a new scan, discarding the results of the
+    /// previous one.
+    fn start_scan(&mut self) {
+        // Clear information about matches found in a previous scan, if any.
+        self.clear_matches();
+
+        let ctx = self.wasm_store.data_mut();
+
+        #[cfg(any(test, feature = "memory-checks"))]
+        {
+            ctx.memory_checks = Default::default();
+        }
 
         // TODO: this should be done only if the string pool is too large.
         ctx.string_pool = BStringPool::new();
 
-        for module_name in ctx.compiled_rules.imports() {
-            // Lookup the module in the list of built-in modules.
-            let module = modules::BUILTIN_MODULES.get(module_name).unwrap();
+        // Digests cached by the `hash` module are valid for a single scan.
+        #[cfg(feature = "hash-module")]
+        ctx.hash_cache.clear();
 
-            // Call the module's main function if any. This function returns
-            // a data structure serialized as a protocol buffer. The format of
-            // the data is specified by the .proto file associated to the
-            // module.
-            let module_output = if let Some(main_fn) = module.main_fn {
-                main_fn(ctx)
-            } else {
-                // Implement the case in which the module doesn't have a main
-                // function and the serialized data should be provided by the
-                // user.
-                todo!()
-            };
+        // The same applies to the import hash computed by the `pe` module.
+        #[cfg(feature = "pe-module")]
+        ctx.imphash_cache.take();
 
-            // Make sure that the module is returning a protobuf message of the
-            // expected type.
-            debug_assert_eq!(
-                module_output.descriptor_dyn().full_name(),
-                module.root_struct_descriptor.full_name(),
-                "main function of module `{}` must return `{}`, but returned `{}`",
-                module_name,
-                module.root_struct_descriptor.full_name(),
-                module_output.descriptor_dyn().full_name(),
-            );
+        ctx.module_errors.clear();
 
-            // Make sure that the module is returning a protobuf message where
-            // all required fields are initialized.
-            debug_assert!(
-                module_output.is_initialized_dyn(),
-                "module `{}` returned a protobuf `{}` where some required fields are not initialized ",
-                module_name,
-                module.root_struct_descriptor.full_name()
-            );
+        if let Some(profiler) = ctx.profiler.as_mut() {
+            profiler.reset();
+        }
+
+        // The callback is set only by `CallbackGuard`, which removes it
+        // when the scan ends. This makes sure that a callback is never used
+        // after its scan, even if the guard was not dropped.
+        ctx.callback = None;
+        ctx.scan_aborted = false;
+        ctx.pending_rule_matches.clear();
+    }
+
+    /// Evaluates the rules once the data to be scanned is set in the
+    /// [`ScanContext`], and returns the results. See [`Scanner::evaluate`].
+    fn finish_scan<'s>(&'s mut self, filesize: usize) -> ScanResults<'s, 'r> {
+        self.evaluate(filesize);
+        ScanResults::new(self)
+    }
+
+    /// Evaluates the rules once the data to be scanned is set in the
+    /// [`ScanContext`]. `filesize` is the size of the whole data, which
+    /// in streaming scans is larger than the data in the context.
+    fn evaluate(&mut self, filesize: usize) {
+        // Set the global variable `filesize` to the size of the scanned data.
+        self.filesize
+            .set(
+                self.wasm_store.as_context_mut(),
+                Val::I64(wasm::wasm_i64(filesize)),
+            )
+            .unwrap();
+
+        let ctx = self.wasm_store.data_mut();
+        let compiled_rules = ctx.compiled_rules;
+
+        for module_name in compiled_rules.imports() {
+            // Modules registered with `Compiler::register_module` obtain
+            // their data from the handler set with `set_module_handler`.
+            // Without a handler, the module is an empty message. In both
+            // user-defined and built-in modules, the data set with
+            // `set_module_output` takes precedence.
+            let module_output = if let Some(module_output) =
+                ctx.module_outputs.remove(module_name)
+            {
+                module_output
+            } else if let Some(descriptor) =
+                compiled_rules.user_module(module_name)
+            {
+                match ctx.module_handlers.get(module_name) {
+                    Some(handler) => {
+                        let module_output = handler(ctx.scanned_data());
+
+                        // The handler is provided by the user, a message of
+                        // some other type is not used, and the module is
+                        // undefined in this scan.
+                        if module_output.descriptor_dyn().full_name()
+                            == descriptor.full_name()
+                        {
+                            module_output
+                        } else {
+                            ctx.module_errors.push(
+                                ScanError::InvalidModuleOutput {
+                                    module: module_name.to_string(),
+                                    expected: descriptor
+                                        .full_name()
+                                        .to_string(),
+                                    actual: module_output
+                                        .descriptor_dyn()
+                                        .full_name()
+                                        .to_string(),
+                                },
+                            );
+                            descriptor.new_instance()
+                        }
+                    }
+                    None => descriptor.new_instance(),
+                }
+            } else {
+                // Lookup the module in the list of built-in modules.
+                let module =
+                    modules::BUILTIN_MODULES.get(module_name).unwrap();
+
+                // Call the module's main function if any. This function
+                // returns a data structure serialized as a protocol buffer.
+                // The format of the data is specified by the .proto file
+                // associated to the module.
+                let module_output = if let Some(main_fn) = module.main_fn {
+                    main_fn(ctx)
+                } else {
+                    // Implement the case in which the module doesn't have a
+                    // main function and the serialized data should be
+                    // provided by the user.
+                    todo!()
+                };
+
+                // Make sure that the module is returning a protobuf message
+                // of the expected type.
+                debug_assert_eq!(
+                    module_output.descriptor_dyn().full_name(),
+                    module.root_struct_descriptor.full_name(),
+                    "main function of module `{}` must return `{}`, but returned `{}`",
+                    module_name,
+                    module.root_struct_descriptor.full_name(),
+                    module_output.descriptor_dyn().full_name(),
+                );
+
+                // Make sure that the module is returning a protobuf message
+                // where all required fields are initialized.
+                debug_assert!(
+                    module_output.is_initialized_dyn(),
+                    "module `{}` returned a protobuf `{}` where some required fields are not initialized ",
+                    module_name,
+                    module.root_struct_descriptor.full_name()
+                );
+
+                module_output
+            };
 
             // When compile-time optimizations are enabled we don't need to
             // generate structure fields for enums. This is because during the
@@ -220,6 +1031,13 @@ impl<'r> Scanner<'r> {
             let generate_fields_for_enums =
                 !cfg!(feature = "compile-time-optimization");
 
+            if ctx.send_event(ScanEvent::ModuleOutput {
+                name: module_name,
+                output: module_output.as_ref(),
+            }) {
+                ctx.abort_scan();
+            }
+
             let module_struct = Struct::from_proto_msg(
                 module_output,
                 generate_fields_for_enums,
@@ -232,172 +1050,1138 @@ impl<'r> Scanner<'r> {
             // in the data structure, as they are used in the rule conditions.
             ctx.root_struct.add_field(
                 module_name,
-                TypeValue::Struct(Rc::new(module_struct)),
+                TypeValue::Struct(Arc::new(module_struct)),
             );
         }
 
+        // Data set with `set_module_output` is used only in a single scan,
+        // even for modules not imported by the rules.
+        ctx.module_outputs.clear();
+
+        // The time spent by modules is not charged to any rule.
+        if let Some(profiler) = ctx.profiler.as_mut() {
+            profiler.start();
+        }
+
         // Invoke the main function, which evaluates the rules' conditions. It
         // triggers the Aho-Corasick scanning phase only if necessary. See
         // ScanContext::search_for_patterns.
         self.wasm_main_fn.call(self.wasm_store.as_context_mut(), ()).unwrap();
 
-        let ctx = self.wasm_store.data_mut();
+        #[cfg(any(test, feature = "memory-checks"))]
+        wasm::memory_checks::check_after_scan(&self.wasm_store);
+
+        let ctx = self.wasm_store.data_mut();
+
+        ctx.apply_global_rules();
+        ctx.apply_rule_selection();
+
+        if ctx.send_event(ScanEvent::ScanFinished) {
+            ctx.abort_scan();
+        }
+
+        // Set pointer to data back to nil. This means that accessing
+        // `scanned_data` from within `ScanResults` is not possible.
+        ctx.scanned_data = null();
+        ctx.scanned_data_len = 0;
+        ctx.stream_offset = None;
+
+        // Clear the value of `current_struct` as it may contain a reference
+        // to some struct.
+        ctx.current_struct = None;
+    }
+
+    /// Selects the rules accepted by `filter`, together with the rules
+    /// they depend on, for the next scan. See [`Scanner::scan_with_filter`].
+    fn select_rules<F>(&mut self, mut filter: F)
+    where
+        F: FnMut(&Rule) -> bool,
+    {
+        let ctx = self.wasm_store.data_mut();
+        let rules = ctx.compiled_rules;
+        let num_rules = rules.rules().len();
+
+        let selected: BitVec = (0..num_rules)
+            .map(|i| filter(&Rule::new(rules, None, RuleId::from(i))))
+            .collect();
+
+        // Rules that must be evaluated, including the dependencies of the
+        // selected rules.
+        let mut evaluated = selected.clone();
+
+        let mut pending: Vec<RuleId> =
+            selected.iter_ones().map(RuleId::from).collect();
+
+        // When a global rule doesn't match, no other rule in the same
+        // namespace matches, so global rules are dependencies of every
+        // rule in their namespace.
+        for global_rule_id in rules.global_rules() {
+            let namespace_id = rules.get(*global_rule_id).namespace_id;
+            if pending.iter().any(|rule_id| {
+                rules.get(*rule_id).namespace_id == namespace_id
+            }) {
+                pending.push(*global_rule_id);
+            }
+        }
+
+        while let Some(rule_id) = pending.pop() {
+            evaluated.set(usize::from(rule_id), true);
+            for dependency in &rules.get(rule_id).dependencies {
+                if !evaluated[usize::from(*dependency)] {
+                    pending.push(*dependency);
+                }
+            }
+        }
+
+        let layout = ctx.memory_layout();
+        let mem = ctx
+            .main_memory
+            .unwrap()
+            .data_mut(self.wasm_store.as_context_mut());
+
+        let bits = BitSlice::<u8, Lsb0>::from_slice_mut(
+            &mut mem[layout.selected_rules_bitmap()],
+        );
+
+        bits.fill(false);
+
+        for rule_id in evaluated.iter_ones() {
+            bits.set(rule_id, true);
+        }
+
+        self.wasm_store.data_mut().selected_rules = Some(selected);
+    }
+
+    /// Selects all the rules for the next scan.
+    fn select_all_rules(&mut self) {
+        let ctx = self.wasm_store.data_mut();
+
+        // The bitmap is modified only by scans with a filter, and by
+        // aborted scans.
+        if ctx.selected_rules.take().is_some() || ctx.scan_aborted {
+            let layout = ctx.memory_layout();
+            let mem = ctx
+                .main_memory
+                .unwrap()
+                .data_mut(self.wasm_store.as_context_mut());
+
+            mem[layout.selected_rules_bitmap()].fill(0xff);
+        }
+    }
+
+    /// Returns the descriptor of the message that describes the structure
+    /// of the module with the given name.
+    fn module_descriptor(
+        &self,
+        name: &str,
+    ) -> Result<MessageDescriptor, ScanError> {
+        if let Some(descriptor) =
+            self.wasm_store.data().compiled_rules.user_module(name)
+        {
+            return Ok(descriptor.clone());
+        }
+        modules::BUILTIN_MODULES
+            .get(name)
+            .map(|module| module.root_struct_descriptor.clone())
+            .ok_or_else(|| ScanError::UnknownModule(name.to_string()))
+    }
+
+    // Clear information about previous matches.
+    fn clear_matches(&mut self) {
+        let ctx = self.wasm_store.data_mut();
+        let layout = ctx.memory_layout();
+
+        if ctx.patterns_found
+            || !ctx.rules_matching.is_empty()
+            || !ctx.private_rules_matching.is_empty()
+        {
+            // Clear the list of matching rules.
+            ctx.rules_matching.clear();
+            ctx.private_rules_matching.clear();
+            let mem = ctx
+                .main_memory
+                .unwrap()
+                .data_mut(self.wasm_store.as_context_mut());
+            // Starting at MATCHING_RULES_BITMAP in main memory there's a bitmap
+            // were the N-th bit indicates if the rule with ID = N matched or not,
+            // If some rule matched in a previous call the bitmap will contain some
+            // bits set to 1 and need to be cleared. The same happens with the
+            // bitmap for patterns.
+            mem[layout.matching_rules_bitmap()].fill(0);
+            mem[layout.matching_patterns_bitmap()].fill(0);
+        }
+
+        let ctx = self.wasm_store.data_mut();
+
+        // Remove the matches found for each pattern. The lists that
+        // contained them are kept for reusing them in the next scan.
+        ctx.free_match_lists.extend(ctx.pattern_matches.drain().map(
+            |(_, mut list)| {
+                list.clear();
+                list
+            },
+        ));
+
+        // Local variables from the previous scan may hold references to
+        // structures created by modules, which are not needed anymore.
+        ctx.vars_stack.clear();
+
+        // After a scan that needed much more memory than usual, the memory
+        // that exceeds the limit is released instead of keeping it for the
+        // next scan. Nothing was released so far, the memory used by the
+        // matches of the previous scan is still counted.
+        let retained_capacity = ctx.retained_capacity();
+
+        if retained_capacity > ctx.max_retained_capacity {
+            ctx.release_capacity(
+                retained_capacity - ctx.max_retained_capacity,
+            );
+        }
+    }
+}
+
+/// Shrinks the capacity of `v` for releasing up to `excess` bytes, and
+/// subtracts the released bytes from `excess`.
+fn shrink_vec<T>(v: &mut Vec<T>, excess: &mut usize) {
+    let size = mem::size_of::<T>();
+    let before = v.capacity();
+    v.shrink_to(before.saturating_sub(excess.div_ceil(size)));
+    *excess = excess.saturating_sub((before - v.capacity()) * size);
+}
+
+/// Sets the callback of a scan started with [`Scanner::scan_with_callback`]
+/// in the [`ScanContext`], and removes it when dropped.
+///
+/// The callback lives in the stack frame of `scan_with_callback`, so it
+/// must be removed from the context before that function returns or
+/// unwinds, otherwise the next scan would call it. Both the callback and
+/// the handlers of user-defined modules can panic during the scan.
+struct CallbackGuard<'a, 'r> {
+    scanner: &'a mut Scanner<'r>,
+}
+
+impl<'a, 'r> CallbackGuard<'a, 'r> {
+    fn new<F>(scanner: &'a mut Scanner<'r>, callback: &'a mut F) -> Self
+    where
+        F: FnMut(ScanEvent<'_, 'r>) -> CallbackAction + Send,
+    {
+        // The rules notify the scanner after evaluating their conditions
+        // only while this global variable is set, as in profiling mode.
+        scanner
+            .profiling_enabled
+            .set(scanner.wasm_store.as_context_mut(), Val::I32(1))
+            .unwrap();
+
+        let callback: &mut (dyn FnMut(ScanEvent<'_, 'r>) -> CallbackAction
+                  + Send
+                  + 'a) = callback;
+
+        // SAFETY: The lifetime of the callback is erased, as the context
+        // can't be parameterized by it. The callback outlives the guard,
+        // and the guard removes it from the context when dropped, so the
+        // pointer is not used after the callback is gone.
+        scanner.wasm_store.data_mut().callback = Some(unsafe {
+            mem::transmute::<
+                NonNull<
+                    dyn FnMut(ScanEvent<'_, 'r>) -> CallbackAction + Send + 'a,
+                >,
+                NonNull<ScanCallback<'r>>,
+            >(NonNull::from(callback))
+        });
+
+        Self { scanner }
+    }
+}
+
+impl Drop for CallbackGuard<'_, '_> {
+    fn drop(&mut self) {
+        let ctx = self.scanner.wasm_store.data_mut();
+
+        ctx.callback = None;
+
+        // Notifications about evaluated rules are needed only if profiling
+        // is enabled.
+        let profiling_enabled = ctx.profiler.is_some();
+
+        self.scanner
+            .profiling_enabled
+            .set(
+                self.scanner.wasm_store.as_context_mut(),
+                Val::I32(profiling_enabled.into()),
+            )
+            .unwrap();
+    }
+}
+
+/// Statistics about a [`Scanner`], see [`Scanner::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ScannerStats {
+    /// Number of bytes allocated by the scanner for storing information
+    /// about scans, like the matching rules and the matches found for each
+    /// pattern. After a scan this includes the memory used by its results,
+    /// which is kept for the next scan, except for the part that exceeds
+    /// [`Scanner::set_max_retained_capacity`], which is released when the
+    /// next scan starts.
+    pub retained_capacity: usize,
+}
+
+/// Errors returned while scanning.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ScanError {
+    /// The file to be scanned could not be opened or read.
+    #[error("can not open `{}`", path.display())]
+    OpenError { path: PathBuf, source: std::io::Error },
+    /// The process to be scanned doesn't exist, or its memory could not be
+    /// read for reasons other than lack of permissions.
+    #[error("can not scan process {pid}")]
+    ProcessError { pid: u32, source: std::io::Error },
+    /// Not enough privileges for reading the memory of the process to be
+    /// scanned. Reading the memory of a process requires the same
+    /// privileges as attaching to it with a debugger, which may be further
+    /// restricted by settings like Linux's `ptrace_scope`.
+    #[error("access denied to the memory of process {pid}")]
+    ProcessAccessDenied { pid: u32 },
+    /// The module does not exist, neither as a built-in module nor as a
+    /// module registered with [`crate::Compiler::register_module`].
+    #[error("unknown module `{0}`")]
+    UnknownModule(String),
+    /// The data given for a module is not of the type that describes the
+    /// module's structure.
+    #[error(
+        "data for module `{module}` must be `{expected}`, not `{actual}`"
+    )]
+    InvalidModuleOutput { module: String, expected: String, actual: String },
+    /// The serialized data given for a module could not be decoded.
+    #[error("can not decode data for module `{module}`")]
+    ProtoError { module: String, source: protobuf::Error },
+    /// The global variable passed to [`Scanner::set_global`] was not
+    /// defined with [`crate::Compiler::define_global`].
+    #[error("unknown global variable `{0}`")]
+    UnknownGlobal(String),
+    /// The value passed to [`Scanner::set_global`] doesn't have the type
+    /// of the global variable.
+    #[error(
+        "global variable `{variable}` must be `{expected}`, not `{actual}`"
+    )]
+    InvalidGlobalType { variable: String, expected: String, actual: String },
+}
+
+/// Results of a scan operation.
+///
+/// The results borrow from the scanner that produced them, they are not a
+/// copy. For this reason, the scanner can't be used while the results are
+/// alive, neither for scanning more data, nor for changing its settings.
+/// These restrictions are enforced by the compiler, as all the functions
+/// that scan or modify the scanner receive `&mut self`. Use
+/// [`ScanResults::to_owned_output`] for keeping the results of a scan after
+/// the next one starts.
+///
+/// ```compile_fail,E0499
+/// # use yara_x::{compile, Scanner};
+/// let rules = compile("rule test { condition: true }").unwrap();
+/// let mut scanner = Scanner::new(&rules);
+/// let results = scanner.scan(b"foo");
+/// // Scanning again while `results` is alive is not allowed.
+/// scanner.scan(b"bar");
+/// assert_eq!(results.num_matching_rules(), 1);
+/// ```
+///
+/// ```compile_fail,E0499
+/// # use yara_x::{compile, Scanner};
+/// let rules = compile("rule test { condition: true }").unwrap();
+/// let mut scanner = Scanner::new(&rules);
+/// let results = scanner.scan(b"foo");
+/// // Neither is changing the scanner's settings.
+/// scanner.set_max_retained_capacity(0);
+/// assert_eq!(results.num_matching_rules(), 1);
+/// ```
+///
+/// The results don't borrow the scanned data, which can be dropped right
+/// after the scan. Data from the rules, like the rule names, borrows from
+/// the compiled [`Rules`] instead of the scanner, and remains available
+/// after the results are dropped.
+///
+/// ```
+/// # use yara_x::{compile, Scanner};
+/// let rules = compile("rule test { condition: true }").unwrap();
+/// let mut scanner = Scanner::new(&rules);
+/// let data = b"foo".to_vec();
+/// let results = scanner.scan(&data);
+/// drop(data);
+///
+/// let name = results.iter().next().unwrap().name();
+/// let output = results.to_owned_output();
+///
+/// // `results` is not used anymore, the scanner can be used again.
+/// scanner.set_max_retained_capacity(0);
+/// scanner.scan(b"bar");
+///
+/// assert_eq!(name, "test");
+/// assert_eq!(output.matching_rules.len(), 1);
+/// ```
+pub struct ScanResults<'s, 'r> {
+    scanner: &'s Scanner<'r>,
+}
+
+impl<'s, 'r> ScanResults<'s, 'r> {
+    fn new(scanner: &'s Scanner<'r>) -> Self {
+        Self { scanner }
+    }
+
+    /// Returns the `n` rules where the scan spent most of its time, sorted
+    /// by decreasing [`RuleProfile::total_time`].
+    ///
+    /// All the rules are taken into account, including private rules and
+    /// rules that didn't match, except the ones where no time was measured.
+    /// Returns an empty vector if profiling is disabled, see
+    /// [`Scanner::set_profiling`].
+    pub fn profiling(&self, n: usize) -> Vec<RuleProfile<'s, 'r>> {
+        let ctx = self.scanner.wasm_store.data();
+
+        let Some(profiler) = ctx.profiler.as_ref() else {
+            return Vec::new();
+        };
+
+        let mut profiles: Vec<_> = ctx
+            .compiled_rules
+            .rules()
+            .iter()
+            .enumerate()
+            .map(|(i, rule_info)| RuleProfile {
+                rule: Rule::new(
+                    ctx.compiled_rules,
+                    Some(ctx),
+                    RuleId::from(i),
+                ),
+                condition_time: profiler.condition_times[i],
+                pattern_time: rule_info
+                    .patterns
+                    .iter()
+                    .map(|(_, pattern_id)| {
+                        profiler.pattern_times[usize::from(*pattern_id)]
+                    })
+                    .sum(),
+            })
+            .filter(|profile| !profile.total_time().is_zero())
+            .collect();
+
+        profiles.sort_by_key(|profile| Reverse(profile.total_time()));
+        profiles.truncate(n);
+        profiles
+    }
+
+    /// Returns the number of rules that matched.
+    ///
+    /// Private rules are not taken into account.
+    pub fn num_matching_rules(&self) -> usize {
+        self.scanner.wasm_store.data().rules_matching.len()
+    }
+
+    /// Returns true if some pattern reached the maximum number of matches
+    /// and the rest of its matches were discarded, see
+    /// [`Scanner::set_max_matches_per_pattern`].
+    ///
+    /// All the patterns are taken into account, including the ones in
+    /// private and non-matching rules. Use [`Pattern::matches_truncated`]
+    /// for knowing which patterns were affected.
+    pub fn matches_truncated(&self) -> bool {
+        self.scanner
+            .wasm_store
+            .data()
+            .pattern_matches
+            .values()
+            .any(|matches| matches.is_truncated())
+    }
+
+    /// Returns the errors found while obtaining the data of the modules
+    /// imported by the rules.
+    ///
+    /// Currently, these are the [`ScanError::InvalidModuleOutput`] errors
+    /// produced when the handler set with [`Scanner::set_module_handler`]
+    /// returns a message of the wrong type. The fields of these modules
+    /// are undefined during the scan.
+    pub fn module_errors(&self) -> &[ScanError] {
+        self.scanner.wasm_store.data().module_errors.as_slice()
+    }
+
+    /// Returns the offset added to the offsets of the matches in the
+    /// owned output, see [`Scanner::set_base_offset`].
+    pub fn base_offset(&self) -> u64 {
+        self.scanner.wasm_store.data().base_offset
+    }
+
+    /// Returns true if the rule with the given [`RuleId`] matched.
+    ///
+    /// This works for private rules too. A rule doesn't match if some
+    /// global rule in its namespace didn't match, even if its own condition
+    /// is true. Returns false if no rule has the given ID.
+    ///
+    /// ```
+    /// # use yara_x::{compile, Scanner};
+    /// let rules = compile(r#"
+    ///     private rule foo { condition: true }
+    ///     rule bar { condition: false }
+    /// "#).unwrap();
+    ///
+    /// let foo = rules.rule_by_name("default", "foo").unwrap().id();
+    /// let bar = rules.rule_by_name("default", "bar").unwrap().id();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// let results = scanner.scan(b"");
+    ///
+    /// assert!(results.matched(foo));
+    /// assert!(!results.matched(bar));
+    /// ```
+    pub fn matched(&self, rule_id: RuleId) -> bool {
+        matching_rules_bitmap(self.scanner)
+            .get(usize::from(rule_id))
+            .is_some_and(|bit| *bit)
+    }
+
+    /// Returns an iterator that yields the matching rules, sorted by
+    /// [`RuleId`].
+    ///
+    /// Private rules are not included, unless requested with
+    /// [`Matches::include_private`].
+    pub fn matching_rules(&self) -> Matches<'s, 'r> {
+        Matches::new(self.scanner)
+    }
+
+    /// Returns an iterator that yields the non-matching rules, sorted by
+    /// [`RuleId`].
+    ///
+    /// Private rules are not included, unless requested with
+    /// [`NonMatches::include_private`]. Disabled rules are never included,
+    /// see [`ScanResults::iter_disabled`]. In scans started with
+    /// [`Scanner::scan_with_filter`], only the selected rules are included.
+    ///
+    /// Together with [`ScanResults::matching_rules`], this gives the status
+    /// of every rule that was evaluated:
+    ///
+    /// ```
+    /// # use yara_x::{compile, Scanner};
+    /// let rules = compile(r#"
+    ///     rule foo { condition: true }
+    ///     private rule bar { condition: false }
+    ///     rule baz { condition: bar }
+    /// "#).unwrap();
+    ///
+    /// let mut scanner = Scanner::new(&rules);
+    /// let results = scanner.scan(b"");
+    ///
+    /// let non_matching: Vec<_> = results
+    ///     .non_matching_rules()
+    ///     .include_private(true)
+    ///     .map(|rule| rule.name())
+    ///     .collect();
+    ///
+    /// assert_eq!(results.matching_rules().count(), 1);
+    /// assert_eq!(non_matching, ["bar", "baz"]);
+    /// ```
+    pub fn non_matching_rules(&self) -> NonMatches<'s, 'r> {
+        NonMatches::new(self.scanner)
+    }
+
+    /// Returns an iterator that yields the matching rules.
+    ///
+    /// Same as [`ScanResults::matching_rules`].
+    pub fn iter(&self) -> Matches<'s, 'r> {
+        self.matching_rules()
+    }
+
+    /// Returns an iterator that yields the non-matching rules.
+    ///
+    /// Same as [`ScanResults::non_matching_rules`].
+    pub fn iter_non_matches(&self) -> NonMatches<'s, 'r> {
+        self.non_matching_rules()
+    }
+
+    /// Returns an iterator that yields the disabled rules.
+    ///
+    /// Disabled rules are not evaluated, so they are neither matching nor
+    /// non-matching. Private rules are not included. See
+    /// [`crate::Compiler::force_enable_rules`].
+    pub fn iter_disabled(&self) -> Disabled<'s, 'r> {
+        Disabled::new(self.scanner)
+    }
+
+    /// Returns an owned copy of the scan results.
+    ///
+    /// The returned [`ScanOutput`] doesn't borrow from the scanner, and can
+    /// be serialized when the `serde` feature is enabled.
+    pub fn to_owned_output(&self) -> ScanOutput {
+        self.owned_output(None)
+    }
+
+    /// Like [`ScanResults::to_owned_output`], but the matches include the
+    /// matching data, see [`MatchOutput::data`].
+    ///
+    /// `data` must be the scanned data, the results don't keep a copy of
+    /// it. At most `max_data_len` bytes are included for each match.
+    ///
+    /// ```
+    /// # use yara_x::{compile, escape::unescape, Scanner};
+    /// let rules = compile(r#"rule test { strings: $a = "foo" condition: $a }"#)
+    ///     .unwrap();
+    ///
+    /// let data = b"\x1b[2Jfoo";
+    /// let mut scanner = Scanner::new(&rules);
+    /// let output = scanner.scan(data).to_owned_output_with_data(data, 2);
+    /// let m = &output.matching_rules[0].patterns[0].matches[0];
+    ///
+    /// assert_eq!(m.data.as_deref(), Some("fo"));
+    /// assert_eq!(unescape(m.data.as_ref().unwrap()).unwrap(), b"fo");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `data` is not the scanned data and some match is out of its
+    /// bounds.
+    pub fn to_owned_output_with_data(
+        &self,
+        data: &[u8],
+        max_data_len: usize,
+    ) -> ScanOutput {
+        self.owned_output(Some((data, max_data_len)))
+    }
+
+    fn owned_output(&self, data: Option<(&[u8], usize)>) -> ScanOutput {
+        ScanOutput {
+            matching_rules: self
+                .iter()
+                .map(|rule| RuleOutput {
+                    id: usize::from(rule.id()),
+                    namespace: rule.namespace().to_owned(),
+                    name: rule.name().to_owned(),
+                    tags: rule.tags().map(str::to_owned).collect(),
+                    metadata: rule
+                        .metadata()
+                        .map(|(identifier, value)| MetadataOutput {
+                            identifier: identifier.to_owned(),
+                            value: value.into(),
+                        })
+                        .collect(),
+                    patterns: rule
+                        .patterns()
+                        .map(|pattern| PatternOutput {
+                            identifier: pattern.identifier().to_owned(),
+                            matches: pattern
+                                .matches()
+                                .iter()
+                                .map(|m| MatchOutput {
+                                    offset: m.address(),
+                                    length: m.range.len(),
+                                    xor_key: m.xor_key,
+                                    base64: m.base64,
+                                    data: data.map(|(data, max_len)| {
+                                        let range = m.range.start
+                                            ..m.range
+                                                .end
+                                                .min(m.range.start + max_len);
+                                        escape::escape(&data[range])
+                                    }),
+                                })
+                                .collect(),
+                            matches_truncated: pattern.matches_truncated(),
+                        })
+                        .collect(),
+                    match_stats: rule.match_stats(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Returns the bitmap in main memory that tells which rules matched. The
+/// N-th bit corresponds to the rule with [`RuleId`] N.
+fn matching_rules_bitmap<'s>(scanner: &'s Scanner<'_>) -> &'s BitSlice<u8> {
+    let ctx = scanner.wasm_store.data();
+    let num_rules = ctx.compiled_rules.rules().len();
+    let main_memory =
+        ctx.main_memory.unwrap().data(scanner.wasm_store.as_context());
+
+    // Create a BitSlice that covers the region of main memory containing
+    // the bitmap that tells which rules matched and which did not.
+    let matching_rules_bitmap = BitSlice::<_, Lsb0>::from_slice(
+        &main_memory[ctx.memory_layout().matching_rules_bitmap()],
+    );
+
+    // The BitSlice will cover more bits than necessary, for example, if
+    // there are 3 rules the BitSlice will have 8 bits because it is
+    // created from a u8 slice that has 1 byte. Here we make sure that
+    // the BitSlice has exactly as many bits as existing rules.
+    &matching_rules_bitmap[0..num_rules]
+}
+
+/// Iterator that yields the rules that matched.
+pub struct Matches<'s, 'r> {
+    scanner: &'s Scanner<'r>,
+    iterator: bitvec::slice::IterOnes<'s, u8, Lsb0>,
+    include_private: bool,
+}
+
+impl<'s, 'r> Matches<'s, 'r> {
+    fn new(scanner: &'s Scanner<'r>) -> Self {
+        Self {
+            scanner,
+            iterator: matching_rules_bitmap(scanner).iter_ones(),
+            include_private: false,
+        }
+    }
+
+    /// Includes private rules in the results if `yes` is true. By default
+    /// they are not included.
+    pub fn include_private(mut self, yes: bool) -> Self {
+        self.include_private = yes;
+        self
+    }
+}
+
+impl<'s, 'r> Iterator for Matches<'s, 'r> {
+    type Item = Rule<'s, 'r>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ctx = self.scanner.wasm_store.data();
+        loop {
+            let rule_id = RuleId::from(self.iterator.next()?);
+            let rule = Rule::new(ctx.compiled_rules, Some(ctx), rule_id);
+            if self.include_private || !rule.rule_info.private {
+                return Some(rule);
+            }
+        }
+    }
+}
+
+/// Iterator that yields the rules that didn't match.
+pub struct NonMatches<'s, 'r> {
+    scanner: &'s Scanner<'r>,
+    iterator: bitvec::slice::IterZeros<'s, u8, Lsb0>,
+    include_private: bool,
+}
+
+impl<'s, 'r> NonMatches<'s, 'r> {
+    fn new(scanner: &'s Scanner<'r>) -> Self {
+        Self {
+            scanner,
+            iterator: matching_rules_bitmap(scanner).iter_zeros(),
+            include_private: false,
+        }
+    }
+
+    /// Includes private rules in the results if `yes` is true. By default
+    /// they are not included.
+    pub fn include_private(mut self, yes: bool) -> Self {
+        self.include_private = yes;
+        self
+    }
+}
+
+impl<'s, 'r> Iterator for NonMatches<'s, 'r> {
+    type Item = Rule<'s, 'r>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ctx = self.scanner.wasm_store.data();
+        loop {
+            let rule_id = RuleId::from(self.iterator.next()?);
+            let rule = Rule::new(ctx.compiled_rules, Some(ctx), rule_id);
+            if !rule.rule_info.disabled
+                && (self.include_private || !rule.rule_info.private)
+                && ctx.is_rule_selected(rule_id)
+            {
+                return Some(rule);
+            }
+        }
+    }
+}
+
+/// Iterator that yields the rules that are disabled.
+pub struct Disabled<'s, 'r> {
+    ctx: &'s ScanContext<'r>,
+    iterator: Enumerate<Iter<'r, RuleInfo>>,
+}
+
+impl<'s, 'r> Disabled<'s, 'r> {
+    fn new(scanner: &'s Scanner<'r>) -> Self {
+        let ctx = scanner.wasm_store.data();
+        Self { ctx, iterator: ctx.compiled_rules.rules().iter().enumerate() }
+    }
+}
+
+impl<'s, 'r> Iterator for Disabled<'s, 'r> {
+    type Item = Rule<'s, 'r>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ctx = self.ctx;
+        self.iterator
+            .find(|(_, rule_info)| rule_info.disabled && !rule_info.private)
+            .map(|(i, _)| {
+                Rule::new(ctx.compiled_rules, Some(ctx), RuleId::from(i))
+            })
+    }
+}
+
+/// A structure that describes a rule.
+///
+/// Rules are obtained from scan results, or directly from the compiled
+/// [`Rules`] with [`Rules::iter`] and [`Rules::rule_by_id`]. In the latter
+/// case the rule is not associated to any scan, and its patterns don't have
+/// matches.
+pub struct Rule<'s, 'r> {
+    rules: &'r Rules,
+    ctx: Option<&'s ScanContext<'r>>,
+    id: RuleId,
+    rule_info: &'r RuleInfo,
+}
+
+impl<'s, 'r> Rule<'s, 'r> {
+    /// Creates a new [`Rule`]. `ctx` is the context of the scan where the
+    /// rule was evaluated, if any.
+    ///
+    /// # Panics
+    ///
+    /// If no rule with such [`RuleId`] exists.
+    pub(crate) fn new(
+        rules: &'r Rules,
+        ctx: Option<&'s ScanContext<'r>>,
+        id: RuleId,
+    ) -> Self {
+        Self { rules, ctx, id, rule_info: rules.get(id) }
+    }
+
+    /// Returns the rule's ID.
+    ///
+    /// See [`RuleId`] for the guarantees about IDs.
+    pub fn id(&self) -> RuleId {
+        self.id
+    }
+
+    /// Returns the rule's name.
+    pub fn name(&self) -> &'r str {
+        self.rules.ident_pool().get(self.rule_info.ident_id).unwrap()
+    }
+
+    /// Returns the rule's namespace.
+    ///
+    /// Rules added to the compiler without specifying a namespace are in
+    /// [`crate::DEFAULT_NAMESPACE`].
+    pub fn namespace(&self) -> &'r str {
+        self.rules.ident_pool().get(self.rule_info.namespace_id).unwrap()
+    }
+
+    /// Returns the origin of the source code that contained the rule, as
+    /// set with [`crate::SourceCode::origin`], usually a file path.
+    /// Returns [`None`] if the source code didn't have an origin.
+    pub fn origin(&self) -> Option<&'r str> {
+        self.rule_info
+            .origin
+            .map(|origin| self.rules.ident_pool().get(origin).unwrap())
+    }
+
+    /// Returns a hash of the rule's source code.
+    ///
+    /// Two rules with the same fingerprint have the same source code, even
+    /// if they were compiled separately. This allows telling apart rules
+    /// that were modified from rules that remain the same between two
+    /// versions of a set of rules. Any change in the source code of the
+    /// rule, including whitespace and comments, changes its fingerprint.
+    /// The fingerprint is not a cryptographic hash.
+    pub fn fingerprint(&self) -> u64 {
+        self.rule_info.fingerprint
+    }
 
-        // Set pointer to data back to nil. This means that accessing
-        // `scanned_data` from within `ScanResults` is not possible.
-        ctx.scanned_data = null();
-        ctx.scanned_data_len = 0;
+    /// Returns true if the rule has the `private` modifier.
+    ///
+    /// Private rules are never included in scan results, but they are
+    /// returned by [`Rules::iter`] and [`Rules::rule_by_id`].
+    pub fn is_private(&self) -> bool {
+        self.rule_info.private
+    }
 
-        // Clear the value of `current_struct` as it may contain a reference
-        // to some struct.
-        ctx.current_struct = None;
+    /// Returns true if the rule has the `global` modifier.
+    pub fn is_global(&self) -> bool {
+        self.rule_info.global
+    }
 
-        ScanResults::new(self)
+    /// Returns true if the rule is disabled.
+    ///
+    /// A rule is disabled by including `enabled = false` in its `meta`
+    /// section, unless the compiler was told to ignore it with
+    /// [`crate::Compiler::force_enable_rules`].
+    pub fn is_disabled(&self) -> bool {
+        self.rule_info.disabled
     }
 
-    // Clear information about previous matches.
-    fn clear_matches(&mut self) {
-        let ctx = self.wasm_store.data_mut();
-        let num_rules = ctx.compiled_rules.rules().len();
-        let num_patterns = ctx.compiled_rules.num_patterns();
+    /// Returns the IDs of the rules referenced in the rule's condition, in
+    /// the order they first appear. Rules referenced more than once are
+    /// included only once.
+    ///
+    /// These rules are always declared before this one, in the same
+    /// namespace. Use [`Rules::rule_by_id`] for obtaining the rules.
+    pub fn dependencies(&self) -> &'r [RuleId] {
+        self.rule_info.dependencies.as_slice()
+    }
 
-        if ctx.patterns_found || !ctx.rules_matching.is_empty() {
-            // Clear the list of matching rules.
-            ctx.rules_matching.clear();
-            let mem = ctx
-                .main_memory
-                .unwrap()
-                .data_mut(self.wasm_store.as_context_mut());
-            // Starting at MATCHING_RULES_BITMAP in main memory there's a bitmap
-            // were the N-th bit indicates if the rule with ID = N matched or not,
-            // If some rule matched in a previous call the bitmap will contain some
-            // bits set to 1 and need to be cleared.
-            let base = MATCHING_RULES_BITMAP_BASE as usize;
-            let bitmap = BitSlice::<_, Lsb0>::from_slice_mut(
-                &mut mem[base..base
-                    + (num_rules / 8 + 1)
-                    + (num_patterns / 8 + 1)],
-            );
-            // Set to zero all bits in the bitmap.
-            bitmap.fill(false);
-        }
+    /// Returns an iterator that yields the rule's tags.
+    pub fn tags(&self) -> Tags<'r> {
+        Tags { rules: self.rules, iterator: self.rule_info.tags.iter() }
     }
-}
 
-/// Results of a scan operation.
-pub struct ScanResults<'s, 'r> {
-    scanner: &'s Scanner<'r>,
-}
+    /// Returns true if the rule has the given tag.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        // A tag that is not in the identifiers pool can't be in the rule.
+        self.rules
+            .ident_pool()
+            .get_id(tag)
+            .is_some_and(|ident_id| self.rule_info.tags.contains(&ident_id))
+    }
 
-impl<'s, 'r> ScanResults<'s, 'r> {
-    fn new(scanner: &'s Scanner<'r>) -> Self {
-        Self { scanner }
+    /// Returns an iterator that yields the rule's metadata as
+    /// (identifier, value) pairs.
+    ///
+    /// Entries are returned in the order they were declared. YARA allows
+    /// the same identifier to appear more than once, in that case each
+    /// occurrence is returned. Entries inherited from the defaults set with
+    /// [`crate::Compiler::namespace_default_meta`] or
+    /// [`crate::SourceCode::with_default_meta`] come after the rule's own
+    /// entries, and are indistinguishable from them.
+    pub fn metadata(&self) -> Metadata<'r> {
+        Metadata {
+            rules: self.rules,
+            iterator: self.rule_info.metadata.iter(),
+        }
     }
 
-    /// Returns the number of rules that matched.
-    pub fn num_matching_rules(&self) -> usize {
-        self.scanner.wasm_store.data().rules_matching.len()
+    /// Returns an iterator that yields only the metadata entries that the
+    /// rule inherited from the defaults, instead of declaring them in its
+    /// own `meta` section. These are the last entries returned by
+    /// [`Rule::metadata`].
+    pub fn inherited_metadata(&self) -> Metadata<'r> {
+        let metadata = self.rule_info.metadata.as_slice();
+        Metadata {
+            rules: self.rules,
+            iterator: metadata
+                [metadata.len() - self.rule_info.num_inherited_metadata..]
+                .iter(),
+        }
     }
 
-    /// Returns an iterator that yields the matching rules.
-    pub fn iter(&self) -> Matches<'s, 'r> {
-        Matches::new(self.scanner)
+    /// Returns a summary of the XOR keys and base64 variants found in the
+    /// matches of this rule.
+    ///
+    /// Only the patterns returned by [`Rule::patterns`] are taken into
+    /// account, private patterns are ignored.
+    pub fn match_stats(&self) -> MatchStats {
+        MatchStats::new(self.patterns().map(|pattern| pattern.matches()))
     }
 
-    /// Returns an iterator that yields the non-matching rules.
-    pub fn iter_non_matches(&self) -> NonMatches<'s, 'r> {
-        NonMatches::new(self.scanner)
+    /// Returns an iterator that yields the patterns defined by this rule.
+    ///
+    /// Patterns with the `private` modifier are not included.
+    pub fn patterns(&self) -> Patterns<'s, 'r> {
+        Patterns {
+            rules: self.rules,
+            ctx: self.ctx,
+            iterator: self.rule_info.patterns.iter(),
+            private_patterns: self.rule_info.private_patterns.as_slice(),
+        }
     }
 }
 
-/// Iterator that yields the rules that matched.
-pub struct Matches<'s, 'r> {
-    scanner: &'s Scanner<'r>,
-    iterator: Iter<'s, RuleId>,
+/// Iterator that yields the tags of a rule.
+pub struct Tags<'r> {
+    rules: &'r Rules,
+    iterator: Iter<'r, IdentId>,
 }
 
-impl<'s, 'r> Matches<'s, 'r> {
-    fn new(scanner: &'s Scanner<'r>) -> Self {
-        Self {
-            scanner,
-            iterator: scanner.wasm_store.data().rules_matching.iter(),
-        }
+impl<'r> Iterator for Tags<'r> {
+    type Item = &'r str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ident_id = self.iterator.next()?;
+        Some(self.rules.ident_pool().get(*ident_id).unwrap())
     }
 }
 
-impl<'s, 'r> Iterator for Matches<'s, 'r> {
-    type Item = Rule<'r>;
+/// Value of a metadata entry in a rule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetaValue<'r> {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    /// Metadata strings can contain arbitrary bytes, including zeroes.
+    String(&'r BStr),
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let rule_id = *self.iterator.next()?;
-        let rules = self.scanner.wasm_store.data().compiled_rules;
-        let rule_info = rules.get(rule_id);
+impl From<MetaValue<'_>> for MetaValueOutput {
+    fn from(value: MetaValue<'_>) -> Self {
+        match value {
+            MetaValue::Integer(i) => Self::Integer(i),
+            MetaValue::Float(f) => Self::Float(f),
+            MetaValue::Bool(b) => Self::Bool(b),
+            MetaValue::String(s) => Self::String(escape::escape(s)),
+        }
+    }
+}
 
-        Some(Rule { rule_info, rules })
+/// Strings are shown between double quotes and escaped with
+/// [`escape::escape`], so that they are safe to print.
+impl Display for MetaValue<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetaValue::Integer(i) => write!(f, "{}", i),
+            MetaValue::Float(v) => write!(f, "{}", v),
+            MetaValue::Bool(b) => write!(f, "{}", b),
+            MetaValue::String(s) => write!(f, "\"{}\"", escape::escape(s)),
+        }
     }
 }
 
-/// Iterator that yields the rules that didn't match.
-pub struct NonMatches<'s, 'r> {
-    scanner: &'s Scanner<'r>,
-    iterator: bitvec::slice::IterZeros<'s, u8, Lsb0>,
+/// Iterator that yields the metadata entries of a rule.
+pub struct Metadata<'r> {
+    rules: &'r Rules,
+    iterator: Iter<'r, (IdentId, compiler::MetaValue)>,
 }
 
-impl<'s, 'r> NonMatches<'s, 'r> {
-    fn new(scanner: &'s Scanner<'r>) -> Self {
-        let ctx = scanner.wasm_store.data();
-        let num_rules = ctx.compiled_rules.rules().len();
-        let main_memory =
-            ctx.main_memory.unwrap().data(scanner.wasm_store.as_context());
+impl<'r> Iterator for Metadata<'r> {
+    type Item = (&'r str, MetaValue<'r>);
 
-        let base = MATCHING_RULES_BITMAP_BASE as usize;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (ident_id, value) = self.iterator.next()?;
+        let value = match value {
+            compiler::MetaValue::Integer(i) => MetaValue::Integer(*i),
+            compiler::MetaValue::Float(f) => MetaValue::Float(*f),
+            compiler::MetaValue::Bool(b) => MetaValue::Bool(*b),
+            compiler::MetaValue::String(id) => {
+                MetaValue::String(self.rules.lit_pool().get(*id).unwrap())
+            }
+        };
+        Some((self.rules.ident_pool().get(*ident_id).unwrap(), value))
+    }
+}
 
-        // Create a BitSlice that covers the region of main memory containing
-        // the bitmap that tells which rules matched and which did not.
-        let matching_rules_bitmap = BitSlice::<_, Lsb0>::from_slice(
-            &main_memory[base..base + num_rules / 8 + 1],
-        );
+/// Iterator that yields the patterns defined by a rule.
+pub struct Patterns<'s, 'r> {
+    rules: &'r Rules,
+    ctx: Option<&'s ScanContext<'r>>,
+    iterator: Iter<'r, (IdentId, PatternId)>,
+    private_patterns: &'r [PatternId],
+}
 
-        // The BitSlice will cover more bits than necessary, for example, if
-        // there are 3 rules the BitSlice will have 8 bits because it is
-        // created from a u8 slice that has 1 byte. Here we make sure that
-        // the BitSlice has exactly as many bits as existing rules.
-        let matching_rules_bitmap = &matching_rules_bitmap[0..num_rules];
+impl<'s, 'r> Iterator for Patterns<'s, 'r> {
+    type Item = Pattern<'s, 'r>;
 
-        Self { scanner, iterator: matching_rules_bitmap.iter_zeros() }
+    fn next(&mut self) -> Option<Self::Item> {
+        let (ident_id, pattern_id) =
+            self.iterator.find(|(_, pattern_id)| {
+                !self.private_patterns.contains(pattern_id)
+            })?;
+        Some(Pattern {
+            rules: self.rules,
+            ctx: self.ctx,
+            ident_id: *ident_id,
+            pattern_id: *pattern_id,
+        })
     }
 }
 
-impl<'s, 'r> Iterator for NonMatches<'s, 'r> {
-    type Item = Rule<'r>;
+/// A structure that describes a pattern defined by some rule.
+pub struct Pattern<'s, 'r> {
+    rules: &'r Rules,
+    ctx: Option<&'s ScanContext<'r>>,
+    ident_id: IdentId,
+    pattern_id: PatternId,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let rule_id = RuleId::from(self.iterator.next()?);
-        let rules = self.scanner.wasm_store.data().compiled_rules;
-        let rule_info = rules.get(rule_id);
+impl<'s, 'r> Pattern<'s, 'r> {
+    /// Returns the pattern's identifier (e.g: $a, $b).
+    pub fn identifier(&self) -> &'r str {
+        self.rules.ident_pool().get(self.ident_id).unwrap()
+    }
+
+    /// Returns the pattern's ID.
+    ///
+    /// See [`PatternId`] for the guarantees about IDs.
+    pub fn id(&self) -> PatternId {
+        self.pattern_id
+    }
 
-        Some(Rule { rule_info, rules })
+    /// Returns the matches found for this pattern, sorted by offset.
+    ///
+    /// The result is empty if the rule is not associated to a scan. The
+    /// matches are stored in the scanner, like the rest of the
+    /// [`ScanResults`], and they can't outlive the next scan.
+    ///
+    /// ```compile_fail,E0499
+    /// # use yara_x::{compile, Scanner};
+    /// let rules = compile(r#"rule test { strings: $a = "foo" condition: $a }"#)
+    ///     .unwrap();
+    /// let mut scanner = Scanner::new(&rules);
+    /// let results = scanner.scan(b"foo");
+    /// let rule = results.iter().next().unwrap();
+    /// let matches = rule.patterns().next().unwrap().matches();
+    /// scanner.scan(b"bar");
+    /// assert_eq!(matches.len(), 1);
+    /// ```
+    pub fn matches(&self) -> &'s [Match] {
+        self.ctx
+            .and_then(|ctx| ctx.pattern_matches.get(&self.pattern_id))
+            .map(|matches| matches.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if the pattern reached the maximum number of matches,
+    /// in which case [`Pattern::matches`] doesn't include all of them. See
+    /// [`Scanner::set_max_matches_per_pattern`].
+    pub fn matches_truncated(&self) -> bool {
+        self.ctx
+            .and_then(|ctx| ctx.pattern_matches.get(&self.pattern_id))
+            .is_some_and(|matches| matches.is_truncated())
     }
 }
 
 pub(crate) type RuntimeStringId = u32;
 
+/// Function that provides the data for a module registered with
+/// [`crate::Compiler::register_module`], see [`Scanner::set_module_handler`].
+pub(crate) type ModuleHandler<'r> =
+    Box<dyn Fn(&[u8]) -> Box<dyn MessageDyn> + Send + 'r>;
+
 /// Structure that holds information about the current scan.
 pub(crate) struct ScanContext<'r> {
     /// Pointer to the WASM store.
-    wasm_store: NonNull<Store<ScanContext<'r>>>,
-    /// Pointer to the data being scanned.
+    pub(crate) wasm_store: NonNull<Store<ScanContext<'r>>>,
+    /// Pointer to the data being scanned. In streaming scans this is only
+    /// the part of the data that is still available, see
+    /// [`ScanContext::stream_offset`].
     scanned_data: *const u8,
     /// Length of data being scanned.
     scanned_data_len: usize,
-    /// Vector containing the IDs of the rules that matched.
+    /// In streaming scans, the offset within the whole data where
+    /// `scanned_data` starts. [`None`] if the scan is not a streaming scan,
+    /// see [`StreamScanner`].
+    pub(crate) stream_offset: Option<usize>,
+    /// Vector containing the IDs of the rules that matched, excluding
+    /// private rules.
     pub(crate) rules_matching: Vec<RuleId>,
+    /// Vector containing the IDs of the private rules that matched.
+    pub(crate) private_rules_matching: Vec<RuleId>,
     /// True if some pattern has been found. This is simply a flag that
     /// indicates that the bitmap that tells which patterns has matched
     /// needs to be cleared.
     pub(crate) patterns_found: bool,
     /// Compiled rules for this scan.
     pub(crate) compiled_rules: &'r Rules,
+    /// Callback that receives the events of the current scan, only set
+    /// during scans started with [`Scanner::scan_with_callback`].
+    pub(crate) callback: Option<NonNull<ScanCallback<'r>>>,
+    /// True if the callback aborted the current scan.
+    pub(crate) scan_aborted: bool,
+    /// Matching rules for which [`ScanEvent::RuleMatched`] was not sent
+    /// yet, because some global rule in the same namespace was not
+    /// evaluated yet.
+    pub(crate) pending_rule_matches: Vec<RuleId>,
     /// Structure that contains top-level symbols, like module names
     /// and external variables. Symbols are normally looked up in this
     /// structure, except if `current_struct` is set to some other
@@ -405,23 +2189,202 @@ pub(crate) struct ScanContext<'r> {
     pub(crate) root_struct: Struct,
     /// Currently active structure that overrides the `root_struct` if
     /// set.
-    pub(crate) current_struct: Option<Rc<Struct>>,
+    pub(crate) current_struct: Option<Arc<Struct>>,
     /// String pool where the strings produced at runtime are stored. This
     /// for example stores the strings returned by YARA modules.
     pub(crate) string_pool: BStringPool<RuntimeStringId>,
     /// Module's main memory.
     pub(crate) main_memory: Option<wasmtime::Memory>,
+    /// Matches found for each pattern. Patterns that didn't match don't
+    /// have an entry in this map.
+    pub(crate) pattern_matches: FxHashMap<PatternId, MatchList>,
+    /// Empty lists of matches that are reused in the next scan, see
+    /// [`Scanner::set_max_retained_capacity`].
+    pub(crate) free_match_lists: Vec<MatchList>,
+    /// Time spent on each rule, [`None`] if profiling is disabled. See
+    /// [`Scanner::set_profiling`].
+    pub(crate) profiler: Option<Profiler>,
+    /// Rules selected by the filter passed to
+    /// [`Scanner::scan_with_filter`], indexed by [`RuleId`]. [`None`] if
+    /// all the rules are selected.
+    pub(crate) selected_rules: Option<BitVec>,
     /// The host-side stack of local variables.
     ///
     /// See [`crate::compiler::Context::new_var`] for a more detailed
     /// description of what is this, and what "host-side" means in this
     /// case.
     pub(crate) vars_stack: Vec<TypeValue>,
+    /// See [`Scanner::set_max_retained_capacity`].
+    pub(crate) max_retained_capacity: usize,
+    /// See [`Scanner::set_max_matches_per_pattern`].
+    pub(crate) max_matches_per_pattern: usize,
+    /// See [`Scanner::set_base_offset`].
+    pub(crate) base_offset: u64,
+    /// See [`Scanner::set_fast_mode`].
+    pub(crate) fast_mode: bool,
+    /// Handlers set with [`Scanner::set_module_handler`], keyed by module
+    /// name.
+    pub(crate) module_handlers: FxHashMap<String, ModuleHandler<'r>>,
+    /// Data set with [`Scanner::set_module_output`] for the next scan,
+    /// keyed by module name.
+    pub(crate) module_outputs: FxHashMap<String, Box<dyn MessageDyn>>,
+    /// Errors found while obtaining the data of the modules in the current
+    /// scan, see [`ScanResults::module_errors`].
+    pub(crate) module_errors: Vec<ScanError>,
+    /// Callback that receives the messages logged by the `console` module,
+    /// see [`Scanner::console_log`].
+    #[cfg(feature = "console-module")]
+    pub(crate) console_log: Option<Box<dyn FnMut(String) + Send + 'r>>,
+    /// Digests computed by the `hash` module during the current scan.
+    #[cfg(feature = "hash-module")]
+    pub(crate) hash_cache: modules::hash::Cache,
+    /// Import hash computed by the `pe` module during the current scan.
+    #[cfg(feature = "pe-module")]
+    pub(crate) imphash_cache: modules::pe::Cache,
+    /// Clock that provides the value returned by `time.now()`, see
+    /// [`Scanner::set_clock`].
+    #[cfg(feature = "time-module")]
+    pub(crate) clock: Box<dyn Clock + Send + 'r>,
+    /// State used by the memory checks, see [`wasm::memory_checks`].
+    #[cfg(any(test, feature = "memory-checks"))]
+    pub(crate) memory_checks: wasm::memory_checks::State,
 }
 
-impl ScanContext<'_> {
+// SAFETY: `ScanContext` is not `Send` only because of the raw pointers
+// it contains. Every other field is `Send` by itself: the closures in
+// `module_handlers`, `console_log` and `callback`, as well as `clock`, are
+// required to be `Send`, and `module_outputs` holds `MessageDyn` values, which are `Send`
+// too. As for the pointers:
+//
+// * `wasm_store` points to the store that owns this `ScanContext`. Both
+//   are owned by the same `Scanner` and move together with it to another
+//   thread, and the pointer is never shared outside the scanner.
+//
+// * `scanned_data` points to data borrowed by the scan function that is
+//   running, and is reset to null before that function returns. As the
+//   scanner is mutably borrowed during the scan, it can't be sent to
+//   another thread while the pointer is valid.
+//
+// * `callback` points to a `Send` closure borrowed by
+//   `Scanner::scan_with_callback`, and is removed from the context when
+//   that function returns or unwinds, so the same argument applies.
+unsafe impl Send for ScanContext<'_> {}
+
+impl<'r> ScanContext<'r> {
+    /// Returns the layout of module's main memory.
+    pub(crate) fn memory_layout(&self) -> MemoryLayout {
+        MemoryLayout::new(
+            self.compiled_rules.rules().len(),
+            self.compiled_rules.num_patterns(),
+        )
+    }
+
+    /// Returns the number of bytes allocated for storing information about
+    /// scans, see [`ScannerStats::retained_capacity`].
+    pub(crate) fn retained_capacity(&self) -> usize {
+        let matches: usize = self
+            .pattern_matches
+            .values()
+            .chain(self.free_match_lists.iter())
+            .map(|list| list.capacity() * mem::size_of::<Match>())
+            .sum();
+
+        matches
+            + self.pattern_matches.capacity()
+                * mem::size_of::<(PatternId, MatchList)>()
+            + self.free_match_lists.capacity() * mem::size_of::<MatchList>()
+            + (self.rules_matching.capacity()
+                + self.private_rules_matching.capacity())
+                * mem::size_of::<RuleId>()
+            + self.vars_stack.capacity() * mem::size_of::<TypeValue>()
+    }
+
+    /// Releases at least `excess` bytes of the memory counted by
+    /// [`ScanContext::retained_capacity`], if possible, and keeps the rest.
+    ///
+    /// This is called between scans, when the containers are empty. The
+    /// lists of matches are released first, as they are the largest ones
+    /// and their size depends on the scanned data.
+    fn release_capacity(&mut self, mut excess: usize) {
+        while excess > 0 {
+            let list = match self.free_match_lists.last_mut() {
+                Some(list) => list,
+                None => break,
+            };
+            let size = mem::size_of::<Match>();
+            let before = list.capacity();
+            list.shrink_to(before.saturating_sub(excess.div_ceil(size)));
+            excess = excess.saturating_sub((before - list.capacity()) * size);
+            if list.capacity() > 0 {
+                break;
+            }
+            self.free_match_lists.pop();
+        }
+
+        shrink_vec(&mut self.free_match_lists, &mut excess);
+
+        let before = self.pattern_matches.capacity();
+        if excess > 0 && before > 0 {
+            let size = mem::size_of::<(PatternId, MatchList)>();
+            let target = before.saturating_sub(excess.div_ceil(size));
+            // The capacity of the hash map is rounded up, so it may end up
+            // larger than the target.
+            let mut min_capacity = target;
+            loop {
+                self.pattern_matches.shrink_to(min_capacity);
+                if self.pattern_matches.capacity() <= target
+                    || min_capacity == 0
+                {
+                    break;
+                }
+                min_capacity /= 2;
+            }
+            excess = excess.saturating_sub(
+                (before - self.pattern_matches.capacity()) * size,
+            );
+        }
+
+        shrink_vec(&mut self.rules_matching, &mut excess);
+        shrink_vec(&mut self.private_rules_matching, &mut excess);
+        shrink_vec(&mut self.vars_stack, &mut excess);
+    }
+
     /// An slice with the data being scanned.
+    ///
+    /// In streaming scans the data is never available as a whole, and this
+    /// returns an empty slice. Use [`ScanContext::scanned_data_in`] for
+    /// accessing the data at some specific offset.
     pub(crate) fn scanned_data<'a>(&self) -> &'a [u8] {
+        if self.stream_offset.is_some() {
+            return &[];
+        }
+        self.available_data()
+    }
+
+    /// Returns the bytes of the scanned data within `range`, or [`None`]
+    /// if they are not available.
+    ///
+    /// In streaming scans only the last bytes of the data are retained, and
+    /// any range that includes bytes that were discarded is not available.
+    pub(crate) fn scanned_data_in<'a>(
+        &self,
+        range: Range<usize>,
+    ) -> Option<&'a [u8]> {
+        let offset = self.stream_offset.unwrap_or(0);
+        let start = range.start.checked_sub(offset)?;
+        let end = range.end.checked_sub(offset)?;
+        self.available_data().get(start..end)
+    }
+
+    /// Returns the size of the scanned data, including the data that is
+    /// not available anymore in streaming scans.
+    pub(crate) fn scanned_data_size(&self) -> usize {
+        self.stream_offset.unwrap_or(0) + self.scanned_data_len
+    }
+
+    /// An slice with the part of the scanned data that is available. This
+    /// is the whole data, except in streaming scans.
+    fn available_data<'a>(&self) -> &'a [u8] {
         unsafe {
             std::slice::from_raw_parts::<u8>(
                 self.scanned_data,
@@ -430,33 +2393,296 @@ impl ScanContext<'_> {
         }
     }
 
+    /// Called after evaluating all the rules. If some global rule didn't
+    /// match, the rules in the same namespace are removed from the list of
+    /// matching rules, and their bits in the matching rules bitmap are
+    /// cleared.
+    pub(crate) fn apply_global_rules(&mut self) {
+        let rules = self.compiled_rules;
+        let layout = self.memory_layout();
+        let wasm_store = unsafe { self.wasm_store.as_mut() };
+        let main_mem = self.main_memory.unwrap().data_mut(wasm_store);
+
+        let bits = BitSlice::<u8, Lsb0>::from_slice_mut(
+            &mut main_mem[layout.matching_rules_bitmap()],
+        );
+
+        let failed_namespaces: Vec<IdentId> = rules
+            .global_rules()
+            .iter()
+            .filter(|rule_id| !bits[usize::from(**rule_id)])
+            .map(|rule_id| rules.get(*rule_id).namespace_id)
+            .collect();
+
+        if failed_namespaces.is_empty() {
+            return;
+        }
+
+        for rules_matching in
+            [&mut self.rules_matching, &mut self.private_rules_matching]
+        {
+            rules_matching.retain(|rule_id| {
+                let namespace_id = rules.get(*rule_id).namespace_id;
+                if failed_namespaces.contains(&namespace_id) {
+                    bits.set(usize::from(*rule_id), false);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    /// Called after evaluating all the rules in a scan started with
+    /// [`Scanner::scan_with_filter`]. The rules that were evaluated only
+    /// because some selected rule depends on them are removed from the
+    /// list of matching rules, and their bits in the matching rules bitmap
+    /// are cleared.
+    pub(crate) fn apply_rule_selection(&mut self) {
+        let Some(selected_rules) = self.selected_rules.as_ref() else {
+            return;
+        };
+
+        let layout = self.memory_layout();
+        let wasm_store = unsafe { self.wasm_store.as_mut() };
+        let main_mem = self.main_memory.unwrap().data_mut(wasm_store);
+
+        let bits = BitSlice::<u8, Lsb0>::from_slice_mut(
+            &mut main_mem[layout.matching_rules_bitmap()],
+        );
+
+        for rules_matching in
+            [&mut self.rules_matching, &mut self.private_rules_matching]
+        {
+            rules_matching.retain(|rule_id| {
+                if selected_rules[usize::from(*rule_id)] {
+                    true
+                } else {
+                    bits.set(usize::from(*rule_id), false);
+                    false
+                }
+            });
+        }
+    }
+
+    /// Returns true if the rule was selected for the current scan. See
+    /// [`Scanner::scan_with_filter`].
+    pub(crate) fn is_rule_selected(&self, rule_id: RuleId) -> bool {
+        self.selected_rules
+            .as_ref()
+            .is_none_or(|selected| selected[usize::from(rule_id)])
+    }
+
     /// Called during the scan process when a rule has matched for tracking
     /// the matching rules.
     pub(crate) fn track_rule_match(&mut self, rule_id: RuleId) {
-        // Store the RuleId in the vector of matching rules.
-        self.rules_matching.push(rule_id);
+        // Store the RuleId in the vector of matching rules. Private rules
+        // are kept apart, as they are not reported in the results.
+        if self.compiled_rules.get(rule_id).private {
+            self.private_rules_matching.push(rule_id);
+        } else {
+            self.rules_matching.push(rule_id);
+        }
 
+        let layout = self.memory_layout();
         let wasm_store = unsafe { self.wasm_store.as_mut() };
         let main_mem = self.main_memory.unwrap().data_mut(wasm_store);
 
-        let base = MATCHING_RULES_BITMAP_BASE as usize;
-        let bits = BitSlice::<u8, Lsb0>::from_slice_mut(&mut main_mem[base..]);
+        let bits = BitSlice::<u8, Lsb0>::from_slice_mut(
+            &mut main_mem[layout.matching_rules_bitmap()],
+        );
 
         // The RuleId-th bit in the `rule_matches` bit vector is set to 1.
         bits.set(rule_id.into(), true);
+
+        let rule_info = self.compiled_rules.get(rule_id);
+
+        if self.callback.is_none()
+            || rule_info.private
+            || !self.is_rule_selected(rule_id)
+        {
+            return;
+        }
+
+        // The match is not sent while it may be discarded by some global
+        // rule that was not evaluated yet.
+        match self.global_rules_matched(rule_info.namespace_id, rule_id) {
+            Some(true) => {
+                let rule = Rule::new(self.compiled_rules, Some(self), rule_id);
+                if self.send_event(ScanEvent::RuleMatched(rule)) {
+                    self.abort_scan();
+                }
+            }
+            Some(false) => {}
+            None => self.pending_rule_matches.push(rule_id),
+        }
+    }
+
+    /// Called after evaluating the condition of a rule, only if profiling
+    /// is enabled or the scan was started with
+    /// [`Scanner::scan_with_callback`].
+    pub(crate) fn rule_evaluated(&mut self, rule_id: RuleId) {
+        if self.callback.is_none() || !self.is_rule_selected(rule_id) {
+            return;
+        }
+
+        let rules = self.compiled_rules;
+        let rule = Rule::new(rules, Some(self), rule_id);
+
+        if self.send_event(ScanEvent::RuleEvaluated(rule)) {
+            self.abort_scan();
+            return;
+        }
+
+        let rule_info = rules.get(rule_id);
+
+        if !rule_info.global {
+            return;
+        }
+
+        let namespace_id = rule_info.namespace_id;
+
+        match self.global_rules_matched(namespace_id, rule_id) {
+            Some(true) => {
+                // All the global rules in the namespace matched, the
+                // pending matches in the namespace are not discarded.
+                let pending = mem::take(&mut self.pending_rule_matches);
+                for pending_rule_id in pending {
+                    if rules.get(pending_rule_id).namespace_id != namespace_id
+                    {
+                        self.pending_rule_matches.push(pending_rule_id);
+                        continue;
+                    }
+                    let rule = Rule::new(rules, Some(self), pending_rule_id);
+                    if self.send_event(ScanEvent::RuleMatched(rule)) {
+                        self.abort_scan();
+                        return;
+                    }
+                }
+            }
+            Some(false) => {
+                // Only the first global rule that fails is sent, after that
+                // no rule in the namespace can match. Rules are evaluated in
+                // ID order, so the first global rule that didn't match is
+                // the first one that failed.
+                let first_failed =
+                    rules.global_rules().iter().find(|global_rule_id| {
+                        rules.get(**global_rule_id).namespace_id
+                            == namespace_id
+                            && !self.rule_matched(**global_rule_id)
+                    });
+
+                if first_failed == Some(&rule_id)
+                    && self.send_event(ScanEvent::GlobalRuleFailed(Rule::new(
+                        rules,
+                        Some(self),
+                        rule_id,
+                    )))
+                {
+                    self.abort_scan();
+                    return;
+                }
+                self.pending_rule_matches.retain(|pending_rule_id| {
+                    rules.get(*pending_rule_id).namespace_id != namespace_id
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Returns `Some(false)` if some global rule in the given namespace,
+    /// with an ID lower or equal than `last`, didn't match. Otherwise,
+    /// returns [`None`] if the namespace contains global rules with IDs
+    /// greater than `last`, or `Some(true)` if it doesn't. As rules are
+    /// evaluated in ID order, this tells whether the global rules
+    /// evaluated up to the rule `last` failed, and whether there are
+    /// global rules that were not evaluated yet.
+    fn global_rules_matched(
+        &self,
+        namespace_id: IdentId,
+        last: RuleId,
+    ) -> Option<bool> {
+        let mut result = Some(true);
+
+        for rule_id in self.compiled_rules.global_rules() {
+            if self.compiled_rules.get(*rule_id).namespace_id != namespace_id {
+                continue;
+            }
+            if usize::from(*rule_id) > usize::from(last) {
+                result = None;
+            } else if !self.rule_matched(*rule_id) {
+                return Some(false);
+            }
+        }
+
+        result
+    }
+
+    /// Returns true if the rule's bit is set in the matching rules bitmap.
+    fn rule_matched(&self, rule_id: RuleId) -> bool {
+        let layout = self.memory_layout();
+        let wasm_store = unsafe { self.wasm_store.as_ref() };
+        let main_mem = self.main_memory.unwrap().data(wasm_store);
+
+        BitSlice::<u8, Lsb0>::from_slice(
+            &main_mem[layout.matching_rules_bitmap()],
+        )[usize::from(rule_id)]
+    }
+
+    /// Sends an event to the callback passed to
+    /// [`Scanner::scan_with_callback`], if any. Returns true if the
+    /// callback asked for aborting the scan. Nothing is sent after the
+    /// scan is aborted.
+    fn send_event(&self, event: ScanEvent<'_, 'r>) -> bool {
+        match self.callback {
+            Some(mut callback) if !self.scan_aborted => {
+                // SAFETY: The callback is valid during the scan, see
+                // `Scanner::scan_with_callback`, and it's not called
+                // recursively.
+                let callback = unsafe { callback.as_mut() };
+                callback(event) == CallbackAction::Abort
+            }
+            _ => false,
+        }
+    }
+
+    /// Aborts the current scan. The selected rules bitmap is cleared, so
+    /// the `main` function doesn't evaluate any other rule. The bitmap is
+    /// restored by the next scan.
+    fn abort_scan(&mut self) {
+        self.scan_aborted = true;
+        self.pending_rule_matches.clear();
+
+        let layout = self.memory_layout();
+        let wasm_store = unsafe { self.wasm_store.as_mut() };
+        let main_mem = self.main_memory.unwrap().data_mut(wasm_store);
+
+        main_mem[layout.selected_rules_bitmap()].fill(0);
     }
 
     /// Called during the scan process when a pattern has matched for tracking
     /// the matching patterns.
-    pub(crate) fn track_pattern_match(&mut self, pattern_id: PatternId) {
+    pub(crate) fn track_pattern_match(
+        &mut self,
+        pattern_id: PatternId,
+        m: Match,
+    ) {
         self.patterns_found = true;
 
+        let free_match_lists = &mut self.free_match_lists;
+
+        self.pattern_matches
+            .entry(pattern_id)
+            .or_insert_with(|| free_match_lists.pop().unwrap_or_default())
+            .add(m, self.max_matches_per_pattern);
+
+        let layout = self.memory_layout();
         let wasm_store = unsafe { self.wasm_store.as_mut() };
         let main_mem = self.main_memory.unwrap().data_mut(wasm_store);
-        let num_rules = self.compiled_rules.rules().len();
 
-        let base = MATCHING_RULES_BITMAP_BASE as usize + num_rules / 8 + 1;
-        let bits = BitSlice::<u8, Lsb0>::from_slice_mut(&mut main_mem[base..]);
+        let bits = BitSlice::<u8, Lsb0>::from_slice_mut(
+            &mut main_mem[layout.matching_patterns_bitmap()],
+        );
 
         bits.set(pattern_id.into(), true);
     }
@@ -473,9 +2699,29 @@ impl ScanContext<'_> {
     /// without looking for any of the patterns. If it must be called, it will be
     /// called only once.
     pub(crate) fn search_for_patterns(&mut self) {
+        // In streaming scans the patterns are searched for while the data
+        // is received, see `StreamScanner::feed`.
+        if self.stream_offset.is_none() {
+            self.search_for_patterns_in_window(true);
+        }
+    }
+
+    /// Search for patterns in the available data, see
+    /// [`ScanContext::available_data`].
+    ///
+    /// In streaming scans, `last` is false if more data will follow. In that
+    /// case, matches that are too close to the end of the available data
+    /// for deciding if they are `fullword` are ignored. They are found
+    /// again with the following data, as the end of the available data is
+    /// retained, see [`StreamScanner`]. The same happens with matches that
+    /// are too close to the start of the available data, except at the
+    /// start of the stream, as they were found with the previous data.
+    pub(crate) fn search_for_patterns_in_window(&mut self, last: bool) {
         let ac = self.compiled_rules.aho_corasick();
+        let search_start = self.profiler.is_some().then(Instant::now);
+        let offset = self.stream_offset.unwrap_or(0);
 
-        for atom_match in ac.find_overlapping_iter(self.scanned_data()) {
+        for atom_match in ac.find_overlapping_iter(self.available_data()) {
             let matched_atom =
                 &self.compiled_rules.atoms()[atom_match.pattern()];
 
@@ -494,20 +2740,41 @@ impl ScanContext<'_> {
                 .compiled_rules
                 .get_sub_pattern(matched_atom.sub_pattern_id);
 
-            let match_verified = match sub_pattern {
-                SubPattern::Fixed(pattern_lit_id) => self.verify_fixed_match(
-                    match_start,
-                    *pattern_lit_id,
-                    false,
-                ),
-                SubPattern::FixedCaseInsensitive(pattern_lit_id) => {
-                    self.verify_fixed_match(match_start, *pattern_lit_id, true)
+            // In fast mode, once a pattern has matched there's no need to
+            // verify more matches, unless some condition needs them.
+            if self.fast_mode
+                && self.pattern_matches.contains_key(pattern_id)
+                && !self.compiled_rules.all_matches_required(*pattern_id)
+            {
+                continue;
+            }
+
+            let verification_start = search_start.map(|_| Instant::now());
+
+            let verified_match = match sub_pattern {
+                SubPattern::Fixed(pattern_lit_id, flags) => self
+                    .verify_fixed_match(
+                        match_start,
+                        *pattern_lit_id,
+                        *flags,
+                        false,
+                    ),
+                SubPattern::FixedCaseInsensitive(pattern_lit_id, flags) => {
+                    self.verify_fixed_match(
+                        match_start,
+                        *pattern_lit_id,
+                        *flags,
+                        true,
+                    )
                 }
-                SubPattern::Xor(pattern_lit_id) => self.verify_xor_match(
-                    match_start,
-                    matched_atom,
-                    *pattern_lit_id,
-                ),
+                SubPattern::Xor(pattern_lit_id, key_range, flags) => self
+                    .verify_xor_match(
+                        match_start,
+                        matched_atom,
+                        *pattern_lit_id,
+                        key_range,
+                        *flags,
+                    ),
                 SubPattern::Base64(id, padding)
                 | SubPattern::Base64Wide(id, padding) => self
                     .verify_base64_match(
@@ -548,9 +2815,31 @@ impl ScanContext<'_> {
                 }
             };
 
-            if match_verified {
-                self.track_pattern_match(*pattern_id);
+            if let (Some(profiler), Some(start)) =
+                (self.profiler.as_mut(), verification_start)
+            {
+                profiler.pattern_verified(*pattern_id, start.elapsed());
+            }
+
+            let Some(mut m) = verified_match else {
+                continue;
+            };
+
+            if (!last
+                && m.range.end + FULLWORD_CONTEXT > self.scanned_data_len)
+                || (offset > 0 && m.range.start < FULLWORD_CONTEXT)
+            {
+                continue;
             }
+
+            m.range = m.range.start + offset..m.range.end + offset;
+            self.track_pattern_match(*pattern_id, m);
+        }
+
+        if let (Some(profiler), Some(start)) =
+            (self.profiler.as_mut(), search_start)
+        {
+            profiler.pattern_search_done(start.elapsed());
         }
     }
 
@@ -558,22 +2847,36 @@ impl ScanContext<'_> {
         &self,
         match_start: usize,
         pattern_id: LiteralId,
+        flags: SubPatternFlags,
         case_insensitive: bool,
-    ) -> bool {
+    ) -> Option<Match> {
         let pattern = self.compiled_rules.lit_pool().get(pattern_id).unwrap();
+        let range = match_start..match_start + pattern.len();
 
-        if self.scanned_data_len < match_start + pattern.len() {
-            return false;
+        if self.scanned_data_len < range.end {
+            return None;
         }
 
-        let data =
-            &self.scanned_data()[match_start..match_start + pattern.len()];
+        let data = &self.available_data()[range.clone()];
 
-        if case_insensitive {
+        let verified = if case_insensitive {
             pattern.eq_ignore_ascii_case(data)
         } else {
             memx::memeq(data, pattern.as_bytes())
+        };
+
+        if !verified
+            || (flags.fullword && !self.is_fullword(&range, flags.wide, 0))
+        {
+            return None;
         }
+
+        Some(Match {
+            range,
+            xor_key: None,
+            base64: None,
+            base_offset: self.base_offset,
+        })
     }
 
     fn verify_xor_match(
@@ -581,11 +2884,14 @@ impl ScanContext<'_> {
         match_start: usize,
         matched_atom: &AtomInfo,
         pattern_id: LiteralId,
-    ) -> bool {
+        key_range: &RangeInclusive<u8>,
+        flags: SubPatternFlags,
+    ) -> Option<Match> {
         let pattern = self.compiled_rules.lit_pool().get(pattern_id).unwrap();
+        let range = match_start..match_start + pattern.len();
 
-        if self.scanned_data_len < match_start + pattern.len() {
-            return false;
+        if self.scanned_data_len < range.end {
+            return None;
         }
 
         let mut pattern = pattern.to_owned();
@@ -596,6 +2902,10 @@ impl ScanContext<'_> {
         let key = matched_atom.atom.as_ref()[0]
             ^ pattern[matched_atom.atom.backtrack as usize];
 
+        // Atoms are generated only for the keys within the pattern's range,
+        // therefore the key obtained from the atom must be within that range.
+        debug_assert!(key_range.contains(&key));
+
         // Now we can XOR the whole pattern with the obtained key and make sure
         // that it matches the data. This only makes sense if the key is not
         // zero.
@@ -605,10 +2915,58 @@ impl ScanContext<'_> {
             }
         }
 
-        let data =
-            &self.scanned_data()[match_start..match_start + pattern.len()];
+        let data = &self.available_data()[range.clone()];
+
+        if !memx::memeq(data, pattern.as_bytes())
+            || (flags.fullword && !self.is_fullword(&range, flags.wide, key))
+        {
+            return None;
+        }
+
+        Some(Match {
+            range,
+            xor_key: Some(key),
+            base64: None,
+            base_offset: self.base_offset,
+        })
+    }
+
+    /// Returns true if the match at `range` is delimited by word boundaries,
+    /// which means that it is not immediately preceded or followed by an
+    /// alphanumeric character. The start and end of the scanned data are
+    /// word boundaries.
+    ///
+    /// In wide matches the characters that surround the match are two bytes
+    /// long, and they are alphanumeric if the first byte is alphanumeric and
+    /// the second one is zero. For matches found with the `xor` modifier,
+    /// the characters are decoded with the same key as the match before
+    /// being checked.
+    fn is_fullword(&self, range: &Range<usize>, wide: bool, key: u8) -> bool {
+        let data = self.available_data();
+        let is_alnum = |c: &[u8]| match c {
+            [c] => (c ^ key).is_ascii_alphanumeric(),
+            [c, z] => (c ^ key).is_ascii_alphanumeric() && z ^ key == 0,
+            _ => unreachable!(),
+        };
+        let char_len = if wide { 2 } else { 1 };
+
+        if let Some(prev) = range
+            .start
+            .checked_sub(char_len)
+            .map(|start| &data[start..range.start])
+        {
+            if is_alnum(prev) {
+                return false;
+            }
+        }
+
+        if let Some(next) = data.get(range.end..range.end + char_len) {
+            if is_alnum(next) {
+                return false;
+            }
+        }
 
-        memx::memeq(data, pattern.as_bytes())
+        true
     }
 
     fn verify_base64_match(
@@ -618,7 +2976,7 @@ impl ScanContext<'_> {
         pattern_id: LiteralId,
         alphabet: Option<base64::alphabet::Alphabet>,
         wide: bool,
-    ) -> bool {
+    ) -> Option<Match> {
         // The pattern is stored in its original form, not encoded as base64.
         let pattern = self.compiled_rules.lit_pool().get(pattern_id).unwrap();
 
@@ -667,11 +3025,11 @@ impl ScanContext<'_> {
         {
             adjusted_start..match_start + len - right_adjustment
         } else {
-            return false;
+            return None;
         };
 
         if range.end > self.scanned_data_len {
-            return false;
+            return None;
         }
 
         let base64_engine = base64::engine::GeneralPurpose::new(
@@ -683,22 +3041,30 @@ impl ScanContext<'_> {
             // Collect the ASCII characters at even positions and make sure
             // that bytes at odd positions are zeroes.
             let mut ascii = Vec::with_capacity(len / 2);
-            for (i, b) in self.scanned_data()[range].iter().enumerate() {
+            for (i, b) in
+                self.available_data()[range.clone()].iter().enumerate()
+            {
                 if i % 2 == 0 {
                     ascii.push(*b)
                 } else if *b != 0 {
-                    return false;
+                    return None;
                 }
             }
             base64_engine.decode(ascii.as_slice())
         } else {
-            base64_engine.decode(&self.scanned_data()[range])
+            base64_engine.decode(&self.available_data()[range.clone()])
         };
 
-        if let Ok(decoded) = decoded {
-            pattern.eq(&decoded[padding as usize..])
-        } else {
-            false
+        match decoded {
+            Ok(decoded) if pattern.eq(&decoded[padding as usize..]) => {
+                Some(Match {
+                    range,
+                    xor_key: None,
+                    base64: Some(Base64Variant { offset: padding, wide }),
+                    base_offset: self.base_offset,
+                })
+            }
+            _ => None,
         }
     }
 }
@@ -0,0 +1,158 @@
+/*! Owned representation of scan results.
+
+[`crate::ScanResults`] borrows from the scanner, which makes it unsuitable
+for storing the results or sending them somewhere else. The types in this
+module hold a copy of the same information. When the `serde` feature is
+enabled they implement `serde::Serialize`, and with the `json-schema`
+feature the schema of the resulting JSON is available in
+[`crate::schemas`].
+
+Any change in these types changes the JSON schema, see [`crate::schemas`]
+before modifying them.
+*/
+
+use crate::scanner::matches::{Base64Variant, MatchStats};
+
+/// Owned representation of the results of a scan.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct ScanOutput {
+    /// Rules that matched, in the same order they were declared.
+    pub matching_rules: Vec<RuleOutput>,
+}
+
+/// A rule that matched.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct RuleOutput {
+    /// Rule ID. IDs are assigned sequentially starting at 0, in the order
+    /// in which rules were compiled, and are meaningful only within the
+    /// same set of compiled rules.
+    pub id: usize,
+    /// Namespace the rule belongs to.
+    pub namespace: String,
+    /// Rule name.
+    pub name: String,
+    /// Rule tags, in the same order they were declared.
+    pub tags: Vec<String>,
+    /// Metadata entries, including the ones inherited from the defaults,
+    /// in the same order as [`crate::Rule::metadata`].
+    pub metadata: Vec<MetadataOutput>,
+    /// Patterns declared in the rule, in the same order they were declared.
+    pub patterns: Vec<PatternOutput>,
+    /// XOR keys and base64 variants found in the rule's matches.
+    pub match_stats: MatchStats,
+}
+
+/// A metadata entry of a matching rule.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct MetadataOutput {
+    /// Metadata identifier.
+    pub identifier: String,
+    /// Metadata value.
+    pub value: MetaValueOutput,
+}
+
+/// Value of a metadata entry.
+///
+/// In JSON it is represented by the value itself. Strings can contain
+/// arbitrary bytes, they are escaped with [`crate::escape::escape`] like
+/// [`MatchOutput::data`], and can be decoded with
+/// [`crate::escape::unescape`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub enum MetaValueOutput {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+/// A pattern declared in a matching rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct PatternOutput {
+    /// Pattern identifier, including the `$` prefix.
+    pub identifier: String,
+    /// Matches found for the pattern, sorted by offset. It may be empty.
+    pub matches: Vec<MatchOutput>,
+    /// True if the pattern had more matches than the limit set with
+    /// [`crate::Scanner::set_max_matches_per_pattern`], in which case
+    /// `matches` contains only the ones with the lowest offsets.
+    pub matches_truncated: bool,
+}
+
+/// A match found for a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct MatchOutput {
+    /// Offset within the scanned data where the match starts, plus the
+    /// base offset set with [`crate::Scanner::set_base_offset`]. In the
+    /// results of [`crate::Scanner::scan_process`] this is the virtual
+    /// address where the match starts.
+    pub offset: u64,
+    /// Length of the match in bytes.
+    pub length: usize,
+    /// XOR key that decodes the matching data, only for patterns with the
+    /// `xor` modifier.
+    pub xor_key: Option<u8>,
+    /// Base64 variant found in the matching data, only for patterns with
+    /// the `base64` or `base64wide` modifiers.
+    pub base64: Option<Base64Variant>,
+    /// Matching data, only when the output is created with
+    /// [`crate::ScanResults::to_owned_output_with_data`].
+    ///
+    /// The data is escaped with [`crate::escape::escape`], which produces
+    /// printable ASCII only, and can be decoded with
+    /// [`crate::escape::unescape`]. It may be truncated, in which case the
+    /// decoded data is shorter than `length`.
+    pub data: Option<String>,
+}
+
+/// Owned representation of the results of scanning the memory of a
+/// process, see [`crate::Scanner::scan_process`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct ProcessScanOutput {
+    /// Results for each memory region where some rule matched, sorted by
+    /// address.
+    pub regions: Vec<RegionOutput>,
+    /// Memory regions that were skipped because they became unreadable
+    /// during the scan, sorted by address.
+    pub skipped_regions: Vec<MemoryRegion>,
+}
+
+/// Results of scanning a memory region of a process.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct RegionOutput {
+    /// The memory region.
+    pub region: MemoryRegion,
+    /// Results of the scan. The offsets of the matches are virtual
+    /// addresses, not offsets within the region.
+    pub output: ScanOutput,
+}
+
+/// A memory region of a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
+pub struct MemoryRegion {
+    /// Virtual address where the region starts.
+    pub address: usize,
+    /// Size of the region in bytes.
+    pub size: usize,
+    /// Path of the file mapped in the region, or pseudo-path like `[heap]`
+    /// or `[stack]`. It's `None` for anonymous regions.
+    pub path: Option<String>,
+}
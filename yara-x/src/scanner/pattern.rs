@@ -0,0 +1,97 @@
+/*! Search for individual patterns outside of a rule.
+
+A pattern like `"foo" wide nocase` is compiled as the only pattern of an
+otherwise empty rule, and searched for by a regular [`Scanner`]. This means
+that patterns are validated and matched exactly in the same way as they
+would be when declared in a rule. Only text patterns are supported, as the
+scanner can't search for hex patterns and regular expressions yet.
+*/
+
+use crate::compiler::{Compiler, Error, Rules};
+use crate::scanner::{Match, Pattern, Scanner};
+use crate::PatternId;
+
+impl Pattern<'_, '_> {
+    /// Compiles a single text pattern, like `"foo" wide nocase`.
+    ///
+    /// The pattern is written exactly as in the `strings` section of a rule,
+    /// but without the identifier and the equal sign. Modifiers are
+    /// supported, and the pattern goes through the same validations as in
+    /// a rule. Hex patterns and regular expressions, like `{ 4D 5A }` or
+    /// `/foo[0-9]+/i`, are not supported yet and produce an
+    /// [`Error::UnsupportedPattern`].
+    ///
+    /// ```
+    /// # use yara_x::Pattern;
+    /// let pattern = Pattern::compile(r#""foo" wide"#).unwrap();
+    /// let matches: Vec<_> =
+    ///     pattern.find_iter(b"f\x00o\x00o\x00 foo").map(|m| m.range).collect();
+    ///
+    /// assert_eq!(matches, [0..6]);
+    /// ```
+    pub fn compile(pattern: &str) -> Result<CompiledPattern, Error> {
+        let src = format!(
+            "rule pattern {{\n  strings:\n    $a = {}\n  condition:\n    $a\n}}",
+            pattern
+        );
+
+        let mut compiler = Compiler::new();
+        compiler.add_source(src.as_str())?;
+        let rules = compiler.build()?;
+
+        // The source code must contain a single rule with a single pattern,
+        // anything else means that `pattern` was more than a pattern.
+        let pattern_id = match rules.rules() {
+            [rule] if rule.patterns.len() == 1 => rule.patterns[0].1,
+            _ => return Err(Error::InvalidPattern(pattern.to_string())),
+        };
+
+        if !rules.has_sub_patterns(pattern_id) {
+            return Err(Error::UnsupportedPattern(pattern.to_string()));
+        }
+
+        Ok(CompiledPattern { rules, pattern_id })
+    }
+}
+
+/// A pattern compiled with [`Pattern::compile`].
+pub struct CompiledPattern {
+    rules: Rules,
+    pattern_id: PatternId,
+}
+
+impl CompiledPattern {
+    /// Returns an iterator that yields the matches of the pattern in `data`,
+    /// sorted by offset.
+    ///
+    /// The matches are the same that the [`Scanner`] finds for a pattern
+    /// declared in a rule.
+    pub fn find_iter(&self, data: &[u8]) -> FindIter {
+        let mut scanner = Scanner::new(&self.rules);
+
+        scanner.scan(data);
+
+        let matches = scanner
+            .wasm_store
+            .data()
+            .pattern_matches
+            .get(&self.pattern_id)
+            .map(|matches| matches.as_slice().to_vec())
+            .unwrap_or_default();
+
+        FindIter { matches: matches.into_iter() }
+    }
+}
+
+/// Iterator returned by [`CompiledPattern::find_iter`].
+pub struct FindIter {
+    matches: std::vec::IntoIter<Match>,
+}
+
+impl Iterator for FindIter {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.matches.next()
+    }
+}
@@ -0,0 +1,121 @@
+/*! Access to the memory of a running process.
+
+See [`crate::Scanner::scan_process`]. Only Linux is supported, the memory
+regions are obtained from `/proc/<pid>/maps` and their content is read with
+`process_vm_readv`, which requires the same privileges as attaching to the
+process with `ptrace`.
+*/
+
+use std::fs;
+use std::io;
+
+use crate::scanner::{MemoryRegion, ScanError};
+
+/// Maximum number of bytes read from a process at once. Regions larger
+/// than this are read in blocks of this size, so that the memory needed
+/// for scanning a region doesn't depend on its size.
+pub(crate) const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Returns the readable memory regions of a process, sorted by address.
+pub(crate) fn readable_regions(
+    pid: u32,
+) -> Result<Vec<MemoryRegion>, ScanError> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid))
+        .map_err(|err| process_error(pid, err))?;
+
+    // Each line has the format:
+    //
+    // 7f2c3a1e5000-7f2c3a1e7000 r--p 00000000 08:01 1234   /usr/lib/foo.so
+    //
+    // The fields are the address range, the permissions, the offset within
+    // the mapped file, the device, the inode and the path, which is empty
+    // for anonymous mappings.
+    let mut regions = Vec::new();
+
+    for line in maps.lines() {
+        let mut fields = line.splitn(6, ' ');
+        let (Some(range), Some(perms)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        if !perms.starts_with('r') {
+            continue;
+        }
+        let Some((start, end)) = range.split_once('-') else {
+            continue;
+        };
+        let (Ok(start), Ok(end)) =
+            (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16))
+        else {
+            continue;
+        };
+        let path =
+            fields.nth(3).map(str::trim).filter(|path| !path.is_empty());
+
+        regions.push(MemoryRegion {
+            address: start,
+            size: end - start,
+            path: path.map(String::from),
+        });
+    }
+
+    Ok(regions)
+}
+
+/// Reads `size` bytes starting at `address` in the memory of a process
+/// into `buf`, replacing any previous content. `size` must not be larger
+/// than [`MAX_BLOCK_SIZE`].
+///
+/// Returns `Ok(false)` if the memory can't be read, which happens when the
+/// process unmaps it or changes its protection after the regions were
+/// obtained. Errors that affect the process as a whole, like not having
+/// permission for reading its memory, are returned as `Err`.
+pub(crate) fn read_block(
+    pid: u32,
+    address: usize,
+    size: usize,
+    buf: &mut Vec<u8>,
+) -> Result<bool, ScanError> {
+    debug_assert!(size <= MAX_BLOCK_SIZE);
+
+    buf.clear();
+    buf.resize(size, 0);
+
+    let local =
+        libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: size };
+
+    let remote =
+        libc::iovec { iov_base: address as *mut libc::c_void, iov_len: size };
+
+    // SAFETY: `local` describes the memory owned by `buf`, which has
+    // exactly `size` bytes. The remote memory is not accessed directly,
+    // the kernel checks that it's valid.
+    let read = unsafe {
+        libc::process_vm_readv(pid as libc::pid_t, &local, 1, &remote, 1, 0)
+    };
+
+    if read < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            // The memory is not mapped anymore, or is not readable.
+            Some(libc::EFAULT) | Some(libc::EIO) | Some(libc::ENOMEM) => {
+                Ok(false)
+            }
+            _ => Err(process_error(pid, err)),
+        };
+    }
+
+    // A partial read means that some part of the memory is not mapped
+    // anymore.
+    Ok(read as usize == size)
+}
+
+/// Converts an error that occurred while accessing a process into the
+/// corresponding [`ScanError`].
+fn process_error(pid: u32, err: io::Error) -> ScanError {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => {
+            ScanError::ProcessAccessDenied { pid }
+        }
+        _ => ScanError::ProcessError { pid, source: err },
+    }
+}
@@ -0,0 +1,84 @@
+/*! Measures the time spent on each rule during a scan.
+
+See [`crate::Scanner::set_profiling`].
+*/
+
+use std::time::{Duration, Instant};
+
+use crate::compiler::RuleId;
+use crate::scanner::Rule;
+use crate::PatternId;
+
+/// Time spent on a rule during a scan, see [`crate::ScanResults::profiling`].
+pub struct RuleProfile<'s, 'r> {
+    /// The rule.
+    pub rule: Rule<'s, 'r>,
+    /// Time spent evaluating the rule's condition. This doesn't include the
+    /// pattern search phase, even if it was triggered by this rule.
+    pub condition_time: Duration,
+    /// Time spent verifying the matches found for the rule's patterns,
+    /// including private patterns.
+    pub pattern_time: Duration,
+}
+
+impl RuleProfile<'_, '_> {
+    /// Returns the total time spent on the rule.
+    pub fn total_time(&self) -> Duration {
+        self.condition_time + self.pattern_time
+    }
+}
+
+/// Accumulates the time spent on each rule and pattern during a scan.
+pub(crate) struct Profiler {
+    /// Time spent evaluating the condition of each rule, indexed by
+    /// [`RuleId`].
+    pub(crate) condition_times: Vec<Duration>,
+    /// Time spent verifying the matches of each pattern, indexed by
+    /// [`PatternId`].
+    pub(crate) pattern_times: Vec<Duration>,
+    /// Instant when the evaluation of the current rule started.
+    checkpoint: Instant,
+}
+
+impl Profiler {
+    pub fn new(num_rules: usize, num_patterns: usize) -> Self {
+        Self {
+            condition_times: vec![Duration::ZERO; num_rules],
+            pattern_times: vec![Duration::ZERO; num_patterns],
+            checkpoint: Instant::now(),
+        }
+    }
+
+    /// Discards the times measured in a previous scan. Called when a new
+    /// scan starts, before searching for any pattern.
+    pub fn reset(&mut self) {
+        self.condition_times.fill(Duration::ZERO);
+        self.pattern_times.fill(Duration::ZERO);
+    }
+
+    /// Called right before evaluating the first rule.
+    pub fn start(&mut self) {
+        self.checkpoint = Instant::now();
+    }
+
+    /// Called after evaluating the condition of a rule.
+    ///
+    /// Rules are evaluated one after the other, so the time since the
+    /// previous rule was evaluated is the time spent on this one.
+    pub fn rule_evaluated(&mut self, rule_id: RuleId) {
+        let now = Instant::now();
+        self.condition_times[usize::from(rule_id)] += now - self.checkpoint;
+        self.checkpoint = now;
+    }
+
+    /// Called after verifying a match for a pattern.
+    pub fn pattern_verified(&mut self, pattern_id: PatternId, time: Duration) {
+        self.pattern_times[usize::from(pattern_id)] += time;
+    }
+
+    /// Called after the pattern search phase. This phase is executed while
+    /// evaluating some rule, but its time is not charged to that rule.
+    pub fn pattern_search_done(&mut self, time: Duration) {
+        self.checkpoint += time;
+    }
+}
@@ -0,0 +1,112 @@
+/*! Scanning of data that is received in chunks.
+
+See [`StreamScanner`].
+*/
+
+use std::ptr::null;
+
+use crate::scanner::{ScanResults, Scanner, FULLWORD_CONTEXT};
+
+/// Scans data that is received in chunks, like data read from a network
+/// connection, without keeping the whole data in memory.
+///
+/// A stream scanner is created with [`Scanner::stream`]. The data is passed
+/// to [`StreamScanner::feed`] in as many chunks as needed, and the rules are
+/// evaluated by [`StreamScanner::finish`] once all the data was received.
+///
+/// ```
+/// # use yara_x::{compile, Scanner};
+/// let rules = compile(r#"
+///     rule test {
+///         strings: $a = "foobar"
+///         condition: $a and filesize == 9
+///     }
+/// "#).unwrap();
+///
+/// let mut scanner = Scanner::new(&rules);
+/// let mut stream = scanner.stream();
+///
+/// stream.feed(b"xxxfoo").feed(b"bar");
+///
+/// assert_eq!(stream.finish().num_matching_rules(), 1);
+/// ```
+///
+/// Patterns are searched for as soon as each chunk is received, and they
+/// are found even if they span multiple chunks. The offsets of the matches
+/// are relative to the start of the whole data. However, only the last
+/// bytes of the data are retained, which affects other features:
+///
+/// * `filesize` is the size of the whole data, as in regular scans.
+///
+/// * Functions that read the data at some offset, like `uint32(offset)`,
+///   `hash.md5(offset, size)` or `math.entropy(offset, size)`, are
+///   undefined unless all the bytes they read were retained. The number of
+///   retained bytes depends on the length of the longest pattern, so only
+///   the end of the data can be read, if any.
+///
+/// * Modules that parse the data, like `pe` and `elf`, receive empty data,
+///   as if the scanned data was empty. The `entrypoint` keyword is
+///   undefined.
+pub struct StreamScanner<'s, 'r> {
+    scanner: &'s mut Scanner<'r>,
+    /// Last bytes of the data received so far.
+    window: Vec<u8>,
+    /// Offset within the whole data where `window` starts.
+    window_offset: usize,
+    /// Number of bytes retained in `window` after each chunk. This is
+    /// enough for containing any match, together with the bytes around it
+    /// that decide if it's a `fullword` match.
+    retained: usize,
+}
+
+impl<'s, 'r> StreamScanner<'s, 'r> {
+    pub(crate) fn new(scanner: &'s mut Scanner<'r>) -> Self {
+        scanner.start_scan();
+
+        let retained =
+            scanner.wasm_store.data().compiled_rules.max_match_len()
+                + 2 * FULLWORD_CONTEXT;
+
+        Self { scanner, window: Vec::new(), window_offset: 0, retained }
+    }
+
+    /// Scans the next chunk of data.
+    pub fn feed(&mut self, chunk: &[u8]) -> &mut Self {
+        self.window.extend_from_slice(chunk);
+        self.search_for_patterns(false);
+
+        let discarded = self.window.len().saturating_sub(self.retained);
+
+        self.window.drain(..discarded);
+        self.window_offset += discarded;
+        self
+    }
+
+    /// Evaluates the rules once all the data was received.
+    pub fn finish(mut self) -> ScanResults<'s, 'r> {
+        self.search_for_patterns(true);
+
+        let ctx = self.scanner.wasm_store.data_mut();
+
+        ctx.scanned_data = self.window.as_ptr();
+        ctx.scanned_data_len = self.window.len();
+        ctx.stream_offset = Some(self.window_offset);
+
+        self.scanner.finish_scan(self.window_offset + self.window.len())
+    }
+
+    /// Searches for patterns in the data retained so far. `last` is true
+    /// if no more data will be received.
+    fn search_for_patterns(&mut self, last: bool) {
+        let ctx = self.scanner.wasm_store.data_mut();
+
+        ctx.scanned_data = self.window.as_ptr();
+        ctx.scanned_data_len = self.window.len();
+        ctx.stream_offset = Some(self.window_offset);
+
+        ctx.search_for_patterns_in_window(last);
+
+        ctx.scanned_data = null();
+        ctx.scanned_data_len = 0;
+    }
+}
@@ -1,9 +1,28 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bstr::BStr;
+use protobuf::descriptor::field_descriptor_proto::{Label, Type};
+use protobuf::descriptor::{
+    DescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+};
+use protobuf::reflect::{FileDescriptor, MessageDescriptor, ReflectValueBox};
+use protobuf::well_known_types::timestamp::Timestamp;
+use wasmtime::AsContextMut;
+
 use crate::compiler::Compiler;
-use crate::scanner::Scanner;
+use crate::escape::unescape;
+use crate::scanner::{
+    Base64Variant, Base64VariantStats, CallbackAction, Match, MatchOutput,
+    MatchStats, MetaValue, Pattern, PatternOutput, RuleOutput, ScanEvent,
+    Scanner, XorKeyStats,
+};
+use crate::Error;
+use crate::RuleId;
 
 #[test]
 fn iterators() {
-    let rules = Compiler::new()
+    let mut compiler = Compiler::new();
+    compiler
         .add_source(
             r#"
 rule rule_1 { condition: true }
@@ -12,9 +31,8 @@ rule rule_3 { condition: true }
 rule rule_4 { condition: false }
 "#,
         )
-        .unwrap()
-        .build()
         .unwrap();
+    let rules = compiler.build().unwrap();
 
     let mut scanner = Scanner::new(&rules);
     let results = scanner.scan(&[]);
@@ -33,3 +51,2855 @@ rule rule_4 { condition: false }
     assert_eq!(iter.next().unwrap().name(), "rule_4");
     assert!(iter.next().is_none());
 }
+
+#[test]
+fn rule_status() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+private rule private_1 { condition: true }
+private rule private_2 { condition: false }
+rule rule_1 { condition: private_1 }
+rule rule_2 { condition: private_2 }
+"#,
+        )
+        .unwrap()
+        .new_namespace("other")
+        .add_source(
+            r#"
+global rule global_1 { condition: false }
+rule rule_3 { condition: true }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(&[]);
+
+    fn names<'s, 'r: 's>(
+        rules: impl Iterator<Item = crate::Rule<'s, 'r>>,
+    ) -> Vec<&'r str> {
+        rules.map(|rule| rule.name()).collect()
+    }
+
+    assert_eq!(names(results.matching_rules()), ["rule_1"]);
+    assert_eq!(
+        names(results.matching_rules().include_private(true)),
+        ["private_1", "rule_1"]
+    );
+
+    // The global rule didn't match, so neither does `rule_3`.
+    assert_eq!(
+        names(results.non_matching_rules()),
+        ["rule_2", "global_1", "rule_3"]
+    );
+    assert_eq!(
+        names(results.non_matching_rules().include_private(true)),
+        ["private_2", "rule_2", "global_1", "rule_3"]
+    );
+
+    let matched = |name| {
+        let namespace = if name == "rule_3" { "other" } else { "default" };
+        results.matched(rules.rule_by_name(namespace, name).unwrap().id())
+    };
+
+    assert!(matched("private_1"));
+    assert!(!matched("private_2"));
+    assert!(matched("rule_1"));
+    assert!(!matched("rule_2"));
+    assert!(!matched("rule_3"));
+    assert!(!results.matched(RuleId::from(5)));
+}
+
+#[test]
+fn wide_and_ascii_matches() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  strings:
+    $a = "cmd" wide ascii
+  condition:
+    $a
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"cmd.exe c\x00m\x00d\x00.exe");
+
+    assert_eq!(results.num_matching_rules(), 1);
+
+    let rule = results.iter().next().unwrap();
+    let pattern = rule.patterns().next().unwrap();
+
+    assert_eq!(pattern.identifier(), "$a");
+    assert_eq!(
+        pattern.matches(),
+        &[
+            Match { range: 0..3, xor_key: None, base64: None, base_offset: 0 },
+            Match {
+                range: 8..14,
+                xor_key: None,
+                base64: None,
+                base_offset: 0
+            }
+        ]
+    );
+}
+
+#[test]
+fn fullword_matches() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  strings:
+    $a = "domain" fullword
+  condition:
+    $a
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"mydomain domain1 domain. domain");
+
+    assert_eq!(results.num_matching_rules(), 1);
+
+    let rule = results.iter().next().unwrap();
+    let pattern = rule.patterns().next().unwrap();
+
+    assert_eq!(
+        pattern.matches(),
+        &[
+            Match {
+                range: 17..23,
+                xor_key: None,
+                base64: None,
+                base_offset: 0
+            },
+            Match {
+                range: 25..31,
+                xor_key: None,
+                base64: None,
+                base_offset: 0
+            }
+        ]
+    );
+}
+
+#[test]
+fn xor_matches() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  strings:
+    $a = "cmd" xor(0-0x10)
+  condition:
+    $a
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"cmd \x73\x7d\x74 \x62\x6c\x65 \x53\x5d\x54");
+
+    let rule = results.iter().next().unwrap();
+    let pattern = rule.patterns().next().unwrap();
+
+    // The last sequence in the data is "cmd" XORed with 0x30, which is out
+    // of the range of accepted keys, so it's not a match.
+    assert_eq!(
+        pattern.matches(),
+        &[
+            Match {
+                range: 0..3,
+                xor_key: Some(0),
+                base64: None,
+                base_offset: 0
+            },
+            Match {
+                range: 4..7,
+                xor_key: Some(0x10),
+                base64: None,
+                base_offset: 0
+            },
+            Match {
+                range: 8..11,
+                xor_key: Some(0x01),
+                base64: None,
+                base_offset: 0
+            },
+        ]
+    );
+}
+
+#[test]
+fn owned_output() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+    $b = "bar" xor
+  condition:
+    $a or $b
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let output = scanner.scan(b"foo c`s").to_owned_output();
+
+    assert_eq!(
+        output.matching_rules,
+        vec![RuleOutput {
+            id: 0,
+            namespace: "default".to_string(),
+            name: "test".to_string(),
+            tags: vec![],
+            metadata: vec![],
+            patterns: vec![
+                PatternOutput {
+                    identifier: "$a".to_string(),
+                    matches: vec![MatchOutput {
+                        offset: 0,
+                        length: 3,
+                        xor_key: None,
+                        base64: None,
+                        data: None,
+                    }],
+                    matches_truncated: false,
+                },
+                PatternOutput {
+                    identifier: "$b".to_string(),
+                    matches: vec![MatchOutput {
+                        offset: 4,
+                        length: 3,
+                        xor_key: Some(0x01),
+                        base64: None,
+                        data: None,
+                    }],
+                    matches_truncated: false,
+                }
+            ],
+            match_stats: MatchStats {
+                xor_keys: vec![XorKeyStats {
+                    key: 0x01,
+                    patterns: 1,
+                    matches: 1
+                }],
+                base64_variants: vec![]
+            }
+        }]
+    );
+
+    #[cfg(feature = "serde")]
+    assert_eq!(
+        serde_json::to_string(&output).unwrap(),
+        r#"{"matching_rules":[{"id":0,"namespace":"default","name":"test","tags":[],"metadata":[],"patterns":[{"identifier":"$a","matches":[{"offset":0,"length":3,"xor_key":null,"base64":null,"data":null}],"matches_truncated":false},{"identifier":"$b","matches":[{"offset":4,"length":3,"xor_key":1,"base64":null,"data":null}],"matches_truncated":false}],"match_stats":{"xor_keys":[{"key":1,"patterns":1,"matches":1}],"base64_variants":[]}}]}"#
+    );
+}
+
+#[test]
+fn owned_output_with_data() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  meta:
+    description = "\x1b]0;pwned\x07"
+  strings:
+    $a = "\x1b[31mEVIL"
+  condition:
+    $a
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let data = b"\x00\x1b[31mEVIL\x1b[0m";
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(data);
+
+    let output = results.to_owned_output_with_data(data, 1024);
+    let m = &output.matching_rules[0].patterns[0].matches[0];
+
+    // The escape sequence is rendered in a form that terminals don't
+    // interpret, and the original data can be recovered.
+    assert_eq!(m.data.as_deref(), Some(r"\x1b[31mEVIL"));
+    assert_eq!(unescape(m.data.as_ref().unwrap()).unwrap(), &data[1..10]);
+
+    let output = results.to_owned_output_with_data(data, 3);
+    let m = &output.matching_rules[0].patterns[0].matches[0];
+
+    assert_eq!(m.data.as_deref(), Some(r"\x1b[3"));
+    assert_eq!(m.length, 9);
+
+    #[cfg(feature = "serde")]
+    assert!(!serde_json::to_string(&output).unwrap().contains('\x1b'));
+
+    let rule = results.iter().next().unwrap();
+    let (_, description) = rule.metadata().next().unwrap();
+
+    assert_eq!(description.to_string(), r#""\x1b]0;pwned\x07""#);
+}
+
+#[test]
+#[cfg(all(feature = "console-module", feature = "test_proto2-module"))]
+fn console_log_escaped() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "console"
+import "test_proto2"
+rule test {
+  condition:
+    console.log("\x1b]0;pwned\x07") and
+    console.log(test_proto2.head(12)) and
+    console.log("data: ", test_proto2.head(12))
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let data = b"\x1b[2J\x1b[31m\\\xff\x00";
+    let mut messages = Vec::new();
+    let mut scanner = Scanner::new(&rules);
+
+    scanner.console_log(|message| messages.push(message));
+
+    assert_eq!(scanner.scan(data).num_matching_rules(), 1);
+
+    drop(scanner);
+
+    // Escape sequences in the rule or the scanned data don't reach the
+    // callback, and the original bytes can be recovered.
+    assert_eq!(
+        messages,
+        vec![
+            r"\x1b]0;pwned\x07",
+            r"\x1b[2J\x1b[31m\\\xff\x00",
+            r"data: \x1b[2J\x1b[31m\\\xff\x00",
+        ]
+    );
+
+    assert_eq!(unescape(&messages[1]).unwrap(), data);
+}
+
+/// The JSON produced from the owned output is compared with a golden file,
+/// so that changes in its shape don't go unnoticed. If the shape changes,
+/// the JSON schema in [`crate::schemas`] must change too.
+#[cfg(feature = "serde")]
+#[test]
+fn owned_output_json() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test : tag1 tag2 {
+  meta:
+    string = "foo\x00\xff\x1b[0m"
+    integer = -1
+    float = 1.5
+    bool = true
+  strings:
+    $a = "foobar"
+    $b = "bar" xor(1-2)
+    $c = "baz" private
+    $d = "\xffqux"
+  condition:
+    any of them
+}
+
+rule no_patterns { condition: true }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let data = b"foobar c`s baz \xffqux";
+    let mut scanner = Scanner::new(&rules);
+    let output = scanner.scan(data).to_owned_output_with_data(data, 4);
+
+    let mut json = serde_json::to_string_pretty(&output).unwrap();
+    json.push('\n');
+
+    assert_eq!(json, include_str!("testdata/owned_output.json"));
+}
+
+#[test]
+fn results_between_scans() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule foo { strings: $a = "foo" condition: $a }
+rule bar { strings: $a = "bar" condition: $a }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // The scanned data can be dropped before the results are used.
+    let data = b"foo foo".to_vec();
+    let results = scanner.scan(&data);
+    drop(data);
+
+    let rule = results.iter().next().unwrap();
+    let name = rule.name();
+
+    assert_eq!(rule.patterns().next().unwrap().matches().len(), 2);
+
+    let first = results.to_owned_output();
+
+    // Once the results are not used anymore, the scanner can be modified
+    // and used again. Names borrowed from the rules and owned outputs
+    // remain valid, and are not affected by the next scan.
+    scanner.set_max_retained_capacity(0);
+
+    let second = scanner.scan(b"bar").to_owned_output();
+
+    assert_eq!(name, "foo");
+    assert_eq!(first.matching_rules.len(), 1);
+    assert_eq!(first.matching_rules[0].name, "foo");
+    assert_eq!(first.matching_rules[0].patterns[0].matches.len(), 2);
+    assert_eq!(second.matching_rules.len(), 1);
+    assert_eq!(second.matching_rules[0].name, "bar");
+    assert_eq!(second.matching_rules[0].patterns[0].matches.len(), 1);
+
+    // Results of a new scan don't include anything from the previous ones.
+    let results = scanner.scan(b"");
+
+    assert_eq!(results.num_matching_rules(), 0);
+    assert_eq!(results.iter_non_matches().count(), 2);
+}
+
+#[test]
+#[cfg(feature = "hash-module")]
+fn hash_cache_between_scans() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "hash"
+rule abc { condition: hash.md5(0, 3) == "900150983cd24fb0d6963f7d28e17f72" }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // The digest cached while scanning "abc" must not be used for the
+    // same block of the data in the following scans.
+    assert_eq!(scanner.scan(b"abc").num_matching_rules(), 1);
+    assert_eq!(scanner.scan(b"xyz").num_matching_rules(), 0);
+    assert_eq!(scanner.scan(b"abcd").num_matching_rules(), 1);
+    assert_eq!(scanner.scan(b"ab").num_matching_rules(), 0);
+}
+
+#[test]
+#[cfg(feature = "pe-module")]
+fn imphash_cache_between_scans() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "pe"
+rule pe32 {
+  condition:
+    pe.imphash() == "f8cfd4aa25b0c1dbc8d5ad377cf90dd0" and
+    pe.imphash() != "1ceda87a5b5ed9235d6f444e819e2263"
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let pe32 = include_bytes!("../tests/testdata/pe/pe32.exe");
+    let pe64 = include_bytes!("../tests/testdata/pe/pe64.exe");
+
+    let mut scanner = Scanner::new(&rules);
+
+    // The import hash cached while scanning a file must not be used in
+    // the following scans.
+    assert_eq!(scanner.scan(pe32).num_matching_rules(), 1);
+    assert_eq!(scanner.scan(pe64).num_matching_rules(), 0);
+    assert_eq!(scanner.scan(b"MZ").num_matching_rules(), 0);
+    assert_eq!(scanner.scan(pe32).num_matching_rules(), 1);
+}
+
+#[test]
+#[cfg(all(feature = "console-module", feature = "test_proto2-module"))]
+fn console_log() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "console"
+import "test_proto2"
+rule test {
+  condition:
+    console.log("foo") and
+    console.log(-1) and
+    console.log(2.5) and
+    console.log("bar: ", "baz") and
+    console.log("size: ", filesize) and
+    console.log("half: ", filesize * 0.5) and
+    console.log(test_proto2.int64_undef) and
+    console.log(test_proto2.float_undef) and
+    console.log(test_proto2.string_undef) and
+    console.log("undef: ", test_proto2.int64_undef) and
+    console.log(test_proto2.string_undef, "qux") and
+    console.log("upper: ", test_proto2.uppercase(test_proto2.string_undef))
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut messages = Vec::new();
+    let mut scanner = Scanner::new(&rules);
+
+    // Without a callback the messages are discarded.
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+
+    scanner.console_log(|message| messages.push(message));
+
+    assert_eq!(scanner.scan(b"abc").num_matching_rules(), 1);
+
+    drop(scanner);
+
+    assert_eq!(
+        messages,
+        vec![
+            "foo",
+            "-1",
+            "2.5",
+            "bar: baz",
+            "size: 3",
+            "half: 1.5",
+            "undef",
+            "undef",
+            "undef",
+            "undef: undef",
+            "undefqux",
+            "upper: undef",
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "time-module")]
+fn set_current_time() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "time"
+rule expired { condition: time.now() > 1700000000 }
+rule epoch { condition: time.now() == 0 }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // By default the system clock is used.
+    let results = scanner.scan(&[]);
+    assert_eq!(results.iter().next().unwrap().name(), "expired");
+
+    let results = scanner.set_current_time(1600000000).scan(&[]);
+    assert_eq!(results.num_matching_rules(), 0);
+
+    let results = scanner.set_current_time(0).scan(&[]);
+    assert_eq!(results.num_matching_rules(), 1);
+    assert_eq!(results.iter().next().unwrap().name(), "epoch");
+}
+
+#[test]
+#[cfg(feature = "time-module")]
+fn set_clock() {
+    use std::sync::atomic::AtomicI64;
+
+    use crate::Clock;
+
+    /// A clock that advances one second every time it's read.
+    struct TickingClock(AtomicI64);
+
+    impl Clock for TickingClock {
+        fn now(&self) -> i64 {
+            self.0.fetch_add(1, Ordering::Relaxed)
+        }
+    }
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "time"
+rule ticking { condition: time.now() < time.now() }
+rule second { condition: time.now() == 2 }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    scanner.set_clock(TickingClock(AtomicI64::new(0)));
+
+    // Every call to `time.now()` reads the clock.
+    let results = scanner.scan(&[]);
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+    assert_eq!(matching, ["ticking", "second"]);
+
+    // The clock is not reset between scans.
+    assert_eq!(scanner.scan(&[]).num_matching_rules(), 1);
+}
+
+#[test]
+fn match_stats() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  strings:
+    $a = "foo" xor
+    $b = "bar" xor
+    $c = "baz" xor
+    $d = "qux" xor private
+    $e = "secret" base64
+  condition:
+    any of them
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let xor = |data: &[u8], key: u8| -> Vec<u8> {
+        data.iter().map(|b| b ^ key).collect()
+    };
+
+    // `$a` and `$b` use the same key, `$c` uses a different one and `$d`
+    // is private, so its key is not counted. "c2VjcmV0" is "secret" in
+    // base64 (offset 0), and "ZWNyZX" is part of "xsecret" (offset 1).
+    let mut data = Vec::new();
+    data.extend(xor(b"foo", 0x20));
+    data.extend(b" ");
+    data.extend(xor(b"bar", 0x20));
+    data.extend(b" ");
+    data.extend(xor(b"baz", 0x05));
+    data.extend(b" ");
+    data.extend(xor(b"foo", 0x20));
+    data.extend(b" ");
+    data.extend(xor(b"qux", 0x07));
+    data.extend(b" c2VjcmV0 eHNlY3JldA");
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(data.as_slice());
+    let rule = results.iter().next().unwrap();
+
+    assert_eq!(
+        rule.match_stats(),
+        MatchStats {
+            xor_keys: vec![
+                XorKeyStats { key: 0x05, patterns: 1, matches: 1 },
+                XorKeyStats { key: 0x20, patterns: 2, matches: 3 },
+            ],
+            base64_variants: vec![
+                Base64VariantStats {
+                    variant: Base64Variant { offset: 0, wide: false },
+                    patterns: 1,
+                    matches: 1,
+                },
+                Base64VariantStats {
+                    variant: Base64Variant { offset: 1, wide: false },
+                    patterns: 1,
+                    matches: 1,
+                },
+            ],
+        }
+    );
+
+    assert_eq!(
+        results.to_owned_output().matching_rules[0].match_stats,
+        rule.match_stats()
+    );
+
+    // A rule without xor or base64 patterns has empty stats.
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source("rule test { strings: $a = \"foo\" condition: $a }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"foo");
+
+    assert_eq!(
+        results.iter().next().unwrap().match_stats(),
+        MatchStats::default()
+    );
+}
+
+#[test]
+fn private_patterns() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  strings:
+    $a = "foo"
+    $b = "bar" private
+  condition:
+    $a and $b
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // `$b` must match for the rule to match, but it's not reported.
+    assert_eq!(scanner.scan(b"foo").num_matching_rules(), 0);
+
+    let output = scanner.scan(b"foo bar").to_owned_output();
+
+    assert_eq!(
+        output.matching_rules,
+        vec![RuleOutput {
+            id: 0,
+            namespace: "default".to_string(),
+            name: "test".to_string(),
+            tags: vec![],
+            metadata: vec![],
+            patterns: vec![PatternOutput {
+                identifier: "$a".to_string(),
+                matches: vec![MatchOutput {
+                    offset: 0,
+                    length: 3,
+                    xor_key: None,
+                    base64: None,
+                    data: None,
+                }],
+                matches_truncated: false,
+            }],
+            match_stats: MatchStats::default()
+        }]
+    );
+}
+
+#[test]
+fn scan_file_errors() {
+    use std::error::Error;
+
+    use crate::scanner::ScanError;
+
+    let mut compiler = Compiler::new();
+    compiler.add_source("rule test { condition: true }").unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let Err(err) = scanner.scan_file("non-existent-file") else {
+        panic!("scanning a non-existent file must fail")
+    };
+
+    assert!(matches!(&err, ScanError::OpenError { path, source }
+        if path.to_str() == Some("non-existent-file")
+            && source.kind() == std::io::ErrorKind::NotFound));
+    assert_eq!(err.to_string(), "can not open `non-existent-file`");
+    assert!(err.source().is_some());
+}
+
+#[test]
+fn scan_file() {
+    use crate::tests::fs::FileTree;
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule small { condition: filesize < 100 }
+rule large { condition: filesize >= 100 and uint8(99) == 99 }
+rule empty { condition: filesize == 0 }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let dir = FileTree::new()
+        .sized_file("data/small.bin", 10)
+        .sized_file("data/large.bin", 4096)
+        .file("data/empty.bin", "")
+        .symlink("link.bin", "data/large.bin")
+        .dir("subdir")
+        .create();
+
+    let mut scanner = Scanner::new(&rules);
+
+    let mut matching = |path: &str| {
+        scanner
+            .scan_file(dir.path().join(path))
+            .unwrap()
+            .iter()
+            .map(|rule| rule.name())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(matching("data/small.bin"), vec!["small"]);
+    assert_eq!(matching("data/large.bin"), vec!["large"]);
+    assert_eq!(matching("data/empty.bin"), vec!["small", "empty"]);
+    assert_eq!(matching("link.bin"), vec!["large"]);
+
+    // Directories can't be scanned.
+    assert!(scanner.scan_file(dir.path().join("subdir")).is_err());
+
+    // The directory is removed when dropped.
+    let path = dir.path().to_path_buf();
+    drop(dir);
+    assert!(!path.exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn scan_file_not_mappable() {
+    let rules = crate::compile(
+        r#"rule test { condition: filesize > 0 and uint8(0) == 0x4E }"#,
+    )
+    .unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // Files in `/proc` can't be mapped, and they report a size of zero
+    // even if they have content. The content is read anyways.
+    assert_eq!(
+        scanner.scan_file("/proc/self/status").unwrap().num_matching_rules(),
+        1
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn scan_process() {
+    use crate::scanner::ScanError;
+
+    static MARKER: [u8; 26] = *b"yara-x scan_process marker";
+
+    let rules = crate::compile(
+        r#"rule marker { strings: $a = "yara-x scan_process marker" condition: $a }"#,
+    )
+    .unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let output = scanner.scan_process(std::process::id()).unwrap();
+
+    // The marker is found at its virtual address, within a region that
+    // contains it. The marker is found in other places too, like the
+    // source code of the rule.
+    let address = std::hint::black_box(&MARKER).as_ptr() as usize;
+
+    assert!(output.regions.iter().any(|region| {
+        (region.region.address..region.region.address + region.region.size)
+            .contains(&address)
+            && region.output.matching_rules[0].patterns[0]
+                .matches
+                .iter()
+                .any(|m| m.offset == address as u64)
+    }));
+
+    // Processes that don't exist can't be scanned.
+    assert!(matches!(
+        scanner.scan_process(u32::MAX),
+        Err(ScanError::ProcessError { pid: u32::MAX, .. })
+    ));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn scan_process_large_region() {
+    use crate::scanner::process::{readable_regions, MAX_BLOCK_SIZE};
+
+    const MARKER: &[u8] = b"yara-x large region marker";
+
+    let rules = crate::compile(
+        r#"rule marker { strings: $a = "yara-x large region marker" condition: $a }"#,
+    )
+    .unwrap();
+
+    // A region larger than a block, with the marker spanning the boundary
+    // between the first and second blocks.
+    let mut data = vec![0_u8; 2 * MAX_BLOCK_SIZE];
+    let data_start = data.as_ptr() as usize;
+
+    let region = readable_regions(std::process::id())
+        .unwrap()
+        .into_iter()
+        .find(|region| {
+            (region.address..region.address + region.size)
+                .contains(&data_start)
+        })
+        .unwrap();
+
+    let boundary = region.address
+        + ((data_start - region.address) / MAX_BLOCK_SIZE + 1)
+            * MAX_BLOCK_SIZE;
+
+    let address = boundary - MARKER.len() / 2;
+    let offset = address - data_start;
+
+    data[offset..offset + MARKER.len()].copy_from_slice(MARKER);
+
+    let mut scanner = Scanner::new(&rules);
+    let output = scanner.scan_process(std::process::id()).unwrap();
+
+    assert!(output.regions.iter().any(|region| {
+        region.region.size > MAX_BLOCK_SIZE
+            && region.output.matching_rules[0].patterns[0]
+                .matches
+                .iter()
+                .any(|m| m.offset == address as u64)
+    }));
+
+    std::hint::black_box(data);
+}
+
+#[test]
+fn rule_origin() {
+    use crate::SourceCode;
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            SourceCode::from("rule foo { condition: true }")
+                .origin("rules/foo.yar"),
+        )
+        .unwrap()
+        .add_source(
+            SourceCode::from("rule bar { condition: true }")
+                .origin("rules/bar.yar"),
+        )
+        .unwrap()
+        .add_source("rule baz { condition: true }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let scan_results = scanner.scan(&[]);
+
+    assert_eq!(
+        scan_results
+            .iter()
+            .map(|rule| (rule.name(), rule.origin()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("foo", Some("rules/foo.yar")),
+            ("bar", Some("rules/bar.yar")),
+            ("baz", None),
+        ]
+    );
+}
+
+#[test]
+fn tags() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test : foo bar foo baz { condition: true }
+rule no_tags { condition: true }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"");
+    let mut matching_rules = results.iter();
+
+    // Duplicated tags are reported only once.
+    let rule = matching_rules.next().unwrap();
+    assert_eq!(rule.tags().collect::<Vec<_>>(), vec!["foo", "bar", "baz"]);
+    assert!(rule.has_tag("foo"));
+    assert!(rule.has_tag("baz"));
+    assert!(!rule.has_tag("qux"));
+    // `test` is in the identifiers pool, but it's not a tag.
+    assert!(!rule.has_tag("test"));
+
+    let rule = matching_rules.next().unwrap();
+    assert_eq!(rule.tags().next(), None);
+    assert!(!rule.has_tag("foo"));
+}
+
+#[test]
+fn metadata() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  meta:
+    author = "foo"
+    version = 2
+    score = 0.5
+    author = "bar"
+    enabled = true
+    bytes = "\x00\xffbaz\n"
+  condition:
+    true
+}
+
+rule no_meta { condition: true }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"");
+    let mut matching_rules = results.iter();
+
+    // Duplicated identifiers are kept, in declaration order.
+    let rule = matching_rules.next().unwrap();
+    assert_eq!(
+        rule.metadata().collect::<Vec<_>>(),
+        vec![
+            ("author", MetaValue::String(BStr::new("foo"))),
+            ("version", MetaValue::Integer(2)),
+            ("score", MetaValue::Float(0.5)),
+            ("author", MetaValue::String(BStr::new("bar"))),
+            ("enabled", MetaValue::Bool(true)),
+            ("bytes", MetaValue::String(BStr::new(b"\x00\xffbaz\n"))),
+        ]
+    );
+
+    let rule = matching_rules.next().unwrap();
+    assert_eq!(rule.metadata().next(), None);
+}
+
+#[test]
+fn metadata_defaults() {
+    use crate::SourceCode;
+    use yara_x_parser::ast;
+
+    let mut compiler = Compiler::new();
+    compiler
+        .namespace_default_meta(
+            "default",
+            [("author", "ns".into()), ("license", "MIT".into())],
+        )
+        .add_source(
+            SourceCode::from(
+                r#"
+rule test {
+  meta:
+    license = "GPL"
+  condition:
+    true
+}
+"#,
+            )
+            .with_default_meta([
+                ("author", "source".into()),
+                ("score", ast::MetaValue::Integer(10)),
+            ]),
+        )
+        .unwrap()
+        .new_namespace("other")
+        .add_source("rule other { condition: true }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"");
+    let mut matching_rules = results.iter();
+
+    // The rule's own entries come first, then the source code defaults,
+    // and finally the namespace defaults, which are overridden by both.
+    let rule = matching_rules.next().unwrap();
+    assert_eq!(
+        rule.metadata().collect::<Vec<_>>(),
+        vec![
+            ("license", MetaValue::String(BStr::new("GPL"))),
+            ("author", MetaValue::String(BStr::new("source"))),
+            ("score", MetaValue::Integer(10)),
+        ]
+    );
+    assert_eq!(
+        rule.inherited_metadata().collect::<Vec<_>>(),
+        vec![
+            ("author", MetaValue::String(BStr::new("source"))),
+            ("score", MetaValue::Integer(10)),
+        ]
+    );
+
+    // Defaults don't apply to other namespaces.
+    let rule = matching_rules.next().unwrap();
+    assert_eq!(rule.metadata().next(), None);
+
+    // An inherited `enabled = false` disables the rule.
+    let mut compiler = Compiler::new();
+    compiler
+        .namespace_default_meta("default", [("enabled", false.into())])
+        .add_source("rule test { condition: true }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let rule = rules.iter().next().unwrap();
+    assert!(rule.is_disabled());
+    assert_eq!(Scanner::new(&rules).scan(b"").num_matching_rules(), 0);
+}
+
+#[test]
+fn disabled_rules() {
+    let src = r#"
+rule disabled {
+  meta:
+    enabled = false
+  strings:
+    $a = "foo"
+  condition:
+    $a
+}
+
+rule disabled_ref {
+  meta:
+    enabled = false
+  condition:
+    disabled
+}
+
+rule enabled {
+  strings:
+    $b = "bar"
+  condition:
+    $b
+}
+"#;
+
+    let mut compiler = Compiler::new();
+    compiler.add_source(src).unwrap();
+    let rules = compiler.build().unwrap();
+
+    // The patterns in disabled rules don't produce atoms.
+    assert_eq!(rules.atoms().len(), 1);
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"foobar");
+
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["enabled"]
+    );
+    assert_eq!(results.iter_non_matches().count(), 0);
+    assert_eq!(
+        results.iter_disabled().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["disabled", "disabled_ref"]
+    );
+    assert!(results.iter_disabled().all(|r| r.is_disabled()));
+
+    let mut compiler = Compiler::new();
+    compiler.force_enable_rules(true).add_source(src).unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"foobar");
+
+    assert_eq!(results.num_matching_rules(), 3);
+    assert_eq!(results.iter_disabled().count(), 0);
+}
+
+#[test]
+fn private_rules() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+private rule is_foo {
+  strings:
+    $a = "foo"
+  condition:
+    $a
+}
+
+private rule is_bar {
+  strings:
+    $a = "bar"
+  condition:
+    $a
+}
+
+rule foo_not_bar {
+  condition:
+    is_foo and not is_bar
+}
+
+rule bar {
+  condition:
+    is_bar
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // Private rules are evaluated, but they are never reported.
+    let results = scanner.scan(b"foo");
+    assert_eq!(results.num_matching_rules(), 1);
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["foo_not_bar"]
+    );
+    assert_eq!(
+        results.iter_non_matches().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["bar"]
+    );
+
+    // Scanning again must not keep matches from private rules that
+    // matched in the previous scan.
+    let results = scanner.scan(b"bar");
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["bar"]
+    );
+
+    let results = scanner.scan(b"");
+    assert_eq!(results.num_matching_rules(), 0);
+    assert_eq!(results.iter_non_matches().count(), 2);
+}
+
+#[test]
+fn private_rules_without_patterns() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+private rule big { condition: filesize > 2 }
+rule small_and_big { condition: filesize < 2 and big }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // Only the private rule matches here, that match must be cleared
+    // before the next scan.
+    assert_eq!(scanner.scan(b"foo").num_matching_rules(), 0);
+    assert_eq!(scanner.scan(b"f").num_matching_rules(), 0);
+}
+
+#[test]
+fn global_rules() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+global rule is_foo {
+  strings:
+    $a = "foo"
+  condition:
+    $a
+}
+
+rule ordinary {
+  condition:
+    true
+}
+"#,
+        )
+        .unwrap()
+        .new_namespace("other")
+        .add_source("rule other { condition: true }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // The global rule doesn't match, `ordinary` is suppressed but `other`
+    // is in a different namespace.
+    let results = scanner.scan(b"bar");
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["other"]
+    );
+    assert_eq!(
+        results.iter_non_matches().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["is_foo", "ordinary"]
+    );
+
+    let results = scanner.scan(b"foo");
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["is_foo", "ordinary", "other"]
+    );
+}
+
+#[test]
+fn multiple_global_rules() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+global private rule is_foo {
+  strings:
+    $a = "foo"
+  condition:
+    $a
+}
+
+global rule is_bar {
+  strings:
+    $a = "bar"
+  condition:
+    $a
+}
+
+rule ordinary {
+  condition:
+    true
+}
+
+rule uses_private {
+  condition:
+    is_foo
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // All global rules must match.
+    assert_eq!(scanner.scan(b"foo").num_matching_rules(), 0);
+    assert_eq!(scanner.scan(b"bar").num_matching_rules(), 0);
+
+    let results = scanner.scan(b"foobar");
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["is_bar", "ordinary", "uses_private"]
+    );
+
+    // A disabled global rule doesn't suppress other rules.
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+global rule never {
+  meta:
+    enabled = false
+  condition:
+    false
+}
+
+rule ordinary {
+  condition:
+    true
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+}
+
+#[test]
+fn rule_ids() {
+    let src = r#"
+rule foo { condition: false }
+private rule bar { condition: true }
+rule baz { strings: $a = "baz" condition: bar and $a }
+"#;
+
+    let mut compiler = Compiler::new();
+    compiler.add_source(src).unwrap();
+    let rules = compiler.build().unwrap();
+
+    // All rules are returned, sorted by ID.
+    assert_eq!(
+        rules
+            .iter()
+            .map(|r| (usize::from(r.id()), r.name(), r.is_private()))
+            .collect::<Vec<_>>(),
+        vec![(0, "foo", false), (1, "bar", true), (2, "baz", false)]
+    );
+
+    for rule in rules.iter() {
+        assert_eq!(rules.rule_by_id(rule.id()).unwrap().name(), rule.name());
+    }
+
+    // Rules obtained outside of a scan don't have matches.
+    let rule = rules.rule_by_id(RuleId::from(2)).unwrap();
+    assert_eq!(rule.patterns().next().unwrap().matches(), &[]);
+
+    // Out-of-range IDs don't panic.
+    assert!(rules.rule_by_id(RuleId::from(3)).is_none());
+    assert!(rules.rule_by_id(RuleId::from(usize::MAX)).is_none());
+    assert!(rules.rule_by_id(RuleId::from(-1)).is_none());
+
+    // IDs in the scan results are the same.
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(b"baz");
+    let rule = results.iter().next().unwrap();
+
+    assert_eq!(rule.id(), RuleId::from(2));
+    assert_eq!(rule.patterns().next().unwrap().matches().len(), 1);
+    assert_eq!(
+        results.iter_non_matches().next().unwrap().id(),
+        RuleId::from(0)
+    );
+    assert_eq!(results.to_owned_output().matching_rules[0].id, 2);
+
+    // Compiling the same source again produces the same IDs.
+    let mut compiler = Compiler::new();
+    compiler.add_source(src).unwrap();
+    let recompiled = compiler.build().unwrap();
+
+    assert!(rules
+        .iter()
+        .zip(recompiled.iter())
+        .all(|(a, b)| a.id() == b.id() && a.name() == b.name()));
+}
+
+#[test]
+fn rule_by_name() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source("rule foo { condition: true }")
+        .unwrap()
+        .new_namespace("bar")
+        .add_source("rule foo { condition: true } rule baz { condition: foo }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    for rule in rules.iter() {
+        assert_eq!(
+            rules.rule_by_name(rule.namespace(), rule.name()).unwrap().id(),
+            rule.id()
+        );
+    }
+
+    assert_eq!(
+        rules.rule_by_name("bar", "baz").unwrap().id(),
+        RuleId::from(2)
+    );
+
+    // Both the namespace and the name must match.
+    assert!(rules.rule_by_name("default", "baz").is_none());
+    assert!(rules.rule_by_name("bar", "qux").is_none());
+    assert!(rules.rule_by_name("qux", "foo").is_none());
+}
+
+#[test]
+fn rule_dependencies() {
+    let rules = crate::compile(
+        r#"
+private rule a { condition: true }
+rule b { condition: a }
+rule c { condition: b and a and b }
+rule d { condition: true }
+"#,
+    )
+    .unwrap();
+
+    let dependencies = |name| {
+        rules
+            .rule_by_name("default", name)
+            .unwrap()
+            .dependencies()
+            .iter()
+            .map(|id| rules.rule_by_id(*id).unwrap().name())
+            .collect::<Vec<_>>()
+    };
+
+    assert!(dependencies("a").is_empty());
+    assert_eq!(dependencies("b"), ["a"]);
+    assert_eq!(dependencies("c"), ["b", "a"]);
+    assert!(dependencies("d").is_empty());
+}
+
+#[test]
+fn pattern_ids() {
+    let sources = [
+        r#"rule foo { strings: $a = "foo" $b = "bar" condition: all of them }"#,
+        r#"rule bar { strings: $a = "bar" $b = { 01 02 } condition: $a or $b }"#,
+    ];
+
+    let compile = || {
+        let mut compiler = Compiler::new();
+        for src in sources {
+            compiler.add_source(src).unwrap();
+        }
+        compiler.build().unwrap()
+    };
+
+    let pattern_ids = |rules: &crate::Rules| {
+        rules
+            .iter()
+            .flat_map(|rule| {
+                rule.patterns().map(|pattern| usize::from(pattern.id()))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let rules = compile();
+
+    assert_eq!(pattern_ids(&rules), [0, 1, 2, 3]);
+
+    // Compiling the same sources again produces the same IDs.
+    assert_eq!(pattern_ids(&compile()), pattern_ids(&rules));
+}
+
+#[test]
+fn profiling() {
+    let rules = crate::compile(
+        r#"
+rule fast { condition: true }
+rule slow { condition: for all i in (0..filesize) : (uint8(i) < 0xFF) }
+rule pattern { strings: $a = "foo" condition: $a }
+"#,
+    )
+    .unwrap();
+
+    let data = b"foo".repeat(10000);
+    let mut scanner = Scanner::new(&rules);
+
+    // Profiling is disabled by default.
+    assert!(scanner.scan(&data).profiling(3).is_empty());
+
+    scanner.set_profiling(true);
+
+    let results = scanner.scan(&data);
+    let profiles = results.profiling(3);
+
+    assert_eq!(profiles[0].rule.name(), "slow");
+    assert!(profiles[0].pattern_time.is_zero());
+    assert_eq!(results.profiling(1).len(), 1);
+
+    // The pattern search phase is not charged to the rule that triggered
+    // it, only the verification of the pattern's matches.
+    let pattern = profiles
+        .iter()
+        .find(|profile| profile.rule.name() == "pattern")
+        .unwrap();
+
+    assert!(!pattern.pattern_time.is_zero());
+
+    scanner.set_profiling(false);
+    assert!(scanner.scan(&data).profiling(3).is_empty());
+}
+
+#[test]
+fn scan_with_filter() {
+    let rules = crate::compile(
+        r#"
+global rule always { condition: true }
+private rule has_foo { strings: $a = "foo" condition: $a }
+rule foo { condition: has_foo }
+rule bar { strings: $b = "bar" condition: $b }
+rule baz { condition: not foo }
+"#,
+    )
+    .unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // Only `foo` is selected, but `has_foo` is evaluated too because `foo`
+    // depends on it, and so is `always` because it's a global rule.
+    let results =
+        scanner.scan_with_filter(b"foobar", |rule| rule.name() == "foo");
+
+    assert_eq!(results.iter().map(|r| r.name()).collect::<Vec<_>>(), ["foo"]);
+
+    // Rules that were not selected are not reported as non-matching.
+    assert_eq!(results.iter_non_matches().count(), 0);
+
+    // `baz` doesn't match because it depends on `foo`, which is evaluated
+    // but not reported.
+    let results =
+        scanner.scan_with_filter(b"foobar", |rule| rule.name() != "foo");
+
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        ["always", "bar"]
+    );
+
+    assert_eq!(
+        results.iter_non_matches().map(|r| r.name()).collect::<Vec<_>>(),
+        ["baz"]
+    );
+
+    // A regular scan evaluates all rules again.
+    assert_eq!(scanner.scan(b"foobar").num_matching_rules(), 3);
+}
+
+#[test]
+fn stream() {
+    let rules = crate::compile(
+        r#"
+rule fixed { strings: $a = "foobar" condition: #a == 2 }
+rule full_word { strings: $a = "foo" fullword condition: #a == 1 }
+rule xored { strings: $a = "bazqux" xor condition: $a }
+rule base64_encoded { strings: $a = "base64 pattern" base64 condition: $a }
+rule size { condition: filesize == 76 }
+rule last_byte { condition: uint8(filesize - 1) == 0x21 }
+"#,
+    )
+    .unwrap();
+
+    let data = b"xx foobar xyfoobar foo fooa BAZQUX YmFzZTY0IHBhdHRlcm4 0123456789abcdefghij!";
+
+    let mut scanner = Scanner::new(&rules);
+    let expected = scanner.scan(data).to_owned_output();
+
+    assert_eq!(expected.matching_rules.len(), 6);
+
+    // The results are the same regardless of where the data is split, as
+    // patterns spanning multiple chunks are found too.
+    for chunk_size in 1..=data.len() {
+        let mut stream = scanner.stream();
+        for chunk in data.chunks(chunk_size) {
+            stream.feed(chunk);
+        }
+        assert_eq!(
+            stream.finish().to_owned_output(),
+            expected,
+            "chunk size: {}",
+            chunk_size
+        );
+    }
+}
+
+#[test]
+fn stream_discarded_data() {
+    let rules = crate::compile(
+        r#"
+rule first_byte { condition: defined uint8(0) }
+rule last_byte { condition: uint8(filesize - 1) == 0x21 }
+rule size { condition: filesize == 1001 }
+"#,
+    )
+    .unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let mut stream = scanner.stream();
+
+    for _ in 0..100 {
+        stream.feed(b"0123456789");
+    }
+
+    stream.feed(b"!");
+
+    // The beginning of the data is not available anymore, but the end is.
+    let results = stream.finish();
+
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        ["last_byte", "size"]
+    );
+
+    // A regular scan of the same data reads the first byte.
+    let data = [b"0123456789".repeat(100).as_slice(), b"!"].concat();
+
+    assert_eq!(scanner.scan(&data).num_matching_rules(), 3);
+}
+
+#[test]
+fn base64_matches() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule test {
+  strings:
+    $a = "This program cannot" base64
+  condition:
+    $a
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // The pattern encoded as base64 with 0, 1 and 2 bytes before it. Each
+    // of the three permutations produces a match.
+    let results = scanner.scan(
+        b"VGhpcyBwcm9ncmFtIGNhbm5vdA== \
+          QVRoaXMgcHJvZ3JhbSBjYW5ub3Q= \
+          QUFUaGlzIHByb2dyYW0gY2Fubm90",
+    );
+
+    let rule = results.iter().next().unwrap();
+    let pattern = rule.patterns().next().unwrap();
+
+    assert_eq!(pattern.matches().len(), 3);
+}
+
+#[test]
+#[should_panic(
+    expected = "the canary after the lookup indexes was overwritten by WASM code executed before `enter_rule@i@`"
+)]
+fn corrupted_canary() {
+    let mut compiler = Compiler::new();
+    compiler.add_source("rule test { condition: true }").unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    let ctx = scanner.wasm_store.data();
+    let (_, offset) = ctx.memory_layout().canaries()[1];
+    let main_memory = ctx.main_memory.unwrap();
+
+    main_memory.data_mut(scanner.wasm_store.as_context_mut())[offset] = 0;
+
+    scanner.scan(&[]);
+}
+
+#[test]
+fn retained_capacity() {
+    let mut src = String::from("rule test { strings:\n");
+    for i in 0..1000 {
+        src.push_str(&format!("  $p{i} = \"pattern_{i:04}\"\n"));
+    }
+    src.push_str("  condition: any of them }");
+
+    let mut compiler = Compiler::new();
+    compiler.add_source(src.as_str()).unwrap();
+    let rules = compiler.build().unwrap();
+
+    let big: Vec<u8> = (0..1000)
+        .flat_map(|i| format!("pattern_{i:04}").into_bytes())
+        .collect();
+
+    let small = b"nothing to see here";
+    let limit = 16 * 1024;
+
+    let mut scanner = Scanner::new(&rules);
+    scanner.set_max_retained_capacity(limit);
+
+    for _ in 0..100 {
+        assert_eq!(scanner.scan(&big).num_matching_rules(), 1);
+        let peak = scanner.stats().retained_capacity;
+        assert!(peak > limit);
+
+        // The memory used by the big scan is released before the small
+        // one starts.
+        assert_eq!(scanner.scan(small).num_matching_rules(), 0);
+        assert!(scanner.stats().retained_capacity <= limit);
+    }
+
+    // Without a limit, the scanner keeps all the memory used by the big
+    // scan, and reuses it in the next one.
+    let mut scanner = Scanner::new(&rules);
+    scanner.set_max_retained_capacity(usize::MAX);
+
+    scanner.scan(&big);
+    let peak = scanner.stats().retained_capacity;
+    scanner.scan(small);
+    let retained = scanner.stats().retained_capacity;
+
+    assert!(retained >= peak);
+
+    scanner.scan(&big);
+
+    assert_eq!(scanner.stats().retained_capacity, retained);
+
+    // With a limit below that, only the memory that exceeds the limit is
+    // released.
+    let limit = retained / 2;
+    scanner.set_max_retained_capacity(limit);
+    scanner.scan(small);
+
+    let retained = scanner.stats().retained_capacity;
+
+    assert!(retained <= limit);
+    assert!(retained > limit / 2);
+}
+
+#[test]
+fn compiled_pattern() {
+    let ranges = |pattern: &str, data: &[u8]| {
+        Pattern::compile(pattern)
+            .unwrap()
+            .find_iter(data)
+            .map(|m| (m.range.start, m.range.end))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(ranges(r#""foo""#, b"foo foofoo"), [(0, 3), (4, 7), (7, 10)]);
+    assert_eq!(ranges(r#""aa""#, b"aaaa"), [(0, 2), (1, 3), (2, 4)]);
+    assert_eq!(ranges(r#""foo" nocase"#, b"FoO"), [(0, 3)]);
+    assert_eq!(ranges(r#""foo" wide"#, b"foo f\x00o\x00o\x00"), [(4, 10)]);
+    assert_eq!(ranges(r#""foo" fullword"#, b"foobar foo"), [(7, 10)]);
+    assert_eq!(ranges(r#""foo" private"#, b"foo"), [(0, 3)]);
+    assert_eq!(ranges(r#""foo""#, b"bar"), []);
+
+    let matches: Vec<_> =
+        Pattern::compile(r#""foo" xor"#).unwrap().find_iter(b"gnn").collect();
+
+    assert_eq!(
+        matches,
+        [Match {
+            range: 0..3,
+            xor_key: Some(1),
+            base64: None,
+            base_offset: 0
+        }]
+    );
+
+    // The same compiled pattern can be searched for in different data.
+    let pattern = Pattern::compile(r#""foo""#).unwrap();
+
+    assert_eq!(pattern.find_iter(b"foo").count(), 1);
+    assert_eq!(pattern.find_iter(b"bar").count(), 0);
+}
+
+#[test]
+fn compiled_pattern_errors() {
+    assert!(matches!(
+        Pattern::compile("{ 4D 5X }"),
+        Err(Error::ParseError(_))
+    ));
+
+    assert!(matches!(
+        Pattern::compile(r#""foo" xor(300)"#),
+        Err(Error::ParseError(_))
+    ));
+
+    // Hex patterns and regular expressions are not supported yet.
+    assert!(matches!(
+        Pattern::compile("{ 4D 5A }"),
+        Err(Error::UnsupportedPattern(_))
+    ));
+
+    assert!(matches!(
+        Pattern::compile("/foo[0-9]+/i"),
+        Err(Error::UnsupportedPattern(_))
+    ));
+
+    // Patterns can't declare other patterns, nor other rules.
+    assert!(Pattern::compile(r#""foo" $b = "bar""#).is_err());
+
+    assert!(matches!(
+        Pattern::compile(
+            r#""foo" condition: $a } rule other { strings: $a = "bar""#
+        ),
+        Err(Error::InvalidPattern(_))
+    ));
+}
+
+/// Searching for a pattern with [`Pattern::compile`] produces the same
+/// matches that a full scan produces for the same pattern declared in a
+/// rule along with other patterns.
+#[test]
+fn compiled_pattern_same_as_scanner() {
+    let patterns = [
+        r#""foo""#,
+        r#""foo" nocase"#,
+        r#""foo" wide"#,
+        r#""foo" ascii wide nocase"#,
+        r#""foo" fullword"#,
+        r#""foo" xor"#,
+        r#""foobar" base64"#,
+        r#""foobar" base64wide"#,
+        r#""bar" fullword"#,
+        r#""fo" wide ascii"#,
+    ];
+
+    let data = b"foo FOO f\x00o\x00o\x00 foobar Zm9vYmFy fa bar fooo \
+                 gnn f\no\x00Z\x00m\x009\x00v\x00Y\x00m\x00F\x00y\x00";
+
+    let src = patterns
+        .iter()
+        .enumerate()
+        .map(|(i, pattern)| format!("$p{i} = {pattern}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            format!(
+                "rule test {{ strings: {src} \n condition: any of them }}"
+            )
+            .as_str(),
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(data);
+    let rule = results.iter().next().unwrap();
+
+    for pattern in rule.patterns() {
+        let i: usize = pattern.identifier()[2..].parse().unwrap();
+        let expected = pattern.matches();
+        let found: Vec<_> =
+            Pattern::compile(patterns[i]).unwrap().find_iter(data).collect();
+
+        assert!(!expected.is_empty(), "`{}` didn't match", patterns[i]);
+        assert_eq!(found, expected, "matches for `{}`", patterns[i]);
+    }
+}
+
+#[test]
+#[cfg(feature = "elf-module")]
+fn module_data_between_scans() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "elf"
+rule elf64 { condition: elf.machine == elf.EM_X86_64 }
+rule elf32 { condition: elf.machine == elf.EM_PPC }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // Each scan must see the data produced by the module for that scan,
+    // not the data from previous scans.
+    for (data, expected) in [
+        (
+            include_bytes!("../tests/testdata/elf/elf64_x86_64_dyn")
+                .as_slice(),
+            "elf64",
+        ),
+        (
+            include_bytes!("../tests/testdata/elf/elf32_ppc_exec").as_slice(),
+            "elf32",
+        ),
+    ] {
+        let results = scanner.scan(data);
+        let matching: Vec<_> =
+            results.iter().map(|rule| rule.name()).collect();
+        assert_eq!(matching, [expected]);
+    }
+
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 0);
+}
+
+/// Returns the descriptor of a toy module built at runtime, equivalent to:
+///
+/// ```text
+/// syntax = "proto2";
+///
+/// message Toy {
+///   optional int64 size = 1;
+///   optional string magic = 2;
+///   repeated int64 bytes = 3;
+///   optional int64 undef = 4;
+/// }
+/// ```
+fn toy_module_descriptor() -> MessageDescriptor {
+    let field = |name: &str, number, label, ty| {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_string());
+        field.set_number(number);
+        field.set_label(label);
+        field.set_type(ty);
+        field
+    };
+
+    let mut message = DescriptorProto::new();
+    message.set_name("Toy".to_string());
+    message.field = vec![
+        field("size", 1, Label::LABEL_OPTIONAL, Type::TYPE_INT64),
+        field("magic", 2, Label::LABEL_OPTIONAL, Type::TYPE_STRING),
+        field("bytes", 3, Label::LABEL_REPEATED, Type::TYPE_INT64),
+        field("undef", 4, Label::LABEL_OPTIONAL, Type::TYPE_INT64),
+    ];
+
+    let mut file = FileDescriptorProto::new();
+    file.set_name("toy.proto".to_string());
+    file.set_syntax("proto2".to_string());
+    file.message_type = vec![message];
+
+    FileDescriptor::new_dynamic(file, &[])
+        .unwrap()
+        .message_by_package_relative_name("Toy")
+        .unwrap()
+}
+
+#[test]
+fn user_module() {
+    let descriptor = toy_module_descriptor();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .register_module("toy", descriptor.clone())
+        .unwrap()
+        .add_source(
+            r#"
+import "toy"
+rule size { condition: toy.size == filesize }
+rule magic { condition: toy.magic == "abc" }
+rule bytes {
+  condition:
+    toy.bytes.length == 4 and
+    for all i in (0..3) : (toy.bytes[i] == uint8(i))
+}
+rule undef { condition: not defined toy.undef }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // Without a handler every field is undefined.
+    let results = scanner.scan(b"abcd");
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+    assert_eq!(matching, ["undef"]);
+
+    let calls = AtomicUsize::new(0);
+
+    scanner.set_module_handler("toy", |data| {
+        calls.fetch_add(1, Ordering::Relaxed);
+        let mut msg = descriptor.new_instance();
+        descriptor.field_by_name("size").unwrap().set_singular_field(
+            msg.as_mut(),
+            ReflectValueBox::I64(data.len() as i64),
+        );
+        if let Some(magic) = data.get(0..3) {
+            descriptor.field_by_name("magic").unwrap().set_singular_field(
+                msg.as_mut(),
+                ReflectValueBox::String(
+                    String::from_utf8_lossy(magic).to_string(),
+                ),
+            );
+        }
+        let mut bytes = descriptor
+            .field_by_name("bytes")
+            .unwrap()
+            .mut_repeated(msg.as_mut());
+        for b in data.iter().take(4) {
+            bytes.push(ReflectValueBox::I64(*b as i64));
+        }
+        msg
+    });
+
+    let results = scanner.scan(b"abcd");
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+    assert_eq!(matching, ["size", "magic", "bytes", "undef"]);
+
+    // The handler is called on every scan, with the data being scanned.
+    let results = scanner.scan(b"xy");
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+    assert_eq!(matching, ["size", "undef"]);
+
+    drop(scanner);
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn user_module_uint64() {
+    let field = |name: &str, number, label| {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_string());
+        field.set_number(number);
+        field.set_label(label);
+        field.set_type(Type::TYPE_UINT64);
+        field
+    };
+
+    let mut message = DescriptorProto::new();
+    message.set_name("Counters".to_string());
+    message.field = vec![
+        field("values", 1, Label::LABEL_REPEATED),
+        field("value", 2, Label::LABEL_OPTIONAL),
+        field("small_value", 3, Label::LABEL_OPTIONAL),
+    ];
+
+    let mut file = FileDescriptorProto::new();
+    file.set_name("counters.proto".to_string());
+    file.set_syntax("proto2".to_string());
+    file.message_type = vec![message];
+
+    let descriptor = FileDescriptor::new_dynamic(file, &[])
+        .unwrap()
+        .message_by_package_relative_name("Counters")
+        .unwrap();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .register_module("counters", descriptor.clone())
+        .unwrap()
+        .add_source(
+            r#"
+import "counters"
+rule array {
+  condition:
+    counters.values.length == 4 and
+    counters.values[0] == 1 and
+    not defined counters.values[1] and
+    counters.values[2] == 0x7fffffffffffffff and
+    counters.values[3] == 3
+}
+rule singular {
+  condition:
+    not defined counters.value and
+    counters.small_value == 0x7fffffffffffffff
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut msg = descriptor.new_instance();
+
+    // Values that don't fit in an i64 are undefined, both in arrays and in
+    // singular fields. The other items in the array keep their indexes.
+    let mut values =
+        descriptor.field_by_name("values").unwrap().mut_repeated(msg.as_mut());
+
+    values.push(ReflectValueBox::U64(1));
+    values.push(ReflectValueBox::U64(u64::MAX));
+    values.push(ReflectValueBox::U64(i64::MAX as u64));
+    values.push(ReflectValueBox::U64(3));
+
+    descriptor
+        .field_by_name("value")
+        .unwrap()
+        .set_singular_field(msg.as_mut(), ReflectValueBox::U64(u64::MAX));
+
+    descriptor.field_by_name("small_value").unwrap().set_singular_field(
+        msg.as_mut(),
+        ReflectValueBox::U64(i64::MAX as u64),
+    );
+
+    let mut scanner = Scanner::new(&rules);
+    scanner.set_module_output("counters", msg).unwrap();
+
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 2);
+}
+
+#[test]
+fn user_module_handler_with_wrong_type() {
+    let descriptor = toy_module_descriptor();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .register_module("toy", descriptor.clone())
+        .unwrap()
+        .add_source(
+            r#"
+import "toy"
+rule size { condition: toy.size == 0 }
+rule undef { condition: not defined toy.size }"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    scanner.set_module_handler("toy", |_| Box::new(Timestamp::new()));
+
+    // The module is undefined, and the error is reported in the results.
+    let results = scanner.scan(b"");
+    let matching: Vec<_> =
+        results.matching_rules().map(|rule| rule.name()).collect();
+
+    assert_eq!(matching, ["undef"]);
+    assert_eq!(results.module_errors().len(), 1);
+    assert_eq!(
+        results.module_errors()[0].to_string(),
+        "data for module `toy` must be `Toy`, not `google.protobuf.Timestamp`"
+    );
+
+    // Errors are reported only in the scan where they happened.
+    scanner
+        .set_module_handler("toy", |_| toy_module_descriptor().new_instance());
+
+    assert!(scanner.scan(b"").module_errors().is_empty());
+}
+
+#[test]
+fn module_output() {
+    use crate::scanner::ScanError;
+
+    let descriptor = toy_module_descriptor();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .register_module("toy", descriptor.clone())
+        .unwrap()
+        .add_source(r#"import "toy" rule test { condition: toy.size == 5 }"#)
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let toy = |size: i64| {
+        let mut msg = descriptor.new_instance();
+        descriptor
+            .field_by_name("size")
+            .unwrap()
+            .set_singular_field(msg.as_mut(), ReflectValueBox::I64(size));
+        msg
+    };
+
+    let mut scanner = Scanner::new(&rules);
+
+    scanner.set_module_handler("toy", |data| toy(data.len() as i64));
+    scanner.set_module_output("toy", toy(5)).unwrap();
+
+    // The data set with `set_module_output` is used instead of calling the
+    // handler, but only in the next scan.
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 0);
+    assert_eq!(scanner.scan(b"12345").num_matching_rules(), 1);
+
+    scanner
+        .set_module_output_raw("toy", &toy(5).write_to_bytes_dyn().unwrap())
+        .unwrap();
+
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 1);
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 0);
+
+    let err = scanner
+        .set_module_output("toy", Box::new(Timestamp::new()))
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, ScanError::InvalidModuleOutput { .. }));
+    assert_eq!(
+        err.to_string(),
+        "data for module `toy` must be `Toy`, not `google.protobuf.Timestamp`"
+    );
+
+    let err = scanner.set_module_output_raw("toy", b"\xff").err().unwrap();
+
+    assert!(matches!(err, ScanError::ProtoError { .. }));
+    assert_eq!(err.to_string(), "can not decode data for module `toy`");
+
+    let err = scanner.set_module_output("foo", toy(5)).err().unwrap();
+
+    assert!(matches!(err, ScanError::UnknownModule(_)));
+    assert_eq!(err.to_string(), "unknown module `foo`");
+
+    // Failed calls don't set any data.
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 0);
+}
+
+#[test]
+#[cfg(feature = "elf-module")]
+fn builtin_module_output() {
+    use crate::modules::protos::elf::{Machine, ELF};
+
+    let descriptor = toy_module_descriptor();
+
+    let mut compiler = Compiler::new();
+    compiler
+        .register_module("toy", descriptor.clone())
+        .unwrap()
+        .add_source(
+            r#"
+import "elf"
+import "toy"
+rule ppc { condition: elf.machine == elf.EM_PPC }
+rule size { condition: toy.size == 5 }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    let mut elf = ELF::new();
+    elf.set_machine(Machine::EM_PPC as u32);
+
+    // The data of built-in modules can be replaced too.
+    scanner.set_module_output("elf", Box::new(elf)).unwrap();
+
+    let results = scanner.scan(b"");
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+    assert_eq!(matching, ["ppc"]);
+
+    // Modules without data set with `set_module_output` are not affected.
+    let mut toy = descriptor.new_instance();
+    descriptor
+        .field_by_name("size")
+        .unwrap()
+        .set_singular_field(toy.as_mut(), ReflectValueBox::I64(5));
+
+    scanner.set_module_output("toy", toy).unwrap();
+
+    let results =
+        scanner.scan(include_bytes!("../tests/testdata/elf/elf32_ppc_exec"));
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+    assert_eq!(matching, ["ppc", "size"]);
+
+    assert_eq!(scanner.scan(b"").num_matching_rules(), 0);
+}
+
+#[test]
+fn max_matches_per_pattern() {
+    let rules = crate::compile(
+        r#"
+rule count { strings: $a = "ab" condition: #a == 3 }
+rule offset { strings: $a = "ab" condition: @a[3] == 4 }
+rule beyond_limit { strings: $a = "ab" condition: defined @a[4] }
+rule other { strings: $b = "c" condition: $b }
+"#,
+    )
+    .unwrap();
+
+    let data = b"abababababc";
+
+    let mut scanner = Scanner::new(&rules);
+    let results = scanner.scan(data);
+
+    assert!(!results.matches_truncated());
+    assert_eq!(results.num_matching_rules(), 3);
+
+    scanner.set_max_matches_per_pattern(3);
+
+    let results = scanner.scan(data);
+    let matching: Vec<_> = results.iter().map(|rule| rule.name()).collect();
+
+    // The matches with the lowest offsets are kept.
+    assert_eq!(matching, ["count", "offset", "other"]);
+    assert!(results.matches_truncated());
+
+    let output = results.to_owned_output();
+    let truncated: Vec<_> = output
+        .matching_rules
+        .iter()
+        .flat_map(|rule| &rule.patterns)
+        .map(|pattern| (pattern.matches.len(), pattern.matches_truncated))
+        .collect();
+
+    assert_eq!(truncated, [(3, true), (3, true), (1, false)]);
+
+    // The flag doesn't carry over to the next scan.
+    assert!(!scanner.scan(b"ab").matches_truncated());
+}
+
+#[test]
+fn fast_mode() {
+    let rules = crate::compile(
+        r#"
+rule matched { strings: $a = "abc" condition: $a }
+rule set { strings: $a = "bca" $b = "cab" condition: all of them }
+rule count { strings: $a = "ab" condition: #a == 1000 }
+rule offset { strings: $a = "ca" condition: @a[2] == 5 }
+rule length { strings: $a = "cab" condition: !a[3] == 3 }
+rule anchored { strings: $a = "bc" condition: $a at 2998 }
+rule anchored_set { strings: $a = "ab" $b = "abcx" condition: any of them in (3..3) }
+rule for_of { strings: $a = "a" $b = "b" condition: for all of them : (# > 500) }
+rule for_of_match { strings: $a = "c" condition: for any of ($a) : ($) }
+"#,
+    )
+    .unwrap();
+
+    let data = b"abc".repeat(1000);
+
+    let results = |fast_mode| {
+        let mut scanner = Scanner::new(&rules);
+        scanner.set_fast_mode(fast_mode);
+        let results = scanner.scan(&data);
+        results
+            .iter()
+            .map(|rule| {
+                let matches: Vec<_> = rule
+                    .patterns()
+                    .map(|pattern| pattern.matches().len())
+                    .collect();
+                (rule.name(), matches)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        results(false),
+        [
+            ("matched", vec![1000]),
+            ("set", vec![999, 999]),
+            ("count", vec![1000]),
+            ("offset", vec![999]),
+            ("length", vec![999]),
+            ("anchored", vec![1000]),
+            ("anchored_set", vec![1000, 0]),
+            ("for_of", vec![1000, 1000]),
+            ("for_of_match", vec![1000]),
+        ]
+    );
+
+    // The same rules match, but only the patterns whose matches are used
+    // by the conditions have all of them.
+    assert_eq!(
+        results(true),
+        [
+            ("matched", vec![1]),
+            ("set", vec![1, 1]),
+            ("count", vec![1000]),
+            ("offset", vec![999]),
+            ("length", vec![999]),
+            ("anchored", vec![1000]),
+            ("anchored_set", vec![1000, 0]),
+            ("for_of", vec![1000, 1000]),
+            ("for_of_match", vec![1]),
+        ]
+    );
+}
+
+#[test]
+fn thread_safety() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>() {}
+
+    assert_send_sync::<crate::Rules>();
+    assert_send::<Scanner>();
+}
+
+#[test]
+fn concurrent_scans() {
+    let mut compiler = Compiler::new();
+    compiler
+        .add_source(
+            r#"
+rule foo { strings: $a = "foo" condition: $a }
+rule bar { strings: $a = /ba[rz]/ condition: #a == 2 }
+rule size { condition: filesize > 10 }
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let inputs: [&[u8]; 4] =
+        [b"foo", b"bar baz", b"foo bar baz bar", b"nothing here"];
+
+    let expected: Vec<_> = inputs
+        .iter()
+        .map(|data| Scanner::new(&rules).scan(data).to_owned_output())
+        .collect();
+
+    // Each thread has its own scanner, but all of them share the same
+    // compiled rules.
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut scanner = Scanner::new(&rules);
+                    inputs
+                        .iter()
+                        .map(|data| scanner.scan(data).to_owned_output())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    });
+
+    // A scanner can be moved to another thread too.
+    let mut scanner = Scanner::new(&rules);
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            assert_eq!(scanner.scan(inputs[2]).to_owned_output(), expected[2]);
+        });
+    });
+}
+
+#[test]
+fn globals() {
+    use protobuf::MessageFull;
+
+    let mut compiler = Compiler::new();
+
+    compiler
+        .register_module("clock", Timestamp::descriptor())
+        .unwrap()
+        .define_global("max_size", 10)
+        .unwrap()
+        .define_global("file_name", "foo.exe")
+        .unwrap()
+        .add_source(
+            r#"
+import "clock"
+rule small { condition: filesize < max_size }
+rule named { condition: file_name == "foo.exe" and clock.seconds == 0 }
+"#,
+        )
+        .unwrap();
+
+    // Variables defined after the module import have field indexes greater
+    // than the module's index in the root structure.
+    compiler
+        .define_global("enabled", false)
+        .unwrap()
+        .define_global("ratio", 0.5)
+        .unwrap()
+        .add_source(
+            r#"
+rule on { condition: enabled and ratio > 1.0 }
+"#,
+        )
+        .unwrap();
+
+    let rules = compiler.build().unwrap();
+    let mut scanner = Scanner::new(&rules);
+
+    fn names(scanner: &mut Scanner, data: &[u8]) -> Vec<String> {
+        scanner.scan(data).iter().map(|rule| rule.name().to_string()).collect()
+    }
+
+    // Variables that are not set have their default values.
+    assert_eq!(names(&mut scanner, b"foo"), ["small", "named"]);
+
+    scanner
+        .set_global("max_size", 2)
+        .unwrap()
+        .set_global("file_name", String::from("bar.exe"))
+        .unwrap();
+
+    // The same data produces different results with other values.
+    assert_eq!(names(&mut scanner, b"foo"), Vec::<String>::new());
+
+    scanner
+        .set_global("enabled", true)
+        .unwrap()
+        .set_global("ratio", 2.0)
+        .unwrap()
+        .set_global("max_size", 1000_i64)
+        .unwrap();
+
+    assert_eq!(names(&mut scanner, b"foo"), ["small", "on"]);
+
+    // Values are specific to each scanner.
+    assert_eq!(names(&mut Scanner::new(&rules), b"foo"), ["small", "named"]);
+
+    let err = scanner.set_global("max_size", "big").err().unwrap();
+
+    assert!(matches!(err, crate::ScanError::InvalidGlobalType { .. }));
+    assert_eq!(
+        err.to_string(),
+        "global variable `max_size` must be `integer`, not `string`"
+    );
+
+    let err = scanner.set_global("ratio", 2).err().unwrap();
+
+    assert_eq!(
+        err.to_string(),
+        "global variable `ratio` must be `float`, not `integer`"
+    );
+
+    // Modules and built-in functions are not global variables.
+    for name in ["foo", "clock", "uint8"] {
+        let err = scanner.set_global(name, 1).err().unwrap();
+
+        assert!(matches!(err, crate::ScanError::UnknownGlobal(_)));
+        assert_eq!(
+            err.to_string(),
+            format!("unknown global variable `{}`", name)
+        );
+    }
+
+    // Failed calls don't change the values.
+    assert_eq!(names(&mut scanner, b"foo"), ["small", "on"]);
+}
+
+#[test]
+fn scan_with_callback() {
+    use protobuf::MessageFull;
+
+    let mut compiler = Compiler::new();
+
+    compiler
+        .register_module("clock", Timestamp::descriptor())
+        .unwrap()
+        .add_source(
+            r#"
+import "clock"
+rule a { condition: true }
+private rule p { condition: true }
+global rule g { condition: filesize > 2 }
+rule b { condition: true }
+"#,
+        )
+        .unwrap();
+
+    let rules = compiler.build().unwrap();
+    let mut scanner = Scanner::new(&rules);
+
+    // Returns the events sent during a scan, aborting the scan at the
+    // first event for which `abort` returns true.
+    fn events(
+        scanner: &mut Scanner,
+        data: &[u8],
+        abort: impl Fn(&str) -> bool + Sync,
+    ) -> (Vec<String>, usize) {
+        let mut events = Vec::new();
+        let results = scanner.scan_with_callback(data, |event| {
+            let event = match event {
+                ScanEvent::ModuleOutput { name, output } => {
+                    format!(
+                        "output {} {}",
+                        name,
+                        output.descriptor_dyn().name()
+                    )
+                }
+                ScanEvent::RuleMatched(rule) => {
+                    format!("matched {}", rule.name())
+                }
+                ScanEvent::RuleEvaluated(rule) => {
+                    format!("evaluated {}", rule.name())
+                }
+                ScanEvent::GlobalRuleFailed(rule) => {
+                    format!("failed {}", rule.name())
+                }
+                ScanEvent::ScanFinished => "finished".to_string(),
+            };
+            let action = if abort(&event) {
+                CallbackAction::Abort
+            } else {
+                CallbackAction::Continue
+            };
+            events.push(event);
+            action
+        });
+        let num_matching_rules = results.num_matching_rules();
+        (events, num_matching_rules)
+    }
+
+    // The match of `a` is sent once the global rule `g` is known to match.
+    assert_eq!(
+        events(&mut scanner, b"abcd", |_| false),
+        (
+            vec![
+                "output clock Timestamp".to_string(),
+                "evaluated a".to_string(),
+                "evaluated p".to_string(),
+                "matched g".to_string(),
+                "evaluated g".to_string(),
+                "matched a".to_string(),
+                "matched b".to_string(),
+                "evaluated b".to_string(),
+                "finished".to_string(),
+            ],
+            3
+        )
+    );
+
+    // When `g` fails no rule in the namespace is sent as matching.
+    assert_eq!(
+        events(&mut scanner, b"ab", |_| false),
+        (
+            vec![
+                "output clock Timestamp".to_string(),
+                "evaluated a".to_string(),
+                "evaluated p".to_string(),
+                "evaluated g".to_string(),
+                "failed g".to_string(),
+                "evaluated b".to_string(),
+                "finished".to_string(),
+            ],
+            0
+        )
+    );
+
+    // After aborting no more events are sent, and `b` is not evaluated.
+    assert_eq!(
+        events(&mut scanner, b"abcd", |event| event == "matched g"),
+        (
+            vec![
+                "output clock Timestamp".to_string(),
+                "evaluated a".to_string(),
+                "evaluated p".to_string(),
+                "matched g".to_string(),
+            ],
+            2
+        )
+    );
+
+    // The scanner evaluates all the rules again after an aborted scan.
+    assert_eq!(scanner.scan(b"abcd").num_matching_rules(), 3);
+
+    assert_eq!(
+        events(&mut scanner, b"abcd", |event| event.starts_with("output")),
+        (vec!["output clock Timestamp".to_string()], 0)
+    );
+
+    assert_eq!(scanner.scan(b"abcd").num_matching_rules(), 3);
+
+    // Profiling is not affected by scans with callbacks.
+    scanner.set_profiling(true);
+    events(&mut scanner, b"abcd", |_| false);
+    assert!(!scanner.scan(b"abcd").profiling(10).is_empty());
+}
+
+#[test]
+fn scan_with_callback_global_rules() {
+    let mut compiler = Compiler::new();
+
+    compiler
+        .add_source(
+            r#"
+global rule g1 { condition: filesize > 2 }
+global rule g2 { condition: filesize > 3 }
+rule a { condition: true }
+"#,
+        )
+        .unwrap()
+        .new_namespace("other")
+        .add_source(
+            r#"
+global rule g3 { condition: filesize > 1 }
+rule b { condition: true }
+"#,
+        )
+        .unwrap();
+
+    let rules = compiler.build().unwrap();
+    let mut scanner = Scanner::new(&rules);
+
+    let mut failed = |data: &[u8]| {
+        let mut failed = Vec::new();
+        scanner.scan_with_callback(data, |event| {
+            if let ScanEvent::GlobalRuleFailed(rule) = event {
+                failed.push(rule.name());
+            }
+            CallbackAction::Continue
+        });
+        failed
+    };
+
+    // Only the first global rule that fails in each namespace is sent.
+    assert_eq!(failed(b"a"), ["g1", "g3"]);
+    assert_eq!(failed(b"ab"), ["g1"]);
+    assert_eq!(failed(b"abc"), ["g2"]);
+    assert!(failed(b"abcd").is_empty());
+}
+
+#[test]
+fn scan_with_callback_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let rules = crate::compile(
+        r#"
+rule a { condition: true }
+rule b { condition: true }
+"#,
+    )
+    .unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+    let calls = AtomicUsize::new(0);
+
+    // The callback panics while the rules are being evaluated, and also
+    // outside the evaluation, when the scan finishes.
+    for panic_at in ["matched a", "finished"] {
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            scanner.scan_with_callback(b"", |event| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                let event = match event {
+                    ScanEvent::RuleMatched(rule) => {
+                        format!("matched {}", rule.name())
+                    }
+                    ScanEvent::ScanFinished => "finished".to_string(),
+                    _ => String::new(),
+                };
+                if event == panic_at {
+                    panic!("{}", event);
+                }
+                CallbackAction::Continue
+            });
+        }));
+
+        assert!(result.is_err());
+
+        // The callback is not used anymore after the panic.
+        let num_calls = calls.load(Ordering::Relaxed);
+
+        assert_eq!(scanner.scan(b"").num_matching_rules(), 2);
+        assert_eq!(calls.load(Ordering::Relaxed), num_calls);
+    }
+}
+
+#[test]
+fn base_offset() {
+    let rules = crate::compile(
+        r#"
+rule test {
+  strings:
+    $a = "foo"
+  condition:
+    $a at 2
+}
+"#,
+    )
+    .unwrap();
+
+    let mut scanner = Scanner::new(&rules);
+
+    // Returns the base offset, and the range, address and offset in the
+    // owned output of the only match.
+    let offsets = |scanner: &mut Scanner| {
+        let results = scanner.scan(b"xxfoo");
+        let base_offset = results.base_offset();
+        let rule = results.iter().next().unwrap();
+        let pattern = rule.patterns().next().unwrap();
+        let m = &pattern.matches()[0];
+        let output = results.to_owned_output();
+        let offset = output.matching_rules[0].patterns[0].matches[0].offset;
+        (base_offset, m.range.clone(), m.address(), offset)
+    };
+
+    assert_eq!(offsets(&mut scanner), (0, 2..5, 2, 2));
+
+    // The base offset shifts the addresses and the offsets in the owned
+    // output only, the condition `$a at 2` still refers to the scanned
+    // data.
+    scanner.set_base_offset(0x7ff6_0000_0000);
+
+    assert_eq!(
+        offsets(&mut scanner),
+        (0x7ff6_0000_0000, 2..5, 0x7ff6_0000_0002, 0x7ff6_0000_0002)
+    );
+
+    // Offsets that overflow saturate.
+    scanner.set_base_offset(u64::MAX - 1);
+
+    assert_eq!(
+        offsets(&mut scanner),
+        (u64::MAX - 1, 2..5, u64::MAX, u64::MAX)
+    );
+}
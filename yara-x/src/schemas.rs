@@ -0,0 +1,192 @@
+/*! JSON schemas for the JSON documents produced by YARA-X.
+
+Schemas are generated from the Rust types that are serialized, and the
+generated schemas are stored in the `schemas` directory at the root of this
+crate, with one file per version. Those files are what integrators validate
+against, so they are never modified once published. Changing the shape of a
+serialized type requires bumping the corresponding version constant, and
+generating a new file with:
+
+```text
+UPDATE_SCHEMAS=1 cargo test --features=json-schema,migration schemas
+```
+
+The first versions of the schemas are not published until the first
+release. Until then, the current files are regenerated in place instead of
+bumping the versions, see `RELEASED` in the tests.
+
+The tests in this module fail if the types and the files are out of sync,
+if the file for an existing version would be overwritten, or if the
+directory contains files for versions that were never generated. They run
+only with the `json-schema` feature, which is enabled in one of the CI
+configurations.
+*/
+
+/// Version of the schema returned by [`scan_results_schema`].
+pub const SCAN_RESULTS_SCHEMA_VERSION: u32 = 1;
+
+/// Returns the JSON schema for the serialized form of
+/// [`crate::ScanOutput`].
+pub fn scan_results_schema() -> &'static str {
+    include_str!("../schemas/scan-results-v1.json")
+}
+
+/// Version of the schema returned by [`migration_report_schema`].
+#[cfg(feature = "migration")]
+pub const MIGRATION_REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Returns the JSON schema for the serialized form of
+/// [`crate::migration::MigrationReport`].
+#[cfg(feature = "migration")]
+pub fn migration_report_schema() -> &'static str {
+    include_str!("../schemas/migration-report-v1.json")
+}
+
+/// Version of the schema returned by [`build_options_schema`].
+pub const BUILD_OPTIONS_SCHEMA_VERSION: u32 = 1;
+
+/// Returns the JSON schema for the serialized form of
+/// [`crate::BuildOptions`].
+pub fn build_options_schema() -> &'static str {
+    include_str!("../schemas/build-options-v1.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{scan_results_schema, SCAN_RESULTS_SCHEMA_VERSION};
+    use crate::ScanOutput;
+
+    /// Whether the current schemas were published in a release. While
+    /// they are not, changes in the serialized types regenerate the current
+    /// files instead of requiring a new version. Set this to `true` when
+    /// releasing.
+    const RELEASED: bool = false;
+
+    fn check_schema(name: &str, version: u32, published: &str, current: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("schemas")
+            .join(format!("{}-v{}.json", name, version));
+
+        let in_file = std::fs::read_to_string(&path).ok();
+
+        if std::env::var_os("UPDATE_SCHEMAS").is_some() {
+            match in_file {
+                Some(in_file) if in_file != current && RELEASED => panic!(
+                    "{} already exists and can't be modified, bump the schema version",
+                    path.display()
+                ),
+                Some(in_file) if in_file == current => {}
+                _ => std::fs::write(&path, current).unwrap(),
+            }
+            return;
+        }
+
+        assert_eq!(
+            in_file.as_deref(),
+            Some(current),
+            "{} is outdated, bump the schema version and run the tests with UPDATE_SCHEMAS=1",
+            path.display()
+        );
+
+        assert_eq!(
+            published,
+            current,
+            "the published schema must be {}",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn scan_results() {
+        let schema = schemars::schema_for!(ScanOutput);
+        let mut current = serde_json::to_string_pretty(&schema).unwrap();
+        current.push('\n');
+
+        check_schema(
+            "scan-results",
+            SCAN_RESULTS_SCHEMA_VERSION,
+            scan_results_schema(),
+            current.as_str(),
+        );
+    }
+
+    #[test]
+    fn build_options() {
+        use super::{build_options_schema, BUILD_OPTIONS_SCHEMA_VERSION};
+        use crate::BuildOptions;
+
+        let schema = schemars::schema_for!(BuildOptions);
+        let mut current = serde_json::to_string_pretty(&schema).unwrap();
+        current.push('\n');
+
+        check_schema(
+            "build-options",
+            BUILD_OPTIONS_SCHEMA_VERSION,
+            build_options_schema(),
+            current.as_str(),
+        );
+    }
+
+    #[cfg(feature = "migration")]
+    #[test]
+    fn migration_report() {
+        use super::{
+            migration_report_schema, MIGRATION_REPORT_SCHEMA_VERSION,
+        };
+        use crate::migration::MigrationReport;
+
+        let schema = schemars::schema_for!(MigrationReport);
+        let mut current = serde_json::to_string_pretty(&schema).unwrap();
+        current.push('\n');
+
+        check_schema(
+            "migration-report",
+            MIGRATION_REPORT_SCHEMA_VERSION,
+            migration_report_schema(),
+            current.as_str(),
+        );
+    }
+
+    #[test]
+    fn published_schemas() {
+        use super::BUILD_OPTIONS_SCHEMA_VERSION;
+
+        let versions = [
+            ("scan-results", SCAN_RESULTS_SCHEMA_VERSION),
+            ("build-options", BUILD_OPTIONS_SCHEMA_VERSION),
+            #[cfg(feature = "migration")]
+            ("migration-report", super::MIGRATION_REPORT_SCHEMA_VERSION),
+        ];
+
+        let mut expected: Vec<String> = versions
+            .iter()
+            .flat_map(|(name, version)| {
+                (1..=*version).map(move |v| format!("{}-v{}.json", name, v))
+            })
+            .collect();
+
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("schemas");
+
+        let mut in_dir: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            // The migration report is not checked without its feature.
+            .filter(|file_name| {
+                cfg!(feature = "migration")
+                    || !file_name.starts_with("migration-report-")
+            })
+            .collect();
+
+        expected.sort();
+        in_dir.sort();
+
+        // Every version up to the current one was published, and the
+        // directory doesn't contain schemas for versions that don't exist
+        // yet, which can only be written by hand.
+        assert_eq!(in_dir, expected);
+    }
+}
@@ -50,12 +50,25 @@ where
         }
     }
 
+    /// Returns the ID corresponding to `s` if it was previously interned.
+    /// If not returns [`None`].
+    #[inline]
+    pub fn get_id(&self, s: &str) -> Option<T> {
+        self.pool.check_interned(s).map(|s| T::from(s.id()))
+    }
+
     /// Returns the string corresponding to a given `id` if it was previously
     /// interned. If not returns [`None`].
     #[inline]
     pub fn get(&self, id: T) -> Option<&str> {
         self.pool.get(Symbol::from(id.into()))
     }
+
+    /// Returns the number of strings in the pool.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pool.len()
+    }
 }
 
 pub struct BStringPool<T>
@@ -95,6 +108,17 @@ where
         }
     }
 
+    /// Returns the ID corresponding to `s` if it was previously interned.
+    /// If not returns [`None`]. Unlike [`BStringPool::get_or_intern`], this
+    /// doesn't modify the pool.
+    #[inline]
+    pub fn get_id<S>(&self, s: S) -> Option<T>
+    where
+        S: AsRef<[u8]>,
+    {
+        self.pool.check_interned(s.as_ref()).map(|s| T::from(s.id()))
+    }
+
     /// Returns the string corresponding to a given ID if it was previously
     /// interned. If not returns [`None`].
     #[inline]
@@ -102,6 +126,12 @@ where
         self.pool.get(Symbol::from(id.into())).map(BStr::new)
     }
 
+    /// Returns the number of strings in the pool.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pool.len()
+    }
+
     /// Similar to [`BStringPool::get`], but returns the string as `&str`.
     ///
     /// # Panics
@@ -1,6 +1,7 @@
 use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
+use std::sync::Arc;
 
 #[cfg(test)]
 use bstr::{BStr, ByteSlice};
@@ -12,6 +13,11 @@ use crate::compiler::{RuleId, Var};
 /// Trait implemented by types that allow looking up for a symbol.
 pub(crate) trait SymbolLookup {
     fn lookup(&self, ident: &str) -> Option<Symbol>;
+
+    /// Returns the identifiers of all the symbols that can be looked up.
+    /// This is used only for suggesting alternatives to unknown
+    /// identifiers, so it doesn't need to be fast.
+    fn identifiers(&self) -> Vec<String>;
 }
 
 #[derive(Clone)]
@@ -36,7 +42,7 @@ pub(crate) enum SymbolKind {
     /// The symbol refers to a rule.
     Rule(RuleId),
     /// The symbol refers to a function.
-    Func(Rc<Func>),
+    Func(Arc<Func>),
 }
 
 impl Symbol {
@@ -94,6 +100,13 @@ impl SymbolLookup for Option<Symbol> {
             None
         }
     }
+
+    fn identifiers(&self) -> Vec<String> {
+        match self.as_ref().map(|symbol| symbol.type_value()) {
+            Some(TypeValue::Struct(s)) => s.identifiers(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl SymbolLookup for Struct {
@@ -109,6 +122,10 @@ impl SymbolLookup for Struct {
 
         Some(symbol)
     }
+
+    fn identifiers(&self) -> Vec<String> {
+        self.fields().map(|field| field.name.clone()).collect()
+    }
 }
 
 /// A symbol table is a structure used for resolving symbols during the
@@ -124,6 +141,7 @@ impl SymbolLookup for Struct {
 /// [`Symbol`] will be of type [`Type::Struct`], which encapsulates another
 /// object that also implements the [`SymbolLookup`] trait, possibly another
 /// [`SymbolTable`].
+#[derive(Clone)]
 pub(crate) struct SymbolTable {
     map: HashMap<String, Symbol>,
 }
@@ -149,6 +167,12 @@ impl SymbolTable {
     {
         self.map.insert(ident.into(), symbol)
     }
+
+    /// Removes a symbol from the symbol table, returning it if it was in
+    /// the table.
+    pub(crate) fn remove(&mut self, ident: &str) -> Option<Symbol> {
+        self.map.remove(ident)
+    }
 }
 
 impl Default for SymbolTable {
@@ -161,18 +185,30 @@ impl SymbolLookup for SymbolTable {
     fn lookup(&self, ident: &str) -> Option<Symbol> {
         self.map.get(ident).cloned()
     }
+
+    fn identifiers(&self) -> Vec<String> {
+        self.map.keys().cloned().collect()
+    }
 }
 
 impl SymbolLookup for &SymbolTable {
     fn lookup(&self, ident: &str) -> Option<Symbol> {
         self.map.get(ident).cloned()
     }
+
+    fn identifiers(&self) -> Vec<String> {
+        self.map.keys().cloned().collect()
+    }
 }
 
 impl SymbolLookup for RefCell<SymbolTable> {
     fn lookup(&self, ident: &str) -> Option<Symbol> {
         self.borrow().map.get(ident).cloned()
     }
+
+    fn identifiers(&self) -> Vec<String> {
+        self.borrow().identifiers()
+    }
 }
 
 /// A set of stacked symbol tables.
@@ -216,6 +252,17 @@ impl<'a> StackedSymbolTable<'a> {
     pub(crate) fn pop(&mut self) -> Option<Rc<dyn SymbolLookup + 'a>> {
         self.stack.pop_back()
     }
+
+    /// Returns the number of symbol tables in the stack.
+    pub(crate) fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Removes symbol tables from the top of the stack until it contains
+    /// `len` tables. Does nothing if the stack has `len` tables or less.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.stack.truncate(len)
+    }
 }
 
 impl<'a> SymbolLookup for StackedSymbolTable<'a> {
@@ -232,6 +279,10 @@ impl<'a> SymbolLookup for StackedSymbolTable<'a> {
         // The symbol was not found in any of the symbol tables..
         None
     }
+
+    fn identifiers(&self) -> Vec<String> {
+        self.stack.iter().flat_map(|t| t.identifiers()).collect()
+    }
 }
 
 #[cfg(test)]
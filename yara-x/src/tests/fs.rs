@@ -0,0 +1,198 @@
+/*! Filesystem fixtures for tests of file-oriented APIs.
+
+A [`FileTree`] declares a tree of files, directories and symlinks, which is
+materialized in a fresh temporary directory by [`FileTree::create`]. The
+directory is removed when the returned [`TempDir`] is dropped, so tests
+don't interfere with each other even when they run in parallel.
+
+```text
+let dir = FileTree::new()
+    .file("rules/a.yar", "rule a { condition: true }")
+    .sized_file("data/big.bin", 1 << 20)
+    .symlink("latest.yar", "rules/a.yar")
+    .create();
+
+scanner.scan_file(dir.path().join("data/big.bin"))?;
+```
+
+The same tree can be kept in memory instead, with [`FileTree::into_reader`].
+The returned [`MemoryReader`] is a [`FileReader`] that can be passed to
+[`crate::Compiler::set_file_reader`], so tests of the compiler's logic for
+source files and includes don't touch the file system at all.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use tempfile::TempDir;
+
+use crate::compiler::{DirEntry, FileReader};
+
+/// An entry in a [`FileTree`].
+enum Entry {
+    File(Vec<u8>),
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// Builder for a tree of files that is created in a temporary directory.
+///
+/// Paths are relative to the root of the tree and use `/` as separator.
+/// Parent directories are created as needed, and entries are created in
+/// the order they were declared.
+#[derive(Default)]
+pub(crate) struct FileTree {
+    entries: Vec<(PathBuf, Entry)>,
+}
+
+impl FileTree {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file with the given content.
+    pub fn file<P: AsRef<Path>, C: AsRef<[u8]>>(
+        mut self,
+        path: P,
+        content: C,
+    ) -> Self {
+        self.entries.push((
+            path.as_ref().to_path_buf(),
+            Entry::File(content.as_ref().to_vec()),
+        ));
+        self
+    }
+
+    /// Adds a file of `size` bytes. The content is a repeating sequence of
+    /// all byte values, so that it is the same every time.
+    pub fn sized_file<P: AsRef<Path>>(self, path: P, size: usize) -> Self {
+        let content: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        self.file(path, content)
+    }
+
+    /// Adds an empty directory.
+    pub fn dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.entries.push((path.as_ref().to_path_buf(), Entry::Dir));
+        self
+    }
+
+    /// Adds a symbolic link pointing to `target`. The target is stored as
+    /// is, a relative target is relative to the directory containing the
+    /// link, and it doesn't need to exist.
+    pub fn symlink<P: AsRef<Path>, T: AsRef<Path>>(
+        mut self,
+        path: P,
+        target: T,
+    ) -> Self {
+        self.entries.push((
+            path.as_ref().to_path_buf(),
+            Entry::Symlink(target.as_ref().to_path_buf()),
+        ));
+        self
+    }
+
+    /// Creates the tree in a new temporary directory.
+    ///
+    /// # Panics
+    ///
+    /// If any of the entries can't be created.
+    pub fn create(self) -> TempDir {
+        let dir = tempfile::Builder::new()
+            .prefix("yara-x-test-")
+            .tempdir()
+            .expect("can't create temporary directory");
+
+        for (path, entry) in self.entries {
+            let path = dir.path().join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).unwrap();
+            }
+            match entry {
+                Entry::File(content) => fs::write(&path, content),
+                Entry::Dir => fs::create_dir_all(&path),
+                Entry::Symlink(target) => symlink(&target, &path),
+            }
+            .unwrap_or_else(|err| {
+                panic!("can't create `{}`: {}", path.display(), err)
+            });
+        }
+
+        dir
+    }
+
+    /// Returns a [`FileReader`] that reads the tree from memory.
+    ///
+    /// Paths passed to the reader are relative to the root of the tree,
+    /// which is the empty path.
+    pub fn into_reader(self) -> MemoryReader {
+        let mut entries = BTreeMap::new();
+        for (path, entry) in self.entries {
+            // Parent directories exist even if they were not declared.
+            for parent in path.ancestors().skip(1) {
+                entries.entry(parent.to_path_buf()).or_insert(Entry::Dir);
+            }
+            entries.insert(path, entry);
+        }
+        MemoryReader { entries }
+    }
+}
+
+/// A [`FileReader`] for a [`FileTree`] kept in memory, see
+/// [`FileTree::into_reader`].
+pub(crate) struct MemoryReader {
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl MemoryReader {
+    /// Returns the entry at `path`, following symbolic links.
+    fn entry(&self, path: &Path) -> io::Result<&Entry> {
+        let mut path = path.to_path_buf();
+        // Limits the number of links followed, as links can form cycles.
+        for _ in 0..32 {
+            match self.entries.get(&path) {
+                Some(Entry::Symlink(target)) => {
+                    path = path.parent().unwrap_or(Path::new("")).join(target)
+                }
+                Some(entry) => return Ok(entry),
+                None => return Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+        Err(io::Error::other("too many symbolic links"))
+    }
+}
+
+impl FileReader for MemoryReader {
+    fn read(&mut self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.entry(path)? {
+            Entry::File(content) => Ok(content.clone()),
+            _ => Err(io::Error::other("not a file")),
+        }
+    }
+
+    fn read_dir(&mut self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        if !matches!(self.entry(path)?, Entry::Dir) {
+            return Err(io::Error::other("not a directory"));
+        }
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(p, _)| p.parent() == Some(path))
+            .map(|(p, entry)| DirEntry {
+                path: p.clone(),
+                is_dir: matches!(entry, Entry::Dir),
+            })
+            .collect())
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
@@ -1,6 +1,11 @@
 /*! End-to-end tests.*/
 use pretty_assertions::assert_eq;
 
+pub(crate) mod fs;
+mod regression;
+#[cfg(feature = "test_proto2-module")]
+mod soak;
+
 macro_rules! test_condition {
     ($condition:literal, $data:expr, $expected_result:expr) => {{
         let mut src = String::new();
@@ -13,15 +18,41 @@ macro_rules! test_condition {
             src.push_str(r#"import "test_proto3""#);
         }
 
+        if cfg!(feature = "console-module") {
+            src.push_str(r#"import "console""#);
+        }
+
+        if cfg!(feature = "elf-module") {
+            src.push_str(r#"import "elf""#);
+        }
+
+        if cfg!(feature = "hash-module") {
+            src.push_str(r#"import "hash""#);
+        }
+
+        if cfg!(feature = "math-module") {
+            src.push_str(r#"import "math""#);
+        }
+
+        if cfg!(feature = "pe-module") {
+            src.push_str(r#"import "pe""#);
+        }
+
+        if cfg!(feature = "string-module") {
+            src.push_str(r#"import "string""#);
+        }
+
+        if cfg!(feature = "time-module") {
+            src.push_str(r#"import "time""#);
+        }
+
         src.push_str(
             format!("rule t {{condition: {} }}", $condition).as_str(),
         );
 
-        let rules = crate::compiler::Compiler::new()
-            .add_source(src.as_str())
-            .unwrap()
-            .build()
-            .unwrap();
+        let mut compiler = crate::compiler::Compiler::new();
+        compiler.add_source(src.as_str()).unwrap();
+        let rules = compiler.build().unwrap();
 
         let num_matching_rules = crate::scanner::Scanner::new(&rules)
             .scan($data)
@@ -55,11 +86,9 @@ macro_rules! condition_false {
 
 macro_rules! test_rule {
     ($rule:expr,  $data:expr, $expected_result:expr) => {{
-        let rules = crate::compiler::Compiler::new()
-            .add_source($rule)
-            .unwrap()
-            .build()
-            .unwrap();
+        let mut compiler = crate::compiler::Compiler::new();
+        compiler.add_source($rule).unwrap();
+        let rules = compiler.build().unwrap();
 
         let num_matching_rules = crate::scanner::Scanner::new(&rules)
             .scan($data)
@@ -162,6 +191,204 @@ fn test_comparison_operationd() {
     condition_true!("1.0 < 1.000000000000001");
 }
 
+#[test]
+#[cfg(feature = "test_proto2-module")]
+fn comparison_type_promotion() {
+    use std::collections::BTreeSet;
+
+    use crate::compiler::{CompileErrorInfo, Compiler, Error};
+    use crate::scanner::Scanner;
+
+    // Numeric operands, either constant or only known at scan time, and
+    // their values. `None` means that the operand is undefined.
+    let numeric = [
+        ("2", Some(2.0)),
+        ("2.5", Some(2.5)),
+        ("test_proto2.int64_one", Some(1.0)),
+        ("test_proto2.double_one", Some(1.0)),
+        ("test_proto2.int64_undef", None),
+        ("test_proto2.double_undef", None),
+    ];
+
+    type Cmp = fn(&f64, &f64) -> bool;
+
+    let operators: [(&str, Cmp); 6] = [
+        ("==", f64::eq),
+        ("!=", f64::ne),
+        ("<", f64::lt),
+        ("<=", f64::le),
+        (">", f64::gt),
+        (">=", f64::ge),
+    ];
+
+    // Conditions that compile, and whether they are true or not. They are
+    // compiled as a single source and scanned at once, as building the
+    // rules for each condition separately is too slow.
+    let mut cases: Vec<(String, bool)> = Vec::new();
+
+    for (lhs, lhs_value) in numeric {
+        for (rhs, rhs_value) in numeric {
+            for (op, cmp) in operators {
+                let expected = match (lhs_value, rhs_value) {
+                    (Some(lhs), Some(rhs)) => cmp(&lhs, &rhs),
+                    _ => false,
+                };
+                cases.push((format!("{} {} {}", lhs, op, rhs), expected));
+            }
+        }
+    }
+
+    // Strings are compared with other strings, byte by byte.
+    let strings = [
+        (r#""bar""#, Some("bar")),
+        (r#""foo""#, Some("foo")),
+        ("test_proto2.string_bar", Some("bar")),
+        ("test_proto2.string_foo", Some("foo")),
+        ("test_proto2.string_undef", None),
+    ];
+
+    type StrCmp = fn(&str, &str) -> bool;
+
+    let str_operators: [(&str, StrCmp); 6] = [
+        ("==", str::eq),
+        ("!=", str::ne),
+        ("<", str::lt),
+        ("<=", str::le),
+        (">", str::gt),
+        (">=", str::ge),
+    ];
+
+    for (lhs, lhs_value) in strings {
+        for (rhs, rhs_value) in strings {
+            for (op, cmp) in str_operators {
+                let expected = match (lhs_value, rhs_value) {
+                    (Some(lhs), Some(rhs)) => cmp(lhs, rhs),
+                    _ => false,
+                };
+                cases.push((format!("{} {} {}", lhs, op, rhs), expected));
+            }
+        }
+    }
+
+    // Booleans can be compared for equality with other booleans.
+    cases
+        .push(("test_proto2.bool_yara == test_proto2.bool_yara".into(), true));
+    cases.push((
+        "test_proto2.bool_yara != test_proto2.bool_yara".into(),
+        false,
+    ));
+
+    let mut src = String::from(r#"import "test_proto2""#);
+
+    for (i, (condition, _)) in cases.iter().enumerate() {
+        src.push_str(&format!(
+            "\nrule case_{} {{ condition: {} }}",
+            i, condition
+        ));
+    }
+
+    let mut compiler = Compiler::new();
+    compiler.add_source(src.as_str()).unwrap();
+    let rules = compiler.build().unwrap();
+    let mut scanner = Scanner::new(&rules);
+    let scan_results = scanner.scan(&[]);
+
+    let matching: BTreeSet<&str> = scan_results
+        .matching_rules()
+        .map(|rule| {
+            let i: usize =
+                rule.name().strip_prefix("case_").unwrap().parse().unwrap();
+            cases[i].0.as_str()
+        })
+        .collect();
+
+    let expected: BTreeSet<&str> = cases
+        .iter()
+        .filter(|(_, expected)| *expected)
+        .map(|(condition, _)| condition.as_str())
+        .collect();
+
+    assert_eq!(matching, expected);
+
+    // Conditions that don't compile are checked with a single compiler, as
+    // the failed sources don't change it.
+    let mut compiler = Compiler::new();
+
+    let mut compile_error = |condition: &str| -> Option<Error> {
+        let src = format!(
+            r#"import "test_proto2" rule t {{ condition: {} }}"#,
+            condition
+        );
+        compiler.add_source(src.as_str()).err()
+    };
+
+    // Strings can't be mixed with numeric operands, not even when any of
+    // them is undefined.
+    for (string, _) in strings {
+        for (number, _) in numeric {
+            for (op, _) in operators {
+                for condition in [
+                    format!("{} {} {}", string, op, number),
+                    format!("{} {} {}", number, op, string),
+                ] {
+                    assert!(
+                        matches!(
+                            compile_error(condition.as_str()),
+                            Some(Error::CompileError(err)) if matches!(
+                                err.info(),
+                                CompileErrorInfo::MismatchingTypes { .. }
+                            )
+                        ),
+                        "`{}`",
+                        condition
+                    );
+                }
+            }
+        }
+    }
+
+    // Booleans can't be ordered.
+    for op in ["<", "<=", ">", ">="] {
+        let condition =
+            format!("test_proto2.bool_yara {} test_proto2.bool_yara", op);
+        assert!(
+            matches!(
+                compile_error(condition.as_str()),
+                Some(Error::CompileError(err))
+                    if matches!(err.info(), CompileErrorInfo::WrongType { .. })
+            ),
+            "`{}`",
+            condition
+        );
+    }
+
+    // Strings and booleans can't be mixed with other types.
+    for (lhs, rhs) in [
+        ("1", "test_proto2.bool_yara"),
+        ("test_proto2.bool_yara", "test_proto2.double_one"),
+        ("test_proto2.string_foo", "1"),
+        ("1.0", "test_proto2.string_foo"),
+        ("test_proto2.bool_yara", "test_proto2.string_foo"),
+        ("test_proto2.bool_yara", "test_proto2.int64_undef"),
+        ("test_proto2.string_undef", "test_proto2.bool_yara"),
+    ] {
+        for op in ["==", "!="] {
+            let condition = format!("{} {} {}", lhs, op, rhs);
+            assert!(
+                matches!(
+                    compile_error(condition.as_str()),
+                    Some(Error::CompileError(err)) if matches!(
+                        err.info(),
+                        CompileErrorInfo::MismatchingTypes { .. }
+                    )
+                ),
+                "`{}`",
+                condition
+            );
+        }
+    }
+}
+
 #[test]
 fn bitwise_operations() {
     condition_true!("0x55 | 0xAA == 0xFF");
@@ -179,6 +406,16 @@ fn bitwise_operations() {
     condition_true!("1 << 65 == 0");
     condition_true!("1 >> 65 == 0");
     condition_true!("1 | 3 ^ 3 != (1 | 3) ^ 3");
+
+    // Shifting by a negative number of bits is undefined.
+    rule_false!("rule test { condition: 1 << (filesize - 1) != 0 }", b"");
+    rule_false!("rule test { condition: 1 >> (filesize - 1) == 0 }", b"");
+    rule_true!("rule test { condition: 8 >> filesize == 4 }", b"a");
+    rule_true!("rule test { condition: 1 << (filesize + 63) == 0 }", b"a");
+    rule_true!(
+        "rule test { condition: (filesize >> 4) & 1 == 1 and ~filesize == -17 }",
+        &[0; 16]
+    );
 }
 
 #[test]
@@ -213,6 +450,43 @@ fn string_operations() {
     condition_true!(r#""foo" iequals "FOO""#);
     condition_true!(r#""foo" iequals "FoO""#);
     condition_false!(r#""foo" iequals "bar""#);
+
+    // Case-insensitive operations fold ASCII characters only.
+    condition_true!(r#""FOO\xc3\x96" iequals "foo\xc3\x96""#);
+    condition_false!(r#""\xc3\x96" iequals "\xc3\xb6""#);
+    condition_false!(r#""\xc3\x96" icontains "\xc3\xb6""#);
+}
+
+#[test]
+fn matches_operator() {
+    condition_true!(r#""foo" matches /fo+/"#);
+    condition_true!(r#""foo" matches /^foo$/"#);
+    condition_true!(r#""foo/bar" matches /o\/b/"#);
+    condition_true!(r#""fooo" matches /^fo{,3}$/"#);
+    condition_true!(r#""f{,3}" matches /f[{,3}]+/"#);
+    condition_true!(r#""foo" matches /FOO/i"#);
+    condition_true!(r#""f\no" matches /f.o/s"#);
+    condition_true!(r#""\xff\x00" matches /^\xff.$/"#);
+    condition_false!(r#""foo" matches /FOO/"#);
+    condition_false!(r#""foo" matches /^oo/"#);
+    condition_false!(r#""f\no" matches /f.o/"#);
+
+    // Case-insensitive regexps fold ASCII characters only.
+    condition_false!(r#""\xc3\x96" matches /\xc3\xb6/i"#);
+
+    condition_true!(r#"test_proto2.string_foo matches /fo+/"#);
+    condition_true!(r#"test_proto2.string_bar matches /BAR/i"#);
+    condition_false!(r#"test_proto2.string_bar matches /BAR/"#);
+
+    let data = b"f\noo";
+
+    condition_true!(r#"test_proto2.head(4) matches /f.oo/s"#, data);
+    condition_true!(r#"test_proto2.head(4) matches /o+$/"#, data);
+    condition_false!(r#"test_proto2.head(4) matches /f.oo/"#, data);
+
+    // `head(5)` is undefined, and so is the result of `matches`.
+    condition_false!(r#"test_proto2.head(5) matches /./"#, data);
+    condition_false!(r#"not test_proto2.head(5) matches /./"#, data);
 }
 
 #[test]
@@ -226,6 +500,27 @@ fn boolean_operations() {
     condition_false!("not true");
     condition_true!("true or (false and false)");
     condition_false!("not (true or true)");
+
+    rule_false!(
+        r#"rule test { strings: $a = "foo" condition: false and $a }"#,
+        b"foo"
+    );
+    rule_true!(
+        r#"rule test { strings: $a = "foo" condition: $a or 1 }"#,
+        b"bar"
+    );
+    rule_false!(
+        r#"rule test { strings: $a = "foo" condition: filesize < 0 and $a }"#,
+        b"foo"
+    );
+    rule_true!(
+        r#"rule test { strings: $a = "foo" condition: $a or #a >= 0 }"#,
+        b"bar"
+    );
+    rule_true!(
+        r#"rule test { strings: $a = "foo" condition: $a and filesize >= 3 }"#,
+        b"foo"
+    );
 }
 
 #[test]
@@ -290,6 +585,153 @@ fn uintxx() {
     condition_false!("uint64(3) != 0", &data);
 }
 
+#[test]
+fn intxx() {
+    let data = [0x01, 0x80, 0xFF, 0xFE, 0x7F, 0x00, 0x00, 0x80];
+
+    condition_true!("int8(0) == 1", &data);
+    condition_true!("int8(1) == -128", &data);
+    condition_true!("int8(2) == -1", &data);
+    condition_true!("int8be(2) == -1", &data);
+
+    condition_true!("int16(0) == -32767", &data);
+    condition_true!("int16(2) == -257", &data);
+    condition_true!("int16be(0) == 0x0180", &data);
+    condition_true!("int16be(2) == -2", &data);
+
+    condition_true!("int32(0) == -16809983", &data);
+    condition_true!("int32(4) == -2147483521", &data);
+    condition_true!("int32be(0) == 0x0180FFFE", &data);
+    condition_true!("int32be(4) == 0x7F000080", &data);
+
+    // The unsigned variants of the same values.
+    condition_true!("uint8(1) == 0x80", &data);
+    condition_true!("uint16(2) == 0xFEFF", &data);
+    condition_true!("uint32(4) == 0x8000007F", &data);
+}
+
+#[test]
+fn intxx_out_of_bounds() {
+    let data = [0x01, 0x02, 0x03, 0x04];
+
+    // The last byte can be read, but reading past the end of the data, or
+    // at a negative offset, is undefined.
+    for (function, size) in [
+        ("uint8", 1),
+        ("uint16", 2),
+        ("uint32", 4),
+        ("uint16be", 2),
+        ("uint32be", 4),
+        ("int8", 1),
+        ("int16", 2),
+        ("int32", 4),
+        ("int16be", 2),
+        ("int32be", 4),
+    ] {
+        let last = data.len() - size;
+
+        rule_true!(
+            format!("rule test {{ condition: defined {function}({last}) }}")
+                .as_str(),
+            &data
+        );
+        rule_false!(
+            format!(
+                "rule test {{ condition: defined {function}({}) }}",
+                last + 1
+            )
+            .as_str(),
+            &data
+        );
+        rule_false!(
+            format!("rule test {{ condition: defined {function}(filesize) }}")
+                .as_str(),
+            &data
+        );
+        rule_false!(
+            format!(
+                "rule test {{ condition: defined {function}(filesize - 5) }}"
+            )
+            .as_str(),
+            &data
+        );
+        rule_false!(
+            format!("rule test {{ condition: {function}(filesize) == 0 }}")
+                .as_str(),
+            &data
+        );
+        rule_false!(
+            format!("rule test {{ condition: {function}(filesize) != 0 }}")
+                .as_str(),
+            &data
+        );
+    }
+
+    condition_true!("uint8(filesize - 1) == 0x04", &data);
+    condition_true!("int8(filesize - 1) == 0x04", &data);
+    condition_true!("uint16be(filesize - 2) == 0x0304", &data);
+    condition_true!("int32be(filesize - 4) == 0x01020304", &data);
+}
+
+#[test]
+fn entrypoint() {
+    // PE file with a single section that contains the entry point.
+    let mut pe = vec![0_u8; 0x400];
+    pe[0..2].copy_from_slice(b"MZ");
+    pe[0x3c..0x40].copy_from_slice(&0x80_u32.to_le_bytes());
+    pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+    // Number of sections.
+    pe[0x86..0x88].copy_from_slice(&1_u16.to_le_bytes());
+    // Size of the optional header.
+    pe[0x94..0x96].copy_from_slice(&0xe0_u16.to_le_bytes());
+    // Entry point.
+    pe[0xa8..0xac].copy_from_slice(&0x1010_u32.to_le_bytes());
+    // Virtual size, virtual address, raw size and raw offset of the section.
+    pe[0x180..0x184].copy_from_slice(&0x100_u32.to_le_bytes());
+    pe[0x184..0x188].copy_from_slice(&0x1000_u32.to_le_bytes());
+    pe[0x188..0x18c].copy_from_slice(&0x200_u32.to_le_bytes());
+    pe[0x18c..0x190].copy_from_slice(&0x200_u32.to_le_bytes());
+
+    rule_true!("rule test { condition: entrypoint == 0x210 }", &pe);
+
+    // 64-bits little-endian ELF file with a single loadable segment.
+    let mut elf = vec![0_u8; 0x100];
+    elf[0..6].copy_from_slice(b"\x7fELF\x02\x01");
+    elf[24..32].copy_from_slice(&0x400080_u64.to_le_bytes());
+    elf[32..40].copy_from_slice(&64_u64.to_le_bytes());
+    elf[54..56].copy_from_slice(&56_u16.to_le_bytes());
+    elf[56..58].copy_from_slice(&1_u16.to_le_bytes());
+    elf[64..68].copy_from_slice(&1_u32.to_le_bytes());
+    elf[80..88].copy_from_slice(&0x400000_u64.to_le_bytes());
+    elf[96..104].copy_from_slice(&0x100_u64.to_le_bytes());
+
+    rule_true!("rule test { condition: entrypoint == 0x80 }", &elf);
+
+    // 32-bits big-endian ELF file with a single loadable segment.
+    let mut elf = vec![0_u8; 0x100];
+    elf[0..6].copy_from_slice(b"\x7fELF\x01\x02");
+    elf[24..28].copy_from_slice(&0x8048010_u32.to_be_bytes());
+    elf[28..32].copy_from_slice(&52_u32.to_be_bytes());
+    elf[42..44].copy_from_slice(&32_u16.to_be_bytes());
+    elf[44..46].copy_from_slice(&1_u16.to_be_bytes());
+    elf[52..56].copy_from_slice(&1_u32.to_be_bytes());
+    elf[60..64].copy_from_slice(&0x8048000_u32.to_be_bytes());
+    elf[68..72].copy_from_slice(&0x100_u32.to_be_bytes());
+
+    rule_true!("rule test { condition: entrypoint == 0x10 }", &elf);
+
+    // The entry point is outside the only loadable segment.
+    elf[68..72].copy_from_slice(&0x10_u32.to_be_bytes());
+
+    rule_false!("rule test { condition: defined entrypoint }", &elf);
+
+    // Truncated PE and ELF files, and data that is not an executable.
+    rule_false!("rule test { condition: defined entrypoint }", &pe[..0x100]);
+    rule_false!("rule test { condition: defined entrypoint }", &elf[..40]);
+    rule_false!("rule test { condition: defined entrypoint }", b"foo");
+    rule_false!("rule test { condition: defined entrypoint }");
+}
+
 #[test]
 fn for_in() {
     condition_true!("for all i in (0..0) : ( true )");
@@ -327,12 +769,25 @@ fn for_in() {
     condition_true!("for 10% i in (0..9) : ( i == 0 )");
     condition_false!("for 11% i in (0..9) : ( i == 0 )");
 
-    // If the range's lower bound is greater than the upper bound
-    // the `for` loop is always false.
-    condition_false!("for all i in (5..2) : ( true )");
-    condition_false!("for all i in (5..2) : ( false )");
-    condition_false!("for none i in (5..2) : ( true )");
-    condition_false!("for none i in (5..2) : ( false )");
+    // If the range's lower bound is greater than the upper bound the
+    // `for` loop doesn't iterate, and the result is the one of a quantifier
+    // applied to zero items.
+    condition_true!("for all i in (5..2) : ( true )");
+    condition_true!("for all i in (5..2) : ( false )");
+    condition_true!("for none i in (5..2) : ( true )");
+    condition_true!("for none i in (5..2) : ( false )");
+    condition_false!("for any i in (5..2) : ( true )");
+    condition_true!("for 0 i in (5..2) : ( true )");
+    condition_false!("for 1 i in (5..2) : ( true )");
+    condition_true!("for 50% i in (5..2) : ( true )");
+    condition_true!("for all i in (0..filesize-1) : ( i < 0 )");
+    condition_false!("for any i in (0..filesize-1) : ( i == 0 )");
+    condition_true!("for any i in (0..filesize-1) : ( i == 2 )", b"foo");
+    condition_false!("for any i in (0..filesize-1) : ( i == 3 )", b"foo");
+
+    condition_true!(r#"for any i in (1, 3, 5) : (i == 3)"#);
+    condition_false!(r#"for any i in (1, 3, 5) : (i == 2)"#);
+    condition_true!(r#"for all i in (1, 3, 5) : (i % 2 == 1)"#);
 
     condition_true!(r#"for any e in (1,2,3) : (e == 3)"#);
     condition_true!(r#"for any e in (1+1,2+2) : (e == 2)"#);
@@ -350,6 +805,45 @@ fn text_patterns() {
     pattern_true!(r#""IssI" nocase"#, b"mississippi");
 }
 
+#[test]
+fn wide_patterns() {
+    pattern_true!(r#""issi" wide"#, b"m\x00i\x00s\x00s\x00i\x00");
+    pattern_false!(r#""issi" wide"#, b"mississippi");
+    pattern_true!(r#""issi" ascii"#, b"mississippi");
+    pattern_false!(r#""issi" ascii"#, b"m\x00i\x00s\x00s\x00i\x00");
+    pattern_true!(r#""issi" wide ascii"#, b"mississippi");
+    pattern_true!(r#""issi" wide ascii"#, b"m\x00i\x00s\x00s\x00i\x00");
+    pattern_true!(r#""IssI" wide nocase"#, b"m\x00i\x00s\x00s\x00i\x00");
+    pattern_false!(r#""IssI" wide nocase"#, b"mississippi");
+    pattern_true!(r#""issi" wide xor"#, b"l\x01h\x01r\x01r\x01h\x01");
+    pattern_false!(r#""issi" wide xor"#, b"lhrrh");
+}
+
+#[test]
+fn fullword() {
+    pattern_true!(r#""domain" fullword"#, b"domain");
+    pattern_true!(r#""domain" fullword"#, b"domain.");
+    pattern_true!(r#""domain" fullword"#, b".domain");
+    pattern_true!(r#""domain" fullword"#, b"mydomain domain");
+    pattern_false!(r#""domain" fullword"#, b"mydomain");
+    pattern_false!(r#""domain" fullword"#, b"domain1");
+    pattern_false!(r#""domain" fullword"#, b"mydomain domain1");
+    pattern_true!(r#""domain" nocase fullword"#, b"DOMAIN.");
+    pattern_false!(r#""domain" nocase fullword"#, b"myDOMAIN");
+    pattern_true!(r#""domain" wide fullword"#, b"d o m a i n ");
+    pattern_true!(r#""domain" wide fullword"#, b"d o m a i n . ");
+    // The byte before the match is alphanumeric, but it is not followed by
+    // zero, so it is not a wide alphanumeric character.
+    pattern_true!(r#""domain" wide fullword"#, b"yd o m a i n ");
+    pattern_false!(r#""domain" wide fullword"#, b"m y d o m a i n ");
+    pattern_false!(r#""domain" wide fullword"#, b"d o m a i n 1 ");
+    // With `xor` the surrounding bytes are decoded with the same key as
+    // the match. Here the key is 1, and "A" is decoded as "@", while "1" is
+    // decoded as "0".
+    pattern_true!(r#""domain" xor fullword"#, b"enl`hoA");
+    pattern_false!(r#""domain" xor fullword"#, b"enl`ho1");
+}
+
 #[test]
 fn xor() {
     pattern_true!(r#""mississippi" xor"#, b"lhrrhrrhqqh");
@@ -358,6 +852,12 @@ fn xor() {
         r#""mississippi" xor(255)"#,
         &[0x92, 0x96, 0x8C, 0x8C, 0x96, 0x8C, 0x8C, 0x96, 0x8F, 0x8F, 0x96]
     );
+    pattern_true!(
+        r#""mississippi" xor(0x10-0x1f)"#,
+        b"\x7dy\x63\x63y\x63\x63y``y"
+    );
+    pattern_false!(r#""mississippi" xor(0x10-0x1f)"#, b"mississippi");
+    pattern_true!(r#""mississippi" xor(0-0x1f)"#, b"mississippi");
 }
 
 #[test]
@@ -531,11 +1031,38 @@ fn base64() {
             base64("./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789")"#,
         b"X\x00k\x007\x00t\x00W\x00k\x00D\x00w\x00"
     );
+
+    // The characters at both ends of each base64 permutation depend on the
+    // bytes that surround the pattern, and they are not part of the atoms.
+    // However, matches are verified by decoding the data, so patterns that
+    // differ only in those characters don't produce false positives.
+    pattern_true!(
+        r#""This program cannot" base64"#,
+        b"QVRoaXMgcHJvZ3JhbSBjYW5ub3Q" // base64("AThis program cannot")
+    );
+
+    pattern_false!(
+        r#""Dhis program cannow" base64"#,
+        b"QVRoaXMgcHJvZ3JhbSBjYW5ub3Q" // base64("AThis program cannot")
+    );
+
+    pattern_true!(
+        r#""This program cannot" base64wide"#,
+        // wide(base64("AThis program cannot"))
+        b"Q\x00V\x00R\x00o\x00a\x00X\x00M\x00g\x00c\x00H\x00J\x00v\x00Z\x003\x00J\x00h\x00b\x00S\x00B\x00j\x00Y\x00W\x005\x00u\x00b\x003\x00Q\x00"
+    );
+
+    pattern_false!(
+        r#""This program cannot" base64wide"#,
+        // Same as above, but one of the zeroes was replaced with 0x01.
+        b"Q\x00V\x00R\x00o\x00a\x00X\x00M\x00g\x00c\x00H\x00J\x00v\x00Z\x013\x00J\x00h\x00b\x00S\x00B\x00j\x00Y\x00W\x005\x00u\x00b\x003\x00Q\x00"
+    );
 }
 
 #[test]
 fn filesize() {
-    let rules = crate::compiler::Compiler::new()
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
         .add_source(
             r#"
         rule filesize_0 {
@@ -548,19 +1075,22 @@ fn filesize() {
         }
         "#,
         )
-        .unwrap()
-        .build()
         .unwrap();
+    let rules = compiler.build().unwrap();
 
     let mut scanner = crate::scanner::Scanner::new(&rules);
 
     assert_eq!(scanner.scan(&[]).num_matching_rules(), 1);
     assert_eq!(scanner.scan(&[1]).num_matching_rules(), 1);
+
+    rule_false!("rule test { condition: filesize > 100 }", &[0; 50]);
+    rule_true!("rule test { condition: filesize > 100 }", &[0; 200]);
 }
 
 #[test]
 fn for_of() {
-    let rules = crate::compiler::Compiler::new()
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
         .add_source(
             r#"
         rule test {
@@ -570,20 +1100,274 @@ fn for_of() {
           condition:
             for none of ($a, $b) : ($)
         }
+        rule test_them {
+          strings:
+            $a = "foo"
+            $b = "bar"
+          condition:
+            for all of them : ($)
+        }
         "#,
         )
-        .unwrap()
-        .build()
         .unwrap();
+    let rules = compiler.build().unwrap();
 
     let mut scanner = crate::scanner::Scanner::new(&rules);
 
     assert_eq!(scanner.scan(&[]).num_matching_rules(), 1);
+    assert_eq!(scanner.scan(b"foobar").num_matching_rules(), 1);
+}
+
+#[test]
+fn for_of_nested() {
+    let rule = r#"
+        rule test {
+          strings:
+            $a = "foo"
+            $b = "bar"
+            $c = "baz"
+          condition:
+            for all of ($a, $b) : ( for any of ($c) : ( $ ) and $ )
+        }
+        "#;
+
+    rule_true!(rule, b"foobarbaz");
+    rule_false!(rule, b"foobar");
+    rule_false!(rule, b"foobaz");
+
+    // In the inner loop `$` refers to the patterns in ($b, $c), after the
+    // inner loop it refers to $a again.
+    let rule = r#"
+        rule test {
+          strings:
+            $a = "foo"
+            $b = "bar"
+            $c = "baz"
+          condition:
+            for any of ($a) : ( for all of ($b, $c) : ( $ ) and not $ )
+        }
+        "#;
+
+    rule_true!(rule, b"barbaz");
+    rule_false!(rule, b"foobarbaz");
+    rule_false!(rule, b"bar");
+
+    // `for .. in` loops inside `for .. of` don't change the meaning of `$`.
+    rule_true!(
+        r#"
+        rule test {
+          strings:
+            $a = "foo"
+            $b = "bar"
+          condition:
+            for 2 of ($a, $b) : ( for all i in (1..3) : ( $ ) )
+        }
+        "#,
+        b"foobar"
+    );
+}
+
+#[test]
+fn pattern_count_offset_length() {
+    let data = b"foo bar foo baz foofoo";
+
+    // All patterns must be used, the condition is extended with a term
+    // that uses them without changing the result.
+    macro_rules! test {
+        ($condition:literal, $expected:expr) => {{
+            test_rule!(
+                concat!(
+                    r#"rule test {
+                      strings:
+                        $a = "foo"
+                        $b = "bar"
+                        $c = "qux"
+                        $d = "foofoo"
+                      condition: ("#,
+                    $condition,
+                    ") or (any of them and false) }"
+                ),
+                data,
+                $expected
+            );
+        }};
+    }
+
+    test!("#a == 4", true);
+    test!("#b == 1", true);
+    test!("#c == 0", true);
+    test!("#a in (0..7) == 1", true);
+    test!("#a in (8..100) == 3", true);
+    test!("#a in (9..15) == 0", true);
+    test!("#a in (16..19) == 2", true);
+    test!("#a in (-10..0) == 1", true);
+    test!("#a in (10..0) == 0", true);
+
+    test!("@a == 0", true);
+    test!("@a[1] == 0", true);
+    test!("@a[2] == 8", true);
+    test!("@a[4] == 19", true);
+    test!("@b[1] == 4", true);
+    test!("@a[1] < @b[1] and @b[1] < @a[2]", true);
+
+    test!("!a == 3", true);
+    test!("!a[4] == 3", true);
+    test!("!d[1] == 6", true);
+
+    // Indexes past the number of matches are undefined.
+    test!("@a[5] == 0", false);
+    test!("@a[5] != 0", false);
+    test!("!a[5] == 0", false);
+    test!("defined @a[4]", true);
+    test!("defined @a[5]", false);
+    test!("defined @c", false);
+    test!("defined !c", false);
+    test!("for all i in (1..#a) : ( @a[i] + !a[i] <= filesize )", true);
+    test!("for all i in (1..#a + 1) : ( @a[i] >= 0 )", false);
+    test!("for any i in (1..#a + 1) : ( @a[i] == 19 )", true);
+
+    // The anonymous forms refer to the pattern being iterated.
+    test!("for all of ($a, $b) : ( # >= 1 and @ < 10 and ! == 3 )", true);
+    test!("for any of ($a, $b, $c) : ( # == 1 and @[1] == 4 )", true);
+    test!("for all of ($a, $b) : ( # in (0..5) == 1 )", true);
+}
+
+#[test]
+fn pattern_at_in() {
+    let data = b"foo bar foo baz foofoo";
+
+    macro_rules! test {
+        ($condition:literal, $expected:expr) => {{
+            test_rule!(
+                concat!(
+                    r#"rule test {
+                      strings:
+                        $a = "foo"
+                        $b = "bar"
+                      condition: ("#,
+                    $condition,
+                    ") or ($a and $b and false) }"
+                ),
+                data,
+                $expected
+            );
+        }};
+    }
+
+    // `at` must look beyond the first match.
+    test!("$a at 0", true);
+    test!("$a at 8", true);
+    test!("$a at 16", true);
+    test!("$a at 19", true);
+    test!("$a at 1", false);
+    test!("$a at 22", false);
+    test!("$a at @a[1] - 1", false);
+    test!("$b at 4", true);
+    test!("$b at 2 + 2", true);
+    test!("$b at @a[2] - 4", true);
+
+    test!("$a in (1..7)", false);
+    test!("$a in (1..8)", true);
+    test!("$a in (17..18)", false);
+    test!("$a in (17..19)", true);
+    test!("$a in (-10..0)", true);
+    test!("$a in (-10..-1)", false);
+    test!("$b in (0..filesize)", true);
+
+    // Reversed ranges never contain any offset.
+    test!("$a in (8..0)", false);
+    test!("$a in (8..8)", true);
+    test!("not $a in (8..0)", true);
+}
+
+#[test]
+fn extreme_offsets() {
+    let data = b"foo bar foo";
+
+    macro_rules! test {
+        ($condition:literal, $expected:expr) => {{
+            test_rule!(
+                concat!(
+                    r#"rule test {
+                      strings:
+                        $a = "foo"
+                      condition: ("#,
+                    $condition,
+                    ") or ($a and false) }"
+                ),
+                data,
+                $expected
+            );
+        }};
+    }
+
+    // Offsets and indexes at the limits of `i64` never reach the data, and
+    // must not wrap around into it.
+    test!("$a at 0x7fffffffffffffff", false);
+    test!("$a in (0..0x7fffffffffffffff)", true);
+    test!("$a in (0x7fffffffffffffff..0x7fffffffffffffff)", false);
+    test!("$a in (-0x7fffffffffffffff..0)", true);
+    test!("#a in (0..0x7fffffffffffffff) == 2", true);
+    test!("#a in (-0x7fffffffffffffff..-1) == 0", true);
+    test!("defined @a[0x7fffffffffffffff]", false);
+    test!("defined !a[0x7fffffffffffffff]", false);
+    test!("defined uint8(0x7fffffffffffffff)", false);
+    test!("defined uint64(0x7fffffffffffffff)", false);
+    test!("@a[2] + 0x7ffffffffffffff0 > filesize", true);
+    test!("filesize == 11", true);
+
+    // `uint64` values that don't fit in an `i64` wrap around.
+    condition_true!(
+        "uint64(0) == -1",
+        &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+    );
+    condition_true!(
+        "uint64be(0) == -0x7fffffffffffffff - 1",
+        &[0x80, 0, 0, 0, 0, 0, 0, 0]
+    );
+}
+
+#[cfg(feature = "test_proto2-module")]
+#[test]
+fn pattern_at_in_undefined() {
+    let data = b"foo";
+
+    // Undefined offsets and bounds make the whole expression undefined,
+    // which is false even when negated.
+    for condition in [
+        "$a at test_proto2.int64_undef",
+        "not $a at test_proto2.int64_undef",
+        "$a in (test_proto2.int64_undef..10)",
+        "not $a in (0..test_proto2.int64_undef)",
+    ] {
+        let rule = format!(
+            r#"import "test_proto2"
+            rule test {{
+              strings:
+                $a = "foo"
+              condition: {}
+            }}"#,
+            condition
+        );
+        test_rule!(rule.as_str(), data, false);
+    }
+
+    test_rule!(
+        r#"import "test_proto2"
+        rule test {
+          strings:
+            $a = "foo"
+          condition: $a at test_proto2.int64_zero
+        }"#,
+        data,
+        true
+    );
 }
 
 #[test]
 fn of() {
-    let rules = crate::compiler::Compiler::new()
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
         .add_source(
             r#"
         rule test_1 {
@@ -608,18 +1392,249 @@ fn of() {
         }
         "#,
         )
-        .unwrap()
-        .build()
         .unwrap();
+    let rules = compiler.build().unwrap();
 
     let mut scanner = crate::scanner::Scanner::new(&rules);
 
     assert_eq!(scanner.scan(&[]).num_matching_rules(), 3);
 }
 
+#[test]
+fn of_pattern_set() {
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .add_source(
+            r#"
+        rule any_of_them {
+          strings:
+            $a = "foo"
+            $b = "bar"
+          condition:
+            any of them
+        }
+        rule all_of_wildcard {
+          strings:
+            $a1 = "foo"
+            $a2 = "bar"
+            $b = "qux"
+          condition:
+            all of ($a*) and not $b
+        }
+        rule two_of_mixed {
+          strings:
+            $foo_1 = "foo"
+            $foo_2 = "qux"
+            $bar = "bar"
+            $baz = "baz"
+          condition:
+            2 of ($foo_*, $bar) and not $baz
+        }
+        "#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+
+    let matching = |scanner: &mut crate::scanner::Scanner, data: &[u8]| {
+        scanner
+            .scan(data)
+            .iter()
+            .map(|rule| rule.name().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert!(matching(&mut scanner, b"").is_empty());
+    assert_eq!(matching(&mut scanner, b"foo"), vec!["any_of_them"]);
+    assert_eq!(
+        matching(&mut scanner, b"foo bar"),
+        vec!["any_of_them", "all_of_wildcard", "two_of_mixed"]
+    );
+    assert_eq!(
+        matching(&mut scanner, b"qux bar"),
+        vec!["any_of_them", "two_of_mixed"]
+    );
+    assert_eq!(
+        matching(&mut scanner, b"foo bar baz"),
+        vec!["any_of_them", "all_of_wildcard"]
+    );
+}
+
+#[test]
+fn percentage_of() {
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .add_source(
+            r#"
+        rule half_of_three {
+          strings:
+            $a1 = "foo"
+            $a2 = "bar"
+            $a3 = "baz"
+          condition:
+            50% of ($a*)
+        }
+        rule variable_percentage {
+          strings:
+            $a = "foo"
+            $b = "bar"
+            $c = "baz"
+            $d = "qux"
+          condition:
+            (filesize - 10)% of them
+        }
+        "#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+
+    let matching = |scanner: &mut crate::scanner::Scanner, data: &[u8]| {
+        scanner
+            .scan(data)
+            .iter()
+            .map(|rule| rule.name().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // 50% of 3 patterns is 1.5, which is rounded up to 2.
+    assert!(matching(&mut scanner, b"foo").is_empty());
+    assert_eq!(matching(&mut scanner, b"foo bar"), vec!["half_of_three"]);
+
+    // With 35 bytes the percentage is 25%, and 1 of the 4 patterns is
+    // enough. With 36 bytes it is 26%, which requires 2 patterns.
+    let data = [b"qux".as_slice(), &[b'.'; 32]].concat();
+    assert_eq!(matching(&mut scanner, &data), vec!["variable_percentage"]);
+
+    let data = [b"qux".as_slice(), &[b'.'; 33]].concat();
+    assert!(matching(&mut scanner, &data).is_empty());
+}
+
+#[test]
+fn count_of() {
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .enable_extensions(true)
+        .add_source(
+            r#"
+        rule test_1 {
+          strings:
+            $a1 = "foo"
+            $a2 = "bar"
+            $a3 = "qux"
+            $b1 = "baz"
+          condition:
+            count_of($a*) == 2 and count_of(them) == 3
+        }
+        rule test_2 {
+          strings:
+            $a1 = "foo"
+            $a2 = "bar"
+          condition:
+            count_of($a1, $a2) * 10 + filesize == 31
+        }
+        rule test_3 {
+          strings:
+            $a1 = "foo"
+            $a2 = "bar"
+            $a3 = "qux"
+          condition:
+            for all i in (1..count_of(them)) : ( i <= 2 )
+        }
+        rule test_4 {
+          strings:
+            $a1 = "foo"
+            $a2 = "bar"
+            $a3 = "qux"
+          condition:
+            for 3 i in (1..count_of(them)) : ( true )
+        }
+        rule test_5 {
+          strings:
+            $a1 = "foo"
+            $b1 = "qux"
+          condition:
+            $a1 and count_of($b*) == 0
+        }
+        "#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+    let results = scanner.scan(b"foo bar baz");
+
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["test_1", "test_2", "test_3", "test_5"]
+    );
+}
+
+#[test]
+#[cfg(feature = "test_proto2-module")]
+fn string_sets() {
+    let mut names: Vec<String> =
+        (0..100_000).map(|i| format!("domain{}.com", i)).collect();
+
+    names.push("foo".to_string());
+    names.push("baz".to_string());
+
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .enable_extensions(true)
+        .define_string_set("names", names.iter())
+        .unwrap()
+        .define_string_set("empty", Vec::<&[u8]>::new())
+        .unwrap()
+        .add_source(
+            r#"
+        import "test_proto2"
+        rule test_1 {
+          condition:
+            test_proto2.string_foo in names
+        }
+        rule test_2 {
+          condition:
+            test_proto2.string_bar in names
+        }
+        rule test_3 {
+          condition:
+            "domain99999.com" in names and not "domain100000.com" in names
+        }
+        rule test_4 {
+          condition:
+            for 2 s in test_proto2.array_string : ( s in names )
+        }
+        rule test_5 {
+          condition:
+            test_proto2.string_foo in empty
+        }
+        rule test_6 {
+          strings:
+            $a = "domain"
+          condition:
+            $a and not test_proto2.string_bar in names
+        }
+        "#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+    let results = scanner.scan(b"domain");
+
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["test_1", "test_3", "test_4", "test_6"]
+    );
+}
+
 #[test]
 fn rule_reuse() {
-    let rules = crate::compiler::Compiler::new()
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
         .add_source(
             r#"
         rule rule_1 {
@@ -660,13 +1675,78 @@ fn rule_reuse() {
         }
         "#,
         )
-        .unwrap()
-        .build()
         .unwrap();
+    let rules = compiler.build().unwrap();
 
     let mut scanner = crate::scanner::Scanner::new(&rules);
 
     assert_eq!(scanner.scan(&[]).num_matching_rules(), 9);
+
+    // Rules that don't match must be false when referenced by other rules,
+    // regardless of the rules that matched before them.
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .add_source(
+            r#"
+        rule rule_1 { condition: true }
+        rule rule_2 { condition: false }
+        rule rule_3 { condition: rule_1 and not rule_2 }
+        "#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+    let results = scanner.scan(&[]);
+
+    assert_eq!(
+        results.iter().map(|r| r.name()).collect::<Vec<_>>(),
+        vec!["rule_1", "rule_3"]
+    );
+}
+
+#[test]
+fn namespaces() {
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .add_source("rule a { condition: true }")
+        .unwrap()
+        .new_namespace("foo")
+        .add_source("rule a { condition: false }")
+        .unwrap()
+        // Going back to the default namespace, where `a` is true.
+        .new_namespace(crate::DEFAULT_NAMESPACE)
+        .add_source("rule b { condition: a }")
+        .unwrap()
+        // Going back to `foo`, where `a` is false.
+        .new_namespace("foo")
+        .add_source("rule c { condition: not a }")
+        .unwrap()
+        .new_namespace("bar")
+        .add_source("rule a { condition: true }")
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+    let results = scanner.scan(&[]);
+
+    assert_eq!(
+        results
+            .iter()
+            .map(|r| format!("{}:{}", r.namespace(), r.name()))
+            .collect::<Vec<_>>(),
+        vec!["default:a", "default:b", "foo:c", "bar:a"]
+    );
+
+    // A rule in a namespace is not visible from other namespaces, even
+    // if they are created again.
+    assert!(crate::compiler::Compiler::new()
+        .add_source("rule a { condition: true }")
+        .unwrap()
+        .new_namespace("foo")
+        .new_namespace("foo")
+        .add_source("rule b { condition: a }")
+        .is_err());
 }
 
 #[test]
@@ -689,6 +1769,58 @@ fn test_defined_2() {
     condition_true!(
         r#"defined (for any x in (0..10) : (test_proto2.undef_i64() == 0))"#
     );
+
+    // An undefined bound makes the whole loop undefined.
+    condition_false!(
+        r#"defined (for all x in (0..test_proto2.undef_i64()) : (true))"#
+    );
+    condition_false!(
+        r#"defined (for none x in (test_proto2.int64_undef..10) : (true))"#
+    );
+    condition_false!(r#"for all x in (0..test_proto2.int64_undef) : (true)"#);
+    condition_false!(
+        r#"not for all x in (0..test_proto2.int64_undef) : (true)"#
+    );
+
+    // Fields that are present are defined regardless of their values,
+    // even if they are zero, false or an empty string.
+    condition_true!(r#"defined test_proto2.int64_zero"#);
+    condition_true!(r#"defined test_proto2.float_one"#);
+    condition_true!(r#"defined test_proto2.string_foo"#);
+    condition_true!(r#"defined test_proto2.bool_yara"#);
+    condition_true!(r#"defined test_proto2.array_bool[0]"#);
+    condition_true!(r#"defined test_proto2.map_string_bool["foo"]"#);
+    condition_true!(r#"defined test_proto2.head(0)"#);
+
+    // Absent fields of every type.
+    condition_false!(r#"defined test_proto2.int64_undef"#);
+    condition_false!(r#"defined test_proto2.float_undef"#);
+    condition_false!(r#"defined test_proto2.string_undef"#);
+    condition_false!(r#"defined test_proto2.array_bool[2]"#);
+    condition_false!(r#"defined test_proto2.map_string_bool["bar"]"#);
+    condition_false!(r#"defined test_proto2.head(1)"#);
+
+    // `defined` produces a value that is never undefined, so it can be
+    // combined with other expressions.
+    condition_true!(
+        r#"not defined test_proto2.int64_undef and test_proto2.int64_one == 1"#
+    );
+    condition_true!(
+        r#"defined test_proto2.int64_undef or defined test_proto2.int64_one"#
+    );
+    condition_false!(
+        r#"defined test_proto2.int64_undef and test_proto2.int64_undef == 0"#
+    );
+    condition_true!(
+        r#"defined test_proto2.int64_one and test_proto2.int64_one < 0x1000"#
+    );
+    condition_true!(r#"defined (defined test_proto2.int64_undef)"#);
+    condition_true!(
+        r#"not defined (test_proto2.int64_undef + test_proto2.int64_one)"#
+    );
+    condition_true!(
+        r#"for all x in (0..2) : (not defined test_proto2.array_int64[x + 3])"#
+    );
 }
 
 #[test]
@@ -706,11 +1838,25 @@ fn test_defined_3() {
     condition_true!(r#"test_proto3.string_undef == """#);
 }
 
+#[test]
+#[cfg(feature = "test_proto3-module")]
+fn test_proto3_module() {
+    // The values of the fields are the ones set by the module, default
+    // values are used only for fields that were not set.
+    condition_true!(r#"test_proto3.int64_one == 1"#);
+    condition_true!(r#"test_proto3.uint64_one == 1"#);
+    condition_true!(r#"test_proto3.float_one == 1.0"#);
+    condition_true!(r#"test_proto3.string_foo == "foo""#);
+    condition_false!(r#"test_proto3.int64_one == 0"#);
+    condition_true!(r#"test_proto3.int64_zero == 0"#);
+}
+
 #[test]
 #[cfg(feature = "test_proto2-module")]
 fn test_proto2_module() {
     condition_true!(r#"test_proto2.add(1,2) == 3"#);
     condition_true!(r#"test_proto2.add(1.0,2.0) == 3.0"#);
+    condition_true!(r#"test_proto2.add(1,2,3) == 6"#);
 
     condition_true!(r#"test_proto2.uppercase("foo") == "FOO""#);
     condition_true!(r#"test_proto2.nested.nested_func()"#);
@@ -722,6 +1868,16 @@ fn test_proto2_module() {
     condition_false!(r#"test_proto2.undef_i64() == 0"#);
     condition_false!(r#"test_proto2.undef_i64() != 0"#);
 
+    // Functions invoked with undefined arguments return undefined.
+    condition_false!(r#"defined test_proto2.add(test_proto2.int64_undef, 1)"#);
+    condition_false!(
+        r#"defined test_proto2.add(test_proto2.float_undef, 1.0)"#
+    );
+    condition_false!(
+        r#"defined test_proto2.uppercase(test_proto2.string_undef)"#
+    );
+    condition_false!(r#"defined test_proto2.head(test_proto2.undef_i64())"#);
+
     condition_true!(r#"test_proto2.int64_zero == 0"#);
     condition_true!(r#"test_proto2.int64_one == 1"#);
     condition_true!(r#"test_proto2.int64_one + test_proto2.int64_zero == 1"#);
@@ -766,6 +1922,26 @@ fn test_proto2_module() {
     condition_true!(r#"test_proto2.string_bar istartswith "BAR""#);
     condition_true!(r#"test_proto2.string_bar iequals "BAR""#);
 
+    // The same operations with strings that are not known at compile time.
+    // "FOO\xc3\x96" is "FOOÖ", and case-insensitive operations fold ASCII
+    // characters only, so "Ö" and "ö" are different.
+    let data = b"FOO\xc3\x96";
+
+    condition_true!(r#"test_proto2.head(5) icontains "o\xc3\x96""#, data);
+    condition_true!(r#"test_proto2.head(5) istartswith "fo""#, data);
+    condition_true!(r#"test_proto2.head(5) iendswith "O\xc3\x96""#, data);
+    condition_true!(r#"test_proto2.head(5) iequals "foo\xc3\x96""#, data);
+    condition_false!(r#"test_proto2.head(5) icontains "\xc3\xb6""#, data);
+    condition_false!(r#"test_proto2.head(5) iendswith "\xc3\xb6""#, data);
+    condition_false!(r#"test_proto2.head(5) iequals "foo\xc3\xb6""#, data);
+    condition_false!(r#"test_proto2.head(5) istartswith "foo ""#, data);
+    condition_false!(r#"test_proto2.head(5) iendswith " foo\xc3\x96""#, data);
+
+    // `head(6)` is undefined, and so is any operation that uses it.
+    condition_false!(r#"test_proto2.head(6) contains """#, data);
+    condition_false!(r#"not test_proto2.head(6) contains "x""#, data);
+    condition_false!(r#"not "foo" istartswith test_proto2.head(6)"#, data);
+
     condition_true!(r#"test_proto2.array_int64[0] == 1"#);
     condition_true!(r#"test_proto2.array_int64[1] == 10"#);
     condition_true!(r#"test_proto2.array_int64[2] == 100"#);
@@ -798,6 +1974,34 @@ fn test_proto2_module() {
         r#"test_proto2.array_struct[0].nested_array_int64[1] == 10"#
     );
 
+    // The `length` field of arrays is the number of items in the array.
+    condition_true!(r#"test_proto2.array_int64.length == 3"#);
+    condition_true!(r#"test_proto2.array_float.length == 3"#);
+    condition_true!(r#"test_proto2.array_bool.length == 2"#);
+    condition_true!(r#"test_proto2.array_string.length == 3"#);
+    condition_true!(r#"test_proto2.array_struct.length == 1"#);
+    condition_true!(
+        r#"test_proto2.array_struct[0].nested_array_int64.length == 3"#
+    );
+    condition_true!(
+        r#"test_proto2.array_int64[test_proto2.array_int64.length - 1] == 100"#
+    );
+    condition_true!(
+        r#"for all i in (0..test_proto2.array_int64.length - 1) : (
+             defined test_proto2.array_int64[i]
+           )"#
+    );
+    condition_true!(
+        r#"for any s in test_proto2.array_struct : (
+             s.nested_array_int64.length == 3
+           )"#
+    );
+
+    // `array_struct[1]` is undefined, and so is its length.
+    condition_false!(
+        r#"defined test_proto2.array_struct[1].nested_array_int64.length"#
+    );
+
     condition_true!(r#"test_proto2.Enumeration.ITEM_0 == 0"#);
     condition_true!(r#"test_proto2.Enumeration.ITEM_1 == 1"#);
 
@@ -805,6 +2009,9 @@ fn test_proto2_module() {
         r#"test_proto2.TopLevelEnumeration.ITEM_0x1000 == 0x1000"#
     );
 
+    condition_true!(r#"test_proto2.INLINE_0x1000 == 0x1000"#);
+    condition_true!(r#"test_proto2.INLINE_0x2000 == 0x2000"#);
+
     condition_true!(r#"test_proto2.map_string_int64["one"] == 1"#);
 
     condition_true!(
@@ -834,6 +2041,28 @@ fn test_proto2_module() {
 
     condition_true!(r#"test_proto2.map_string_string["foo"] == "FOO""#);
 
+    condition_true!(r#"test_proto2.map_string_float["foo"] == 1.5"#);
+    condition_true!(r#"test_proto2.map_int64_float[100] == 1.5"#);
+
+    condition_true!(r#"test_proto2.map_string_string["foo"] icontains "foo""#);
+
+    condition_true!(
+        r#"test_proto2.map_string_struct["foo"].nested_array_int64[0] == 1"#
+    );
+
+    condition_true!(
+        r#"test_proto2.map_int64_struct[test_proto2.map_string_int64["one"] * 100].nested_int64_one == 1"#
+    );
+
+    // Lookups with keys that are not in the map return undefined.
+    condition_false!(r#"defined test_proto2.map_int64_int64[1]"#);
+    condition_false!(
+        r#"defined test_proto2.map_int64_struct[1].nested_int64_one"#
+    );
+    condition_false!(
+        r#"test_proto2.map_string_string["bar"] icontains "foo""#
+    );
+
     condition_true!(r#"for any i in test_proto2.array_int64 : (i == 10)"#);
     condition_true!(r#"for all i in test_proto2.array_int64 : (i < 10000)"#);
     condition_true!(r#"for any s in test_proto2.array_string : (s == "foo")"#);
@@ -881,6 +2110,12 @@ fn test_proto2_module() {
           )"#
     );
 
+    condition_true!(
+        r#"for any key, value in test_proto2.map_int64_float : (
+                key == 100 and value == 1.5
+          )"#
+    );
+
     condition_true!(
         r#"for any key, value in test_proto2.map_int64_struct : (
                 key == 100 and value.nested_int64_one == 1
@@ -905,6 +2140,12 @@ fn test_proto2_module() {
           )"#
     );
 
+    condition_true!(
+        r#"for any key, value in test_proto2.map_string_float : (
+                key == "foo" and value == 1.5
+          )"#
+    );
+
     condition_true!(
         r#"for any key, value in test_proto2.map_string_struct : (
                 key == "foo" and value.nested_int64_one == 1
@@ -918,3 +2159,699 @@ fn test_proto2_module() {
     //
     condition_true!(r#"test_proto2.bool_yara"#);
 }
+
+#[test]
+fn map_iteration_order() {
+    use crate::modules::protos::test_proto2::TestProto2;
+    use yara_x_parser::types::Struct;
+
+    let mut test = TestProto2::new();
+
+    for i in 0..100 {
+        test.map_string_int64.insert(format!("key{:03}", i), i);
+        test.map_int64_int64.insert(100 - i, i);
+    }
+
+    // Protobuf maps are hash maps, their order can change from one instance
+    // to the next, but keys must be sorted in the structure.
+    for _ in 0..2 {
+        let module = Struct::from_proto_msg(Box::new(test.clone()), true);
+
+        let map = module.field_by_name("map_string_int64").unwrap();
+        let keys: Vec<_> = map
+            .type_value
+            .as_map()
+            .with_string_keys()
+            .keys()
+            .map(|k| k.to_string())
+            .collect();
+
+        let expected: Vec<_> =
+            (0..100).map(|i| format!("key{:03}", i)).collect();
+
+        assert_eq!(keys, expected);
+
+        let map = module.field_by_name("map_int64_int64").unwrap();
+        let keys: Vec<_> = map
+            .type_value
+            .as_map()
+            .with_integer_keys()
+            .keys()
+            .copied()
+            .collect();
+
+        assert_eq!(keys, (1..=100).collect::<Vec<i64>>());
+    }
+}
+
+#[test]
+#[cfg(feature = "test_proto2-module")]
+fn max_loop_nesting() {
+    use crate::compiler::MAX_LOOP_NESTING;
+
+    // Loops over maps with a percentage quantifier use the largest number
+    // of variables, nesting them up to the limit must be fine.
+    let mut condition = String::from("v0 == 1");
+
+    for i in 0..MAX_LOOP_NESTING {
+        condition = format!(
+            "for 100% k{i}, v{i} in test_proto2.map_string_int64 : ({})",
+            condition.replace("v0 == 1", &format!("v{i} == 1"))
+        );
+    }
+
+    rule_true!(format!(
+        r#"import "test_proto2" rule test {{ condition: {} }}"#,
+        condition
+    )
+    .as_str());
+}
+
+#[test]
+#[cfg(feature = "console-module")]
+fn console_module() {
+    condition_true!(r#"console.log("foo")"#);
+    condition_true!(r#"console.log(1)"#);
+    condition_true!(r#"console.log(1.5)"#);
+    condition_true!(r#"console.log("foo: ", "bar")"#);
+    condition_true!(r#"console.log("foo: ", 1)"#);
+    condition_true!(r#"console.log("foo: ", 1.5)"#);
+    condition_true!(
+        r#"console.log("size: ", filesize) and filesize == 3"#,
+        b"foo"
+    );
+    condition_false!(
+        r#"console.log("size: ", filesize) and filesize == 4"#,
+        b"foo"
+    );
+}
+
+#[test]
+#[cfg(all(feature = "console-module", feature = "test_proto2-module"))]
+fn console_module_with_undefined_arguments() {
+    // Undefined arguments don't prevent the function from being called.
+    condition_true!(r#"console.log(test_proto2.int64_undef)"#);
+    condition_true!(r#"console.log(test_proto2.float_undef)"#);
+    condition_true!(r#"console.log(test_proto2.string_undef)"#);
+    condition_true!(r#"console.log(test_proto2.string_undef, 1)"#);
+    condition_true!(r#"console.log("foo: ", test_proto2.int64_undef)"#);
+    condition_true!(r#"defined console.log(test_proto2.int64_undef)"#);
+
+    // Function calls don't affect the field lookups that follow them.
+    condition_true!(r#"console.log("foo") and test_proto2.int64_one == 1"#);
+}
+
+#[test]
+#[cfg(feature = "hash-module")]
+fn hash_module() {
+    let data = b"The quick brown fox jumps over the lazy dog";
+
+    condition_true!(
+        r#"hash.md5(0, filesize) == "9e107d9d372bb6826bd81d3542a419d6""#,
+        data
+    );
+    condition_true!(
+        r#"hash.sha1(0, filesize) == "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12""#,
+        data
+    );
+    condition_true!(
+        r#"hash.sha256(0, filesize) == "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592""#,
+        data
+    );
+    condition_true!(r#"hash.crc32(0, filesize) == 0x414fa339"#, data);
+    condition_true!(r#"hash.checksum32(0, filesize) == 0xfd9"#, data);
+
+    // Hashing a block of the data is the same as hashing a string with
+    // the same content.
+    condition_true!(r#"hash.md5(4, 5) == hash.md5("quick")"#, data);
+    condition_true!(r#"hash.sha1(4, 5) == hash.sha1("quick")"#, data);
+    condition_true!(r#"hash.sha256(4, 5) == hash.sha256("quick")"#, data);
+    condition_true!(r#"hash.crc32(4, 5) == hash.crc32("quick")"#, data);
+    condition_true!(
+        r#"hash.checksum32(4, 5) == hash.checksum32("quick")"#,
+        data
+    );
+
+    condition_true!(
+        r#"hash.md5("abc") == "900150983cd24fb0d6963f7d28e17f72""#
+    );
+    condition_true!(r#"hash.md5("") == "d41d8cd98f00b204e9800998ecf8427e""#);
+    condition_true!(r#"hash.md5(0, 0) == "d41d8cd98f00b204e9800998ecf8427e""#);
+    condition_true!(r#"hash.crc32("") == 0"#);
+
+    // Repeated calls with the same arguments return the cached digest.
+    condition_true!(
+        r#"hash.sha256(0, 3) == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+            and hash.sha256(0, 3) == hash.sha256("abc")
+            and hash.sha256(1, 2) != hash.sha256(0, 3)
+            and hash.sha1(0, 3) != hash.sha256(0, 3)"#,
+        b"abcd"
+    );
+
+    // Blocks extending beyond the end of the data, and negative offsets or
+    // sizes are undefined.
+    condition_false!(r#"defined hash.md5(0, filesize + 1)"#, data);
+    condition_false!(r#"defined hash.sha1(filesize, 1)"#, data);
+    condition_false!(r#"defined hash.sha256(-1, 2)"#, data);
+    condition_false!(r#"defined hash.crc32(0, -1)"#, data);
+    condition_false!(r#"defined hash.checksum32(1, filesize)"#, data);
+}
+
+#[test]
+#[cfg(feature = "math-module")]
+fn math_module() {
+    let all_bytes: Vec<u8> = (0..=255).collect();
+
+    condition_true!(r#"math.entropy(0, filesize) == 8.0"#, &all_bytes);
+    condition_true!(r#"math.entropy(0, filesize) == 0.0"#, b"AAAA");
+    condition_true!(
+        r#"math.in_range(math.entropy(0, filesize), 0.8112, 0.8113)"#,
+        b"AAAB"
+    );
+    condition_true!(r#"math.in_range(math.entropy("AAAB"), 0.8112, 0.8113)"#);
+
+    // Blocks that extend beyond the end of the data are truncated.
+    condition_true!(r#"math.entropy(2, 100) == 1.0"#, b"AAAB");
+
+    // Empty blocks, and blocks with negative offsets or sizes are undefined.
+    condition_false!(r#"defined math.entropy(0, 0)"#, b"AAAB");
+    condition_false!(r#"defined math.entropy(-1, 2)"#, b"AAAB");
+    condition_false!(r#"defined math.entropy(0, -1)"#, b"AAAB");
+    condition_false!(r#"defined math.entropy(filesize, 1)"#, b"AAAB");
+    condition_false!(r#"defined math.entropy(filesize + 1, 1)"#, b"AAAB");
+    condition_false!(r#"defined math.entropy("")"#);
+
+    condition_true!(r#"math.MEAN_BYTES == 127.5"#);
+    condition_true!(r#"math.mean(0, filesize) == 127.5"#, &all_bytes);
+    condition_true!(r#"math.mean("ABCD") == 66.5"#);
+    condition_true!(r#"math.deviation("ABCD", math.MEAN_BYTES) == 61.0"#);
+    condition_true!(
+        r#"math.deviation(0, filesize, math.MEAN_BYTES) == 64.0"#,
+        &all_bytes
+    );
+
+    condition_true!(
+        r#"math.in_range(math.serial_correlation(0, filesize), 0.9766, 0.9767)"#,
+        &all_bytes
+    );
+    condition_true!(r#"math.serial_correlation("ABCD") == -0.2"#);
+    condition_true!(r#"math.serial_correlation("AAAA") == -100000.0"#);
+
+    condition_true!(
+        r#"math.in_range(math.monte_carlo_pi(0, filesize), 0.0905, 0.0906)"#,
+        &all_bytes
+    );
+    condition_false!(r#"defined math.monte_carlo_pi("ABCDE")"#);
+
+    condition_true!(r#"math.count(0x41) == 3"#, b"AAAB");
+    condition_true!(r#"math.count(0x41, 1, 2) == 2"#, b"AAAB");
+    condition_true!(r#"math.count(0x43) == 0"#, b"AAAB");
+    condition_false!(r#"defined math.count(256)"#, b"AAAB");
+    condition_true!(r#"math.percentage(0x41) == 0.75"#, b"AAAB");
+    condition_true!(r#"math.percentage(0x42, 2, 2) == 0.5"#, b"AAAB");
+    condition_false!(r#"defined math.percentage(0x41)"#);
+
+    condition_true!(r#"math.in_range(1.5, 1.0, 2.0)"#);
+    condition_false!(r#"math.in_range(2.5, 1.0, 2.0)"#);
+
+    condition_true!(r#"math.min(1, 2) == 1"#);
+    condition_true!(r#"math.max(1, 2) == 2"#);
+    condition_true!(r#"math.abs(-3) == 3"#);
+    condition_false!(r#"defined math.abs(-0x7fffffffffffffff - 1)"#);
+}
+
+/// A PE32 file for i386 with sections `.text`, `.rdata` and `.data`, that
+/// imports `ExitProcess`, `GetStdHandle` and `WriteFile` from KERNEL32.dll,
+/// `MessageBoxA` from USER32.dll and the ordinal 115 from WS2_32.dll.
+#[cfg(feature = "pe-module")]
+const PE32: &[u8] = include_bytes!("testdata/pe/pe32.exe");
+
+/// A PE32+ file for AMD64 with sections `.text`, `.idata` and `verylong`,
+/// whose name fills the 8 bytes reserved for it. It imports `ExitProcess`
+/// from kernel32.dll and the ordinal 17 from comctl32.ocx.
+#[cfg(feature = "pe-module")]
+const PE64: &[u8] = include_bytes!("testdata/pe/pe64.exe");
+
+#[test]
+#[cfg(feature = "pe-module")]
+fn pe_module() {
+    condition_true!(r#"pe.is_pe"#, PE32);
+    condition_true!(r#"pe.machine == pe.Machine.MACHINE_I386"#, PE32);
+    condition_true!(
+        r#"pe.subsystem == pe.Subsystem.SUBSYSTEM_WINDOWS_CUI"#,
+        PE32
+    );
+    condition_true!(r#"pe.timestamp == 0x5f5e1000"#, PE32);
+    condition_true!(r#"pe.entry_point == 0x210"#, PE32);
+    condition_true!(r#"pe.entry_point_raw == 0x1010"#, PE32);
+    condition_true!(r#"uint8(pe.entry_point) == 0xc3"#, PE32);
+    condition_true!(r#"pe.number_of_sections == 3"#, PE32);
+    condition_true!(r#"pe.sections.length == 3"#, PE32);
+    condition_true!(r#"pe.sections[0].name == ".text""#, PE32);
+    condition_true!(r#"pe.sections[1].name == ".rdata""#, PE32);
+    condition_true!(r#"pe.sections[2].name == ".data""#, PE32);
+    condition_true!(r#"pe.sections[0].virtual_address == 0x1000"#, PE32);
+    condition_true!(r#"pe.sections[0].virtual_size == 0x30"#, PE32);
+    condition_true!(r#"pe.sections[0].raw_data_offset == 0x200"#, PE32);
+    condition_true!(r#"pe.sections[0].raw_data_size == 0x200"#, PE32);
+    condition_true!(r#"pe.sections[0].characteristics == 0x60000020"#, PE32);
+    condition_true!(
+        r#"pe.imphash() == "f8cfd4aa25b0c1dbc8d5ad377cf90dd0""#,
+        PE32
+    );
+
+    condition_true!(r#"pe.is_pe"#, PE64);
+    condition_true!(r#"pe.machine == pe.Machine.MACHINE_AMD64"#, PE64);
+    condition_true!(
+        r#"pe.subsystem == pe.Subsystem.SUBSYSTEM_WINDOWS_GUI"#,
+        PE64
+    );
+    condition_true!(r#"pe.timestamp == 0x65a0b3c0"#, PE64);
+    condition_true!(r#"pe.entry_point == 0x200"#, PE64);
+    condition_true!(r#"pe.entry_point_raw == 0x1000"#, PE64);
+    condition_true!(
+        r#"for any section in pe.sections : (
+             section.name == "verylong" and section.raw_data_offset == 0x600
+           )"#,
+        PE64
+    );
+    condition_true!(
+        r#"pe.imphash() == "1ceda87a5b5ed9235d6f444e819e2263""#,
+        PE64
+    );
+
+    // Data that is not a PE file.
+    condition_false!(r#"pe.is_pe"#, b"MZ");
+    condition_false!(r#"pe.is_pe"#);
+    condition_false!(r#"defined pe.machine"#, b"MZ");
+    condition_false!(r#"defined pe.entry_point"#, b"MZ");
+    condition_false!(r#"defined pe.imphash()"#, b"MZ");
+    condition_true!(r#"pe.sections.length == 0"#, b"MZ");
+}
+
+#[test]
+#[cfg(feature = "pe-module")]
+fn pe_module_malformed() {
+    // Only the headers, without the data of the sections. The entry point
+    // and the import table are not in the file anymore.
+    let headers = &PE32[..0x200];
+
+    condition_true!(r#"pe.is_pe"#, headers);
+    condition_true!(r#"pe.sections[1].name == ".rdata""#, headers);
+    condition_false!(r#"defined pe.entry_point"#, headers);
+    condition_true!(r#"pe.entry_point_raw == 0x1010"#, headers);
+    condition_false!(r#"defined pe.imphash()"#, headers);
+
+    // The section table is truncated in the middle of the last section.
+    let truncated = &PE32[..0x1d0];
+
+    condition_true!(r#"pe.number_of_sections == 3"#, truncated);
+    condition_true!(r#"pe.sections.length == 2"#, truncated);
+
+    // The file header is truncated, which is not a PE.
+    condition_false!(r#"pe.is_pe"#, &PE32[..0x90]);
+
+    // The offset of the PE header points outside the file.
+    let mut bad_pe_offset = PE32.to_vec();
+    bad_pe_offset[0x3c..0x40].copy_from_slice(&0xffffffff_u32.to_le_bytes());
+    condition_false!(r#"pe.is_pe"#, &bad_pe_offset);
+
+    // The file claims to have far more sections than it actually has. The
+    // file is padded so that the section table doesn't end with the file,
+    // but only the first 96 sections are parsed anyways.
+    let mut many_sections = PE32.to_vec();
+    many_sections[0x86..0x88].copy_from_slice(&0xffff_u16.to_le_bytes());
+    many_sections.resize(0x2000, 0);
+    condition_true!(r#"pe.number_of_sections == 0xffff"#, &many_sections);
+    condition_true!(r#"pe.sections.length == 96"#, &many_sections);
+    condition_true!(r#"pe.sections[0].name == ".text""#, &many_sections);
+
+    // The entry point is not in any section.
+    let mut bad_entry_point = PE32.to_vec();
+    bad_entry_point[0xa8..0xac].copy_from_slice(&0x80000000_u32.to_le_bytes());
+    condition_false!(r#"defined pe.entry_point"#, &bad_entry_point);
+
+    // Unknown magic in the optional header.
+    let mut bad_magic = PE32.to_vec();
+    bad_magic[0x98..0x9a].copy_from_slice(&0_u16.to_le_bytes());
+    condition_true!(r#"pe.is_pe"#, &bad_magic);
+    condition_false!(r#"defined pe.subsystem"#, &bad_magic);
+    condition_false!(r#"defined pe.imphash()"#, &bad_magic);
+    condition_true!(r#"pe.sections[2].name == ".data""#, &bad_magic);
+}
+
+#[test]
+#[cfg(feature = "pe-module")]
+fn pe_module_many_imports() {
+    use std::time::{Duration, Instant};
+
+    const NUM_DESCRIPTORS: usize = 10_000;
+    const NUM_THUNKS: usize = 100_000;
+
+    // The `.data` section, which starts at RVA 0x3000 and offset 0x600, is
+    // extended for containing many import descriptors that point to the
+    // same DLL name and the same import lookup table. The entries in the
+    // table are RVAs that are not in any section, so they don't resolve to
+    // any function.
+    let descriptors_rva = 0x3000_u32;
+    let name_rva = descriptors_rva + (NUM_DESCRIPTORS as u32 + 1) * 20;
+    let thunks_rva = name_rva + 16;
+
+    let mut descriptor = [0_u8; 20];
+    descriptor[0..4].copy_from_slice(&thunks_rva.to_le_bytes());
+    descriptor[12..16].copy_from_slice(&name_rva.to_le_bytes());
+    descriptor[16..20].copy_from_slice(&thunks_rva.to_le_bytes());
+
+    let mut section_data = descriptor.repeat(NUM_DESCRIPTORS);
+    section_data.extend_from_slice(&[0; 20]);
+    section_data.extend_from_slice(b"evil.dll\0\0\0\0\0\0\0\0");
+    section_data.extend(0x7fff0000_u32.to_le_bytes().repeat(NUM_THUNKS));
+    section_data.extend_from_slice(&[0; 4]);
+
+    let section_size = section_data.len() as u32;
+
+    let mut data = PE32[..0x600].to_vec();
+    data.extend(section_data);
+
+    // Import directory.
+    data[0x100..0x104].copy_from_slice(&descriptors_rva.to_le_bytes());
+    // Virtual size and raw data size of the `.data` section.
+    data[0x1d0..0x1d4].copy_from_slice(&section_size.to_le_bytes());
+    data[0x1d8..0x1dc].copy_from_slice(&section_size.to_le_bytes());
+
+    let start = Instant::now();
+
+    condition_true!(r#"pe.sections[2].raw_data_size > 0x80000"#, &data);
+    condition_false!(r#"defined pe.imphash()"#, &data);
+
+    // Without limits on the number of descriptors and on the entries read
+    // from the lookup tables, this takes 10_000 x 100_000 iterations.
+    assert!(start.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+#[cfg(feature = "pe-module")]
+fn pe_module_truncated() {
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "pe"
+rule test {
+  condition:
+    pe.is_pe or
+    pe.machine == 0 or
+    pe.subsystem == 0 or
+    pe.timestamp == 0 or
+    pe.entry_point == 0 or
+    pe.number_of_sections == 0 or
+    for any s in pe.sections : (s.name == "" or s.raw_data_size == 0) or
+    pe.imphash() == ""
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+
+    // The module must not panic regardless of where the file ends.
+    for data in [PE32, PE64] {
+        for len in 0..data.len() {
+            scanner.scan(&data[..len]);
+        }
+    }
+}
+
+/// A 64-bit little-endian shared object for x86-64, with sections `.text`,
+/// `.data`, `.bss` and `.shstrtab`, and segments for the code, the data and
+/// the stack.
+#[cfg(feature = "elf-module")]
+const ELF64: &[u8] = include_bytes!("testdata/elf/elf64_x86_64_dyn");
+
+/// A 32-bit big-endian executable for PowerPC, with sections `.text`,
+/// `.rodata` and `.shstrtab`, all of them in a single segment.
+#[cfg(feature = "elf-module")]
+const ELF32: &[u8] = include_bytes!("testdata/elf/elf32_ppc_exec");
+
+#[test]
+#[cfg(feature = "elf-module")]
+fn elf_module() {
+    condition_true!(r#"elf.type == elf.ET_DYN"#, ELF64);
+    condition_true!(r#"elf.machine == elf.EM_X86_64"#, ELF64);
+    condition_true!(r#"elf.entry_point == 0x100"#, ELF64);
+    condition_true!(r#"uint8(elf.entry_point) == 0x31"#, ELF64);
+    condition_true!(r#"elf.number_of_sections == 5"#, ELF64);
+    condition_true!(r#"elf.sections.length == 5"#, ELF64);
+    condition_true!(r#"elf.sections[0].name == """#, ELF64);
+    condition_true!(r#"elf.sections[0].type == elf.SHT_NULL"#, ELF64);
+    condition_true!(r#"elf.sections[1].name == ".text""#, ELF64);
+    condition_true!(r#"elf.sections[1].type == elf.SHT_PROGBITS"#, ELF64);
+    condition_true!(
+        r#"elf.sections[1].flags == elf.SHF_ALLOC | elf.SHF_EXECINSTR"#,
+        ELF64
+    );
+    condition_true!(r#"elf.sections[1].address == 0x1100"#, ELF64);
+    condition_true!(r#"elf.sections[1].offset == 0x100"#, ELF64);
+    condition_true!(r#"elf.sections[1].size == 0x10"#, ELF64);
+    condition_true!(r#"elf.sections[3].type == elf.SHT_NOBITS"#, ELF64);
+    condition_true!(r#"elf.sections[4].name == ".shstrtab""#, ELF64);
+    condition_true!(r#"elf.number_of_segments == 3"#, ELF64);
+    condition_true!(r#"elf.segments.length == 3"#, ELF64);
+    condition_true!(r#"elf.segments[0].type == elf.PT_LOAD"#, ELF64);
+    condition_true!(r#"elf.segments[0].flags == elf.PF_R | elf.PF_X"#, ELF64);
+    condition_true!(r#"elf.segments[1].offset == 0x110"#, ELF64);
+    condition_true!(r#"elf.segments[1].virtual_address == 0x2110"#, ELF64);
+    condition_true!(r#"elf.segments[1].physical_address == 0x2110"#, ELF64);
+    condition_true!(r#"elf.segments[1].file_size == 0x10"#, ELF64);
+    condition_true!(r#"elf.segments[1].memory_size == 0x110"#, ELF64);
+    condition_true!(r#"elf.segments[2].type == elf.PT_GNU_STACK"#, ELF64);
+
+    condition_true!(r#"elf.type == elf.ET_EXEC"#, ELF32);
+    condition_true!(r#"elf.machine == elf.EM_PPC"#, ELF32);
+    condition_true!(r#"elf.entry_point == 0x80"#, ELF32);
+    condition_true!(r#"uint32be(elf.entry_point) == 0x4e800020"#, ELF32);
+    condition_true!(r#"elf.number_of_sections == 4"#, ELF32);
+    condition_true!(r#"elf.sections[2].name == ".rodata""#, ELF32);
+    condition_true!(r#"elf.sections[2].flags == elf.SHF_ALLOC"#, ELF32);
+    condition_true!(r#"elf.sections[2].address == 0x10000088"#, ELF32);
+    condition_true!(r#"elf.number_of_segments == 1"#, ELF32);
+    condition_true!(r#"elf.segments[0].virtual_address == 0x10000000"#, ELF32);
+    condition_true!(r#"elf.segments[0].file_size == 0x160"#, ELF32);
+    condition_true!(
+        r#"for any section in elf.sections : (
+             section.name == ".text" and section.offset == 0x80
+           )"#,
+        ELF32
+    );
+
+    // Data that is not an ELF file.
+    condition_false!(r#"defined elf.type"#, b"\x7fELF");
+    condition_false!(r#"defined elf.type"#);
+    condition_false!(r#"defined elf.entry_point"#, b"\x7fELF");
+    condition_true!(r#"elf.sections.length == 0"#, b"\x7fELF");
+    condition_true!(r#"elf.segments.length == 0"#, b"\x7fELF");
+}
+
+#[test]
+#[cfg(feature = "elf-module")]
+fn elf_module_malformed() {
+    // The file ends right after the ELF header.
+    let header = &ELF64[..0x40];
+
+    condition_true!(r#"elf.type == elf.ET_DYN"#, header);
+    condition_true!(r#"elf.number_of_sections == 5"#, header);
+    condition_true!(r#"elf.sections.length == 0"#, header);
+    condition_true!(r#"elf.segments.length == 0"#, header);
+    condition_false!(r#"defined elf.entry_point"#, header);
+
+    // The ELF header is truncated, which is not an ELF file.
+    condition_false!(r#"defined elf.type"#, &ELF64[..0x3f]);
+    condition_false!(r#"defined elf.type"#, &ELF32[..0x33]);
+
+    // The section table is truncated in the middle of the last section,
+    // which is the string table with the section names.
+    let truncated = &ELF64[..0x2b0];
+
+    condition_true!(r#"elf.sections.length == 4"#, truncated);
+    condition_false!(r#"defined elf.sections[1].name"#, truncated);
+    condition_true!(r#"elf.sections[1].address == 0x1100"#, truncated);
+    condition_true!(r#"elf.entry_point == 0x100"#, truncated);
+
+    // Invalid class and byte order.
+    let mut bad_class = ELF64.to_vec();
+    bad_class[4] = 3;
+    condition_false!(r#"defined elf.type"#, &bad_class);
+
+    let mut bad_byte_order = ELF64.to_vec();
+    bad_byte_order[5] = 0;
+    condition_false!(r#"defined elf.type"#, &bad_byte_order);
+
+    // The index of the section names string table is out of range.
+    let mut bad_shstrndx = ELF64.to_vec();
+    bad_shstrndx[0x3e..0x40].copy_from_slice(&0xffff_u16.to_le_bytes());
+    condition_false!(r#"defined elf.sections[1].name"#, &bad_shstrndx);
+    condition_true!(r#"elf.sections[1].size == 0x10"#, &bad_shstrndx);
+
+    // The entry point is not in any loadable segment.
+    let mut bad_entry_point = ELF32.to_vec();
+    bad_entry_point[0x18..0x1c].copy_from_slice(&0x20000000_u32.to_be_bytes());
+    condition_false!(r#"defined elf.entry_point"#, &bad_entry_point);
+
+    // A relocatable object without segments, where the entry point is
+    // translated using the sections.
+    let mut relocatable = ELF64.to_vec();
+    relocatable[0x10..0x12].copy_from_slice(&1_u16.to_le_bytes());
+    relocatable[0x38..0x3a].copy_from_slice(&0_u16.to_le_bytes());
+    condition_true!(r#"elf.type == elf.ET_REL"#, &relocatable);
+    condition_true!(r#"elf.segments.length == 0"#, &relocatable);
+    condition_true!(r#"elf.entry_point == 0x100"#, &relocatable);
+}
+
+#[test]
+#[cfg(feature = "elf-module")]
+fn elf_module_long_names() {
+    use std::time::{Duration, Instant};
+
+    const STRING_TABLE_SIZE: usize = 1024 * 1024;
+
+    // A string table without null characters, followed by a table with
+    // 0xffff sections whose names point to it. The first section is the
+    // string table itself.
+    let mut data = ELF64.to_vec();
+
+    let string_table_offset = data.len() as u64;
+    data.resize(data.len() + STRING_TABLE_SIZE, b'A');
+
+    let section_table_offset = data.len() as u64;
+    let mut string_table_section = [0_u8; 64];
+    string_table_section[4..8].copy_from_slice(&3_u32.to_le_bytes());
+    string_table_section[24..32]
+        .copy_from_slice(&string_table_offset.to_le_bytes());
+    string_table_section[32..40]
+        .copy_from_slice(&(STRING_TABLE_SIZE as u64).to_le_bytes());
+
+    data.extend_from_slice(&string_table_section);
+    data.resize(data.len() + 64 * 0xfffe, 0);
+
+    data[0x28..0x30].copy_from_slice(&section_table_offset.to_le_bytes());
+    data[0x3c..0x3e].copy_from_slice(&0xffff_u16.to_le_bytes());
+    data[0x3e..0x40].copy_from_slice(&0_u16.to_le_bytes());
+
+    let start = Instant::now();
+
+    condition_true!(r#"elf.sections.length == 0xffff"#, &data);
+    condition_false!(r#"defined elf.sections[1].name"#, &data);
+
+    // Without a limit in the length of names, each section reads the
+    // whole string table looking for the null character.
+    assert!(start.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+#[cfg(feature = "elf-module")]
+fn elf_module_truncated() {
+    let mut compiler = crate::compiler::Compiler::new();
+    compiler
+        .add_source(
+            r#"
+import "elf"
+rule test {
+  condition:
+    elf.type == 0 or
+    elf.machine == 0 or
+    elf.entry_point == 0 or
+    elf.number_of_sections == 0 or
+    elf.number_of_segments == 0 or
+    for any s in elf.sections : (s.name == "" or s.size == 0) or
+    for any s in elf.segments : (s.type == 0 or s.file_size == 0)
+}
+"#,
+        )
+        .unwrap();
+    let rules = compiler.build().unwrap();
+
+    let mut scanner = crate::scanner::Scanner::new(&rules);
+
+    // The module must not panic regardless of where the file ends.
+    for data in [ELF32, ELF64] {
+        for len in 0..data.len() {
+            scanner.scan(&data[..len]);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "string-module")]
+fn string_module() {
+    condition_true!(r#"string.to_int("1234") == 1234"#);
+    condition_true!(r#"string.to_int("-10") == -10"#);
+    condition_true!(r#"string.to_int("+10") == 10"#);
+    condition_true!(r#"string.to_int("  10") == 10"#);
+    condition_true!(r#"string.to_int("0x1A") == 26"#);
+    condition_true!(r#"string.to_int("-0x10") == -16"#);
+    condition_true!(r#"string.to_int("010") == 10"#);
+    condition_true!(
+        r#"string.to_int("-9223372036854775808") == -0x7fffffffffffffff - 1"#
+    );
+
+    condition_true!(r#"string.to_int("1010", 2) == 10"#);
+    condition_true!(r#"string.to_int("777", 8) == 511"#);
+    condition_true!(r#"string.to_int("0x1a", 16) == 26"#);
+    condition_true!(r#"string.to_int("1a", 16) == 26"#);
+    condition_true!(r#"string.to_int("z", 36) == 35"#);
+    condition_true!(r#"string.to_int("-z", 36) == -35"#);
+
+    // Unparsable strings, out of range values and invalid bases produce
+    // undefined results.
+    condition_false!(r#"defined string.to_int("")"#);
+    condition_false!(r#"defined string.to_int("-")"#);
+    condition_false!(r#"defined string.to_int("0x")"#);
+    condition_false!(r#"defined string.to_int("1a")"#);
+    condition_false!(r#"defined string.to_int("10 ")"#);
+    condition_false!(r#"defined string.to_int("--10")"#);
+    condition_false!(r#"defined string.to_int("-+10")"#);
+    condition_false!(r#"defined string.to_int("9223372036854775808")"#);
+    condition_false!(r#"defined string.to_int("2", 2)"#);
+    condition_false!(r#"defined string.to_int("0x10", 10)"#);
+    condition_false!(r#"defined string.to_int("10", 1)"#);
+    condition_false!(r#"defined string.to_int("10", 37)"#);
+    condition_false!(r#"defined string.to_int("10", -2)"#);
+
+    condition_true!(r#"string.length("foo") == 3"#);
+    condition_true!(r#"string.length("") == 0"#);
+    condition_true!(r#"string.length("\x00\xff") == 2"#);
+}
+
+#[test]
+#[cfg(all(feature = "string-module", feature = "test_proto2-module"))]
+fn string_module_with_module_strings() {
+    condition_true!(r#"string.length(test_proto2.string_foo) == 3"#);
+    condition_true!(r#"string.to_int(test_proto2.head(3)) == 123"#, b"123abc");
+    condition_true!(
+        r#"string.to_int(test_proto2.head(2), 16) == 0xAB"#,
+        b"ABCD"
+    );
+    condition_false!(
+        r#"defined string.to_int(test_proto2.head(4))"#,
+        b"123abc"
+    );
+
+    // The length is in bytes, even for strings that are not valid UTF-8.
+    condition_true!(
+        r#"string.length(test_proto2.head(4)) == 4"#,
+        b"\xff\xfe\x00\xe2\x82"
+    );
+    condition_false!(
+        r#"defined string.to_int(test_proto2.head(2))"#,
+        b"\xff\xfe"
+    );
+}
+
+#[test]
+#[cfg(feature = "time-module")]
+fn time_module() {
+    // 2020-09-13 12:26:40 UTC, the clock can't be earlier than this.
+    condition_true!(r#"time.now() > 1600000000"#);
+    condition_true!(r#"defined time.now()"#);
+}
@@ -0,0 +1,163 @@
+use pretty_assertions::assert_eq;
+
+use crate::compile;
+use crate::regression::{
+    ChangeKind, Regression, RuleChange, RuleSummary, Summary,
+};
+
+const BASELINE: &str = r#"
+rule unchanged { strings: $a = "foo" condition: $a }
+rule removed { strings: $a = "bar" condition: $a }
+rule narrowed { strings: $a = "baz" condition: $a and filesize < 10 }
+rule counted { strings: $a = "qux" condition: $a }
+"#;
+
+const CANDIDATE: &str = r#"
+rule unchanged { strings: $a = "foo" condition: $a }
+rule added { strings: $a = "bar" condition: $a }
+rule narrowed { strings: $a = "baz" condition: $a and filesize < 5 }
+rule counted { strings: $a = "qu" condition: $a }
+"#;
+
+#[test]
+fn regression() {
+    let baseline = compile(BASELINE).unwrap();
+    let candidate = compile(CANDIDATE).unwrap();
+
+    let fingerprint = |rules: &crate::Rules, name: &str| {
+        rules.iter().find(|r| r.name() == name).map(|r| r.fingerprint())
+    };
+
+    let mut regression = Regression::new(&baseline, &candidate);
+
+    let diffs: Vec<_> = regression
+        .compare_all([
+            ("clean", b"foo".as_slice()),
+            ("bar", b"bar".as_slice()),
+            ("baz", b"bazbazbaz".as_slice()),
+            ("qux", b"quxqu".as_slice()),
+        ])
+        .collect();
+
+    assert_eq!(diffs.len(), 4);
+    assert!(diffs[0].is_empty());
+    assert_eq!(diffs[0].sample, "clean");
+
+    // Changes are sorted by rule name, `added` comes before `removed`.
+    assert_eq!(
+        diffs[1].changes,
+        [
+            RuleChange {
+                namespace: "default".to_string(),
+                rule: "added".to_string(),
+                kind: ChangeKind::NewMatch,
+                baseline_fingerprint: None,
+                candidate_fingerprint: fingerprint(&candidate, "added"),
+                baseline_matches: None,
+                candidate_matches: Some(1),
+            },
+            RuleChange {
+                namespace: "default".to_string(),
+                rule: "removed".to_string(),
+                kind: ChangeKind::LostMatch,
+                baseline_fingerprint: fingerprint(&baseline, "removed"),
+                candidate_fingerprint: None,
+                baseline_matches: Some(1),
+                candidate_matches: None,
+            },
+        ]
+    );
+
+    assert_eq!(diffs[2].changes.len(), 1);
+    assert_eq!(diffs[2].changes[0].kind, ChangeKind::LostMatch);
+    assert_eq!(diffs[2].changes[0].rule, "narrowed");
+    assert_ne!(
+        diffs[2].changes[0].baseline_fingerprint,
+        diffs[2].changes[0].candidate_fingerprint
+    );
+
+    assert_eq!(diffs[3].changes.len(), 1);
+    assert_eq!(diffs[3].changes[0].kind, ChangeKind::MatchCountChanged);
+    assert_eq!(diffs[3].changes[0].baseline_matches, Some(1));
+    assert_eq!(diffs[3].changes[0].candidate_matches, Some(2));
+
+    let summary = |rule: &str, new, lost, changed| RuleSummary {
+        namespace: "default".to_string(),
+        rule: rule.to_string(),
+        new_matches: new,
+        lost_matches: lost,
+        changed_match_counts: changed,
+    };
+
+    assert_eq!(
+        regression.summary(),
+        &Summary {
+            samples: 4,
+            diverging_samples: 3,
+            new_matches: 1,
+            lost_matches: 2,
+            changed_match_counts: 1,
+            rules: vec![
+                summary("added", 1, 0, 0),
+                summary("counted", 0, 0, 1),
+                summary("narrowed", 0, 1, 0),
+                summary("removed", 0, 1, 0),
+            ],
+        }
+    );
+}
+
+#[test]
+fn regression_merge() {
+    let baseline = compile(BASELINE).unwrap();
+    let candidate = compile(CANDIDATE).unwrap();
+
+    let mut first = Regression::new(&baseline, &candidate);
+    let mut second = Regression::new(&baseline, &candidate);
+
+    first.compare("bar", b"bar");
+    second.compare("bar", b"bar");
+    second.compare("baz", b"bazbazbaz");
+
+    let mut summary = first.into_summary();
+    summary.merge(second.summary());
+
+    assert_eq!(summary.samples, 3);
+    assert_eq!(summary.diverging_samples, 3);
+    assert_eq!(summary.new_matches, 2);
+    assert_eq!(summary.lost_matches, 3);
+    assert_eq!(
+        summary.rules.iter().map(|r| r.rule.as_str()).collect::<Vec<_>>(),
+        ["added", "narrowed", "removed"]
+    );
+    assert_eq!(summary.rules[2].lost_matches, 2);
+}
+
+#[test]
+fn regression_identical_rules() {
+    let rules = compile(BASELINE).unwrap();
+    let mut regression = Regression::new(&rules, &rules);
+
+    assert!(regression.compare("sample", b"foo bar baz qux").is_empty());
+    assert!(regression.summary().is_clean());
+}
+
+#[test]
+fn rule_fingerprint() {
+    let fingerprints = |src: &str| {
+        compile(src)
+            .unwrap()
+            .iter()
+            .map(|r| r.fingerprint())
+            .collect::<Vec<_>>()
+    };
+
+    // The fingerprint depends only on the rule's own source code.
+    let a = fingerprints("rule a { condition: true }");
+    let b =
+        fingerprints("rule b { condition: false } rule a { condition: true }");
+    let c = fingerprints("rule a { condition: false }");
+
+    assert_eq!(a[0], b[1]);
+    assert_ne!(a[0], c[0]);
+}
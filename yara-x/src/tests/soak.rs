@@ -0,0 +1,255 @@
+/*! Soak test that scans data with randomly generated conditions.
+
+Memory checks are always enabled while running tests (see
+[`crate::wasm::memory_checks`]), so the purpose of this test is exercising
+as many combinations of loops, field lookups and pattern matches as
+possible, and let the memory checks detect any corruption. The result of
+each condition is irrelevant.
+
+Some conditions have loops nested as deep as the compiler allows, which
+fill the vars stack up to the canary that follows it. The scanned data is
+up to 1KB long, or up to 64KB in the longer version of the test, so that
+patterns have from zero to thousands of matches.
+
+The generator is deterministic, a failure can be reproduced by running the
+test again. The longer version performs 10,000 scans and is ignored by
+default, CI runs it with `cargo test --release soak -- --ignored`.
+*/
+
+use crate::compiler::{Compiler, MAX_LOOP_NESTING};
+use crate::scanner::Scanner;
+
+/// Number of rules in each rule set.
+const NUM_RULES: usize = 50;
+
+/// A xorshift pseudo-random number generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn choose<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+/// Generates random conditions.
+struct Generator {
+    rng: Rng,
+    /// Integer loop variables that are in scope.
+    vars: Vec<String>,
+}
+
+impl Generator {
+    fn bool_expr(&mut self, depth: usize) -> String {
+        let choices = if depth == 0 { 4 } else { 11 };
+        match self.rng.below(choices) {
+            0 => self.rng.choose(&["true", "false"]).to_string(),
+            1 => format!(
+                "{} {} {}",
+                self.int_expr(depth.saturating_sub(1)),
+                self.rng.choose(&["==", "!=", "<", "<=", ">", ">="]),
+                self.int_expr(depth.saturating_sub(1))
+            ),
+            2 => match self.rng.below(3) {
+                0 => format!("$p{}", self.rng.below(4)),
+                1 => format!(
+                    "$p{} in ({}..{})",
+                    self.rng.below(4),
+                    self.int_expr(depth.saturating_sub(1)),
+                    self.int_expr(depth.saturating_sub(1))
+                ),
+                _ => format!(
+                    "$p{} at @p{}",
+                    self.rng.below(4),
+                    self.rng.below(4)
+                ),
+            },
+            3 => self
+                .rng
+                .choose(&[
+                    r#"test_proto2.string_foo contains "o""#,
+                    r#"test_proto2.array_string[1] == "bar""#,
+                    r#"test_proto2.map_string_int64["one"] == 1"#,
+                    "test_proto2.array_bool[1]",
+                    "any of them",
+                    "2 of ($p*)",
+                    "none of ($p0, $p1)",
+                ])
+                .to_string(),
+            4 => format!("not {}", self.bool_expr(depth - 1)),
+            5 | 6 => format!(
+                "({} {} {})",
+                self.bool_expr(depth - 1),
+                self.rng.choose(&["and", "or"]),
+                self.bool_expr(depth - 1)
+            ),
+            7 | 8 => {
+                let var = self.new_var();
+                let (lower, upper) = (self.rng.below(5), self.rng.below(10));
+                let quantifier = self.quantifier();
+                let body = self.bool_expr(depth - 1);
+                self.vars.pop();
+                format!(
+                    "for {} {} in ({}..{}) : ({})",
+                    quantifier, var, lower, upper, body
+                )
+            }
+            9 => {
+                let var = self.new_var();
+                let quantifier = self.quantifier();
+                let body = self.bool_expr(depth - 1);
+                self.vars.pop();
+                format!(
+                    "for {} {} in test_proto2.array_int64 : ({})",
+                    quantifier, var, body
+                )
+            }
+            _ => {
+                let quantifier = self.quantifier();
+                format!(
+                    "for {} s in test_proto2.array_string : (s == {})",
+                    quantifier,
+                    self.rng.choose(&[r#""foo""#, r#""qux""#])
+                )
+            }
+        }
+    }
+
+    fn int_expr(&mut self, depth: usize) -> String {
+        let choices = if depth == 0 { 4 } else { 6 };
+        match self.rng.below(choices) {
+            0 => self.rng.below(100).to_string(),
+            1 => match self.vars.len() {
+                0 => "filesize".to_string(),
+                n => self.vars[self.rng.below(n)].clone(),
+            },
+            2 => self
+                .rng
+                .choose(&[
+                    "filesize",
+                    "test_proto2.int64_one",
+                    "test_proto2.nested.nested_int64_one",
+                    "test_proto2.map_int64_int64[100]",
+                    "test_proto2.array_struct[0].nested_int64_one",
+                    "#p0",
+                    "#p2 in (0..10)",
+                    "@p1[2]",
+                    "!p3",
+                ])
+                .to_string(),
+            3 => format!("test_proto2.array_int64[{}]", self.rng.below(4)),
+            _ => format!(
+                "({} {} {})",
+                self.int_expr(depth - 1),
+                self.rng.choose(&["+", "-", "*", "\\", "%"]),
+                self.int_expr(depth - 1)
+            ),
+        }
+    }
+
+    fn quantifier(&mut self) -> String {
+        match self.rng.below(5) {
+            0 => "all".to_string(),
+            1 => "none".to_string(),
+            2 => format!("{}%", self.rng.below(101)),
+            3 => self.rng.below(5).to_string(),
+            _ => "any".to_string(),
+        }
+    }
+
+    /// Returns `n` nested loops, each one declaring its own variable.
+    fn nested_loops(&mut self, n: usize) -> String {
+        if n == 0 {
+            // At depth 0 the expression can still contain an `of`, which
+            // counts as a loop too, but it doesn't declare any variable.
+            return self.bool_expr(0);
+        }
+        let var = self.new_var();
+        let quantifier = self.quantifier();
+        let body = self.nested_loops(n - 1);
+        self.vars.pop();
+        format!("for {} {} in (0..1) : ({})", quantifier, var, body)
+    }
+
+    fn new_var(&mut self) -> String {
+        let var = format!("i{}", self.vars.len());
+        self.vars.push(var.clone());
+        var
+    }
+}
+
+/// Compiles `num_rule_sets` rule sets with random conditions, and scans
+/// random data `num_scans` times with each of them. The size of the data is
+/// below one of the sizes in `max_sizes`.
+fn soak(
+    seed: u64,
+    num_rule_sets: usize,
+    num_scans: usize,
+    max_sizes: &[usize],
+) {
+    let mut generator = Generator { rng: Rng(seed), vars: vec![] };
+
+    for _ in 0..num_rule_sets {
+        let mut src = String::from("import \"test_proto2\"\n");
+
+        for i in 0..NUM_RULES {
+            // One out of ten rules has loops nested as deep as possible.
+            let condition = if generator.rng.below(10) == 0 {
+                generator.nested_loops(MAX_LOOP_NESTING - 1)
+            } else {
+                generator.bool_expr(4)
+            };
+            src.push_str(&format!(
+                r#"rule r{} {{
+  strings:
+    $p0 = "ab"
+    $p1 = "ba"
+    $p2 = "aab"
+    $p3 = {{ 62 62 }}
+  condition:
+    ({}) or any of them
+}}
+"#,
+                i, condition,
+            ));
+        }
+
+        let mut compiler = Compiler::new();
+        compiler
+            .add_source(src.as_str())
+            .unwrap_or_else(|err| panic!("{}\n{}", err, src));
+        let rules = compiler.build().unwrap();
+
+        let mut scanner = Scanner::new(&rules);
+
+        for _ in 0..num_scans {
+            let max_size = max_sizes[generator.rng.below(max_sizes.len())];
+            let data: Vec<u8> = (0..generator.rng.below(max_size))
+                .map(|_| b"ab\x00"[generator.rng.below(3)])
+                .collect();
+
+            scanner.scan(data.as_slice());
+        }
+    }
+}
+
+#[test]
+fn random_conditions() {
+    soak(0x2545f4914f6cdd1d, 20, 50, &[64, 1024]);
+}
+
+#[test]
+#[ignore]
+fn random_conditions_long() {
+    soak(0x9e3779b97f4a7c15, 100, 100, &[64, 4096, 65536]);
+}
@@ -0,0 +1,59 @@
+/*! Values for the global variables used in rule conditions.
+
+Global variables are defined with [`crate::Compiler::define_global`], which
+also sets their default value, and their value for a given scanner can be
+changed with [`crate::Scanner::set_global`].
+*/
+
+use bstr::BString;
+use yara_x_parser::types::TypeValue;
+
+/// The value of a global variable.
+///
+/// This type is not created directly, values are converted from `bool`,
+/// integer, float and string types. The type of a global variable is the
+/// type of the value passed to [`crate::Compiler::define_global`].
+#[derive(Clone, Debug)]
+pub struct Variable(TypeValue);
+
+impl From<bool> for Variable {
+    fn from(value: bool) -> Self {
+        Self(TypeValue::Bool(Some(value)))
+    }
+}
+
+impl From<i32> for Variable {
+    fn from(value: i32) -> Self {
+        Self(TypeValue::Integer(Some(value.into())))
+    }
+}
+
+impl From<i64> for Variable {
+    fn from(value: i64) -> Self {
+        Self(TypeValue::Integer(Some(value)))
+    }
+}
+
+impl From<f64> for Variable {
+    fn from(value: f64) -> Self {
+        Self(TypeValue::Float(Some(value)))
+    }
+}
+
+impl From<&str> for Variable {
+    fn from(value: &str) -> Self {
+        Self(TypeValue::String(Some(BString::from(value))))
+    }
+}
+
+impl From<String> for Variable {
+    fn from(value: String) -> Self {
+        Self(TypeValue::String(Some(BString::from(value))))
+    }
+}
+
+impl From<Variable> for TypeValue {
+    fn from(value: Variable) -> Self {
+        value.0
+    }
+}
@@ -1,15 +1,21 @@
 use rustc_hash::FxHashMap;
-use walrus::FunctionId;
+use walrus::ir::ExtendedLoad::ZeroExtend;
+use walrus::ir::{BinaryOp, LoadKind, MemArg, Value};
 use walrus::ValType::{F64, I32, I64};
+use walrus::{FunctionId, InitExpr};
 
 use super::WasmSymbols;
+use crate::compiler::RuleId;
 
 /// Builds the WebAssembly module for a set of compiled rules.
 pub(crate) struct ModuleBuilder {
     module: walrus::Module,
     wasm_symbols: WasmSymbols,
     pub(crate) wasm_funcs: FxHashMap<String, FunctionId>,
-    pub(crate) main_fn: walrus::FunctionBuilder,
+    /// Functions that evaluate the condition of each rule, in the order
+    /// they are called by the `main` function. Disabled rules don't have
+    /// a function.
+    rule_fns: Vec<(RuleId, FunctionId)>,
 }
 
 macro_rules! global_var {
@@ -26,6 +32,15 @@ macro_rules! global_const {
     };
 }
 
+macro_rules! local {
+    ($module:ident, $name:ident, $ty:ident) => {{
+        let local = $module.locals.add($ty);
+        $module.locals.get_mut(local).name =
+            Some(stringify!($name).to_string());
+        local
+    }};
+}
+
 impl ModuleBuilder {
     /// Creates a new module builder.
     pub fn new() -> Self {
@@ -44,11 +59,15 @@ impl ModuleBuilder {
                 fully_qualified_name.as_str(),
                 ty,
             );
+            module.funcs.get_mut(func_id).name =
+                Some(fully_qualified_name.clone());
             wasm_funcs.insert(fully_qualified_name, func_id);
         }
 
         global_const!(module, matching_patterns_bitmap_base, I32);
+        global_const!(module, selected_rules_bitmap_base, I32);
         global_var!(module, filesize, I64);
+        global_var!(module, profiling_enabled, I32);
 
         let (main_memory, _) =
             module.add_import_memory("yara_x", "main_memory", false, 1, None);
@@ -56,17 +75,20 @@ impl ModuleBuilder {
         let wasm_symbols = WasmSymbols {
             main_memory,
             matching_patterns_bitmap_base,
+            selected_rules_bitmap_base,
             filesize,
-            pattern_search_done: module.locals.add(I32),
-            i64_tmp: module.locals.add(I64),
-            i32_tmp: module.locals.add(I32),
-            f64_tmp: module.locals.add(F64),
+            profiling_enabled,
+            pattern_search_done: module.globals.add_local(
+                I32,
+                true,
+                InitExpr::Value(Value::I32(0)),
+            ),
+            i64_tmp: local!(module, i64_tmp, I64),
+            i32_tmp: local!(module, i32_tmp, I32),
+            f64_tmp: local!(module, f64_tmp, F64),
         };
 
-        let main_fn =
-            walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
-
-        Self { module, wasm_symbols, wasm_funcs, main_fn }
+        Self { module, wasm_symbols, wasm_funcs, rule_fns: Vec::new() }
     }
 
     /// Returns the symbols imported by the module.
@@ -74,10 +96,86 @@ impl ModuleBuilder {
         self.wasm_symbols.clone()
     }
 
+    /// Creates the builder for the function that evaluates the condition of
+    /// a rule. Once the rule's code is emitted, the function must be added
+    /// to the module with [`ModuleBuilder::add_rule_fn`].
+    pub fn new_rule_fn(&mut self) -> walrus::FunctionBuilder {
+        walrus::FunctionBuilder::new(&mut self.module.types, &[], &[])
+    }
+
+    /// Adds the function that evaluates the condition of a rule. `name` is
+    /// the name of the function in the module.
+    pub fn add_rule_fn(
+        &mut self,
+        rule_id: RuleId,
+        name: String,
+        mut rule_fn: walrus::FunctionBuilder,
+    ) {
+        rule_fn.name(name);
+        self.rule_fns.push((
+            rule_id,
+            rule_fn.finish(Vec::new(), &mut self.module.funcs),
+        ));
+    }
+
+    /// Returns the number of functions added with
+    /// [`ModuleBuilder::add_rule_fn`].
+    pub fn num_rule_fns(&self) -> usize {
+        self.rule_fns.len()
+    }
+
+    /// Removes the functions added after the first `len` ones.
+    pub fn truncate_rule_fns(&mut self, len: usize) {
+        for (_, func_id) in self.rule_fns.drain(len..) {
+            self.module.funcs.delete(func_id);
+        }
+    }
+
     /// Builds the module and consumes the builder.
     pub fn build(mut self) -> walrus::Module {
-        let main_fn = self.main_fn.finish(Vec::new(), &mut self.module.funcs);
+        let mut main_fn =
+            walrus::FunctionBuilder::new(&mut self.module.types, &[], &[]);
+
+        main_fn.name("main".to_string());
+
+        let mut instr = main_fn.func_body();
+
+        // The pattern search phase is executed at most once per scan.
+        instr.i32_const(0);
+        instr.global_set(self.wasm_symbols.pattern_search_done);
+
+        // Call the function of each rule whose bit is set in the selected
+        // rules bitmap.
+        for (rule_id, rule_fn) in &self.rule_fns {
+            let rule_id = u32::try_from(usize::from(*rule_id)).unwrap();
+            instr.global_get(self.wasm_symbols.selected_rules_bitmap_base);
+            instr.load(
+                self.wasm_symbols.main_memory,
+                LoadKind::I32_8 { kind: ZeroExtend },
+                MemArg { align: 1, offset: rule_id / 8 },
+            );
+            instr.i32_const(1_i32 << (rule_id % 8));
+            instr.binop(BinaryOp::I32And);
+            instr.if_else(
+                None,
+                |then_| {
+                    then_.call(*rule_fn);
+                },
+                |_| {},
+            );
+        }
+
+        let main_fn = main_fn.finish(Vec::new(), &mut self.module.funcs);
         self.module.exports.add("main", main_fn);
         self.module
     }
+
+    /// Builds the module and returns it in WebAssembly text format (WAT).
+    ///
+    /// Function names are taken from the name section emitted by walrus,
+    /// so each rule's function is named after the rule.
+    pub fn build_wat(self) -> String {
+        wasmprinter::print_bytes(self.build().emit_wasm())
+            .expect("the module emitted by walrus is valid")
+    }
 }
@@ -0,0 +1,170 @@
+/*! Detection of corruption in module's main memory.
+
+Module's main memory is shared by the WASM code emitted for rule conditions
+and the Rust functions called from that code, and both sides access the
+memory at offsets computed by hand (see "Memory layout" in [`crate::wasm`]).
+A wrong offset doesn't produce any error, the memory region that comes next
+is silently overwritten instead.
+
+For detecting this kind of bugs each region is followed by a canary, a
+sequence of bytes with a known value that is never written during normal
+operation. When the `memory-checks` feature is enabled, or while running
+tests, the canaries are verified before and after each call to a function
+exported to WASM. A canary overwritten during a call is attributed to the
+called function, while a canary that was already overwritten when the
+function was called is attributed to the WASM code executed since the
+previous call. In both cases the panic message indicates the rule whose
+condition was being evaluated.
+*/
+use std::fmt::{Display, Formatter};
+use std::ptr::NonNull;
+
+use wasmtime::{Caller, Store, ValRaw};
+
+use crate::compiler::RuleId;
+use crate::scanner::ScanContext;
+use crate::wasm::{MemoryLayout, TrampolineFn, CANARY_SIZE};
+
+/// The value of each canary.
+#[allow(clippy::as_conversions)]
+const CANARY: [u8; CANARY_SIZE as usize] =
+    [0xde, 0xad, 0xbe, 0xef, 0xfe, 0xed, 0xfa, 0xce];
+
+/// Information used for attributing memory corruption to a function and
+/// a rule.
+#[derive(Default)]
+pub(crate) struct State {
+    /// Rule whose condition is being evaluated.
+    pub current_rule: Option<RuleId>,
+    /// Name of the last exported function that returned.
+    pub last_fn: Option<&'static str>,
+}
+
+/// Moment at which the canaries are verified.
+enum CheckPoint {
+    Entry(&'static str),
+    Exit(&'static str),
+    EndOfScan,
+}
+
+/// Writes the canaries in module's main memory.
+pub(crate) fn write_canaries(main_memory: &mut [u8], layout: &MemoryLayout) {
+    for (_, offset) in layout.canaries() {
+        main_memory[offset..offset + CANARY.len()].copy_from_slice(&CANARY);
+    }
+}
+
+/// Verifies the canaries after the WASM code has finished evaluating the
+/// conditions of all rules.
+pub(crate) fn check_after_scan(store: &Store<ScanContext>) {
+    check_canaries(store.data(), main_memory(store), CheckPoint::EndOfScan);
+}
+
+/// Wraps the trampoline of the exported function `fn_name` with code that
+/// verifies the canaries before and after calling the function.
+pub(crate) fn guard(
+    fn_name: &'static str,
+    trampoline: TrampolineFn,
+) -> TrampolineFn {
+    Box::new(
+        move |caller: Caller<'_, ScanContext>,
+              args_and_results: &mut [ValRaw]|
+              -> anyhow::Result<()> {
+            let ctx = caller.data();
+
+            check_canaries(
+                ctx,
+                ctx.main_memory.unwrap().data(&caller),
+                CheckPoint::Entry(fn_name),
+            );
+
+            // The caller is consumed by the trampoline, after it returns
+            // the store is accessed with the pointer kept in ScanContext,
+            // like `ScanContext::track_rule_match` does.
+            let mut wasm_store: NonNull<Store<ScanContext>> = ctx.wasm_store;
+
+            trampoline(caller, args_and_results)?;
+
+            let store = unsafe { wasm_store.as_ref() };
+
+            check_canaries(
+                store.data(),
+                main_memory(store),
+                CheckPoint::Exit(fn_name),
+            );
+
+            unsafe { wasm_store.as_mut() }.data_mut().memory_checks.last_fn =
+                Some(fn_name);
+
+            Ok(())
+        },
+    )
+}
+
+fn main_memory<'a>(store: &'a Store<ScanContext>) -> &'a [u8] {
+    store.data().main_memory.unwrap().data(store)
+}
+
+fn check_canaries(
+    ctx: &ScanContext,
+    main_memory: &[u8],
+    check_point: CheckPoint,
+) {
+    let layout = ctx.memory_layout();
+
+    for (region, offset) in layout.canaries() {
+        if main_memory[offset..offset + CANARY.len()] != CANARY {
+            panic!(
+                "memory corruption: the canary after the {} was overwritten {}{}",
+                region,
+                Culprit { check_point, last_fn: ctx.memory_checks.last_fn },
+                CurrentRule(ctx),
+            );
+        }
+    }
+}
+
+struct Culprit {
+    check_point: CheckPoint,
+    last_fn: Option<&'static str>,
+}
+
+impl Display for Culprit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.check_point, self.last_fn) {
+            (CheckPoint::Exit(fn_name), _) => {
+                write!(f, "by function `{}`", fn_name)
+            }
+            (CheckPoint::Entry(fn_name), Some(last_fn)) => write!(
+                f,
+                "by WASM code executed between `{}` and `{}`",
+                last_fn, fn_name
+            ),
+            (CheckPoint::Entry(fn_name), None) => {
+                write!(f, "by WASM code executed before `{}`", fn_name)
+            }
+            (CheckPoint::EndOfScan, Some(last_fn)) => {
+                write!(f, "by WASM code executed after `{}`", last_fn)
+            }
+            (CheckPoint::EndOfScan, None) => write!(f, "by WASM code"),
+        }
+    }
+}
+
+struct CurrentRule<'a, 'r>(&'a ScanContext<'r>);
+
+impl Display for CurrentRule<'_, '_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let rules = self.0.compiled_rules;
+        if let Some(rule_id) = self.0.memory_checks.current_rule {
+            let rule_info = &rules.rules()[usize::from(rule_id)];
+            write!(
+                f,
+                ", while evaluating rule `{}:{}`",
+                rules.ident_pool().get(rule_info.namespace_id).unwrap(),
+                rules.ident_pool().get(rule_info.ident_id).unwrap(),
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -7,9 +7,10 @@ in YARA.
 
 For each instance of [`crate::compiler::Rules`] the compiler creates a WASM
 module. This WASM module works in close collaboration with YARA's Rust code for
-evaluating the rule's conditions. For example, the WASM module exports a
-function called `main`, which contains the code that evaluates the conditions
-of all the compiled rules. This WASM function is called by YARA at scan time,
+evaluating the rule's conditions. The condition of each rule is evaluated by a
+separate WASM function, and the module exports a function called `main` that
+calls them in order, skipping the rules that were not selected for the current
+scan (see "Rule selection"). This WASM function is called by YARA at scan time,
 and the WASM code calls back the Rust [`rule_match`] function for notifying
 YARA about matching rules. The WASM module calls Rust functions in many other
 cases, for example when it needs to call YARA built-in functions like
@@ -33,20 +34,40 @@ The memory of these WASM modules is organized as follows.
   : ...                      :
   │                          │
   ├──────────────────────────┤ 1024
+  │ Canary                   │
+  ├──────────────────────────┤ 1032
   │ Field lookup indexes     │
-  ├──────────────────────────┤ 2048
+  ├──────────────────────────┤ 2056
+  │ Canary                   │
+  ├──────────────────────────┤ 2064
   │ Matching rules bitmap    │
   │                          │
   :                          :
   │                          │
-  ├──────────────────────────┤ (number of rules / 8) + 1
+  ├──────────────────────────┤ + (number of rules / 8) + 1
+  │ Canary                   │
+  ├──────────────────────────┤ + 8
+  │ Selected rules bitmap    │
+  │                          │
+  :                          :
+  │                          │
+  ├──────────────────────────┤ + (number of rules / 8) + 1
+  │ Canary                   │
+  ├──────────────────────────┤ + 8
   │ Matching patterns bitmap │
   │                          │
   :                          :
   │                          │
-  └──────────────────────────┘
+  ├──────────────────────────┤ + (number of patterns / 8) + 1
+  │ Canary                   │
+  └──────────────────────────┘ + 8
 ```
 
+The location of the regions that start after the lookup indexes depends on
+the number of rules and patterns, and is described by [`MemoryLayout`]. The
+canaries are unused during normal operation, see the `memory_checks`
+module for details about them.
+
 # Field lookup
 
 While evaluating rule condition's, the WASM code needs to obtain from YARA the
@@ -66,9 +87,27 @@ many indexes to lookup.
 
 See the [`lookup_field`] function.
 
+# Rule selection
+
+Before calling the function that evaluates a rule, `main` checks the rule's
+bit in the selected rules bitmap, and skips the rule if the bit is not set.
+All the bits are set unless the scan was started with
+[`crate::Scanner::scan_with_filter`], in which case only the rules accepted
+by the filter and the rules they depend on are selected.
+
+# Integer conversions
+
+Offsets, lengths and counts are `usize` values on the Rust side, but WASM code
+operates with `i64`. Every value of this kind returned to WASM goes through
+[`wasm_i64`], and every integer received from WASM is converted with
+`try_from`. `as` conversions, which truncate or wrap silently, are denied in
+this module.
+
  */
+#![deny(clippy::as_conversions)]
 use std::any::{type_name, TypeId};
 use std::mem;
+use std::ops::Range;
 
 use bstr::ByteSlice;
 use lazy_static::lazy_static;
@@ -81,29 +120,93 @@ use wasmtime::{
 use yara_x_macros::wasm_export;
 use yara_x_parser::types::TypeValue;
 
-use crate::compiler::{PatternId, RuleId};
+use crate::compiler::{PatternId, RegexpId, RuleId, StringSetId};
 use crate::modules::BUILTIN_MODULES;
-use crate::scanner::ScanContext;
+use crate::scanner::{Match, ScanContext};
 use crate::wasm::string::{RuntimeString, RuntimeStringWasm};
 use crate::LiteralId;
 
 pub(crate) mod builder;
+#[cfg(any(test, feature = "memory-checks"))]
+pub(crate) mod memory_checks;
 pub(crate) mod string;
 
+/// Size of the canaries placed after each region in module's main memory.
+pub(crate) const CANARY_SIZE: i32 = 8;
+
 /// Offset in module's main memory where the space for loop variables start.
 pub(crate) const VARS_STACK_START: i32 = 0;
 /// Offset in module's main memory where the space for loop variables end.
 pub(crate) const VARS_STACK_END: i32 = VARS_STACK_START + 1024;
 
 /// Offset in module's main memory where the space for lookup indexes start.
-pub(crate) const LOOKUP_INDEXES_START: i32 = VARS_STACK_END;
+pub(crate) const LOOKUP_INDEXES_START: i32 = VARS_STACK_END + CANARY_SIZE;
 /// Offset in module's main memory where the space for lookup indexes end.
 pub(crate) const LOOKUP_INDEXES_END: i32 = LOOKUP_INDEXES_START + 1024;
 
 /// Offset in module's main memory where resides the bitmap that tells if a
 /// rule matches or not. This bitmap contains one bit per rule, if the N-th
 /// bit is set, it indicates that the rule with RuleId = N matched.
-pub(crate) const MATCHING_RULES_BITMAP_BASE: i32 = LOOKUP_INDEXES_END;
+pub(crate) const MATCHING_RULES_BITMAP_BASE: i32 =
+    LOOKUP_INDEXES_END + CANARY_SIZE;
+
+/// Describes the regions of module's main memory whose location depends on
+/// the number of rules and patterns.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MemoryLayout {
+    num_rules: usize,
+    num_patterns: usize,
+}
+
+// The constants used here are small, non-negative values, so they can be
+// converted with `as` safely.
+#[allow(clippy::as_conversions)]
+impl MemoryLayout {
+    pub fn new(num_rules: usize, num_patterns: usize) -> Self {
+        Self { num_rules, num_patterns }
+    }
+
+    /// Range of main memory occupied by the bitmap that tells which rules
+    /// matched.
+    pub fn matching_rules_bitmap(&self) -> Range<usize> {
+        let start = MATCHING_RULES_BITMAP_BASE as usize;
+        start..start + self.num_rules / 8 + 1
+    }
+
+    /// Range of main memory occupied by the bitmap that tells which rules
+    /// must be evaluated. See "Rule selection" in the [`crate::wasm`]
+    /// module.
+    pub fn selected_rules_bitmap(&self) -> Range<usize> {
+        let start = self.matching_rules_bitmap().end + CANARY_SIZE as usize;
+        start..start + self.num_rules / 8 + 1
+    }
+
+    /// Range of main memory occupied by the bitmap that tells which
+    /// patterns matched. The bitmap has 1 bit per pattern, the N-th bit is
+    /// set if pattern with PatternId = N matched.
+    pub fn matching_patterns_bitmap(&self) -> Range<usize> {
+        let start = self.selected_rules_bitmap().end + CANARY_SIZE as usize;
+        start..start + self.num_patterns / 8 + 1
+    }
+
+    /// Returns the offset of each canary, together with the name of the
+    /// region that precedes it.
+    #[cfg(any(test, feature = "memory-checks"))]
+    pub fn canaries(&self) -> [(&'static str, usize); 5] {
+        [
+            ("vars stack", VARS_STACK_END as usize),
+            ("lookup indexes", LOOKUP_INDEXES_END as usize),
+            ("matching rules bitmap", self.matching_rules_bitmap().end),
+            ("selected rules bitmap", self.selected_rules_bitmap().end),
+            ("matching patterns bitmap", self.matching_patterns_bitmap().end),
+        ]
+    }
+
+    /// Size of main memory, in bytes.
+    pub fn size(&self) -> usize {
+        self.matching_patterns_bitmap().end + CANARY_SIZE as usize
+    }
+}
 
 /// Global slice that contains an entry for each function that is callable from
 /// WASM code. Functions with attributes `#[wasm_export]` and `#[module_export]`
@@ -238,6 +341,18 @@ impl From<WasmArg> for PatternId {
     }
 }
 
+impl From<WasmArg> for StringSetId {
+    fn from(value: WasmArg) -> Self {
+        StringSetId::from(value.0.get_i32())
+    }
+}
+
+impl From<WasmArg> for RegexpId {
+    fn from(value: WasmArg) -> Self {
+        RegexpId::from(value.0.get_i32())
+    }
+}
+
 impl From<WasmArg> for LiteralId {
     fn from(value: WasmArg) -> Self {
         LiteralId::from(value.0.get_i32())
@@ -250,6 +365,45 @@ impl From<WasmArg> for RuntimeString {
     }
 }
 
+/// A trait for converting the values passed to a `#[wasm_export]` function
+/// into its arguments.
+///
+/// Most arguments are passed as a single value, but optional arguments
+/// (e.g: `Option<i64>`) are passed as a value followed by an `i32` that is
+/// 1 if the argument is undefined, just like optional results. See
+/// [`WasmResult`].
+trait FromWasmArgs: Sized {
+    /// Creates an argument from the values at the start of `args`. Returns
+    /// the argument and the number of values consumed.
+    fn from_wasm_args(args: &[ValRaw]) -> (Self, usize);
+}
+
+impl<T> FromWasmArgs for T
+where
+    T: From<WasmArg>,
+{
+    fn from_wasm_args(args: &[ValRaw]) -> (Self, usize) {
+        (WasmArg::from(args[0]).into(), 1)
+    }
+}
+
+// The `Default` bound is not actually used, but without it this would
+// conflict with the implementation above, as `Option<WasmArg>` implements
+// `From<WasmArg>`.
+impl<T> FromWasmArgs for Option<T>
+where
+    T: From<WasmArg> + Default,
+{
+    fn from_wasm_args(args: &[ValRaw]) -> (Self, usize) {
+        // The value is not converted if undefined, as it can be garbage.
+        if args[1].get_i32() == 1 {
+            (None, 2)
+        } else {
+            (Some(T::from(WasmArg::from(args[0]))), 2)
+        }
+    }
+}
+
 /// A trait for converting a function result into an array of
 /// [`wasmtime::ValRaw`] values suitable to be passed to WASM code.
 ///
@@ -315,7 +469,7 @@ impl WasmResult for f64 {
 
 impl WasmResult for bool {
     fn values(&self) -> WasmResultArray<ValRaw> {
-        smallvec![ValRaw::i32(*self as i32)]
+        smallvec![ValRaw::i32(i32::from(*self))]
     }
 
     fn types() -> WasmResultArray<wasmtime::ValType> {
@@ -408,10 +562,20 @@ fn type_id_to_wasmtime(
         return &[wasmtime::ValType::I32];
     } else if type_id == TypeId::of::<RuleId>() {
         return &[wasmtime::ValType::I32];
+    } else if type_id == TypeId::of::<StringSetId>() {
+        return &[wasmtime::ValType::I32];
+    } else if type_id == TypeId::of::<RegexpId>() {
+        return &[wasmtime::ValType::I32];
     } else if type_id == TypeId::of::<()>() {
         return &[];
     } else if type_id == TypeId::of::<RuntimeString>() {
         return &[wasmtime::ValType::I64];
+    } else if type_id == TypeId::of::<Option<i64>>() {
+        return &[wasmtime::ValType::I64, wasmtime::ValType::I32];
+    } else if type_id == TypeId::of::<Option<f64>>() {
+        return &[wasmtime::ValType::F64, wasmtime::ValType::I32];
+    } else if type_id == TypeId::of::<Option<RuntimeString>>() {
+        return &[wasmtime::ValType::I64, wasmtime::ValType::I32];
     }
     panic!("type `{}` can't be an argument", type_name)
 }
@@ -433,7 +597,7 @@ macro_rules! impl_wasm_exported_fn {
 
         impl<$($args,)* R> WasmExportedFn for $name<$($args,)* R>
         where
-            $($args: From<WasmArg>,)*
+            $($args: FromWasmArgs,)*
             R: WasmResult,
         {
             #[allow(unused_mut)]
@@ -463,8 +627,8 @@ macro_rules! impl_wasm_exported_fn {
                      -> anyhow::Result<()> {
                         let mut i = 0;
                         $(
-                            let $args = WasmArg::from(args_and_results[i].clone()).into();
-                            i += 1;
+                            let ($args, n) = $args::from_wasm_args(&args_and_results[i..]);
+                            i += n;
                         )*
 
                         let result = (self.target_fn)(caller, $($args),*);
@@ -512,15 +676,24 @@ pub(crate) struct WasmSymbols {
     /// or not.
     pub matching_patterns_bitmap_base: walrus::GlobalId,
 
+    /// Global variable that contains the offset within the module's main
+    /// memory where resides the bitmap that indicates which rules must be
+    /// evaluated.
+    pub selected_rules_bitmap_base: walrus::GlobalId,
+
     /// Global variable that contains the value for `filesize`.
     pub filesize: walrus::GlobalId,
 
-    /// Local variable that is set to true after the pattern search phase
+    /// Global variable that is set to 1 when profiling is enabled, see
+    /// [`crate::Scanner::set_profiling`].
+    pub profiling_enabled: walrus::GlobalId,
+
+    /// Global variable that is set to true after the pattern search phase
     /// has been executed. In this phase the data is scanned looking for
     /// all the patterns at the same time using the Aho-Corasick algorithm.
     /// However this phase is executed lazily, when rule conditions are
     /// evaluated and some of them needs to know if a pattern matched or not.
-    pub pattern_search_done: walrus::LocalId,
+    pub pattern_search_done: walrus::GlobalId,
 
     /// Local variables used for temporary storage.
     pub i64_tmp: walrus::LocalId,
@@ -545,6 +718,11 @@ pub(crate) fn new_linker<'r>() -> Linker<ScanContext<'r>> {
             export.func.wasmtime_args(),
             export.func.wasmtime_results(),
         );
+        let trampoline = export.func.trampoline();
+
+        #[cfg(any(test, feature = "memory-checks"))]
+        let trampoline = memory_checks::guard(export.mangled_name, trampoline);
+
         // Using `func_new_unchecked` instead of `func_new` makes function
         // calls from WASM to Rust around 3x faster.
         unsafe {
@@ -553,7 +731,7 @@ pub(crate) fn new_linker<'r>() -> Linker<ScanContext<'r>> {
                     export.rust_module_path,
                     export.fully_qualified_mangled_name().as_str(),
                     func_type,
-                    export.func.trampoline(),
+                    trampoline,
                 )
                 .unwrap();
         }
@@ -577,35 +755,163 @@ pub(crate) fn rule_match(
     caller.data_mut().track_rule_match(rule_id);
 }
 
+/// Invoked from WASM after evaluating the condition of a rule, only when
+/// profiling is enabled or the scan was started with a callback. See
+/// [`crate::Scanner::set_profiling`] and
+/// [`crate::Scanner::scan_with_callback`].
+#[wasm_export]
+pub(crate) fn rule_evaluated(
+    mut caller: Caller<'_, ScanContext>,
+    rule_id: RuleId,
+) {
+    let ctx = caller.data_mut();
+    if let Some(profiler) = ctx.profiler.as_mut() {
+        profiler.rule_evaluated(rule_id);
+    }
+    ctx.rule_evaluated(rule_id);
+}
+
+/// Invoked from WASM before evaluating the condition of a rule, only when
+/// memory checks are enabled. See the `memory_checks` module.
+#[cfg(any(test, feature = "memory-checks"))]
+#[wasm_export]
+pub(crate) fn enter_rule(
+    mut caller: Caller<'_, ScanContext>,
+    rule_id: RuleId,
+) {
+    caller.data_mut().memory_checks.current_rule = Some(rule_id);
+}
+
+/// Converts an offset, length or count into the `i64` used by WASM code.
+///
+/// These values are bounded by the size of the scanned data and always fit
+/// in an `i64`, a value that doesn't is a bug. Debug builds panic in that
+/// case, while release builds saturate the result to `i64::MAX`.
+#[inline]
+pub(crate) fn wasm_i64(n: usize) -> i64 {
+    let result = i64::try_from(n);
+    debug_assert!(result.is_ok(), "{} doesn't fit in an i64", n);
+    result.unwrap_or(i64::MAX)
+}
+
 /// Invoked from WASM to ask whether a pattern matches at a given file
 /// offset.
 ///
-/// Returns 1 if the pattern identified by `pattern_id` matches at `offset`,
-/// or 0 if otherwise.
+/// Returns 1 if some match of the pattern identified by `pattern_id` starts
+/// at `offset`, or 0 if otherwise.
 #[wasm_export]
 pub(crate) fn is_pat_match_at(
-    _caller: Caller<'_, ScanContext>,
-    _pattern_id: PatternId,
-    _offset: i64,
+    caller: Caller<'_, ScanContext>,
+    pattern_id: PatternId,
+    offset: i64,
 ) -> bool {
-    // TODO
-    false
+    pat_matches_in(caller, pattern_id, offset, offset) > 0
 }
 
 /// Invoked from WASM to ask whether a pattern at some offset within
 /// given range.
 ///
-/// Returns 1 if the pattern identified by `pattern_id` matches at some offset
-/// in the range [`lower_bound`, `upper_bound`].
+/// Returns 1 if some match of the pattern identified by `pattern_id` starts
+/// at some offset in the range [`lower_bound`, `upper_bound`].
 #[wasm_export]
 pub(crate) fn is_pat_match_in(
-    _caller: Caller<'_, ScanContext>,
-    _pattern_id: PatternId,
-    _lower_bound: i64,
-    _upper_bound: i64,
+    caller: Caller<'_, ScanContext>,
+    pattern_id: PatternId,
+    lower_bound: i64,
+    upper_bound: i64,
 ) -> bool {
-    // TODO
-    false
+    pat_matches_in(caller, pattern_id, lower_bound, upper_bound) > 0
+}
+
+/// Invoked from WASM to ask for the number of matches of a pattern, as in
+/// `#a`.
+#[wasm_export]
+pub(crate) fn pat_matches(
+    caller: Caller<'_, ScanContext>,
+    pattern_id: PatternId,
+) -> i64 {
+    caller
+        .data()
+        .pattern_matches
+        .get(&pattern_id)
+        .map_or(0, |matches| wasm_i64(matches.as_slice().len()))
+}
+
+/// Invoked from WASM to ask for the number of matches of a pattern that
+/// start within a given range, as in `#a in (0..100)`.
+///
+/// Both bounds are inclusive.
+#[wasm_export]
+pub(crate) fn pat_matches_in(
+    caller: Caller<'_, ScanContext>,
+    pattern_id: PatternId,
+    lower_bound: i64,
+    upper_bound: i64,
+) -> i64 {
+    // Matches can't start at negative offsets.
+    if upper_bound < 0 {
+        return 0;
+    }
+
+    // Bounds that don't fit in a `usize` are past the end of any data.
+    let range = usize::try_from(lower_bound.max(0)).unwrap_or(usize::MAX)
+        ..=usize::try_from(upper_bound).unwrap_or(usize::MAX);
+
+    caller
+        .data()
+        .pattern_matches
+        .get(&pattern_id)
+        .map_or(0, |matches| wasm_i64(matches.starting_in(range).len()))
+}
+
+/// Invoked from WASM to ask for the offset where the `index`-th match of a
+/// pattern starts, as in `@a[index]`.
+///
+/// Indexes start at 1, and the result is undefined if the index is out
+/// of range.
+#[wasm_export]
+pub(crate) fn pat_offset(
+    caller: Caller<'_, ScanContext>,
+    pattern_id: PatternId,
+    index: i64,
+) -> Option<i64> {
+    get_match(caller.data(), pattern_id, index)
+        .map(|m| wasm_i64(m.range.start))
+}
+
+/// Invoked from WASM to ask for the length of the `index`-th match of a
+/// pattern, as in `!a[index]`.
+///
+/// Indexes start at 1, and the result is undefined if the index is out
+/// of range.
+#[wasm_export]
+pub(crate) fn pat_length(
+    caller: Caller<'_, ScanContext>,
+    pattern_id: PatternId,
+    index: i64,
+) -> Option<i64> {
+    get_match(caller.data(), pattern_id, index)
+        .map(|m| wasm_i64(m.range.len()))
+}
+
+/// Invoked from WASM for evaluating the `entrypoint` keyword.
+///
+/// Returns the file offset of the entry point, or undefined if the scanned
+/// data is not a PE or ELF file.
+#[wasm_export]
+pub(crate) fn entrypoint(caller: Caller<'_, ScanContext>) -> Option<i64> {
+    crate::scanner::entrypoint::entrypoint(caller.data().scanned_data())
+        .and_then(|offset| i64::try_from(offset).ok())
+}
+
+/// Returns the `index`-th match of a pattern, starting at 1.
+fn get_match<'a>(
+    ctx: &'a ScanContext,
+    pattern_id: PatternId,
+    index: i64,
+) -> Option<&'a Match> {
+    let index = usize::try_from(index).ok()?.checked_sub(1)?;
+    ctx.pattern_matches.get(&pattern_id)?.as_slice().get(index)
 }
 
 /// Given some local variable containing an array, returns the length of the
@@ -617,8 +923,8 @@ pub(crate) fn is_pat_match_in(
 #[wasm_export]
 pub(crate) fn array_len(mut caller: Caller<'_, ScanContext>, var: i32) -> i64 {
     let ctx = caller.data_mut();
-    let len = ctx.vars_stack.get(var as usize).unwrap().as_array().len();
-    len as i64
+    let var = usize::try_from(var).unwrap();
+    wasm_i64(ctx.vars_stack.get(var).unwrap().as_array().len())
 }
 
 /// Given some local variable containing a map, returns the length of the
@@ -630,8 +936,8 @@ pub(crate) fn array_len(mut caller: Caller<'_, ScanContext>, var: i32) -> i64 {
 #[wasm_export]
 pub(crate) fn map_len(mut caller: Caller<'_, ScanContext>, var: i32) -> i64 {
     let ctx = caller.data_mut();
-    let len = ctx.vars_stack.get(var as usize).unwrap().as_map().len();
-    len as i64
+    let var = usize::try_from(var).unwrap();
+    wasm_i64(ctx.vars_stack.get(var).unwrap().as_map().len())
 }
 
 /// Given a structure and a series of fields indexes, walks the structure
@@ -674,11 +980,21 @@ fn lookup_field(
     let lookup_indexes_ptr =
         store_ctx.data_mut().main_memory.unwrap().data_ptr(&mut store_ctx);
 
+    let num_lookup_indexes = usize::try_from(num_lookup_indexes).unwrap();
+    let lookup_indexes_start = usize::try_from(LOOKUP_INDEXES_START).unwrap();
+    let lookup_indexes_end = usize::try_from(LOOKUP_INDEXES_END).unwrap();
+
+    assert!(
+        num_lookup_indexes * mem::size_of::<i32>()
+            <= lookup_indexes_end - lookup_indexes_start,
+        "too many lookup indexes: {}",
+        num_lookup_indexes
+    );
+
     let lookup_indexes = unsafe {
         std::slice::from_raw_parts::<i32>(
-            lookup_indexes_ptr.offset(LOOKUP_INDEXES_START as isize)
-                as *const i32,
-            num_lookup_indexes as usize,
+            lookup_indexes_ptr.add(lookup_indexes_start).cast::<i32>(),
+            num_lookup_indexes,
         )
     };
 
@@ -688,7 +1004,8 @@ fn lookup_field(
         {
             current_structure.as_ref()
         } else if struct_var != -1 {
-            let var = &store_ctx.data().vars_stack[struct_var as usize];
+            let var = &store_ctx.data().vars_stack
+                [usize::try_from(struct_var).unwrap()];
 
             if let TypeValue::Struct(s) = var {
                 s
@@ -705,8 +1022,9 @@ fn lookup_field(
         let mut final_field = None;
 
         for field_index in lookup_indexes {
-            let field =
-                structure.field_by_index(*field_index as usize).unwrap();
+            let field = structure
+                .field_by_index(usize::try_from(*field_index).unwrap())
+                .unwrap();
             final_field = Some(field);
             if let TypeValue::Struct(s) = &field.type_value {
                 structure = s
@@ -715,7 +1033,7 @@ fn lookup_field(
 
         &final_field.unwrap().type_value
     } else if struct_var != -1 {
-        &store_ctx.data().vars_stack[struct_var as usize]
+        &store_ctx.data().vars_stack[usize::try_from(struct_var).unwrap()]
     } else {
         unreachable!();
     };
@@ -756,7 +1074,7 @@ pub(crate) fn lookup_value(
     dst_var: i32,
 ) {
     let type_value = lookup_field(&mut caller, num_lookup_indexes, struct_var);
-    let index = dst_var as usize;
+    let index = usize::try_from(dst_var).unwrap();
     let vars = &mut caller.data_mut().vars_stack;
 
     if vars.len() <= index {
@@ -766,6 +1084,23 @@ pub(crate) fn lookup_value(
     vars[index] = type_value;
 }
 
+/// Lookup a field of array type and returns the number of items in the
+/// array.
+///
+/// See [`lookup_field`].
+#[wasm_export]
+pub(crate) fn lookup_array_len(
+    mut caller: Caller<'_, ScanContext>,
+    num_lookup_indexes: i32,
+    struct_var: i32,
+) -> i64 {
+    wasm_i64(
+        lookup_field(&mut caller, num_lookup_indexes, struct_var)
+            .as_array()
+            .len(),
+    )
+}
+
 macro_rules! gen_lookup_fn {
     ($name:ident, $return_type:ty, $type:path) => {
         #[wasm_export]
@@ -777,7 +1112,7 @@ macro_rules! gen_lookup_fn {
             if let $type(Some(value)) =
                 lookup_field(&mut caller, num_lookup_indexes, struct_var)
             {
-                Some(value as $return_type)
+                Some(value)
             } else {
                 None
             }
@@ -798,19 +1133,37 @@ macro_rules! gen_array_indexing_fn {
             num_lookup_indexes: i32,
             struct_var: i32,
         ) -> Option<$return_type> {
+            let index = usize::try_from(index).ok()?;
             lookup_field(&mut caller, num_lookup_indexes, struct_var)
                 .as_array()
                 .$fn()
-                .get(index as usize)
-                .map(|value| *value as $return_type)
+                .get(index)
+                .copied()
         }
     };
 }
 
-gen_array_indexing_fn!(array_indexing_integer, as_integer_array, i64);
 gen_array_indexing_fn!(array_indexing_float, as_float_array, f64);
 gen_array_indexing_fn!(array_indexing_bool, as_bool_array, bool);
 
+/// Like the functions generated by `gen_array_indexing_fn`, but for arrays
+/// of integers, which can have undefined items.
+#[wasm_export]
+pub(crate) fn array_indexing_integer(
+    mut caller: Caller<'_, ScanContext>,
+    index: i64,
+    num_lookup_indexes: i32,
+    struct_var: i32,
+) -> Option<i64> {
+    let index = usize::try_from(index).ok()?;
+    lookup_field(&mut caller, num_lookup_indexes, struct_var)
+        .as_array()
+        .as_integer_array()
+        .get(index)
+        .copied()
+        .flatten()
+}
+
 #[wasm_export]
 #[rustfmt::skip]
 pub(crate) fn array_indexing_string(
@@ -819,10 +1172,11 @@ pub(crate) fn array_indexing_string(
     num_lookup_indexes: i32,
     struct_var: i32,
 ) -> Option<RuntimeString> {
+    let index = usize::try_from(index).ok()?;
     lookup_field(&mut caller, num_lookup_indexes,struct_var )
         .as_array()
         .as_string_array()
-        .get(index as usize)
+        .get(index)
         .map(|s| { 
             RuntimeString::from_bytes(caller.data_mut(), s.as_bstr())
         })
@@ -837,13 +1191,14 @@ pub(crate) fn array_indexing_struct(
     struct_var: i32,
     dst_var: i32,
 ) -> Option<()> {
+    let index = usize::try_from(index).ok()?;
     lookup_field(&mut caller, num_lookup_indexes,struct_var )
         .as_array()
         .as_struct_array()
-        .get(index as usize)
+        .get(index)
         .map(|s| {
             if dst_var != -1 {
-                let index = dst_var as usize;
+                let index = usize::try_from(dst_var).unwrap();
                 let vars = &mut caller.data_mut().vars_stack;
                 if vars.len() <= index {
                     vars.resize(index + 1, TypeValue::Unknown);
@@ -1022,6 +1377,9 @@ pub(crate) fn map_lookup_string_struct(
         .map(|v| caller.data_mut().current_struct = Some(v.as_struct()))
 }
 
+/// Generates functions that return the key and value at a given index in a
+/// map. These are used by `for .. in` loops, which go from index 0 to the
+/// map's length, following the order of keys in the map.
 macro_rules! gen_map_lookup_by_index_fn {
     ($name:ident, RuntimeString, $val:ty, $with:ident, $as:ident) => {
         #[wasm_export]
@@ -1034,7 +1392,10 @@ macro_rules! gen_map_lookup_by_index_fn {
             let map =
                 lookup_field(&mut caller, num_lookup_indexes, struct_var)
                     .as_map();
-            let (key, value) = map.$with().get_index(index as usize).unwrap();
+            let (key, value) = map
+                .$with()
+                .get_index(usize::try_from(index).unwrap())
+                .unwrap();
             let key =
                 RuntimeString::from_bytes(caller.data_mut(), key.as_bstr());
             (key, value.$as())
@@ -1051,7 +1412,10 @@ macro_rules! gen_map_lookup_by_index_fn {
             let map =
                 lookup_field(&mut caller, num_lookup_indexes, struct_var)
                     .as_map();
-            let (key, value) = map.$with().get_index(index as usize).unwrap();
+            let (key, value) = map
+                .$with()
+                .get_index(usize::try_from(index).unwrap())
+                .unwrap();
             (*key, value.$as())
         }
     };
@@ -1120,8 +1484,10 @@ pub(crate) fn map_lookup_by_index_integer_string(
 ) -> (i64, RuntimeString) {
     let map =
         lookup_field(&mut caller, num_lookup_indexes, struct_var).as_map();
-    let (key, value) =
-        map.with_integer_keys().get_index(index as usize).unwrap();
+    let (key, value) = map
+        .with_integer_keys()
+        .get_index(usize::try_from(index).unwrap())
+        .unwrap();
     let value = RuntimeString::from_bytes(caller.data_mut(), value.as_bstr());
     (*key, value)
 }
@@ -1135,8 +1501,10 @@ pub(crate) fn map_lookup_by_index_string_string(
 ) -> (RuntimeString, RuntimeString) {
     let map =
         lookup_field(&mut caller, num_lookup_indexes, struct_var).as_map();
-    let (key, value) =
-        map.with_string_keys().get_index(index as usize).unwrap();
+    let (key, value) = map
+        .with_string_keys()
+        .get_index(usize::try_from(index).unwrap())
+        .unwrap();
     let key = RuntimeString::from_bytes(caller.data_mut(), key.as_bstr());
     let value = RuntimeString::from_bytes(caller.data_mut(), value.as_bstr());
     (key, value)
@@ -1152,13 +1520,15 @@ pub(crate) fn map_lookup_by_index_integer_struct(
 ) -> i64 {
     let map =
         lookup_field(&mut caller, num_lookup_indexes, struct_var).as_map();
-    let (key, value) =
-        map.with_integer_keys().get_index(index as usize).unwrap();
+    let (key, value) = map
+        .with_integer_keys()
+        .get_index(usize::try_from(index).unwrap())
+        .unwrap();
 
     let value = value.as_struct();
 
     if dst_var != -1 {
-        let index = dst_var as usize;
+        let index = usize::try_from(dst_var).unwrap();
         let vars = &mut caller.data_mut().vars_stack;
         if vars.len() <= index {
             vars.resize(index + 1, TypeValue::Unknown);
@@ -1181,13 +1551,15 @@ pub(crate) fn map_lookup_by_index_string_struct(
 ) -> RuntimeString {
     let map =
         lookup_field(&mut caller, num_lookup_indexes, struct_var).as_map();
-    let (key, value) =
-        map.with_string_keys().get_index(index as usize).unwrap();
+    let (key, value) = map
+        .with_string_keys()
+        .get_index(usize::try_from(index).unwrap())
+        .unwrap();
 
     let value = value.as_struct();
 
     if dst_var != -1 {
-        let index = dst_var as usize;
+        let index = usize::try_from(dst_var).unwrap();
         let vars = &mut caller.data_mut().vars_stack;
         if vars.len() <= index {
             vars.resize(index + 1, TypeValue::Unknown);
@@ -1241,12 +1613,47 @@ gen_str_op_fn!(str_istartswith, starts_with, true);
 gen_str_op_fn!(str_iendswith, ends_with, true);
 gen_str_op_fn!(str_iequals, equals, true);
 
+/// Returns true if the string `s` belongs to the set of strings identified
+/// by `set_id`.
+#[wasm_export]
+pub(crate) fn str_in_set(
+    caller: Caller<'_, ScanContext>,
+    set_id: StringSetId,
+    s: RuntimeString,
+) -> bool {
+    let ctx = caller.data();
+    let rules = ctx.compiled_rules;
+
+    // Literals are already in the pool, for other strings the pool is
+    // searched by content. A string that is not in the pool can't be in
+    // the set.
+    let literal_id = match s {
+        RuntimeString::Literal(id) => Some(id),
+        _ => rules.lit_pool().get_id(s.as_bstr(ctx)),
+    };
+
+    literal_id
+        .is_some_and(|id| rules.get_string_set(set_id).literals.contains(&id))
+}
+
+/// Returns true if the string `s` matches the regexp identified by
+/// `regexp_id`.
+#[wasm_export]
+pub(crate) fn str_matches(
+    caller: Caller<'_, ScanContext>,
+    s: RuntimeString,
+    regexp_id: RegexpId,
+) -> bool {
+    let ctx = caller.data();
+    ctx.compiled_rules.get_regexp(regexp_id).is_match(s.as_bstr(ctx))
+}
+
 #[wasm_export]
 pub(crate) fn str_len(
     caller: Caller<'_, ScanContext>,
     s: RuntimeString,
 ) -> i64 {
-    s.len(caller.data()) as i64
+    wasm_i64(s.len(caller.data()))
 }
 
 macro_rules! gen_uint_fn {
@@ -1259,12 +1666,15 @@ macro_rules! gen_uint_fn {
             let offset = usize::try_from(offset).ok()?;
             caller
                 .data()
-                .scanned_data()
-                .get(offset..offset + mem::size_of::<$return_type>())
+                .scanned_data_in(
+                    offset..offset + mem::size_of::<$return_type>(),
+                )
                 .map_or(None, |bytes| {
                     let value =
                         <$return_type>::$from_fn(bytes.try_into().unwrap());
-                    Some(value as i64)
+                    // `uint64` values above `i64::MAX` wrap around, like
+                    // in YARA.
+                    Some(u64::from(value).cast_signed())
                 })
         }
     };
@@ -1279,9 +1689,60 @@ gen_uint_fn!(uint16be, u16, from_be_bytes);
 gen_uint_fn!(uint32be, u32, from_be_bytes);
 gen_uint_fn!(uint64be, u64, from_be_bytes);
 
+macro_rules! gen_int_fn {
+    ($name:ident, $return_type:ty, $from_fn:ident) => {
+        #[wasm_export(public = true)]
+        pub(crate) fn $name(
+            caller: Caller<'_, ScanContext>,
+            offset: i64,
+        ) -> Option<i64> {
+            let offset = usize::try_from(offset).ok()?;
+            caller
+                .data()
+                .scanned_data_in(
+                    offset..offset + mem::size_of::<$return_type>(),
+                )
+                .map(|bytes| {
+                    i64::from(<$return_type>::$from_fn(
+                        bytes.try_into().unwrap(),
+                    ))
+                })
+        }
+    };
+}
+
+gen_int_fn!(int8, i8, from_le_bytes);
+gen_int_fn!(int16, i16, from_le_bytes);
+gen_int_fn!(int32, i32, from_le_bytes);
+gen_int_fn!(int8be, i8, from_be_bytes);
+gen_int_fn!(int16be, i16, from_be_bytes);
+gen_int_fn!(int32be, i32, from_be_bytes);
+
 #[cfg(test)]
 mod tests {
-    use crate::wasm::WasmResult;
+    use crate::wasm::{wasm_i64, WasmResult};
+
+    #[test]
+    fn wasm_i64_conversion() {
+        assert_eq!(wasm_i64(0), 0);
+        let max = usize::try_from(i64::MAX).unwrap();
+        assert_eq!(wasm_i64(max), i64::MAX);
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, target_pointer_width = "64"))]
+    #[should_panic]
+    fn wasm_i64_overflow_panics_in_debug() {
+        wasm_i64(usize::MAX);
+    }
+
+    #[test]
+    #[cfg(all(not(debug_assertions), target_pointer_width = "64"))]
+    fn wasm_i64_overflow_saturates_in_release() {
+        assert_eq!(wasm_i64(usize::MAX), i64::MAX);
+        let max = usize::try_from(i64::MAX).unwrap();
+        assert_eq!(wasm_i64(max + 1), i64::MAX);
+    }
 
     #[test]
     fn wasm_result_conversion() {
@@ -88,13 +88,19 @@ impl RuntimeString {
         let data = ctx.scanned_data();
         let s = s.as_ref();
 
-        let data_start = data.as_ptr() as usize;
+        let data_start = data.as_ptr().addr();
         let data_end = data_start + data.len();
 
-        let s_start = s.as_ptr() as usize;
+        let s_start = s.as_ptr().addr();
         let s_end = s_start + s.len();
 
-        if s_start >= data_start && s_end <= data_end {
+        // Slices that can't be encoded by `as_wasm` are copied, like any
+        // other string.
+        if s_start >= data_start
+            && s_end <= data_end
+            && s_start - data_start <= Self::MAX_SLICE_OFFSET
+            && s.len() < usize::from(u16::MAX)
+        {
             Self::ScannedDataSlice {
                 offset: s_start - data_start,
                 length: s.len(),
@@ -118,19 +124,31 @@ impl RuntimeString {
         }
     }
 
+    /// Largest offset of a [`RuntimeString::ScannedDataSlice`] that fits in
+    /// the 46 bits reserved for it in [`RuntimeStringWasm`].
+    const MAX_SLICE_OFFSET: usize = (1 << 45) - 1;
+
     /// Returns this string as a primitive type suitable to be passed to WASM.
     pub(crate) fn as_wasm(&self) -> RuntimeStringWasm {
         match self {
             Self::Literal(id) => i64::from(*id) << 2,
-            Self::Owned(id) => (*id as i64) << 2 | 1,
+            Self::Owned(id) => i64::from(*id) << 2 | 1,
             Self::ScannedDataSlice { offset, length } => {
-                if *length >= u16::MAX as usize {
+                if *length >= usize::from(u16::MAX) {
                     panic!(
                         "runtime-string slices can't be larger than {}",
                         u16::MAX
                     )
                 }
-                (*offset as i64) << 18 | (*length as i64) << 2 | 2
+                if *offset > Self::MAX_SLICE_OFFSET {
+                    panic!(
+                        "runtime-string slices can't start after offset {}",
+                        Self::MAX_SLICE_OFFSET
+                    )
+                }
+                i64::try_from(*offset).unwrap() << 18
+                    | i64::try_from(*length).unwrap() << 2
+                    | 2
             }
         }
     }
@@ -138,11 +156,13 @@ impl RuntimeString {
     /// Creates a [`RuntimeString`] from a [`RuntimeStringWasm`].
     pub(crate) fn from_wasm(s: RuntimeStringWasm) -> Self {
         match s & 0x3 {
-            0 => Self::Literal(LiteralId::from((s >> 2) as u32)),
-            1 => Self::Owned((s >> 2) as u32),
+            0 => {
+                Self::Literal(LiteralId::from(u32::try_from(s >> 2).unwrap()))
+            }
+            1 => Self::Owned(u32::try_from(s >> 2).unwrap()),
             2 => Self::ScannedDataSlice {
-                offset: (s >> 18) as usize,
-                length: ((s >> 2) & 0xffff) as usize,
+                offset: usize::try_from(s >> 18).unwrap(),
+                length: usize::try_from((s >> 2) & 0xffff).unwrap(),
             },
             _ => unreachable!(),
         }
@@ -190,9 +210,11 @@ impl RuntimeString {
         ctx: &ScanContext,
         case_insensitive: bool,
     ) -> bool {
+        // Case-insensitive operations fold ASCII characters only, like in
+        // YARA and in the constant folding done by the compiler.
         if case_insensitive {
-            let this = self.as_bstr(ctx).to_lowercase();
-            let other = other.as_bstr(ctx).to_lowercase();
+            let this = self.as_bstr(ctx).to_ascii_lowercase();
+            let other = other.as_bstr(ctx).to_ascii_lowercase();
             this.contains_str(other)
         } else {
             self.as_bstr(ctx).contains_str(other.as_bstr(ctx))
@@ -206,12 +228,13 @@ impl RuntimeString {
         ctx: &ScanContext,
         case_insensitive: bool,
     ) -> bool {
+        let this = self.as_bstr(ctx);
+        let other = other.as_bstr(ctx);
         if case_insensitive {
-            let this = self.as_bstr(ctx).to_lowercase();
-            let other = other.as_bstr(ctx).to_lowercase();
-            this.starts_with_str(other)
+            this.len() >= other.len()
+                && this[..other.len()].eq_ignore_ascii_case(other)
         } else {
-            self.as_bstr(ctx).starts_with_str(other.as_bstr(ctx))
+            this.starts_with_str(other)
         }
     }
 
@@ -222,12 +245,13 @@ impl RuntimeString {
         ctx: &ScanContext,
         case_insensitive: bool,
     ) -> bool {
+        let this = self.as_bstr(ctx);
+        let other = other.as_bstr(ctx);
         if case_insensitive {
-            let this = self.as_bstr(ctx).to_lowercase();
-            let other = other.as_bstr(ctx).to_lowercase();
-            this.ends_with_str(other)
+            this.len() >= other.len()
+                && this[this.len() - other.len()..].eq_ignore_ascii_case(other)
         } else {
-            self.as_bstr(ctx).ends_with_str(other.as_bstr(ctx))
+            this.ends_with_str(other)
         }
     }
 
@@ -238,12 +262,12 @@ impl RuntimeString {
         ctx: &ScanContext,
         case_insensitive: bool,
     ) -> bool {
+        let this = self.as_bstr(ctx);
+        let other = other.as_bstr(ctx);
         if case_insensitive {
-            let this = self.as_bstr(ctx).to_lowercase();
-            let other = other.as_bstr(ctx).to_lowercase();
-            this.eq(&other)
+            this.eq_ignore_ascii_case(other)
         } else {
-            self.as_bstr(ctx).eq(other.as_bstr(ctx))
+            this.eq(other)
         }
     }
 }
@@ -262,15 +286,34 @@ mod tests {
         let s =
             RuntimeString::ScannedDataSlice { length: 100, offset: 0x1000000 };
         assert_eq!(s, RuntimeString::from_wasm(s.as_wasm()));
+
+        let s = RuntimeString::Owned(u32::MAX);
+        assert_eq!(s, RuntimeString::from_wasm(s.as_wasm()));
+
+        let s = RuntimeString::ScannedDataSlice {
+            length: usize::from(u16::MAX) - 1,
+            offset: RuntimeString::MAX_SLICE_OFFSET,
+        };
+        assert_eq!(s, RuntimeString::from_wasm(s.as_wasm()));
     }
 
     #[test]
     #[should_panic]
     fn runtime_string_wasm_max_size() {
         let s = RuntimeString::ScannedDataSlice {
-            length: u32::MAX as usize + 1,
+            length: usize::from(u16::MAX),
             offset: 0x1000000,
         };
         assert_eq!(s, RuntimeString::from_wasm(s.as_wasm()));
     }
+
+    #[test]
+    #[should_panic]
+    fn runtime_string_wasm_max_offset() {
+        let s = RuntimeString::ScannedDataSlice {
+            length: 1,
+            offset: RuntimeString::MAX_SLICE_OFFSET + 1,
+        };
+        assert_eq!(s, RuntimeString::from_wasm(s.as_wasm()));
+    }
 }